@@ -0,0 +1,87 @@
+use std::hint::black_box;
+use std::time::Instant;
+use py_ca_compiler::automata::terms::{AbstractExpression, Expression, Term};
+use py_ca_compiler::lexer::lexer::Lexer;
+use py_ca_compiler::parser::parse::parse;
+use py_ca_compiler::parser::parser_helpers::TokenStack;
+use py_ca_compiler::potato_cpu::bit_allocation::{BitAllocation, GrowableBitAllocation};
+
+/*
+Rough timings of the parts of the compiler and automata that have been
+slow before, printed rather than asserted since they depend on the machine.
+Run with: cargo bench --bench timing
+*/
+
+fn time<T>(run: impl FnOnce() -> T) -> (T, std::time::Duration) {
+    let start = Instant::now();
+    let result = run();
+    (result, start.elapsed())
+}
+
+fn large_input_parse() {
+    // every statement has failed parse attempts that used to copy the whole token stack
+    let lexer = Lexer::new();
+    let statement = lexer.tokenize("a = (long) a * 3 + (a & 7) - ~a; long b = a << 2; b;").unwrap();
+    let mut tokens = lexer.tokenize("int main(void) { int a = 0;").unwrap();
+    for _ in 0..4000 {
+        tokens.extend(statement.iter().cloned());
+    }
+    tokens.extend(lexer.tokenize("return a; }").unwrap());
+    let token_count = tokens.len();
+
+    let (_, elapsed) = time(|| parse(&mut TokenStack::new_from_vec(tokens)).unwrap());
+    println!("parsed {} tokens in {:?}", token_count, elapsed);
+}
+
+fn ripple_carry_add() {
+    // adding through the bit vectors against a round trip through BigUint
+    let a = GrowableBitAllocation::new_from((0..4096).map(|i| i % 3 == 0).collect());
+    let b = GrowableBitAllocation::new_from((0..4000).map(|i| i % 5 != 0).collect());
+    let num_additions = 10_000;
+
+    let (_, elapsed) = time(|| {
+        for _ in 0..num_additions {
+            black_box(&a + &b);
+        }
+    });
+    println!("ripple carry: {} additions in {:?}", num_additions, elapsed);
+    let (_, elapsed) = time(|| {
+        for _ in 0..num_additions {
+            let sum = a.to_big_num() + b.to_big_num();
+            black_box(GrowableBitAllocation::from_big_num(&sum));
+        }
+    });
+    println!("BigUint: {} additions in {:?}", num_additions, elapsed);
+}
+
+fn rule_110_expansion() {
+    // mostly comes down to copying terms between products
+    let expr_mapping = Expression::from_wolfram_rule(110);
+    let seed = (Term::new(0, 1, false) * Term::new(1, 0, false)).to_expression();
+
+    let (expanded, elapsed) = time(|| seed._expand_steps(&expr_mapping, 2));
+    let num_terms = expanded._get_num_terms();
+    println!(
+        "expanded {} products ({} terms, {} bytes of terms) in {:?}",
+        expanded._get_num_products(), num_terms,
+        num_terms * std::mem::size_of::<Term>(), elapsed
+    );
+    let (product, elapsed) = time(|| {
+        let mut product = expr_mapping[&1].clone();
+        for offset in 1..8 {
+            product = product * expr_mapping[&0].offset(offset * 3);
+        }
+        product
+    });
+    println!("multiplied out {} products in {:?}", product._get_num_products(), elapsed);
+}
+
+fn main() {
+    // cargo test --benches runs this without --bench, where the timings are meaningless
+    if !std::env::args().any(|arg| arg == "--bench") {
+        return;
+    }
+    large_input_parse();
+    ripple_carry_add();
+    rule_110_expansion();
+}
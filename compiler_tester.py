@@ -3,7 +3,8 @@ import subprocess
 from enum import StrEnum
 from tqdm import tqdm
 from typing import Final
-from py_ca_compiler import PyPotatoCPUTester
+from py_ca_compiler.compiler import PyTacky
+from py_ca_compiler.potato import PyPotatoCPUTester
 
 
 COMPILER_TESTS_DIR: Final[str] = './writing-a-c-compiler-tests'
@@ -86,6 +87,47 @@ class CompilerTester(object):
         result = potato_program.execute()
         return result
 
+    def execute_test_reference_vm(
+        self, test_name: str, chapter_no: int, prefix: Prefix = Prefix.valid
+    ) -> int:
+        """
+        Run the test program compiled for the Potato CPU on the reference VM
+        instead, to tell code generation bugs apart from Potato CPU bugs
+        """
+        test_path = self.get_test_path(test_name, chapter_no, prefix)
+        potato_program = PyPotatoCPUTester.compile_from_source(test_path)
+        return potato_program.execute_on_reference_vm()
+
+    def print_potato_cpu_trace(
+        self, test_name: str, chapter_no: int,
+        prefix: Prefix = Prefix.valid, num_entries: int = 20
+    ) -> None:
+        """
+        Print the last instructions executed by the Potato CPU
+        for the test program, along with the registers they changed
+        """
+        test_path = self.get_test_path(test_name, chapter_no, prefix)
+        potato_program = PyPotatoCPUTester.compile_from_source(test_path)
+        _, trace = potato_program.execute_with_trace()
+        print(f'Last {num_entries} of {len(trace)} executed instructions:')
+        for entry in trace[-num_entries:]:
+            deltas = ', '.join(
+                f'{register}={value}'
+                for register, value in entry.register_deltas
+            )
+            print(f'  [{entry.program_counter}] {entry.instruction} {deltas}')
+
+    def print_tacky(
+        self, test_name: str, chapter_no: int, prefix: Prefix = Prefix.valid
+    ) -> None:
+        """
+        Print the tacky IR generated for the test program,
+        to tell frontend bugs apart from backend bugs
+        """
+        test_path = self.get_test_path(test_name, chapter_no, prefix)
+        tacky = PyTacky.compile_from_source(test_path)
+        print(tacky.print_tacky_code())
+
     def count_total_tests(self, chapters: list[int]) -> int:
         total_tests = 0
         for chapter_no in chapters:
@@ -144,10 +186,16 @@ class CompilerTester(object):
                     f'    x86 return code: {x86_return_code}, '
                     f'Potato CPU return code: {potato_cpu_return_code}'
                 )
-                if x86_return_code != potato_cpu_return_code:
+                # exit codes only keep the lowest byte of the return value
+                if x86_return_code != potato_cpu_return_code % 256:
+                    tester.print_potato_cpu_trace(test_file, chapter)
+                    reference_vm_return_code = (
+                        tester.execute_test_reference_vm(test_file, chapter)
+                    )
                     raise ValueError(
                         f'FAILED (x86: {x86_return_code}, '
-                        f'Potato CPU: {potato_cpu_return_code})'
+                        f'Potato CPU: {potato_cpu_return_code}, '
+                        f'reference VM: {reference_vm_return_code})'
                     )
 
                 pbar.update(1)
@@ -0,0 +1,9 @@
+# This file is automatically generated by pyo3_stub_gen
+# ruff: noqa: E501, F401
+
+import builtins
+import numpy
+import numpy.typing
+
+def alu_tape_history(operation:builtins.str, a:builtins.int, b:builtins.int, num_steps:builtins.int, start:builtins.int, end:builtins.int) -> numpy.typing.NDArray[numpy.uint8]: ...
+
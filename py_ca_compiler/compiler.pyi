@@ -0,0 +1,32 @@
+# This file is automatically generated by pyo3_stub_gen
+# ruff: noqa: E501, F401
+
+import builtins
+import py_ca_compiler.potato
+import typing
+
+class PyAst:
+    @classmethod
+    def parse_from_source(cls, source_filepath:builtins.str) -> PyAst: ...
+    @classmethod
+    def parse_from_string(cls, source:builtins.str) -> PyAst: ...
+    def to_dict(self) -> typing.Any: ...
+    def to_json(self) -> builtins.str: ...
+    def function_name(self) -> builtins.str: ...
+    def function_return_type(self) -> builtins.str: ...
+    def function_body(self) -> typing.Any: ...
+
+class PyTacky:
+    @classmethod
+    def compile_from_source(cls, source_filepath:builtins.str) -> PyTacky: ...
+    @classmethod
+    def compile_from_string(cls, source:builtins.str) -> PyTacky: ...
+    def print_tacky_code(self) -> builtins.str: ...
+    def to_text(self) -> builtins.str: ...
+    def function_name(self) -> builtins.str: ...
+    def instructions(self) -> builtins.list[builtins.dict[builtins.str, builtins.str]]: ...
+
+def compile_and_run(source:builtins.str, backend:builtins.str, cancel_token:typing.Optional[PyCancellationToken]=None) -> builtins.int: ...
+
+def compile_to_asm(source_path:builtins.str, target:builtins.str) -> builtins.str: ...
+
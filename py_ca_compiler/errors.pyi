@@ -0,0 +1,35 @@
+# This file is automatically generated by pyo3_stub_gen
+# ruff: noqa: E501, F401
+
+import builtins
+
+class CaCodegenError(CaError):
+    r"""
+    Failure to generate (or assemble) code for a valid program
+    """
+    ...
+
+class CaError(builtins.Exception):
+    r"""
+    Base class of the errors raised by py_ca_compiler
+    """
+    ...
+
+class CaExecutionError(CaError):
+    r"""
+    Program that failed while running, e.g. one that loops forever
+    """
+    ...
+
+class CaLexError(CaError):
+    r"""
+    Invalid token in the source
+    """
+    ...
+
+class CaParseError(CaError):
+    r"""
+    Source (or potato assembly) that doesn't parse, or fails semantic analysis
+    """
+    ...
+
@@ -0,0 +1,67 @@
+# This file is automatically generated by pyo3_stub_gen
+# ruff: noqa: E501, F401
+
+import builtins
+import numpy
+import numpy.typing
+import typing
+
+class PyCancellationToken:
+    def __new__(cls) -> PyCancellationToken: ...
+    def cancel(self) -> None: ...
+    def is_cancelled(self) -> builtins.bool: ...
+
+class PyPotatoCPU:
+    def __new__(cls, asm_text:builtins.str, num_scratch_registers:builtins.int, stack_width:builtins.int) -> PyPotatoCPU: ...
+    @classmethod
+    def compile_from_source(cls, source_filepath:builtins.str) -> PyPotatoCPU: ...
+    def step(self) -> builtins.bool: ...
+    def run(self, max_steps:builtins.int, cancel_token:typing.Optional[PyCancellationToken]=None) -> builtins.bool: ...
+    def is_halted(self) -> builtins.bool: ...
+    def time_steps(self) -> builtins.int: ...
+    def program_counter(self) -> builtins.int: ...
+    def next_instruction(self) -> typing.Optional[builtins.str]: ...
+    def read_register(self, name:builtins.str) -> builtins.str: ...
+    def read_stack(self, index:builtins.int) -> builtins.str: ...
+    def stack_array(self) -> numpy.typing.NDArray[numpy.uint32]: ...
+    def stack_size(self) -> builtins.int: ...
+    def output(self) -> builtins.list[builtins.int]: ...
+    def __getstate__(self) -> bytes: ...
+    def __setstate__(self, state:bytes) -> None: ...
+    @classmethod
+    def from_state(cls, state:bytes) -> PyPotatoCPU: ...
+    def __reduce__(self) -> tuple[typing.Any, tuple[bytes]]: ...
+
+class PyPotatoCPUTester:
+    @classmethod
+    def compile_from_source(cls, source_filepath:builtins.str) -> PyPotatoCPUTester: ...
+    @classmethod
+    def compile_from_string(cls, source:builtins.str) -> PyPotatoCPUTester: ...
+    def execute(self, cancel_token:typing.Optional[PyCancellationToken]=None) -> builtins.int: ...
+    def execute_on_reference_vm(self, cancel_token:typing.Optional[PyCancellationToken]=None) -> builtins.int: ...
+    def execute_with_trace(self, max_steps:builtins.int=1000000, cancel_token:typing.Optional[PyCancellationToken]=None) -> tuple[builtins.int, builtins.list[PyTraceEntry]]: ...
+    def trace(self, max_steps:builtins.int=1000000, cancel_token:typing.Optional[PyCancellationToken]=None) -> PyTrace: ...
+    def step(self) -> builtins.bool: ...
+    def run(self, max_steps:builtins.int, cancel_token:typing.Optional[PyCancellationToken]=None) -> builtins.bool: ...
+    def read_register(self, name:builtins.str) -> builtins.str: ...
+    def read_stack(self, index:builtins.int) -> builtins.str: ...
+    def program_counter(self) -> builtins.int: ...
+    def __getstate__(self) -> bytes: ...
+    def __setstate__(self, state:bytes) -> None: ...
+    @classmethod
+    def from_state(cls, state:bytes) -> PyPotatoCPUTester: ...
+    def __reduce__(self) -> tuple[typing.Any, tuple[bytes]]: ...
+
+class PyTrace:
+    def __iter__(self) -> PyTrace: ...
+    def __next__(self) -> typing.Optional[builtins.dict[builtins.str, typing.Any]]: ...
+    def __len__(self) -> builtins.int: ...
+
+class PyTraceEntry:
+    @property
+    def program_counter(self) -> builtins.int: ...
+    @property
+    def instruction(self) -> builtins.str: ...
+    @property
+    def register_deltas(self) -> builtins.list[tuple[builtins.str, builtins.str]]: ...
+
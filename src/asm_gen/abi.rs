@@ -0,0 +1,119 @@
+use crate::asm_gen::registers::Register;
+
+/*
+Platform conventions that the x86-64 backend has to follow for its
+output to link with the platform's C toolchain. Only main is generated
+for now, so symbol names, section directives and the registers saved
+in the prologue are all that differ in the emitted code; the argument
+registers and shadow space are here for when function calls are supported
+*/
+
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum Abi {
+    // Linux and other ELF platforms
+    #[default]
+    SystemV,
+    // Microsoft x64 convention, linkable with MSVC and MinGW
+    Windows,
+    // macOS, which follows System V for calls but assembles to Mach-O
+    Darwin,
+}
+impl Abi {
+    pub fn integer_argument_registers(&self) -> &'static [Register] {
+        match self {
+            Abi::SystemV | Abi::Darwin => &[
+                Register::RDI, Register::RSI, Register::RDX,
+                Register::RCX, Register::R8, Register::R9
+            ],
+            Abi::Windows => &[Register::RCX, Register::RDX, Register::R8, Register::R9],
+        }
+    }
+    pub fn double_argument_registers(&self) -> &'static [Register] {
+        match self {
+            Abi::SystemV | Abi::Darwin => &[
+                Register::XMM0, Register::XMM1, Register::XMM2, Register::XMM3,
+                Register::XMM4, Register::XMM5, Register::XMM6, Register::XMM7
+            ],
+            // each argument takes a slot, so doubles share the 4 positions with integers
+            Abi::Windows => &[Register::XMM0, Register::XMM1, Register::XMM2, Register::XMM3],
+        }
+    }
+    pub fn callee_saved_registers(&self) -> &'static [Register] {
+        /*
+        Registers a function has to restore before returning, out of the
+        ones the backend can emit. System V only preserves %rbx, %rbp and
+        %r12-%r15, which are never used outside of the frame setup, while
+        Windows also preserves %rdi, %rsi and the upper SSE registers
+        */
+        match self {
+            Abi::SystemV | Abi::Darwin => &[],
+            Abi::Windows => &[
+                Register::RDI, Register::RSI,
+                Register::XMM6, Register::XMM7, Register::XMM8, Register::XMM9,
+                Register::XMM10, Register::XMM11, Register::XMM12, Register::XMM13,
+                Register::XMM14, Register::XMM15
+            ],
+        }
+    }
+    pub fn shadow_space_size(&self) -> u64 {
+        // space the caller reserves above the return address for the callee
+        match self {
+            Abi::SystemV | Abi::Darwin => 0,
+            Abi::Windows => 32,
+        }
+    }
+    pub fn symbol_name(&self, name: &str) -> String {
+        // only Mach-O keeps the leading underscore of C names in 64-bit code
+        match self {
+            Abi::SystemV | Abi::Windows => name.to_string(),
+            Abi::Darwin => format!("_{name}"),
+        }
+    }
+    pub fn local_label_prefix(&self) -> &'static str {
+        // labels with this prefix are left out of the object file's symbol table
+        match self {
+            Abi::SystemV | Abi::Windows => ".L",
+            Abi::Darwin => "L",
+        }
+    }
+    pub fn has_gnu_stack_note(&self) -> bool {
+        // marks the stack as non-executable, which only ELF linkers understand
+        matches!(self, Abi::SystemV)
+    }
+    pub fn has_symbol_interposition(&self) -> bool {
+        /*
+        Whether global names in a shared library can be bound to a
+        definition in another module at load time. Mach-O and PE resolve
+        references to a module's own globals when it is linked
+        */
+        matches!(self, Abi::SystemV)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_calling_conventions() {
+        assert_eq!(Abi::SystemV.integer_argument_registers()[0], Register::RDI);
+        assert_eq!(Abi::Windows.integer_argument_registers()[0], Register::RCX);
+        assert_eq!(Abi::Windows.double_argument_registers().len(), 4);
+        assert_eq!(Abi::SystemV.shadow_space_size(), 0);
+        assert_eq!(Abi::Windows.shadow_space_size(), 32);
+        assert!(!Abi::Windows.has_gnu_stack_note());
+        assert_eq!(Abi::Darwin.integer_argument_registers()[0], Register::RDI);
+        assert!(Abi::SystemV.callee_saved_registers().is_empty());
+        assert!(Abi::Windows.callee_saved_registers().contains(&Register::XMM14));
+        assert!(!Abi::Windows.callee_saved_registers().contains(&Register::XMM5));
+    }
+
+    #[test]
+    fn test_symbol_names() {
+        assert_eq!(Abi::SystemV.symbol_name("main"), "main");
+        assert_eq!(Abi::Windows.symbol_name("main"), "main");
+        assert_eq!(Abi::Darwin.symbol_name("main"), "_main");
+        assert!(!Abi::Darwin.has_gnu_stack_note());
+        assert_eq!(Abi::Darwin.local_label_prefix(), "L");
+    }
+}
@@ -1,39 +1,186 @@
-use std::collections::HashMap;
-use std::fmt::format;
+use std::cell::RefCell;
+use std::collections::{HashMap, HashSet};
+use std::fmt::{format, Display, Formatter};
+use std::io::Write;
+use std::rc::Rc;
 use crate::parser::parse::{
-    Expression, ExpressionVariant, Statement,
+    Expression, ExpressionVariant, Statement, StatementVariant,
     SupportedBinaryOperators, SupportedUnaryOperators
 };
 use helpers::ToStackAllocated;
+use crate::asm_gen::abi::Abi;
 use crate::asm_gen::binary_instruction::{AsmBinaryInstruction, AsmBinaryOperators};
 use crate::asm_gen::helpers;
 use crate::asm_gen::helpers::{
     AppendOnlyHashMap, BufferedHashMap, DiffableHashMap, StackAllocationResult
 };
+use crate::asm_gen::cmp_instruction::{AsmCompareInstruction, AsmSetConditional, ConditionCode};
+use crate::asm_gen::double_conversion::AsmDoubleConversion;
 use crate::asm_gen::interger_division::AsmIntegerDivision;
+use crate::asm_gen::fixup::fix_instructions;
+use crate::asm_gen::peephole::optimize_instructions;
+use crate::asm_gen::registers::Register;
 use crate::asm_gen::unary_instruction::AsmUnaryInstruction;
-use crate::parser::parser_helpers::{ParseError, PoppedTokenContext};
-use crate::tacky::tacky_symbols::{tacky_gen_from_filepath, BinaryInstruction, TackyFunction, TackyInstruction, TackyProgram, TackyValue, TackyVariable};
+use crate::lexer::tokens::SourceLocation;
+use crate::parser::c_types::CType;
+use crate::parser::parser_helpers::PoppedTokenContext;
+use crate::errors::CompilerError;
+use crate::tacky::tacky_symbols::{tacky_gen_from_filepath, BinaryInstruction, ConversionKind, TackyFunction, TackyInstruction, TackyProgram, TackyStaticVariable, TackyValue, TackyVariable};
 
 const STACK_VARIABLE_SIZE: u64 = 4; // bytes
+const SAVED_REGISTER_SLOT_SIZE: u64 = 16; // bytes
 pub const TAB: &str = "    ";
 pub const SCRATCH_REGISTER: &str = "%r10d";
 pub const MUL_SCRATCH_REGISTER: &str = "%r11d";
+pub const SCRATCH_REGISTER_64: &str = "%r10";
+pub const MUL_SCRATCH_REGISTER_64: &str = "%r11";
+pub const SCRATCH_REGISTER_8: &str = "%r10b";
+pub const MUL_SCRATCH_REGISTER_8: &str = "%r11b";
+pub const SCRATCH_REGISTER_XMM: &str = "%xmm14";
+pub const MUL_SCRATCH_REGISTER_XMM: &str = "%xmm15";
 const STACK_REGISTER: &str = "%rsp";
 // base of current stack frame
 const BASE_REGISTER: &str = "%rbp";
+// keeps jump labels out of the symbol table, so they can't clash with functions
+pub const LOCAL_LABEL_PREFIX: &str = ".L";
+// .file number of the source file that .loc directives refer to
+const DEBUG_FILE_NUMBER: u64 = 1;
 
 
 #[derive(Debug)]
 pub enum AsmGenError {
     InvalidInstructionType(String),
     UnsupportedInstruction(String),
-    ParseError(ParseError)
+    // writing the generated code out failed
+    IoError(std::io::Error),
+}
+impl Display for AsmGenError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AsmGenError::InvalidInstructionType(msg) => {
+                write!(f, "AsmGenError: Invalid instruction type: {}", msg)
+            },
+            AsmGenError::UnsupportedInstruction(msg) => {
+                write!(f, "AsmGenError: Unsupported instruction: {}", msg)
+            },
+            AsmGenError::IoError(err) => write!(f, "AsmGenError: I/O error: {}", err),
+        }
+    }
+}
+impl std::error::Error for AsmGenError {}
+impl From<std::io::Error> for AsmGenError {
+    fn from(err: std::io::Error) -> Self {
+        AsmGenError::IoError(err)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AsmType {
+    Byte, // 1 byte
+    Longword, // 4 bytes
+    Quadword, // 8 bytes
+    Double, // 8 byte floating point value, kept in SSE registers
+}
+impl AsmType {
+    pub fn from_c_type(c_type: &CType) -> AsmType {
+        if c_type.is_double() {
+            return AsmType::Double;
+        }
+        match c_type.size() {
+            1 => AsmType::Byte,
+            8 => AsmType::Quadword,
+            _ => AsmType::Longword,
+        }
+    }
+    pub fn from_tacky_value(tacky_value: &TackyValue) -> AsmType {
+        Self::from_c_type(&tacky_value.get_type())
+    }
+    pub fn suffix(&self) -> &'static str {
+        // instruction suffix for operands of this size
+        match self {
+            AsmType::Byte => "b",
+            AsmType::Longword => "l",
+            AsmType::Quadword => "q",
+            AsmType::Double => "sd",
+        }
+    }
+    pub fn scratch_register(&self) -> &'static str {
+        match self {
+            AsmType::Byte => SCRATCH_REGISTER_8,
+            AsmType::Longword => SCRATCH_REGISTER,
+            AsmType::Quadword => SCRATCH_REGISTER_64,
+            AsmType::Double => SCRATCH_REGISTER_XMM,
+        }
+    }
+    pub fn mul_scratch_register(&self) -> &'static str {
+        match self {
+            AsmType::Byte => MUL_SCRATCH_REGISTER_8,
+            AsmType::Longword => MUL_SCRATCH_REGISTER,
+            AsmType::Quadword => MUL_SCRATCH_REGISTER_64,
+            AsmType::Double => MUL_SCRATCH_REGISTER_XMM,
+        }
+    }
 }
 
 pub trait AsmSymbol {
-    fn to_asm_code(self) -> Result<String, AsmGenError>;
+    fn to_asm_code(&self) -> Result<String, AsmGenError>;
+    fn to_cached_asm_code(
+        &self, _operand_codes: &mut OperandCodeCache
+    ) -> Result<String, AsmGenError> {
+        // symbols with operands override this to reuse already rendered operands
+        self.to_asm_code()
+    }
+    fn emit_asm_code(&self, writer: &mut impl Write) -> Result<(), AsmGenError> where Self: Sized {
+        /*
+        Writes the code out instead of returning it. Symbols that make up
+        most of a program override this to stream their code line by line
+        rather than concatenating it all into one string first
+        */
+        writer.write_all(self.to_asm_code()?.as_bytes())?;
+        Ok(())
+    }
 }
+
+fn emit_to_string(symbol: &impl AsmSymbol) -> Result<String, AsmGenError> {
+    let mut buffer: Vec<u8> = vec![];
+    symbol.emit_asm_code(&mut buffer)?;
+    Ok(String::from_utf8(buffer).expect("assembly code is always valid UTF-8"))
+}
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+enum OperandCacheKey {
+    Register(Register),
+    Stack(u64),
+    Memory(Register, i64),
+}
+
+#[derive(Default)]
+pub struct OperandCodeCache {
+    /*
+    Registers and stack slots make up most operands and are used over
+    and over within a function, so each is only formatted once and then
+    shared. Immediates and globals are formatted every time
+    */
+    codes: HashMap<OperandCacheKey, Rc<str>>,
+}
+impl OperandCodeCache {
+    pub fn operand_code(&mut self, operand: &AsmOperand) -> Result<Rc<str>, AsmGenError> {
+        let key = match operand {
+            AsmOperand::Register(register) => OperandCacheKey::Register(register.clone()),
+            AsmOperand::Stack(stack_address) => OperandCacheKey::Stack(stack_address.offset),
+            AsmOperand::Memory(memory_address) => OperandCacheKey::Memory(
+                memory_address.base.clone(), memory_address.offset
+            ),
+            _ => return Ok(Rc::from(operand.to_asm_code()?))
+        };
+        if let Some(code) = self.codes.get(&key) {
+            return Ok(code.clone());
+        }
+        let code: Rc<str> = Rc::from(operand.to_asm_code()?);
+        self.codes.insert(key, code.clone());
+        Ok(code)
+    }
+}
+
 pub trait HasPopContexts: Clone {
     fn _get_pop_contexts(&self) -> &Vec<PoppedTokenContext>;
     fn _add_pop_context(&mut self, pop_context: PoppedTokenContext);
@@ -60,31 +207,241 @@ pub trait HasPopContexts: Clone {
     }
 }
 
+#[derive(Clone, Debug)]
+pub struct AsmStaticVariable {
+    pub(crate) name: String,
+    pub(crate) var_type: CType,
+    // bit pattern of the initializer, None for zero initialized variables
+    pub(crate) initial_value: Option<u64>,
+    // also define a local label for position independent references
+    pub(crate) local_alias: bool,
+    pub(crate) abi: Abi,
+}
+impl AsmStaticVariable {
+    pub fn from_tacky_static_variable(
+        static_variable: TackyStaticVariable
+    ) -> AsmStaticVariable {
+        AsmStaticVariable {
+            name: static_variable.variable.name,
+            var_type: static_variable.variable.var_type,
+            initial_value: static_variable.initial_value.map(
+                |ast_constant| ast_constant.to_bits()
+            ),
+            local_alias: false,
+            abi: Abi::default(),
+        }
+    }
+}
+impl AsmSymbol for AsmStaticVariable {
+    fn to_asm_code(&self) -> Result<String, AsmGenError> {
+        /*
+            .globl <name>
+            .data
+            .balign <alignment>
+        <name>:
+            .long <value>
+        zero initialized variables go in .bss as .zero <size> instead,
+        or are declared with .zerofill for Mach-O, which reserves space in
+        __bss without the assembler having to emit any zeros
+        */
+        let size = self.var_type.size();
+        let alignment = self.var_type.alignment();
+        let mut code = String::new();
+        code.push_str(&format!("{TAB}.globl {}\n", self.name));
+        let initial_value = self.initial_value.filter(|value| *value != 0);
+        if initial_value.is_none() && self.abi == Abi::Darwin {
+            code.push_str(&format!(
+                "{TAB}.zerofill __DATA,__bss,{},{size},{}\n",
+                self.name, alignment.trailing_zeros()
+            ));
+            return Ok(code);
+        }
+        match initial_value {
+            Some(_) => code.push_str(&format!("{TAB}.data\n")),
+            None => code.push_str(&format!("{TAB}.bss\n"))
+        }
+        code.push_str(&format!("{TAB}.balign {alignment}\n"));
+        code.push_str(&format!("{}:\n", self.name));
+        if self.local_alias {
+            code.push_str(&format!("{}:\n", DataAddress::local_alias(&self.name)));
+        }
+        match initial_value {
+            Some(value) => {
+                let directive = match size {
+                    1 => ".byte",
+                    4 => ".long",
+                    8 => ".quad",
+                    _ => return Err(AsmGenError::UnsupportedInstruction(format!(
+                        "Can't initialize {} bytes for {}", size, self.name
+                    )))
+                };
+                code.push_str(&format!("{TAB}{directive} {}\n", value as i64));
+            },
+            None => code.push_str(&format!("{TAB}.zero {size}\n"))
+        }
+        Ok(code)
+    }
+}
+
 pub struct AsmProgram {
+    pub(crate) static_variables: Vec<AsmStaticVariable>,
     pub(crate) function: AsmFunction,
+    // run the peephole optimizer on the fixed up instructions
+    pub(crate) peephole_optimize: bool,
+    // address the stack through %rsp and don't set up %rbp at all
+    pub(crate) omit_frame_pointer: bool,
+    // emit DWARF line info pointing into this source file
+    pub(crate) debug_source_file: Option<String>,
+    // keep the output linkable into shared objects
+    pub(crate) position_independent: bool,
+    // platform the output is assembled and linked on
+    pub(crate) abi: Abi,
 }
 impl AsmProgram {
     pub fn new(function: AsmFunction) -> AsmProgram {
-        AsmProgram { function }
+        AsmProgram {
+            static_variables: vec![], function,
+            peephole_optimize: false, omit_frame_pointer: false,
+            debug_source_file: None, position_independent: false,
+            abi: Abi::default()
+        }
     }
     pub fn from_tacky_program(
         tacky_program: TackyProgram
     ) -> Self {
-        Self::new(AsmFunction::from_tacky_function(tacky_program.function))
+        let mut program = Self::new(
+            AsmFunction::from_tacky_function(tacky_program.function)
+        );
+        program.static_variables = tacky_program.static_variables.into_iter().map(
+            AsmStaticVariable::from_tacky_static_variable
+        ).collect();
+        program
     }
-    fn _to_asm_code(self) -> Result<String, AsmGenError> {
-        let mut code = self.function.to_asm_code()?;
-        code.push_str(".section .note.GNU-stack,\"\",@progbits\n");
-        Ok(code)
+    pub fn with_peephole_optimization(mut self) -> Self {
+        self.peephole_optimize = true;
+        self
+    }
+    pub fn with_omitted_frame_pointer(mut self) -> Self {
+        self.omit_frame_pointer = true;
+        self
+    }
+    pub fn with_debug_info(mut self, source_file: String) -> Self {
+        self.debug_source_file = Some(source_file);
+        self
+    }
+    pub fn with_position_independent_code(mut self) -> Self {
+        self.position_independent = true;
+        self
+    }
+    pub fn with_abi(mut self, abi: Abi) -> Self {
+        self.abi = abi;
+        self
+    }
+    fn with_abi_conventions(mut self) -> Self {
+        // renames functions and globals to what the platform's C compiler calls them
+        let abi = self.abi.clone();
+        self.function.name = abi.symbol_name(&self.function.name);
+        self.function.local_label_prefix = abi.local_label_prefix();
+        for static_variable in &mut self.static_variables {
+            static_variable.name = abi.symbol_name(&static_variable.name);
+            static_variable.abi = abi.clone();
+        }
+        self.function.instructions = self.function.instructions.into_iter().map(
+            |instruction| instruction.map_operands(&|operand| match operand {
+                AsmOperand::Data(data_address) => AsmOperand::Data(DataAddress::new(
+                    abi.symbol_name(&data_address.name)
+                )),
+                operand => operand
+            })
+        ).collect();
+        self
+    }
+    fn with_local_data_aliases(mut self) -> Self {
+        /*
+        An ELF shared object can't reference its own global variables
+        through plain RIP-relative relocations, as the dynamic linker may
+        bind their names to a definition in another module. Every global is
+        defined in this file, so references go through a local label at
+        the same address instead, which the assembler resolves without
+        any relocation against the global name. Function calls will
+        likewise need to go through name@PLT once they are supported
+        */
+        for static_variable in &mut self.static_variables {
+            static_variable.local_alias = true;
+        }
+        self.function.instructions = self.function.instructions.into_iter().map(
+            |instruction| instruction.map_operands(&|operand| match operand {
+                AsmOperand::Data(data_address) => AsmOperand::Data(DataAddress::new(
+                    DataAddress::local_alias(&data_address.name)
+                )),
+                operand => operand
+            })
+        ).collect();
+        self
+    }
+    fn fixed_up(mut self) -> Self {
+        // makes every instruction valid x86-64, then cleans up the result
+        let mut instructions = fix_instructions(self.function.instructions);
+        if self.peephole_optimize {
+            instructions = optimize_instructions(instructions);
+        }
+        self.function.instructions = instructions;
+        self
+    }
+    fn _emit_asm_code(&self, writer: &mut impl Write) -> Result<(), AsmGenError> {
+        /*
+        The assembler builds the DWARF line table from the .file and .loc
+        directives, so nothing else is needed for gdb to step through
+        the source. Static variables switch sections, so they are
+        emitted after the function
+        */
+        if let Some(source_file) = &self.debug_source_file {
+            writeln!(writer, "{TAB}.file {DEBUG_FILE_NUMBER} {:?}", source_file)?;
+        }
+        self.function.emit_asm_code(writer)?;
+        for static_variable in &self.static_variables {
+            static_variable.emit_asm_code(writer)?;
+        }
+        if self.abi.has_gnu_stack_note() {
+            writeln!(writer, ".section .note.GNU-stack,\"\",@progbits")?;
+        }
+        Ok(())
     }
 }
 impl AsmSymbol for AsmProgram {
-    fn to_asm_code(self) -> Result<String, AsmGenError> {
+    fn to_asm_code(&self) -> Result<String, AsmGenError> {
+        emit_to_string(self)
+    }
+    fn emit_asm_code(&self, writer: &mut impl Write) -> Result<(), AsmGenError> {
+        // stack allocation makes the copy that the remaining passes rewrite
         let stack_alloc_map: AppendOnlyHashMap<u64, u64> =
             AppendOnlyHashMap::new();
-        let stack_allocated_program =
-            self.to_stack_allocated(0, &stack_alloc_map).0;
-        Ok(stack_allocated_program._to_asm_code()?)
+        let (mut program, alloc_result) =
+            self.to_stack_allocated(0, &stack_alloc_map);
+        if program.debug_source_file.is_none() {
+            // .loc directives need a .file directive to refer to
+            program.function.start_location = None;
+            program.function.instructions.retain(
+                |instruction| !matches!(instruction, AsmInstruction::Loc(_))
+            );
+        }
+        program = program.with_abi_conventions();
+        if program.position_independent && program.abi.has_symbol_interposition() {
+            program = program.with_local_data_aliases();
+        }
+        /*
+        Fixing up can introduce scratch registers, so the registers the
+        function has to preserve are only known afterwards. The frame
+        covers every stack slot handed out during allocation
+        */
+        program = program.fixed_up();
+        let saved_registers = program.function.used_registers(
+            program.abi.callee_saved_registers()
+        );
+        let frame = StackFrame::new(alloc_result.new_stack_value, program.omit_frame_pointer)
+            .with_saved_registers(saved_registers);
+        program.function = program.function.with_frame(frame);
+        program._emit_asm_code(writer)
     }
 }
 impl ToStackAllocated for AsmProgram {
@@ -95,7 +452,13 @@ impl ToStackAllocated for AsmProgram {
         let (new_function, alloc_result) =
             self.function.to_stack_allocated(stack_value, allocations);
         let new_program = AsmProgram {
+            static_variables: self.static_variables.clone(),
             function: new_function,
+            peephole_optimize: self.peephole_optimize,
+            omit_frame_pointer: self.omit_frame_pointer,
+            debug_source_file: self.debug_source_file.clone(),
+            position_independent: self.position_independent,
+            abi: self.abi.clone(),
         };
 
         (new_program, alloc_result)
@@ -106,6 +469,11 @@ pub struct AsmFunction {
     pub(crate) name: String,
     pub(crate) instructions: Vec<AsmInstruction>,
     pub(crate) pop_contexts: Vec<PoppedTokenContext>,
+    // sized once every pseudo register has been allocated a stack slot
+    pub(crate) frame: StackFrame,
+    // where the function is defined, so that the prologue has a source line
+    pub(crate) start_location: Option<SourceLocation>,
+    pub(crate) local_label_prefix: &'static str,
 }
 impl AsmFunction {
     pub fn new(name: String) -> AsmFunction {
@@ -113,6 +481,9 @@ impl AsmFunction {
             name,
             instructions: vec![],
             pop_contexts: vec![],
+            frame: StackFrame::default(),
+            start_location: None,
+            local_label_prefix: LOCAL_LABEL_PREFIX,
         }
     }
     pub fn add_instruction(&mut self, instruction: AsmInstruction) {
@@ -128,6 +499,9 @@ impl AsmFunction {
         tacky_function: TackyFunction
     ) -> AsmFunction {
         let mut asm_function = AsmFunction::new(tacky_function.name_to_string());
+        asm_function.start_location = tacky_function.pop_context.and_then(
+            |pop_context| pop_context.start_location
+        );
         for tacky_instruction in tacky_function.instructions {
             let asm_instructions =
                 AsmInstruction::from_tacky_instruction(tacky_instruction);
@@ -135,6 +509,37 @@ impl AsmFunction {
         }
         asm_function
     }
+    pub fn with_frame(mut self, frame: StackFrame) -> AsmFunction {
+        if frame.omit_frame_pointer {
+            self.instructions = self.instructions.into_iter().map(
+                |instruction| instruction.map_operands(
+                    &|operand| frame.relative_to_stack_pointer(operand)
+                )
+            ).collect();
+        }
+        self.frame = frame;
+        self
+    }
+    pub fn used_registers(&self, candidates: &[Register]) -> Vec<Register> {
+        // the candidates that any operand of the function reads or writes
+        let used = RefCell::new(HashSet::new());
+        for instruction in &self.instructions {
+            instruction.clone().map_operands(&|operand| {
+                match &operand {
+                    AsmOperand::Register(register) => {
+                        used.borrow_mut().insert(register.to_quadword());
+                    },
+                    AsmOperand::Memory(memory_address) => {
+                        used.borrow_mut().insert(memory_address.base.to_quadword());
+                    },
+                    _ => {}
+                }
+                operand
+            });
+        }
+        let used = used.into_inner();
+        candidates.iter().filter(|register| used.contains(*register)).cloned().collect()
+    }
 }
 impl HasPopContexts for AsmFunction {
     fn _get_pop_contexts(&self) -> &Vec<PoppedTokenContext> {
@@ -145,30 +550,46 @@ impl HasPopContexts for AsmFunction {
     }
 }
 impl AsmSymbol for AsmFunction {
-    fn to_asm_code(self) -> Result<String, AsmGenError> {
+    fn to_asm_code(&self) -> Result<String, AsmGenError> {
+        emit_to_string(self)
+    }
+    fn emit_asm_code(&self, writer: &mut impl Write) -> Result<(), AsmGenError> {
         /*
         TODO: Should there be an extra layer for abstracted
             assembly instructions to architecturally specific ones?
         */
-        let mut code = "".to_string();
         // println!("ASM_INSTRUCTIONS: {:?}", self.instructions);
+        write!(writer, "{TAB}.globl {}{}", self.name, self.contexts_to_string())?;
+        writeln!(writer, "{}:", self.name)?;
+        if let Some(location) = self.start_location {
+            writeln!(writer, "{TAB}{}", AsmInstruction::Loc(location).to_asm_code()?)?;
+        }
 
-        code.push_str(&format!("{TAB}.globl {}", self.name));
-        code.push_str(&*self.contexts_to_string());
-        code.push_str(&format!("{}:\n", self.name));
-
-        code.push_str(&format!("{TAB}pushq {BASE_REGISTER}\n"));
-        code.push_str(&format!("{TAB}movq {STACK_REGISTER}, {BASE_REGISTER}\n"));
+        for line in self.frame.prologue() {
+            writeln!(writer, "{TAB}{line}")?;
+        }
 
-        for instruction in self.instructions {
-            let inner_code = &instruction.to_asm_code()?;
+        let mut operand_codes = OperandCodeCache::default();
+        for instruction in &self.instructions {
+            let is_label = matches!(instruction, AsmInstruction::Label(_));
+            if matches!(instruction, AsmInstruction::Ret) {
+                for line in self.frame.epilogue() {
+                    writeln!(writer, "{TAB}{line}")?;
+                }
+            }
+            let inner_code = &instruction.to_asm_code_with_label_prefix(
+                self.local_label_prefix, &mut operand_codes
+            )?;
+            if is_label {
+                writeln!(writer, "{}", inner_code)?;
+                continue;
+            }
             let indented_inner_code = indent::indent_all_with(TAB, inner_code);
             // println!("Indented inner code: {}", indented_inner_code);
-            code.push_str(&*indented_inner_code);
-            code.push_str("\n");
+            writeln!(writer, "{}", indented_inner_code)?;
         }
 
-        Ok(code)
+        Ok(())
     }
 }
 impl ToStackAllocated for AsmFunction {
@@ -195,6 +616,9 @@ impl ToStackAllocated for AsmFunction {
             name: self.name.clone(),
             instructions: new_instructions,
             pop_contexts: self.pop_contexts.clone(),
+            frame: self.frame.clone(),
+            start_location: self.start_location,
+            local_label_prefix: self.local_label_prefix,
         };
         let new_stack_allocations =
             alloc_buffer.build_changes().to_hash_map();
@@ -205,24 +629,6 @@ impl ToStackAllocated for AsmFunction {
     }
 }
 
-#[derive(Clone, Debug)]
-pub enum Register {
-    EAX, // division quotient register 1 + division result register
-    EDX, // division quotient register 2 + division remainder register
-    R10D, // scratch register
-    R11D,
-}
-impl AsmSymbol for Register {
-    fn to_asm_code(self) -> Result<String, AsmGenError> {
-        match self {
-            Register::EAX => Ok("%eax".to_string()),
-            Register::R10D => Ok("%r10d".to_string()),
-            Register::EDX => Ok("%edx".to_string()),
-            Register::R11D => Ok("%r11d".to_string()),
-        }
-    }
-}
-
 #[derive(Debug, Clone)]
 pub struct PseudoRegister {
     pub(crate) id: u64,
@@ -273,49 +679,110 @@ impl PseudoRegister {
     }
 }
 
+impl PseudoRegister {
+    pub fn get_size(&self) -> u64 {
+        // size of the value stored in the pseudo register in bytes
+        match &self.tacky_var {
+            Some(tacky_var) => tacky_var.var_type.size(),
+            None => STACK_VARIABLE_SIZE
+        }
+    }
+    pub fn get_alignment(&self) -> u64 {
+        match &self.tacky_var {
+            Some(tacky_var) => tacky_var.var_type.alignment(),
+            None => STACK_VARIABLE_SIZE
+        }
+    }
+}
+
 #[derive(Clone, Debug)]
 pub enum AsmInstruction {
     Mov(MovInstruction),
+    // move a longword into a quadword with sign extension
+    Movsx(MovsxInstruction),
+    // move a longword into a quadword with zero extension
+    MovZeroExtend(MovZeroExtendInstruction),
+    Lea(LeaInstruction),
     Unary(AsmUnaryInstruction),
     Binary(AsmBinaryInstruction),
+    Compare(AsmCompareInstruction),
+    SetConditional(AsmSetConditional),
+    Jmp(String),
+    // jump to the label if the condition holds for the last comparison
+    JmpConditional(ConditionCode, String),
+    Label(String),
     IntegerDivision(AsmIntegerDivision),
-    SignExtension,
-    AllocateStack(StackAllocation),
+    // conversions between doubles and integers
+    DoubleConversion(AsmDoubleConversion),
+    SignExtension(AsmType),
+    // labels the instructions generated for a piece of the source code
+    Comment(String),
+    // source location of the instructions that follow, for debuggers
+    Loc(SourceLocation),
     Ret,
 }
 impl AsmSymbol for AsmInstruction {
-    fn to_asm_code(self) -> Result<String, AsmGenError> {
+    fn to_asm_code(&self) -> Result<String, AsmGenError> {
+        self.to_cached_asm_code(&mut OperandCodeCache::default())
+    }
+    fn to_cached_asm_code(
+        &self, operand_codes: &mut OperandCodeCache
+    ) -> Result<String, AsmGenError> {
         match self {
             AsmInstruction::Mov(mov_instruction) => {
-                Ok(mov_instruction.to_asm_code()?)
+                mov_instruction.to_cached_asm_code(operand_codes)
+            },
+            AsmInstruction::Movsx(movsx_instruction) => {
+                movsx_instruction.to_cached_asm_code(operand_codes)
+            },
+            AsmInstruction::MovZeroExtend(mov_zero_extend_instruction) => {
+                mov_zero_extend_instruction.to_cached_asm_code(operand_codes)
+            },
+            AsmInstruction::Lea(lea_instruction) => {
+                lea_instruction.to_cached_asm_code(operand_codes)
             },
             AsmInstruction::Unary(unary_instruction) => {
-                Ok(unary_instruction.to_asm_code()?)
+                unary_instruction.to_cached_asm_code(operand_codes)
             },
             AsmInstruction::Binary(binary_instruction) => {
-                Ok(binary_instruction.to_asm_code()?)
+                binary_instruction.to_cached_asm_code(operand_codes)
             }
-            AsmInstruction::AllocateStack(stack_allocation) => {
-                Ok(stack_allocation.to_asm_code()?)
+            AsmInstruction::Compare(compare_instruction) => {
+                compare_instruction.to_cached_asm_code(operand_codes)
+            },
+            AsmInstruction::SetConditional(set_conditional) => {
+                set_conditional.to_cached_asm_code(operand_codes)
+            },
+            AsmInstruction::Jmp(_) | AsmInstruction::JmpConditional(_, _) |
+            AsmInstruction::Label(_) => {
+                self.to_asm_code_with_label_prefix(LOCAL_LABEL_PREFIX, operand_codes)
             },
             AsmInstruction::IntegerDivision(int_div_instruction) => {
-                Ok(int_div_instruction.to_asm_code()?)
+                int_div_instruction.to_cached_asm_code(operand_codes)
             },
-            AsmInstruction::SignExtension => {
-                Ok("cdq".parse().unwrap())
-            }
-            AsmInstruction::Ret => {
-                let mut code = String::new();
-                code.push_str(&format!("movq {BASE_REGISTER}, {STACK_REGISTER}\n"));
-                code.push_str(&format!("popq {BASE_REGISTER}\n"));
-                code.push_str("ret\n");
-                Ok(code.to_string())
+            AsmInstruction::DoubleConversion(double_conversion) => {
+                double_conversion.to_cached_asm_code(operand_codes)
             },
-            _ => {
-                Err(AsmGenError::InvalidInstructionType(
-                    format!("Unsupported AsmInstruction: {:?}", self)
-                ))
+            AsmInstruction::SignExtension(asm_type) => {
+                // sign extend EAX into EDX (or RAX into RDX)
+                match asm_type {
+                    // byte operands are always promoted before division
+                    AsmType::Byte => Err(AsmGenError::UnsupportedInstruction(
+                        "Cannot sign extend a byte into DL".to_string()
+                    )),
+                    AsmType::Longword => Ok("cdq".to_string()),
+                    AsmType::Quadword => Ok("cqo".to_string()),
+                    AsmType::Double => Err(AsmGenError::UnsupportedInstruction(
+                        "Cannot sign extend a double".to_string()
+                    )),
+                }
             }
+            AsmInstruction::Comment(comment) => Ok(format!("# {}", comment)),
+            AsmInstruction::Loc(location) => Ok(format!(
+                ".loc {DEBUG_FILE_NUMBER} {} {}", location.line, location.column
+            )),
+            // the epilogue is emitted by the function, since it depends on the frame
+            AsmInstruction::Ret => Ok("ret".to_string()),
         }
     }
 }
@@ -325,35 +792,49 @@ impl AsmInstruction {
     ) -> Vec<Self> {
         match tacky_instruction {
             TackyInstruction::Return(tacky_value) => {
+                let asm_type = AsmType::from_tacky_value(&tacky_value);
                 let src_operand = match tacky_value {
                     TackyValue::Constant(ast_constant) => {
-                        let value = ast_constant.to_u64().unwrap();
+                        let value = ast_constant.to_bits();
                         let asm_value = AsmImmediateValue::new(value)
                             .with_added_pop_context(ast_constant.pop_context.clone());
                         AsmOperand::ImmediateValue(asm_value)
                     },
-                    TackyValue::Var(tacky_var) => {
-                        // Handle variable return case
-                        AsmOperand::Pseudo(PseudoRegister::from_tacky_var(tacky_var))
-                    },
+                    tacky_var => AsmOperand::from_tacky_value(tacky_var),
                 };
-                let dst_operand = AsmOperand::Register(Register::EAX);
-                let mov_instruction = MovInstruction::new(src_operand, dst_operand);
+                let dst_operand = AsmOperand::Register(Register::ax(&asm_type));
+                let mov_instruction = MovInstruction::new(
+                    asm_type, src_operand, dst_operand
+                );
                 vec![
                     AsmInstruction::Mov(mov_instruction),
                     AsmInstruction::Ret
                 ]
             },
             TackyInstruction::UnaryInstruction(unary_instruction) => {
+                let asm_type = AsmType::from_tacky_value(&unary_instruction.src);
                 let src_operand = AsmOperand::from_tacky_value(unary_instruction.src);
                 let dst_operand = AsmOperand::from_tacky_value(
                     TackyValue::Var(unary_instruction.dst)
                 );
+                if matches!(unary_instruction.operator, SupportedUnaryOperators::Not) {
+                    /*
+                    !x is x == 0:
+                    Cmp($0, src)
+                    Mov(0, dst)
+                    SetCC(E, dst)
+                    */
+                    let zero_operand = AsmOperand::ImmediateValue(AsmImmediateValue::new(0));
+                    return AsmBinaryInstruction::build_comparison_instructions(
+                        src_operand, zero_operand, dst_operand, ConditionCode::E, asm_type
+                    );
+                }
                 let asm_mov_instruction = MovInstruction::new(
-                    src_operand, dst_operand.clone()
+                    asm_type.clone(), src_operand, dst_operand.clone()
                 );
                 let asm_unary_instruction = AsmUnaryInstruction {
                     operator: unary_instruction.operator,
+                    asm_type,
                     destination: dst_operand
                 };
                 vec![
@@ -362,17 +843,269 @@ impl AsmInstruction {
                 ]
             },
             TackyInstruction::BinaryInstruction(binary_instruction) => {
-                AsmBinaryInstruction::unpack_from_tacky(binary_instruction)
+                // points debuggers at the operation within a longer statement
+                let start_location = binary_instruction.pop_context.as_ref().and_then(
+                    |pop_context| pop_context.start_location
+                );
+                let mut instructions: Vec<AsmInstruction> =
+                    start_location.map(AsmInstruction::Loc).into_iter().collect();
+                instructions.extend(AsmBinaryInstruction::unpack_from_tacky(binary_instruction));
+                instructions
             },
-            _ => {
-                panic!(
-                    "Unsupported TackyInstruction for AsmInstruction conversion: {:?}",
-                    tacky_instruction
+            TackyInstruction::CopyInstruction(copy_instruction) => {
+                let asm_type = AsmType::from_tacky_value(&copy_instruction.src);
+                let src_operand = AsmOperand::from_tacky_value(copy_instruction.src);
+                let dst_operand = AsmOperand::from_tacky_value(
+                    TackyValue::Var(copy_instruction.dst)
+                );
+                vec![AsmInstruction::Mov(MovInstruction::new(
+                    asm_type, src_operand, dst_operand
+                ))]
+            },
+            TackyInstruction::ConvertInstruction(convert_instruction) => {
+                let src_type = AsmType::from_tacky_value(&convert_instruction.src);
+                let dst_type = AsmType::from_c_type(&convert_instruction.dst.var_type);
+                let src_operand = AsmOperand::from_tacky_value(convert_instruction.src);
+                let dst_operand = AsmOperand::from_tacky_value(
+                    TackyValue::Var(convert_instruction.dst)
+                );
+                match convert_instruction.kind {
+                    ConversionKind::SignExtend => vec![AsmInstruction::Movsx(
+                        MovsxInstruction::new(src_type, dst_type, src_operand, dst_operand)
+                    )],
+                    ConversionKind::ZeroExtend => vec![AsmInstruction::MovZeroExtend(
+                        MovZeroExtendInstruction::new(
+                            src_type, dst_type, src_operand, dst_operand
+                        )
+                    )],
+                    ConversionKind::Truncate => {
+                        // the lower bytes of a little endian value
+                        // live at the same address as the value itself
+                        vec![AsmInstruction::Mov(MovInstruction::new(
+                            dst_type, src_operand, dst_operand
+                        ))]
+                    }
+                    kind => {
+                        // the integer side of the conversion decides the instruction size
+                        let int_type = if src_type == AsmType::Double { dst_type } else { src_type };
+                        vec![AsmInstruction::DoubleConversion(AsmDoubleConversion::new(
+                            kind, int_type, src_operand, dst_operand
+                        ))]
+                    }
+                }
+            },
+            TackyInstruction::GetAddressInstruction(get_address_instruction) => {
+                let src_operand = AsmOperand::from_tacky_value(
+                    TackyValue::Var(get_address_instruction.src)
+                );
+                let dst_operand = AsmOperand::from_tacky_value(
+                    TackyValue::Var(get_address_instruction.dst)
+                );
+                vec![AsmInstruction::Lea(LeaInstruction::new(src_operand, dst_operand))]
+            },
+            TackyInstruction::LoadInstruction(load_instruction) => {
+                /*
+                movq <ptr>, %r10
+                mov{t} <offset>(%r10), <dst>
+                */
+                let asm_type = AsmType::from_c_type(&load_instruction.dst.var_type);
+                let pointer_operand = AsmOperand::from_tacky_value(
+                    load_instruction.src_pointer
+                );
+                let dst_operand = AsmOperand::from_tacky_value(
+                    TackyValue::Var(load_instruction.dst)
+                );
+                let memory_operand = AsmOperand::Memory(MemoryAddress::new(
+                    Register::R10, load_instruction.offset as i64
+                ));
+                vec![
+                    AsmInstruction::Mov(MovInstruction::new(
+                        AsmType::Quadword, pointer_operand,
+                        AsmOperand::Register(Register::R10)
+                    )),
+                    AsmInstruction::Mov(MovInstruction::new(
+                        asm_type, memory_operand, dst_operand
+                    ))
+                ]
+            },
+            TackyInstruction::StoreInstruction(store_instruction) => {
+                /*
+                movq <ptr>, %r11
+                mov{t} <src>, <offset>(%r11)
+                %r11 is used since moving <src> may need %r10 as scratch
+                */
+                let asm_type = AsmType::from_tacky_value(&store_instruction.src);
+                let src_operand = AsmOperand::from_tacky_value(store_instruction.src);
+                let pointer_operand = AsmOperand::from_tacky_value(
+                    store_instruction.dst_pointer
+                );
+                let memory_operand = AsmOperand::Memory(MemoryAddress::new(
+                    Register::R11, store_instruction.offset as i64
+                ));
+                vec![
+                    AsmInstruction::Mov(MovInstruction::new(
+                        AsmType::Quadword, pointer_operand,
+                        AsmOperand::Register(Register::R11)
+                    )),
+                    AsmInstruction::Mov(MovInstruction::new(
+                        asm_type, src_operand, memory_operand
+                    ))
+                ]
+            },
+            TackyInstruction::JumpInstruction(jump_instruction) => {
+                vec![AsmInstruction::Jmp(jump_instruction.target.name_to_string())]
+            },
+            TackyInstruction::JumpIfZeroInstruction(jump_instruction) => {
+                Self::build_zero_jump_instructions(
+                    jump_instruction.condition, ConditionCode::E,
+                    jump_instruction.target.name_to_string()
+                )
+            },
+            TackyInstruction::JumpIfNotZeroInstruction(jump_instruction) => {
+                Self::build_zero_jump_instructions(
+                    jump_instruction.condition, ConditionCode::NE,
+                    jump_instruction.target.name_to_string()
+                )
+            },
+            TackyInstruction::LabelInstruction(label_instruction) => {
+                vec![AsmInstruction::Label(label_instruction.label.name_to_string())]
+            },
+            TackyInstruction::AnnotationStartInstruction(annotation) => {
+                let start_location = annotation.pop_context.and_then(
+                    |pop_context| pop_context.start_location
                 );
+                let mut instructions = vec![
+                    AsmInstruction::Comment(format!("begin: {}", annotation.description))
+                ];
+                instructions.extend(start_location.map(AsmInstruction::Loc));
+                instructions
+            },
+            TackyInstruction::AnnotationEndInstruction(annotation) => {
+                vec![AsmInstruction::Comment(format!("end: {}", annotation.description))]
             }
         }
     }
 }
+impl AsmInstruction {
+    fn build_zero_jump_instructions(
+        condition: TackyValue, condition_code: ConditionCode, label: String
+    ) -> Vec<AsmInstruction> {
+        /*
+        Cmp($0, condition)
+        JmpCC(condition_code, label)
+        */
+        let asm_type = AsmType::from_tacky_value(&condition);
+        let condition_operand = AsmOperand::from_tacky_value(condition);
+        let zero_operand = AsmOperand::ImmediateValue(AsmImmediateValue::new(0));
+        if asm_type == AsmType::Double {
+            return Self::build_double_zero_jump_instructions(
+                condition_operand, zero_operand, condition_code, label
+            );
+        }
+        vec![
+            AsmInstruction::Compare(AsmCompareInstruction::new(
+                asm_type, condition_operand, zero_operand
+            )),
+            AsmInstruction::JmpConditional(condition_code, label)
+        ]
+    }
+    fn build_double_zero_jump_instructions(
+        condition_operand: AsmOperand, zero_operand: AsmOperand,
+        condition_code: ConditionCode, label: String
+    ) -> Vec<AsmInstruction> {
+        /*
+        NaN is non-zero, but comisd sets ZF for it as well as PF.
+        JumpIfNotZero also jumps on parity:
+        Cmp($0, condition)
+        JmpCC(NE, label)
+        JmpCC(P, label)
+        JumpIfZero works out condition == 0 into %r10 first, the And
+        of its parity check leaves ZF clear if the result is 1:
+        <condition == 0 into %r10>
+        JmpCC(NE, label)
+        */
+        if condition_code == ConditionCode::NE {
+            return vec![
+                AsmInstruction::Compare(AsmCompareInstruction::new(
+                    AsmType::Double, condition_operand, zero_operand
+                )),
+                AsmInstruction::JmpConditional(ConditionCode::NE, label.clone()),
+                AsmInstruction::JmpConditional(ConditionCode::P, label)
+            ];
+        }
+        let mut instructions = AsmBinaryInstruction::build_comparison_instructions(
+            condition_operand, zero_operand, AsmOperand::Register(Register::R10D),
+            ConditionCode::E, AsmType::Double
+        );
+        instructions.push(AsmInstruction::JmpConditional(ConditionCode::NE, label));
+        instructions
+    }
+    pub fn to_asm_code_with_label_prefix(
+        &self, label_prefix: &str, operand_codes: &mut OperandCodeCache
+    ) -> Result<String, AsmGenError> {
+        // object formats differ in which label names they keep out of the symbol table
+        match self {
+            AsmInstruction::Jmp(label) => Ok(format!("jmp {label_prefix}{label}")),
+            AsmInstruction::JmpConditional(condition_code, label) => Ok(format!(
+                "j{} {label_prefix}{label}", condition_code.to_suffix()
+            )),
+            AsmInstruction::Label(label) => Ok(format!("{label_prefix}{label}:")),
+            instruction => instruction.to_cached_asm_code(operand_codes)
+        }
+    }
+    pub fn map_operands(self, f: &impl Fn(AsmOperand) -> AsmOperand) -> AsmInstruction {
+        // rewrites every operand of the instruction, keeping everything else
+        match self {
+            AsmInstruction::Mov(mov) => AsmInstruction::Mov(MovInstruction {
+                source: f(mov.source), destination: f(mov.destination), ..mov
+            }),
+            AsmInstruction::Movsx(movsx) => AsmInstruction::Movsx(MovsxInstruction {
+                source: f(movsx.source), destination: f(movsx.destination), ..movsx
+            }),
+            AsmInstruction::MovZeroExtend(mov_zero_extend) => {
+                AsmInstruction::MovZeroExtend(MovZeroExtendInstruction {
+                    source: f(mov_zero_extend.source),
+                    destination: f(mov_zero_extend.destination),
+                    ..mov_zero_extend
+                })
+            },
+            AsmInstruction::Lea(lea) => AsmInstruction::Lea(LeaInstruction::new(
+                f(lea.source), f(lea.destination)
+            )),
+            AsmInstruction::Unary(unary) => AsmInstruction::Unary(AsmUnaryInstruction {
+                destination: f(unary.destination), ..unary
+            }),
+            AsmInstruction::Binary(binary) => AsmInstruction::Binary(AsmBinaryInstruction {
+                source: f(binary.source), destination: f(binary.destination), ..binary
+            }),
+            AsmInstruction::Compare(compare) => AsmInstruction::Compare(AsmCompareInstruction {
+                left: f(compare.left), right: f(compare.right), ..compare
+            }),
+            AsmInstruction::SetConditional(set_conditional) => {
+                AsmInstruction::SetConditional(AsmSetConditional {
+                    destination: f(set_conditional.destination), ..set_conditional
+                })
+            },
+            AsmInstruction::IntegerDivision(division) => {
+                AsmInstruction::IntegerDivision(AsmIntegerDivision {
+                    operand: f(division.operand), ..division
+                })
+            },
+            AsmInstruction::DoubleConversion(conversion) => {
+                AsmInstruction::DoubleConversion(AsmDoubleConversion {
+                    source: f(conversion.source),
+                    destination: f(conversion.destination),
+                    ..conversion
+                })
+            },
+            other @ (
+                AsmInstruction::Jmp(_) | AsmInstruction::JmpConditional(_, _) |
+                AsmInstruction::Label(_) | AsmInstruction::SignExtension(_) |
+                AsmInstruction::Comment(_) | AsmInstruction::Loc(_) | AsmInstruction::Ret
+            ) => other,
+        }
+    }
+}
 impl ToStackAllocated for AsmInstruction {
     fn to_stack_allocated(
         &self, stack_value: u64,
@@ -384,6 +1117,21 @@ impl ToStackAllocated for AsmInstruction {
                     mov_instruction.to_stack_allocated(stack_value, allocations);
                 (AsmInstruction::Mov(new_mov_instruction), alloc_result)
             },
+            AsmInstruction::Movsx(movsx_instruction) => {
+                let (new_movsx_instruction, alloc_result) =
+                    movsx_instruction.to_stack_allocated(stack_value, allocations);
+                (AsmInstruction::Movsx(new_movsx_instruction), alloc_result)
+            },
+            AsmInstruction::MovZeroExtend(mov_zero_extend_instruction) => {
+                let (new_instruction, alloc_result) =
+                    mov_zero_extend_instruction.to_stack_allocated(stack_value, allocations);
+                (AsmInstruction::MovZeroExtend(new_instruction), alloc_result)
+            },
+            AsmInstruction::Lea(lea_instruction) => {
+                let (new_lea_instruction, alloc_result) =
+                    lea_instruction.to_stack_allocated(stack_value, allocations);
+                (AsmInstruction::Lea(new_lea_instruction), alloc_result)
+            },
             AsmInstruction::Unary(unary_instruction) => {
                 let (new_unary_instruction, alloc_result) =
                     unary_instruction.to_stack_allocated(stack_value, allocations);
@@ -394,20 +1142,40 @@ impl ToStackAllocated for AsmInstruction {
                     binary_instruction.to_stack_allocated(stack_value, allocations);
                 (AsmInstruction::Binary(new_binary_instruction), alloc_result)
             },
+            AsmInstruction::Compare(compare_instruction) => {
+                let (new_compare_instruction, alloc_result) =
+                    compare_instruction.to_stack_allocated(stack_value, allocations);
+                (AsmInstruction::Compare(new_compare_instruction), alloc_result)
+            },
+            AsmInstruction::SetConditional(set_conditional) => {
+                let (new_set_conditional, alloc_result) =
+                    set_conditional.to_stack_allocated(stack_value, allocations);
+                (AsmInstruction::SetConditional(new_set_conditional), alloc_result)
+            },
             AsmInstruction::IntegerDivision(int_div_instruction) => {
                 let (new_int_div_instruction, alloc_result) =
                     int_div_instruction.to_stack_allocated(stack_value, allocations);
                 (AsmInstruction::IntegerDivision(new_int_div_instruction), alloc_result)
             },
-            AsmInstruction::AllocateStack(stack_allocation) => {
-                // Stack allocation is not needed, pass through
-                let clone = AsmInstruction::AllocateStack(stack_allocation.clone());
-                (clone, StackAllocationResult::new(stack_value))
-            }
-            AsmInstruction::SignExtension => {
+            AsmInstruction::DoubleConversion(double_conversion) => {
+                let (new_double_conversion, alloc_result) =
+                    double_conversion.to_stack_allocated(stack_value, allocations);
+                (AsmInstruction::DoubleConversion(new_double_conversion), alloc_result)
+            },
+            AsmInstruction::SignExtension(_) => {
                 // Sign extension does not affect stack allocations
                 (self.clone(), StackAllocationResult::new(stack_value))
             },
+            AsmInstruction::Jmp(_) |
+            AsmInstruction::JmpConditional(_, _) |
+            AsmInstruction::Label(_) => {
+                // Jumps and labels have no operands to allocate
+                (self.clone(), StackAllocationResult::new(stack_value))
+            },
+            AsmInstruction::Comment(_) | AsmInstruction::Loc(_) => {
+                // Comments do not affect stack allocations
+                (self.clone(), StackAllocationResult::new(stack_value))
+            },
             AsmInstruction::Ret => {
                 // Return does not affect stack allocations
                 (self.clone(), StackAllocationResult::new(stack_value))
@@ -416,40 +1184,52 @@ impl ToStackAllocated for AsmInstruction {
     }
 }
 
+fn allocate_operand_pair(
+    source: &AsmOperand, destination: &AsmOperand, stack_value: u64,
+    allocations: &dyn DiffableHashMap<u64, u64>
+) -> (AsmOperand, AsmOperand, StackAllocationResult) {
+    // stack allocates a (source, destination) operand pair
+    let mut alloc_buffer = BufferedHashMap::new(allocations);
+
+    let (source, src_alloc_result) =
+        source.to_stack_allocated(stack_value, alloc_buffer.get_source_ref());
+    let stack_value = src_alloc_result.new_stack_value;
+    alloc_buffer.apply_changes(src_alloc_result.new_stack_allocations).unwrap();
+
+    let (destination, dst_alloc_result) =
+        destination.to_stack_allocated(stack_value, &alloc_buffer);
+    let stack_value = dst_alloc_result.new_stack_value;
+    alloc_buffer.apply_changes(dst_alloc_result.new_stack_allocations).unwrap();
+
+    let alloc_result =
+        StackAllocationResult::new_from_buffered(stack_value, alloc_buffer);
+    (source, destination, alloc_result)
+}
+
 #[derive(Clone, Debug)]
 pub struct MovInstruction {
+    pub(crate) asm_type: AsmType,
     pub(crate) source: AsmOperand,
     pub(crate) destination: AsmOperand,
 }
 impl MovInstruction {
-    pub fn new(source: AsmOperand, destination: AsmOperand) -> Self {
-        MovInstruction { source, destination }
+    pub fn new(
+        asm_type: AsmType, source: AsmOperand, destination: AsmOperand
+    ) -> Self {
+        MovInstruction { asm_type, source, destination }
     }
 }
 impl AsmSymbol for MovInstruction {
-    fn to_asm_code(self) -> Result<String, AsmGenError> {
-        let is_src_stack_addr = self.source.is_stack_address();
-        let is_src_constant = self.source.is_constant();
-        let is_dst_stack_addr = self.destination.is_stack_address();
-        println!("MOV_PRE {}", format!("{:?}, {:?}", &self.source, &self.destination));
-
-        let src_asm = self.source.to_asm_code()?;
-        let dst_asm = self.destination.to_asm_code()?;
-
-        if (is_src_stack_addr || is_src_constant) && is_dst_stack_addr {
-            /*
-            Apparently moving stack allocated values and constants
-            directly to stack addresses is not allowed in x86-64 assembly.
-            So we move the value to a scratch register first,
-            then move it to the stack address.
-            */
-            let mut asm_code: String = String::new();
-            asm_code.push_str(&format!("movl {src_asm}, {SCRATCH_REGISTER}\n"));
-            asm_code.push_str(&format!("movl {SCRATCH_REGISTER}, {dst_asm}"));
-            Ok(asm_code)
-        } else {
-            Ok(format!("mov {}, {}", src_asm, dst_asm))
-        }
+    fn to_asm_code(&self) -> Result<String, AsmGenError> {
+        self.to_cached_asm_code(&mut OperandCodeCache::default())
+    }
+    fn to_cached_asm_code(
+        &self, operand_codes: &mut OperandCodeCache
+    ) -> Result<String, AsmGenError> {
+        // operands have already been made valid by the fixup pass
+        let src_asm = operand_codes.operand_code(&self.source)?;
+        let dst_asm = operand_codes.operand_code(&self.destination)?;
+        Ok(format!("mov{} {}, {}", self.asm_type.suffix(), src_asm, dst_asm))
     }
 }
 impl ToStackAllocated for MovInstruction {
@@ -457,38 +1237,234 @@ impl ToStackAllocated for MovInstruction {
         &self, stack_value: u64,
         allocations: &dyn DiffableHashMap<u64, u64>
     ) -> (Self, StackAllocationResult) {
-        let mut alloc_buffer = BufferedHashMap::new(allocations);
-
-        let (source, src_alloc_result) =
-            self.source.to_stack_allocated(stack_value, alloc_buffer.get_source_ref());
-        let stack_value = src_alloc_result.new_stack_value;
-        alloc_buffer.apply_changes(src_alloc_result.new_stack_allocations).unwrap();
-
-        let (destination, dst_alloc_result) =
-            self.destination.to_stack_allocated(stack_value, alloc_buffer.get_source_ref());
-        let stack_value = dst_alloc_result.new_stack_value;
-        alloc_buffer.apply_changes(dst_alloc_result.new_stack_allocations).unwrap();
+        let (source, destination, alloc_result) = allocate_operand_pair(
+            &self.source, &self.destination, stack_value, allocations
+        );
+        let new_instruction = MovInstruction {
+            asm_type: self.asm_type.clone(), source, destination
+        };
+        (new_instruction, alloc_result)
+    }
+}
 
-        let new_instruction = MovInstruction { source, destination };
-        let alloc_result = StackAllocationResult::new_with_allocations(
-            stack_value,
-            alloc_buffer.build_changes().to_hash_map()
+#[derive(Clone, Debug)]
+pub struct LeaInstruction {
+    pub(crate) source: AsmOperand,
+    pub(crate) destination: AsmOperand,
+}
+impl LeaInstruction {
+    pub fn new(source: AsmOperand, destination: AsmOperand) -> Self {
+        LeaInstruction { source, destination }
+    }
+}
+impl AsmSymbol for LeaInstruction {
+    fn to_asm_code(&self) -> Result<String, AsmGenError> {
+        self.to_cached_asm_code(&mut OperandCodeCache::default())
+    }
+    fn to_cached_asm_code(
+        &self, operand_codes: &mut OperandCodeCache
+    ) -> Result<String, AsmGenError> {
+        let src_asm = operand_codes.operand_code(&self.source)?;
+        let dst_asm = operand_codes.operand_code(&self.destination)?;
+        Ok(format!("leaq {src_asm}, {dst_asm}"))
+    }
+}
+impl ToStackAllocated for LeaInstruction {
+    fn to_stack_allocated(
+        &self, stack_value: u64,
+        allocations: &dyn DiffableHashMap<u64, u64>
+    ) -> (Self, StackAllocationResult) {
+        let (source, destination, alloc_result) = allocate_operand_pair(
+            &self.source, &self.destination, stack_value, allocations
         );
+        (LeaInstruction { source, destination }, alloc_result)
+    }
+}
 
+#[derive(Clone, Debug)]
+pub struct MovsxInstruction {
+    pub(crate) src_type: AsmType,
+    pub(crate) dst_type: AsmType,
+    pub(crate) source: AsmOperand,
+    pub(crate) destination: AsmOperand,
+}
+impl MovsxInstruction {
+    pub fn new(
+        src_type: AsmType, dst_type: AsmType,
+        source: AsmOperand, destination: AsmOperand
+    ) -> Self {
+        MovsxInstruction { src_type, dst_type, source, destination }
+    }
+}
+impl AsmSymbol for MovsxInstruction {
+    fn to_asm_code(&self) -> Result<String, AsmGenError> {
+        self.to_cached_asm_code(&mut OperandCodeCache::default())
+    }
+    fn to_cached_asm_code(
+        &self, operand_codes: &mut OperandCodeCache
+    ) -> Result<String, AsmGenError> {
+        // e.g. movsbl, movslq
+        let src_asm = operand_codes.operand_code(&self.source)?;
+        let dst_asm = operand_codes.operand_code(&self.destination)?;
+        let src_suffix = self.src_type.suffix();
+        let dst_suffix = self.dst_type.suffix();
+        Ok(format!("movs{src_suffix}{dst_suffix} {src_asm}, {dst_asm}"))
+    }
+}
+impl ToStackAllocated for MovsxInstruction {
+    fn to_stack_allocated(
+        &self, stack_value: u64,
+        allocations: &dyn DiffableHashMap<u64, u64>
+    ) -> (Self, StackAllocationResult) {
+        let (source, destination, alloc_result) = allocate_operand_pair(
+            &self.source, &self.destination, stack_value, allocations
+        );
+        let new_instruction = MovsxInstruction {
+            src_type: self.src_type.clone(),
+            dst_type: self.dst_type.clone(),
+            source, destination
+        };
         (new_instruction, alloc_result)
     }
 }
 
 #[derive(Clone, Debug)]
-pub struct StackAllocation {
-    pub(crate) offset: u64,
-    pub(crate) offset_size: u64,
-    pub(crate) pop_contexts: Vec<PoppedTokenContext>,
-    pub(crate) tacky_var: Option<TackyVariable>,
+pub struct MovZeroExtendInstruction {
+    pub(crate) src_type: AsmType,
+    pub(crate) dst_type: AsmType,
+    pub(crate) source: AsmOperand,
+    pub(crate) destination: AsmOperand,
+}
+impl MovZeroExtendInstruction {
+    pub fn new(
+        src_type: AsmType, dst_type: AsmType,
+        source: AsmOperand, destination: AsmOperand
+    ) -> Self {
+        MovZeroExtendInstruction { src_type, dst_type, source, destination }
+    }
+}
+impl AsmSymbol for MovZeroExtendInstruction {
+    fn to_asm_code(&self) -> Result<String, AsmGenError> {
+        self.to_cached_asm_code(&mut OperandCodeCache::default())
+    }
+    fn to_cached_asm_code(
+        &self, operand_codes: &mut OperandCodeCache
+    ) -> Result<String, AsmGenError> {
+        /*
+        Bytes are zero extended with movzb{dst}. There is no movzlq,
+        so longwords are zero extended with plain moves by the fixup pass
+        */
+        if self.src_type != AsmType::Byte {
+            return Err(AsmGenError::UnsupportedInstruction(format!(
+                "Cannot zero extend a {:?} without fixing it up", self.src_type
+            )));
+        }
+        let src_asm = operand_codes.operand_code(&self.source)?;
+        let dst_asm = operand_codes.operand_code(&self.destination)?;
+        Ok(format!("movzb{} {src_asm}, {dst_asm}", self.dst_type.suffix()))
+    }
+}
+impl ToStackAllocated for MovZeroExtendInstruction {
+    fn to_stack_allocated(
+        &self, stack_value: u64,
+        allocations: &dyn DiffableHashMap<u64, u64>
+    ) -> (Self, StackAllocationResult) {
+        let (source, destination, alloc_result) = allocate_operand_pair(
+            &self.source, &self.destination, stack_value, allocations
+        );
+        let new_instruction = MovZeroExtendInstruction {
+            src_type: self.src_type.clone(),
+            dst_type: self.dst_type.clone(),
+            source, destination
+        };
+        (new_instruction, alloc_result)
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct StackFrame {
+    // bytes reserved below the saved %rbp (or the return address without one)
+    pub(crate) size: u64,
+    pub(crate) omit_frame_pointer: bool,
+    pub(crate) locals_size: u64,
+    // callee-saved registers the function uses, spilled right below the locals
+    pub(crate) saved_registers: Vec<Register>,
 }
-impl AsmSymbol for StackAllocation {
-    fn to_asm_code(self) -> Result<String, AsmGenError> {
-        Ok(format!("subq ${}, {STACK_REGISTER}", self.offset))
+impl StackFrame {
+    pub fn new(locals_size: u64, omit_frame_pointer: bool) -> StackFrame {
+        StackFrame {
+            size: Self::aligned_size(locals_size, omit_frame_pointer),
+            omit_frame_pointer, locals_size, saved_registers: vec![]
+        }
+    }
+    pub fn with_saved_registers(mut self, saved_registers: Vec<Register>) -> StackFrame {
+        // every register gets a 16 byte slot so that SSE registers are saved whole
+        let reserved = self.locals_size + SAVED_REGISTER_SLOT_SIZE * saved_registers.len() as u64;
+        self.size = Self::aligned_size(reserved, self.omit_frame_pointer);
+        self.saved_registers = saved_registers;
+        self
+    }
+    fn aligned_size(reserved: u64, omit_frame_pointer: bool) -> u64 {
+        /*
+        %rsp is 8 bytes past a 16-byte boundary on entry because of the
+        pushed return address. Pushing %rbp realigns it, so the frame is
+        rounded up to 16 bytes. Without %rbp the frame makes up the
+        missing 8 bytes instead, unless there is nothing to allocate
+        */
+        match (omit_frame_pointer, reserved) {
+            (true, 0) => 0,
+            (true, _) => (reserved + 8).next_multiple_of(16) - 8,
+            (false, _) => reserved.next_multiple_of(16),
+        }
+    }
+    fn saved_register_moves(&self, save: bool) -> Vec<String> {
+        self.saved_registers.iter().enumerate().map(|(index, register)| {
+            let offset = self.locals_size + SAVED_REGISTER_SLOT_SIZE * (index as u64 + 1);
+            let slot = match self.omit_frame_pointer {
+                true => format!("{}({STACK_REGISTER})", self.size - offset),
+                false => format!("-{offset}({BASE_REGISTER})"),
+            };
+            // movq would only preserve the low half of an SSE register
+            let mnemonic = if register.is_sse() { "movdqu" } else { "movq" };
+            let register = register.to_asm_code().expect("registers always have a name");
+            match save {
+                true => format!("{mnemonic} {register}, {slot}"),
+                false => format!("{mnemonic} {slot}, {register}"),
+            }
+        }).collect()
+    }
+    pub fn prologue(&self) -> Vec<String> {
+        let mut lines = vec![];
+        if !self.omit_frame_pointer {
+            lines.push(format!("pushq {BASE_REGISTER}"));
+            lines.push(format!("movq {STACK_REGISTER}, {BASE_REGISTER}"));
+        }
+        if self.size > 0 {
+            lines.push(format!("subq ${}, {STACK_REGISTER}", self.size));
+        }
+        lines.extend(self.saved_register_moves(true));
+        lines
+    }
+    pub fn epilogue(&self) -> Vec<String> {
+        let mut lines = self.saved_register_moves(false);
+        match (self.omit_frame_pointer, self.size) {
+            (true, 0) => {},
+            (true, size) => lines.push(format!("addq ${size}, {STACK_REGISTER}")),
+            (false, _) => lines.extend([
+                format!("movq {BASE_REGISTER}, {STACK_REGISTER}"),
+                format!("popq {BASE_REGISTER}"),
+            ])
+        }
+        lines
+    }
+    pub fn relative_to_stack_pointer(&self, operand: AsmOperand) -> AsmOperand {
+        // slots count down from the top of the frame, which is at size(%rsp)
+        match operand {
+            AsmOperand::Stack(stack_address) => AsmOperand::Memory(MemoryAddress::new(
+                Register::RSP, (self.size - stack_address.offset) as i64
+            )),
+            other => other
+        }
     }
 }
 
@@ -540,21 +1516,65 @@ impl StackAddress {
     }
 }
 impl AsmSymbol for StackAddress {
-    fn to_asm_code(self) -> Result<String, AsmGenError> {
+    fn to_asm_code(&self) -> Result<String, AsmGenError> {
         Ok(format!("-{}({BASE_REGISTER})", self.offset))
     }
 }
 
 
+#[derive(Clone, Debug)]
+pub struct MemoryAddress {
+    // address held in a register, e.g. 4(%r10)
+    pub(crate) base: Register,
+    pub(crate) offset: i64,
+}
+impl MemoryAddress {
+    pub fn new(base: Register, offset: i64) -> MemoryAddress {
+        MemoryAddress { base, offset }
+    }
+}
+impl AsmSymbol for MemoryAddress {
+    fn to_asm_code(&self) -> Result<String, AsmGenError> {
+        let base_asm = self.base.to_asm_code()?;
+        if self.offset == 0 {
+            Ok(format!("({base_asm})"))
+        } else {
+            Ok(format!("{}({base_asm})", self.offset))
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct DataAddress {
+    // static variable addressed relative to the instruction pointer
+    pub(crate) name: String,
+}
+impl DataAddress {
+    pub fn new(name: String) -> DataAddress {
+        DataAddress { name }
+    }
+    pub fn local_alias(name: &str) -> String {
+        // .L labels stay out of the symbol table
+        format!(".L{name}$local")
+    }
+}
+impl AsmSymbol for DataAddress {
+    fn to_asm_code(&self) -> Result<String, AsmGenError> {
+        Ok(format!("{}(%rip)", self.name))
+    }
+}
+
 #[derive(Clone, Debug)]
 pub enum AsmOperand {
     ImmediateValue(AsmImmediateValue),
     Register(Register),
     Pseudo(PseudoRegister),
-    Stack(StackAddress)
+    Stack(StackAddress),
+    Memory(MemoryAddress),
+    Data(DataAddress)
 }
 impl AsmSymbol for AsmOperand {
-    fn to_asm_code(self) -> Result<String, AsmGenError> {
+    fn to_asm_code(&self) -> Result<String, AsmGenError> {
         match self {
             AsmOperand::ImmediateValue(value) => {
                 Ok(value.to_asm_code()?)
@@ -572,6 +1592,12 @@ impl AsmSymbol for AsmOperand {
             },
             AsmOperand::Stack(stack_address) => {
                 Ok(stack_address.to_asm_code()?)
+            },
+            AsmOperand::Memory(memory_address) => {
+                Ok(memory_address.to_asm_code()?)
+            },
+            AsmOperand::Data(data_address) => {
+                Ok(data_address.to_asm_code()?)
             }
         }
     }
@@ -580,16 +1606,66 @@ impl AsmOperand {
     pub fn is_stack_address(&self) -> bool {
         matches!(self, AsmOperand::Stack(_))
     }
+    pub fn is_memory(&self) -> bool {
+        // whether the operand lives in memory rather than a register
+        matches!(
+            self, AsmOperand::Stack(_) | AsmOperand::Memory(_) | AsmOperand::Data(_)
+        )
+    }
     pub fn is_constant(&self) -> bool {
         matches!(self, AsmOperand::ImmediateValue(_))
     }
+    pub fn is_register(&self) -> bool {
+        matches!(self, AsmOperand::Register(_))
+    }
+    pub fn with_width(&self, asm_type: &AsmType) -> AsmOperand {
+        /*
+        The operand narrowed or widened to the given type. Memory is
+        addressed by its lowest byte on x86-64, so only registers change
+        */
+        match self {
+            AsmOperand::Register(register) => AsmOperand::Register(register.with_width(asm_type)),
+            operand => operand.clone()
+        }
+    }
+    pub fn is_wide_constant(&self, asm_type: &AsmType) -> bool {
+        /*
+        Whether the operand is a quadword immediate that can't be
+        encoded as a sign extended 32-bit immediate
+        */
+        match self {
+            AsmOperand::ImmediateValue(value) => {
+                *asm_type == AsmType::Quadword && !value.fits_in_i32()
+            },
+            _ => false
+        }
+    }
+    pub fn move_to_double_register(
+        &self, register: &str, operand_codes: &mut OperandCodeCache
+    ) -> Result<String, AsmGenError> {
+        // moves a double operand into an SSE register
+        let is_constant = self.is_constant();
+        let operand_asm = operand_codes.operand_code(self)?;
+        if is_constant {
+            let mut asm_code: String = String::new();
+            asm_code.push_str(&format!("movq {operand_asm}, {SCRATCH_REGISTER_64}\n"));
+            asm_code.push_str(&format!("movq {SCRATCH_REGISTER_64}, {register}"));
+            Ok(asm_code)
+        } else {
+            Ok(format!("movsd {operand_asm}, {register}"))
+        }
+    }
     pub fn from_tacky_value(tacky_value: TackyValue) -> Self {
         match tacky_value {
             TackyValue::Constant(ast_constant) => {
-                let value = ast_constant.to_u64().unwrap();
+                // doubles are kept as their bit pattern
+                let value = ast_constant.to_bits();
                 AsmOperand::ImmediateValue(AsmImmediateValue::new(value)
                     .with_added_pop_context(ast_constant.pop_context.clone()))
             },
+            TackyValue::Var(tacky_var) if tacky_var.is_static => {
+                AsmOperand::Data(DataAddress::new(tacky_var.name))
+            },
             TackyValue::Var(tacky_var) => {
                 AsmOperand::Pseudo(PseudoRegister::from_tacky_var(tacky_var))
             },
@@ -608,16 +1684,23 @@ impl ToStackAllocated for AsmOperand {
         */
         match self {
             AsmOperand::Pseudo(pseudo_register) => {
+                /*
+                The value occupies the bytes [-offset, -offset + size)
+                relative to the base register, so the offset is the end
+                of the allocation, rounded up to keep the value aligned
+                */
+                let size = pseudo_register.get_size();
+                let alignment = pseudo_register.get_alignment();
+                let offset = (stack_value + size).div_ceil(alignment) * alignment;
                 let conversion = StackAddress::from_pseudo_register(
-                    pseudo_register, stack_value, STACK_VARIABLE_SIZE,
-                    allocations
+                    pseudo_register, offset, size, allocations
                 );
 
                 let mut new_stack_value = stack_value;
                 let mut new_allocations: HashMap<u64, u64> = HashMap::new();
                 if conversion.newly_allocated {
-                    new_stack_value += STACK_VARIABLE_SIZE;
-                    new_allocations.insert(pseudo_register.id, stack_value);
+                    new_stack_value = offset;
+                    new_allocations.insert(pseudo_register.id, offset);
                 }
                 let stack_alloc_result = StackAllocationResult::new_with_allocations(
                     new_stack_value, new_allocations
@@ -645,11 +1728,15 @@ impl AsmImmediateValue {
             pop_contexts: vec![]
         }
     }
+    pub fn fits_in_i32(&self) -> bool {
+        let signed_value = self.value as i64;
+        i32::MIN as i64 <= signed_value && signed_value <= i32::MAX as i64
+    }
 
     pub fn from_expression(expr: Expression) -> Self {
         match expr.expr_item {
             ExpressionVariant::Constant(ref constant) => {
-                let value = constant.to_u64().unwrap();
+                let value = constant.to_bits();
                 AsmImmediateValue::new(value).with_added_pop_context(
                     expr.pop_context.clone()
                 )
@@ -664,7 +1751,10 @@ impl AsmImmediateValue {
     }
 
     pub fn from_statement(statement: Statement) -> Self {
-        Self::from_expression(statement.expression)
+        match statement.variant {
+            StatementVariant::Return(expression) => Self::from_expression(expression),
+            _ => panic!("Unsupported statement type for AsmImmediateValue")
+        }
     }
 }
 impl HasPopContexts for AsmImmediateValue {
@@ -676,14 +1766,16 @@ impl HasPopContexts for AsmImmediateValue {
     }
 }
 impl AsmSymbol for AsmImmediateValue {
-    fn to_asm_code(self) -> Result<String, AsmGenError> {
-        Ok(format!("${}", self.value))
+    fn to_asm_code(&self) -> Result<String, AsmGenError> {
+        // values above i64::MAX are written as their negative
+        // two's complement equivalent to keep the assembler happy
+        Ok(format!("${}", self.value as i64))
     }
 }
 
 pub fn asm_gen_from_filepath(
     file_path: &str, verbose: bool
-) -> Result<AsmProgram, ParseError> {
+) -> Result<AsmProgram, CompilerError> {
     let tacky_program = tacky_gen_from_filepath(file_path, verbose)?;
     let asm_program = AsmProgram::from_tacky_program(tacky_program);
     Ok(asm_program)
@@ -691,7 +1783,20 @@ pub fn asm_gen_from_filepath(
 
 #[cfg(test)]
 mod tests {
-    use crate::asm_gen::asm_symbols::AsmSymbol;
+    use crate::asm_gen::abi::Abi;
+    use crate::asm_gen::registers::Register;
+    use crate::assembler::{build_from_asm_code, OutputKind, Target};
+    use std::collections::HashSet;
+    use std::process::Command;
+    use std::rc::Rc;
+    use crate::asm_gen::asm_symbols::{
+        AsmImmediateValue, AsmOperand, AsmProgram, AsmSymbol, OperandCodeCache,
+        StackAddress, StackFrame
+    };
+    use crate::lexer::lexer::Lexer;
+    use crate::parser::parse::parse;
+    use crate::parser::parser_helpers::TokenStack;
+    use crate::tacky::tacky_symbols::TackyProgram;
 
     #[test]
     fn test_chapter_3_valid_sub() {
@@ -711,4 +1816,377 @@ mod tests {
         let asm_program = super::asm_gen_from_filepath(file_path, true).unwrap();
         let _asm_code = asm_program.to_asm_code().unwrap();
     }
+
+    fn asm_code_from_source(source: &str) -> String {
+        let tokens = Lexer::new().tokenize(source).unwrap();
+        let mut token_stack = TokenStack::new_from_vec(tokens);
+        let program = parse(&mut token_stack).unwrap();
+        let tacky_program = TackyProgram::from_program(&program);
+        AsmProgram::from_tacky_program(tacky_program).to_asm_code().unwrap()
+    }
+
+    #[test]
+    fn test_unsigned_division_and_comparison() {
+        let asm_code = asm_code_from_source(
+            "unsigned int main(void) { return 10u / 3u < 4294967295u; }"
+        );
+        assert!(asm_code.contains("divl"));
+        assert!(!asm_code.contains("idivl"));
+        assert!(asm_code.contains("setb"));
+    }
+
+    #[test]
+    fn test_long_arithmetic_uses_quadwords() {
+        let asm_code = asm_code_from_source(
+            "long main(void) { return (1 + 2) * 3l; }"
+        );
+        // the int sub-expression is sign extended to a long
+        assert!(asm_code.contains("movslq"));
+        assert!(asm_code.contains("imulq"));
+        assert!(asm_code.contains("%rax"));
+    }
+
+    #[test]
+    fn test_char_return_uses_bytes() {
+        let asm_code = asm_code_from_source(
+            "char main(void) { return 'a' + '\\n'; }"
+        );
+        // the int sum is truncated to a single byte
+        assert!(asm_code.contains("movb"));
+        assert!(asm_code.contains("%al"));
+        assert!(asm_code.contains("$97"));
+        assert!(asm_code.contains("$10"));
+    }
+
+    #[test]
+    fn test_pointer_load_and_store() {
+        let asm_code = asm_code_from_source(
+            "int main(void) { int x = 1; int *p = &x; *p = 2; return *p; }"
+        );
+        assert!(asm_code.contains("leaq"));
+        assert!(asm_code.contains("movl (%r10), %r10d"));
+        assert!(asm_code.contains("movl $2, (%r11)"));
+    }
+
+    #[test]
+    fn test_struct_member_offsets() {
+        let asm_code = asm_code_from_source(
+            "struct pair { char a; int b; };
+            int main(void) { struct pair p; p.b = 3; return p.b; }"
+        );
+        assert!(asm_code.contains("movl $3, 4(%r11)"));
+        assert!(asm_code.contains("movl 4(%r10), %r10d"));
+    }
+
+    #[test]
+    fn test_union_members_share_offset() {
+        let asm_code = asm_code_from_source(
+            "union number { char c; long l; };
+            int main(void) { union number u; u.l = 258; return u.c; }"
+        );
+        assert!(asm_code.contains("movq $258, (%r11)"));
+        assert!(asm_code.contains("movb (%r10), %r10b"));
+    }
+
+    #[test]
+    fn test_global_variables() {
+        let asm_code = asm_code_from_source(
+            "int counter = 3; long negative = -2; int zeroed;
+            int main(void) { int counter_copy = counter; return counter_copy + zeroed; }"
+        );
+        assert!(asm_code.contains("movl counter(%rip), %r10d"));
+        assert!(asm_code.contains("zeroed(%rip), "));
+        assert!(asm_code.contains("    .data\n    .balign 4\ncounter:\n    .long 3\n"));
+        assert!(asm_code.contains("negative:\n    .quad -2\n"));
+        assert!(asm_code.contains("    .bss\n    .balign 4\nzeroed:\n    .zero 4\n"));
+    }
+
+    #[test]
+    fn test_position_independent_globals() {
+        let source = "int counter = 3;\nint main(void) { return counter; }";
+        let tokens = Lexer::new().tokenize(source).unwrap();
+        let program = parse(&mut TokenStack::new_from_vec(tokens)).unwrap();
+        let asm_program = AsmProgram::from_tacky_program(TackyProgram::from_program(&program));
+        let asm_code = asm_program.with_position_independent_code().to_asm_code().unwrap();
+        assert!(asm_code.contains(".Lcounter$local(%rip), "));
+        assert!(!asm_code.contains(" counter(%rip)"));
+        assert!(asm_code.contains("    .globl counter\n"));
+        assert!(asm_code.contains("counter:\n.Lcounter$local:\n    .long 3\n"));
+    }
+
+    #[test]
+    fn test_windows_abi_output() {
+        let source = "int counter = 3;\nint main(void) { return counter; }";
+        let tokens = Lexer::new().tokenize(source).unwrap();
+        let program = parse(&mut TokenStack::new_from_vec(tokens)).unwrap();
+        let asm_program = AsmProgram::from_tacky_program(TackyProgram::from_program(&program));
+        let asm_code = asm_program.with_abi(Abi::Windows).to_asm_code().unwrap();
+        assert!(asm_code.contains("main:\n"));
+        assert!(asm_code.contains("counter(%rip), "));
+        // COFF has no use for the ELF non-executable stack marker
+        assert!(!asm_code.contains(".note.GNU-stack"));
+        assert!(asm_code_from_source(source).contains(".note.GNU-stack"));
+    }
+
+    #[test]
+    fn test_darwin_abi_output() {
+        let source = "int counter = 3; int zeroed;\nint main(void) { return counter && zeroed; }";
+        let tokens = Lexer::new().tokenize(source).unwrap();
+        let program = parse(&mut TokenStack::new_from_vec(tokens)).unwrap();
+        let asm_program = AsmProgram::from_tacky_program(TackyProgram::from_program(&program));
+        let asm_code = asm_program.with_abi(Abi::Darwin)
+            .with_position_independent_code().to_asm_code().unwrap();
+        assert!(asm_code.starts_with("    .globl _main\n_main:\n"));
+        assert!(asm_code.contains(" _counter(%rip)"));
+        assert!(asm_code.contains("    .globl _counter\n    .data\n    .balign 4\n_counter:\n"));
+        assert!(asm_code.contains("    .globl _zeroed\n    .zerofill __DATA,__bss,_zeroed,4,2\n"));
+        assert!(!asm_code.contains("$local"));
+        assert!(asm_code.contains("\nLshort_circuit_end_"));
+        assert!(!asm_code.contains(".note.GNU-stack"));
+    }
+
+    #[test]
+    fn test_cast_conversions() {
+        let asm_code = asm_code_from_source(
+            "int main(void) {
+                long l = 300; char c = (char)l; unsigned char u = (unsigned char)c;
+                return (long)c + (unsigned long)u;
+            }"
+        );
+        // truncation only moves the low byte
+        assert!(asm_code.contains("movb"));
+        assert!(asm_code.contains("movsbq"));
+        assert!(asm_code.contains("movzbq"));
+    }
+
+    #[test]
+    fn test_double_arithmetic() {
+        let asm_code = asm_code_from_source(
+            "int main(void) {
+                double d = 2.5; int i = 3; double e = d / i;
+                return (int)(e * 2.0) + (d < e);
+            }"
+        );
+        // 2.5 is materialized through its bit pattern
+        assert!(asm_code.contains(&format!("movq ${}, %r10", 2.5f64.to_bits())));
+        assert!(asm_code.contains("cvtsi2sdl %r10d, %xmm15"));
+        assert!(asm_code.contains("divsd"));
+        assert!(asm_code.contains("mulsd %xmm14, %xmm15"));
+        assert!(asm_code.contains("cvttsd2sil %xmm15, %r10d"));
+        assert!(asm_code.contains("comisd"));
+        // d < e is checked as e > d, which is false for NaN
+        assert!(asm_code.contains("seta"));
+    }
+
+    #[test]
+    fn test_nan_comparisons() {
+        let source = "int main(void) {
+            double zero = 0.0; double nan = zero / zero;
+            int ordered = (1.0 < 2.0) && (2.0 <= 2.0) && !zero && (zero == 0.0) && !(zero != 0.0);
+            return (nan == nan) + 2 * (nan != nan) +
+                4 * ((nan < 1.0) + (nan <= 1.0) + (1.0 > nan) + (1.0 >= nan) + !nan) +
+                8 * (nan && 1) + 16 * (nan || 0) + 32 * ((zero && 1) + (zero || 0)) + 64 * ordered;
+        }";
+        let asm_code = asm_code_from_source(source);
+        assert!(asm_code.contains("setnp %r11b"));
+        assert!(asm_code.contains("setp %r11b"));
+        assert!(asm_code.contains("    jp .L"));
+
+        // comparisons with NaN are false, except for !=
+        let temp_dir = std::env::temp_dir();
+        let exe_path = temp_dir.join("ca_compiler_nan_test");
+        build_from_asm_code(
+            &Target::X86_64, &asm_code, &temp_dir.join("ca_compiler_nan_test.s"),
+            &OutputKind::Executable, &exe_path
+        ).unwrap();
+        let status = Command::new(&exe_path).status().unwrap();
+        std::fs::remove_file(&exe_path).unwrap();
+        assert_eq!(status.code(), Some(2 + 8 + 16 + 64));
+    }
+
+    #[test]
+    fn test_bitwise_operators() {
+        let asm_code = asm_code_from_source(
+            "int main(void) {
+                int a = 6; int n = 2; unsigned int u = 8u;
+                return (a & 3) + (a | n) + (a ^ n) + (a << n) + (a >> 1) + (u >> n);
+            }"
+        );
+        assert!(asm_code.contains("andl $3, "));
+        assert!(asm_code.contains("orl"));
+        assert!(asm_code.contains("xorl"));
+        // variable shift counts go through cl
+        assert!(asm_code.contains("sall %cl, "));
+        assert!(asm_code.contains("sarl $1, "));
+        assert!(asm_code.contains("shrl %cl, "));
+    }
+
+    #[test]
+    fn test_logical_not() {
+        let asm_code = asm_code_from_source(
+            "int main(void) { long a = 3; double d = 0.0; return !a + !d; }"
+        );
+        assert!(asm_code.contains("    cmpq $0, -8(%rbp)\n    movl $0, -"));
+        assert!(asm_code.contains("comisd"));
+        assert!(asm_code.contains("sete"));
+        assert!(!asm_code.contains("notl") && !asm_code.contains("notq"));
+    }
+
+    #[test]
+    fn test_short_circuit_jumps() {
+        let asm_code = asm_code_from_source(
+            "int main(void) { int a = 0; double d = 2.5; return (a && 3) + (d || a); }"
+        );
+        assert!(asm_code.contains("    cmpl $0, -4(%rbp)\n    je .Lshort_circuit_jmp_"));
+        assert!(asm_code.contains("    jne .Lshort_circuit_jmp_"));
+        assert!(asm_code.contains("    jmp .Lshort_circuit_end_"));
+        assert!(asm_code.contains("\n.Lshort_circuit_end_"));
+        assert!(asm_code.contains("comisd"));
+
+        // nested operators each define their own labels
+        let asm_code = asm_code_from_source(
+            "int main(void) { int a = 1; int b = 0; return (a && (b || a)) || (b && a); }"
+        );
+        let labels: Vec<&str> = asm_code.lines()
+            .filter(|line| line.starts_with(".Lshort_circuit_"))
+            .collect();
+        assert_eq!(labels.len(), 8);
+        assert_eq!(labels.iter().collect::<HashSet<_>>().len(), labels.len());
+    }
+
+    #[test]
+    fn test_annotation_comments() {
+        let asm_code = asm_code_from_source(
+            "int main(void) { int a = 6; a * 2; return -(a + 1); }"
+        );
+        assert!(asm_code.contains("    # begin: a = 6\n    movl $6, "));
+        assert!(asm_code.contains("    # begin: a * 2\n"));
+        assert!(asm_code.contains("    # begin: return -(a + 1)\n"));
+        assert!(asm_code.contains("    # end: return -(a + 1)\n"));
+    }
+
+    #[test]
+    fn test_stack_frame_sizes() {
+        assert_eq!(StackFrame::new(0, false).size, 0);
+        assert_eq!(StackFrame::new(4, false).size, 16);
+        assert_eq!(StackFrame::new(20, false).size, 32);
+        // the return address takes up the other 8 bytes without %rbp
+        assert_eq!(StackFrame::new(0, true).size, 0);
+        assert_eq!(StackFrame::new(4, true).size, 8);
+        assert_eq!(StackFrame::new(12, true).size, 24);
+        // saved registers get 16 byte slots below the locals
+        let frame = StackFrame::new(4, false).with_saved_registers(vec![Register::XMM14]);
+        assert_eq!(frame.size, 32);
+        assert_eq!(frame.prologue().last().unwrap(), "movdqu %xmm14, -20(%rbp)");
+        assert_eq!(frame.epilogue().first().unwrap(), "movdqu -20(%rbp), %xmm14");
+        let frame = StackFrame::new(4, true).with_saved_registers(vec![Register::XMM14]);
+        assert_eq!(frame.size, 24);
+        assert_eq!(frame.prologue().last().unwrap(), "movdqu %xmm14, 4(%rsp)");
+    }
+
+    #[test]
+    fn test_windows_callee_saved_registers() {
+        // adding to a double in memory goes through the scratch SSE registers
+        let source = "int main(void) { double d = 1.5; d = d + 2.0; return d > 3.0; }";
+        let tokens = Lexer::new().tokenize(source).unwrap();
+        let program = parse(&mut TokenStack::new_from_vec(tokens)).unwrap();
+        let asm_program = AsmProgram::from_tacky_program(TackyProgram::from_program(&program));
+        let asm_code = asm_program.with_abi(Abi::Windows).to_asm_code().unwrap();
+        let saves: Vec<&str> = asm_code.lines()
+            .filter(|line| line.starts_with("    movdqu %xmm"))
+            .collect();
+        assert!(!saves.is_empty());
+        for save in saves {
+            // each save is undone with the operands swapped at every return
+            let (register, slot) = save.trim_start_matches("    movdqu ").split_once(", ").unwrap();
+            let restore = format!("    movdqu {slot}, {register}\n");
+            assert_eq!(asm_code.matches(&restore).count(), asm_code.matches("ret\n").count());
+        }
+        // System V leaves every SSE register to the caller
+        assert!(!asm_code_from_source(source).contains("movdqu"));
+    }
+
+    #[test]
+    fn test_single_stack_allocation() {
+        let asm_code = asm_code_from_source(
+            "int main(void) { int a = 6; long b = 7; return a + b; }"
+        );
+        assert!(asm_code.contains(
+            "    pushq %rbp\n    movq %rsp, %rbp\n    subq $48, %rsp\n"
+        ));
+        assert_eq!(asm_code.matches("subq").count(), 1);
+        assert!(asm_code.contains("    movq %rbp, %rsp\n    popq %rbp\n    ret\n"));
+    }
+
+    #[test]
+    fn test_omitted_frame_pointer() {
+        let tokens = Lexer::new().tokenize(
+            "int main(void) { int a = 6; long b = 7; return a + b; }"
+        ).unwrap();
+        let program = parse(&mut TokenStack::new_from_vec(tokens)).unwrap();
+        let asm_code = AsmProgram::from_tacky_program(TackyProgram::from_program(&program))
+            .with_omitted_frame_pointer().to_asm_code().unwrap();
+        assert!(!asm_code.contains("%rbp"));
+        assert!(asm_code.contains("main:\n    subq $40, %rsp\n"));
+        // a is at -4(%rbp) with a frame pointer
+        assert!(asm_code.contains("movl $6, 36(%rsp)"));
+        assert!(asm_code.contains("    addq $40, %rsp\n    ret\n"));
+    }
+
+    #[test]
+    fn test_debug_line_info() {
+        let source = "int main(void) {\n    int a = 6;\n    return a;\n}\n";
+        let tokens = Lexer::new().tokenize(source).unwrap();
+        let program = parse(&mut TokenStack::new_from_vec(tokens)).unwrap();
+        let asm_program = AsmProgram::from_tacky_program(TackyProgram::from_program(&program));
+        let asm_code = asm_program.with_debug_info("prog.c".to_string()).to_asm_code().unwrap();
+        assert!(asm_code.starts_with("    .file 1 \"prog.c\"\n"));
+        assert!(asm_code.contains("main:\n    .loc 1 1 1\n    pushq %rbp\n"));
+        assert!(asm_code.contains("    # begin: a = 6\n    .loc 1 2 5\n"));
+        assert!(asm_code.contains("    .loc 1 3 5\n"));
+
+        // line info is left out unless it is asked for
+        assert!(!asm_code_from_source(source).contains(".loc"));
+    }
+
+    #[test]
+    fn test_binary_expression_line_info() {
+        let source = "int main(void) {\n    int a = 6;\n    return 1 +\n        a * 2;\n}\n";
+        let tokens = Lexer::new().tokenize(source).unwrap();
+        let program = parse(&mut TokenStack::new_from_vec(tokens)).unwrap();
+        let tacky_program = TackyProgram::from_program(&program);
+        let asm_program = AsmProgram::from_tacky_program(tacky_program);
+        let asm_code = asm_program.with_debug_info("prog.c".to_string()).to_asm_code().unwrap();
+        // a * 2 is evaluated before the addition that spans both lines
+        let multiply_line = asm_code.find("    .loc 1 4 9\n").unwrap();
+        let add_line = asm_code.find("    .loc 1 3 12\n").unwrap();
+        assert!(multiply_line < add_line);
+    }
+
+    #[test]
+    fn test_stream_asm_code() {
+        let source = "int main(void) {\n    int a = 6;\n    return a;\n}\n";
+        let tokens = Lexer::new().tokenize(source).unwrap();
+        let program = parse(&mut TokenStack::new_from_vec(tokens)).unwrap();
+        let asm_program = AsmProgram::from_tacky_program(TackyProgram::from_program(&program));
+
+        let mut buffer: Vec<u8> = vec![];
+        asm_program.emit_asm_code(&mut buffer).unwrap();
+        assert_eq!(String::from_utf8(buffer).unwrap(), asm_code_from_source(source));
+        // emitting only borrows the program, so it can be emitted again
+        assert_eq!(asm_program.to_asm_code().unwrap(), asm_code_from_source(source));
+    }
+
+    #[test]
+    fn test_operand_code_cache() {
+        let mut operand_codes = OperandCodeCache::default();
+        let stack_slot = AsmOperand::Stack(StackAddress::new(8, 4));
+        let first = operand_codes.operand_code(&stack_slot).unwrap();
+        let second = operand_codes.operand_code(&stack_slot).unwrap();
+        assert_eq!(&*first, "-8(%rbp)");
+        assert!(Rc::ptr_eq(&first, &second));
+
+        let immediate = AsmOperand::ImmediateValue(AsmImmediateValue::new(3));
+        assert_eq!(&*operand_codes.operand_code(&immediate).unwrap(), "$3");
+    }
 }
@@ -1,16 +1,17 @@
 use std::cmp::PartialEq;
 use crate::asm_gen::asm_symbols::{
-    SCRATCH_REGISTER, MUL_SCRATCH_REGISTER
+    AsmGenError, AsmImmediateValue, AsmInstruction, AsmOperand, AsmSymbol,
+    AsmType, MovInstruction, OperandCodeCache
 };
-use crate::asm_gen::asm_symbols::{
-    AsmGenError, AsmInstruction, AsmOperand, AsmSymbol,
-    MovInstruction, Register
+use crate::asm_gen::cmp_instruction::{
+    AsmCompareInstruction, AsmSetConditional, ConditionCode
 };
 use crate::asm_gen::helpers::{
     BufferedHashMap, DiffableHashMap, StackAllocationResult,
     ToStackAllocated
 };
 use crate::asm_gen::interger_division::AsmIntegerDivision;
+use crate::asm_gen::registers::Register;
 use crate::parser::parse::SupportedBinaryOperators;
 use crate::tacky::tacky_symbols::{BinaryInstruction, TackyValue};
 
@@ -18,21 +19,59 @@ use crate::tacky::tacky_symbols::{BinaryInstruction, TackyValue};
 pub enum AsmBinaryOperators {
     Add,
     Subtract,
-    Multiply
+    Multiply,
+    // floating point division (integers are divided with AsmIntegerDivision)
+    DivideDouble,
+    BitwiseAnd,
+    BitwiseOr,
+    BitwiseXor,
+    ShiftLeft,
+    // sign filling shift for signed values
+    ShiftRightArithmetic,
+    // zero filling shift for unsigned values
+    ShiftRightLogical,
 }
 impl AsmBinaryOperators {
-    pub fn to_asm_string(&self) -> String {
-        match self {
-            AsmBinaryOperators::Add => "addl".to_string(),
-            AsmBinaryOperators::Subtract => "subl".to_string(),
-            AsmBinaryOperators::Multiply => "imull".to_string(),
+    pub fn to_asm_string(&self, asm_type: &AsmType) -> String {
+        let suffix = asm_type.suffix();
+        match (self, asm_type) {
+            (AsmBinaryOperators::Add, _) => format!("add{suffix}"),
+            (AsmBinaryOperators::Subtract, _) => format!("sub{suffix}"),
+            (AsmBinaryOperators::Multiply, AsmType::Double) => "mulsd".to_string(),
+            (AsmBinaryOperators::Multiply, _) => format!("imul{suffix}"),
+            (AsmBinaryOperators::DivideDouble, _) => "divsd".to_string(),
+            (AsmBinaryOperators::BitwiseAnd, _) => format!("and{suffix}"),
+            (AsmBinaryOperators::BitwiseOr, _) => format!("or{suffix}"),
+            (AsmBinaryOperators::BitwiseXor, _) => format!("xor{suffix}"),
+            (AsmBinaryOperators::ShiftLeft, _) => format!("sal{suffix}"),
+            (AsmBinaryOperators::ShiftRightArithmetic, _) => format!("sar{suffix}"),
+            (AsmBinaryOperators::ShiftRightLogical, _) => format!("shr{suffix}"),
         }
     }
-    pub fn from_supported(op: SupportedBinaryOperators) -> Result<Self, AsmGenError> {
+    pub fn is_shift(&self) -> bool {
+        matches!(
+            self,
+            AsmBinaryOperators::ShiftLeft |
+            AsmBinaryOperators::ShiftRightArithmetic |
+            AsmBinaryOperators::ShiftRightLogical
+        )
+    }
+    pub fn from_supported(
+        op: SupportedBinaryOperators, is_signed: bool
+    ) -> Result<Self, AsmGenError> {
         match op {
             SupportedBinaryOperators::Add => Ok(AsmBinaryOperators::Add),
             SupportedBinaryOperators::Subtract => Ok(AsmBinaryOperators::Subtract),
             SupportedBinaryOperators::Multiply => Ok(AsmBinaryOperators::Multiply),
+            SupportedBinaryOperators::Divide => Ok(AsmBinaryOperators::DivideDouble),
+            SupportedBinaryOperators::BitwiseAnd => Ok(AsmBinaryOperators::BitwiseAnd),
+            SupportedBinaryOperators::BitwiseOr => Ok(AsmBinaryOperators::BitwiseOr),
+            SupportedBinaryOperators::BitwiseXor => Ok(AsmBinaryOperators::BitwiseXor),
+            SupportedBinaryOperators::LeftShift => Ok(AsmBinaryOperators::ShiftLeft),
+            SupportedBinaryOperators::RightShift if is_signed => {
+                Ok(AsmBinaryOperators::ShiftRightArithmetic)
+            },
+            SupportedBinaryOperators::RightShift => Ok(AsmBinaryOperators::ShiftRightLogical),
             _ => Err(AsmGenError::UnsupportedInstruction(
                 format!("Unsupported binary operator: {:?}", op))
             ),
@@ -49,6 +88,7 @@ pub enum DivisionOutputs {
 #[derive(Clone, Debug)]
 pub struct AsmBinaryInstruction {
     pub(crate) operator: AsmBinaryOperators,
+    pub(crate) asm_type: AsmType,
     pub(crate) source: AsmOperand,
     pub(crate) destination: AsmOperand,
 }
@@ -57,30 +97,108 @@ impl AsmBinaryInstruction {
         left_operand: AsmOperand,
         right_operand: AsmOperand,
         dst_operand: AsmOperand,
-        desired_output: DivisionOutputs
+        desired_output: DivisionOutputs,
+        asm_type: AsmType,
+        is_signed: bool
     ) -> Vec<AsmInstruction> {
         // Move left operand into EAX (division input register)
         let move_into_instruction = MovInstruction::new(
-            left_operand.clone(), AsmOperand::Register(Register::EAX)
+            asm_type.clone(), left_operand.clone(),
+            AsmOperand::Register(Register::ax(&asm_type))
         );
         let output_register = match desired_output {
-            DivisionOutputs::Quotient => AsmOperand::Register(Register::EAX),
-            DivisionOutputs::Remainder => AsmOperand::Register(Register::EDX),
+            DivisionOutputs::Quotient => AsmOperand::Register(Register::ax(&asm_type)),
+            DivisionOutputs::Remainder => AsmOperand::Register(Register::dx(&asm_type)),
         };
         // move division output into dst operand
         let move_out_instruction = MovInstruction::new(
-            output_register, dst_operand.clone()
+            asm_type.clone(), output_register, dst_operand.clone()
         );
+        let extend_instruction = if is_signed {
+            // sign extend EAX into EDX
+            AsmInstruction::SignExtension(asm_type.clone())
+        } else {
+            // the upper half of an unsigned dividend is just zero
+            AsmInstruction::Mov(MovInstruction::new(
+                asm_type.clone(),
+                AsmOperand::ImmediateValue(AsmImmediateValue::new(0)),
+                AsmOperand::Register(Register::dx(&asm_type))
+            ))
+        };
         vec![
             AsmInstruction::Mov(move_into_instruction),
-            AsmInstruction::SignExtension,
-            AsmInstruction::IntegerDivision(
-                AsmIntegerDivision::new(right_operand.clone())
-            ),
+            extend_instruction,
+            AsmInstruction::IntegerDivision(AsmIntegerDivision::new(
+                right_operand.clone(), asm_type, is_signed
+            )),
             AsmInstruction::Mov(move_out_instruction)
         ]
     }
 
+    pub fn build_comparison_instructions(
+        left_operand: AsmOperand,
+        right_operand: AsmOperand,
+        dst_operand: AsmOperand,
+        condition_code: ConditionCode,
+        asm_type: AsmType
+    ) -> Vec<AsmInstruction> {
+        /*
+        Cmp(src2, src1)
+        Mov(0, dst)
+        SetCC(cond_code, dst)
+        */
+        let compare_instruction = AsmCompareInstruction::new(
+            asm_type.clone(), left_operand, right_operand
+        );
+        // comparison results are always ints
+        let clear_instruction = MovInstruction::new(
+            AsmType::Longword,
+            AsmOperand::ImmediateValue(AsmImmediateValue::new(0)),
+            dst_operand.clone()
+        );
+        let mut instructions = vec![
+            AsmInstruction::Compare(compare_instruction),
+            AsmInstruction::Mov(clear_instruction),
+            AsmInstruction::SetConditional(
+                AsmSetConditional::new(condition_code.clone(), dst_operand.clone())
+            )
+        ];
+        /*
+        comisd sets ZF, PF and CF when either operand is NaN, so for doubles
+        == also needs the parity flag clear and != holds if it is set:
+        Mov(0, %r11)
+        SetCC(NP / P, %r11)
+        And / Or(%r11, dst)
+        */
+        let parity_combination = match (asm_type, condition_code) {
+            (AsmType::Double, ConditionCode::E) => {
+                Some((ConditionCode::NP, AsmBinaryOperators::BitwiseAnd))
+            },
+            (AsmType::Double, ConditionCode::NE) => {
+                Some((ConditionCode::P, AsmBinaryOperators::BitwiseOr))
+            },
+            _ => None
+        };
+        if let Some((parity_code, operator)) = parity_combination {
+            let scratch = AsmOperand::Register(Register::R11D);
+            instructions.extend([
+                AsmInstruction::Mov(MovInstruction::new(
+                    AsmType::Longword,
+                    AsmOperand::ImmediateValue(AsmImmediateValue::new(0)),
+                    scratch.clone()
+                )),
+                AsmInstruction::SetConditional(
+                    AsmSetConditional::new(parity_code, scratch.clone())
+                ),
+                AsmInstruction::Binary(AsmBinaryInstruction {
+                    operator, asm_type: AsmType::Longword,
+                    source: scratch, destination: dst_operand
+                })
+            ]);
+        }
+        instructions
+    }
+
     pub fn unpack_from_tacky(binary_instruction: BinaryInstruction) -> Vec<AsmInstruction> {
         /*
       TACKY:
@@ -95,6 +213,12 @@ impl AsmBinaryInstruction {
       ASM instruction applies op to dst using src2
       and stores result in dst
       */
+        // operands have already been converted to a common type
+        let operand_type = binary_instruction.left.get_type();
+        let asm_type = AsmType::from_c_type(&operand_type);
+        let is_double = operand_type.is_double();
+        // comisd sets the same flags as an unsigned comparison
+        let is_signed = operand_type.is_signed() && !is_double;
         let left_operand = AsmOperand::from_tacky_value(binary_instruction.left);
         let right_operand = AsmOperand::from_tacky_value(binary_instruction.right.clone());
         let dst_operand = AsmOperand::from_tacky_value(
@@ -102,30 +226,53 @@ impl AsmBinaryInstruction {
         );
 
         match binary_instruction.operator {
-            SupportedBinaryOperators::Divide => {
+            SupportedBinaryOperators::Divide if !is_double => {
                 return Self::build_divide_instructions(
                     left_operand, right_operand, dst_operand,
-                    DivisionOutputs::Quotient
+                    DivisionOutputs::Quotient, asm_type, is_signed
                 );
             }
             SupportedBinaryOperators::Modulo => {
                 return Self::build_divide_instructions(
                     left_operand, right_operand, dst_operand,
-                    DivisionOutputs::Remainder
+                    DivisionOutputs::Remainder, asm_type, is_signed
+                );
+            },
+            ref operator if operator.is_relational() => {
+                /*
+                below (CF set) also holds when a double comparison is unordered,
+                so < and <= compare the swapped operands with above instead
+                */
+                let (left_operand, right_operand, operator) = match operator {
+                    SupportedBinaryOperators::LessThan if is_double => (
+                        right_operand, left_operand, SupportedBinaryOperators::GreaterThan
+                    ),
+                    SupportedBinaryOperators::LessOrEqual if is_double => (
+                        right_operand, left_operand, SupportedBinaryOperators::GreaterOrEqual
+                    ),
+                    operator => (left_operand, right_operand, operator.clone())
+                };
+                let condition_code = ConditionCode::from_binary_operator(
+                    &operator, is_signed
+                ).unwrap();
+                return Self::build_comparison_instructions(
+                    left_operand, right_operand, dst_operand,
+                    condition_code, asm_type
                 );
             },
             _ => {}
         }
 
         let asm_binary_operator = AsmBinaryOperators::from_supported(
-            binary_instruction.operator
+            binary_instruction.operator, is_signed
         ).unwrap();
         let asm_mov_instruction = MovInstruction::new(
-            left_operand.clone(), dst_operand.clone()
+            asm_type.clone(), left_operand.clone(), dst_operand.clone()
         );
 
         let asm_binary_instruction = AsmBinaryInstruction {
             operator: asm_binary_operator,
+            asm_type,
             source: right_operand,
             destination: dst_operand
         };
@@ -148,12 +295,13 @@ impl ToStackAllocated for AsmBinaryInstruction {
         alloc_buffer.apply_changes(src_alloc_result.new_stack_allocations).unwrap();
 
         let (destination, dest_alloc) =
-            self.destination.to_stack_allocated(stack_value, allocations);
+            self.destination.to_stack_allocated(stack_value, &alloc_buffer);
         let stack_value = dest_alloc.new_stack_value;
         alloc_buffer.apply_changes(dest_alloc.new_stack_allocations).unwrap();
 
         let new_instruction = AsmBinaryInstruction {
             operator: self.operator.clone(),
+            asm_type: self.asm_type.clone(),
             source,
             destination,
         };
@@ -163,61 +311,20 @@ impl ToStackAllocated for AsmBinaryInstruction {
     }
 }
 
-fn generate_multiply_asm(src_asm: String, dst_asm: String) -> String {
-    let mut asm_code: String = String::new();
-    // move destination to multiply scratch register first
-    asm_code.push_str(&format!("movl {dst_asm}, {MUL_SCRATCH_REGISTER}\n"));
-
-    let operator_asm = AsmBinaryOperators::Multiply.to_asm_string();
-    asm_code.push_str(&format!(
-        "{} {}, {}\n",
-        operator_asm, src_asm, MUL_SCRATCH_REGISTER
-    ));
-
-    // move multiply scratch register (modified inplace) back to destination
-    asm_code.push_str(&format!("movl {MUL_SCRATCH_REGISTER}, {dst_asm}"));
-    asm_code
-}
-
 impl AsmSymbol for AsmBinaryInstruction {
-    fn to_asm_code(self) -> Result<String, AsmGenError> {
+    fn to_asm_code(&self) -> Result<String, AsmGenError> {
+        self.to_cached_asm_code(&mut OperandCodeCache::default())
+    }
+    fn to_cached_asm_code(
+        &self, operand_codes: &mut OperandCodeCache
+    ) -> Result<String, AsmGenError> {
         /*
-        e.g. addl -4(%rbp), -8(%rbp)
+        e.g. addl $2, -8(%rbp)
+        operands have already been made valid by the fixup pass
         */
-        let operator_asm = self.operator.to_asm_string();
-        let is_src_stack_addr = self.source.is_stack_address();
-        let src_asm = self.source.to_asm_code()?;
-        let is_dst_stack_addr = self.destination.is_stack_address();
-        let dst_asm = self.destination.to_asm_code()?;
-
-        if is_src_stack_addr && is_dst_stack_addr {
-            /*
-            binary asm instructions where both source and destination
-            operands are stack allocated are not allowed in x86-64 assembly.
-            So we move the value to a scratch register first,
-            then move it to the stack address.
-            */
-            // TODO: maybe a new layer for asm rewrites would be cleaner
-            let mut asm_code: String = String::new();
-            asm_code.push_str(&format!("movl {src_asm}, {SCRATCH_REGISTER}\n"));
-
-            if self.operator == AsmBinaryOperators::Multiply {
-                asm_code.push_str(&*generate_multiply_asm(
-                    SCRATCH_REGISTER.to_string(), dst_asm
-                ))
-            } else {
-                asm_code.push_str(&format!(
-                    "{} {}, {}",
-                    operator_asm, SCRATCH_REGISTER, dst_asm
-                ));
-            }
-            Ok(asm_code)
-        } else {
-            if self.operator == AsmBinaryOperators::Multiply {
-                Ok(generate_multiply_asm(src_asm, dst_asm))
-            } else {
-                Ok(format!("{} {}, {}", operator_asm, src_asm, dst_asm))
-            }
-        }
+        let operator_asm = self.operator.to_asm_string(&self.asm_type);
+        let src_asm = operand_codes.operand_code(&self.source)?;
+        let dst_asm = operand_codes.operand_code(&self.destination)?;
+        Ok(format!("{} {}, {}", operator_asm, src_asm, dst_asm))
     }
 }
@@ -0,0 +1,207 @@
+use crate::asm_gen::asm_symbols::{AsmGenError, AsmOperand, AsmSymbol, AsmType, OperandCodeCache};
+use crate::asm_gen::helpers::{
+    BufferedHashMap, DiffableHashMap, StackAllocationResult, ToStackAllocated
+};
+use crate::parser::parse::SupportedBinaryOperators;
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConditionCode {
+    E, // equal
+    NE, // not equal
+    // signed comparisons
+    L, // less than
+    LE, // less than or equal
+    G, // greater than
+    GE, // greater than or equal
+    // unsigned comparisons
+    B, // below
+    BE, // below or equal
+    A, // above
+    AE, // above or equal
+    // comisd sets the parity flag when either operand is NaN
+    P, // parity (unordered)
+    NP, // no parity (ordered)
+}
+impl ConditionCode {
+    pub fn from_binary_operator(
+        operator: &SupportedBinaryOperators, is_signed: bool
+    ) -> Result<ConditionCode, AsmGenError> {
+        /*
+        Condition code that holds after `cmp right, left` when
+        `left <operator> right` is true. Unsigned operands use the
+        below / above condition codes that read the carry flag instead
+        of the sign and overflow flags.
+        */
+        let condition_code = match (operator, is_signed) {
+            (SupportedBinaryOperators::CheckEqual, _) => ConditionCode::E,
+            (SupportedBinaryOperators::NotEqual, _) => ConditionCode::NE,
+            (SupportedBinaryOperators::LessThan, true) => ConditionCode::L,
+            (SupportedBinaryOperators::LessOrEqual, true) => ConditionCode::LE,
+            (SupportedBinaryOperators::GreaterThan, true) => ConditionCode::G,
+            (SupportedBinaryOperators::GreaterOrEqual, true) => ConditionCode::GE,
+            (SupportedBinaryOperators::LessThan, false) => ConditionCode::B,
+            (SupportedBinaryOperators::LessOrEqual, false) => ConditionCode::BE,
+            (SupportedBinaryOperators::GreaterThan, false) => ConditionCode::A,
+            (SupportedBinaryOperators::GreaterOrEqual, false) => ConditionCode::AE,
+            _ => return Err(AsmGenError::UnsupportedInstruction(
+                format!("Not a comparison operator: {:?}", operator)
+            ))
+        };
+        Ok(condition_code)
+    }
+    pub fn to_suffix(&self) -> &'static str {
+        match self {
+            ConditionCode::E => "e",
+            ConditionCode::NE => "ne",
+            ConditionCode::L => "l",
+            ConditionCode::LE => "le",
+            ConditionCode::G => "g",
+            ConditionCode::GE => "ge",
+            ConditionCode::B => "b",
+            ConditionCode::BE => "be",
+            ConditionCode::A => "a",
+            ConditionCode::AE => "ae",
+            ConditionCode::P => "p",
+            ConditionCode::NP => "np",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct AsmCompareInstruction {
+    pub(crate) asm_type: AsmType,
+    // flags are set according to (left - right)
+    pub(crate) left: AsmOperand,
+    pub(crate) right: AsmOperand,
+}
+impl AsmCompareInstruction {
+    pub fn new(
+        asm_type: AsmType, left: AsmOperand, right: AsmOperand
+    ) -> AsmCompareInstruction {
+        AsmCompareInstruction { asm_type, left, right }
+    }
+}
+impl ToStackAllocated for AsmCompareInstruction {
+    fn to_stack_allocated(
+        &self, stack_value: u64,
+        allocations: &dyn DiffableHashMap<u64, u64>
+    ) -> (Self, StackAllocationResult) {
+        let mut alloc_buffer = BufferedHashMap::new(allocations);
+
+        let (left, left_alloc_result) =
+            self.left.to_stack_allocated(stack_value, alloc_buffer.get_source_ref());
+        let stack_value = left_alloc_result.new_stack_value;
+        alloc_buffer.apply_changes(left_alloc_result.new_stack_allocations).unwrap();
+
+        let (right, right_alloc_result) =
+            self.right.to_stack_allocated(stack_value, &alloc_buffer);
+        let stack_value = right_alloc_result.new_stack_value;
+        alloc_buffer.apply_changes(right_alloc_result.new_stack_allocations).unwrap();
+
+        let new_instruction = AsmCompareInstruction {
+            asm_type: self.asm_type.clone(), left, right
+        };
+        let alloc_result =
+            StackAllocationResult::new_from_buffered(stack_value, alloc_buffer);
+        (new_instruction, alloc_result)
+    }
+}
+impl AsmSymbol for AsmCompareInstruction {
+    fn to_asm_code(&self) -> Result<String, AsmGenError> {
+        self.to_cached_asm_code(&mut OperandCodeCache::default())
+    }
+    fn to_cached_asm_code(
+        &self, operand_codes: &mut OperandCodeCache
+    ) -> Result<String, AsmGenError> {
+        /*
+        e.g. cmpl -8(%rbp), %r10d
+        operands have already been made valid by the fixup pass
+        */
+        let operator_asm = match self.asm_type {
+            AsmType::Double => "comisd".to_string(),
+            _ => format!("cmp{}", self.asm_type.suffix())
+        };
+        let right_asm = operand_codes.operand_code(&self.right)?;
+        let left_asm = operand_codes.operand_code(&self.left)?;
+        Ok(format!("{operator_asm} {right_asm}, {left_asm}"))
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct AsmSetConditional {
+    pub(crate) condition_code: ConditionCode,
+    pub(crate) destination: AsmOperand,
+}
+impl AsmSetConditional {
+    pub fn new(
+        condition_code: ConditionCode, destination: AsmOperand
+    ) -> AsmSetConditional {
+        AsmSetConditional { condition_code, destination }
+    }
+}
+impl ToStackAllocated for AsmSetConditional {
+    fn to_stack_allocated(
+        &self, stack_value: u64,
+        allocations: &dyn DiffableHashMap<u64, u64>
+    ) -> (Self, StackAllocationResult) {
+        let (destination, alloc_result) =
+            self.destination.to_stack_allocated(stack_value, allocations);
+        let new_instruction = AsmSetConditional {
+            condition_code: self.condition_code.clone(),
+            destination
+        };
+        (new_instruction, alloc_result)
+    }
+}
+impl AsmSymbol for AsmSetConditional {
+    fn to_asm_code(&self) -> Result<String, AsmGenError> {
+        self.to_cached_asm_code(&mut OperandCodeCache::default())
+    }
+    fn to_cached_asm_code(
+        &self, operand_codes: &mut OperandCodeCache
+    ) -> Result<String, AsmGenError> {
+        // setcc writes a single byte, which is the lowest byte of
+        // the (little endian) stack address, or the byte alias of a register
+        let destination = self.destination.with_width(&AsmType::Byte);
+        let dst_asm = operand_codes.operand_code(&destination)?;
+        Ok(format!("set{} {}", self.condition_code.to_suffix(), dst_asm))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm_gen::registers::Register;
+
+    #[test]
+    fn test_set_conditional_byte_destination() {
+        let set_register = AsmSetConditional::new(
+            ConditionCode::E, AsmOperand::Register(Register::EAX)
+        );
+        assert_eq!(set_register.to_asm_code().unwrap(), "sete %al");
+        let set_scratch = AsmSetConditional::new(
+            ConditionCode::L, AsmOperand::Register(Register::R10)
+        );
+        assert_eq!(set_scratch.to_asm_code().unwrap(), "setl %r10b");
+    }
+
+    #[test]
+    fn test_unsigned_condition_codes() {
+        let less_than = ConditionCode::from_binary_operator(
+            &SupportedBinaryOperators::LessThan, false
+        ).unwrap();
+        let greater_than = ConditionCode::from_binary_operator(
+            &SupportedBinaryOperators::GreaterThan, false
+        ).unwrap();
+        assert_eq!(less_than.to_suffix(), "b");
+        assert_eq!(greater_than.to_suffix(), "a");
+
+        let signed_less_than = ConditionCode::from_binary_operator(
+            &SupportedBinaryOperators::LessThan, true
+        ).unwrap();
+        assert_eq!(signed_less_than, ConditionCode::L);
+        assert!(ConditionCode::from_binary_operator(
+            &SupportedBinaryOperators::Add, true
+        ).is_err());
+    }
+}
@@ -0,0 +1,183 @@
+use crate::asm_gen::asm_symbols::{
+    AsmGenError, AsmOperand, AsmSymbol, AsmType, OperandCodeCache, MUL_SCRATCH_REGISTER_64,
+    MUL_SCRATCH_REGISTER_XMM, SCRATCH_REGISTER_64, SCRATCH_REGISTER_XMM
+};
+use crate::asm_gen::helpers::{
+    BufferedHashMap, DiffableHashMap, StackAllocationResult, ToStackAllocated
+};
+use crate::tacky::tacky_symbols::ConversionKind;
+
+#[derive(Clone, Debug)]
+pub struct AsmDoubleConversion {
+    pub(crate) kind: ConversionKind,
+    // type of the integer side of the conversion (longword or quadword)
+    pub(crate) int_type: AsmType,
+    pub(crate) source: AsmOperand,
+    pub(crate) destination: AsmOperand,
+}
+impl AsmDoubleConversion {
+    pub fn new(
+        kind: ConversionKind, int_type: AsmType,
+        source: AsmOperand, destination: AsmOperand
+    ) -> AsmDoubleConversion {
+        AsmDoubleConversion { kind, int_type, source, destination }
+    }
+
+    fn int_to_double_asm(&self, src_asm: &str, dst_asm: &str) -> String {
+        /*
+        cvtsi2sd only accepts signed operands, so unsigned ints are zero
+        extended into a quadword first. Unsigned longs that don't fit
+        into a signed long are halved (rounding to odd), converted,
+        then doubled back again
+        */
+        let mut asm_code = String::new();
+        let suffix = self.int_type.suffix();
+        let scratch_register = self.int_type.scratch_register();
+        match (&self.kind, &self.int_type) {
+            (ConversionKind::IntToDouble, _) => {
+                asm_code.push_str(&format!("mov{suffix} {src_asm}, {scratch_register}\n"));
+                asm_code.push_str(&format!(
+                    "cvtsi2sd{suffix} {scratch_register}, {MUL_SCRATCH_REGISTER_XMM}\n"
+                ));
+            },
+            (_, AsmType::Longword) => {
+                // writing a 32-bit register clears the upper half of the register
+                asm_code.push_str(&format!("movl {src_asm}, {scratch_register}\n"));
+                asm_code.push_str(&format!(
+                    "cvtsi2sdq {SCRATCH_REGISTER_64}, {MUL_SCRATCH_REGISTER_XMM}\n"
+                ));
+            },
+            _ => {
+                asm_code.push_str(&format!("movq {src_asm}, {SCRATCH_REGISTER_64}\n"));
+                asm_code.push_str(&format!("testq {SCRATCH_REGISTER_64}, {SCRATCH_REGISTER_64}\n"));
+                asm_code.push_str("js 1f\n");
+                asm_code.push_str(&format!(
+                    "cvtsi2sdq {SCRATCH_REGISTER_64}, {MUL_SCRATCH_REGISTER_XMM}\n"
+                ));
+                asm_code.push_str("jmp 2f\n");
+                asm_code.push_str("1:\n");
+                asm_code.push_str(&format!(
+                    "movq {SCRATCH_REGISTER_64}, {MUL_SCRATCH_REGISTER_64}\n"
+                ));
+                asm_code.push_str(&format!("shrq $1, {MUL_SCRATCH_REGISTER_64}\n"));
+                asm_code.push_str(&format!("andq $1, {SCRATCH_REGISTER_64}\n"));
+                asm_code.push_str(&format!(
+                    "orq {SCRATCH_REGISTER_64}, {MUL_SCRATCH_REGISTER_64}\n"
+                ));
+                asm_code.push_str(&format!(
+                    "cvtsi2sdq {MUL_SCRATCH_REGISTER_64}, {MUL_SCRATCH_REGISTER_XMM}\n"
+                ));
+                asm_code.push_str(&format!(
+                    "addsd {MUL_SCRATCH_REGISTER_XMM}, {MUL_SCRATCH_REGISTER_XMM}\n"
+                ));
+                asm_code.push_str("2:\n");
+            }
+        }
+        asm_code.push_str(&format!("movsd {MUL_SCRATCH_REGISTER_XMM}, {dst_asm}"));
+        asm_code
+    }
+
+    fn double_to_int_asm(&self, dst_asm: &str) -> String {
+        /*
+        cvttsd2si only produces signed results, so unsigned ints are
+        converted as longs and truncated. Doubles that are too large
+        for a signed long have 2^63 subtracted before the conversion,
+        which is added back (as the top bit) afterward.
+        The double has already been moved into the scratch SSE register
+        */
+        let mut asm_code = String::new();
+        let suffix = self.int_type.suffix();
+        let scratch_register = self.int_type.scratch_register();
+        match (&self.kind, &self.int_type) {
+            (ConversionKind::DoubleToInt, _) => {
+                asm_code.push_str(&format!(
+                    "cvttsd2si{suffix} {MUL_SCRATCH_REGISTER_XMM}, {scratch_register}\n"
+                ));
+            },
+            (_, AsmType::Longword) => {
+                asm_code.push_str(&format!(
+                    "cvttsd2siq {MUL_SCRATCH_REGISTER_XMM}, {SCRATCH_REGISTER_64}\n"
+                ));
+            },
+            _ => {
+                let upper_bound = 2f64.powi(63).to_bits();
+                asm_code.push_str(&format!("movq ${upper_bound}, {SCRATCH_REGISTER_64}\n"));
+                asm_code.push_str(&format!("movq {SCRATCH_REGISTER_64}, {SCRATCH_REGISTER_XMM}\n"));
+                asm_code.push_str(&format!(
+                    "comisd {SCRATCH_REGISTER_XMM}, {MUL_SCRATCH_REGISTER_XMM}\n"
+                ));
+                asm_code.push_str("jae 1f\n");
+                asm_code.push_str(&format!(
+                    "cvttsd2siq {MUL_SCRATCH_REGISTER_XMM}, {SCRATCH_REGISTER_64}\n"
+                ));
+                asm_code.push_str("jmp 2f\n");
+                asm_code.push_str("1:\n");
+                asm_code.push_str(&format!(
+                    "subsd {SCRATCH_REGISTER_XMM}, {MUL_SCRATCH_REGISTER_XMM}\n"
+                ));
+                asm_code.push_str(&format!(
+                    "cvttsd2siq {MUL_SCRATCH_REGISTER_XMM}, {SCRATCH_REGISTER_64}\n"
+                ));
+                asm_code.push_str(&format!("btsq $63, {SCRATCH_REGISTER_64}\n"));
+                asm_code.push_str("2:\n");
+            }
+        }
+        asm_code.push_str(&format!("mov{suffix} {scratch_register}, {dst_asm}"));
+        asm_code
+    }
+}
+impl ToStackAllocated for AsmDoubleConversion {
+    fn to_stack_allocated(
+        &self, stack_value: u64,
+        allocations: &dyn DiffableHashMap<u64, u64>
+    ) -> (Self, StackAllocationResult) {
+        let mut alloc_buffer = BufferedHashMap::new(allocations);
+
+        let (source, src_alloc_result) =
+            self.source.to_stack_allocated(stack_value, alloc_buffer.get_source_ref());
+        let stack_value = src_alloc_result.new_stack_value;
+        alloc_buffer.apply_changes(src_alloc_result.new_stack_allocations).unwrap();
+
+        let (destination, dst_alloc_result) =
+            self.destination.to_stack_allocated(stack_value, &alloc_buffer);
+        let stack_value = dst_alloc_result.new_stack_value;
+        alloc_buffer.apply_changes(dst_alloc_result.new_stack_allocations).unwrap();
+
+        let new_instruction = AsmDoubleConversion {
+            kind: self.kind.clone(),
+            int_type: self.int_type.clone(),
+            source,
+            destination
+        };
+        let alloc_result =
+            StackAllocationResult::new_from_buffered(stack_value, alloc_buffer);
+        (new_instruction, alloc_result)
+    }
+}
+impl AsmSymbol for AsmDoubleConversion {
+    fn to_asm_code(&self) -> Result<String, AsmGenError> {
+        self.to_cached_asm_code(&mut OperandCodeCache::default())
+    }
+    fn to_cached_asm_code(
+        &self, operand_codes: &mut OperandCodeCache
+    ) -> Result<String, AsmGenError> {
+        let dst_asm = operand_codes.operand_code(&self.destination)?;
+        match self.kind {
+            ConversionKind::IntToDouble | ConversionKind::UIntToDouble => {
+                let src_asm = operand_codes.operand_code(&self.source)?;
+                Ok(self.int_to_double_asm(&src_asm, &dst_asm))
+            },
+            ConversionKind::DoubleToInt | ConversionKind::DoubleToUInt => {
+                let mut asm_code = self.source.move_to_double_register(
+                    MUL_SCRATCH_REGISTER_XMM, operand_codes
+                )?;
+                asm_code.push('\n');
+                asm_code.push_str(&self.double_to_int_asm(&dst_asm));
+                Ok(asm_code)
+            },
+            _ => Err(AsmGenError::InvalidInstructionType(format!(
+                "{:?} is not a conversion between doubles and integers", self.kind
+            )))
+        }
+    }
+}
@@ -0,0 +1,401 @@
+use crate::asm_gen::asm_symbols::{
+    AsmInstruction, AsmOperand, AsmType, LeaInstruction, MovInstruction,
+    MovZeroExtendInstruction, MovsxInstruction
+};
+use crate::asm_gen::binary_instruction::{AsmBinaryInstruction, AsmBinaryOperators};
+use crate::asm_gen::cmp_instruction::AsmCompareInstruction;
+use crate::asm_gen::interger_division::AsmIntegerDivision;
+use crate::asm_gen::registers::Register;
+
+/*
+Rewrites instructions with operand combinations that x86-64 doesn't
+allow into equivalent instructions that go through scratch registers,
+so that emitting assembly afterward is only a matter of formatting, e.g.
+addl -4(%rbp), -8(%rbp)
+becomes
+movl -4(%rbp), %r10d
+addl %r10d, -8(%rbp)
+
+This runs after stack allocation, since pseudo registers turn into
+stack addresses there. Instructions that are already valid are
+kept as they are, and every rewrite produces at least two instructions
+*/
+
+fn register(register: Register) -> AsmOperand {
+    AsmOperand::Register(register)
+}
+
+fn mov(asm_type: &AsmType, source: AsmOperand, destination: AsmOperand) -> AsmInstruction {
+    AsmInstruction::Mov(MovInstruction::new(asm_type.clone(), source, destination))
+}
+
+fn is_sse_register(operand: &AsmOperand) -> bool {
+    matches!(operand, AsmOperand::Register(register) if register.is_sse())
+}
+
+fn move_double_into(source: AsmOperand, destination: Register) -> Vec<AsmInstruction> {
+    /*
+    there are no floating point immediates, so the bit pattern
+    of a double constant is moved through a general purpose register
+    */
+    match source.is_constant() {
+        true => vec![
+            mov(&AsmType::Quadword, source, register(Register::R10)),
+            mov(&AsmType::Quadword, register(Register::R10), register(destination)),
+        ],
+        false => vec![mov(&AsmType::Double, source, register(destination))]
+    }
+}
+
+fn fix_mov(instruction: MovInstruction) -> Vec<AsmInstruction> {
+    let asm_type = &instruction.asm_type;
+    let source = instruction.source.clone();
+    let destination = instruction.destination.clone();
+
+    if source.is_constant() && (*asm_type == AsmType::Double || is_sse_register(&destination)) {
+        return vec![
+            mov(&AsmType::Quadword, source, register(Register::R10)),
+            mov(&AsmType::Quadword, register(Register::R10), destination),
+        ];
+    }
+    // memory to memory moves aren't allowed, and
+    // 64-bit immediates can only be moved into registers
+    let is_memory_to_memory = source.is_memory() && destination.is_memory();
+    let is_wide_to_memory = source.is_wide_constant(asm_type) && !destination.is_register();
+    if is_memory_to_memory || is_wide_to_memory {
+        let scratch = Register::scratch(asm_type);
+        return vec![
+            mov(asm_type, source, register(scratch.clone())),
+            mov(asm_type, register(scratch), destination),
+        ];
+    }
+    vec![AsmInstruction::Mov(instruction)]
+}
+
+fn fix_double_binary(instruction: AsmBinaryInstruction) -> Vec<AsmInstruction> {
+    /*
+    SSE arithmetic instructions need a register destination, e.g.
+    movsd -8(%rbp), %xmm15
+    addsd -16(%rbp), %xmm15
+    movsd %xmm15, -8(%rbp)
+    */
+    let mut instructions: Vec<AsmInstruction> = vec![];
+    let source = match instruction.source.is_constant() {
+        true => {
+            instructions.extend(move_double_into(instruction.source, Register::XMM14));
+            register(Register::XMM14)
+        },
+        false => instruction.source
+    };
+    let destination = instruction.destination;
+    let binary = |destination: AsmOperand| AsmInstruction::Binary(AsmBinaryInstruction {
+        operator: instruction.operator.clone(),
+        asm_type: AsmType::Double,
+        source: source.clone(),
+        destination,
+    });
+
+    if destination.is_register() {
+        instructions.push(binary(destination));
+    } else {
+        let scratch = register(Register::XMM15);
+        instructions.push(mov(&AsmType::Double, destination.clone(), scratch.clone()));
+        instructions.push(binary(scratch.clone()));
+        instructions.push(mov(&AsmType::Double, scratch, destination));
+    }
+    instructions
+}
+
+fn fix_binary(instruction: AsmBinaryInstruction) -> Vec<AsmInstruction> {
+    let asm_type = instruction.asm_type.clone();
+    if asm_type == AsmType::Double {
+        let is_valid = instruction.destination.is_register() && !instruction.source.is_constant();
+        return match is_valid {
+            true => vec![AsmInstruction::Binary(instruction)],
+            false => fix_double_binary(instruction)
+        };
+    }
+
+    let operator = instruction.operator.clone();
+    let is_count_register = matches!(&instruction.source, AsmOperand::Register(Register::CL));
+    if operator.is_shift() {
+        // variable shift counts have to be in the cl register
+        if instruction.source.is_constant() || is_count_register {
+            return vec![AsmInstruction::Binary(instruction)];
+        }
+        return vec![
+            mov(&asm_type, instruction.source, register(Register::cx(&asm_type))),
+            AsmInstruction::Binary(AsmBinaryInstruction {
+                operator, asm_type, source: register(Register::CL),
+                destination: instruction.destination,
+            }),
+        ];
+    }
+
+    let mut instructions: Vec<AsmInstruction> = vec![];
+    let is_wide_source = instruction.source.is_wide_constant(&asm_type);
+    let is_memory_to_memory =
+        instruction.source.is_memory() && instruction.destination.is_memory();
+    // imul can take a memory source as long as the destination is a register
+    let needs_source_scratch = is_wide_source ||
+        (is_memory_to_memory && operator != AsmBinaryOperators::Multiply);
+    let source = match needs_source_scratch {
+        true => {
+            let scratch = register(Register::scratch(&asm_type));
+            instructions.push(mov(&asm_type, instruction.source, scratch.clone()));
+            scratch
+        },
+        false => instruction.source
+    };
+    let destination = instruction.destination;
+
+    if operator == AsmBinaryOperators::Multiply && !destination.is_register() {
+        // imul needs a register destination
+        let scratch = register(Register::mul_scratch(&asm_type));
+        instructions.push(mov(&asm_type, destination.clone(), scratch.clone()));
+        instructions.push(AsmInstruction::Binary(AsmBinaryInstruction {
+            operator, asm_type: asm_type.clone(), source, destination: scratch.clone()
+        }));
+        instructions.push(mov(&asm_type, scratch, destination));
+    } else {
+        instructions.push(AsmInstruction::Binary(AsmBinaryInstruction {
+            operator, asm_type, source, destination
+        }));
+    }
+    instructions
+}
+
+fn fix_compare(instruction: AsmCompareInstruction) -> Vec<AsmInstruction> {
+    /*
+    cmp right, left sets the flags according to (left - right).
+    left can't be an immediate, both operands can't be memory
+    addresses at the same time and 64-bit immediates have to be
+    in a register. comisd needs left to be an SSE register
+    */
+    let asm_type = instruction.asm_type.clone();
+    let mut instructions: Vec<AsmInstruction> = vec![];
+    let compare = |left: AsmOperand, right: AsmOperand| {
+        AsmInstruction::Compare(AsmCompareInstruction::new(asm_type.clone(), left, right))
+    };
+
+    if asm_type == AsmType::Double {
+        let left = match instruction.left.is_register() {
+            true => instruction.left,
+            false => {
+                instructions.extend(move_double_into(instruction.left, Register::XMM15));
+                register(Register::XMM15)
+            }
+        };
+        let right = match instruction.right.is_constant() {
+            true => {
+                instructions.extend(move_double_into(instruction.right, Register::XMM14));
+                register(Register::XMM14)
+            },
+            false => instruction.right
+        };
+        instructions.push(compare(left, right));
+        return instructions;
+    }
+
+    let left_needs_scratch = instruction.left.is_constant();
+    let right_needs_scratch = instruction.right.is_wide_constant(&asm_type) ||
+        (instruction.right.is_memory() && instruction.left.is_memory());
+    let right = match left_needs_scratch && right_needs_scratch {
+        true => {
+            let scratch = register(Register::mul_scratch(&asm_type));
+            instructions.push(mov(&asm_type, instruction.right, scratch.clone()));
+            scratch
+        },
+        false => instruction.right
+    };
+    let scratch = register(Register::scratch(&asm_type));
+    if left_needs_scratch {
+        instructions.push(mov(&asm_type, instruction.left, scratch.clone()));
+        instructions.push(compare(scratch, right));
+    } else if right_needs_scratch {
+        instructions.push(mov(&asm_type, right, scratch.clone()));
+        instructions.push(compare(instruction.left, scratch));
+    } else {
+        instructions.push(compare(instruction.left, right));
+    }
+    instructions
+}
+
+fn fix_integer_division(instruction: AsmIntegerDivision) -> Vec<AsmInstruction> {
+    // div and idiv can't divide by an immediate
+    if !instruction.operand.is_constant() {
+        return vec![AsmInstruction::IntegerDivision(instruction)];
+    }
+    let scratch = register(Register::scratch(&instruction.asm_type));
+    vec![
+        mov(&instruction.asm_type, instruction.operand, scratch.clone()),
+        AsmInstruction::IntegerDivision(AsmIntegerDivision::new(
+            scratch, instruction.asm_type, instruction.is_signed
+        )),
+    ]
+}
+
+fn fix_lea(instruction: LeaInstruction) -> Vec<AsmInstruction> {
+    // the destination of lea has to be a register
+    if instruction.destination.is_register() {
+        return vec![AsmInstruction::Lea(instruction)];
+    }
+    let scratch = register(Register::R10);
+    vec![
+        AsmInstruction::Lea(LeaInstruction::new(instruction.source, scratch.clone())),
+        mov(&AsmType::Quadword, scratch, instruction.destination),
+    ]
+}
+
+fn fix_extension_operands(
+    src_type: &AsmType, dst_type: &AsmType, source: AsmOperand, destination: AsmOperand,
+    build: impl Fn(AsmOperand, AsmOperand) -> AsmInstruction
+) -> Vec<AsmInstruction> {
+    /*
+    movs{src}{dst} and movzb{dst} can't take an immediate
+    source operand, and their destination has to be a register
+    */
+    if !source.is_constant() && destination.is_register() {
+        return vec![build(source, destination)];
+    }
+    let mut instructions: Vec<AsmInstruction> = vec![];
+    let source = match source.is_constant() {
+        true => {
+            let scratch = register(Register::scratch(src_type));
+            instructions.push(mov(src_type, source, scratch.clone()));
+            scratch
+        },
+        false => source
+    };
+    match destination.is_register() {
+        true => instructions.push(build(source, destination)),
+        false => {
+            let scratch = register(Register::scratch(dst_type));
+            instructions.push(build(source, scratch.clone()));
+            instructions.push(mov(dst_type, scratch, destination));
+        }
+    }
+    instructions
+}
+
+fn fix_movsx(instruction: MovsxInstruction) -> Vec<AsmInstruction> {
+    let MovsxInstruction { src_type, dst_type, source, destination } = instruction;
+    fix_extension_operands(
+        &src_type, &dst_type, source, destination,
+        |source, destination| AsmInstruction::Movsx(MovsxInstruction::new(
+            src_type.clone(), dst_type.clone(), source, destination
+        ))
+    )
+}
+
+fn fix_mov_zero_extend(instruction: MovZeroExtendInstruction) -> Vec<AsmInstruction> {
+    let MovZeroExtendInstruction { src_type, dst_type, source, destination } = instruction;
+    if src_type != AsmType::Byte {
+        /*
+        there is no movzlq: moving a longword into a 32-bit register
+        already clears the upper 32 bits of the full 64-bit register
+        */
+        return vec![
+            mov(&src_type, source, register(Register::scratch(&src_type))),
+            mov(&dst_type, register(Register::scratch(&dst_type)), destination),
+        ];
+    }
+    fix_extension_operands(
+        &src_type, &dst_type, source, destination,
+        |source, destination| AsmInstruction::MovZeroExtend(MovZeroExtendInstruction::new(
+            src_type.clone(), dst_type.clone(), source, destination
+        ))
+    )
+}
+
+pub fn fix_instruction(instruction: AsmInstruction) -> Vec<AsmInstruction> {
+    match instruction {
+        AsmInstruction::Mov(mov_instruction) => fix_mov(mov_instruction),
+        AsmInstruction::Binary(binary_instruction) => fix_binary(binary_instruction),
+        AsmInstruction::Compare(compare_instruction) => fix_compare(compare_instruction),
+        AsmInstruction::IntegerDivision(int_div_instruction) => {
+            fix_integer_division(int_div_instruction)
+        },
+        AsmInstruction::Lea(lea_instruction) => fix_lea(lea_instruction),
+        AsmInstruction::Movsx(movsx_instruction) => fix_movsx(movsx_instruction),
+        AsmInstruction::MovZeroExtend(mov_zero_extend_instruction) => {
+            fix_mov_zero_extend(mov_zero_extend_instruction)
+        },
+        other => vec![other]
+    }
+}
+
+pub fn is_valid_instruction(instruction: &AsmInstruction) -> bool {
+    // whether the instruction can be emitted without any fixing up
+    fix_instruction(instruction.clone()).len() == 1
+}
+
+pub fn fix_instructions(instructions: Vec<AsmInstruction>) -> Vec<AsmInstruction> {
+    instructions.into_iter().flat_map(fix_instruction).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm_gen::asm_symbols::{AsmImmediateValue, AsmSymbol, StackAddress};
+
+    fn stack(offset: u64) -> AsmOperand {
+        AsmOperand::Stack(StackAddress::new(offset, 4))
+    }
+    fn immediate(value: u64) -> AsmOperand {
+        AsmOperand::ImmediateValue(AsmImmediateValue::new(value))
+    }
+    fn to_asm_lines(instruction: AsmInstruction) -> Vec<String> {
+        fix_instruction(instruction).into_iter().map(
+            |instruction| instruction.to_asm_code().unwrap()
+        ).collect()
+    }
+
+    #[test]
+    fn test_fix_memory_operands() {
+        let lines = to_asm_lines(mov(&AsmType::Longword, stack(4), stack(8)));
+        assert_eq!(lines, vec!["movl -4(%rbp), %r10d", "movl %r10d, -8(%rbp)"]);
+        let lines = to_asm_lines(mov(&AsmType::Longword, immediate(3), stack(8)));
+        assert_eq!(lines, vec!["movl $3, -8(%rbp)"]);
+
+        let lines = to_asm_lines(AsmInstruction::Binary(AsmBinaryInstruction {
+            operator: AsmBinaryOperators::Multiply,
+            asm_type: AsmType::Quadword,
+            source: immediate(1 << 40),
+            destination: stack(8),
+        }));
+        assert_eq!(lines, vec![
+            "movq $1099511627776, %r10", "movq -8(%rbp), %r11",
+            "imulq %r10, %r11", "movq %r11, -8(%rbp)"
+        ]);
+    }
+
+    #[test]
+    fn test_fix_compare_operands() {
+        let lines = to_asm_lines(AsmInstruction::Compare(AsmCompareInstruction::new(
+            AsmType::Longword, immediate(1), stack(4)
+        )));
+        assert_eq!(lines, vec!["movl $1, %r10d", "cmpl -4(%rbp), %r10d"]);
+
+        let lines = to_asm_lines(AsmInstruction::Compare(AsmCompareInstruction::new(
+            AsmType::Double, stack(8), immediate(0)
+        )));
+        assert_eq!(lines, vec![
+            "movsd -8(%rbp), %xmm15", "movq $0, %r10",
+            "movq %r10, %xmm14", "comisd %xmm14, %xmm15"
+        ]);
+    }
+
+    #[test]
+    fn test_valid_instructions_are_kept() {
+        let valid = AsmInstruction::IntegerDivision(AsmIntegerDivision::new(
+            stack(4), AsmType::Longword, true
+        ));
+        assert!(is_valid_instruction(&valid));
+        let invalid = AsmInstruction::IntegerDivision(AsmIntegerDivision::new(
+            immediate(3), AsmType::Longword, true
+        ));
+        assert!(!is_valid_instruction(&invalid));
+        assert_eq!(to_asm_lines(invalid), vec!["movl $3, %r10d", "idivl %r10d"]);
+    }
+}
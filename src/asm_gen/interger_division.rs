@@ -1,16 +1,22 @@
-use crate::asm_gen::asm_symbols::SCRATCH_REGISTER;
-use crate::asm_gen::asm_symbols::{AsmOperand, AsmSymbol};
+use crate::asm_gen::asm_symbols::{
+    AsmGenError, AsmOperand, AsmSymbol, AsmType, OperandCodeCache
+};
 use crate::asm_gen::helpers::{
     DiffableHashMap, StackAllocationResult, ToStackAllocated
 };
 
 #[derive(Clone, Debug)]
 pub struct AsmIntegerDivision {
-    operand: AsmOperand,
+    pub(crate) operand: AsmOperand,
+    pub(crate) asm_type: AsmType,
+    // idiv for signed division, div for unsigned division
+    pub(crate) is_signed: bool,
 }
 impl AsmIntegerDivision {
-    pub fn new(operand: AsmOperand) -> AsmIntegerDivision {
-        AsmIntegerDivision { operand }
+    pub fn new(
+        operand: AsmOperand, asm_type: AsmType, is_signed: bool
+    ) -> AsmIntegerDivision {
+        AsmIntegerDivision { operand, asm_type, is_signed }
     }
 }
 impl ToStackAllocated for AsmIntegerDivision {
@@ -22,22 +28,25 @@ impl ToStackAllocated for AsmIntegerDivision {
             self.operand.to_stack_allocated(stack_value, allocations);
         let new_instruction = AsmIntegerDivision {
             operand,
+            asm_type: self.asm_type.clone(),
+            is_signed: self.is_signed,
         };
         (new_instruction, alloc_result)
     }
 }
 impl AsmSymbol for AsmIntegerDivision {
-    fn to_asm_code(self) -> Result<String, crate::asm_gen::asm_symbols::AsmGenError> {
-        let is_constant = self.operand.is_constant();
-        let operand_asm = self.operand.to_asm_code()?;
-
-        if is_constant {
-            let mut asm_code: String = String::new();
-            asm_code.push_str(&format!("movl {operand_asm}, {SCRATCH_REGISTER}\n"));
-            asm_code.push_str(&format!("idivl {SCRATCH_REGISTER}"));
-            Ok(asm_code)
+    fn to_asm_code(&self) -> Result<String, AsmGenError> {
+        self.to_cached_asm_code(&mut OperandCodeCache::default())
+    }
+    fn to_cached_asm_code(
+        &self, operand_codes: &mut OperandCodeCache
+    ) -> Result<String, AsmGenError> {
+        let operand_asm = operand_codes.operand_code(&self.operand)?;
+        let suffix = self.asm_type.suffix();
+        if self.is_signed {
+            Ok(format!("idiv{suffix} {operand_asm}"))
         } else {
-            Ok(format!("idivl {}", operand_asm))
+            Ok(format!("div{suffix} {operand_asm}"))
         }
     }
-}
\ No newline at end of file
+}
@@ -1,5 +1,11 @@
-pub(crate) mod asm_symbols;
+pub mod asm_symbols;
+pub mod abi;
 mod helpers;
+mod registers;
 mod unary_instruction;
 mod binary_instruction;
-mod interger_division;
\ No newline at end of file
+mod cmp_instruction;
+mod interger_division;
+mod double_conversion;
+mod fixup;
+pub mod peephole;pub mod py_asm_gen;
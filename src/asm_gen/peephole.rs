@@ -0,0 +1,314 @@
+use crate::asm_gen::asm_symbols::{AsmInstruction, AsmOperand, AsmType, MovInstruction};
+use crate::asm_gen::binary_instruction::AsmBinaryInstruction;
+use crate::asm_gen::fixup::is_valid_instruction;
+use crate::asm_gen::registers::Register;
+
+/*
+Peephole optimizations over the fixed up assembly instructions,
+looking at one or two neighbouring instructions at a time:
+- mov X, X is removed
+- mov A, R followed by mov R, B becomes mov A, B if R is dead afterwards
+- mov $imm, R followed by op R, M becomes op $imm, M if R is dead afterwards
+
+Registers only ever hold values within the instructions generated for
+a single tacky instruction, but liveness is still checked conservatively:
+jumps, labels and instructions with implicit register operands
+are treated as reading every register. Instructions are only combined
+if the result doesn't need fixing up again
+*/
+
+// largest shift count that fits in the 8-bit immediate of a shift instruction
+const MAX_SHIFT_IMMEDIATE: u64 = 255;
+
+fn same_operand(first: &AsmOperand, second: &AsmOperand) -> bool {
+    match (first, second) {
+        (AsmOperand::ImmediateValue(first), AsmOperand::ImmediateValue(second)) => {
+            first.value == second.value
+        },
+        (AsmOperand::Register(first), AsmOperand::Register(second)) => first == second,
+        (AsmOperand::Pseudo(first), AsmOperand::Pseudo(second)) => first == second,
+        (AsmOperand::Stack(first), AsmOperand::Stack(second)) => {
+            first.offset == second.offset
+        },
+        (AsmOperand::Memory(first), AsmOperand::Memory(second)) => {
+            first.base == second.base && first.offset == second.offset
+        },
+        (AsmOperand::Data(first), AsmOperand::Data(second)) => first.name == second.name,
+        _ => false
+    }
+}
+
+fn uses_register(operand: &AsmOperand, register: &Register) -> bool {
+    // whether the operand is the register or is addressed through it
+    match operand {
+        AsmOperand::Register(operand_register) => operand_register.overlaps(register),
+        AsmOperand::Memory(memory_address) => memory_address.base.overlaps(register),
+        _ => false
+    }
+}
+
+fn addresses_through(operand: &AsmOperand, register: &Register) -> bool {
+    matches!(
+        operand, AsmOperand::Memory(memory_address) if memory_address.base.overlaps(register)
+    )
+}
+
+fn reads_register(instruction: &AsmInstruction, register: &Register) -> bool {
+    match instruction {
+        AsmInstruction::Mov(mov) => {
+            uses_register(&mov.source, register) ||
+                addresses_through(&mov.destination, register)
+        },
+        AsmInstruction::Movsx(movsx) => {
+            uses_register(&movsx.source, register) ||
+                addresses_through(&movsx.destination, register)
+        },
+        AsmInstruction::MovZeroExtend(mov_zero_extend) => {
+            uses_register(&mov_zero_extend.source, register) ||
+                addresses_through(&mov_zero_extend.destination, register)
+        },
+        AsmInstruction::Lea(lea) => {
+            uses_register(&lea.source, register) ||
+                addresses_through(&lea.destination, register)
+        },
+        AsmInstruction::Unary(unary) => uses_register(&unary.destination, register),
+        AsmInstruction::Binary(binary) => {
+            uses_register(&binary.source, register) ||
+                uses_register(&binary.destination, register)
+        },
+        AsmInstruction::Compare(compare) => {
+            uses_register(&compare.left, register) ||
+                uses_register(&compare.right, register)
+        },
+        AsmInstruction::SetConditional(set_conditional) => {
+            // setcc only writes the lowest byte of a register destination
+            uses_register(&set_conditional.destination, register)
+        },
+        AsmInstruction::Comment(_) | AsmInstruction::Loc(_) => false,
+        // return values are passed back in rax or xmm0
+        AsmInstruction::Ret => {
+            register.overlaps(&Register::RAX) || register.overlaps(&Register::XMM0)
+        },
+        AsmInstruction::Jmp(_) | AsmInstruction::JmpConditional(_, _) |
+        AsmInstruction::Label(_) | AsmInstruction::IntegerDivision(_) |
+        AsmInstruction::DoubleConversion(_) | AsmInstruction::SignExtension(_) => true,
+    }
+}
+
+fn overwrites_register(instruction: &AsmInstruction, register: &Register) -> bool {
+    // whether the instruction replaces the whole value of the register
+    let (destination, dst_type) = match instruction {
+        AsmInstruction::Mov(mov) => (&mov.destination, &mov.asm_type),
+        AsmInstruction::Movsx(movsx) => (&movsx.destination, &movsx.dst_type),
+        AsmInstruction::MovZeroExtend(mov_zero_extend) => {
+            (&mov_zero_extend.destination, &mov_zero_extend.dst_type)
+        },
+        AsmInstruction::Lea(lea) => (&lea.destination, &AsmType::Quadword),
+        _ => return false
+    };
+    // byte moves leave the rest of the register unchanged
+    *dst_type != AsmType::Byte && matches!(
+        destination, AsmOperand::Register(dst_register) if dst_register.overlaps(register)
+    )
+}
+
+fn is_dead_after(instructions: &[AsmInstruction], register: &Register) -> bool {
+    // whether the register is overwritten before it is read again
+    for instruction in instructions {
+        if reads_register(instruction, register) {
+            return false;
+        } else if overwrites_register(instruction, register) {
+            return true;
+        }
+    }
+    true
+}
+
+fn is_self_move(mov: &MovInstruction) -> bool {
+    // movl %eax, %eax clears the upper half of rax, so it isn't a no-op
+    let is_zero_extending = mov.asm_type == AsmType::Longword && mov.destination.is_register();
+    same_operand(&mov.source, &mov.destination) && !is_zero_extending
+}
+
+fn merge_moves(
+    first: &MovInstruction, second: &MovInstruction, rest: &[AsmInstruction]
+) -> Option<MovInstruction> {
+    // mov A, R; mov R, B => mov A, B
+    let AsmOperand::Register(register) = &first.destination else {
+        return None;
+    };
+    let is_mergeable = first.asm_type == second.asm_type &&
+        same_operand(&first.destination, &second.source) &&
+        !uses_register(&first.source, register) &&
+        !uses_register(&second.destination, register) &&
+        is_dead_after(rest, register);
+
+    let merged = MovInstruction::new(
+        first.asm_type.clone(), first.source.clone(), second.destination.clone()
+    );
+    match is_mergeable && is_valid_instruction(&AsmInstruction::Mov(merged.clone())) {
+        true => Some(merged),
+        false => None
+    }
+}
+
+fn fold_immediate_source(
+    mov: &MovInstruction, binary: &AsmBinaryInstruction, rest: &[AsmInstruction]
+) -> Option<AsmBinaryInstruction> {
+    // mov $imm, R; op R, M => op $imm, M
+    let (AsmOperand::ImmediateValue(value), AsmOperand::Register(register)) =
+        (&mov.source, &mov.destination) else {
+        return None;
+    };
+    let is_valid_immediate = match binary.operator.is_shift() {
+        true => value.value <= MAX_SHIFT_IMMEDIATE,
+        false => !mov.source.is_wide_constant(&mov.asm_type)
+    };
+    let is_foldable = mov.asm_type == binary.asm_type &&
+        binary.asm_type != AsmType::Double &&
+        is_valid_immediate &&
+        same_operand(&mov.destination, &binary.source) &&
+        !uses_register(&binary.destination, register) &&
+        is_dead_after(rest, register);
+
+    let folded = AsmBinaryInstruction {
+        operator: binary.operator.clone(),
+        asm_type: binary.asm_type.clone(),
+        source: mov.source.clone(),
+        destination: binary.destination.clone(),
+    };
+    match is_foldable && is_valid_instruction(&AsmInstruction::Binary(folded.clone())) {
+        true => Some(folded),
+        false => None
+    }
+}
+
+fn optimize_once(instructions: Vec<AsmInstruction>) -> (Vec<AsmInstruction>, bool) {
+    let mut optimized: Vec<AsmInstruction> = vec![];
+    let mut changed = false;
+    let mut index = 0;
+
+    while index < instructions.len() {
+        let instruction = &instructions[index];
+        if let AsmInstruction::Mov(mov) = instruction {
+            if is_self_move(mov) {
+                changed = true;
+                index += 1;
+                continue;
+            }
+        }
+
+        let rest = &instructions[(index + 1).min(instructions.len())..];
+        let combined = match (instruction, rest.first()) {
+            (AsmInstruction::Mov(first), Some(AsmInstruction::Mov(second))) => {
+                merge_moves(first, second, &rest[1..]).map(AsmInstruction::Mov)
+            },
+            (AsmInstruction::Mov(mov), Some(AsmInstruction::Binary(binary))) => {
+                fold_immediate_source(mov, binary, &rest[1..]).map(AsmInstruction::Binary)
+            },
+            _ => None
+        };
+
+        match combined {
+            Some(combined) => {
+                optimized.push(combined);
+                changed = true;
+                index += 2;
+            },
+            None => {
+                optimized.push(instruction.clone());
+                index += 1;
+            }
+        }
+    }
+    (optimized, changed)
+}
+
+pub fn optimize_instructions(instructions: Vec<AsmInstruction>) -> Vec<AsmInstruction> {
+    // applies the rewrites until none of them match anymore
+    let mut instructions = instructions;
+    loop {
+        let (optimized, changed) = optimize_once(instructions);
+        instructions = optimized;
+        if !changed {
+            return instructions;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm_gen::asm_symbols::{AsmImmediateValue, AsmSymbol, DataAddress};
+    use crate::asm_gen::binary_instruction::AsmBinaryOperators;
+
+    fn immediate(value: u64) -> AsmOperand {
+        AsmOperand::ImmediateValue(AsmImmediateValue::new(value))
+    }
+    fn data(name: &str) -> AsmOperand {
+        AsmOperand::Data(DataAddress::new(name.to_string()))
+    }
+    fn mov(source: AsmOperand, destination: AsmOperand) -> AsmInstruction {
+        AsmInstruction::Mov(MovInstruction::new(AsmType::Longword, source, destination))
+    }
+    fn add(source: AsmOperand, destination: AsmOperand) -> AsmInstruction {
+        AsmInstruction::Binary(AsmBinaryInstruction {
+            operator: AsmBinaryOperators::Add,
+            asm_type: AsmType::Longword,
+            source,
+            destination,
+        })
+    }
+    fn to_asm_lines(instructions: Vec<AsmInstruction>) -> Vec<String> {
+        optimize_instructions(instructions).into_iter().map(
+            |instruction| instruction.to_asm_code().unwrap()
+        ).collect()
+    }
+
+    #[test]
+    fn test_remove_self_moves() {
+        let lines = to_asm_lines(vec![
+            mov(data("a"), data("a")),
+            mov(AsmOperand::Register(Register::EAX), AsmOperand::Register(Register::EAX)),
+            AsmInstruction::Ret,
+        ]);
+        assert_eq!(lines[0], "movl %eax, %eax");
+        assert_eq!(lines.len(), 2);
+    }
+
+    #[test]
+    fn test_merge_moves_through_register() {
+        let r10 = AsmOperand::Register(Register::R10D);
+        let lines = to_asm_lines(vec![
+            mov(immediate(3), r10.clone()),
+            mov(r10.clone(), data("a")),
+            mov(data("a"), r10.clone()),
+            mov(r10.clone(), data("b")),
+            // the register is still read afterwards, so these are kept
+            mov(data("b"), AsmOperand::Register(Register::EAX)),
+            mov(AsmOperand::Register(Register::EAX), data("c")),
+            AsmInstruction::Ret,
+        ]);
+        // memory to memory moves would need fixing up again, so they aren't merged
+        assert_eq!(lines[..6], [
+            "movl $3, a(%rip)", "movl a(%rip), %r10d", "movl %r10d, b(%rip)",
+            "movl b(%rip), %eax", "movl %eax, c(%rip)", "ret"
+        ]);
+    }
+
+    #[test]
+    fn test_fold_immediate_into_binary() {
+        let r11 = AsmOperand::Register(Register::R11D);
+        let lines = to_asm_lines(vec![
+            mov(immediate(5), r11.clone()),
+            add(r11.clone(), data("a")),
+            mov(immediate(7), r11.clone()),
+            add(r11.clone(), data("a")),
+            add(r11.clone(), data("b")),
+        ]);
+        assert_eq!(lines, vec![
+            "addl $5, a(%rip)", "movl $7, %r11d",
+            "addl %r11d, a(%rip)", "addl %r11d, b(%rip)"
+        ]);
+    }
+}
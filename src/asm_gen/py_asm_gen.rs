@@ -0,0 +1,62 @@
+use pyo3::{pyfunction, PyResult};
+use pyo3::exceptions::PyValueError;
+use pyo3_stub_gen::derive::gen_stub_pyfunction;
+use crate::asm_gen::abi::Abi;
+use crate::asm_gen::asm_symbols::{asm_gen_from_filepath, AsmSymbol};
+use crate::assembler::Target;
+use crate::py_errors::{codegen_error_to_py, compiler_error_to_py};
+use crate::riscv_gen::riscv_asm::RiscvProgram;
+use crate::tacky::tacky_symbols::tacky_gen_from_filepath;
+
+/*
+Compiles a C source file to assembly for a target named as in
+ccc --target (x86_64, x86_64-windows, x86_64-darwin or riscv64),
+without the optimizations that ccc only applies when asked to
+*/
+#[gen_stub_pyfunction(module = "py_ca_compiler.compiler")]
+#[pyfunction]
+pub fn compile_to_asm(source_path: String, target: String) -> PyResult<String> {
+    let target = match Target::from_name(&target) {
+        Some(target) => target,
+        None => return Err(PyValueError::new_err(format!("Unknown target {}", target)))
+    };
+    let abi = match target {
+        Target::X86_64 => Abi::SystemV,
+        Target::X86_64Windows => Abi::Windows,
+        Target::X86_64Darwin => Abi::Darwin,
+        Target::Riscv64 => {
+            let tacky_program = tacky_gen_from_filepath(&source_path, false)
+                .map_err(compiler_error_to_py)?;
+            return RiscvProgram::from_tacky_program(tacky_program)
+                .to_asm_code().map_err(codegen_error_to_py);
+        }
+    };
+    let asm_program = asm_gen_from_filepath(&source_path, false)
+        .map_err(compiler_error_to_py)?;
+    asm_program.with_abi(abi).to_asm_code().map_err(codegen_error_to_py)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use super::*;
+
+    #[test]
+    fn test_compile_to_asm_targets() {
+        // errors get raised as exceptions, which need the interpreter
+        pyo3::prepare_freethreaded_python();
+        let source_path = std::env::temp_dir().join("py_asm_gen_return_2.c");
+        fs::write(&source_path, "int main(void) { return 2; }").unwrap();
+        let source_path = source_path.to_string_lossy().to_string();
+
+        let x86_asm = compile_to_asm(source_path.clone(), "x86_64".to_string()).unwrap();
+        assert!(x86_asm.contains("movl $2, %eax"), "{}", x86_asm);
+        let darwin_asm = compile_to_asm(source_path.clone(), "x86_64-darwin".to_string()).unwrap();
+        assert!(darwin_asm.contains("_main"), "{}", darwin_asm);
+        let riscv_asm = compile_to_asm(source_path.clone(), "riscv64".to_string()).unwrap();
+        assert!(riscv_asm.contains("li"), "{}", riscv_asm);
+
+        assert!(compile_to_asm(source_path, "arm".to_string()).is_err());
+        assert!(compile_to_asm("missing.c".to_string(), "x86_64".to_string()).is_err());
+    }
+}
@@ -0,0 +1,228 @@
+use crate::asm_gen::asm_symbols::{AsmGenError, AsmSymbol, AsmType};
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Register {
+    EAX, // division quotient register 1 + division result register
+    EDX, // division quotient register 2 + division remainder register
+    R10D, // scratch register
+    R11D,
+    ECX, // shift count register
+    // 64-bit versions of the registers above
+    RAX,
+    RDX,
+    R10,
+    R11,
+    RCX,
+    // lowest byte of the registers above
+    AL,
+    DL,
+    R10B,
+    R11B,
+    CL,
+    // stack pointer, used to address stack slots when %rbp is omitted
+    RSP,
+    // remaining integer argument registers of the calling conventions
+    RDI,
+    RSI,
+    R8,
+    R9,
+    EDI,
+    ESI,
+    R8D,
+    R9D,
+    DIL,
+    SIL,
+    R8B,
+    R9B,
+    // SSE registers for double precision floating point values
+    XMM0, // double return value register
+    XMM1,
+    XMM2,
+    XMM3,
+    XMM4,
+    XMM5,
+    XMM6,
+    XMM7,
+    XMM8,
+    XMM9,
+    XMM10,
+    XMM11,
+    XMM12,
+    XMM13,
+    XMM14, // double scratch register
+    XMM15,
+}
+impl Register {
+    pub fn ax(asm_type: &AsmType) -> Register {
+        match asm_type {
+            // doubles are returned in xmm0 instead of rax
+            AsmType::Double => Register::XMM0,
+            _ => Register::RAX.with_width(asm_type),
+        }
+    }
+    pub fn dx(asm_type: &AsmType) -> Register {
+        match asm_type {
+            AsmType::Double => panic!("Doubles are not divided through rdx"),
+            _ => Register::RDX.with_width(asm_type),
+        }
+    }
+    pub fn cx(asm_type: &AsmType) -> Register {
+        match asm_type {
+            AsmType::Double => panic!("Doubles can't be used as shift counts"),
+            _ => Register::RCX.with_width(asm_type),
+        }
+    }
+    pub fn scratch(asm_type: &AsmType) -> Register {
+        // register used to fix up operands that aren't allowed in an instruction
+        match asm_type {
+            AsmType::Double => Register::XMM14,
+            _ => Register::R10.with_width(asm_type),
+        }
+    }
+    pub fn mul_scratch(asm_type: &AsmType) -> Register {
+        // second scratch register, for when two operands need fixing up
+        match asm_type {
+            AsmType::Double => Register::XMM15,
+            _ => Register::R11.with_width(asm_type),
+        }
+    }
+    pub fn with_width(&self, asm_type: &AsmType) -> Register {
+        /*
+        The alias of this general purpose register that is as wide as
+        the given type, e.g. %eax -> %al for bytes. Doubles only pass
+        through general purpose registers as their quadword bit pattern.
+        SSE registers and %rsp have no narrower aliases to pick from
+        */
+        let (byte, longword, quadword) = match self.to_quadword() {
+            Register::RAX => (Register::AL, Register::EAX, Register::RAX),
+            Register::RDX => (Register::DL, Register::EDX, Register::RDX),
+            Register::RCX => (Register::CL, Register::ECX, Register::RCX),
+            Register::R10 => (Register::R10B, Register::R10D, Register::R10),
+            Register::R11 => (Register::R11B, Register::R11D, Register::R11),
+            Register::RDI => (Register::DIL, Register::EDI, Register::RDI),
+            Register::RSI => (Register::SIL, Register::ESI, Register::RSI),
+            Register::R8 => (Register::R8B, Register::R8D, Register::R8),
+            Register::R9 => (Register::R9B, Register::R9D, Register::R9),
+            other => return other
+        };
+        match asm_type {
+            AsmType::Byte => byte,
+            AsmType::Longword => longword,
+            AsmType::Quadword | AsmType::Double => quadword,
+        }
+    }
+    pub fn is_sse(&self) -> bool {
+        self.sse_index().is_some()
+    }
+    pub fn to_quadword(&self) -> Register {
+        // the full 64-bit register that this register is a part of
+        match self {
+            Register::EAX | Register::AL => Register::RAX,
+            Register::EDX | Register::DL => Register::RDX,
+            Register::R10D | Register::R10B => Register::R10,
+            Register::R11D | Register::R11B => Register::R11,
+            Register::ECX | Register::CL => Register::RCX,
+            Register::EDI | Register::DIL => Register::RDI,
+            Register::ESI | Register::SIL => Register::RSI,
+            Register::R8D | Register::R8B => Register::R8,
+            Register::R9D | Register::R9B => Register::R9,
+            other => other.clone(),
+        }
+    }
+    pub fn overlaps(&self, other: &Register) -> bool {
+        // whether writing to one register changes the value of the other
+        self.to_quadword() == other.to_quadword()
+    }
+    fn sse_index(&self) -> Option<u8> {
+        let index = match self {
+            Register::XMM0 => 0,
+            Register::XMM1 => 1,
+            Register::XMM2 => 2,
+            Register::XMM3 => 3,
+            Register::XMM4 => 4,
+            Register::XMM5 => 5,
+            Register::XMM6 => 6,
+            Register::XMM7 => 7,
+            Register::XMM8 => 8,
+            Register::XMM9 => 9,
+            Register::XMM10 => 10,
+            Register::XMM11 => 11,
+            Register::XMM12 => 12,
+            Register::XMM13 => 13,
+            Register::XMM14 => 14,
+            Register::XMM15 => 15,
+            _ => return None
+        };
+        Some(index)
+    }
+}
+impl AsmSymbol for Register {
+    fn to_asm_code(&self) -> Result<String, AsmGenError> {
+        if let Some(index) = self.sse_index() {
+            return Ok(format!("%xmm{index}"));
+        }
+        match self {
+            Register::EAX => Ok("%eax".to_string()),
+            Register::R10D => Ok("%r10d".to_string()),
+            Register::EDX => Ok("%edx".to_string()),
+            Register::R11D => Ok("%r11d".to_string()),
+            Register::ECX => Ok("%ecx".to_string()),
+            Register::RAX => Ok("%rax".to_string()),
+            Register::RDX => Ok("%rdx".to_string()),
+            Register::R10 => Ok("%r10".to_string()),
+            Register::R11 => Ok("%r11".to_string()),
+            Register::RCX => Ok("%rcx".to_string()),
+            Register::AL => Ok("%al".to_string()),
+            Register::DL => Ok("%dl".to_string()),
+            Register::R10B => Ok("%r10b".to_string()),
+            Register::R11B => Ok("%r11b".to_string()),
+            Register::CL => Ok("%cl".to_string()),
+            Register::RSP => Ok("%rsp".to_string()),
+            Register::RDI => Ok("%rdi".to_string()),
+            Register::RSI => Ok("%rsi".to_string()),
+            Register::R8 => Ok("%r8".to_string()),
+            Register::R9 => Ok("%r9".to_string()),
+            Register::EDI => Ok("%edi".to_string()),
+            Register::ESI => Ok("%esi".to_string()),
+            Register::R8D => Ok("%r8d".to_string()),
+            Register::R9D => Ok("%r9d".to_string()),
+            Register::DIL => Ok("%dil".to_string()),
+            Register::SIL => Ok("%sil".to_string()),
+            Register::R8B => Ok("%r8b".to_string()),
+            Register::R9B => Ok("%r9b".to_string()),
+            _ => unreachable!()
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sse_registers() {
+        assert_eq!(Register::XMM0.to_asm_code().unwrap(), "%xmm0");
+        assert_eq!(Register::XMM15.to_asm_code().unwrap(), "%xmm15");
+        assert_eq!(Register::RAX.to_asm_code().unwrap(), "%rax");
+        assert_eq!(Register::ax(&AsmType::Double).to_asm_code().unwrap(), "%xmm0");
+    }
+
+    #[test]
+    fn test_register_overlaps() {
+        assert!(Register::AL.overlaps(&Register::RAX));
+        assert!(Register::R10D.overlaps(&Register::R10B));
+        assert!(!Register::R10.overlaps(&Register::R11));
+        assert!(!Register::XMM14.overlaps(&Register::XMM15));
+    }
+
+    #[test]
+    fn test_register_widths() {
+        assert_eq!(Register::EAX.with_width(&AsmType::Byte), Register::AL);
+        assert_eq!(Register::R10B.with_width(&AsmType::Quadword), Register::R10);
+        assert_eq!(Register::R9.with_width(&AsmType::Longword), Register::R9D);
+        assert_eq!(Register::DIL.with_width(&AsmType::Double), Register::RDI);
+        assert_eq!(Register::XMM3.with_width(&AsmType::Byte), Register::XMM3);
+        assert_eq!(Register::scratch(&AsmType::Byte), Register::R10B);
+        assert_eq!(Register::ax(&AsmType::Double), Register::XMM0);
+    }
+}
@@ -1,19 +1,23 @@
-use crate::asm_gen::asm_symbols::{AsmGenError, AsmOperand, AsmSymbol};
+use crate::asm_gen::asm_symbols::{
+    AsmGenError, AsmOperand, AsmSymbol, AsmType, OperandCodeCache
+};
 use crate::asm_gen::helpers::{DiffableHashMap, StackAllocationResult, ToStackAllocated};
 use crate::parser::parse::SupportedUnaryOperators;
 
 #[derive(Clone, Debug)]
 pub struct AsmUnaryInstruction {
     pub(crate) operator: SupportedUnaryOperators,
+    pub(crate) asm_type: AsmType,
     pub(crate) destination: AsmOperand,
 }
 impl AsmUnaryInstruction {
     fn operator_to_asm_string(
-        operator: SupportedUnaryOperators
+        operator: &SupportedUnaryOperators, asm_type: &AsmType
     ) -> Result<String, AsmGenError> {
+        let suffix = asm_type.suffix();
         match operator {
-            SupportedUnaryOperators::Subtract => Ok("negl".to_string()),
-            SupportedUnaryOperators::BitwiseNot => Ok("notl".to_string()),
+            SupportedUnaryOperators::Subtract => Ok(format!("neg{suffix}")),
+            SupportedUnaryOperators::BitwiseNot => Ok(format!("not{suffix}")),
             _ => Err(AsmGenError::UnsupportedInstruction(
                 format!("Unsupported unary operator: {:?}", operator)
             )),
@@ -29,15 +33,32 @@ impl ToStackAllocated for AsmUnaryInstruction {
             self.destination.to_stack_allocated(stack_value, allocations);
         let new_instruction = AsmUnaryInstruction {
             operator: self.operator.clone(),
+            asm_type: self.asm_type.clone(),
             destination: operand,
         };
         (new_instruction, alloc_result)
     }
 }
 impl AsmSymbol for AsmUnaryInstruction {
-    fn to_asm_code(self) -> Result<String, AsmGenError> {
-        let operand_asm = self.destination.to_asm_code()?;
-        let operator_asm = Self::operator_to_asm_string(self.operator)?;
+    fn to_asm_code(&self) -> Result<String, AsmGenError> {
+        self.to_cached_asm_code(&mut OperandCodeCache::default())
+    }
+    fn to_cached_asm_code(
+        &self, operand_codes: &mut OperandCodeCache
+    ) -> Result<String, AsmGenError> {
+        let operand_asm = operand_codes.operand_code(&self.destination)?;
+        if self.asm_type == AsmType::Double {
+            // negating a double just flips its sign bit
+            return match &self.operator {
+                SupportedUnaryOperators::Subtract => Ok(format!("btcq $63, {operand_asm}")),
+                operator => Err(AsmGenError::UnsupportedInstruction(
+                    format!("Unsupported unary operator for doubles: {:?}", operator)
+                ))
+            };
+        }
+        let operator_asm = Self::operator_to_asm_string(
+            &self.operator, &self.asm_type
+        )?;
         Ok(format!("{} {}", operator_asm, operand_asm))
     }
 }
\ No newline at end of file
@@ -0,0 +1,245 @@
+use std::fmt::{Debug, Display};
+use std::fs;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use crate::asm_gen::asm_symbols::{asm_gen_from_filepath, AsmGenError, AsmSymbol};
+use crate::errors::CompilerError;
+
+/*
+Final stage of the pipeline: writes the generated assembly to a .s file,
+then hands it to gcc to assemble (and link) it into an object file
+or executable
+*/
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum OutputKind {
+    // stop after writing the .s file
+    Assembly,
+    // assemble into a .o file without linking
+    Object,
+    // assemble and link into an executable
+    Executable,
+}
+impl OutputKind {
+    pub fn default_output_path(&self, source_path: &Path) -> PathBuf {
+        match self {
+            OutputKind::Assembly => source_path.with_extension("s"),
+            OutputKind::Object => source_path.with_extension("o"),
+            OutputKind::Executable => source_path.with_extension(""),
+        }
+    }
+    pub fn asm_path<'a>(&self, scratch_asm_path: &'a Path, output_path: &'a Path) -> &'a Path {
+        // the .s file is only kept around if it is the requested output
+        match self {
+            OutputKind::Assembly => output_path,
+            _ => scratch_asm_path
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Target {
+    X86_64,
+    // x86-64 Windows through the MinGW cross toolchain
+    X86_64Windows,
+    // x86-64 macOS, assembled by clang
+    X86_64Darwin,
+    // RV64 linux, run through qemu-riscv64 on other hosts
+    Riscv64,
+}
+impl Target {
+    pub fn from_name(name: &str) -> Option<Target> {
+        match name {
+            "x86_64" => Some(Target::X86_64),
+            "x86_64-windows" => Some(Target::X86_64Windows),
+            "x86_64-darwin" => Some(Target::X86_64Darwin),
+            "riscv64" => Some(Target::Riscv64),
+            _ => None
+        }
+    }
+    pub fn gcc_command(&self) -> &'static str {
+        match self {
+            Target::X86_64 => "gcc",
+            Target::X86_64Windows => "x86_64-w64-mingw32-gcc",
+            Target::X86_64Darwin => "clang",
+            Target::Riscv64 => "riscv64-linux-gnu-gcc",
+        }
+    }
+    fn arch_args(&self) -> &'static [&'static str] {
+        // clang on Apple silicon builds for arm64 unless told otherwise
+        match self {
+            Target::X86_64Darwin => &["-arch", "x86_64"],
+            _ => &[]
+        }
+    }
+    fn link_args(&self) -> &'static [&'static str] {
+        // static linking lets qemu-riscv64 run the executable without a sysroot
+        match self {
+            Target::X86_64 | Target::X86_64Windows | Target::X86_64Darwin => &[],
+            Target::Riscv64 => &["-static"],
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum AssembleAndLinkError {
+    IoError(std::io::Error),
+    GccError(String),
+    AsmGenError(AsmGenError),
+}
+impl Display for AssembleAndLinkError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AssembleAndLinkError::IoError(err) => write!(f, "IO Error: {}", err),
+            AssembleAndLinkError::GccError(msg) => write!(f, "GCC Error: {}", msg),
+            AssembleAndLinkError::AsmGenError(err) => {
+                write!(f, "Assembly Generation Error: {}", err)
+            },
+        }
+    }
+}
+impl std::error::Error for AssembleAndLinkError {}
+
+fn run_gcc(
+    target: &Target, args: &[&Path], compile_only: bool
+) -> Result<(), AssembleAndLinkError> {
+    // args are the output path followed by the input files
+    let mut command = Command::new(target.gcc_command());
+    command.args(target.arch_args());
+    if compile_only {
+        command.arg("-c");
+    } else {
+        command.args(target.link_args());
+    }
+    command.arg("-o").args(args);
+
+    let status = match command.status() {
+        Ok(status) => status,
+        Err(err) => return Err(AssembleAndLinkError::IoError(err)),
+    };
+    if !status.success() {
+        let error_message = format!(
+            "GCC failed with status: {}", status
+        );
+        return Err(AssembleAndLinkError::GccError(error_message));
+    }
+    Ok(())
+}
+
+pub fn assemble(
+    target: &Target, asm_path: &Path, object_path: &Path
+) -> Result<(), AssembleAndLinkError> {
+    run_gcc(target, &[object_path, asm_path], true)
+}
+
+pub fn assemble_and_link(
+    target: &Target, asm_path: &Path, exe_path: &Path
+) -> Result<(), AssembleAndLinkError> {
+    run_gcc(target, &[exe_path, asm_path], false)
+}
+
+pub fn write_asm_file(asm_code: &str, asm_path: &Path) -> Result<(), AssembleAndLinkError> {
+    fs::write(asm_path, asm_code).map_err(AssembleAndLinkError::IoError)
+}
+
+pub fn stream_asm_file<F>(asm_path: &Path, emit: F) -> Result<(), AssembleAndLinkError>
+where F: FnOnce(&mut BufWriter<fs::File>) -> Result<(), AsmGenError> {
+    // writes the assembly into the .s file as it is generated
+    let file = fs::File::create(asm_path).map_err(AssembleAndLinkError::IoError)?;
+    let mut writer = BufWriter::new(file);
+    emit(&mut writer).map_err(AssembleAndLinkError::AsmGenError)?;
+    writer.flush().map_err(AssembleAndLinkError::IoError)
+}
+
+pub fn build_from_asm_code(
+    target: &Target, asm_code: &str, asm_path: &Path,
+    output_kind: &OutputKind, output_path: &Path
+) -> Result<(), AssembleAndLinkError> {
+    // writes asm_code to asm_path and produces the requested output from it
+    let asm_path = output_kind.asm_path(asm_path, output_path);
+    write_asm_file(asm_code, asm_path)?;
+    build_from_asm_file(target, asm_path, output_kind, output_path)
+}
+
+pub fn build_from_asm_file(
+    target: &Target, asm_path: &Path,
+    output_kind: &OutputKind, output_path: &Path
+) -> Result<(), AssembleAndLinkError> {
+    /*
+    Produces the requested output from an already written .s file,
+    which is removed afterward unless it is the requested output
+    */
+    let build_result = match output_kind {
+        OutputKind::Assembly => return Ok(()),
+        OutputKind::Object => assemble(target, asm_path, output_path),
+        OutputKind::Executable => assemble_and_link(target, asm_path, output_path),
+    };
+    let _ = fs::remove_file(asm_path);
+    build_result
+}
+
+pub fn build_from_filepath(
+    source_path: &Path, output_kind: &OutputKind,
+    output_path: Option<&Path>, verbose: bool
+) -> Result<PathBuf, CompilerError> {
+    // compiles the C source file, returning the path of the output file
+    let source_filepath = match source_path.to_str() {
+        Some(source_filepath) => source_filepath,
+        None => return Err(CompilerError::AssembleError(AssembleAndLinkError::IoError(
+            std::io::Error::new(std::io::ErrorKind::InvalidInput, "Source path is not valid UTF-8")
+        )))
+    };
+    let asm_program = asm_gen_from_filepath(source_filepath, verbose)?;
+
+    let output_path = match output_path {
+        Some(output_path) => output_path.to_path_buf(),
+        None => output_kind.default_output_path(source_path)
+    };
+    let scratch_asm_path = source_path.with_extension("s");
+    let asm_path = output_kind.asm_path(&scratch_asm_path, &output_path);
+    stream_asm_file(asm_path, |writer| asm_program.emit_asm_code(writer))?;
+    build_from_asm_file(&Target::X86_64, asm_path, output_kind, &output_path)?;
+    Ok(output_path)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_output_paths() {
+        let source_path = Path::new("dir/prog.c");
+        assert_eq!(
+            OutputKind::Assembly.default_output_path(source_path), PathBuf::from("dir/prog.s")
+        );
+        assert_eq!(
+            OutputKind::Object.default_output_path(source_path), PathBuf::from("dir/prog.o")
+        );
+        assert_eq!(
+            OutputKind::Executable.default_output_path(source_path), PathBuf::from("dir/prog")
+        );
+    }
+
+    #[test]
+    fn test_build_assembly_output() {
+        let output_path = std::env::temp_dir().join("ca_compiler_build_test.s");
+        let asm_code = "    .globl main\nmain:\n    movl $0, %eax\n    ret\n";
+        build_from_asm_code(
+            &Target::X86_64, asm_code, Path::new("unused.s"),
+            &OutputKind::Assembly, &output_path
+        ).unwrap();
+        assert_eq!(fs::read_to_string(&output_path).unwrap(), asm_code);
+        fs::remove_file(&output_path).unwrap();
+    }
+
+    #[test]
+    fn test_target_names() {
+        assert_eq!(Target::from_name("x86_64"), Some(Target::X86_64));
+        assert_eq!(Target::from_name("riscv64"), Some(Target::Riscv64));
+        assert_eq!(Target::from_name("x86_64-windows"), Some(Target::X86_64Windows));
+        assert_eq!(Target::from_name("x86_64-darwin"), Some(Target::X86_64Darwin));
+        assert_eq!(Target::from_name("arm"), None);
+        assert_eq!(Target::Riscv64.gcc_command(), "riscv64-linux-gnu-gcc");
+    }
+}
@@ -1,14 +1,12 @@
-use std::cmp::Ordering;
 use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
 use std::hash::Hash;
 use enum_iterator::Sequence;
+use crate::automata::terms::Expression;
 
 /*
 TODO:
-    - add rules accumulator
     - translate rules to multi-tape equations
-    - compose multi-tape automata to single-tape automata
-    - APL style rule builders (accumulator, reduct input, unary expansion)
 */
 
 const VOID_STATE: u32 = 0;
@@ -19,6 +17,67 @@ pub enum Direction {
     Right,
     Middle,
 }
+impl Direction {
+    // offset of the cell in this direction from the cell being written
+    pub fn offset(&self) -> i64 {
+        match self {
+            Direction::Left => -1,
+            Direction::Middle => 0,
+            Direction::Right => 1,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum CompositionError {
+    // more combinations of tape states than fit in the u8 states of automata::terms
+    TooManyStates(usize),
+    // rules for different write tapes writing different states to the same read tape cell
+    ConflictingWrites { tape_key: TapeKey, states: (u32, u32) },
+}
+impl Display for CompositionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CompositionError::TooManyStates(num_states) => write!(
+                f, "Composed tape needs {} states but at most {} are supported",
+                num_states, u8::MAX
+            ),
+            CompositionError::ConflictingWrites { tape_key, states } => write!(
+                f, "Rules write both {} and {} to the same cell of {:?}",
+                states.0, states.1, tape_key
+            ),
+        }
+    }
+}
+impl std::error::Error for CompositionError {}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RuleBuildError {
+    UnknownTape(String),
+    // rules can only be declared for write tapes, and only write to read tapes
+    WrongTapeKind(String),
+    StateNotAllowed { tape_name: String, state: u32 },
+    DuplicateExpectation { tape_name: String, direction: Direction },
+}
+impl Display for RuleBuildError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RuleBuildError::UnknownTape(tape_name) => write!(
+                f, "No tape named {}", tape_name
+            ),
+            RuleBuildError::WrongTapeKind(tape_name) => write!(
+                f, "Tape {} can't be written this way", tape_name
+            ),
+            RuleBuildError::StateNotAllowed { tape_name, state } => write!(
+                f, "State {} isn't allowed on tape {}", state, tape_name
+            ),
+            RuleBuildError::DuplicateExpectation { tape_name, direction } => write!(
+                f, "Cell {:?} of tape {} is already expected", direction, tape_name
+            ),
+        }
+    }
+}
+impl std::error::Error for RuleBuildError {}
 
 #[derive(Clone, Debug, Eq, PartialEq, Hash, PartialOrd, Ord)]
 pub struct WritableTapeKey {
@@ -118,6 +177,28 @@ impl CellExpectationCombo {
         let prev_value = self.cell_expectations.insert(identifier, expectation);
         assert_eq!(prev_value, None);
     }
+    // whether some cells can meet both combos
+    pub fn overlaps(&self, other: &CellExpectationCombo) -> bool {
+        self.cell_expectations.iter().all(|(identifier, expectation)| {
+            other.cell_expectations.get(identifier).is_none_or(|other| other == expectation)
+        })
+    }
+    // whether cells that meet other always meet this combo too
+    pub fn covers(&self, other: &CellExpectationCombo) -> bool {
+        self.cell_expectations.iter().all(|(identifier, expectation)| {
+            other.cell_expectations.get(identifier) == Some(expectation)
+        })
+    }
+    fn without(&self, identifier: &TapeCellIdentifier) -> CellExpectationCombo {
+        let mut combo = self.clone();
+        combo.cell_expectations.remove(identifier);
+        combo
+    }
+    fn sorted_expectations(&self) -> Vec<&CellExpectation> {
+        let mut expectations: Vec<&CellExpectation> = self.cell_expectations.values().collect();
+        expectations.sort();
+        expectations
+    }
 }
 
 #[derive(Debug, Clone)]
@@ -130,6 +211,115 @@ pub struct WriteRule {
     // at the same position
     read_tape_write_values: Vec<(ReadableTapeKey, TapeState)>,
 }
+impl WriteRule {
+    pub fn new(
+        expectations: CellExpectationCombo,
+        write_tape: WritableTapeKey,
+        self_write_value: TapeState,
+        read_tape_write_values: Vec<(ReadableTapeKey, TapeState)>,
+    ) -> WriteRule {
+        WriteRule {
+            expectations,
+            write_tape,
+            self_write_value,
+            read_tape_write_values,
+        }
+    }
+    // whether both rules write the same states to the same tapes
+    pub fn same_effect(&self, other: &WriteRule) -> bool {
+        self.write_tape == other.write_tape
+            && self.self_write_value == other.self_write_value
+            && self.read_tape_write_values == other.read_tape_write_values
+    }
+    // whether the cells around the written cell meet every expectation
+    pub fn matches(&self, read: &impl Fn(&TapeKey, &Direction) -> u32) -> bool {
+        self.expectations.cell_expectations.values().all(|expectation| {
+            let expected_state = &expectation.expected_state;
+            read(&expected_state.tape_key, &expectation.direction)
+                == expected_state.tape_cell_state
+        })
+    }
+}
+/*
+Shrinks a list of rules (in the order that they get matched in) without
+changing which writes happen for any cells, Quine-McCluskey style:
+rules that an earlier rule for the same tape always matches before are
+dropped, and rules with the same effect whose expectations only differ
+in one cell, between them covering every state in the alphabet of that
+cell's tape, merge into a single rule that doesn't expect that cell
+*/
+pub fn minimize_rules(
+    mut rules: Vec<WriteRule>, alphabets: &HashMap<TapeKey, Vec<u32>>
+) -> Vec<WriteRule> {
+    loop {
+        rules = remove_shadowed_rules(rules);
+        let (members, merged_rule) = match find_rule_merge(&rules, alphabets) {
+            Some(merge) => merge,
+            None => return rules
+        };
+        // the merged rule takes the place of the first rule it replaces
+        rules[members[0]] = merged_rule;
+        for index in members[1..].iter().rev() {
+            rules.remove(*index);
+        }
+    }
+}
+fn remove_shadowed_rules(rules: Vec<WriteRule>) -> Vec<WriteRule> {
+    let mut kept_rules: Vec<WriteRule> = Vec::new();
+    for rule in rules {
+        let shadowed = kept_rules.iter().any(|kept_rule| {
+            kept_rule.write_tape == rule.write_tape
+                && kept_rule.expectations.covers(&rule.expectations)
+        });
+        if !shadowed {
+            kept_rules.push(rule);
+        }
+    }
+    kept_rules
+}
+// indexes of the rules to merge (in order) and the rule they merge into
+fn find_rule_merge(
+    rules: &[WriteRule], alphabets: &HashMap<TapeKey, Vec<u32>>
+) -> Option<(Vec<usize>, WriteRule)> {
+    for rule in rules.iter() {
+        for expectation in rule.expectations.sorted_expectations() {
+            let identifier = expectation.to_identifier();
+            let merged_expectations = rule.expectations.without(&identifier);
+            let members: Vec<usize> = (0..rules.len()).filter(|index| {
+                let other = &rules[*index];
+                other.same_effect(rule)
+                    && other.expectations.cell_expectations.contains_key(&identifier)
+                    && other.expectations.without(&identifier) == merged_expectations
+            }).collect();
+
+            let expected_states: HashSet<u32> = members.iter().map(|index| {
+                rules[*index].expectations.cell_expectations[&identifier]
+                    .expected_state.tape_cell_state
+            }).collect();
+            let alphabet: HashSet<u32> = alphabets[&identifier.tape_key].iter().copied().collect();
+            if expected_states != alphabet {
+                continue;
+            }
+            // rules in between with other effects would lose cells to the merged rule
+            let (first, last) = (members[0], members[members.len() - 1]);
+            let overtakes = (first..=last).any(|index| {
+                let other = &rules[index];
+                other.write_tape == rule.write_tape
+                    && !other.same_effect(rule)
+                    && other.expectations.overlaps(&merged_expectations)
+            });
+            if overtakes {
+                continue;
+            }
+
+            let mut merged_rule = rule.clone();
+            merged_rule.expectations = merged_expectations;
+            return Some((members, merged_rule));
+        }
+    }
+    None
+}
+
 impl Hash for CellExpectationCombo {
     fn hash<H: std::hash::Hasher>(&self, state: &mut H) {
         let mut expectations_vec: Vec<&CellExpectation> =
@@ -178,6 +368,96 @@ impl Tape {
             rev_data: vec![],
         }
     }
+    // states that rules may expect or write to this tape, besides void
+    pub fn with_allowed_states(mut self, allowed_states: impl IntoIterator<Item = u32>) -> Tape {
+        self.allowed_states = allowed_states.into_iter().collect();
+        self
+    }
+    // no allowed states means that any state is allowed
+    pub fn allows_state(&self, state: u32) -> bool {
+        state == VOID_STATE
+            || self.allowed_states.is_empty()
+            || self.allowed_states.contains(&state)
+    }
+    pub fn tape_key(&self) -> TapeKey {
+        if self.self_writeable {
+            TapeKey::Writable(WritableTapeKey { tape_index: self.tape_index })
+        } else {
+            TapeKey::Readable(ReadableTapeKey { tape_index: self.tape_index })
+        }
+    }
+    pub fn read(&self, position: i64) -> u32 {
+        let cell = if position >= 0 {
+            self.data.get(position as usize)
+        } else {
+            self.rev_data.get((-position - 1) as usize)
+        };
+        *cell.unwrap_or(&VOID_STATE)
+    }
+    pub fn write(&mut self, position: i64, state: u32) {
+        let (cells, index) = if position >= 0 {
+            (&mut self.data, position as usize)
+        } else {
+            (&mut self.rev_data, (-position - 1) as usize)
+        };
+        if index >= cells.len() {
+            if state == VOID_STATE {
+                return;
+            }
+            cells.resize(index + 1, VOID_STATE);
+        }
+        cells[index] = state;
+        // only the cells up to the outermost non void one are stored
+        while cells.last() == Some(&VOID_STATE) {
+            cells.pop();
+        }
+    }
+    fn next_state(&self, position: i64) -> u32 {
+        let tape_key = self.tape_key();
+        let read = |key: &TapeKey, direction: &Direction| {
+            assert_eq!(
+                key, &tape_key,
+                "Rule expects cells of another tape, step it as part of a MultiTape"
+            );
+            self.read(position + direction.offset())
+        };
+        match self.write_rules.iter().find(|rule| rule.matches(&read)) {
+            Some(rule) => {
+                assert!(
+                    rule.read_tape_write_values.is_empty(),
+                    "Rule writes to other tapes, step it as part of a MultiTape"
+                );
+                rule.self_write_value.tape_cell_state
+            }
+            None => self.read(position)
+        }
+    }
+    /*
+    Applies the write rules of the tape to all of its cells at once,
+    for rules that only involve this tape (see MultiTape::step otherwise).
+    The tape grows by up to a cell in either direction each step
+    */
+    pub fn step(&mut self) {
+        let (start, end) = self.extent();
+        let next_cells: Vec<(i64, u32)> = (start - 1..end + 1)
+            .map(|position| (position, self.next_state(position)))
+            .collect();
+        for (position, state) in next_cells {
+            self.write(position, state);
+        }
+    }
+    // positions and states of every cell that isn't void
+    pub fn cells(&self) -> HashMap<i64, u32> {
+        let (start, end) = self.extent();
+        (start..end)
+            .map(|position| (position, self.read(position)))
+            .filter(|(_, state)| *state != VOID_STATE)
+            .collect()
+    }
+    // range of positions that are stored, every other cell is void
+    fn extent(&self) -> (i64, i64) {
+        (-(self.rev_data.len() as i64), self.data.len() as i64)
+    }
     pub fn build_cell_expectation(
         &self, tape_cell_state: u32, direction: Direction
     ) -> CellExpectation {
@@ -278,4 +558,705 @@ impl MultiTape {
     pub fn generate_tape_equations(&self) {
         todo!()
     }
+    pub fn add_rule(&mut self, rule: WriteRule) {
+        self.rules.push(rule);
+    }
+    /*
+    Minimizes the rules of every tape together (see minimize_rules),
+    keeping them all in the rules of the multi tape afterwards
+    */
+    pub fn minimize_rules(&mut self) {
+        let alphabets: HashMap<TapeKey, Vec<u32>> = self.tape_keys().into_iter()
+            .map(|tape_key| {
+                let alphabet = self.alphabet(&tape_key);
+                (tape_key, alphabet)
+            })
+            .collect();
+        let rules: Vec<WriteRule> = self.all_rules().cloned().collect();
+        for tape in self.write_tapes.iter_mut() {
+            tape.write_rules.clear();
+        }
+        self.rules = minimize_rules(rules, &alphabets);
+    }
+    /*
+    Declares a rule for the named write tape, e.g.
+    multi_tape.tape("carry").when(&[(Direction::Left, 1), (Direction::Middle, 0)]).writes(1)
+    */
+    pub fn tape(&mut self, tape_name: &str) -> RuleBuilder<'_> {
+        RuleBuilder {
+            multi_tape: self,
+            tape_name: tape_name.to_string(),
+            expectations: vec![],
+            read_tape_writes: vec![],
+        }
+    }
+    /*
+    Unary expansion: each cell of the named write tape becomes function
+    of the input tape cell under it, for every non void input state
+    */
+    pub fn map_input(
+        &mut self, tape_name: &str, input_name: &str, function: impl Fn(u32) -> u32
+    ) -> Result<(), RuleBuildError> {
+        let input_key = self.named_tape_key(input_name)?;
+        for input in self.alphabet(&input_key) {
+            if input == VOID_STATE {
+                continue;
+            }
+            self.tape(tape_name)
+                .when_on(input_name, &[(Direction::Middle, input)])
+                .writes(function(input))?;
+        }
+        Ok(())
+    }
+    /*
+    Reduct input: each cell of the named write tape becomes function of
+    the cell to its left and the non void input tape cell under it, so
+    the tape settles into a running reduction (scan) of each run of input
+    cells from the left. The states that function returns must be finite,
+    as the rules cover every accumulator state it can reach
+    */
+    pub fn reduce_input(
+        &mut self, tape_name: &str, input_name: &str, function: impl Fn(u32, u32) -> u32
+    ) -> Result<(), RuleBuildError> {
+        let tape_key = self.named_tape_key(tape_name)?;
+        let input_key = self.named_tape_key(input_name)?;
+        let inputs = self.alphabet(&input_key);
+        let mut accumulators = self.alphabet(&tape_key);
+
+        let mut index = 0;
+        while index < accumulators.len() {
+            let accumulator = accumulators[index];
+            index += 1;
+            for &input in inputs.iter() {
+                if input == VOID_STATE {
+                    continue;
+                }
+                let state = function(accumulator, input);
+                self.tape(tape_name)
+                    .when(&[(Direction::Left, accumulator)])
+                    .when_on(input_name, &[(Direction::Middle, input)])
+                    .writes(state)?;
+                if !accumulators.contains(&state) {
+                    accumulators.push(state);
+                }
+            }
+        }
+        Ok(())
+    }
+    fn named_tape_key(&self, tape_name: &str) -> Result<TapeKey, RuleBuildError> {
+        self.get_tape_key(tape_name).cloned()
+            .ok_or_else(|| RuleBuildError::UnknownTape(tape_name.to_string()))
+    }
+    // keys of the read tapes followed by the write tapes
+    pub fn tape_keys(&self) -> Vec<TapeKey> {
+        self.read_tapes.iter().chain(self.write_tapes.iter())
+            .map(|tape| tape.tape_key())
+            .collect()
+    }
+    pub fn get_tape(&self, tape_key: &TapeKey) -> &Tape {
+        match tape_key {
+            TapeKey::Readable(key) => &self.read_tapes[key.tape_index],
+            TapeKey::Writable(key) => &self.write_tapes[key.tape_index],
+        }
+    }
+    fn key_index(&self, tape_key: &TapeKey) -> usize {
+        match tape_key {
+            TapeKey::Readable(key) => key.tape_index,
+            TapeKey::Writable(key) => self.read_tapes.len() + key.tape_index,
+        }
+    }
+    // rules of the write tapes, then the ones added with add_rule
+    fn all_rules(&self) -> impl Iterator<Item = &WriteRule> {
+        self.write_tapes.iter()
+            .flat_map(|tape| tape.write_rules.iter())
+            .chain(self.rules.iter())
+    }
+    /*
+    States that cells of the tape can be in: void, the allowed states,
+    the initial cells, and every state that rules expect or write to it.
+    Cells only ever get states from rules, so this covers every state
+    the tape can reach. Sorted, so void (0) is always first
+    */
+    pub fn alphabet(&self, tape_key: &TapeKey) -> Vec<u32> {
+        let tape = self.get_tape(tape_key);
+        let mut states: HashSet<u32> = tape.allowed_states.clone();
+        states.insert(VOID_STATE);
+        states.extend(tape.data.iter().chain(tape.rev_data.iter()));
+        for rule in self.all_rules() {
+            for expectation in rule.expectations.cell_expectations.values() {
+                if &expectation.expected_state.tape_key == tape_key {
+                    states.insert(expectation.expected_state.tape_cell_state);
+                }
+            }
+            if &TapeKey::Writable(rule.write_tape.clone()) == tape_key {
+                states.insert(rule.self_write_value.tape_cell_state);
+            }
+            for (read_tape, state) in rule.read_tape_write_values.iter() {
+                if &TapeKey::Readable(read_tape.clone()) == tape_key {
+                    states.insert(state.tape_cell_state);
+                }
+            }
+        }
+        let mut alphabet: Vec<u32> = states.into_iter().collect();
+        alphabet.sort();
+        alphabet
+    }
+
+    /*
+    Next state of every tape (in the order of tape_keys) at one position,
+    given the states of the cells around it. Each write tape takes the
+    write of its first matching rule, and keeps its state otherwise.
+    Read tapes keep their state unless a matching rule writes to them
+    */
+    fn next_states(
+        &self, read: impl Fn(&TapeKey, &Direction) -> u32
+    ) -> Result<Vec<u32>, CompositionError> {
+        let mut states: Vec<u32> = self.tape_keys().iter()
+            .map(|tape_key| read(tape_key, &Direction::Middle))
+            .collect();
+        let mut read_tape_writes: HashMap<ReadableTapeKey, u32> = HashMap::new();
+
+        for tape in self.write_tapes.iter() {
+            let write_tape = WritableTapeKey { tape_index: tape.tape_index };
+            let rule = self.all_rules()
+                .find(|rule| rule.write_tape == write_tape && rule.matches(&read));
+            let rule = match rule {
+                Some(rule) => rule,
+                None => continue
+            };
+
+            let tape_key = TapeKey::Writable(write_tape);
+            states[self.key_index(&tape_key)] = rule.self_write_value.tape_cell_state;
+            for (read_tape, state) in rule.read_tape_write_values.iter() {
+                let state = state.tape_cell_state;
+                let prev_state = read_tape_writes.insert(read_tape.clone(), state);
+                if let Some(prev_state) = prev_state.filter(|prev| *prev != state) {
+                    return Err(CompositionError::ConflictingWrites {
+                        tape_key: TapeKey::Readable(read_tape.clone()),
+                        states: (prev_state, state)
+                    });
+                }
+                states[self.key_index(&TapeKey::Readable(read_tape.clone()))] = state;
+            }
+        }
+        Ok(states)
+    }
+    /*
+    Applies the rules to every cell of every tape at once. Cells that only
+    see void cells are assumed to stay void, like automata::simulator does
+    */
+    pub fn step(&mut self) -> Result<(), CompositionError> {
+        let tape_keys = self.tape_keys();
+        let extents: Vec<(i64, i64)> = self.read_tapes.iter().chain(self.write_tapes.iter())
+            .map(|tape| tape.extent())
+            .collect();
+        let start = extents.iter().map(|extent| extent.0).min().unwrap_or(0) - 1;
+        let end = extents.iter().map(|extent| extent.1).max().unwrap_or(0) + 1;
+
+        let mut next_cells = Vec::new();
+        for position in start..end {
+            let next_states = self.next_states(|tape_key, direction| {
+                self.get_tape(tape_key).read(position + direction.offset())
+            })?;
+            next_cells.push((position, next_states));
+        }
+        for (position, next_states) in next_cells {
+            for (tape_key, state) in tape_keys.iter().zip(next_states) {
+                let tape = match tape_key {
+                    TapeKey::Readable(key) => &mut self.read_tapes[key.tape_index],
+                    TapeKey::Writable(key) => &mut self.write_tapes[key.tape_index],
+                };
+                tape.write(position, state);
+            }
+        }
+        Ok(())
+    }
+
+    /*
+    Composes the tapes into a single tape automaton, whose cells hold the
+    states of every tape at that position. Stepping the composed automaton
+    (e.g. with automata::simulator) is equivalent to calling step here
+    */
+    pub fn compose(&self) -> Result<ComposedTape, CompositionError> {
+        let tape_keys = self.tape_keys();
+        let alphabets: Vec<Vec<u32>> = tape_keys.iter()
+            .map(|tape_key| self.alphabet(tape_key))
+            .collect();
+        let num_states: usize = alphabets.iter().map(|alphabet| alphabet.len()).product();
+        if num_states > u8::MAX as usize {
+            return Err(CompositionError::TooManyStates(num_states));
+        }
+
+        let mut composed = ComposedTape {
+            tape_keys, alphabets, expansion_mapping: HashMap::new()
+        };
+        let num_states = num_states as u8;
+        let mut next_composed_states = Vec::new();
+        for left in 0..num_states {
+            for center in 0..num_states {
+                for right in 0..num_states {
+                    let neighbourhood = [
+                        composed.decode(left), composed.decode(center), composed.decode(right)
+                    ];
+                    let next_states = self.next_states(|tape_key, direction| {
+                        let cells = &neighbourhood[(direction.offset() + 1) as usize];
+                        cells[self.key_index(tape_key)]
+                    })?;
+                    let next_state = composed.encode(&next_states)
+                        .expect("Alphabets cover every state that rules write");
+                    next_composed_states.push(next_state);
+                }
+            }
+        }
+
+        let size = num_states as usize;
+        composed.expansion_mapping = Expression::from_neighbourhood_rule(
+            num_states, |left, center, right| {
+                let (left, center, right) = (left as usize, center as usize, right as usize);
+                next_composed_states[(left * size + center) * size + right]
+            }
+        );
+        Ok(composed)
+    }
+}
+
+/*
+Single tape automaton from MultiTape::compose. Each of its states stands
+for a combination of the states of every tape, numbered with the first
+tape as the lowest digit (so state 0 is every tape being void)
+*/
+#[derive(Debug, Clone)]
+pub struct ComposedTape {
+    tape_keys: Vec<TapeKey>,
+    // alphabet of each tape, in the order of tape_keys
+    alphabets: Vec<Vec<u32>>,
+    expansion_mapping: HashMap<u8, Expression>,
+}
+impl ComposedTape {
+    pub fn num_states(&self) -> usize {
+        self.alphabets.iter().map(|alphabet| alphabet.len()).product()
+    }
+    pub fn expansion_mapping(&self) -> &HashMap<u8, Expression> {
+        &self.expansion_mapping
+    }
+    // composed state of the cells of every tape, if they're all in its alphabet
+    pub fn encode(&self, cell_states: &[u32]) -> Option<u8> {
+        let mut state = 0;
+        for (alphabet, cell_state) in self.alphabets.iter().zip(cell_states).rev() {
+            let digit = alphabet.iter().position(|allowed| allowed == cell_state)?;
+            state = state * alphabet.len() + digit;
+        }
+        u8::try_from(state).ok()
+    }
+    pub fn decode(&self, state: u8) -> Vec<u32> {
+        let mut state = state as usize;
+        self.alphabets.iter().map(|alphabet| {
+            let cell_state = alphabet[state % alphabet.len()];
+            state /= alphabet.len();
+            cell_state
+        }).collect()
+    }
+    // composed tape of the cells that aren't void on any of the tapes
+    pub fn encode_tapes(&self, multi_tape: &MultiTape) -> HashMap<i64, u8> {
+        let tapes: Vec<&Tape> = self.tape_keys.iter()
+            .map(|tape_key| multi_tape.get_tape(tape_key))
+            .collect();
+        let start = tapes.iter().map(|tape| tape.extent().0).min().unwrap_or(0);
+        let end = tapes.iter().map(|tape| tape.extent().1).max().unwrap_or(0);
+        (start..end).filter_map(|position| {
+            let cell_states: Vec<u32> = tapes.iter()
+                .map(|tape| tape.read(position))
+                .collect();
+            let state = self.encode(&cell_states).expect("Tape cell outside of its alphabet");
+            (state != 0).then_some((position, state))
+        }).collect()
+    }
+    // cells of one of the tapes that aren't void, like Tape::cells
+    pub fn decode_tape(&self, tape: &HashMap<i64, u8>, tape_key: &TapeKey) -> HashMap<i64, u32> {
+        let key_index = self.tape_keys.iter().position(|key| key == tape_key)
+            .expect("Tape isn't part of the composition");
+        tape.iter()
+            .map(|(position, state)| (*position, self.decode(*state)[key_index]))
+            .filter(|(_, state)| *state != VOID_STATE)
+            .collect()
+    }
+}
+
+/*
+Accumulates the expectations and writes of a rule for a named tape
+(see MultiTape::tape), adding it to the multi tape once writes is called.
+Tape names and states are only checked against the allowed states of
+each tape then, so when can be chained without handling errors
+*/
+pub struct RuleBuilder<'a> {
+    multi_tape: &'a mut MultiTape,
+    tape_name: String,
+    expectations: Vec<(String, Direction, u32)>,
+    read_tape_writes: Vec<(String, u32)>,
+}
+impl RuleBuilder<'_> {
+    // expects the cells of the tape that the rule writes
+    pub fn when(self, cells: &[(Direction, u32)]) -> Self {
+        let tape_name = self.tape_name.clone();
+        self.when_on(&tape_name, cells)
+    }
+    pub fn when_on(mut self, tape_name: &str, cells: &[(Direction, u32)]) -> Self {
+        for (direction, state) in cells {
+            self.expectations.push((tape_name.to_string(), direction.clone(), *state));
+        }
+        self
+    }
+    // writes to the cell of a read tape under the written cell
+    pub fn also_writes(mut self, tape_name: &str, state: u32) -> Self {
+        self.read_tape_writes.push((tape_name.to_string(), state));
+        self
+    }
+    pub fn writes(self, state: u32) -> Result<(), RuleBuildError> {
+        let multi_tape = self.multi_tape;
+        let checked_state = |tape_name: &str, state: u32| {
+            let tape_key = multi_tape.named_tape_key(tape_name)?;
+            match multi_tape.get_tape(&tape_key).allows_state(state) {
+                true => Ok(TapeState::new(tape_key, state)),
+                false => Err(RuleBuildError::StateNotAllowed {
+                    tape_name: tape_name.to_string(), state
+                })
+            }
+        };
+
+        let self_write_value = checked_state(&self.tape_name, state)?;
+        let write_tape = match &self_write_value.tape_key {
+            TapeKey::Writable(key) => key.clone(),
+            TapeKey::Readable(_) => return Err(RuleBuildError::WrongTapeKind(self.tape_name))
+        };
+        let mut expectations = CellExpectationCombo::new_empty();
+        for (tape_name, direction, state) in self.expectations {
+            let expectation = CellExpectation::new(
+                direction.clone(), checked_state(&tape_name, state)?
+            );
+            if expectations.cell_expectations.contains_key(&expectation.to_identifier()) {
+                return Err(RuleBuildError::DuplicateExpectation { tape_name, direction });
+            }
+            expectations.insert_expectation(expectation);
+        }
+        let mut read_tape_write_values = Vec::new();
+        for (tape_name, state) in self.read_tape_writes {
+            let tape_state = checked_state(&tape_name, state)?;
+            match &tape_state.tape_key {
+                TapeKey::Readable(key) => read_tape_write_values.push((key.clone(), tape_state)),
+                TapeKey::Writable(_) => return Err(RuleBuildError::WrongTapeKind(tape_name))
+            }
+        }
+
+        multi_tape.add_rule(WriteRule::new(
+            expectations, write_tape, self_write_value, read_tape_write_values
+        ));
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::automata::simulator::Simulator;
+
+    fn expect(tape_key: &TapeKey, direction: Direction, state: u32) -> CellExpectation {
+        CellExpectation::new(direction, TapeState::new(tape_key.clone(), state))
+    }
+    fn rule(
+        expectations: Vec<CellExpectation>, write_tape: &TapeKey, state: u32,
+        read_tape_writes: Vec<(&TapeKey, u32)>
+    ) -> WriteRule {
+        let mut combo = CellExpectationCombo::new_empty();
+        for expectation in expectations {
+            combo.insert_expectation(expectation);
+        }
+        let write_tape = match write_tape {
+            TapeKey::Writable(key) => key.clone(),
+            TapeKey::Readable(_) => panic!("Rules can only be for write tapes")
+        };
+        let read_tape_writes = read_tape_writes.into_iter().map(|(tape_key, state)| {
+            match tape_key {
+                TapeKey::Readable(key) => (key.clone(), TapeState::new(tape_key.clone(), state)),
+                TapeKey::Writable(_) => panic!("Rules can only write to read tapes")
+            }
+        }).collect();
+        let self_write_value = TapeState::new(TapeKey::Writable(write_tape.clone()), state);
+        WriteRule::new(combo, write_tape, self_write_value, read_tape_writes)
+    }
+
+    /*
+    A signal on the write tape that moves right while the read tape has
+    fuel (state 1) under it, using it up (state 2) and leaving a trail (2)
+    */
+    fn signal_multi_tape() -> (MultiTape, TapeKey, TapeKey) {
+        let mut multi_tape = MultiTape::new(vec![], vec![]);
+        let fuel = multi_tape.insert_named_tape(
+            "fuel".to_string(), Tape::new(false, vec![], 0, vec![1, 1, 1, 0, 1])
+        );
+        let signal = multi_tape.insert_named_tape(
+            "signal".to_string(), Tape::new(true, vec![], 0, vec![1])
+        );
+        multi_tape.add_rule(rule(
+            vec![expect(&signal, Direction::Left, 1), expect(&fuel, Direction::Middle, 1)],
+            &signal, 1, vec![(&fuel, 2)]
+        ));
+        multi_tape.add_rule(rule(vec![expect(&signal, Direction::Middle, 1)], &signal, 2, vec![]));
+        (multi_tape, fuel, signal)
+    }
+
+    #[test]
+    fn test_compose_signal() {
+        let (mut multi_tape, fuel, signal) = signal_multi_tape();
+        assert_eq!(multi_tape.get_tape_key("fuel"), Some(&fuel));
+        assert_eq!(multi_tape.alphabet(&fuel), [0, 1, 2]);
+        assert_eq!(multi_tape.alphabet(&signal), [0, 1, 2]);
+
+        let composed = multi_tape.compose().unwrap();
+        assert_eq!(composed.num_states(), 9);
+        assert_eq!(composed.decode(composed.encode(&[2, 1]).unwrap()), [2, 1]);
+        assert_eq!(composed.encode(&[3, 1]), None);
+
+        let mut simulator = Simulator::new(
+            composed.encode_tapes(&multi_tape), composed.expansion_mapping(), 0
+        );
+        for _ in 0..5 {
+            multi_tape.step().unwrap();
+            simulator.step();
+            for tape_key in [&fuel, &signal] {
+                assert_eq!(
+                    composed.decode_tape(simulator.tape(), tape_key),
+                    multi_tape.get_tape(tape_key).cells()
+                );
+            }
+        }
+        // the signal runs out of fuel at the gap
+        assert_eq!(multi_tape.get_tape(&signal).cells(), HashMap::from([(0, 2), (1, 2), (2, 2)]));
+        assert_eq!(multi_tape.get_tape(&fuel).cells(), HashMap::from([
+            (0, 1), (1, 2), (2, 2), (4, 1)
+        ]));
+    }
+
+    #[test]
+    fn test_conflicting_writes() {
+        let (mut multi_tape, fuel, signal) = signal_multi_tape();
+        let echo = multi_tape.insert_named_tape(
+            "echo".to_string(), Tape::new(true, vec![], 1, vec![])
+        );
+        // fights the signal over what the fuel it passes becomes
+        multi_tape.add_rule(rule(
+            vec![expect(&signal, Direction::Left, 1)], &echo, 1, vec![(&fuel, 0)]
+        ));
+        let conflict = CompositionError::ConflictingWrites {
+            tape_key: fuel.clone(), states: (2, 0)
+        };
+        assert_eq!(multi_tape.compose().unwrap_err(), conflict);
+        assert_eq!(multi_tape.step(), Err(conflict));
+    }
+
+    #[test]
+    fn test_rule_builder() {
+        let mut multi_tape = MultiTape::new(vec![], vec![]);
+        multi_tape.insert_named_tape(
+            "bits".to_string(), Tape::new(false, vec![], 0, vec![1, 1, 0, 1])
+                .with_allowed_states([1])
+        );
+        multi_tape.insert_named_tape(
+            "carry".to_string(), Tape::new(true, vec![], 0, vec![1])
+                .with_allowed_states([1, 2])
+        );
+        // a carry moves right through set bits, clearing them
+        multi_tape.tape("carry")
+            .when(&[(Direction::Left, 1)])
+            .when_on("bits", &[(Direction::Middle, 1)])
+            .also_writes("bits", 0)
+            .writes(1).unwrap();
+        multi_tape.tape("carry").when(&[(Direction::Middle, 1)]).writes(2).unwrap();
+        for _ in 0..4 {
+            multi_tape.step().unwrap();
+        }
+        let bits = multi_tape.get_tape_key("bits").unwrap().clone();
+        let carry = multi_tape.get_tape_key("carry").unwrap().clone();
+        assert_eq!(multi_tape.get_tape(&bits).cells(), HashMap::from([(0, 1), (3, 1)]));
+        assert_eq!(multi_tape.get_tape(&carry).cells(), HashMap::from([(0, 2), (1, 2)]));
+
+        assert_eq!(
+            multi_tape.tape("borrow").writes(1),
+            Err(RuleBuildError::UnknownTape("borrow".to_string()))
+        );
+        assert_eq!(
+            multi_tape.tape("carry").when_on("bits", &[(Direction::Left, 2)]).writes(1),
+            Err(RuleBuildError::StateNotAllowed { tape_name: "bits".to_string(), state: 2 })
+        );
+        assert_eq!(
+            multi_tape.tape("bits").writes(1),
+            Err(RuleBuildError::WrongTapeKind("bits".to_string()))
+        );
+        assert_eq!(
+            multi_tape.tape("carry").also_writes("carry", 1).writes(1),
+            Err(RuleBuildError::WrongTapeKind("carry".to_string()))
+        );
+        assert_eq!(
+            multi_tape.tape("carry")
+                .when(&[(Direction::Right, 1), (Direction::Right, 2)])
+                .writes(1),
+            Err(RuleBuildError::DuplicateExpectation {
+                tape_name: "carry".to_string(), direction: Direction::Right
+            })
+        );
+        assert_eq!(multi_tape.rules.len(), 2);
+    }
+
+    #[test]
+    fn test_map_and_reduce_input() {
+        let mut multi_tape = MultiTape::new(vec![], vec![]);
+        let input = multi_tape.insert_named_tape(
+            "input".to_string(), Tape::new(false, vec![], 0, vec![1, 2, 0, 2, 1])
+        );
+        let shifted = multi_tape.insert_named_tape(
+            "shifted".to_string(), Tape::new(true, vec![], 0, vec![])
+        );
+        let parity = multi_tape.insert_named_tape(
+            "parity".to_string(), Tape::new(true, vec![], 1, vec![])
+        );
+        multi_tape.map_input("shifted", "input", |state| state + 10).unwrap();
+        // parity of the sum of the input so far, as 1 for even and 2 for odd
+        multi_tape.reduce_input("parity", "input", |accumulator, state| {
+            (accumulator.saturating_sub(1) + state) % 2 + 1
+        }).unwrap();
+        assert_eq!(multi_tape.alphabet(&parity), [0, 1, 2]);
+
+        let composed = multi_tape.compose().unwrap();
+        let mut simulator = Simulator::new(
+            composed.encode_tapes(&multi_tape), composed.expansion_mapping(), 0
+        );
+        for _ in 0..5 {
+            multi_tape.step().unwrap();
+            simulator.step();
+        }
+        for tape_key in [&input, &shifted, &parity] {
+            assert_eq!(
+                composed.decode_tape(simulator.tape(), tape_key),
+                multi_tape.get_tape(tape_key).cells()
+            );
+        }
+        assert_eq!(multi_tape.get_tape(&shifted).cells(), HashMap::from([
+            (0, 11), (1, 12), (3, 12), (4, 11)
+        ]));
+        assert_eq!(multi_tape.get_tape(&parity).cells(), HashMap::from([
+            (0, 2), (1, 2), (3, 1), (4, 2)
+        ]));
+    }
+
+    #[test]
+    fn test_tape_step() {
+        let mut tape = Tape::new(true, vec![], 0, vec![]);
+        tape.write(-2, 3);
+        tape.write(1, 4);
+        assert_eq!(tape.read(-2), 3);
+        assert_eq!(tape.read(-1), VOID_STATE);
+        assert_eq!(tape.read(5), VOID_STATE);
+        assert_eq!(tape.extent(), (-2, 2));
+        tape.write(-2, VOID_STATE);
+        tape.write(7, VOID_STATE);
+        assert_eq!(tape.extent(), (0, 2));
+        assert_eq!(tape.cells(), HashMap::from([(1, 4)]));
+
+        // a block of 1s that spreads in both directions, and becomes 2 inside
+        let key = tape.tape_key();
+        let mut tape = Tape::new(true, vec![
+            rule(vec![
+                expect(&key, Direction::Left, 1),
+                expect(&key, Direction::Middle, 1),
+                expect(&key, Direction::Right, 1)
+            ], &key, 2, vec![]),
+            rule(vec![expect(&key, Direction::Left, 1)], &key, 1, vec![]),
+            rule(vec![expect(&key, Direction::Right, 1)], &key, 1, vec![]),
+        ], 0, vec![1]);
+        tape.step();
+        assert_eq!(tape.cells(), HashMap::from([(-1, 1), (0, 1), (1, 1)]));
+        tape.step();
+        assert_eq!(tape.extent(), (-2, 3));
+        assert_eq!(tape.cells(), HashMap::from([
+            (-2, 1), (-1, 1), (0, 2), (1, 1), (2, 1)
+        ]));
+    }
+
+    fn two_input_multi_tape() -> MultiTape {
+        let mut multi_tape = MultiTape::new(vec![], vec![]);
+        multi_tape.insert_named_tape(
+            "a".to_string(), Tape::new(false, vec![], 0, vec![1, 0, 1, 1])
+        );
+        multi_tape.insert_named_tape(
+            "b".to_string(), Tape::new(false, vec![], 1, vec![0, 1, 1, 0])
+        );
+        multi_tape.insert_named_tape("out".to_string(), Tape::new(true, vec![], 0, vec![]));
+        multi_tape
+    }
+    // minimizing can shrink alphabets, so the original one is what both get compared over
+    fn assert_same_writes(multi_tape: &MultiTape, minimized: &MultiTape) {
+        let composed = multi_tape.compose().unwrap();
+        let num_states = composed.num_states() as u8;
+        for left in 0..num_states {
+            for center in 0..num_states {
+                for right in 0..num_states {
+                    let neighbourhood = [
+                        composed.decode(left), composed.decode(center), composed.decode(right)
+                    ];
+                    let read = |tape_key: &TapeKey, direction: &Direction| {
+                        let cells = &neighbourhood[(direction.offset() + 1) as usize];
+                        cells[multi_tape.key_index(tape_key)]
+                    };
+                    assert_eq!(multi_tape.next_states(read), minimized.next_states(read));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_minimize_rules() {
+        let mut multi_tape = two_input_multi_tape();
+        let when_ab = |multi_tape: &mut MultiTape, a: u32, b: u32, state: u32| {
+            multi_tape.tape("out")
+                .when_on("a", &[(Direction::Middle, a)])
+                .when_on("b", &[(Direction::Middle, b)])
+                .writes(state).unwrap();
+        };
+        when_ab(&mut multi_tape, 1, 0, 1);
+        when_ab(&mut multi_tape, 0, 1, 2);
+        when_ab(&mut multi_tape, 1, 1, 1);
+        // never matched, as the rule before it always matches first
+        when_ab(&mut multi_tape, 1, 1, 3);
+
+        let mut minimized = multi_tape.clone();
+        minimized.minimize_rules();
+        assert_eq!(minimized.rules.len(), 2);
+        let a = minimized.get_tape_key("a").unwrap().clone();
+        let mut merged = CellExpectationCombo::new_empty();
+        merged.insert_expectation(CellExpectation::new(
+            Direction::Middle, TapeState::new(a, 1)
+        ));
+        assert_eq!(minimized.rules[0].expectations, merged);
+        assert_same_writes(&multi_tape, &minimized);
+    }
+
+    #[test]
+    fn test_minimize_keeps_rule_order() {
+        let mut multi_tape = two_input_multi_tape();
+        multi_tape.tape("out")
+            .when_on("a", &[(Direction::Middle, 1)])
+            .when_on("b", &[(Direction::Middle, 0)])
+            .writes(1).unwrap();
+        multi_tape.tape("out").when_on("b", &[(Direction::Middle, 1)]).writes(2).unwrap();
+        multi_tape.tape("out")
+            .when_on("a", &[(Direction::Middle, 1)])
+            .when_on("b", &[(Direction::Middle, 1)])
+            .writes(1).unwrap();
+
+        // merging the first and last rule would take a=1, b=1 from the middle one
+        let mut minimized = multi_tape.clone();
+        minimized.minimize_rules();
+        assert_eq!(minimized.rules.len(), 2);
+        assert_eq!(minimized.rules[0].expectations, multi_tape.rules[0].expectations);
+        assert_same_writes(&multi_tape, &minimized);
+    }
 }
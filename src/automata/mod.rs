@@ -1,2 +1,6 @@
-mod terms;
-mod composer;
\ No newline at end of file
+pub mod terms;
+pub mod composer;
+pub mod potato_rules;
+pub mod simulator;
+mod sat;
+pub mod py_automata;
@@ -0,0 +1,278 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use crate::automata::simulator::Simulator;
+use crate::automata::terms::{Expression, Product, Term};
+use crate::potato_cpu::bit_allocation::{BitAllocation, GrowableBitAllocation};
+use crate::potato_cpu::potato_cpu::{ALUOperations, PotatoCPU, Registers};
+
+/*
+TODO: running whole compiled programs as a cellular automaton still
+  needs rules for the rest of the PotatoCPU's step function:
+    - the other ALU operations (shifts, rotation, comparison, bit
+      reversal, length and resizing)
+    - register moves and data value loads
+    - stack reads and writes, including the strided ones
+    - program counter updates for jumps, calls and returns
+*/
+
+/*
+Runs the PotatoCPU's bitwise operations and Add as a cellular automaton.
+InputA and InputB are laid out on a single tape with one cell per bit
+position (least significant bit at position 0), and each operation is
+an expansion mapping in the format of automata::terms: the expression
+for a state is satisfied by the neighbourhoods that become that state.
+Every other operation fails with AluRuleError::UnsupportedOperation.
+
+Cell states:
+- BLANK: outside of both registers
+- input cells: 1 + a + 2b for the bits a and b of InputA and InputB
+- digit cells: 5 + sum + 2carry, where carry still has to be added
+  to the cell on the right (the next bit up). Operations turn input
+  cells into digit cells, and are done once no digit has a carry left
+*/
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum AluRuleError {
+    UnsupportedOperation(ALUOperations),
+    // the tape was still changing after this many steps
+    StepLimitExceeded(usize),
+}
+impl Display for AluRuleError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AluRuleError::UnsupportedOperation(op) => {
+                write!(f, "No cellular automaton rules for {:?}", op)
+            },
+            AluRuleError::StepLimitExceeded(max_steps) => {
+                write!(f, "ALU automaton didn't settle within {} steps", max_steps)
+            },
+        }
+    }
+}
+impl std::error::Error for AluRuleError {}
+
+pub const BLANK: u8 = 0;
+const FIRST_INPUT_STATE: u8 = 1;
+const FIRST_DIGIT_STATE: u8 = 5;
+const NUM_STATES: u8 = 9;
+
+fn input_state(a: bool, b: bool) -> u8 {
+    FIRST_INPUT_STATE + a as u8 + 2 * b as u8
+}
+fn digit_state(sum: bool, carry: bool) -> u8 {
+    FIRST_DIGIT_STATE + sum as u8 + 2 * carry as u8
+}
+fn decode_input(state: u8) -> Option<(bool, bool)> {
+    match state {
+        FIRST_INPUT_STATE..FIRST_DIGIT_STATE => {
+            let value = state - FIRST_INPUT_STATE;
+            Some((value & 1 == 1, value & 2 == 2))
+        },
+        _ => None
+    }
+}
+fn decode_digit(state: u8) -> Option<(bool, bool)> {
+    match state {
+        FIRST_DIGIT_STATE..NUM_STATES => {
+            let value = state - FIRST_DIGIT_STATE;
+            Some((value & 1 == 1, value & 2 == 2))
+        },
+        _ => None
+    }
+}
+
+pub fn encode_alu_inputs(a: &GrowableBitAllocation, b: &GrowableBitAllocation) -> Vec<Term> {
+    // missing bits of the narrower input count as zeros, like the ALU
+    let width = usize::max(a.get_length(), b.get_length());
+    (0..width).map(|index| {
+        let a_bit = index < a.get_length() && a.get(index);
+        let b_bit = index < b.get_length() && b.get(index);
+        Term::new(index as i64, input_state(a_bit, b_bit), false)
+    }).collect()
+}
+pub fn encode_cpu_alu_inputs(cpu: &PotatoCPU) -> Vec<Term> {
+    encode_alu_inputs(
+        cpu.read_register(Registers::InputA), cpu.read_register(Registers::InputB)
+    )
+}
+pub fn decode_alu_result(tape: &[Term]) -> GrowableBitAllocation {
+    // the sum bits of the digit cells from position 0 onwards
+    let states: HashMap<i64, u8> = tape.iter().map(|term| (term.position, term.state)).collect();
+    let bits = (0..)
+        .map_while(|position| states.get(&position).and_then(|state| decode_digit(*state)))
+        .map(|(sum, _)| sum)
+        .collect();
+    GrowableBitAllocation::new_from(bits)
+}
+
+/*
+New state of a cell from its own state and that of the cell on its
+left (the next bit down), which is all that the supported operations
+need to look at
+*/
+fn next_state(op: &ALUOperations, left: u8, center: u8) -> Option<u8> {
+    let carry_in = decode_digit(left).is_some_and(|(_, carry)| carry);
+    match (op, decode_input(center), decode_digit(center)) {
+        (ALUOperations::BitwiseNOperation(truth_table), Some((a, b)), _) => {
+            let result = GrowableBitAllocation::new(0).translate_bool_op(a, b, *truth_table);
+            Some(digit_state(result, false))
+        },
+        (ALUOperations::BitwiseNOperation(_), None, _) => Some(center),
+        // half add, after which the carries ripple up one cell per step
+        (ALUOperations::Add, Some((a, b)), _) => Some(digit_state(a ^ b, a & b)),
+        (ALUOperations::Add, None, Some((sum, _))) => {
+            Some(digit_state(sum ^ carry_in, sum & carry_in))
+        },
+        // a carry out of the top digit grows the result by a bit
+        (ALUOperations::Add, None, None) if center == BLANK && carry_in => {
+            Some(digit_state(true, false))
+        },
+        (ALUOperations::Add, None, None) => Some(center),
+        _ => None
+    }
+}
+
+pub fn alu_expansion_mapping(
+    op: &ALUOperations
+) -> Result<HashMap<u8, Expression>, AluRuleError> {
+    let mut mapping: HashMap<u8, Expression> = (0..NUM_STATES)
+        .map(|state| (state, Expression::new(vec![])))
+        .collect();
+    for left in 0..NUM_STATES {
+        for center in 0..NUM_STATES {
+            let state = next_state(op, left, center)
+                .ok_or_else(|| AluRuleError::UnsupportedOperation(op.clone()))?;
+            let neighbourhood = Product::new(vec![
+                Term::new(-1, left, false), Term::new(0, center, false)
+            ]);
+            mapping.get_mut(&state).unwrap().products.push(neighbourhood);
+        }
+    }
+    Ok(mapping)
+}
+
+pub fn step_tape(tape: &[Term], expansion_mapping: &HashMap<u8, Expression>) -> Vec<Term> {
+    let states: HashMap<i64, u8> = tape.iter().map(|term| (term.position, term.state)).collect();
+    let mut simulator = Simulator::new(states, expansion_mapping, BLANK);
+    simulator.step();
+
+    let mut new_tape: Vec<Term> = simulator.tape().iter()
+        .map(|(position, state)| Term::new(*position, *state, false))
+        .collect();
+    new_tape.sort();
+    new_tape
+}
+
+// cell states from start up to (but not including) end
+pub fn tape_window(tape: &[Term], start: i64, end: i64) -> Vec<u8> {
+    let states: HashMap<i64, u8> = tape.iter().map(|term| (term.position, term.state)).collect();
+    (start..end).map(|position| *states.get(&position).unwrap_or(&BLANK)).collect()
+}
+
+// the tape before each step and after the last, for watching the automaton evolve
+pub fn evolve_tape(
+    tape: &[Term], expansion_mapping: &HashMap<u8, Expression>, num_steps: usize
+) -> Vec<Vec<Term>> {
+    let mut history = vec![tape.to_vec()];
+    for _ in 0..num_steps {
+        let next_tape = step_tape(history.last().unwrap(), expansion_mapping);
+        history.push(next_tape);
+    }
+    history
+}
+
+/*
+Runs an ALU operation on the cpu's inputs as a cellular automaton
+until the tape stops changing. Returns the result along with the
+number of automaton steps it took
+*/
+pub fn run_alu_op(
+    cpu: &PotatoCPU, op: &ALUOperations, max_steps: usize
+) -> Result<(GrowableBitAllocation, usize), AluRuleError> {
+    let expansion_mapping = alu_expansion_mapping(op)?;
+    let mut tape = encode_cpu_alu_inputs(cpu);
+    for step_no in 0..max_steps {
+        let new_tape = step_tape(&tape, &expansion_mapping);
+        if new_tape == tape {
+            return Ok((decode_alu_result(&tape), step_no));
+        }
+        tape = new_tape;
+    }
+    Err(AluRuleError::StepLimitExceeded(max_steps))
+}
+
+#[cfg(test)]
+mod tests {
+    use arbitrary_int::u4;
+    use super::*;
+    use crate::potato_cpu::potato_cpu::PotatoSpec;
+
+    fn cpu_with_inputs(a: GrowableBitAllocation, b: GrowableBitAllocation) -> PotatoCPU {
+        let mut cpu = PotatoCPU::new(&PotatoSpec::new(vec![], 1, 32));
+        *cpu.load_register(Registers::InputA) = a;
+        *cpu.load_register(Registers::InputB) = b;
+        cpu
+    }
+
+    #[test]
+    fn test_alu_operations_as_automaton() {
+        let inputs = [(0, 0), (1, 1), (0b1011, 0b110), (255, 1), (0xDEAD_BEEF, 0xFFFF_FFFF)];
+        let mut operations: Vec<ALUOperations> = (0..16)
+            .map(|table| ALUOperations::BitwiseNOperation(u4::new(table)))
+            .collect();
+        operations.push(ALUOperations::Add);
+
+        for op in &operations {
+            for (a, b) in inputs {
+                let a = GrowableBitAllocation::from_num(a);
+                let b = GrowableBitAllocation::from_num(b);
+                let cpu = cpu_with_inputs(a.clone(), b.clone());
+                let (result, num_steps) = run_alu_op(&cpu, op, 100).unwrap();
+
+                let expected = cpu.process_alu_op(op.clone());
+                assert_eq!(
+                    result.to_big_num(), expected.to_big_num(), "{:?} of {} and {}", op, a, b
+                );
+                // O(n) in the width of the inputs, like the modeled ca time
+                let width = usize::max(a.get_length(), b.get_length());
+                assert!(num_steps <= width + 2, "{:?} took {} steps", op, num_steps);
+            }
+        }
+    }
+
+    #[test]
+    fn test_add_carries_ripple() {
+        // 0b1111 + 1 carries through every bit into a new top bit
+        let cpu = cpu_with_inputs(
+            GrowableBitAllocation::from_num(0b1111), GrowableBitAllocation::from_num(1)
+        );
+        let mapping = alu_expansion_mapping(&ALUOperations::Add).unwrap();
+        let mut tape = encode_cpu_alu_inputs(&cpu);
+        assert_eq!(tape.len(), 4);
+        for _ in 0..6 {
+            tape = step_tape(&tape, &mapping);
+        }
+        assert_eq!(decode_alu_result(&tape), GrowableBitAllocation::from_num(0b10000));
+
+        let history = evolve_tape(&encode_cpu_alu_inputs(&cpu), &mapping, 6);
+        assert_eq!(history.len(), 7);
+        // 0b1111 and 0b0001 as input cells, then the carry moving up
+        assert_eq!(tape_window(&history[0], -1, 6), [BLANK, 4, 2, 2, 2, BLANK, BLANK]);
+        assert_eq!(tape_window(&history[1], -1, 6), [BLANK, 7, 6, 6, 6, BLANK, BLANK]);
+        assert_eq!(tape_window(&history[2], -1, 6), [BLANK, 5, 7, 6, 6, BLANK, BLANK]);
+        assert_eq!(tape_window(&history[6], -1, 6), [BLANK, 5, 5, 5, 5, 6, BLANK]);
+
+        assert_eq!(
+            alu_expansion_mapping(&ALUOperations::ShiftLeft).err(),
+            Some(AluRuleError::UnsupportedOperation(ALUOperations::ShiftLeft))
+        );
+        assert_eq!(
+            run_alu_op(&cpu, &ALUOperations::GetLength, 100),
+            Err(AluRuleError::UnsupportedOperation(ALUOperations::GetLength))
+        );
+        assert_eq!(
+            run_alu_op(&cpu, &ALUOperations::Add, 2),
+            Err(AluRuleError::StepLimitExceeded(2))
+        );
+    }
+}
@@ -0,0 +1,33 @@
+use numpy::PyArray2;
+use pyo3::{pyfunction, Bound, PyResult, Python};
+use pyo3::exceptions::PyValueError;
+use pyo3_stub_gen::derive::gen_stub_pyfunction;
+use crate::automata::potato_rules::{
+    alu_expansion_mapping, encode_alu_inputs, evolve_tape, tape_window
+};
+use crate::potato_cpu::bit_allocation::GrowableBitAllocation;
+use crate::potato_cpu::potato_asm_text::parse_potato_alu_operation;
+
+/*
+Simulates an ALU operation (written as in potato assembly, e.g. add or
+bitwise 0b0110) on a and b as a cellular automaton. Returns the cell
+states of tape positions start..end as a (num_steps + 1, end - start)
+array with one row per step, see potato_rules for what the states mean
+*/
+#[gen_stub_pyfunction(module = "py_ca_compiler.automata")]
+#[pyfunction]
+pub fn alu_tape_history<'py>(
+    py: Python<'py>, operation: String, a: u64, b: u64, num_steps: usize, start: i64, end: i64
+) -> PyResult<Bound<'py, PyArray2<u8>>> {
+    let operation = parse_potato_alu_operation(&operation)
+        .map_err(|err| PyValueError::new_err(err.to_string()))?;
+    let expansion_mapping = alu_expansion_mapping(&operation)
+        .map_err(|err| PyValueError::new_err(err.to_string()))?;
+    let tape = encode_alu_inputs(
+        &GrowableBitAllocation::from(a), &GrowableBitAllocation::from(b)
+    );
+    let rows: Vec<Vec<u8>> = evolve_tape(&tape, &expansion_mapping, num_steps).iter()
+        .map(|tape| tape_window(tape, start, end))
+        .collect();
+    PyArray2::from_vec2(py, &rows).map_err(|err| PyValueError::new_err(err.to_string()))
+}
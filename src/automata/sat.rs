@@ -0,0 +1,77 @@
+use std::collections::HashMap;
+
+/*
+DPLL search for states of tape cells that satisfy every clause, where a
+clause is a list of (position, state) literals that each say the cell at
+position is NOT in that state, and every cell takes a state from its
+domain. Clauses only ever rule states out, so unit propagation removes
+states from domains, and cells with several states left get branched on
+*/
+pub(crate) fn find_assignment(
+    domains: HashMap<i64, Vec<u8>>, clauses: &[Vec<(i64, u8)>]
+) -> Option<HashMap<i64, u8>> {
+    let mut domains = domains;
+    loop {
+        let mut propagated = false;
+        for clause in clauses.iter() {
+            // a literal holds once its state is out of the domain of the cell
+            if clause.iter().any(|(position, state)| !domains[position].contains(state)) {
+                continue;
+            }
+            // literals that can still hold, as their cells have other states left
+            let mut open = clause.iter().filter(|(position, _)| domains[position].len() > 1);
+            match (open.next(), open.next()) {
+                (None, _) => return None,
+                (Some((position, state)), None) => {
+                    domains.get_mut(position).unwrap().retain(|allowed| allowed != state);
+                    propagated = true;
+                }
+                _ => {}
+            }
+        }
+        if !propagated {
+            break;
+        }
+    }
+
+    let unsatisfied = clauses.iter().find(|clause| {
+        clause.iter().all(|(position, state)| domains[position].contains(state))
+    });
+    let position = match unsatisfied {
+        // any remaining state of each cell works
+        None => return Some(
+            domains.into_iter().map(|(position, states)| (position, states[0])).collect()
+        ),
+        Some(clause) => clause.iter()
+            .map(|(position, _)| *position)
+            .find(|position| domains[position].len() > 1)
+            .unwrap()
+    };
+    for state in domains[&position].clone() {
+        let mut branch_domains = domains.clone();
+        branch_domains.insert(position, vec![state]);
+        if let Some(assignment) = find_assignment(branch_domains, clauses) {
+            return Some(assignment);
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_find_assignment() {
+        let domains = HashMap::from([(0, vec![0, 1]), (1, vec![0, 1, 2])]);
+        // cell 0 isn't 1, and cell 1 is neither 0 nor 1 unless cell 0 is 1
+        let clauses = vec![vec![(0, 1)], vec![(1, 0), (0, 0)], vec![(1, 1), (0, 0)]];
+        let assignment = find_assignment(domains.clone(), &clauses).unwrap();
+        assert_eq!(assignment, HashMap::from([(0, 0), (1, 2)]));
+
+        let mut clauses = clauses;
+        clauses.push(vec![(1, 2), (0, 0)]);
+        assert_eq!(find_assignment(domains, &clauses), None);
+        assert_eq!(find_assignment(HashMap::new(), &[]), Some(HashMap::new()));
+    }
+}
@@ -0,0 +1,167 @@
+use std::collections::HashMap;
+use crate::automata::terms::{AbstractExpression, Expression};
+
+/*
+Runs a one dimensional cellular automaton on a concrete tape, using an
+expansion mapping in the format of automata::terms (the expression for
+a state is satisfied by the neighbourhoods that become that state).
+Only the cells that aren't blank are stored, so the tape can grow in
+either direction. A cell whose neighbourhood matches no state becomes
+blank, and when several states match the lowest one wins
+*/
+pub struct Simulator<'a> {
+    expansion_mapping: &'a HashMap<u8, Expression>,
+    // state of every cell that isn't on the tape
+    blank: u8,
+    tape: HashMap<i64, u8>,
+    generation: usize,
+    // offsets of the leftmost and rightmost cells in a neighbourhood
+    reach: (i64, i64),
+}
+impl<'a> Simulator<'a> {
+    pub fn new(
+        tape: HashMap<i64, u8>, expansion_mapping: &'a HashMap<u8, Expression>, blank: u8
+    ) -> Self {
+        let offsets: Vec<i64> = expansion_mapping.values()
+            .flat_map(|expression| expression._to_flat_terms())
+            .map(|term| term.position)
+            .collect();
+        let reach = (
+            offsets.iter().copied().min().unwrap_or(0),
+            offsets.iter().copied().max().unwrap_or(0)
+        );
+        Simulator {
+            expansion_mapping,
+            blank,
+            tape: tape.into_iter().filter(|(_, state)| *state != blank).collect(),
+            generation: 0,
+            reach,
+        }
+    }
+    // a dense tape, with cells at positions 0, 1, 2 ...
+    pub fn from_cells(
+        cells: &[u8], expansion_mapping: &'a HashMap<u8, Expression>, blank: u8
+    ) -> Self {
+        let tape = cells.iter().enumerate()
+            .map(|(position, state)| (position as i64, *state))
+            .collect();
+        Self::new(tape, expansion_mapping, blank)
+    }
+
+    pub fn tape(&self) -> &HashMap<i64, u8> {
+        &self.tape
+    }
+    pub fn generation(&self) -> usize {
+        self.generation
+    }
+    pub fn read(&self, position: i64) -> u8 {
+        *self.tape.get(&position).unwrap_or(&self.blank)
+    }
+    // cell states from start up to (but not including) end
+    pub fn window(&self, start: i64, end: i64) -> Vec<u8> {
+        (start..end).map(|position| self.read(position)).collect()
+    }
+
+    fn next_state(&self, position: i64) -> u8 {
+        // the neighbourhood relative to the cell, as the expressions are
+        let (left, right) = self.reach;
+        let neighbourhood: HashMap<i64, u8> = (left..=right)
+            .map(|offset| (offset, self.read(position + offset)))
+            .collect();
+        let mut states: Vec<&u8> = self.expansion_mapping.keys().collect();
+        states.sort();
+        states.into_iter()
+            .find(|state| self.expansion_mapping[state]._sub(&neighbourhood, self.blank))
+            .copied()
+            .unwrap_or(self.blank)
+    }
+    pub fn step(&mut self) {
+        // cells outside of this range only see blank cells, so they stay blank
+        let (left, right) = self.reach;
+        let start = self.tape.keys().min().map_or(0, |position| position - right);
+        let end = self.tape.keys().max().map_or(-1, |position| position - left);
+
+        self.tape = (start..=end)
+            .map(|position| (position, self.next_state(position)))
+            .filter(|(_, state)| *state != self.blank)
+            .collect();
+        self.generation += 1;
+    }
+    // the tape before each step and after the last
+    pub fn run(&mut self, num_steps: usize) -> Vec<HashMap<i64, u8>> {
+        let mut snapshots = vec![self.tape.clone()];
+        for _ in 0..num_steps {
+            self.step();
+            snapshots.push(self.tape.clone());
+        }
+        snapshots
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::automata::terms::Term;
+
+    #[test]
+    fn test_rule_90() {
+        // xor of both neighbours draws a sierpinski triangle from a single cell
+        let mapping = Expression::from_wolfram_rule(90);
+        let mut simulator = Simulator::from_cells(&[1], &mapping, 0);
+        let snapshots = simulator.run(3);
+        assert_eq!(snapshots.len(), 4);
+        assert_eq!(simulator.generation(), 3);
+        assert_eq!(simulator.window(-4, 5), [0, 1, 0, 1, 0, 1, 0, 1, 0]);
+
+        let second = Simulator::new(snapshots[2].clone(), &mapping, 0);
+        assert_eq!(second.window(-3, 4), [0, 1, 0, 0, 0, 1, 0]);
+        assert_eq!(snapshots[0], HashMap::from([(0, 1)]));
+    }
+
+    #[test]
+    fn test_matches_symbolic_expansion() {
+        // a cell is in a state after k steps iff the k step expansion matches the start
+        let mapping = Expression::from_wolfram_rule(30);
+        let cells = [1, 0, 1, 1, 0, 0, 1];
+        let mut simulator = Simulator::from_cells(&cells, &mapping, 0);
+        simulator.run(3);
+
+        let start: HashMap<i64, u8> = Simulator::from_cells(&cells, &mapping, 0).tape().clone();
+        for position in -4..10 {
+            let expansion = Term::new(position, 1, false)
+                .to_expression()
+                .with_contradiction_pruning()
+                ._expand_steps(&mapping, 3);
+            assert_eq!(
+                expansion._sub(&start, 0), simulator.read(position) == 1,
+                "cell {}", position
+            );
+        }
+    }
+
+    #[test]
+    fn test_three_states() {
+        // each cell becomes the sum of its neighbourhood mod 3
+        let mapping = Expression::from_neighbourhood_rule(3, |left, center, right| {
+            (left + center + right) % 3
+        });
+        let cells = [2, 0, 1];
+        let mut simulator = Simulator::from_cells(&cells, &mapping, 0);
+        simulator.step();
+        assert_eq!(simulator.window(-1, 4), [2, 2, 0, 1, 1]);
+
+        let start = Simulator::from_cells(&cells, &mapping, 0).tape().clone();
+        for position in -2..5 {
+            for state in 0..3 {
+                let expansion = Term::new(position, state, false)
+                    .to_expression()
+                    .with_contradiction_pruning()
+                    ._expand_steps(&mapping, 1);
+                assert_eq!(
+                    expansion._sub(&start, 0), simulator.read(position) == state,
+                    "cell {} state {}", position, state
+                );
+            }
+        }
+    }
+}
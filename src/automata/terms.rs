@@ -1,8 +1,14 @@
 use rayon::iter::ParallelIterator;
-use std::collections::{HashMap};
+use std::collections::{HashMap, HashSet};
+use std::cmp::Ordering;
+use std::fmt::{Display, Formatter};
 use std::hash::{Hash, Hasher};
 use std::ops::{BitOr, Mul};
+use std::sync::mpsc::{sync_channel, IntoIter};
+use std::thread;
 use rayon::iter::IntoParallelRefIterator;
+use serde::{Deserialize, Serialize};
+use crate::automata::sat::find_assignment;
 
 pub fn clip_after_space(s: String) -> String {
     s.split_whitespace().next().unwrap_or(&s).to_string()
@@ -24,13 +30,210 @@ pub trait AbstractExpression: Mul + BitOr + Eq + Sized {
     fn _assign_indexes_as_base(&mut self);
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ExpansionMappingError {
+    // state used in the expression of another state, without an expression of its own
+    MissingState { state: u8, used_by: u8 },
+}
+impl Display for ExpansionMappingError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ExpansionMappingError::MissingState { state, used_by } => write!(
+                f, "State {} is used in the expansion of state {} but has no expansion",
+                state, used_by
+            ),
+        }
+    }
+}
+impl std::error::Error for ExpansionMappingError {}
+
+/*
+Checks that every state that expanding can reach has an expansion,
+so that expanding any number of steps from a state in the mapping
+doesn't run into a state that it can't expand further
+*/
+pub fn validate_expansion_mapping(
+    expansion_mapping: &HashMap<u8, Expression>
+) -> Result<(), ExpansionMappingError> {
+    let mut states: Vec<&u8> = expansion_mapping.keys().collect();
+    states.sort();
+    for used_by in states {
+        for term in expansion_mapping[used_by]._to_flat_terms() {
+            if !expansion_mapping.contains_key(&term.state) {
+                return Err(ExpansionMappingError::MissingState {
+                    state: term.state, used_by: *used_by
+                });
+            }
+        }
+    }
+    Ok(())
+}
+fn expansion_of(expansion_mapping: &HashMap<u8, Expression>, state: u8) -> &Expression {
+    expansion_mapping.get(&state).unwrap_or_else(|| {
+        panic!("No expansion for state {} (see validate_expansion_mapping)", state)
+    })
+}
+
+/*
+Expansions of single states at position 0 by number of steps, which
+_expand_steps offsets to wherever a term is. Expanding is distributive
+(the expansion of a product is the product of the expansions of its
+terms), so a term expanded k steps is its expansion mapping with every
+term in it expanded k - 1 steps, and each (state, steps) pair only has
+to be expanded once no matter how often it comes up
+*/
+pub struct ExpansionCache<'a> {
+    expansion_mapping: &'a HashMap<u8, Expression>,
+    // prunes contradictions while multiplying, see with_contradiction_pruning
+    prune_contradictions: bool,
+    expansions: HashMap<(u8, u64), Expression>,
+}
+impl<'a> ExpansionCache<'a> {
+    pub fn new(
+        expansion_mapping: &'a HashMap<u8, Expression>, prune_contradictions: bool
+    ) -> Self {
+        ExpansionCache { expansion_mapping, prune_contradictions, expansions: HashMap::new() }
+    }
+    pub fn expand_state(&mut self, state: u8, steps: u64) -> Expression {
+        if let Some(expansion) = self.expansions.get(&(state, steps)) {
+            return expansion.clone();
+        }
+        let expansion = match steps {
+            0 => Term::new(0, state, false).to_expression(),
+            _ => {
+                let mut products = Vec::new();
+                for product in expansion_of(self.expansion_mapping, state).products.iter() {
+                    products.extend(self.expand_product(product, steps - 1).products);
+                }
+                Expression { products, _optimized: self.prune_contradictions }
+            }
+        };
+        self.expansions.insert((state, steps), expansion.clone());
+        expansion
+    }
+    pub fn expand_term(&mut self, term: &Term, steps: u64) -> Expression {
+        let mut expansion = self.expand_state(term.state, steps).offset(term.position);
+        expansion._optimized = self.prune_contradictions;
+        expansion
+    }
+    pub fn expand_product(&mut self, product: &Product, steps: u64) -> Expression {
+        let mut result: Option<Expression> = None;
+        for term in product._terms.iter() {
+            let expansion = self.expand_term(term, steps);
+            result = Some(match result {
+                Some(result) => result * expansion,
+                None => expansion
+            });
+        }
+        result.unwrap_or(Expression {
+            products: vec![], _optimized: self.prune_contradictions
+        })
+    }
+    pub fn expand(&mut self, expression: &Expression, steps: u64) -> Expression {
+        let mut products = Vec::new();
+        for product in expression.products.iter() {
+            products.extend(self.expand_product(product, steps).products);
+        }
+        let mut expanded = Expression { products, _optimized: self.prune_contradictions };
+        if self.prune_contradictions {
+            expanded.prune_contradictions();
+        }
+        expanded._assign_expr_positions();
+        expanded
+    }
+}
+
+/*
+Products of the one step expansion of an expression, in the same order
+as _expand gives them, but only worked out as they're asked for. Each
+product is expanded as a depth first search over the products of the
+expansions of its terms, so with contradiction pruning a contradictory
+combination of the first few terms skips every product that contains it
+*/
+pub struct ExpandIter<'a> {
+    products: std::slice::Iter<'a, Product>,
+    expansion_mapping: &'a HashMap<u8, Expression>,
+    prune_contradictions: bool,
+    // expansion of each term of the product being expanded
+    term_expansions: Vec<Expression>,
+    // product picked from each term expansion so far, and the number of terms before it
+    picked: Vec<(usize, usize)>,
+    terms: Vec<Term>,
+    next_choice: usize,
+    num_expanded: u64,
+}
+impl<'a> ExpandIter<'a> {
+    fn new(expression: &'a Expression, expansion_mapping: &'a HashMap<u8, Expression>) -> Self {
+        ExpandIter {
+            products: expression.products.iter(),
+            expansion_mapping,
+            prune_contradictions: expression._optimized,
+            term_expansions: vec![],
+            picked: vec![],
+            terms: vec![],
+            next_choice: 0,
+            num_expanded: 0,
+        }
+    }
+    fn backtrack(&mut self) {
+        let (choice, num_terms) = self.picked.pop().unwrap();
+        self.terms.truncate(num_terms);
+        self.next_choice = choice + 1;
+    }
+}
+impl Iterator for ExpandIter<'_> {
+    type Item = Product;
+
+    fn next(&mut self) -> Option<Product> {
+        loop {
+            if self.term_expansions.is_empty() {
+                let product = self.products.next()?;
+                self.term_expansions = product._terms.iter()
+                    .map(|term| term._expand(self.expansion_mapping))
+                    .collect();
+                self.next_choice = 0;
+                continue;
+            }
+
+            let depth = self.picked.len();
+            if depth == self.term_expansions.len() {
+                let mut product = Product::new(self.terms.clone());
+                product._assign_base_indexes(self.num_expanded);
+                self.num_expanded += 1;
+                self.backtrack();
+                return Some(product);
+            }
+            let choices = &self.term_expansions[depth].products;
+            if self.next_choice >= choices.len() {
+                match depth {
+                    0 => self.term_expansions.clear(),
+                    _ => self.backtrack()
+                }
+                continue;
+            }
+
+            self.picked.push((self.next_choice, self.terms.len()));
+            self.terms.extend(choices[self.next_choice]._terms.iter().cloned());
+            self.next_choice = 0;
+            if self.prune_contradictions && terms_have_contradiction(&self.terms) {
+                self.backtrack();
+            }
+        }
+    }
+}
+
+fn terms_have_contradiction(terms: &[Term]) -> bool {
+    let mut states: HashMap<i64, u8> = HashMap::new();
+    terms.iter().any(|term| *states.entry(term.position).or_insert(term.state) != term.state)
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub struct ExprPosition {
     pub (crate) product_idx: u64,
     pub (crate) term_idx: u64
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub struct ExprDebugInfo {
     pub (crate) expansion_index: u16,
     pub (crate) position_info: Option<ExprPosition>,
@@ -47,7 +250,14 @@ impl ExprDebugInfo {
 }
 
 
-#[derive(Clone, Debug)]
+/*
+Terms are small Copy values, so products copy them around directly
+rather than going through handles into an interning table or arena:
+the debug info is different for every occurrence of a term (it says
+where in its expression the term is), so a handle to a shared
+(position, state) entry would still need its own copy of that
+*/
+#[derive(Clone, Copy, Debug, Serialize, Deserialize)]
 pub struct Term {
     // position within the cellular automata tape
     pub (crate) position: i64,
@@ -106,7 +316,7 @@ impl Term {
     }
 
     pub fn _to_product(&self) -> Product {
-        Product::new(vec![self.copy()])
+        Product::new(vec![*self])
     }
     pub fn _get_name() -> String {
         const TERM_EXAMPLE: Term = Term {
@@ -131,6 +341,17 @@ impl PartialEq<Term> for Term {
         self.position == other.position && self.state == other.state
     }
 }
+// by position then state, which is the order of terms in a normalized product
+impl PartialOrd for Term {
+    fn partial_cmp(&self, other: &Term) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for Term {
+    fn cmp(&self, other: &Term) -> Ordering {
+        (self.position, self.state).cmp(&(other.position, other.state))
+    }
+}
 
 impl Mul for Term {
     type Output = Product;
@@ -143,11 +364,8 @@ impl Mul<Product> for Term {
     type Output = Product;
 
     fn mul(self, rhs: Product) -> Product {
-        let mut new_terms: Vec<Term> = Vec::new();
-        new_terms.push(self.clone());
-        for term in rhs._terms.iter() {
-            new_terms.push(term.copy());
-        }
+        let mut new_terms: Vec<Term> = vec![self];
+        new_terms.extend(rhs._terms);
         Product {
             _terms: new_terms,
             _optimized: self._optimized,
@@ -158,10 +376,9 @@ impl Mul<Expression> for Term {
     type Output = Expression;
 
     fn mul(self, rhs: Expression) -> Expression {
-        let mut new_products = Vec::new();
-        for product in rhs.products.iter() {
-            new_products.push(self.clone() * product.copy());
-        }
+        let new_products = rhs.products.into_iter()
+            .map(|product| self * product)
+            .collect();
         Expression::new(new_products)
     }
 }
@@ -197,7 +414,7 @@ impl BitOr<Expression> for Term {
 
 impl AbstractExpression for Term {
     fn copy(&self) -> Self {
-        self.clone()
+        *self
     }
 
     fn _sub(&self, substitutions: &HashMap<i64, u8>, default: u8) -> bool {
@@ -209,7 +426,7 @@ impl AbstractExpression for Term {
     }
 
     fn _expand(&self, expansion_mapping: &HashMap<u8, Expression>) -> Expression {
-        let mut expanded_expr = expansion_mapping[&self.state].offset(self.position);
+        let mut expanded_expr = expansion_of(expansion_mapping, self.state).offset(self.position);
         expanded_expr._assign_parent_debug_info(&self._debug_info);
         expanded_expr._assign_base_expansion_indexes();
         expanded_expr
@@ -219,7 +436,7 @@ impl AbstractExpression for Term {
         &self, expansion_mapping: &HashMap<u8, Expression>, steps: u64
     ) -> Expression {
         let mut expr = self.to_expression();
-        expr._assign_base_indexes();
+        expr._optimized = self._optimized;
         expr._expand_steps(expansion_mapping, steps)
     }
 
@@ -242,7 +459,7 @@ impl AbstractExpression for Term {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Product {
     pub (crate) _terms: Vec<Term>,
     pub (crate) _optimized: bool
@@ -253,6 +470,13 @@ impl Product {
             _terms: terms, _optimized: false,
         }
     }
+    // self * other without taking either of them, like Mul for Product does
+    pub fn concat(&self, other: &Product) -> Product {
+        let mut terms = Vec::with_capacity(self._terms.len() + other._terms.len());
+        terms.extend_from_slice(&self._terms);
+        terms.extend_from_slice(&other._terms);
+        Product { _terms: terms, _optimized: self._optimized }
+    }
     pub(crate) fn _get_term(&self, index: usize) -> Option<&Term> {
         self._terms.get(index)
     }
@@ -263,12 +487,45 @@ impl Product {
         if length >= current_length {
             let pad_length = length - self._terms.len();
             let last_term = self._terms.last().unwrap();
-            for _ in 0..pad_length { new_terms.push(last_term.clone()); }
+            for _ in 0..pad_length { new_terms.push(*last_term); }
             Some(Product::new(new_terms))
         } else {
             None
         }
     }
+    /*
+    Same product without repeated terms, or None if it has two terms
+    at the same position with different states (which can never hold)
+    */
+    pub fn simplify(&self) -> Option<Product> {
+        let mut states: HashMap<i64, u8> = HashMap::new();
+        let mut new_terms: Vec<Term> = Vec::new();
+        for term in self._terms.iter() {
+            match states.get(&term.position) {
+                Some(state) if *state == term.state => continue,
+                Some(_) => return None,
+                None => {
+                    states.insert(term.position, term.state);
+                    new_terms.push(*term);
+                }
+            }
+        }
+        Some(Product { _terms: new_terms, _optimized: self._optimized })
+    }
+    // terms sorted by position (then state), without repeated terms
+    pub fn normalize(&self) -> Product {
+        let mut terms = self._terms.clone();
+        terms.sort();
+        terms.dedup();
+        Product { _terms: terms, _optimized: self._optimized }
+    }
+    // whether two terms need different states at the same position
+    pub fn has_contradiction(&self) -> bool {
+        terms_have_contradiction(&self._terms)
+    }
+    fn _term_set(&self) -> HashSet<(i64, u8)> {
+        self._terms.iter().map(|term| (term.position, term.state)).collect()
+    }
     pub(crate) fn _assign_base_indexes(&mut self, product_idx: u64) {
         for (index, term) in self._terms.iter_mut().enumerate() {
             let expr_position = ExprPosition { product_idx, term_idx: index as u64 };
@@ -277,14 +534,15 @@ impl Product {
     }
 }
 
+// products are equal if they have the same terms, in any order
 impl PartialEq<Self> for Product {
     fn eq(&self, other: &Product) -> bool {
-        self._terms == other._terms
+        self.normalize()._terms == other.normalize()._terms
     }
 }
 impl Hash for Product {
     fn hash<H: Hasher>(&self, state: &mut H) {
-        for term in &self._terms {
+        for term in &self.normalize()._terms {
             term.hash(state);
         }
     }
@@ -292,22 +550,16 @@ impl Hash for Product {
 impl Eq for Product {}
 impl PartialEq<Product> for &Product {
     fn eq(&self, other: &Product) -> bool {
-        self._terms == other._terms
+        *self == other
     }
 }
 
 impl Mul for Product {
     type Output = Product;
 
-    fn mul(self, rhs: Self) -> Self::Output {
-        let mut new_terms: Vec<Term> = Vec::new();
-        for term in self._terms.iter() {
-            new_terms.push(term.clone());
-        }
-        for term in rhs._terms.iter() {
-            new_terms.push(term.clone());
-        }
-        Product { _terms: new_terms, _optimized: self._optimized }
+    fn mul(mut self, rhs: Self) -> Self::Output {
+        self._terms.extend(rhs._terms);
+        self
     }
 }
 impl Mul<Term> for &Product {
@@ -324,23 +576,18 @@ impl Mul<Term> for &Product {
 impl Mul<Term> for Product {
     type Output = Product;
 
-    fn mul(self, rhs: Term) -> Product {
-        let mut new_terms = self._terms.clone();
-        new_terms.push(rhs.copy());
-        Product {
-            _terms: new_terms,
-            _optimized: self._optimized,
-        }
+    fn mul(mut self, rhs: Term) -> Product {
+        self._terms.push(rhs);
+        self
     }
 }
 impl Mul<Expression> for Product {
     type Output = Expression;
 
     fn mul(self, rhs: Expression) -> Self::Output {
-        let mut new_products = Vec::new();
-        for product in rhs.products.iter() {
-            new_products.push(self.copy() * product.copy());
-        }
+        let new_products = rhs.products.iter()
+            .map(|product| self.concat(product))
+            .collect();
         Expression::new(new_products)
     }
 }
@@ -400,6 +647,10 @@ impl AbstractExpression for Product {
             Some(term) => term._expand(expansion_mapping),
             None => return Expression::new(vec![])
         };
+        // multiplying optimized expressions prunes contradictions as it goes
+        if self._optimized {
+            result = result.with_contradiction_pruning();
+        }
         for term in self._terms.iter().skip(1) {
             result = result * term._expand(expansion_mapping);
         }
@@ -407,13 +658,9 @@ impl AbstractExpression for Product {
         result
     }
     fn _expand_steps(&self, expansion_mapping: &HashMap<u8, Expression>, steps: u64) -> Expression {
-        let mut copy = self.copy();
-        copy._assign_indexes_as_base();
-        let mut result = copy._expand(expansion_mapping);
-        for _ in 1..steps {
-            result = result._expand(expansion_mapping);
-        }
-        result
+        let mut expr = self.to_expression();
+        expr._optimized = self._optimized;
+        expr._expand_steps(expansion_mapping, steps)
     }
     fn to_expression(&self) -> Expression {
         Expression::new(vec![self.copy()])
@@ -434,7 +681,7 @@ impl AbstractExpression for Product {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct Expression {
     pub (crate) products: Vec<Product>,
     pub (crate) _optimized: bool
@@ -448,6 +695,34 @@ impl Expression {
     pub fn _get_num_products(&self) -> usize {
         self.products.len()
     }
+    /*
+    Saved forms of the expression (including the debug info of its terms),
+    so that large expansions can be reloaded instead of recomputed.
+    The JSON is pretty printed with one field per line to keep it diffable,
+    while the binary format is the compact bincode encoding
+    */
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("Expressions always serialize")
+    }
+    pub fn from_json(json: &str) -> Result<Self, serde_json::Error> {
+        serde_json::from_str(json)
+    }
+    pub fn to_bytes(&self) -> Vec<u8> {
+        bincode::serialize(self).expect("Expressions always serialize")
+    }
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self, bincode::Error> {
+        bincode::deserialize(bytes)
+    }
+    /*
+    Evaluates the expression against many tape configurations at once
+    (e.g. every window of a tape), spreading them over the rayon pool.
+    The results are in the same order as the substitutions
+    */
+    pub fn sub_many(&self, substitutions: &[HashMap<i64, u8>], default: u8) -> Vec<bool> {
+        substitutions.par_iter()
+            .map(|substitution| self._sub(substitution, default))
+            .collect()
+    }
     pub fn _get_products(&self) -> &Vec<Product> { &self.products }
     pub fn _get_product(&self, index: usize) -> Option<&Product> {
         self.products.get(index)
@@ -472,7 +747,7 @@ impl Expression {
         for product in self.products.iter_mut() {
             product._terms.iter_mut().for_each(|term| {
                 term._debug_info.parent_position_info =
-                    parent_debug_info.position_info.clone()
+                    parent_debug_info.position_info
             });
         }
     }
@@ -493,6 +768,238 @@ impl Expression {
             product._assign_base_indexes(product_index as u64);
         }
     }
+    /*
+    Equivalent expression with fewer products, to keep repeated calls
+    to _expand from growing exponentially:
+    - repeated terms within a product are removed
+    - products with contradictory terms (same position, different state) are dropped
+    - absorption: P | P*Q = P, which also removes duplicate products
+    Products are kept in their original order
+    */
+    pub fn simplify(&self) -> Expression {
+        let products: Vec<Product> = self.products.iter()
+            .filter_map(|product| product.simplify())
+            .collect();
+        let term_sets: Vec<HashSet<(i64, u8)>> = products.iter()
+            .map(|product| product._term_set())
+            .collect();
+
+        let is_absorbed = |index: usize| {
+            // by a smaller product, or the first of a set of duplicates
+            term_sets.iter().enumerate().any(|(other_index, other_terms)| {
+                other_index != index && other_terms.is_subset(&term_sets[index]) && (
+                    other_terms.len() < term_sets[index].len() || other_index < index
+                )
+            })
+        };
+        let mut simplified = Expression {
+            products: products.iter().enumerate()
+                .filter(|(index, _)| !is_absorbed(*index))
+                .map(|(_, product)| product.clone())
+                .collect(),
+            _optimized: self._optimized
+        };
+        simplified._assign_expr_positions();
+        simplified
+    }
+    /*
+    Expansion mapping of an automaton with num_states states, where
+    rule gives the new state of a cell from the states of its left
+    neighbour, itself and its right neighbour. The expression for each
+    state has one product for every neighbourhood that becomes it
+    */
+    pub fn from_neighbourhood_rule(
+        num_states: u8, rule: impl Fn(u8, u8, u8) -> u8
+    ) -> HashMap<u8, Expression> {
+        let mut mapping: HashMap<u8, Expression> = (0..num_states)
+            .map(|state| (state, Expression::new(vec![])))
+            .collect();
+        for left in 0..num_states {
+            for center in 0..num_states {
+                for right in 0..num_states {
+                    let state = rule(left, center, right);
+                    assert!(state < num_states, "Rule gives state {} of {}", state, num_states);
+                    let product = Product::new(vec![
+                        Term::new(-1, left, false),
+                        Term::new(0, center, false),
+                        Term::new(1, right, false)
+                    ]);
+                    mapping.get_mut(&state).unwrap().products.push(product);
+                }
+            }
+        }
+        mapping
+    }
+    /*
+    Expansion mapping of an elementary cellular automaton (0 to 255,
+    e.g. rule 30 or rule 110), where bit n of the rule is the state
+    that the neighbourhood with the binary value n becomes
+    */
+    pub fn from_wolfram_rule(rule: u8) -> HashMap<u8, Expression> {
+        Self::from_neighbourhood_rule(2, |left, center, right| {
+            (rule >> (4 * left + 2 * center + right)) & 1
+        })
+    }
+    /*
+    Canonical form of the expression: every product is normalized, and
+    the products are sorted and deduplicated, so that expressions that
+    only differ in the order (or repetition) of terms and products are
+    the same. Unlike simplify, this doesn't apply absorption
+    */
+    pub fn normalize(&self) -> Expression {
+        let mut products: Vec<Product> = self.products.iter()
+            .map(|product| product.normalize())
+            .collect();
+        products.sort_by(|product, other| product._terms.cmp(&other._terms));
+        products.dedup_by(|product, other| product._terms == other._terms);
+        let mut normalized = Expression { products, _optimized: self._optimized };
+        normalized._assign_expr_positions();
+        normalized
+    }
+    /*
+    Marks the expression as optimized, so that expanding it (and the
+    expressions expanded from it) drops products with contradictory
+    terms as soon as they come up, instead of carrying them along.
+    This assumes every neighbourhood in the expansion mapping leads
+    to a single state, as it does for actual automaton rules
+    */
+    pub fn with_contradiction_pruning(mut self) -> Expression {
+        self._optimized = true;
+        self.prune_contradictions();
+        self
+    }
+    pub fn prune_contradictions(&mut self) {
+        self.products.retain(|product| !product.has_contradiction());
+    }
+    // lazy version of _expand, see ExpandIter
+    pub fn expand_iter<'a>(
+        &'a self, expansion_mapping: &'a HashMap<u8, Expression>
+    ) -> ExpandIter<'a> {
+        ExpandIter::new(self, expansion_mapping)
+    }
+    /*
+    Expands the products of the expression in parallel on the rayon pool,
+    sending the products of their expansions through a channel that holds
+    up to bound of them, so the expansion only runs as far ahead of the
+    consumer as that. Products come in no particular order, with positions
+    in their debug info only counting within the expansion of their own
+    product. Dropping the iterator stops the expansion
+    */
+    pub fn expand_par_iter(
+        &self, expansion_mapping: &HashMap<u8, Expression>, bound: usize
+    ) -> IntoIter<Product> {
+        let (sender, receiver) = sync_channel(bound);
+        let expression = self.clone();
+        let expansion_mapping = expansion_mapping.clone();
+        thread::spawn(move || {
+            expression.products.par_iter().for_each_with(sender, |sender, product| {
+                let single_product = Expression {
+                    products: vec![product.clone()], _optimized: expression._optimized
+                };
+                for expanded in single_product.expand_iter(&expansion_mapping) {
+                    if sender.send(expanded).is_err() {
+                        break;
+                    }
+                }
+            });
+        });
+        receiver.into_iter()
+    }
+    // whether some tape satisfies the expression
+    pub fn is_satisfiable(&self) -> bool {
+        self.products.iter().any(|product| !product.has_contradiction())
+    }
+    // whether the expressions are satisfied by exactly the same tapes
+    pub fn equivalent_to(&self, other: &Expression) -> bool {
+        /*
+        States that neither expression mentions all behave the same,
+        so one of them stands in for every other state a cell can be in
+        */
+        let mut states: Vec<u8> = self._to_flat_terms().iter()
+            .chain(other._to_flat_terms().iter())
+            .map(|term| term.state)
+            .collect::<HashSet<u8>>().into_iter().collect();
+        states.sort();
+        if let Some(unmentioned) = (0..=u8::MAX).find(|state| !states.contains(state)) {
+            states.push(unmentioned);
+        }
+        self._find_difference(other, &states).is_none()
+            && other._find_difference(self, &states).is_none()
+    }
+    // equivalence for tapes whose cells are all in states below num_states
+    pub fn equivalent_within(&self, other: &Expression, num_states: u8) -> bool {
+        self.find_counterexample(other, num_states).is_none()
+    }
+    /*
+    Tape (with cells in states below num_states) that satisfies one of the
+    expressions but not the other, if there is one. Every position that
+    either expression mentions is on the tape
+    */
+    pub fn find_counterexample(
+        &self, other: &Expression, num_states: u8
+    ) -> Option<HashMap<i64, u8>> {
+        let states: Vec<u8> = (0..num_states).collect();
+        self._find_difference(other, &states)
+            .or_else(|| other._find_difference(self, &states))
+    }
+    /*
+    Finds a tape that satisfies this expression but not other, by looking
+    for each product of this expression whether there are states for the
+    cells it leaves open where every product of other has a false term
+    */
+    fn _find_difference(&self, other: &Expression, states: &[u8]) -> Option<HashMap<i64, u8>> {
+        let positions: HashSet<i64> = self._to_flat_terms().iter()
+            .chain(other._to_flat_terms().iter())
+            .map(|term| term.position)
+            .collect();
+
+        for product in self.products.iter() {
+            let in_states = product._terms.iter().all(|term| states.contains(&term.state));
+            if product.has_contradiction() || !in_states {
+                continue;
+            }
+            let fixed: HashMap<i64, u8> = product._terms.iter()
+                .map(|term| (term.position, term.state))
+                .collect();
+
+            let mut clauses: Vec<Vec<(i64, u8)>> = Vec::new();
+            let mut always_satisfied = false;
+            for other_product in other.products.iter() {
+                let always_false = other_product.has_contradiction()
+                    || other_product._terms.iter().any(|term| {
+                        !states.contains(&term.state)
+                            || fixed.get(&term.position).is_some_and(|state| *state != term.state)
+                    });
+                if always_false {
+                    continue;
+                }
+                let clause: Vec<(i64, u8)> = other_product._term_set().into_iter()
+                    .filter(|(position, _)| !fixed.contains_key(position))
+                    .collect();
+                if clause.is_empty() {
+                    always_satisfied = true;
+                    break;
+                }
+                clauses.push(clause);
+            }
+            if always_satisfied {
+                continue;
+            }
+
+            let domains: HashMap<i64, Vec<u8>> = clauses.iter().flatten()
+                .map(|(position, _)| (*position, states.to_vec()))
+                .collect();
+            if let Some(assignment) = find_assignment(domains, &clauses) {
+                let mut tape: HashMap<i64, u8> = positions.iter()
+                    .map(|position| (*position, states[0]))
+                    .collect();
+                tape.extend(assignment);
+                tape.extend(fixed);
+                return Some(tape);
+            }
+        }
+        None
+    }
     pub fn pad_products(&self, new_num_products: usize) -> Option<Expression> {
         let mut new_products = self.products.clone();
         let last_product = self.products.last().unwrap();
@@ -505,20 +1012,24 @@ impl Expression {
 }
 impl PartialEq<Expression> for &Expression {
     fn eq(&self, other: &Expression) -> bool {
-        self.products == other.products
+        *self == other
     }
 }
 impl Hash for Expression {
     fn hash<H: Hasher>(&self, state: &mut H) {
-        for product in &self.products {
+        for product in &self.normalize().products {
             product.hash(state);
         }
     }
 }
 impl Eq for Expression {}
+// expressions are equal if they have the same normal form
 impl PartialEq<Expression> for Expression {
     fn eq(&self, other: &Expression) -> bool {
-        self.products == other.products
+        let (normalized, other_normalized) = (self.normalize(), other.normalize());
+        normalized.products.len() == other_normalized.products.len() &&
+            normalized.products.iter().zip(other_normalized.products.iter())
+                .all(|(product, other_product)| product._terms == other_product._terms)
     }
 }
 
@@ -526,26 +1037,27 @@ impl Mul for Expression {
     type Output = Expression;
 
     fn mul(self, rhs: Self) -> Self::Output {
+        let prune_contradictions = self._optimized || rhs._optimized;
         let mut multiplied_products = Vec::new();
         for product in self.products.iter() {
             for rhs_product in rhs.products.iter() {
-                let new_product = product.copy() * rhs_product.copy();
+                let new_product = product.concat(rhs_product);
+                if prune_contradictions && new_product.has_contradiction() {
+                    continue;
+                }
                 multiplied_products.push(new_product);
             }
         }
-        Expression::new(multiplied_products)
+        Expression { products: multiplied_products, _optimized: prune_contradictions }
     }
 }
 impl Mul<Term> for Expression {
     type Output = Expression;
 
     fn mul(self, rhs: Term) -> Self::Output {
-        // println!("POTATO_PRINT");
-        let mut new_products = Vec::new();
-        for product in self.products.iter() {
-            // println!("PROD_MUL");
-            new_products.push(product * rhs.copy());
-        }
+        let new_products = self.products.iter()
+            .map(|product| product * rhs)
+            .collect();
         Expression::new(new_products)
     }
 }
@@ -553,10 +1065,9 @@ impl Mul<Product> for Expression {
     type Output = Expression;
 
     fn mul(self, rhs: Product) -> Self::Output {
-        let mut new_products = Vec::new();
-        for product in self.products.iter() {
-            new_products.push(product.copy() * rhs.copy());
-        }
+        let new_products = self.products.iter()
+            .map(|product| product.concat(&rhs))
+            .collect();
         Expression::new(new_products)
     }
 }
@@ -564,13 +1075,8 @@ impl BitOr for Expression {
     type Output = Expression;
 
     fn bitor(self, rhs: Self) -> Self::Output {
-        let mut new_products = Vec::new();
-        for product in self.products.iter() {
-            new_products.push(product.copy());
-        }
-        for product in rhs.products.iter() {
-            new_products.push(product.copy());
-        }
+        let mut new_products = self.products;
+        new_products.extend(rhs.products);
         Expression::new(new_products)
     }
 }
@@ -578,10 +1084,7 @@ impl BitOr<Term> for Expression {
     type Output = Expression;
 
     fn bitor(self, rhs: Term) -> Self::Output {
-        let mut new_products: Vec<Product> = Vec::new();
-        for product in self.products.iter() {
-            new_products.push(product.copy());
-        }
+        let mut new_products = self.products;
         new_products.push(rhs._to_product());
         Expression::new(new_products)
     }
@@ -590,10 +1093,7 @@ impl BitOr<Product> for Expression {
     type Output = Expression;
 
     fn bitor(self, rhs: Product) -> Self::Output {
-        let mut new_products: Vec<Product> = Vec::new();
-        for product in self.products.iter() {
-            new_products.push(product.copy());
-        }
+        let mut new_products = self.products;
         new_products.push(rhs);
         Expression::new(new_products)
     }
@@ -632,24 +1132,25 @@ impl AbstractExpression for Expression {
         }
         */
         let mut expanded_expression = self.products.par_iter()
-            .map(|product| product._expand(expansion_mapping))
+            .map(|product| {
+                let mut product = product.clone();
+                product._optimized |= self._optimized;
+                product._expand(expansion_mapping)
+            })
             .reduce(
                 // sum up all the individual expanded products
                 || Expression::new(vec![]),
                 |a, b| { a | b }
             );
+        expanded_expression._optimized = self._optimized;
         expanded_expression._assign_expr_positions();
         expanded_expression
     }
     fn _expand_steps(
         &self, expansion_mapping: &HashMap<u8, Expression>, steps: u64
     ) -> Expression {
-        let mut result = self.copy();
-        result._assign_base_indexes();
-        for _ in 0..steps {
-            result = result._expand(expansion_mapping);
-        }
-        result
+        let mut cache = ExpansionCache::new(expansion_mapping, self._optimized);
+        cache.expand(self, steps)
     }
     fn to_expression(&self) -> Expression {
         self.copy()
@@ -688,21 +1189,6 @@ mod tests {
     use std::collections::HashMap;
     use super::*;
 
-    fn spawn_test_pos_empty_expr() -> Expression {
-        Term::new(-1, 0, false) * Term::new(0, 0, false) * Term::new(1, 1, false) |
-            Term::new(-1, 0, false) * Term::new(0, 1, false) * Term::new(1, 0, false) |
-            Term::new(-1, 1, false) * Term::new(0, 1, false) * Term::new(1, 0, false) |
-            Term::new(-1, 1, false) * Term::new(0, 0, false) * Term::new(1, 1, false) |
-            Term::new(-1, 1, false) * Term::new(0, 1, false) * Term::new(1, 1, false)
-    }
-    fn spawn_test_neg_empty_expr() -> Expression {
-        Term::new(-1, 0, false) * Term::new(0, 0, false) * Term::new(1, 0, false) |
-            Term::new(-1, 0, false) * Term::new(0, 1, false) * Term::new(1, 1, false) |
-            Term::new(-1, 1, false) * Term::new(0, 1, false) * Term::new(1, 1, false) |
-            Term::new(-1, 0, false) * Term::new(0, 0, false) * Term::new(1, 0, false) |
-            Term::new(-1, 0, false) * Term::new(0, 0, false) * Term::new(1, 0, false)
-    }
-
     #[test]
     fn a_test() {
         let a = Term::new(0, 1, false);
@@ -721,13 +1207,9 @@ mod tests {
 
     #[test]
     fn expansion_test() {
-        let pos_exp: Expression = spawn_test_pos_empty_expr();
-        let neg_exp: Expression = spawn_test_neg_empty_expr();
-
-        let expr_mapping: HashMap<u8, Expression> = [
-            (0, pos_exp.clone()),
-            (1, neg_exp.clone())
-        ].iter().cloned().collect();
+        let expr_mapping = Expression::from_wolfram_rule(110);
+        let pos_exp = expr_mapping[&0].clone();
+        let neg_exp = expr_mapping[&1].clone();
 
         let pos_seed_exp = Term::new(0, 0, false);
         let neg_seed_exp = Term::new(0, 1, false);
@@ -744,13 +1226,7 @@ mod tests {
 
     #[test]
     fn chain_expansion_test() {
-        let pos_exp: Expression = spawn_test_pos_empty_expr();
-        let neg_exp: Expression = spawn_test_neg_empty_expr();
-
-        let expr_mapping: HashMap<u8, Expression> = [
-            (0, pos_exp.clone()),
-            (1, neg_exp.clone())
-        ].iter().cloned().collect();
+        let expr_mapping = Expression::from_wolfram_rule(110);
 
         let pos_seed_exp =
             Term::new(0, 0, false) *
@@ -767,13 +1243,7 @@ mod tests {
 
     #[test]
     fn product_debug_info_test() {
-        let pos_exp: Expression = spawn_test_pos_empty_expr();
-        let neg_exp: Expression = spawn_test_neg_empty_expr();
-
-        let expr_mapping: HashMap<u8, Expression> = [
-            (0, pos_exp.clone()),
-            (1, neg_exp.clone())
-        ].iter().cloned().collect();
+        let expr_mapping = Expression::from_wolfram_rule(110);
 
         let pos_seed_prod =
             Term::new(0, 0, false) *
@@ -789,13 +1259,7 @@ mod tests {
 
     #[test]
     fn term_debug_info_test() {
-        let pos_exp: Expression = spawn_test_pos_empty_expr();
-        let neg_exp: Expression = spawn_test_neg_empty_expr();
-
-        let expr_mapping: HashMap<u8, Expression> = [
-            (0, pos_exp.clone()),
-            (1, neg_exp.clone())
-        ].iter().cloned().collect();
+        let expr_mapping = Expression::from_wolfram_rule(110);
 
         let pos_seed_term =
             Term::new(0, 0, false);
@@ -809,13 +1273,7 @@ mod tests {
 
     #[test]
     fn correct_expr_pos_test() {
-        let pos_exp: Expression = spawn_test_pos_empty_expr();
-        let neg_exp: Expression = spawn_test_neg_empty_expr();
-
-        let expr_mapping: HashMap<u8, Expression> = [
-            (0, pos_exp.clone()),
-            (1, neg_exp.clone())
-        ].iter().cloned().collect();
+        let expr_mapping = Expression::from_wolfram_rule(110);
 
         let pos_seed_term =
             Term::new(0, 0, false);
@@ -843,6 +1301,264 @@ mod tests {
         }
     }
 
+    #[test]
+    fn simplify_test() {
+        let a = Term::new(0, 1, false);
+        let b = Term::new(1, 0, false);
+        let c = Term::new(2, 1, false);
+        let not_a = Term::new(0, 0, false);
+
+        // repeated terms, contradictory terms, duplicates and absorption
+        let expr = a.clone() * a.clone() * b.clone() |
+            a.clone() * not_a.clone() |
+            b.clone() * c.clone() * b.clone() |
+            c.clone() * b.clone() |
+            a.clone() * b.clone() * c.clone();
+        let simplified = expr.simplify();
+        assert_eq!(simplified, a.clone() * b.clone() | b.clone() * c.clone());
+        validate_debug_info_exists(&simplified);
+
+        let contradiction = Expression::new(vec![a.clone() * not_a.clone()]);
+        assert_eq!(contradiction.simplify()._get_num_products(), 0);
+
+        // a rule's expression repeated twice loses its duplicate products
+        let rule_exp = Expression::from_wolfram_rule(110)[&1].clone();
+        let doubled_exp = rule_exp.clone() | rule_exp.clone();
+        let simplified = doubled_exp.simplify();
+        assert_eq!(simplified._get_num_products(), 5);
+        for states in 0..8u8 {
+            let substitutions: HashMap<i64, u8> = (-1..=1)
+                .map(|position| (position, (states >> (position + 1)) & 1))
+                .collect();
+            assert_eq!(
+                simplified._sub(&substitutions, 0), doubled_exp._sub(&substitutions, 0)
+            );
+        }
+    }
+
+    #[test]
+    fn contradiction_pruning_test() {
+        let expr_mapping = Expression::from_wolfram_rule(30);
+        let seed = Term::new(0, 1, false) * Term::new(1, 0, false);
+
+        for steps in 1..3 {
+            let expanded = seed.to_expression()._expand_steps(&expr_mapping, steps);
+            let pruned = seed.to_expression()
+                .with_contradiction_pruning()
+                ._expand_steps(&expr_mapping, steps);
+            println!(
+                "PRUNED_PRODUCTS_{} {} -> {}",
+                steps, expanded._get_num_products(), pruned._get_num_products()
+            );
+            assert!(pruned._get_num_products() < expanded._get_num_products());
+            assert!(expanded.products.iter().any(|product| product.has_contradiction()));
+            assert!(pruned.products.iter().all(|product| !product.has_contradiction()));
+            validate_debug_info_exists(&pruned);
+
+            // both still match the same tapes
+            let start = -(steps as i64);
+            let end = 1 + steps as i64;
+            for states in 0..(1u32 << (end - start + 1)) {
+                let substitutions: HashMap<i64, u8> = (start..=end)
+                    .map(|position| (position, ((states >> (position - start)) & 1) as u8))
+                    .collect();
+                assert_eq!(
+                    pruned._sub(&substitutions, 0), expanded._sub(&substitutions, 0)
+                );
+            }
+        }
+
+        // optimized terms start off pruned expansions as well
+        let optimized_seed = Term::new(0, 1, true);
+        let pruned = optimized_seed._expand_steps(&expr_mapping, 2);
+        assert!(pruned._optimized);
+        assert!(pruned.products.iter().all(|product| !product.has_contradiction()));
+    }
+
+    #[test]
+    fn normalize_test() {
+        let a = Term::new(0, 1, false);
+        let b = Term::new(1, 0, false);
+        let c = Term::new(-1, 1, false);
+
+        let expr = b.clone() * a.clone() * b.clone() | c.clone() | a.clone() * b.clone();
+        let normalized = expr.normalize();
+        assert_eq!(normalized._to_string("A"), "A(-1,1) | A(0,1)*A(1,0)");
+        validate_debug_info_exists(&normalized);
+
+        // equality and hashes don't depend on the order of terms or products
+        let reordered = c.clone() | a.clone() * b.clone();
+        assert_eq!(expr, reordered);
+        assert_eq!(b.clone() * a.clone(), a.clone() * b.clone());
+        let expressions: HashSet<Expression> = [expr.clone(), reordered].into_iter().collect();
+        assert_eq!(expressions.len(), 1);
+
+        assert_ne!(expr, c.clone() | a.clone());
+        assert_ne!(expr, (a.clone() * b.clone()).to_expression());
+    }
+
+    #[test]
+    fn memoized_expansion_test() {
+        let expr_mapping = Expression::from_wolfram_rule(110);
+        let seed = (Term::new(0, 1, false) * Term::new(2, 1, false))
+            .to_expression()
+            .with_contradiction_pruning();
+
+        // same products as expanding one step at a time
+        let mut stepped = seed.clone();
+        for steps in 1..4 {
+            stepped = stepped._expand(&expr_mapping);
+            assert_eq!(seed._expand_steps(&expr_mapping, steps), stepped);
+        }
+
+        // every state is expanded once per step count, however often it comes up
+        let mut cache = ExpansionCache::new(&expr_mapping, true);
+        let expanded = cache.expand(&seed, 3);
+        assert_eq!(expanded, stepped);
+        assert_eq!(cache.expansions.len(), 7);
+        validate_debug_info_exists(&expanded);
+    }
+
+    #[test]
+    fn wolfram_rule_test() {
+        // 110 = 0b01101110, so 111, 100 and 000 become 0
+        let rule_110 = Expression::from_wolfram_rule(110);
+        assert_eq!(rule_110[&0]._get_num_products(), 3);
+        assert_eq!(rule_110[&1]._get_num_products(), 5);
+        assert_eq!(
+            rule_110[&0]._to_string("A"),
+            "A(-1,0)*A(0,0)*A(1,0) | A(-1,1)*A(0,0)*A(1,0) | A(-1,1)*A(0,1)*A(1,1)"
+        );
+        assert_eq!(Expression::from_wolfram_rule(0)[&0]._get_num_products(), 8);
+        assert_eq!(Expression::from_wolfram_rule(0)[&1]._get_num_products(), 0);
+    }
+
+    #[test]
+    fn multi_state_test() {
+        // each cell becomes the sum of its neighbourhood mod 3
+        let expr_mapping = Expression::from_neighbourhood_rule(3, |left, center, right| {
+            (left + center + right) % 3
+        });
+        assert_eq!(validate_expansion_mapping(&expr_mapping), Ok(()));
+        for state in 0..3 {
+            assert_eq!(expr_mapping[&state]._get_num_products(), 9);
+        }
+
+        // pruning still only drops products that can't match any tape
+        let seed = (Term::new(0, 2, false) * Term::new(1, 1, false)).to_expression();
+        let expanded = seed._expand_steps(&expr_mapping, 1);
+        let pruned = seed.with_contradiction_pruning()._expand_steps(&expr_mapping, 1);
+        assert_eq!(expanded._get_num_products(), 81);
+        assert_eq!(pruned._get_num_products(), 9);
+        assert_eq!(pruned.simplify(), pruned);
+        for states in 0..3u32.pow(4) {
+            let substitutions: HashMap<i64, u8> = (-1..=2)
+                .map(|position| (position, (states / 3u32.pow((position + 1) as u32) % 3) as u8))
+                .collect();
+            assert_eq!(pruned._sub(&substitutions, 0), expanded._sub(&substitutions, 0));
+        }
+
+        let mut incomplete_mapping = expr_mapping.clone();
+        incomplete_mapping.remove(&2);
+        assert_eq!(
+            validate_expansion_mapping(&incomplete_mapping),
+            Err(ExpansionMappingError::MissingState { state: 2, used_by: 0 })
+        );
+    }
+
+    #[test]
+    fn serialization_test() {
+        let expr_mapping = Expression::from_wolfram_rule(30);
+        let seed = Term::new(0, 1, false).to_expression().with_contradiction_pruning();
+        let expanded = seed._expand_steps(&expr_mapping, 2);
+        validate_debug_info_exists(&expanded);
+
+        let from_json = Expression::from_json(&expanded.to_json()).unwrap();
+        let from_bytes = Expression::from_bytes(&expanded.to_bytes()).unwrap();
+        for loaded in [from_json, from_bytes] {
+            assert_eq!(loaded, expanded);
+            assert_eq!(loaded._optimized, expanded._optimized);
+            // equality ignores debug info, so check that it survives separately
+            assert_eq!(format!("{:?}", loaded), format!("{:?}", expanded));
+        }
+
+        assert!(Expression::from_json("{\"products\": 3}").is_err());
+        assert!(Expression::from_bytes(&[1, 2, 3]).is_err());
+    }
+
+    #[test]
+    fn sub_many_test() {
+        let expr_mapping = Expression::from_wolfram_rule(30);
+        let expr = Term::new(0, 1, false)
+            .to_expression()
+            .with_contradiction_pruning()
+            ._expand_steps(&expr_mapping, 2);
+        // every configuration of the 5 cells that the expression reads
+        let windows: Vec<HashMap<i64, u8>> = (0..32u8)
+            .map(|cells| (-2..=2).map(|position| {
+                (position, (cells >> (position + 2)) & 1)
+            }).collect())
+            .collect();
+
+        let results = expr.sub_many(&windows, 0);
+        assert_eq!(results.len(), windows.len());
+        for (window, result) in windows.iter().zip(results) {
+            assert_eq!(result, expr._sub(window, 0));
+        }
+        assert_eq!(expr.sub_many(&[], 0), Vec::<bool>::new());
+    }
+
+    #[test]
+    fn equivalence_test() {
+        let expr_mapping = Expression::from_wolfram_rule(110);
+        let seed = Term::new(0, 1, false).to_expression();
+        let expanded = seed._expand_steps(&expr_mapping, 2);
+        let pruned = seed.with_contradiction_pruning()._expand_steps(&expr_mapping, 2);
+        assert!(expanded.is_satisfiable());
+        assert!(pruned.equivalent_to(&expanded));
+        assert!(pruned.simplify().equivalent_within(&expanded, 2));
+
+        // a cell is in state 0 exactly when it isn't in state 1
+        let zero = Term::new(0, 0, false).to_expression()._expand_steps(&expr_mapping, 1);
+        let one = Term::new(0, 1, false).to_expression()._expand_steps(&expr_mapping, 1);
+        let either = zero.clone() | one.clone();
+        let anything = Term::new(-1, 0, false) | Term::new(-1, 1, false);
+        assert!(either.equivalent_within(&anything, 2));
+        // but not when cells can be in a third state
+        assert!(!either.equivalent_to(&anything));
+        let tape = either.find_counterexample(&anything, 3).unwrap();
+        assert_ne!(either._sub(&tape, 0), anything._sub(&tape, 0));
+
+        let tape = zero.find_counterexample(&one, 2).unwrap();
+        assert_ne!(zero._sub(&tape, 0), one._sub(&tape, 0));
+        let contradiction = (Term::new(0, 1, false) * Term::new(0, 0, false)).to_expression();
+        assert!(!contradiction.is_satisfiable());
+        assert!(contradiction.equivalent_to(&Expression::new(vec![])));
+    }
+
+    #[test]
+    fn expand_iter_test() {
+        let expr_mapping = Expression::from_wolfram_rule(30);
+        let seed = Term::new(0, 1, false)._expand_steps(&expr_mapping, 1);
+        let expanded = seed._expand(&expr_mapping);
+        let lazily_expanded = Expression::new(seed.expand_iter(&expr_mapping).collect());
+        assert_eq!(lazily_expanded._get_products(), expanded._get_products());
+        assert_eq!(format!("{:?}", lazily_expanded), format!("{:?}", expanded));
+
+        let pruned_seed = seed.clone().with_contradiction_pruning();
+        let pruned = pruned_seed._expand(&expr_mapping);
+        let lazily_pruned: Vec<Product> = pruned_seed.expand_iter(&expr_mapping).collect();
+        assert_eq!(&lazily_pruned, pruned._get_products());
+        assert!(lazily_pruned.len() < expanded._get_num_products());
+        // only as much of the expansion as gets consumed is worked out
+        assert_eq!(seed.expand_iter(&expr_mapping).take(3).count(), 3);
+
+        let par_expanded: HashSet<Product> = pruned_seed.expand_par_iter(&expr_mapping, 4)
+            .collect();
+        assert_eq!(par_expanded, pruned._get_products().iter().cloned().collect());
+        assert_eq!(seed.expand_par_iter(&expr_mapping, 1).take(5).count(), 5);
+    }
+
     #[test]
     fn correct_product_length_test() {
         let p = Term::new(0, 0, false) * Term::new(1, 0, false);
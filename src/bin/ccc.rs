@@ -0,0 +1,402 @@
+use std::env;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::process::exit;
+use std::fs;
+use py_ca_compiler::asm_gen::abi::Abi;
+use py_ca_compiler::asm_gen::asm_symbols::{AsmGenError, AsmProgram, AsmSymbol};
+use py_ca_compiler::assembler::{
+    build_from_asm_file, stream_asm_file, AssembleAndLinkError, OutputKind, Target
+};
+use py_ca_compiler::lexer::lexer::lex_from_str;
+use py_ca_compiler::parser::ast_printer::PrintableAst;
+use py_ca_compiler::parser::parse::parse_from_str_with_diagnostics;
+use py_ca_compiler::optimizer::optimize_program;
+use py_ca_compiler::parser::parser_helpers::ParseError;
+use py_ca_compiler::preprocessor::Preprocessor;
+use py_ca_compiler::riscv_gen::riscv_asm::RiscvProgram;
+use py_ca_compiler::semantic::identifier_resolution::{
+    resolve_identifiers_with_diagnostics, SemanticError
+};
+use py_ca_compiler::semantic::lvalue_validation::validate_lvalues;
+use py_ca_compiler::tacky::tacky_symbols::{tacky_gen_from_str, TackyProgram};
+use py_ca_compiler::tacky::tacky_text::{emit_tacky_text, parse_tacky_text};
+use py_ca_compiler::tacky::tacky_verifier::verify_tacky_program;
+
+/*
+Compiler driver with the same interface as the one used by
+the "Writing a C Compiler" test suite, e.g.
+ccc [--lex | --parse | --validate | --tacky | --codegen | -S | -c] [-O]
+    [-g] [-fomit-frame-pointer] [-fpic] [-I <dir>]...
+    [--target <x86_64 | x86_64-windows | x86_64-darwin | riscv64>] [-o <output>] <file.c>
+C sources are preprocessed before lexing, with -I adding include directories.
+Files with a .tacky extension are loaded as textual tacky instead of C source,
+and -S -o - writes the assembly to stdout
+*/
+
+#[derive(Clone, Debug, PartialEq)]
+enum Stage {
+    Lex,
+    Parse,
+    // parse and run the semantic analysis passes
+    Validate,
+    Tacky,
+    Codegen,
+    // write the assembly file without assembling it
+    EmitAssembly,
+    // assemble into an object file without linking
+    Object,
+    // assemble and link into an executable
+    Executable,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+struct DriverOptions {
+    stage: Stage,
+    source_path: PathBuf,
+    output_path: Option<PathBuf>,
+    // run the tacky optimization passes
+    optimize: bool,
+    // address x86-64 stack slots through %rsp instead of %rbp
+    omit_frame_pointer: bool,
+    // emit DWARF line info for debuggers
+    debug_info: bool,
+    // generate code that can be linked into a shared object
+    position_independent: bool,
+    // directories searched by #include, in order
+    include_paths: Vec<PathBuf>,
+    target: Target,
+}
+impl DriverOptions {
+    fn parse_args(args: &[String]) -> Result<DriverOptions, String> {
+        let mut stage = Stage::Executable;
+        let mut source_path: Option<PathBuf> = None;
+        let mut output_path: Option<PathBuf> = None;
+        let mut optimize = false;
+        let mut omit_frame_pointer = false;
+        let mut debug_info = false;
+        let mut position_independent = false;
+        let mut include_paths: Vec<PathBuf> = Vec::new();
+        let mut target = Target::X86_64;
+
+        let mut args_iter = args.iter();
+        while let Some(arg) = args_iter.next() {
+            match arg.as_str() {
+                "--lex" => stage = Stage::Lex,
+                "--parse" => stage = Stage::Parse,
+                "--validate" => stage = Stage::Validate,
+                "--tacky" => stage = Stage::Tacky,
+                "--codegen" => stage = Stage::Codegen,
+                "-S" => stage = Stage::EmitAssembly,
+                "-c" => stage = Stage::Object,
+                "-O" => optimize = true,
+                "-fomit-frame-pointer" => omit_frame_pointer = true,
+                "-g" => debug_info = true,
+                "-fpic" | "-fPIC" => position_independent = true,
+                "--target" => match args_iter.next().map(|name| Target::from_name(name)) {
+                    Some(Some(target_name)) => target = target_name,
+                    Some(None) => return Err("Unknown target".to_string()),
+                    None => return Err("--target requires a target name".to_string())
+                },
+                "-I" => match args_iter.next() {
+                    Some(path) => include_paths.push(PathBuf::from(path)),
+                    None => return Err("-I requires a directory".to_string())
+                },
+                flag if flag.starts_with("-I") => include_paths.push(PathBuf::from(&flag[2..])),
+                "-o" => match args_iter.next() {
+                    Some(path) => output_path = Some(PathBuf::from(path)),
+                    None => return Err("-o requires an output path".to_string())
+                },
+                flag if flag.starts_with('-') => {
+                    return Err(format!("Unknown flag: {}", flag));
+                },
+                path => {
+                    if source_path.is_some() {
+                        return Err(format!("Unexpected extra source file: {}", path));
+                    }
+                    source_path = Some(PathBuf::from(path));
+                }
+            }
+        }
+
+        match source_path {
+            Some(source_path) => Ok(DriverOptions {
+                stage, source_path, output_path, optimize,
+                omit_frame_pointer, debug_info, position_independent, include_paths, target
+            }),
+            None => Err("No source file given".to_string())
+        }
+    }
+    fn is_tacky_input(&self) -> bool {
+        self.source_path.extension().is_some_and(|extension| extension == "tacky")
+    }
+    fn output_kind(&self) -> OutputKind {
+        match self.stage {
+            Stage::EmitAssembly => OutputKind::Assembly,
+            Stage::Object => OutputKind::Object,
+            _ => OutputKind::Executable
+        }
+    }
+    fn output_path(&self) -> PathBuf {
+        match &self.output_path {
+            Some(output_path) => output_path.clone(),
+            None => self.output_kind().default_output_path(&self.source_path)
+        }
+    }
+}
+
+fn print_usage(program_name: &str) {
+    eprintln!(
+        "Usage: {} [--lex | --parse | --validate | --tacky | --codegen | -S | -c] [-O] \
+        [-g] [-fomit-frame-pointer] [-fpic] [-I <dir>]... \
+        [--target <x86_64 | x86_64-windows | x86_64-darwin | riscv64>] [-o <output>] <file.c>",
+        program_name
+    );
+}
+
+fn fail(message: String) -> ! {
+    eprintln!("{}", message);
+    exit(1);
+}
+
+fn format_parse_errors(errors: Vec<ParseError>) -> String {
+    // one line per error, in the order they appear in the source
+    errors.iter().map(|err| format!("Parse Error: {}", err))
+        .collect::<Vec<String>>().join("\n")
+}
+
+fn format_semantic_errors(errors: Vec<SemanticError>) -> String {
+    errors.iter().map(|err| format!("Semantic Error: {}", err))
+        .collect::<Vec<String>>().join("\n")
+}
+
+fn preprocess(options: &DriverOptions) -> Result<String, String> {
+    Preprocessor::new(options.include_paths.clone())
+        .preprocess_file(&options.source_path)
+        .map_err(|err| format!("Preprocessor Error: {}", err))
+}
+
+fn load_tacky(options: &DriverOptions) -> Result<TackyProgram, String> {
+    if !options.is_tacky_input() {
+        return tacky_gen_from_str(&preprocess(options)?)
+            .map_err(|err| format!("Tacky Generation Error: {}", err));
+    }
+    let tacky_text = fs::read_to_string(&options.source_path)
+        .map_err(|err| format!("IO Error: {}", err))?;
+    parse_tacky_text(&tacky_text).map_err(|err| format!("Tacky Parse Error: {}", err))
+}
+
+fn verify_tacky(tacky_program: &TackyProgram) -> Result<(), String> {
+    // generated tacky should always be valid, so this only runs in debug builds
+    if !cfg!(debug_assertions) {
+        return Ok(());
+    }
+    verify_tacky_program(tacky_program).map_err(|errors| {
+        errors.iter().map(|err| format!("Tacky Verify Error: {}", err))
+            .collect::<Vec<String>>().join("\n")
+    })
+}
+
+fn emit_assembly(
+    options: &DriverOptions, tacky_program: TackyProgram, writer: &mut impl Write
+) -> Result<(), AsmGenError> {
+    let source_name = options.source_path.to_string_lossy().to_string();
+    match options.target {
+        Target::X86_64 | Target::X86_64Windows | Target::X86_64Darwin => {
+            let abi = match options.target {
+                Target::X86_64Windows => Abi::Windows,
+                Target::X86_64Darwin => Abi::Darwin,
+                _ => Abi::SystemV
+            };
+            let mut asm_program = AsmProgram::from_tacky_program(tacky_program).with_abi(abi);
+            if options.optimize {
+                asm_program = asm_program.with_peephole_optimization();
+            }
+            if options.omit_frame_pointer {
+                asm_program = asm_program.with_omitted_frame_pointer();
+            }
+            if options.debug_info {
+                asm_program = asm_program.with_debug_info(source_name);
+            }
+            if options.position_independent {
+                asm_program = asm_program.with_position_independent_code();
+            }
+            asm_program.emit_asm_code(writer)
+        },
+        Target::Riscv64 => {
+            let mut riscv_program = RiscvProgram::from_tacky_program(tacky_program);
+            if options.debug_info {
+                riscv_program = riscv_program.with_debug_info(source_name);
+            }
+            if options.position_independent {
+                riscv_program = riscv_program.with_position_independent_code();
+            }
+            riscv_program.emit_asm_code(writer)
+        }
+    }
+}
+
+fn run(options: &DriverOptions) -> Result<(), String> {
+    match options.stage {
+        Stage::Lex | Stage::Parse | Stage::Validate if options.is_tacky_input() => {
+            return Err(format!("{:?} stage needs a C source file", options.stage));
+        },
+        Stage::Lex => {
+            lex_from_str(&preprocess(options)?)
+                .map_err(|err| format!("Lex Error: {:?}", err))?;
+            return Ok(());
+        },
+        Stage::Parse => {
+            // dump the AST to help with debugging the frontend
+            let program = parse_from_str_with_diagnostics(&preprocess(options)?)
+                .map_err(format_parse_errors)?;
+            print!("{}", program.print_ast_code(0));
+            return Ok(());
+        },
+        Stage::Validate => {
+            let program = parse_from_str_with_diagnostics(&preprocess(options)?)
+                .map_err(format_parse_errors)?;
+            let program = resolve_identifiers_with_diagnostics(program)
+                .map_err(format_semantic_errors)?;
+            validate_lvalues(&program)
+                .map_err(|err| format!("Semantic Error: {}", err))?;
+            // operand types are only checked while generating tacky
+            TackyProgram::try_from_program(&program)
+                .map_err(|err| format!("Semantic Error: {}", err))?;
+            return Ok(());
+        },
+        _ => {}
+    }
+
+    let mut tacky_program = load_tacky(options)?;
+    verify_tacky(&tacky_program)?;
+    if options.optimize {
+        tacky_program = optimize_program(tacky_program);
+        verify_tacky(&tacky_program)?;
+    }
+    if options.stage == Stage::Tacky {
+        print!("{}", emit_tacky_text(&tacky_program));
+        return Ok(());
+    }
+    let output_kind = options.output_kind();
+    let output_path = options.output_path();
+    let to_stdout = output_kind == OutputKind::Assembly && output_path == Path::new("-");
+    if options.stage == Stage::Codegen || to_stdout {
+        // --codegen generates the assembly without writing it anywhere
+        let result = match to_stdout {
+            true => emit_assembly(options, tacky_program, &mut io::stdout().lock()),
+            false => emit_assembly(options, tacky_program, &mut io::sink()),
+        };
+        return result.map_err(|err| AssembleAndLinkError::AsmGenError(err).to_string());
+    }
+
+    let scratch_asm_path = options.source_path.with_extension("s");
+    let asm_path = output_kind.asm_path(&scratch_asm_path, &output_path);
+    stream_asm_file(asm_path, |writer| emit_assembly(options, tacky_program, writer))
+        .and_then(|_| build_from_asm_file(&options.target, asm_path, &output_kind, &output_path))
+        .map_err(|err| err.to_string())
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    let options = match DriverOptions::parse_args(&args[1..]) {
+        Ok(options) => options,
+        Err(message) => {
+            print_usage(&args[0]);
+            fail(message);
+        }
+    };
+    if let Err(message) = run(&options) {
+        fail(message);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn to_args(args: &[&str]) -> Vec<String> {
+        args.iter().map(|arg| arg.to_string()).collect()
+    }
+
+    #[test]
+    fn test_parse_stage_flags() {
+        let options = DriverOptions::parse_args(&to_args(&["--tacky", "prog.c"])).unwrap();
+        assert_eq!(options.stage, Stage::Tacky);
+        let validate_options = DriverOptions::parse_args(&to_args(&["--validate", "prog.c"]));
+        assert_eq!(validate_options.unwrap().stage, Stage::Validate);
+        assert_eq!(options.source_path, PathBuf::from("prog.c"));
+        assert_eq!(options.output_path(), PathBuf::from("prog"));
+
+        let options = DriverOptions::parse_args(&to_args(&["prog.c"])).unwrap();
+        assert_eq!(options.stage, Stage::Executable);
+        assert_eq!(options.output_kind(), OutputKind::Executable);
+        assert!(!options.optimize);
+
+        let options = DriverOptions::parse_args(&to_args(&["-O", "prog.c"])).unwrap();
+        assert!(options.optimize);
+        assert!(!options.omit_frame_pointer);
+        assert_eq!(options.target, Target::X86_64);
+
+        let options = DriverOptions::parse_args(
+            &to_args(&["-fomit-frame-pointer", "prog.c"])
+        ).unwrap();
+        assert!(options.omit_frame_pointer);
+        assert!(!options.debug_info);
+
+        let options = DriverOptions::parse_args(&to_args(&["-g", "prog.c"])).unwrap();
+        assert!(options.debug_info);
+        assert!(!options.position_independent);
+
+        let options = DriverOptions::parse_args(&to_args(&["-fPIC", "prog.c"])).unwrap();
+        assert!(options.position_independent);
+        assert!(options.include_paths.is_empty());
+
+        let options = DriverOptions::parse_args(
+            &to_args(&["-I", "include", "-Ivendor/include", "prog.c"])
+        ).unwrap();
+        assert_eq!(options.include_paths, vec![
+            PathBuf::from("include"), PathBuf::from("vendor/include")
+        ]);
+
+        let options = DriverOptions::parse_args(
+            &to_args(&["--target", "riscv64", "prog.c"])
+        ).unwrap();
+        assert_eq!(options.target, Target::Riscv64);
+
+        let options = DriverOptions::parse_args(&to_args(&["-c", "prog.c"])).unwrap();
+        assert_eq!(options.output_kind(), OutputKind::Object);
+        assert_eq!(options.output_path(), PathBuf::from("prog.o"));
+        assert!(!options.is_tacky_input());
+
+        let options = DriverOptions::parse_args(&to_args(&["-S", "prog.tacky"])).unwrap();
+        assert!(options.is_tacky_input());
+        assert_eq!(options.output_path(), PathBuf::from("prog.s"));
+    }
+
+    #[test]
+    fn test_parse_output_path() {
+        let options = DriverOptions::parse_args(
+            &to_args(&["-S", "-o", "out.s", "prog.c"])
+        ).unwrap();
+        assert_eq!(options.stage, Stage::EmitAssembly);
+        assert_eq!(options.output_path(), PathBuf::from("out.s"));
+
+        let options = DriverOptions::parse_args(&to_args(&["-S", "prog.c"])).unwrap();
+        assert_eq!(options.output_path(), PathBuf::from("prog.s"));
+
+        let options = DriverOptions::parse_args(&to_args(&["prog.c", "-o", "prog.out"])).unwrap();
+        assert_eq!(options.output_path(), PathBuf::from("prog.out"));
+    }
+
+    #[test]
+    fn test_parse_invalid_args() {
+        assert!(DriverOptions::parse_args(&to_args(&[])).is_err());
+        assert!(DriverOptions::parse_args(&to_args(&["-o"])).is_err());
+        assert!(DriverOptions::parse_args(&to_args(&["--optimize", "prog.c"])).is_err());
+        assert!(DriverOptions::parse_args(&to_args(&["a.c", "b.c"])).is_err());
+        assert!(DriverOptions::parse_args(&to_args(&["--target", "arm", "prog.c"])).is_err());
+        assert!(DriverOptions::parse_args(&to_args(&["prog.c", "--target"])).is_err());
+        assert!(DriverOptions::parse_args(&to_args(&["prog.c", "-I"])).is_err());
+    }
+}
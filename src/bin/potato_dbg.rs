@@ -0,0 +1,301 @@
+use std::env;
+use std::fmt::{Binary, Display};
+use std::fs;
+use std::io::{self, BufRead, Write};
+use std::process::exit;
+use py_ca_compiler::potato_cpu::bit_allocation::BitAllocation;
+use py_ca_compiler::potato_cpu::potato_asm::{PotatoProgram, NUM_SCRATCH_REGISTERS, STACK_WIDTH};
+use py_ca_compiler::potato_cpu::potato_asm_text::{
+    emit_potato_instruction, emit_potato_register, parse_potato_asm, parse_potato_register
+};
+use py_ca_compiler::potato_cpu::potato_cpu::{
+    PotatoCPU, PotatoSpec, StepResult, StopReason, Watchpoint
+};
+use py_ca_compiler::tacky::tacky_symbols::tacky_gen_from_filepath;
+
+/*
+Interactive debugger for potato programs, e.g.
+potato-dbg <program.c | program.potato>
+C sources are compiled for the PotatoCPU, and any other file is loaded
+as textual potato assembly (see potato_asm_text). Commands are read
+from stdin one per line:
+    step [n]            execute the next n instructions (default 1)
+    run <n>             execute up to n instructions, stopping at breakpoints
+    regs                print every register
+    stack [start] [n]   print n stack values from start (default all)
+    list                print the instructions around the program counter
+    break <index>       stop before executing the instruction at index
+    delete <index>      remove the breakpoint at index
+    watch <register | @address>
+                        stop after a register or stack address changes
+    quit
+*/
+
+const HELP: &str = "Commands: step [n], run <n>, regs, stack [start] [n], list, \
+break <index>, delete <index>, watch <register | @address>, quit";
+// instructions shown before and after the program counter by list
+const LIST_RADIUS: usize = 4;
+
+fn format_bits<T: BitAllocation + Binary + Display>(value: &T) -> String {
+    // most significant bit first, followed by the unsigned value and width
+    format!("{:#b} ({}, {} bits)", value, value, value.get_length())
+}
+
+struct Debugger {
+    cpu: PotatoCPU,
+}
+impl Debugger {
+    fn new(spec: &PotatoSpec) -> Debugger {
+        Debugger { cpu: PotatoCPU::new(spec) }
+    }
+
+    fn load_spec(path: &str) -> Result<PotatoSpec, String> {
+        if path.ends_with(".c") {
+            let tacky_program = tacky_gen_from_filepath(path, false)
+                .map_err(|err| err.to_string())?;
+            let program = PotatoProgram::try_from_tacky_program(tacky_program)
+                .map_err(|err| err.to_string())?;
+            return Ok(program.spec());
+        }
+        let text = fs::read_to_string(path).map_err(|err| format!("{}: {}", path, err))?;
+        let instructions = parse_potato_asm(&text).map_err(|err| err.to_string())?;
+        let spec = PotatoSpec::new(instructions, NUM_SCRATCH_REGISTERS, STACK_WIDTH as u16);
+        spec.validate().map_err(|err| err.to_string())?;
+        Ok(spec)
+    }
+
+    fn parse_number(text: Option<&str>, default: Option<usize>) -> Result<usize, String> {
+        match (text, default) {
+            (Some(text), _) => text.parse::<usize>()
+                .map_err(|_| format!("Invalid number {}", text)),
+            (None, Some(default)) => Ok(default),
+            (None, None) => Err("Missing number".to_string())
+        }
+    }
+
+    // None once the debugger should exit
+    fn execute_command(&mut self, line: &str) -> Option<Result<String, String>> {
+        let words: Vec<&str> = line.split_whitespace().collect();
+        let result = match words.as_slice() {
+            [] => Ok(String::new()),
+            ["quit"] | ["q"] => return None,
+            ["help"] => Ok(HELP.to_string()),
+            ["step", rest @ ..] | ["s", rest @ ..] if rest.len() <= 1 => {
+                Self::parse_number(rest.first().copied(), Some(1)).map(|num_steps| {
+                    let mut step_result = self.cpu.step();
+                    for _ in 1..num_steps {
+                        if step_result.halted { break; }
+                        step_result = self.cpu.step();
+                    }
+                    self.describe_stop(&step_result)
+                })
+            },
+            ["run", max_steps] | ["r", max_steps] => {
+                Self::parse_number(Some(max_steps), None).map(|max_steps| {
+                    let run_result = self.cpu.run(max_steps);
+                    self.describe_stop(&run_result)
+                })
+            },
+            ["regs"] => Ok(self.format_registers()),
+            ["stack", rest @ ..] if rest.len() <= 2 => {
+                Self::parse_number(rest.first().copied(), Some(0)).and_then(|start| {
+                    let num_values = self.cpu.stack.len().saturating_sub(start);
+                    Self::parse_number(rest.get(1).copied(), Some(num_values))
+                        .map(|num_values| self.format_stack(start, num_values))
+                })
+            },
+            ["list"] | ["l"] => Ok(self.format_listing()),
+            ["break", index] | ["b", index] => {
+                Self::parse_number(Some(index), None).map(|index| {
+                    self.cpu.add_breakpoint(index);
+                    format!("Breakpoint at instruction {}", index)
+                })
+            },
+            ["delete", index] => Self::parse_number(Some(index), None).and_then(|index| {
+                match self.cpu.remove_breakpoint(index) {
+                    true => Ok(format!("Deleted breakpoint at instruction {}", index)),
+                    false => Err(format!("No breakpoint at instruction {}", index))
+                }
+            }),
+            ["watch", location] => self.parse_watchpoint(location).map(|watchpoint| {
+                let message = format!("Watching {}", Self::format_watchpoint(&watchpoint));
+                self.cpu.add_watchpoint(watchpoint);
+                message
+            }),
+            _ => Err(format!("Unknown command {}. {}", line.trim(), HELP))
+        };
+        Some(result)
+    }
+
+    fn parse_watchpoint(&self, text: &str) -> Result<Watchpoint, String> {
+        if let Some(address) = text.strip_prefix('@') {
+            return Self::parse_number(Some(address), None).map(Watchpoint::StackAddress);
+        }
+        match parse_potato_register(text) {
+            Some(register) => Ok(Watchpoint::Register(register)),
+            None => Err(format!("Unknown register {}", text))
+        }
+    }
+    fn format_watchpoint(watchpoint: &Watchpoint) -> String {
+        match watchpoint {
+            Watchpoint::Register(register) => emit_potato_register(register),
+            Watchpoint::StackAddress(address) => format!("@{}", address),
+        }
+    }
+
+    fn describe_stop(&self, step_result: &StepResult) -> String {
+        let reason = match (&step_result.stop_reason, step_result.halted) {
+            (Some(StopReason::Breakpoint(index)), _) => {
+                format!("Stopped at breakpoint {}", index)
+            },
+            (Some(StopReason::Watchpoint(watchpoint, index)), _) => format!(
+                "Instruction {} changed {}", index, Self::format_watchpoint(watchpoint)
+            ),
+            (Some(StopReason::LoopDetected(index)), _) => {
+                format!("Loops forever from instruction {}", index)
+            },
+            (None, true) => "Halted".to_string(),
+            (None, false) => "Paused".to_string(),
+        };
+        let mut description = format!("{} after {} steps", reason, step_result.time_steps);
+        if !step_result.halted {
+            description.push_str(&format!("\n{}", self.format_next_instruction()));
+        }
+        description
+    }
+
+    fn format_next_instruction(&self) -> String {
+        let program_counter = self.cpu.read_program_counter();
+        match self.cpu.get_instructions().get(program_counter) {
+            Some(instruction) => {
+                format!("=> {}: {}", program_counter, emit_potato_instruction(instruction))
+            },
+            None => format!("=> {}: (end of program)", program_counter)
+        }
+    }
+
+    fn format_registers(&self) -> String {
+        let mut registers: Vec<_> = self.cpu.registers.iter().collect();
+        registers.sort_by(|(left, _), (right, _)| left.cmp(right));
+        registers.iter()
+            .map(|(register, value)| {
+                format!("{:>6} = {}", emit_potato_register(register), format_bits(*value))
+            })
+            .collect::<Vec<String>>().join("\n")
+    }
+
+    fn format_stack(&self, start: usize, num_values: usize) -> String {
+        if num_values == 0 {
+            return "(empty)".to_string();
+        }
+        (start..start + num_values)
+            .map(|address| {
+                format!("@{} = {}", address, format_bits(&self.cpu.read_from_stack(address)))
+            })
+            .collect::<Vec<String>>().join("\n")
+    }
+
+    fn format_listing(&self) -> String {
+        let program_counter = self.cpu.read_program_counter();
+        let instructions = self.cpu.get_instructions();
+        let start = program_counter.saturating_sub(LIST_RADIUS);
+        let end = usize::min(program_counter + LIST_RADIUS + 1, instructions.len());
+        (start..end)
+            .map(|index| {
+                let marker = if index == program_counter { "=>" } else { "  " };
+                let breakpoint = if self.cpu.breakpoints.contains(&index) { "*" } else { " " };
+                format!(
+                    "{}{}{:>4}: {}", marker, breakpoint, index,
+                    emit_potato_instruction(&instructions[index])
+                )
+            })
+            .collect::<Vec<String>>().join("\n")
+    }
+}
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() != 2 {
+        eprintln!("Usage: {} <program.c | program.potato>", args[0]);
+        exit(1);
+    }
+    let spec = match Debugger::load_spec(&args[1]) {
+        Ok(spec) => spec,
+        Err(message) => {
+            eprintln!("{}", message);
+            exit(1);
+        }
+    };
+
+    let mut debugger = Debugger::new(&spec);
+    println!("{}", debugger.format_next_instruction());
+    let stdin = io::stdin();
+    loop {
+        print!("(potato) ");
+        io::stdout().flush().unwrap();
+        let mut line = String::new();
+        if stdin.lock().read_line(&mut line).unwrap() == 0 {
+            break;
+        }
+        match debugger.execute_command(&line) {
+            None => break,
+            Some(Ok(output)) if output.is_empty() => {},
+            Some(Ok(output)) => println!("{}", output),
+            Some(Err(message)) => println!("Error: {}", message),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn debugger_from_asm(text: &str) -> Debugger {
+        Debugger::new(&PotatoSpec::new(
+            parse_potato_asm(text).unwrap(), NUM_SCRATCH_REGISTERS, STACK_WIDTH as u16
+        ))
+    }
+    fn execute(debugger: &mut Debugger, command: &str) -> Result<String, String> {
+        debugger.execute_command(command).unwrap()
+    }
+
+    #[test]
+    fn test_debugger_commands() {
+        let mut debugger = debugger_from_asm("
+            jump 2
+            data 0b0101
+            mov_data 1, s0
+            mov_reg_stack s0, 3
+            copy s0, fn_ret
+        ");
+        assert_eq!(
+            execute(&mut debugger, "step").unwrap(),
+            "Paused after 1 steps\n=> 2: mov_data 1, s0"
+        );
+        assert_eq!(execute(&mut debugger, "break 4").unwrap(), "Breakpoint at instruction 4");
+        assert_eq!(execute(&mut debugger, "watch @3").unwrap(), "Watching @3");
+        assert_eq!(
+            execute(&mut debugger, "run 100").unwrap(),
+            "Instruction 3 changed @3 after 3 steps\n=> 4: copy s0, fn_ret"
+        );
+        assert!(execute(&mut debugger, "regs").unwrap().contains("    s0 = 0b0101 (5, 4 bits)"));
+        assert_eq!(
+            execute(&mut debugger, "stack 3").unwrap(),
+            format!("@3 = 0b{}0101 (5, 32 bits)", "0".repeat(28))
+        );
+        assert!(execute(&mut debugger, "list").unwrap().contains("=>*   4: copy s0, fn_ret"));
+        assert_eq!(execute(&mut debugger, "run 100").unwrap(), "Halted after 4 steps");
+    }
+
+    #[test]
+    fn test_debugger_command_errors() {
+        let mut debugger = debugger_from_asm("return");
+        assert!(execute(&mut debugger, "run").is_err());
+        assert!(execute(&mut debugger, "step many").is_err());
+        assert!(execute(&mut debugger, "delete 3").is_err());
+        assert!(execute(&mut debugger, "watch r9").is_err());
+        assert!(execute(&mut debugger, "frobnicate").unwrap_err().starts_with("Unknown command"));
+        assert!(debugger.execute_command("quit").is_none());
+        assert_eq!(execute(&mut debugger, "").unwrap(), "");
+    }
+}
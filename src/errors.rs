@@ -0,0 +1,113 @@
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use crate::asm_gen::asm_symbols::AsmGenError;
+use crate::assembler::AssembleAndLinkError;
+use crate::lexer::lexer::LexerFromFileError;
+use crate::parser::parser_helpers::{ParseError, ParseErrorVariants};
+use crate::semantic::identifier_resolution::SemanticError;
+use crate::tacky::tacky_symbols::TackyError;
+
+/*
+Error returned by the functions that run several stages of the pipeline
+at once (e.g. asm_gen_from_filepath), tagged with the stage that failed.
+Each stage still has its own error type for when it is run on its own
+*/
+
+#[derive(Debug)]
+pub enum CompilerError {
+    LexerError(LexerFromFileError),
+    ParseError(ParseError),
+    SemanticError(SemanticError),
+    TackyError(TackyError),
+    AsmGenError(AsmGenError),
+    AssembleError(AssembleAndLinkError),
+}
+impl Display for CompilerError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CompilerError::LexerError(err) => write!(f, "{}", err),
+            CompilerError::ParseError(err) => write!(f, "{}", err),
+            CompilerError::SemanticError(err) => write!(f, "{}", err),
+            CompilerError::TackyError(err) => write!(f, "{}", err),
+            CompilerError::AsmGenError(err) => write!(f, "{}", err),
+            CompilerError::AssembleError(err) => write!(f, "{}", err),
+        }
+    }
+}
+impl Error for CompilerError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CompilerError::LexerError(err) => Some(err),
+            CompilerError::ParseError(err) => Some(err),
+            CompilerError::SemanticError(err) => Some(err),
+            CompilerError::TackyError(err) => Some(err),
+            CompilerError::AsmGenError(err) => Some(err),
+            CompilerError::AssembleError(err) => Some(err),
+        }
+    }
+}
+impl From<LexerFromFileError> for CompilerError {
+    fn from(err: LexerFromFileError) -> Self {
+        CompilerError::LexerError(err)
+    }
+}
+impl From<ParseError> for CompilerError {
+    fn from(err: ParseError) -> Self {
+        // the parser passes on lexer errors, which belong to an earlier stage
+        match err.variant {
+            ParseErrorVariants::LexerError(lexer_error) => CompilerError::LexerError(lexer_error),
+            ParseErrorVariants::SemanticError(semantic_error) => {
+                CompilerError::SemanticError(semantic_error)
+            },
+            variant => CompilerError::ParseError(ParseError {
+                variant, context: err.context
+            })
+        }
+    }
+}
+impl From<SemanticError> for CompilerError {
+    fn from(err: SemanticError) -> Self {
+        CompilerError::SemanticError(err)
+    }
+}
+impl From<TackyError> for CompilerError {
+    fn from(err: TackyError) -> Self {
+        CompilerError::TackyError(err)
+    }
+}
+impl From<AsmGenError> for CompilerError {
+    fn from(err: AsmGenError) -> Self {
+        CompilerError::AsmGenError(err)
+    }
+}
+impl From<AssembleAndLinkError> for CompilerError {
+    fn from(err: AssembleAndLinkError) -> Self {
+        CompilerError::AssembleError(err)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::asm_gen::asm_symbols::asm_gen_from_filepath;
+
+    #[test]
+    fn test_missing_source_file() {
+        let err = asm_gen_from_filepath("./does_not_exist.c", false).err().unwrap();
+        assert!(matches!(err, CompilerError::LexerError(LexerFromFileError::IoError(_))));
+        assert!(err.source().is_some());
+        assert!(err.to_string().starts_with("LexerFromFileError: I/O error"));
+    }
+
+    #[test]
+    fn test_semantic_error_stage() {
+        let source_path = std::env::temp_dir().join("ca_compiler_semantic_error.c");
+        std::fs::write(&source_path, "int main(void) { return x; }").unwrap();
+        let err = asm_gen_from_filepath(source_path.to_str().unwrap(), false).err().unwrap();
+        std::fs::remove_file(&source_path).unwrap();
+        assert!(matches!(err, CompilerError::SemanticError(_)));
+        assert_eq!(
+            err.to_string(), "SemanticError at line 1:25: Use of undeclared variable x"
+        );
+    }
+}
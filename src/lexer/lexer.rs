@@ -1,4 +1,5 @@
 use std::{fmt};
+use std::collections::HashMap;
 use std::fmt::{Display, Formatter};
 use std::fs::File;
 use std::io::Read;
@@ -7,8 +8,9 @@ use regex::Regex;
 use crate::lexer::base_token_builder::{BaseTokenBuilder};
 use crate::lexer::operators::OperatorsBuilder;
 use crate::lexer::punctuators::PunctuatorsBuilder;
+use crate::preprocessor::Preprocessor;
 pub(crate) use crate::lexer::tokens::{is_word_boundary, Keywords, ProcessResult, TokenBuilder, Tokens};
-pub(crate) use crate::lexer::tokens::{HasLength, SourceContext, WrappedToken};
+pub(crate) use crate::lexer::tokens::{HasLength, SourceContext, SourceLocation, WrappedToken};
 
 struct IdentifierBuilder {
     base: BaseTokenBuilder,
@@ -59,7 +61,14 @@ impl TokenBuilder for IdentifierBuilder {
         if self.is_done() {
             let identifier = self._get_built_str().clone();
             match identifier.as_str() {
+                "char" => Some(Tokens::Keyword(Keywords::Char)),
                 "int" => Some(Tokens::Keyword(Keywords::Integer)),
+                "double" => Some(Tokens::Keyword(Keywords::Double)),
+                "long" => Some(Tokens::Keyword(Keywords::Long)),
+                "signed" => Some(Tokens::Keyword(Keywords::Signed)),
+                "unsigned" => Some(Tokens::Keyword(Keywords::Unsigned)),
+                "struct" => Some(Tokens::Keyword(Keywords::Struct)),
+                "union" => Some(Tokens::Keyword(Keywords::Union)),
                 "void" => Some(Tokens::Keyword(Keywords::Void)),
                 "return" => Some(Tokens::Keyword(Keywords::Return)),
                 _ => Some(Tokens::Identifier(identifier)),
@@ -79,6 +88,23 @@ impl ConstantBuilder {
             base: BaseTokenBuilder::new(),
         }
     }
+    fn is_prefix(candidate: &str) -> bool {
+        // whether more characters could still make candidate a valid constant
+        let prefix_regex = Regex::new(
+            r"^(0[xX][0-9a-fA-F]*|0[bB][01]*|0[0-7]*|[1-9]\d*)([uU][lL]?|[lL][uU]?)?$"
+        ).unwrap();
+        prefix_regex.is_match(candidate)
+    }
+    fn is_complete(candidate: &str) -> bool {
+        /*
+        hexadecimal (0x1F), binary (0b101), octal (017) or decimal
+        digits, followed by an optional integer suffix (u, l, ul, lu)
+        */
+        let complete_regex = Regex::new(
+            r"^(0[xX][0-9a-fA-F]+|0[bB][01]+|0[0-7]*|[1-9]\d*)([uU][lL]?|[lL][uU]?)?$"
+        ).unwrap();
+        complete_regex.is_match(candidate)
+    }
 }
 
 impl Display for ConstantBuilder {
@@ -92,17 +118,16 @@ impl TokenBuilder for ConstantBuilder {
     fn base_mut(&mut self) -> &mut BaseTokenBuilder { &mut self.base }
     fn process_char(&self, c: char) -> ProcessResult {
         // println!("CONST_PROCESS_CHAR {}", c);
-        let length = self.get_length();
+        let built_str = self._get_built_str();
+        let candidate = format!("{built_str}{c}");
 
-        if c.is_digit(10) {
-            return ProcessResult::add_and_continue(true)
-        } else if length == 0 {
-            return ProcessResult::reject()
-        } else if is_word_boundary(c) {
-            return ProcessResult::complete_without_continue(false)
+        if Self::is_prefix(&candidate) {
+            ProcessResult::add_and_continue(Self::is_complete(&candidate))
+        } else if Self::is_complete(built_str) && is_word_boundary(c) {
+            ProcessResult::complete_without_continue(false)
+        } else {
+            ProcessResult::reject()
         }
-        // println!("C4");
-        ProcessResult::reject()
     }
 
     fn build_token(&self) -> Option<Tokens> {
@@ -115,6 +140,156 @@ impl TokenBuilder for ConstantBuilder {
     }
 }
 
+struct FloatingConstantBuilder {
+    base: BaseTokenBuilder,
+}
+impl FloatingConstantBuilder {
+    fn new() -> FloatingConstantBuilder {
+        FloatingConstantBuilder {
+            base: BaseTokenBuilder::new(),
+        }
+    }
+    fn is_prefix(candidate: &str) -> bool {
+        // whether more characters could still make candidate a valid constant
+        let prefix_regex = Regex::new(r"^(\d+\.?\d*|\.\d*)([eE][+-]?\d*)?$").unwrap();
+        prefix_regex.is_match(candidate)
+    }
+    fn is_complete(candidate: &str) -> bool {
+        /*
+        A floating constant needs either a decimal point or an exponent,
+        otherwise it is just an integer constant, e.g. "1.", ".5", "1e10"
+        */
+        let complete_regex = Regex::new(
+            r"^((\d+\.\d*|\.\d+)([eE][+-]?\d+)?|\d+[eE][+-]?\d+)$"
+        ).unwrap();
+        complete_regex.is_match(candidate)
+    }
+}
+impl Display for FloatingConstantBuilder {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "FloatingConstantBuilder")
+    }
+}
+impl TokenBuilder for FloatingConstantBuilder {
+    fn base(&self) -> &BaseTokenBuilder { &self.base }
+    fn base_mut(&mut self) -> &mut BaseTokenBuilder { &mut self.base }
+    fn process_char(&self, c: char) -> ProcessResult {
+        let built_str = self._get_built_str();
+        let candidate = format!("{built_str}{c}");
+
+        if Self::is_prefix(&candidate) {
+            ProcessResult::add_and_continue(Self::is_complete(&candidate))
+        } else if Self::is_complete(built_str) && is_word_boundary(c) && c != '.' {
+            ProcessResult::complete_without_continue(false)
+        } else {
+            ProcessResult::reject()
+        }
+    }
+
+    fn build_token(&self) -> Option<Tokens> {
+        if self.is_done() {
+            let constant = self._get_built_str().clone();
+            Some(Tokens::FloatingConstant(constant))
+        } else {
+            None
+        }
+    }
+}
+
+pub fn decode_char_literal(literal: &str) -> Option<u8> {
+    /*
+    Returns the value of a raw character literal (including quotes),
+    e.g. "'a'" -> 97, "'\\n'" -> 10, "'\\0'" -> 0
+    */
+    let inner = literal.strip_prefix('\'')?.strip_suffix('\'')?;
+    let mut chars = inner.chars();
+    let first_char = chars.next()?;
+
+    if first_char != '\\' {
+        if chars.next().is_some() || !first_char.is_ascii() {
+            return None;
+        }
+        return Some(first_char as u8);
+    }
+
+    let escaped: String = chars.collect();
+    let value = match escaped.as_str() {
+        "'" => b'\'',
+        "\"" => b'"',
+        "?" => b'?',
+        "\\" => b'\\',
+        "a" => 0x07,
+        "b" => 0x08,
+        "f" => 0x0c,
+        "n" => b'\n',
+        "r" => b'\r',
+        "t" => b'\t',
+        "v" => 0x0b,
+        octal if !octal.is_empty() && octal.len() <= 3 &&
+            octal.chars().all(|c| c.is_digit(8)) => {
+            u8::try_from(u32::from_str_radix(octal, 8).ok()?).ok()?
+        },
+        _ => return None
+    };
+    Some(value)
+}
+
+struct CharLiteralBuilder {
+    base: BaseTokenBuilder,
+}
+impl CharLiteralBuilder {
+    fn new() -> CharLiteralBuilder {
+        CharLiteralBuilder {
+            base: BaseTokenBuilder::new(),
+        }
+    }
+}
+impl Display for CharLiteralBuilder {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(f, "CharLiteralBuilder {:?}", self.base)
+    }
+}
+impl TokenBuilder for CharLiteralBuilder {
+    fn base(&self) -> &BaseTokenBuilder { &self.base }
+    fn base_mut(&mut self) -> &mut BaseTokenBuilder { &mut self.base }
+    fn process_char(&self, c: char) -> ProcessResult {
+        let length = self.get_length();
+        let current_str = self._get_built_str();
+
+        if length == 0 {
+            return if c == '\'' {
+                ProcessResult::add_and_continue(false)
+            } else {
+                ProcessResult::reject()
+            }
+        } else if c == '\n' {
+            // character literals can't span multiple lines
+            return ProcessResult::reject()
+        }
+
+        // the literal ends at the first quote that isn't escaped
+        let body = &current_str[1..];
+        let is_escaped = body.ends_with('\\') &&
+            (body.len() - body.trim_end_matches('\\').len()) % 2 == 1;
+        if length > 1 && c == '\'' && !is_escaped {
+            let literal = format!("{current_str}{c}");
+            let is_valid = decode_char_literal(&literal).is_some();
+            ProcessResult::new(is_valid, false, true)
+        } else {
+            ProcessResult::add_and_continue(false)
+        }
+    }
+
+    fn build_token(&self) -> Option<Tokens> {
+        if self.is_done() {
+            let literal = self._get_built_str().clone();
+            Some(Tokens::CharLiteral(literal))
+        } else {
+            None
+        }
+    }
+}
+
 struct SingleLineCommentBuilder {
     base: BaseTokenBuilder,
 }
@@ -252,23 +427,29 @@ pub struct InvalidToken {
     characters: String,
     start_position: usize,
     end_position: usize,
+    location: SourceLocation,
 }
 impl InvalidToken {
     fn new(
-        characters: String, start_position: usize, end_position: usize
+        characters: String, start_position: usize, end_position: usize,
+        location: SourceLocation
     ) -> InvalidToken {
         InvalidToken {
             characters,
             start_position,
             end_position,
+            location,
         }
     }
+    pub fn location(&self) -> SourceLocation {
+        self.location
+    }
 }
 impl fmt::Display for InvalidToken {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
-            f, "InvalidToken('{}' @ {}-{})",
-            self.characters, self.start_position, self.end_position
+            f, "InvalidToken('{}' @ {}-{}, line {})",
+            self.characters, self.start_position, self.end_position, self.location
         )
     }
 }
@@ -284,7 +465,11 @@ impl Lexer {
             Box::new(SingleLineCommentBuilder::new()),
             Box::new(PreprocessorBuilder::new()),
             Box::new(IdentifierBuilder::new()),
+            // has to come before integer constants, which would
+            // otherwise claim the integer part of e.g. "1.5"
+            Box::new(FloatingConstantBuilder::new()),
             Box::new(ConstantBuilder::new()),
+            Box::new(CharLiteralBuilder::new()),
             Box::new(OperatorsBuilder::new()),
             Box::new(PunctuatorsBuilder::new()),
         ]
@@ -296,6 +481,21 @@ impl Lexer {
         padded_input
     }
 
+    fn find_line_markers(source: &str) -> HashMap<usize, usize> {
+        // char index where each line marker starts, mapped to where its line ends
+        let mut marker_ends = HashMap::new();
+        let mut line_start = 0;
+        for line in source.split_inclusive('\n') {
+            let line_end = line_start + line.trim_end_matches('\n').chars().count();
+            if Preprocessor::parse_line_marker(line).is_some() {
+                let indent = line.chars().take_while(|c| c.is_whitespace()).count();
+                marker_ends.insert(line_start + indent, line_end);
+            }
+            line_start += line.chars().count();
+        }
+        marker_ends
+    }
+
     pub fn tokenize(
         &self, raw_source: &str
     ) -> Result<Vec<WrappedToken>, InvalidToken> {
@@ -303,11 +503,18 @@ impl Lexer {
         let mut processing_token: bool = false;
         let source = Lexer::pad_input_str(raw_source);
         let length = source.chars().count();
+        let locations = SourceLocation::locate_chars(&source);
+        let line_marker_ends = Lexer::find_line_markers(&source);
         let mut search_end = 0;
 
         for (k, c) in source.chars().enumerate() {
             if k < search_end { continue }
             if !processing_token && c.is_whitespace() { continue }
+            if let Some(marker_end) = line_marker_ends.get(&k) {
+                // line markers only renumber lines, see SourceLocation::locate_chars
+                search_end = *marker_end;
+                continue
+            }
 
             let mut token_builders = Lexer::make_token_builders();
             let mut searched_string = String::new();
@@ -341,11 +548,13 @@ impl Lexer {
                         format!("Token builder error: {}", error_message).as_str()
                     );
 
-                    println!("MADE TOKEN {}", token);
+                    // kept off stdout, which the driver can stream assembly to
+                    log::debug!("MADE TOKEN {}", token);
                     search_end = search_start + token.get_length();
                     let content = builder._get_built_str().clone();
                     let context = SourceContext::new(
-                        content, search_start, search_end
+                        content, search_start, search_end,
+                        locations[search_start], locations[search_end]
                     );
 
                     let wrapped_token = WrappedToken::new(token.clone(), context);
@@ -360,7 +569,7 @@ impl Lexer {
                 let search_length = searched_string.len();
                 return Err(InvalidToken::new(
                     searched_string, search_start,
-                    search_start + search_length
+                    search_start + search_length, locations[search_start]
                 ));
             }
         }
@@ -392,27 +601,37 @@ impl Display for LexerFromFileError {
         write!(f, "LexerFromFileError: {}", self.message())
     }
 }
+impl std::error::Error for LexerFromFileError {}
 
 
 pub fn lex_from_filepath(
     file_path: &str, verbose: bool
 ) -> Result<Vec<WrappedToken>, LexerFromFileError> {
     let open_result = File::open(file_path);
-    let mut file = match open_result {
+    let file = match open_result {
         Ok(f) => f,
         Err(e) => return Err(LexerFromFileError::IoError(e)),
     };
+    lex_from_reader(file, verbose)
+}
 
+pub fn lex_from_reader(
+    mut reader: impl Read, verbose: bool
+) -> Result<Vec<WrappedToken>, LexerFromFileError> {
     let mut contents = String::new();
-    let read_result = file.read_to_string(&mut contents);
+    let read_result = reader.read_to_string(&mut contents);
     if read_result.is_err() {
         return Err(LexerFromFileError::IoError(read_result.unwrap_err()));
     }
 
-    // Print the file contents
+    // Print the source contents
     if verbose { println!("{}", contents); }
+    lex_from_str(&contents)
+}
+
+pub fn lex_from_str(source: &str) -> Result<Vec<WrappedToken>, LexerFromFileError> {
     let lexer = Lexer::new();
-    let tokens_res = lexer.tokenize(&contents);
+    let tokens_res = lexer.tokenize(source);
     let tokens = match tokens_res {
         Ok(t) => t,
         Err(e) => return Err(LexerFromFileError::InvalidToken(e)),
@@ -423,7 +642,7 @@ pub fn lex_from_filepath(
 #[cfg(test)]
 mod tests {
     use crate::lexer::punctuators::PunctuatorProcessor;
-    use crate::lexer::tokens::Punctuators;
+    use crate::lexer::tokens::{Operators, Punctuators};
     use super::*;
 
     #[test]
@@ -458,4 +677,153 @@ mod tests {
         assert_eq!(builder.is_done(), true);
         assert_eq!(builder.add_char('('), false);
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn test_constant_suffixes() {
+        let lexer = Lexer::new();
+        let tokens = lexer.tokenize("10ul 7L 3u 42").unwrap();
+        let tokens: Vec<Tokens> = tokens.into_iter().map(|t| t.token).collect();
+        assert_eq!(tokens, vec![
+            Tokens::Constant("10ul".to_string()),
+            Tokens::Constant("7L".to_string()),
+            Tokens::Constant("3u".to_string()),
+            Tokens::Constant("42".to_string()),
+        ]);
+        assert!(lexer.tokenize("10uu").is_err());
+    }
+
+    #[test]
+    fn test_char_literals() {
+        let lexer = Lexer::new();
+        let tokens = lexer.tokenize(r"'a' '\n' '\'' '\\' '\0'").unwrap();
+        let values: Vec<Option<u8>> = tokens.into_iter().map(|t| match t.token {
+            Tokens::CharLiteral(literal) => decode_char_literal(&literal),
+            _ => None
+        }).collect();
+        assert_eq!(values, vec![
+            Some(b'a'), Some(b'\n'), Some(b'\''), Some(b'\\'), Some(0)
+        ]);
+        assert!(lexer.tokenize("'ab'").is_err());
+        assert!(lexer.tokenize("''").is_err());
+    }
+
+    #[test]
+    fn test_address_of_operator() {
+        let lexer = Lexer::new();
+        let tokens = lexer.tokenize("&x && *y").unwrap();
+        let tokens: Vec<Tokens> = tokens.into_iter().map(|t| t.token).collect();
+        assert_eq!(tokens, vec![
+            Tokens::Operator(Operators::BitwiseAnd),
+            Tokens::Identifier("x".to_string()),
+            Tokens::Operator(Operators::LogicalAnd),
+            Tokens::Operator(Operators::Multiply),
+            Tokens::Identifier("y".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn test_member_access_tokens() {
+        let lexer = Lexer::new();
+        let tokens = lexer.tokenize("struct s a.b->c-1").unwrap();
+        let tokens: Vec<Tokens> = tokens.into_iter().map(|t| t.token).collect();
+        assert_eq!(tokens, vec![
+            Tokens::Keyword(Keywords::Struct),
+            Tokens::Identifier("s".to_string()),
+            Tokens::Identifier("a".to_string()),
+            Tokens::Punctuator(Punctuators::Dot),
+            Tokens::Identifier("b".to_string()),
+            Tokens::Operator(Operators::Arrow),
+            Tokens::Identifier("c".to_string()),
+            Tokens::Operator(Operators::Subtract),
+            Tokens::Constant("1".to_string()),
+        ]);
+    }
+
+    #[test]
+    fn test_floating_constants() {
+        let lexer = Lexer::new();
+        let tokens = lexer.tokenize("1.5 .25 3. 1e10 2.5E-3 7 s.x").unwrap();
+        let tokens: Vec<Tokens> = tokens.into_iter().map(|t| t.token).collect();
+        assert_eq!(tokens, vec![
+            Tokens::FloatingConstant("1.5".to_string()),
+            Tokens::FloatingConstant(".25".to_string()),
+            Tokens::FloatingConstant("3.".to_string()),
+            Tokens::FloatingConstant("1e10".to_string()),
+            Tokens::FloatingConstant("2.5E-3".to_string()),
+            Tokens::Constant("7".to_string()),
+            Tokens::Identifier("s".to_string()),
+            Tokens::Punctuator(Punctuators::Dot),
+            Tokens::Identifier("x".to_string()),
+        ]);
+        assert!(lexer.tokenize("1e").is_err());
+    }
+
+    #[test]
+    fn test_token_locations() {
+        let lexer = Lexer::new();
+        let tokens = lexer.tokenize("int main(void) {\n    return 42;\n}").unwrap();
+        let return_token = &tokens[6];
+        assert_eq!(return_token.token, Tokens::Keyword(Keywords::Return));
+        assert_eq!(return_token.get_start_location(), SourceLocation::new(2, 5));
+        assert_eq!(return_token.get_end_location(), SourceLocation::new(2, 11));
+        let close_brace = tokens.last().unwrap();
+        assert_eq!(close_brace.get_start_location(), SourceLocation::new(3, 1));
+
+        let invalid_token = lexer.tokenize("int a;\n  a @ 1").unwrap_err();
+        assert_eq!(invalid_token.location(), SourceLocation::new(2, 5));
+    }
+
+    #[test]
+    fn test_bitwise_operators() {
+        let lexer = Lexer::new();
+        let tokens = lexer.tokenize("a | b || c ^ d << 2 >> 1").unwrap();
+        let operators: Vec<Operators> = tokens.into_iter().filter_map(|t| match t.token {
+            Tokens::Operator(op) => Some(op),
+            _ => None
+        }).collect();
+        assert_eq!(operators, vec![
+            Operators::BitwiseOr,
+            Operators::LogicalOr,
+            Operators::BitwiseXor,
+            Operators::LeftShift,
+            Operators::RightShift,
+        ]);
+    }
+
+    #[test]
+    fn test_integer_constant_bases() {
+        let lexer = Lexer::new();
+        let tokens = lexer.tokenize("0x1F 0XffUL 017 0 0b101 0B1l").unwrap();
+        let tokens: Vec<Tokens> = tokens.into_iter().map(|t| t.token).collect();
+        assert_eq!(tokens, vec![
+            Tokens::Constant("0x1F".to_string()),
+            Tokens::Constant("0XffUL".to_string()),
+            Tokens::Constant("017".to_string()),
+            Tokens::Constant("0".to_string()),
+            Tokens::Constant("0b101".to_string()),
+            Tokens::Constant("0B1l".to_string()),
+        ]);
+        assert!(lexer.tokenize("0x").is_err());
+        assert!(lexer.tokenize("0b12").is_err());
+        assert!(lexer.tokenize("09").is_err());
+        assert!(lexer.tokenize("0xfg").is_err());
+    }
+
+    #[test]
+    fn test_lex_from_reader() {
+        let source = "int main(void) { return 2; }";
+        let tokens = lex_from_reader(source.as_bytes(), false).unwrap();
+        assert_eq!(tokens.len(), 10);
+        assert_eq!(tokens[7].token, Tokens::Constant("2".to_string()));
+
+        let invalid_utf8: &[u8] = &[0x69, 0x6e, 0x74, 0xff];
+        assert!(matches!(
+            lex_from_reader(invalid_utf8, false),
+            Err(LexerFromFileError::IoError(_))
+        ));
+        assert!(matches!(
+            lex_from_str("int a @ 1;"),
+            Err(LexerFromFileError::InvalidToken(_))
+        ));
+    }
+}
@@ -93,6 +93,7 @@ impl PunctuatorsBuilder {
                 ("{", Punctuators::OpenBrace),
                 ("}", Punctuators::CloseBrace),
                 (";", Punctuators::Semicolon),
+                (".", Punctuators::Dot),
             ])
         }
     }
@@ -1,7 +1,9 @@
 use std::fmt;
 use std::fmt::{Debug, Display};
 use regex::Regex;
+use serde::{Deserialize, Serialize};
 use crate::lexer::base_token_builder::{BaseTokenBuilder, TokenBuilderStates};
+use crate::preprocessor::Preprocessor;
 use strum::IntoEnumIterator;
 use strum_macros::EnumIter;
 
@@ -16,14 +18,28 @@ pub(crate) trait HasLength {
 
 #[derive(PartialEq, Copy, Clone, Debug, Eq)]
 pub enum Keywords {
+    Char,
     Integer,
+    Double,
+    Long,
+    Signed,
+    Unsigned,
+    Struct,
+    Union,
     Void,
     Return
 }
 impl Keywords {
     fn to_string(&self) -> String {
         match self {
+            Keywords::Char => "char".to_string(),
             Keywords::Integer => "int".to_string(),
+            Keywords::Double => "double".to_string(),
+            Keywords::Long => "long".to_string(),
+            Keywords::Signed => "signed".to_string(),
+            Keywords::Unsigned => "unsigned".to_string(),
+            Keywords::Struct => "struct".to_string(),
+            Keywords::Union => "union".to_string(),
             Keywords::Void => "void".to_string(),
             Keywords::Return => "return".to_string(),
         }
@@ -46,7 +62,9 @@ pub enum Punctuators {
     CloseParens,
     OpenBrace,
     CloseBrace,
-    Semicolon
+    Semicolon,
+    // member access
+    Dot
 }
 impl Punctuators {
     fn to_string(&self) -> String {
@@ -56,6 +74,7 @@ impl Punctuators {
             Punctuators::OpenBrace => "{".parse().unwrap(),
             Punctuators::CloseBrace => "}".parse().unwrap(),
             Punctuators::Semicolon => ";".parse().unwrap(),
+            Punctuators::Dot => ".".parse().unwrap(),
         }
     }
 }
@@ -76,8 +95,12 @@ pub enum Tokens {
     Identifier(String),
     // e.g. "+", "-", "*", "/"
     Operator(Operators),
-    // a bunch of digits, e.g. "1234"
+    // a bunch of digits with an optional suffix, e.g. "1234", "10ul"
     Constant(String),
+    // a decimal floating point constant, e.g. "1.5", ".5", "1e10"
+    FloatingConstant(String),
+    // raw character literal including its quotes, e.g. "'a'", "'\\n'"
+    CharLiteral(String),
     Punctuator(Punctuators),
     Comment(String),
 }
@@ -86,6 +109,8 @@ impl Tokens {
         match self {
             Tokens::Identifier(s) => s.to_string(),
             Tokens::Constant(s) => s.to_string(),
+            Tokens::FloatingConstant(s) => s.to_string(),
+            Tokens::CharLiteral(s) => s.to_string(),
             Tokens::Operator(op) => op.to_string(),
             Tokens::Keyword(k) => k.to_string(),
             Tokens::Punctuator(p) => p.to_string(),
@@ -103,6 +128,8 @@ impl fmt::Display for Tokens {
         match self {
             Tokens::Identifier(s) => write!(f, "Identifier({})", s),
             Tokens::Constant(s) => write!(f, "Constant({})", s),
+            Tokens::FloatingConstant(s) => write!(f, "FloatingConstant({})", s),
+            Tokens::CharLiteral(s) => write!(f, "CharLiteral({})", s),
             Tokens::Operator(op) => write!(f, "Operator({})", op),
             Tokens::Keyword(k) => write!(f, "Keyword({})", k),
             Tokens::Punctuator(p) => write!(f, "Punctuator({})", p),
@@ -111,26 +138,76 @@ impl fmt::Display for Tokens {
     }
 }
 
+#[derive(PartialEq, Copy, Clone, Debug, Eq, Serialize, Deserialize)]
+pub struct SourceLocation {
+    // both are 1-indexed, columns are counted in characters
+    pub line: usize,
+    pub column: usize,
+}
+impl SourceLocation {
+    pub fn new(line: usize, column: usize) -> Self {
+        SourceLocation { line, column }
+    }
+    pub fn start() -> Self {
+        // location of the first character in the source code
+        SourceLocation::new(1, 1)
+    }
+    pub fn locate_chars(source: &str) -> Vec<SourceLocation> {
+        /*
+        Returns the location of every character in the source, followed
+        by the location right after the last character
+        */
+        let mut locations = Vec::with_capacity(source.len() + 1);
+        let mut location = SourceLocation::start();
+        for line in source.split_inclusive('\n') {
+            // line markers left by the preprocessor renumber the lines after them
+            let next_line = Preprocessor::parse_line_marker(line).unwrap_or(location.line + 1);
+            for c in line.chars() {
+                locations.push(location);
+                location = if c == '\n' {
+                    SourceLocation::new(next_line, 1)
+                } else {
+                    SourceLocation::new(location.line, location.column + 1)
+                };
+            }
+        }
+        locations.push(location);
+        locations
+    }
+}
+impl fmt::Display for SourceLocation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
 #[derive(PartialEq, Clone, Debug, Eq)]
 pub struct SourceContext {
     pub source: String,
     pub start_position: usize,
-    pub end_position: usize
+    pub end_position: usize,
+    pub start_location: SourceLocation,
+    pub end_location: SourceLocation,
 }
 impl SourceContext {
-    pub fn new(source: String, start_position: usize, end_position: usize) -> Self {
+    pub fn new(
+        source: String, start_position: usize, end_position: usize,
+        start_location: SourceLocation, end_location: SourceLocation
+    ) -> Self {
         SourceContext {
             source,
             start_position,
-            end_position
+            end_position,
+            start_location,
+            end_location
         }
     }
 }
 impl fmt::Display for SourceContext {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
-            f, "SourceContext('{}' @ {}-{})",
-            self.source, self.start_position, self.end_position
+            f, "SourceContext('{}' @ {}-{}, line {})",
+            self.source, self.start_position, self.end_position, self.start_location
         )
     }
 }
@@ -152,6 +229,14 @@ impl WrappedToken {
         // returns the minimum position of the token
         self.context.start_position
     }
+    pub fn get_start_location(&self) -> SourceLocation {
+        // line and column of the first character of the token
+        self.context.start_location
+    }
+    pub fn get_end_location(&self) -> SourceLocation {
+        // line and column right after the last character of the token
+        self.context.end_location
+    }
 }
 impl fmt::Display for WrappedToken {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -282,12 +367,17 @@ pub trait TokenBuilder: Display {
 pub enum Operators {
     Decrement,
     BitwiseNot,
+    // member access through a pointer
+    Arrow,
 
     Add,
     Subtract,
     Multiply,
     Divide,
     Modulo,
+    // also the address-of operator when used as a unary operator
+    BitwiseAnd,
+    BitwiseOr,
     BitwiseXor,
     LeftShift,
     RightShift,
@@ -308,12 +398,15 @@ impl Operators {
         match self {
             Operators::BitwiseNot => "~".to_string(),
             Operators::Decrement => "--".to_string(),
+            Operators::Arrow => "->".to_string(),
 
             Operators::Add => "+".to_string(),
             Operators::Subtract => "-".to_string(),
             Operators::Multiply => "*".to_string(),
             Operators::Divide => "/".to_string(),
             Operators::Modulo => "%".to_string(),
+            Operators::BitwiseAnd => "&".to_string(),
+            Operators::BitwiseOr => "|".to_string(),
             Operators::BitwiseXor => "^".to_string(),
             Operators::LeftShift => "<<".to_string(),
             Operators::RightShift => ">>".to_string(),
@@ -1,23 +1,74 @@
 use pyo3::prelude::*;
 
-use crate::potato_cpu::py_potato_cpu_tester::PyPotatoCPUTester;
+use crate::asm_gen::py_asm_gen::compile_to_asm;
+use crate::automata::py_automata::alu_tape_history;
+use crate::parser::py_ast::PyAst;
+use crate::potato_cpu::py_cancellation::PyCancellationToken;
+use crate::potato_cpu::py_potato_cpu::PyPotatoCPU;
+use crate::potato_cpu::py_potato_cpu_tester::{
+    compile_and_run, PyPotatoCPUTester, PyTrace, PyTraceEntry
+};
+use crate::py_errors::{
+    CaCodegenError, CaError, CaExecutionError, CaLexError, CaParseError
+};
+use crate::tacky::py_tacky::PyTacky;
 
 pub mod lexer;
 pub mod potato_cpu;
 pub mod parser;
+pub mod semantic;
+pub mod optimizer;
 pub mod tacky;
 pub mod asm_gen;
+pub mod riscv_gen;
+pub mod assembler;
+pub mod errors;
+pub mod py_errors;
+pub mod preprocessor;
 pub mod automata;
 
-/// Formats the sum of two numbers as string.
-#[pyfunction]
-fn sum_as_string(a: usize, b: usize) -> PyResult<String> {
-    Ok((a + b).to_string())
+fn add_submodule(
+    parent: &Bound<'_, PyModule>, name: &str,
+    populate: impl FnOnce(&Bound<'_, PyModule>) -> PyResult<()>
+) -> PyResult<()> {
+    /*
+    Registering the submodule in sys.modules as well lets it be imported
+    directly, e.g. from py_ca_compiler.potato import PyPotatoCPU
+    */
+    let py = parent.py();
+    let submodule = PyModule::new(py, name)?;
+    populate(&submodule)?;
+    parent.add_submodule(&submodule)?;
+    py.import("sys")?.getattr("modules")?
+        .set_item(format!("py_ca_compiler.{}", name), &submodule)?;
+    Ok(())
 }
 
 #[pymodule]
 fn py_ca_compiler(module: &Bound<'_, PyModule>) -> PyResult<()> {
-    module.add_function(wrap_pyfunction!(sum_as_string, module)?)?;
-    module.add_class::<PyPotatoCPUTester>()?;
+    add_submodule(module, "compiler", |compiler| {
+        compiler.add_class::<PyAst>()?;
+        compiler.add_class::<PyTacky>()?;
+        compiler.add_function(wrap_pyfunction!(compile_to_asm, compiler)?)?;
+        compiler.add_function(wrap_pyfunction!(compile_and_run, compiler)?)
+    })?;
+    add_submodule(module, "potato", |potato| {
+        potato.add_class::<PyPotatoCPU>()?;
+        potato.add_class::<PyCancellationToken>()?;
+        potato.add_class::<PyPotatoCPUTester>()?;
+        potato.add_class::<PyTrace>()?;
+        potato.add_class::<PyTraceEntry>()
+    })?;
+    add_submodule(module, "errors", |errors| {
+        let py = errors.py();
+        errors.add("CaError", py.get_type::<CaError>())?;
+        errors.add("CaLexError", py.get_type::<CaLexError>())?;
+        errors.add("CaParseError", py.get_type::<CaParseError>())?;
+        errors.add("CaCodegenError", py.get_type::<CaCodegenError>())?;
+        errors.add("CaExecutionError", py.get_type::<CaExecutionError>())
+    })?;
+    add_submodule(module, "automata", |automata| {
+        automata.add_function(wrap_pyfunction!(alu_tape_history, automata)?)
+    })?;
     Ok(())
 }
@@ -1,5 +1,4 @@
 use std::env;
-use std::fmt::Debug;
 use std::io::{self};
 use std::path::Path;
 use crate::generator::compile_from_filepath;
@@ -8,10 +7,17 @@ use crate::tacky::tacky_symbols::PrintableTacky;
 
 pub mod lexer;
 pub mod parser;
+pub mod semantic;
+pub mod optimizer;
 mod generator;
 pub mod tacky;
 pub mod asm_gen;
+pub mod riscv_gen;
 pub mod potato_cpu;
+pub mod assembler;
+pub mod errors;
+pub mod preprocessor;
+pub mod py_errors;
 
 fn print_usage(args: &Vec<String>) {
     eprintln!("Unknown / invalid args: {:?}", args);
@@ -20,19 +26,6 @@ fn print_usage(args: &Vec<String>) {
     eprintln!("Usage: {} --codegen <file_path>", args[0]);
 }
 
-pub enum AssembleAndLinkError {
-    IoError(std::io::Error),
-    GccError(String),
-}
-impl Debug for AssembleAndLinkError {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        match self {
-            AssembleAndLinkError::IoError(err) => write!(f, "IO Error: {}", err),
-            AssembleAndLinkError::GccError(msg) => write!(f, "GCC Error: {}", msg),
-        }
-    }
-}
-
 fn main() -> io::Result<()> {
     // Collect command line arguments
     let args: Vec<String> = env::args().collect();
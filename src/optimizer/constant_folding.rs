@@ -0,0 +1,233 @@
+use crate::parser::c_types::CType;
+use crate::parser::parse::{ASTConstant, SupportedBinaryOperators, SupportedUnaryOperators};
+use crate::tacky::tacky_symbols::{
+    BinaryInstruction, CopyInstruction, TackyFunction, TackyInstruction,
+    TackyValue, ToTackyInstruction, UnaryInstruction
+};
+
+/*
+Evaluates unary and binary instructions whose operands are all constants
+at compile time, replacing them with a copy of the folded constant.
+Operations with undefined behaviour at runtime (division by zero,
+out of range shifts) are left as they are
+*/
+
+fn to_signed_value(constant: &ASTConstant) -> i128 {
+    // value of an integer constant, sign extended according to its type
+    let magnitude = constant.value.parse::<u128>().unwrap_or(u128::MAX);
+    constant.const_type.sign_extend_value(magnitude) as i128
+}
+
+fn from_signed_value(value: i128, const_type: &CType) -> ASTConstant {
+    let wrapped_value = const_type.wrap_value(value as u128);
+    ASTConstant::new_typed(&wrapped_value.to_string(), const_type.clone())
+}
+
+fn from_bool(value: bool) -> ASTConstant {
+    ASTConstant::new_typed(if value { "1" } else { "0" }, CType::Int)
+}
+
+fn is_zero(constant: &ASTConstant) -> bool {
+    if constant.const_type.is_double() {
+        constant.to_f64().unwrap() == 0.0
+    } else {
+        to_signed_value(constant) == 0
+    }
+}
+
+fn fold_double_binary(
+    operator: &SupportedBinaryOperators, left: f64, right: f64
+) -> Option<ASTConstant> {
+    let double_result = |value: f64| {
+        Some(ASTConstant::new_typed(&format!("{:?}", value), CType::Double))
+    };
+    match operator {
+        SupportedBinaryOperators::Add => double_result(left + right),
+        SupportedBinaryOperators::Subtract => double_result(left - right),
+        SupportedBinaryOperators::Multiply => double_result(left * right),
+        SupportedBinaryOperators::Divide => double_result(left / right),
+        SupportedBinaryOperators::CheckEqual => Some(from_bool(left == right)),
+        SupportedBinaryOperators::NotEqual => Some(from_bool(left != right)),
+        SupportedBinaryOperators::LessThan => Some(from_bool(left < right)),
+        SupportedBinaryOperators::LessOrEqual => Some(from_bool(left <= right)),
+        SupportedBinaryOperators::GreaterThan => Some(from_bool(left > right)),
+        SupportedBinaryOperators::GreaterOrEqual => Some(from_bool(left >= right)),
+        _ => None
+    }
+}
+
+fn fold_integer_binary(
+    operator: &SupportedBinaryOperators, left: &ASTConstant, right: &ASTConstant
+) -> Option<ASTConstant> {
+    /*
+    signed values are sign extended and unsigned values zero extended
+    to 128 bits, so that the arithmetic below matches the semantics
+    of the constant's type once the result is wrapped back to it
+    */
+    let result_type = &left.const_type;
+    let num_bits = (result_type.size() * 8) as i128;
+    let left_value = to_signed_value(left);
+    let right_value = to_signed_value(right);
+
+    let value = match operator {
+        SupportedBinaryOperators::Add => left_value.wrapping_add(right_value),
+        SupportedBinaryOperators::Subtract => left_value.wrapping_sub(right_value),
+        SupportedBinaryOperators::Multiply => left_value.wrapping_mul(right_value),
+        SupportedBinaryOperators::Divide if right_value != 0 => left_value / right_value,
+        SupportedBinaryOperators::Modulo if right_value != 0 => left_value % right_value,
+        SupportedBinaryOperators::BitwiseAnd => left_value & right_value,
+        SupportedBinaryOperators::BitwiseOr => left_value | right_value,
+        SupportedBinaryOperators::BitwiseXor => left_value ^ right_value,
+        SupportedBinaryOperators::LeftShift if (0..num_bits).contains(&right_value) => {
+            left_value << right_value
+        },
+        SupportedBinaryOperators::RightShift if (0..num_bits).contains(&right_value) => {
+            left_value >> right_value
+        },
+        SupportedBinaryOperators::CheckEqual => return Some(from_bool(left_value == right_value)),
+        SupportedBinaryOperators::NotEqual => return Some(from_bool(left_value != right_value)),
+        SupportedBinaryOperators::LessThan => return Some(from_bool(left_value < right_value)),
+        SupportedBinaryOperators::LessOrEqual => {
+            return Some(from_bool(left_value <= right_value))
+        },
+        SupportedBinaryOperators::GreaterThan => {
+            return Some(from_bool(left_value > right_value))
+        },
+        SupportedBinaryOperators::GreaterOrEqual => {
+            return Some(from_bool(left_value >= right_value))
+        },
+        _ => return None
+    };
+    Some(from_signed_value(value, result_type))
+}
+
+pub fn fold_binary(
+    operator: &SupportedBinaryOperators, left: &ASTConstant, right: &ASTConstant
+) -> Option<ASTConstant> {
+    if left.const_type.is_double() {
+        fold_double_binary(operator, left.to_f64().ok()?, right.to_f64().ok()?)
+    } else {
+        fold_integer_binary(operator, left, right)
+    }
+}
+
+pub fn fold_unary(
+    operator: &SupportedUnaryOperators, src: &ASTConstant
+) -> Option<ASTConstant> {
+    match operator {
+        SupportedUnaryOperators::Subtract => Some(src.negated()),
+        SupportedUnaryOperators::Not => Some(from_bool(is_zero(src))),
+        SupportedUnaryOperators::BitwiseNot if !src.const_type.is_double() => {
+            Some(from_signed_value(!to_signed_value(src), &src.const_type))
+        },
+        _ => None
+    }
+}
+
+fn fold_instruction(instruction: TackyInstruction) -> TackyInstruction {
+    let (folded, dst) = match &instruction {
+        TackyInstruction::UnaryInstruction(UnaryInstruction {
+            operator, src: TackyValue::Constant(src), dst, ..
+        }) => (fold_unary(operator, src), dst),
+        TackyInstruction::BinaryInstruction(BinaryInstruction {
+            operator, left: TackyValue::Constant(left),
+            right: TackyValue::Constant(right), dst, ..
+        }) => (fold_binary(operator, left, right), dst),
+        _ => return instruction
+    };
+
+    match folded {
+        Some(constant) => {
+            let constant = if constant.const_type == dst.var_type {
+                constant
+            } else {
+                constant.converted_to(&dst.var_type)
+            };
+            CopyInstruction::new(
+                TackyValue::Constant(constant), dst.clone()
+            ).to_tacky_instruction()
+        },
+        None => instruction
+    }
+}
+
+pub fn fold_constants(function: TackyFunction) -> TackyFunction {
+    let mut function = function;
+    function.instructions = function.instructions.into_iter().map(fold_instruction).collect();
+    function
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tacky::tacky_text::{emit_tacky_text, parse_tacky_text};
+
+    fn fold_tacky_text(text: &str) -> String {
+        let mut program = parse_tacky_text(text).unwrap();
+        program.function = fold_constants(program.function);
+        emit_tacky_text(&program)
+    }
+
+    #[test]
+    fn test_fold_integer_constants() {
+        let folded = fold_tacky_text("
+            function main {
+                var %1: int
+                var %2: unsigned int
+                var %3: long
+                %1 = add (int) 2147483647, (int) 1
+                %1 = div (int) 4294967289, (int) 2
+                %2 = shr (unsigned int) 4294967295, (unsigned int) 28
+                %1 = lt (unsigned int) 4294967295, (unsigned int) 1
+                %1 = lt (int) 4294967295, (int) 1
+                %3 = mul (long) 3, (long) 18446744073709551615
+                %1 = complement (int) 0
+                %1 = not (long) 0
+                return %1
+            }
+        ");
+        let expected = [
+            "%1 = copy (int) 2147483648",
+            "%1 = copy (int) 4294967293",
+            "%2 = copy (unsigned int) 15",
+            "%1 = copy (int) 0",
+            "%1 = copy (int) 1",
+            "%3 = copy (long) 18446744073709551613",
+            "%1 = copy (int) 4294967295",
+            "%1 = copy (int) 1",
+        ];
+        for line in expected {
+            assert!(folded.contains(line), "missing {} in\n{}", line, folded);
+        }
+    }
+
+    #[test]
+    fn test_fold_double_constants() {
+        let folded = fold_tacky_text("
+            function main {
+                var %1: double
+                var %2: int
+                %1 = mul (double) 1.5, (double) 4.0
+                %1 = neg (double) 2.5
+                %2 = ge (double) 1.5, (double) 4.0
+                return %2
+            }
+        ");
+        assert!(folded.contains("%1 = copy (double) 6.0\n"));
+        assert!(folded.contains("%1 = copy (double) -2.5\n"));
+        assert!(folded.contains("%2 = copy (int) 0\n"));
+    }
+
+    #[test]
+    fn test_skip_undefined_operations() {
+        let text = "function main {
+    var %1: int
+    %1 = div (int) 1, (int) 0
+    %1 = shl (int) 1, (int) 40
+    %1 = add %1, (int) 1
+    return %1
+}
+";
+        assert_eq!(fold_tacky_text(text), text);
+    }
+}
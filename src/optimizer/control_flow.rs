@@ -0,0 +1,240 @@
+use std::collections::{HashMap, HashSet};
+use crate::tacky::tacky_symbols::TackyInstruction;
+
+/*
+Splits a function's instructions into basic blocks (straight line code
+that is only entered at the top and left at the bottom) and links
+each block to the blocks that control can flow to from it
+*/
+
+#[derive(Clone, Debug)]
+pub struct BasicBlock {
+    pub instructions: Vec<TackyInstruction>,
+    // indexes of the blocks that control can flow to after this block
+    pub successors: Vec<usize>,
+}
+
+#[derive(Clone, Debug)]
+pub struct NaturalLoop {
+    // the block that every iteration of the loop starts from
+    pub header: usize,
+    // indexes of the blocks in the loop, including the header
+    pub blocks: HashSet<usize>,
+}
+
+#[derive(Clone, Debug)]
+pub struct ControlFlowGraph {
+    pub blocks: Vec<BasicBlock>,
+}
+impl ControlFlowGraph {
+    pub fn from_instructions(instructions: Vec<TackyInstruction>) -> ControlFlowGraph {
+        let mut block_instructions: Vec<Vec<TackyInstruction>> = vec![];
+        let mut current_block: Vec<TackyInstruction> = vec![];
+        for instruction in instructions {
+            match instruction {
+                TackyInstruction::LabelInstruction(_) => {
+                    // labels can be jumped to, so they always start a new block
+                    if !current_block.is_empty() {
+                        block_instructions.push(current_block);
+                    }
+                    current_block = vec![instruction];
+                },
+                TackyInstruction::JumpInstruction(_) |
+                TackyInstruction::JumpIfZeroInstruction(_) |
+                TackyInstruction::JumpIfNotZeroInstruction(_) |
+                TackyInstruction::Return(_) => {
+                    current_block.push(instruction);
+                    block_instructions.push(current_block);
+                    current_block = vec![];
+                },
+                _ => current_block.push(instruction)
+            }
+        }
+        if !current_block.is_empty() {
+            block_instructions.push(current_block);
+        }
+
+        let mut label_blocks: HashMap<String, usize> = HashMap::new();
+        for (index, instructions) in block_instructions.iter().enumerate() {
+            if let Some(TackyInstruction::LabelInstruction(label)) = instructions.first() {
+                label_blocks.insert(label.label.name.clone(), index);
+            }
+        }
+
+        let num_blocks = block_instructions.len();
+        let blocks = block_instructions.into_iter().enumerate().map(|(index, instructions)| {
+            let fallthrough: Vec<usize> = if index + 1 < num_blocks {
+                vec![index + 1]
+            } else {
+                vec![]
+            };
+            let jump_target = |name: &String| match label_blocks.get(name) {
+                Some(target) => *target,
+                None => panic!("Jump to undefined label {}", name)
+            };
+            let successors = match instructions.last() {
+                Some(TackyInstruction::JumpInstruction(jump)) => {
+                    vec![jump_target(&jump.target.name)]
+                },
+                Some(TackyInstruction::JumpIfZeroInstruction(jump)) => {
+                    let mut successors = vec![jump_target(&jump.target.name)];
+                    successors.extend(fallthrough);
+                    successors
+                },
+                Some(TackyInstruction::JumpIfNotZeroInstruction(jump)) => {
+                    let mut successors = vec![jump_target(&jump.target.name)];
+                    successors.extend(fallthrough);
+                    successors
+                },
+                Some(TackyInstruction::Return(_)) => vec![],
+                _ => fallthrough
+            };
+            BasicBlock { instructions, successors }
+        }).collect();
+
+        ControlFlowGraph { blocks }
+    }
+
+    pub fn predecessors(&self) -> Vec<Vec<usize>> {
+        let mut predecessors: Vec<Vec<usize>> = vec![vec![]; self.blocks.len()];
+        for (index, block) in self.blocks.iter().enumerate() {
+            for successor in &block.successors {
+                if !predecessors[*successor].contains(&index) {
+                    predecessors[*successor].push(index);
+                }
+            }
+        }
+        predecessors
+    }
+
+    pub fn reachable_blocks(&self) -> HashSet<usize> {
+        // blocks that can be reached from the entry block
+        let mut reachable: HashSet<usize> = HashSet::new();
+        let mut pending: Vec<usize> = if self.blocks.is_empty() { vec![] } else { vec![0] };
+        while let Some(index) = pending.pop() {
+            if reachable.insert(index) {
+                pending.extend(self.blocks[index].successors.iter());
+            }
+        }
+        reachable
+    }
+
+    pub fn dominators(&self) -> Vec<HashSet<usize>> {
+        /*
+        dominators[b] holds the blocks that lie on every path from the
+        entry block to b (including b itself). Unreachable blocks are
+        left dominated by every block
+        */
+        let num_blocks = self.blocks.len();
+        let all_blocks: HashSet<usize> = (0..num_blocks).collect();
+        let reachable = self.reachable_blocks();
+        let predecessors = self.predecessors();
+        let mut dominators: Vec<HashSet<usize>> = vec![all_blocks; num_blocks];
+        if num_blocks == 0 {
+            return dominators;
+        }
+        dominators[0] = HashSet::from([0]);
+
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for index in 1..num_blocks {
+                if !reachable.contains(&index) { continue; }
+                let mut block_dominators: Option<HashSet<usize>> = None;
+                for predecessor in predecessors[index].iter().filter(
+                    |predecessor| reachable.contains(predecessor)
+                ) {
+                    block_dominators = Some(match block_dominators {
+                        None => dominators[*predecessor].clone(),
+                        Some(current) => current.intersection(
+                            &dominators[*predecessor]
+                        ).cloned().collect()
+                    });
+                }
+                let mut block_dominators = block_dominators.unwrap_or_default();
+                block_dominators.insert(index);
+                if block_dominators != dominators[index] {
+                    dominators[index] = block_dominators;
+                    changed = true;
+                }
+            }
+        }
+        dominators
+    }
+
+    pub fn natural_loops(&self) -> Vec<NaturalLoop> {
+        /*
+        every back edge (an edge to a block that dominates its source)
+        forms a loop made of the header and all the blocks that can
+        reach the source of the edge without going through the header.
+        Loops sharing a header are merged
+        */
+        let dominators = self.dominators();
+        let reachable = self.reachable_blocks();
+        let predecessors = self.predecessors();
+        let mut loops: Vec<NaturalLoop> = vec![];
+
+        for (index, block) in self.blocks.iter().enumerate() {
+            if !reachable.contains(&index) { continue; }
+            for header in &block.successors {
+                if !dominators[index].contains(header) { continue; }
+                let mut body: HashSet<usize> = HashSet::from([*header]);
+                let mut pending: Vec<usize> = vec![index];
+                while let Some(body_block) = pending.pop() {
+                    if body.insert(body_block) {
+                        pending.extend(predecessors[body_block].iter());
+                    }
+                }
+
+                match loops.iter_mut().find(|natural_loop| natural_loop.header == *header) {
+                    Some(natural_loop) => natural_loop.blocks.extend(body),
+                    None => loops.push(NaturalLoop { header: *header, blocks: body })
+                }
+            }
+        }
+        loops
+    }
+
+    pub fn to_instructions(self) -> Vec<TackyInstruction> {
+        self.blocks.into_iter().flat_map(|block| block.instructions).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tacky::tacky_text::parse_tacky_text;
+
+    #[test]
+    fn test_build_basic_blocks() {
+        let program = parse_tacky_text("
+            function main {
+                var %1: int
+                %1 = copy (int) 3
+                label loop
+                %1 = sub %1, (int) 1
+                jump_if_zero %1, end
+                jump loop
+                label end
+                return %1
+            }
+        ").unwrap();
+        let num_instructions = program.function.instructions.len();
+        let graph = ControlFlowGraph::from_instructions(program.function.instructions);
+
+        let successors: Vec<Vec<usize>> = graph.blocks.iter().map(
+            |block| block.successors.clone()
+        ).collect();
+        assert_eq!(successors, vec![vec![1], vec![3, 2], vec![1], vec![]]);
+        assert_eq!(graph.predecessors(), vec![vec![], vec![0, 2], vec![1], vec![1]]);
+        let dominators = graph.dominators();
+        assert_eq!(dominators[2], HashSet::from([0, 1, 2]));
+        assert_eq!(dominators[3], HashSet::from([0, 1, 3]));
+        let loops = graph.natural_loops();
+        assert_eq!(loops.len(), 1);
+        assert_eq!(loops[0].header, 1);
+        assert_eq!(loops[0].blocks, HashSet::from([1, 2]));
+
+        assert_eq!(graph.to_instructions().len(), num_instructions);
+    }
+}
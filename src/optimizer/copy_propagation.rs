@@ -0,0 +1,257 @@
+use std::collections::HashSet;
+use crate::optimizer::control_flow::ControlFlowGraph;
+use crate::tacky::tacky_symbols::{TackyFunction, TackyInstruction, TackyValue, TackyVariable};
+
+/*
+Reaching copies analysis: after dst = copy src, later reads of dst are
+replaced with src for as long as neither of them is written to again
+on every path leading to the read. Static variables and variables whose
+address is taken can be modified through pointers, so copies to or
+from them are never propagated
+*/
+
+#[derive(Clone, Debug)]
+struct CopyFact {
+    dst: TackyVariable,
+    src: TackyValue,
+}
+impl CopyFact {
+    fn is_same(&self, other: &CopyFact) -> bool {
+        self.dst == other.dst && values_match(&self.src, &other.src)
+    }
+    fn involves(&self, variable: &TackyVariable) -> bool {
+        self.dst == *variable || self.src.get_id() == Some(variable.id)
+    }
+}
+
+fn values_match(left: &TackyValue, right: &TackyValue) -> bool {
+    match (left, right) {
+        (TackyValue::Var(left), TackyValue::Var(right)) => left == right,
+        (TackyValue::Constant(left), TackyValue::Constant(right)) => {
+            left.const_type == right.const_type && left.value == right.value
+        },
+        _ => false
+    }
+}
+
+type CopySet = Vec<CopyFact>;
+
+fn intersect(left: &CopySet, right: &CopySet) -> CopySet {
+    left.iter().filter(
+        |fact| right.iter().any(|other| fact.is_same(other))
+    ).cloned().collect()
+}
+
+struct CopyPropagation {
+    aliased_variables: HashSet<u64>,
+}
+impl CopyPropagation {
+    fn new(instructions: &[TackyInstruction]) -> CopyPropagation {
+        let aliased_variables = instructions.iter().filter_map(|instruction| {
+            match instruction {
+                TackyInstruction::GetAddressInstruction(get_address) => {
+                    Some(get_address.src.id)
+                },
+                _ => None
+            }
+        }).collect();
+        CopyPropagation { aliased_variables }
+    }
+
+    fn is_tracked(&self, variable: &TackyVariable) -> bool {
+        !variable.is_static && !self.aliased_variables.contains(&variable.id)
+    }
+
+    fn copy_fact(&self, instruction: &TackyInstruction) -> Option<CopyFact> {
+        // copies that can be propagated, i.e. ones that don't change the value's type
+        let TackyInstruction::CopyInstruction(copy) = instruction else {
+            return None;
+        };
+        if !self.is_tracked(&copy.dst) || copy.src.get_type() != copy.dst.var_type {
+            return None;
+        }
+        if let TackyValue::Var(src) = &copy.src {
+            if !self.is_tracked(src) {
+                return None;
+            }
+        }
+        Some(CopyFact { dst: copy.dst.clone(), src: copy.src.clone() })
+    }
+
+    fn transfer(&self, instruction: &TackyInstruction, copies: &mut CopySet) {
+        // updates the reaching copies from before the instruction to after it
+        if let Some(dst) = instruction.get_dst() {
+            copies.retain(|fact| !fact.involves(dst));
+        }
+        if let Some(fact) = self.copy_fact(instruction) {
+            copies.push(fact);
+        }
+    }
+
+    fn reaching_copies(&self, graph: &ControlFlowGraph) -> Vec<CopySet> {
+        /*
+        copies reaching the start of each block, iterated to a fixed point.
+        None stands for "every copy", which is what blocks start
+        out as before anything flows into them
+        */
+        let num_blocks = graph.blocks.len();
+        let predecessors = graph.predecessors();
+        let mut block_out: Vec<Option<CopySet>> = vec![None; num_blocks];
+        let mut block_in: Vec<CopySet> = vec![vec![]; num_blocks];
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (index, block) in graph.blocks.iter().enumerate() {
+                let mut copies: Option<CopySet> = None;
+                if index == 0 {
+                    copies = Some(vec![]);
+                }
+                for predecessor in &predecessors[index] {
+                    copies = match (copies, &block_out[*predecessor]) {
+                        (None, predecessor_out) => predecessor_out.clone(),
+                        (Some(copies), Some(predecessor_out)) => {
+                            Some(intersect(&copies, predecessor_out))
+                        },
+                        (copies, None) => copies
+                    };
+                }
+                let mut copies = copies.unwrap_or_default();
+                block_in[index] = copies.clone();
+                for instruction in &block.instructions {
+                    self.transfer(instruction, &mut copies);
+                }
+
+                let is_unchanged = match &block_out[index] {
+                    Some(previous) => {
+                        previous.len() == copies.len() &&
+                        previous.iter().all(|fact| copies.iter().any(|other| fact.is_same(other)))
+                    },
+                    None => false
+                };
+                if !is_unchanged {
+                    block_out[index] = Some(copies);
+                    changed = true;
+                }
+            }
+        }
+        block_in
+    }
+
+    fn rewrite(&self, instruction: &mut TackyInstruction, copies: &CopySet) {
+        for value in instruction.get_src_values_mut() {
+            let TackyValue::Var(variable) = value else { continue };
+            if let Some(fact) = copies.iter().find(|fact| fact.dst == *variable) {
+                *value = fact.src.clone();
+            }
+        }
+    }
+}
+
+fn is_redundant_copy(instruction: &TackyInstruction, copies: &CopySet) -> bool {
+    // x = copy x, or a copy whose effect has already been made
+    let TackyInstruction::CopyInstruction(copy) = instruction else {
+        return false;
+    };
+    if copy.src.get_id() == Some(copy.dst.id) {
+        return true;
+    }
+    copies.iter().any(|fact| {
+        let is_repeated = fact.dst == copy.dst && values_match(&fact.src, &copy.src);
+        let is_reversed = copy.src.get_id() == Some(fact.dst.id) &&
+            fact.src.get_id() == Some(copy.dst.id);
+        is_repeated || is_reversed
+    })
+}
+
+pub fn propagate_copies(function: TackyFunction) -> TackyFunction {
+    let mut function = function;
+    let propagation = CopyPropagation::new(&function.instructions);
+    let mut graph = ControlFlowGraph::from_instructions(function.instructions);
+    let block_in = propagation.reaching_copies(&graph);
+
+    for (block, copies) in graph.blocks.iter_mut().zip(block_in) {
+        let mut copies = copies;
+        let mut rewritten_instructions: Vec<TackyInstruction> = vec![];
+        for mut instruction in block.instructions.drain(..) {
+            propagation.rewrite(&mut instruction, &copies);
+            if is_redundant_copy(&instruction, &copies) {
+                continue;
+            }
+            propagation.transfer(&instruction, &mut copies);
+            rewritten_instructions.push(instruction);
+        }
+        block.instructions = rewritten_instructions;
+    }
+
+    function.instructions = graph.to_instructions();
+    function
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tacky::tacky_text::{emit_tacky_text, parse_tacky_text};
+
+    fn propagate_tacky_text(text: &str) -> Vec<String> {
+        let mut program = parse_tacky_text(text).unwrap();
+        program.function = propagate_copies(program.function);
+        let text = emit_tacky_text(&program);
+        text.lines().map(|line| line.trim().to_string()).filter(
+            |line| !line.starts_with("var ") && !line.starts_with("function") && line != "}"
+        ).collect()
+    }
+
+    #[test]
+    fn test_propagate_copy_chain() {
+        let instructions = propagate_tacky_text("
+            function main {
+                var %1: int
+                var %2: int
+                var %3: int
+                var %4: long
+                %1 = copy (int) 7
+                %2 = copy %1
+                %3 = add %2, %1
+                %4 = sign_extend %2
+                %2 = copy %1
+                return %3
+            }
+        ");
+        assert_eq!(instructions, vec![
+            "%1 = copy (int) 7", "%2 = copy (int) 7",
+            "%3 = add (int) 7, (int) 7", "%4 = sign_extend (int) 7", "return %3"
+        ]);
+    }
+
+    #[test]
+    fn test_stop_at_redefinitions_and_joins() {
+        let instructions = propagate_tacky_text("
+            static @total: int
+            function main {
+                var %1: int
+                var %2: int
+                var %3: int *
+                var %4: int
+                %1 = copy (int) 1
+                %2 = copy %1
+                %4 = copy (int) 3
+                %3 = address_of %4
+                @total = copy (int) 5
+                jump_if_zero @total, skip
+                %1 = copy (int) 2
+                label skip
+                %2 = add %2, %1
+                %2 = add %2, %4
+                return @total
+            }
+        ");
+        // %2 = copy %1 is killed by %1 = copy (int) 2 on one of the paths
+        assert_eq!(instructions, vec![
+            "static @total: int",
+            "%1 = copy (int) 1", "%2 = copy (int) 1", "%4 = copy (int) 3",
+            "%3 = address_of %4", "@total = copy (int) 5", "jump_if_zero @total, skip",
+            "%1 = copy (int) 2", "label skip", "%2 = add %2, %1",
+            "%2 = add %2, %4", "return @total"
+        ]);
+    }
+}
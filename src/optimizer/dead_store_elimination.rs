@@ -0,0 +1,194 @@
+use std::collections::HashSet;
+use crate::optimizer::control_flow::ControlFlowGraph;
+use crate::tacky::tacky_symbols::{TackyFunction, TackyInstruction, TackyVariable};
+
+/*
+Liveness analysis over the function's control flow graph, used to
+remove instructions that write to a variable which is never read
+afterwards. Static variables and variables whose address is taken
+can be read through other means (pointers, other functions), so
+they are always treated as live
+*/
+
+type LiveSet = HashSet<u64>;
+
+fn is_removable(instruction: &TackyInstruction) -> bool {
+    // instructions whose only effect is writing to their destination
+    matches!(
+        instruction,
+        TackyInstruction::UnaryInstruction(_) |
+        TackyInstruction::BinaryInstruction(_) |
+        TackyInstruction::CopyInstruction(_) |
+        TackyInstruction::ConvertInstruction(_) |
+        TackyInstruction::GetAddressInstruction(_) |
+        TackyInstruction::LoadInstruction(_)
+    )
+}
+
+struct LivenessAnalysis {
+    aliased_variables: HashSet<u64>,
+}
+impl LivenessAnalysis {
+    fn new(instructions: &[TackyInstruction]) -> LivenessAnalysis {
+        let aliased_variables = instructions.iter().filter_map(|instruction| {
+            match instruction {
+                TackyInstruction::GetAddressInstruction(get_address) => {
+                    Some(get_address.src.id)
+                },
+                _ => None
+            }
+        }).collect();
+        LivenessAnalysis { aliased_variables }
+    }
+
+    fn is_tracked(&self, variable: &TackyVariable) -> bool {
+        !variable.is_static && !self.aliased_variables.contains(&variable.id)
+    }
+
+    fn transfer(&self, instruction: &TackyInstruction, live: &mut LiveSet) {
+        // updates the live set from after the instruction to before it
+        if let Some(dst) = instruction.get_dst() {
+            live.remove(&dst.id);
+        }
+        for variable in instruction.get_src_variables() {
+            if self.is_tracked(variable) {
+                live.insert(variable.id);
+            }
+        }
+    }
+
+    fn is_dead(&self, instruction: &TackyInstruction, live: &LiveSet) -> bool {
+        match instruction.get_dst() {
+            Some(dst) => {
+                is_removable(instruction) && self.is_tracked(dst) && !live.contains(&dst.id)
+            },
+            None => false
+        }
+    }
+
+    fn liveness_sets(&self, graph: &ControlFlowGraph) -> (Vec<LiveSet>, Vec<LiveSet>) {
+        // live variables at the start and end of each block, iterated to a fixed point
+        let num_blocks = graph.blocks.len();
+        let mut live_in: Vec<LiveSet> = vec![LiveSet::new(); num_blocks];
+        let mut live_out: Vec<LiveSet> = vec![LiveSet::new(); num_blocks];
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for (index, block) in graph.blocks.iter().enumerate().rev() {
+                let mut live: LiveSet = LiveSet::new();
+                for successor in &block.successors {
+                    live.extend(live_in[*successor].iter());
+                }
+                live_out[index] = live.clone();
+                for instruction in block.instructions.iter().rev() {
+                    if !self.is_dead(instruction, &live) {
+                        self.transfer(instruction, &mut live);
+                    }
+                }
+                if live != live_in[index] {
+                    live_in[index] = live;
+                    changed = true;
+                }
+            }
+        }
+        (live_in, live_out)
+    }
+}
+
+pub fn live_in_sets(graph: &ControlFlowGraph) -> Vec<HashSet<u64>> {
+    // ids of the variables that are live at the start of each block
+    let instructions: Vec<TackyInstruction> = graph.blocks.iter().flat_map(
+        |block| block.instructions.iter().cloned()
+    ).collect();
+    let (live_in, _) = LivenessAnalysis::new(&instructions).liveness_sets(graph);
+    live_in
+}
+
+pub fn eliminate_dead_stores(function: TackyFunction) -> TackyFunction {
+    let mut function = function;
+    let analysis = LivenessAnalysis::new(&function.instructions);
+    let mut graph = ControlFlowGraph::from_instructions(function.instructions);
+    let (_, live_out) = analysis.liveness_sets(&graph);
+
+    for (block, block_live_out) in graph.blocks.iter_mut().zip(live_out) {
+        let mut live = block_live_out;
+        let mut kept_instructions: Vec<TackyInstruction> = vec![];
+        for instruction in block.instructions.drain(..).rev() {
+            if analysis.is_dead(&instruction, &live) {
+                continue;
+            }
+            analysis.transfer(&instruction, &mut live);
+            kept_instructions.push(instruction);
+        }
+        kept_instructions.reverse();
+        block.instructions = kept_instructions;
+    }
+
+    function.instructions = graph.to_instructions();
+    function
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tacky::tacky_text::{emit_tacky_text, parse_tacky_text};
+
+    fn eliminate_tacky_text(text: &str) -> Vec<String> {
+        let mut program = parse_tacky_text(text).unwrap();
+        program.function = eliminate_dead_stores(program.function);
+        let text = emit_tacky_text(&program);
+        text.lines().map(|line| line.trim().to_string()).filter(
+            |line| !line.starts_with("var ")
+        ).collect()
+    }
+
+    #[test]
+    fn test_remove_dead_stores() {
+        let instructions = eliminate_tacky_text("
+            static @total: int
+            function main {
+                var %1: int
+                var %2: int
+                var %3: int
+                %1 = copy (int) 1
+                %2 = add %1, (int) 2
+                %3 = mul %2, (int) 3
+                %1 = copy (int) 4
+                @total = copy %1
+                return %1
+            }
+        ");
+        assert_eq!(instructions, vec![
+            "static @total: int", "function main {",
+            "%1 = copy (int) 4", "@total = copy %1", "return %1", "}"
+        ]);
+    }
+
+    #[test]
+    fn test_keep_values_live_across_loops() {
+        let instructions = eliminate_tacky_text("
+            function main {
+                var %1: int
+                var %2: int
+                var %3: int *
+                var %4: int
+                %1 = copy (int) 3
+                %2 = copy (int) 0
+                %3 = address_of %4
+                %4 = copy (int) 5
+                label loop
+                %2 = add %2, %1
+                %1 = sub %1, (int) 1
+                jump_if_not_zero %1, loop
+                %4 = load %3, 0
+                return %2
+            }
+        ");
+        assert_eq!(instructions, vec![
+            "function main {", "%1 = copy (int) 3", "%2 = copy (int) 0",
+            "%3 = address_of %4", "%4 = copy (int) 5", "label loop",
+            "%2 = add %2, %1", "%1 = sub %1, (int) 1", "jump_if_not_zero %1, loop",
+            "%4 = load %3, 0", "return %2", "}"
+        ]);
+    }
+}
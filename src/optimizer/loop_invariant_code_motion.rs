@@ -0,0 +1,372 @@
+use std::collections::{HashMap, HashSet};
+use crate::optimizer::control_flow::{ControlFlowGraph, NaturalLoop};
+use crate::optimizer::dead_store_elimination::live_in_sets;
+use crate::parser::parse::SupportedBinaryOperators;
+use crate::tacky::tacky_symbols::{TackyFunction, TackyInstruction, TackyValue, TackyVariable};
+
+/*
+Moves instructions whose result is the same on every iteration of a
+loop in front of the loop, so that they are only computed once.
+
+Hoisted instructions are placed right before the loop header's label,
+which only runs on entry to the loop if the header is entered by falling
+through from the block before it (as opposed to a jump from outside).
+An instruction in the loop is invariant if its destination is only
+written once in the loop and each of its operands is a constant, isn't
+written in the loop, or is itself written by an invariant instruction
+*/
+
+// (block index, instruction index) of an instruction in the graph
+type InstructionPosition = (usize, usize);
+
+fn is_hoistable(instruction: &TackyInstruction) -> bool {
+    // pure instructions whose result only depends on their operands
+    matches!(
+        instruction,
+        TackyInstruction::UnaryInstruction(_) |
+        TackyInstruction::BinaryInstruction(_) |
+        TackyInstruction::CopyInstruction(_) |
+        TackyInstruction::ConvertInstruction(_)
+    )
+}
+
+fn can_trap(instruction: &TackyInstruction) -> bool {
+    /*
+    division by a value that might be zero, or by -1 for signed types,
+    since dividing the most negative value by -1 overflows and traps too
+    */
+    let TackyInstruction::BinaryInstruction(binary) = instruction else {
+        return false;
+    };
+    let is_division = matches!(
+        binary.operator,
+        SupportedBinaryOperators::Divide | SupportedBinaryOperators::Modulo
+    );
+    let has_safe_divisor = match &binary.right {
+        // integer constants are stored as the bit pattern of their type
+        TackyValue::Constant(constant) => constant.value.parse::<u128>().is_ok_and(|value| {
+            let value = constant.const_type.sign_extend_value(value) as i128;
+            value != 0 && value != -1
+        }),
+        TackyValue::Var(_) => false
+    };
+    is_division && !has_safe_divisor && !binary.right.get_type().is_double()
+}
+
+// facts about a loop that are shared by the invariance checks
+struct LoopContext {
+    // sorted indexes of the blocks in the loop
+    blocks: Vec<usize>,
+    // positions in the loop where each variable is written
+    definitions: HashMap<u64, Vec<InstructionPosition>>,
+    // loop blocks that can leave the loop, and the blocks they leave to
+    exit_blocks: Vec<usize>,
+    exit_targets: Vec<usize>,
+}
+
+struct LoopInvariantAnalysis<'a> {
+    graph: &'a ControlFlowGraph,
+    dominators: Vec<HashSet<usize>>,
+    live_in: Vec<HashSet<u64>>,
+    aliased_variables: HashSet<u64>,
+}
+impl LoopInvariantAnalysis<'_> {
+    fn is_tracked(&self, variable: &TackyVariable) -> bool {
+        !variable.is_static && !self.aliased_variables.contains(&variable.id)
+    }
+
+    fn has_preheader(&self, natural_loop: &NaturalLoop) -> bool {
+        /*
+        the only way into the loop from outside has to be falling
+        through from the block right before the header
+        */
+        let header = natural_loop.header;
+        if header == 0 {
+            return false;
+        }
+        let outside_predecessors: Vec<usize> = self.graph.predecessors()[header].iter().filter(
+            |predecessor| !natural_loop.blocks.contains(predecessor)
+        ).cloned().collect();
+        let jumps_to_header = match self.graph.blocks[header - 1].instructions.last() {
+            Some(TackyInstruction::JumpInstruction(_)) => true,
+            Some(TackyInstruction::JumpIfZeroInstruction(_)) |
+            Some(TackyInstruction::JumpIfNotZeroInstruction(_)) => {
+                // the block's jump target is its first successor
+                self.graph.blocks[header - 1].successors.first() == Some(&header)
+            },
+            _ => false
+        };
+        outside_predecessors == vec![header - 1] && !jumps_to_header
+    }
+
+    fn loop_context(&self, natural_loop: &NaturalLoop) -> LoopContext {
+        let mut loop_blocks: Vec<usize> = natural_loop.blocks.iter().cloned().collect();
+        loop_blocks.sort();
+
+        let mut definitions: HashMap<u64, Vec<InstructionPosition>> = HashMap::new();
+        for block_index in &loop_blocks {
+            let block = &self.graph.blocks[*block_index];
+            for (index, instruction) in block.instructions.iter().enumerate() {
+                if let Some(dst) = instruction.get_dst() {
+                    definitions.entry(dst.id).or_default().push((*block_index, index));
+                }
+            }
+        }
+
+        let exit_blocks: Vec<usize> = loop_blocks.iter().filter(
+            |block_index| self.graph.blocks[**block_index].successors.iter().any(
+                |successor| !natural_loop.blocks.contains(successor)
+            )
+        ).cloned().collect();
+        let exit_targets: Vec<usize> = exit_blocks.iter().flat_map(
+            |block_index| self.graph.blocks[*block_index].successors.iter().filter(
+                |successor| !natural_loop.blocks.contains(successor)
+            ).cloned()
+        ).collect();
+        LoopContext { blocks: loop_blocks, definitions, exit_blocks, exit_targets }
+    }
+
+    fn find_invariants(&self, natural_loop: &NaturalLoop) -> Vec<InstructionPosition> {
+        // invariant instructions in the loop, in an order that respects their dependencies
+        let loop_context = self.loop_context(natural_loop);
+        let mut invariants: Vec<InstructionPosition> = vec![];
+        let mut changed = true;
+        while changed {
+            changed = false;
+            for block_index in &loop_context.blocks {
+                let block = &self.graph.blocks[*block_index];
+                for (index, instruction) in block.instructions.iter().enumerate() {
+                    let position = (*block_index, index);
+                    if invariants.contains(&position) { continue; }
+                    if self.is_invariant(instruction, position, &loop_context, &invariants) {
+                        invariants.push(position);
+                        changed = true;
+                    }
+                }
+            }
+        }
+        invariants
+    }
+
+    fn dominates(&self, dominator: InstructionPosition, position: InstructionPosition) -> bool {
+        if dominator.0 == position.0 {
+            dominator.1 < position.1
+        } else {
+            self.dominators[position.0].contains(&dominator.0)
+        }
+    }
+
+    fn is_invariant(
+        &self, instruction: &TackyInstruction, position: InstructionPosition,
+        loop_context: &LoopContext, invariants: &[InstructionPosition]
+    ) -> bool {
+        let definitions = &loop_context.definitions;
+        if !is_hoistable(instruction) {
+            return false;
+        }
+        let dst = instruction.get_dst().unwrap();
+        if !self.is_tracked(dst) || definitions[&dst.id].len() != 1 {
+            return false;
+        }
+
+        let has_invariant_operands = instruction.get_src_variables().iter().all(
+            |variable| match definitions.get(&variable.id) {
+                // operands written in the loop have to be invariant themselves
+                Some(variable_definitions) => {
+                    self.is_tracked(variable) && variable_definitions.len() == 1 &&
+                        invariants.contains(&variable_definitions[0])
+                },
+                // statics and aliased variables may be written through pointers
+                None => self.is_tracked(variable)
+            }
+        );
+        if !has_invariant_operands {
+            return false;
+        }
+
+        // every read of dst in the loop has to see the value written here
+        for block_index in &loop_context.blocks {
+            let block = &self.graph.blocks[*block_index];
+            for (index, other_instruction) in block.instructions.iter().enumerate() {
+                let reads_dst = other_instruction.get_src_variables().iter().any(
+                    |variable| variable.id == dst.id
+                );
+                if reads_dst && !self.dominates(position, (*block_index, index)) {
+                    return false;
+                }
+            }
+        }
+
+        /*
+        hoisting also runs the instruction when the loop body wouldn't
+        have, which is only fine if dst isn't used after the loop
+        and the instruction can't trap
+        */
+        let dominates_exits = loop_context.exit_blocks.iter().all(
+            |exit_block| self.dominators[*exit_block].contains(&position.0)
+        );
+        let is_dead_after_loop = loop_context.exit_targets.iter().all(
+            |exit_target| !self.live_in[*exit_target].contains(&dst.id)
+        );
+        dominates_exits || (is_dead_after_loop && !can_trap(instruction))
+    }
+}
+
+fn hoist_one_loop(function_instructions: Vec<TackyInstruction>) -> (Vec<TackyInstruction>, bool) {
+    // hoists the invariants of the first loop that has any, returning whether it did
+    let aliased_variables: HashSet<u64> = function_instructions.iter().filter_map(
+        |instruction| match instruction {
+            TackyInstruction::GetAddressInstruction(get_address) => Some(get_address.src.id),
+            _ => None
+        }
+    ).collect();
+    let mut graph = ControlFlowGraph::from_instructions(function_instructions);
+    let natural_loops = graph.natural_loops();
+
+    let hoisted: Option<(usize, Vec<InstructionPosition>)> = {
+        let analysis = LoopInvariantAnalysis {
+            graph: &graph,
+            dominators: graph.dominators(),
+            live_in: live_in_sets(&graph),
+            aliased_variables,
+        };
+        natural_loops.iter().filter(
+            |natural_loop| analysis.has_preheader(natural_loop)
+        ).map(
+            |natural_loop| (natural_loop.header, analysis.find_invariants(natural_loop))
+        ).find(|(_, invariants)| !invariants.is_empty())
+    };
+
+    let Some((header, invariants)) = hoisted else {
+        return (graph.to_instructions(), false);
+    };
+    let hoisted_instructions: Vec<TackyInstruction> = invariants.iter().map(
+        |position| graph.blocks[position.0].instructions[position.1].clone()
+    ).collect();
+    for (block_index, block) in graph.blocks.iter_mut().enumerate() {
+        let mut index = 0;
+        block.instructions.retain(|_| {
+            let is_hoisted = invariants.contains(&(block_index, index));
+            index += 1;
+            !is_hoisted
+        });
+    }
+    // the preheader is the end of the block that falls through into the header
+    let preheader = &mut graph.blocks[header - 1].instructions;
+    preheader.extend(hoisted_instructions);
+    (graph.to_instructions(), true)
+}
+
+pub fn hoist_loop_invariants(function: TackyFunction) -> TackyFunction {
+    /*
+    instructions hoisted out of an inner loop can become invariant
+    in the outer loop, so loops are processed until nothing moves
+    */
+    let mut function = function;
+    loop {
+        let (instructions, has_hoisted) = hoist_one_loop(function.instructions);
+        function.instructions = instructions;
+        if !has_hoisted {
+            return function;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::tacky::tacky_text::{emit_tacky_text, parse_tacky_text};
+
+    fn hoist_tacky_text(text: &str) -> Vec<String> {
+        let mut program = parse_tacky_text(text).unwrap();
+        program.function = hoist_loop_invariants(program.function);
+        let text = emit_tacky_text(&program);
+        text.lines().map(|line| line.trim().to_string()).filter(
+            |line| !line.starts_with("var ") && !line.starts_with("function") && line != "}"
+        ).collect()
+    }
+
+    #[test]
+    fn test_hoist_invariants() {
+        let instructions = hoist_tacky_text("
+            function main {
+                var %1: int
+                var %2: int
+                var %3: int
+                var %4: int
+                var %5: int
+                %1 = copy (int) 10
+                %2 = copy (int) 3
+                %5 = copy (int) 0
+                label loop
+                %3 = mul %2, (int) 4
+                %4 = add %3, (int) 1
+                %5 = add %5, %4
+                %1 = sub %1, (int) 1
+                jump_if_not_zero %1, loop
+                return %5
+            }
+        ");
+        assert_eq!(instructions, vec![
+            "%1 = copy (int) 10", "%2 = copy (int) 3", "%5 = copy (int) 0",
+            "%3 = mul %2, (int) 4", "%4 = add %3, (int) 1",
+            "label loop", "%5 = add %5, %4", "%1 = sub %1, (int) 1",
+            "jump_if_not_zero %1, loop", "return %5"
+        ]);
+    }
+
+    #[test]
+    fn test_keep_unsafe_instructions() {
+        // the division may trap, and %4 is read before it is written
+        let text = "
+            function main {
+                var %1: int
+                var %2: int
+                var %3: int
+                var %4: int
+                %1 = copy (int) 10
+                %2 = copy (int) 0
+                %4 = copy (int) 0
+                label loop
+                jump_if_zero %1, end
+                %3 = div (int) 100, %2
+                %1 = sub %1, %4
+                %4 = copy (int) 1
+                jump loop
+                label end
+                return %1
+            }
+        ";
+        let instructions = hoist_tacky_text(text);
+        assert_eq!(instructions[3], "label loop");
+        assert_eq!(instructions.len(), 11);
+    }
+    #[test]
+    fn test_keep_division_by_minus_one() {
+        // only constant divisors that can't trap are hoisted out of a loop that may not run
+        let loop_text = |c_type: &str, divisor: &str| format!("
+            function main {{
+                var %1: int
+                var %2: {c_type}
+                var %3: {c_type}
+                %1 = copy (int) 10
+                %2 = copy ({c_type}) 7
+                label loop
+                jump_if_zero %1, end
+                %3 = div %2, ({c_type}) {divisor}
+                %1 = sub %1, (int) 1
+                jump loop
+                label end
+                return %1
+            }}
+        ");
+        let instructions = hoist_tacky_text(&loop_text("int", "2"));
+        assert_eq!(instructions[2], "%3 = div %2, (int) 2");
+        // -1 as an int
+        let instructions = hoist_tacky_text(&loop_text("int", "4294967295"));
+        assert_eq!(instructions[2], "label loop");
+        assert_eq!(instructions[4], "%3 = div %2, (int) 4294967295");
+        let instructions = hoist_tacky_text(&loop_text("unsigned int", "4294967295"));
+        assert_eq!(instructions[2], "%3 = div %2, (unsigned int) 4294967295");
+    }
+}
@@ -0,0 +1,38 @@
+use crate::tacky::tacky_symbols::{TackyFunction, TackyProgram};
+use crate::tacky::tacky_verifier::verify_tacky_function;
+
+pub mod constant_folding;
+pub mod control_flow;
+pub mod copy_propagation;
+pub mod dead_store_elimination;
+pub mod loop_invariant_code_motion;
+
+fn optimize_function(function: TackyFunction) -> TackyFunction {
+    /*
+    each pass can expose more work for the others (e.g. propagated
+    constants can be folded), so they are repeated until nothing changes
+    */
+    let mut function = function;
+    loop {
+        let previous_instructions = format!("{:?}", function.instructions);
+        function = constant_folding::fold_constants(function);
+        function = copy_propagation::propagate_copies(function);
+        function = loop_invariant_code_motion::hoist_loop_invariants(function);
+        function = dead_store_elimination::eliminate_dead_stores(function);
+        if cfg!(debug_assertions) {
+            if let Err(errors) = verify_tacky_function(&function) {
+                panic!("Optimization passes produced invalid tacky: {:?}", errors);
+            }
+        }
+        if format!("{:?}", function.instructions) == previous_instructions {
+            return function;
+        }
+    }
+}
+
+pub fn optimize_program(program: TackyProgram) -> TackyProgram {
+    // runs the tacky optimization passes that are enabled by -O
+    let mut program = program;
+    program.function = optimize_function(program.function);
+    program
+}
@@ -0,0 +1,269 @@
+use crate::asm_gen::asm_symbols::TAB;
+use crate::parser::parse::{
+    ASTConstant, ASTFunction, ASTProgram, BlockItem, Declaration, Expression,
+    ExpressionVariant, FunctionDeclaration, Statement, StatementVariant, StructDeclaration,
+    SupportedBinaryOperators, SupportedUnaryOperators
+};
+
+/*
+Renders the AST as an indented tree for debugging the frontend,
+in the same format that PrintableTacky uses for tacky programs
+*/
+pub trait PrintableAst {
+    fn print_ast_code(&self, depth: u64) -> String;
+}
+
+fn indent(depth: u64) -> String {
+    TAB.repeat(depth as usize)
+}
+
+impl PrintableAst for ASTConstant {
+    fn print_ast_code(&self, depth: u64) -> String {
+        format!("{}Constant: {} ({})\n", indent(depth), self.value, self.const_type)
+    }
+}
+impl PrintableAst for Expression {
+    fn print_ast_code(&self, depth: u64) -> String {
+        let prefix = indent(depth);
+        match &self.expr_item {
+            ExpressionVariant::Constant(ast_constant) => ast_constant.print_ast_code(depth),
+            ExpressionVariant::Variable(identifier) => {
+                format!("{}Variable: {}\n", prefix, identifier.name)
+            },
+            ExpressionVariant::UnaryOperation(operator, inner) => format!(
+                "{}UnaryOperation: {:?}\n{}", prefix, operator, inner.print_ast_code(depth + 1)
+            ),
+            ExpressionVariant::Dereference(inner) => format!(
+                "{}Dereference:\n{}", prefix, inner.print_ast_code(depth + 1)
+            ),
+            ExpressionVariant::AddressOf(inner) => format!(
+                "{}AddressOf:\n{}", prefix, inner.print_ast_code(depth + 1)
+            ),
+            ExpressionVariant::MemberAccess(inner, member) => format!(
+                "{}MemberAccess: .{}\n{}", prefix, member.name, inner.print_ast_code(depth + 1)
+            ),
+            ExpressionVariant::PointerMemberAccess(inner, member) => format!(
+                "{}PointerMemberAccess: ->{}\n{}",
+                prefix, member.name, inner.print_ast_code(depth + 1)
+            ),
+            ExpressionVariant::Cast(target_type, inner) => format!(
+                "{}Cast: {}\n{}", prefix, target_type, inner.print_ast_code(depth + 1)
+            ),
+            ExpressionVariant::ParensWrapped(inner) => format!(
+                "{}ParensWrapped:\n{}", prefix, inner.print_ast_code(depth + 1)
+            ),
+            ExpressionVariant::BinaryOperation(operator, left, right) => format!(
+                "{}BinaryOperation: {:?}\n{}{}", prefix, operator,
+                left.print_ast_code(depth + 1), right.print_ast_code(depth + 1)
+            ),
+        }
+    }
+}
+impl PrintableAst for Statement {
+    fn print_ast_code(&self, depth: u64) -> String {
+        let prefix = indent(depth);
+        match &self.variant {
+            StatementVariant::Return(expression) => format!(
+                "{}Return:\n{}", prefix, expression.print_ast_code(depth + 1)
+            ),
+            StatementVariant::Expression(expression) => format!(
+                "{}ExpressionStatement:\n{}", prefix, expression.print_ast_code(depth + 1)
+            ),
+            StatementVariant::Null => format!("{}NullStatement\n", prefix),
+        }
+    }
+}
+impl PrintableAst for Declaration {
+    fn print_ast_code(&self, depth: u64) -> String {
+        let mut result = format!(
+            "{}Declaration: name={}, type={}\n", indent(depth), self.name.name, self.var_type
+        );
+        if let Some(initializer) = &self.initializer {
+            result.push_str(&format!("{}Initializer:\n", indent(depth + 1)));
+            result.push_str(&initializer.print_ast_code(depth + 2));
+        }
+        result
+    }
+}
+impl PrintableAst for FunctionDeclaration {
+    fn print_ast_code(&self, depth: u64) -> String {
+        format!(
+            "{}FunctionDeclaration: name={}, return_type={}\n",
+            indent(depth), self.name.name, self.return_type
+        )
+    }
+}
+impl PrintableAst for StructDeclaration {
+    fn print_ast_code(&self, depth: u64) -> String {
+        let mut result = format!("{}StructDeclaration: {}\n", indent(depth), self.struct_type);
+        for member in self.struct_type.members.iter().flatten() {
+            result.push_str(&format!(
+                "{}Member: name={}, type={}\n", indent(depth + 1), member.name, member.member_type
+            ));
+        }
+        result
+    }
+}
+impl PrintableAst for BlockItem {
+    fn print_ast_code(&self, depth: u64) -> String {
+        match self {
+            BlockItem::Statement(statement) => statement.print_ast_code(depth),
+            BlockItem::Declaration(declaration) => declaration.print_ast_code(depth),
+            BlockItem::StructDeclaration(struct_declaration) => {
+                struct_declaration.print_ast_code(depth)
+            }
+        }
+    }
+}
+impl PrintableAst for ASTFunction {
+    fn print_ast_code(&self, depth: u64) -> String {
+        let prefix = indent(depth);
+        let mut result = String::new();
+        result.push_str(&format!("{}ASTFunction:\n", prefix));
+        result.push_str(&format!("{}{TAB}Name: {}\n", prefix, self.name.name));
+        result.push_str(&format!("{}{TAB}ReturnType: {}\n", prefix, self.return_type));
+        result.push_str(&format!("{}{TAB}Body:\n", prefix));
+        for block_item in &self.body {
+            result.push_str(&block_item.print_ast_code(depth + 2));
+        }
+        result
+    }
+}
+impl PrintableAst for ASTProgram {
+    fn print_ast_code(&self, depth: u64) -> String {
+        let mut result = format!("{}ASTProgram:\n", indent(depth));
+        for struct_declaration in &self.struct_declarations {
+            result.push_str(&struct_declaration.print_ast_code(depth + 1));
+        }
+        for declaration in &self.global_declarations {
+            result.push_str(&declaration.print_ast_code(depth + 1));
+        }
+        for function_declaration in &self.function_declarations {
+            result.push_str(&function_declaration.print_ast_code(depth + 1));
+        }
+        result.push_str(&self.function.print_ast_code(depth + 1));
+        result
+    }
+}
+
+fn unary_operator_symbol(operator: &SupportedUnaryOperators) -> &'static str {
+    match operator {
+        SupportedUnaryOperators::Subtract => "-",
+        SupportedUnaryOperators::BitwiseNot => "~",
+        SupportedUnaryOperators::Not => "!",
+    }
+}
+fn binary_operator_symbol(operator: &SupportedBinaryOperators) -> &'static str {
+    match operator {
+        SupportedBinaryOperators::Add => "+",
+        SupportedBinaryOperators::Subtract => "-",
+        SupportedBinaryOperators::Multiply => "*",
+        SupportedBinaryOperators::Divide => "/",
+        SupportedBinaryOperators::Modulo => "%",
+        SupportedBinaryOperators::BitwiseAnd => "&",
+        SupportedBinaryOperators::BitwiseOr => "|",
+        SupportedBinaryOperators::BitwiseXor => "^",
+        SupportedBinaryOperators::LeftShift => "<<",
+        SupportedBinaryOperators::RightShift => ">>",
+        SupportedBinaryOperators::And => "&&",
+        SupportedBinaryOperators::Or => "||",
+        SupportedBinaryOperators::CheckEqual => "==",
+        SupportedBinaryOperators::NotEqual => "!=",
+        SupportedBinaryOperators::LessThan => "<",
+        SupportedBinaryOperators::LessOrEqual => "<=",
+        SupportedBinaryOperators::GreaterThan => ">",
+        SupportedBinaryOperators::GreaterOrEqual => ">=",
+        SupportedBinaryOperators::AssignEqual => "=",
+    }
+}
+
+pub fn format_c_expression(expression: &Expression) -> String {
+    /*
+    Renders an expression back into (single line) C source code,
+    used to label the code generated for it. Parentheses are only
+    emitted where the source had them, since the AST keeps them
+    */
+    match &expression.expr_item {
+        ExpressionVariant::Constant(ast_constant) => ast_constant.value.clone(),
+        ExpressionVariant::Variable(identifier) => identifier.name.clone(),
+        ExpressionVariant::UnaryOperation(operator, inner) => format!(
+            "{}{}", unary_operator_symbol(operator), format_c_expression(inner)
+        ),
+        ExpressionVariant::Dereference(inner) => format!("*{}", format_c_expression(inner)),
+        ExpressionVariant::AddressOf(inner) => format!("&{}", format_c_expression(inner)),
+        ExpressionVariant::MemberAccess(inner, member) => {
+            format!("{}.{}", format_c_expression(inner), member.name)
+        },
+        ExpressionVariant::PointerMemberAccess(inner, member) => {
+            format!("{}->{}", format_c_expression(inner), member.name)
+        },
+        ExpressionVariant::Cast(target_type, inner) => {
+            format!("({}) {}", target_type, format_c_expression(inner))
+        },
+        ExpressionVariant::ParensWrapped(inner) => format!("({})", format_c_expression(inner)),
+        ExpressionVariant::BinaryOperation(operator, left, right) => format!(
+            "{} {} {}", format_c_expression(left),
+            binary_operator_symbol(operator), format_c_expression(right)
+        ),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::lexer::Lexer;
+    use crate::parser::parse::parse;
+    use crate::parser::parser_helpers::TokenStack;
+
+    #[test]
+    fn test_print_ast_tree() {
+        let source = "struct pair { int a; long b; };
+            long total = 2;
+            long helper(void);
+            int main(void) { struct pair p; return -(total + p.a); }";
+        let tokens = Lexer::new().tokenize(source).unwrap();
+        let mut token_stack = TokenStack::new_from_vec(tokens);
+        let program = parse(&mut token_stack).unwrap();
+
+        let expected = [
+            "ASTProgram:",
+            "    StructDeclaration: struct pair",
+            "        Member: name=a, type=int",
+            "        Member: name=b, type=long",
+            "    Declaration: name=total, type=long",
+            "        Initializer:",
+            "            Constant: 2 (int)",
+            "    FunctionDeclaration: name=helper, return_type=long",
+            "    ASTFunction:",
+            "        Name: main",
+            "        ReturnType: int",
+            "        Body:",
+            "            Declaration: name=p, type=struct pair",
+            "            Return:",
+            "                UnaryOperation: Subtract",
+            "                    ParensWrapped:",
+            "                        BinaryOperation: Add",
+            "                            Variable: total",
+            "                            MemberAccess: .a",
+            "                                Variable: p",
+            ""
+        ].join("\n");
+        assert_eq!(program.print_ast_code(0), expected);
+    }
+
+    #[test]
+    fn test_format_c_expression() {
+        let source = "int main(void) { return -(a + *p) * (long) s.b >= ~q->c && !1; }";
+        let tokens = Lexer::new().tokenize(source).unwrap();
+        let program = parse(&mut TokenStack::new_from_vec(tokens)).unwrap();
+        let BlockItem::Statement(statement) = &program.function.body[0] else {
+            panic!("Expected a return statement");
+        };
+        let StatementVariant::Return(expression) = &statement.variant else {
+            panic!("Expected a return statement");
+        };
+        assert_eq!(
+            format_c_expression(expression), "-(a + *p) * (long) s.b >= ~q->c && !1"
+        );
+    }
+}
@@ -0,0 +1,246 @@
+use crate::parser::parse::{
+    ASTFunction, ASTProgram, BlockItem, Declaration, Expression, ExpressionVariant,
+    Statement, StatementVariant
+};
+
+/*
+Traversals over the AST, so that passes only override the nodes they
+care about instead of each hand-rolling a recursive match.
+Visit walks the tree by reference, while Fold takes the tree by value
+and rebuilds it, possibly failing with the pass's own error type.
+The default methods recurse into every child through the walk_* and
+fold_*_children functions, which overrides can call to keep recursing
+*/
+
+pub trait Visit {
+    fn visit_program(&mut self, program: &ASTProgram) {
+        walk_program(self, program)
+    }
+    fn visit_function(&mut self, function: &ASTFunction) {
+        walk_function(self, function)
+    }
+    fn visit_block_item(&mut self, block_item: &BlockItem) {
+        walk_block_item(self, block_item)
+    }
+    fn visit_declaration(&mut self, declaration: &Declaration) {
+        walk_declaration(self, declaration)
+    }
+    fn visit_statement(&mut self, statement: &Statement) {
+        walk_statement(self, statement)
+    }
+    fn visit_expression(&mut self, expression: &Expression) {
+        walk_expression(self, expression)
+    }
+}
+
+pub fn walk_program<V: Visit + ?Sized>(visitor: &mut V, program: &ASTProgram) {
+    for declaration in &program.global_declarations {
+        visitor.visit_declaration(declaration);
+    }
+    visitor.visit_function(&program.function);
+}
+pub fn walk_function<V: Visit + ?Sized>(visitor: &mut V, function: &ASTFunction) {
+    for block_item in &function.body {
+        visitor.visit_block_item(block_item);
+    }
+}
+pub fn walk_block_item<V: Visit + ?Sized>(visitor: &mut V, block_item: &BlockItem) {
+    match block_item {
+        BlockItem::Statement(statement) => visitor.visit_statement(statement),
+        BlockItem::Declaration(declaration) => visitor.visit_declaration(declaration),
+        // struct declarations don't contain any expressions
+        BlockItem::StructDeclaration(_) => {}
+    }
+}
+pub fn walk_declaration<V: Visit + ?Sized>(visitor: &mut V, declaration: &Declaration) {
+    if let Some(initializer) = &declaration.initializer {
+        visitor.visit_expression(initializer);
+    }
+}
+pub fn walk_statement<V: Visit + ?Sized>(visitor: &mut V, statement: &Statement) {
+    match &statement.variant {
+        StatementVariant::Return(expression) |
+        StatementVariant::Expression(expression) => visitor.visit_expression(expression),
+        StatementVariant::Null => {}
+    }
+}
+pub fn walk_expression<V: Visit + ?Sized>(visitor: &mut V, expression: &Expression) {
+    match &expression.expr_item {
+        ExpressionVariant::Constant(_) | ExpressionVariant::Variable(_) => {},
+        ExpressionVariant::UnaryOperation(_, inner) |
+        ExpressionVariant::Dereference(inner) |
+        ExpressionVariant::AddressOf(inner) |
+        ExpressionVariant::MemberAccess(inner, _) |
+        ExpressionVariant::PointerMemberAccess(inner, _) |
+        ExpressionVariant::Cast(_, inner) |
+        ExpressionVariant::ParensWrapped(inner) => visitor.visit_expression(inner),
+        ExpressionVariant::BinaryOperation(_, left, right) => {
+            visitor.visit_expression(left);
+            visitor.visit_expression(right);
+        }
+    }
+}
+
+pub trait Fold {
+    // use std::convert::Infallible for folds that can't fail
+    type Error;
+
+    fn fold_program(&mut self, program: ASTProgram) -> Result<ASTProgram, Self::Error> {
+        fold_program_children(self, program)
+    }
+    fn fold_function(&mut self, function: ASTFunction) -> Result<ASTFunction, Self::Error> {
+        fold_function_children(self, function)
+    }
+    fn fold_block_item(&mut self, block_item: BlockItem) -> Result<BlockItem, Self::Error> {
+        fold_block_item_children(self, block_item)
+    }
+    fn fold_declaration(
+        &mut self, declaration: Declaration
+    ) -> Result<Declaration, Self::Error> {
+        fold_declaration_children(self, declaration)
+    }
+    fn fold_statement(&mut self, statement: Statement) -> Result<Statement, Self::Error> {
+        fold_statement_children(self, statement)
+    }
+    fn fold_expression(&mut self, expression: Expression) -> Result<Expression, Self::Error> {
+        fold_expression_children(self, expression)
+    }
+}
+
+pub fn fold_program_children<F: Fold + ?Sized>(
+    folder: &mut F, mut program: ASTProgram
+) -> Result<ASTProgram, F::Error> {
+    program.global_declarations = program.global_declarations.into_iter()
+        .map(|declaration| folder.fold_declaration(declaration))
+        .collect::<Result<_, _>>()?;
+    program.function = folder.fold_function(program.function)?;
+    Ok(program)
+}
+pub fn fold_function_children<F: Fold + ?Sized>(
+    folder: &mut F, mut function: ASTFunction
+) -> Result<ASTFunction, F::Error> {
+    function.body = function.body.into_iter()
+        .map(|block_item| folder.fold_block_item(block_item))
+        .collect::<Result<_, _>>()?;
+    Ok(function)
+}
+pub fn fold_block_item_children<F: Fold + ?Sized>(
+    folder: &mut F, block_item: BlockItem
+) -> Result<BlockItem, F::Error> {
+    Ok(match block_item {
+        BlockItem::Statement(statement) => BlockItem::Statement(folder.fold_statement(statement)?),
+        BlockItem::Declaration(declaration) => {
+            BlockItem::Declaration(folder.fold_declaration(declaration)?)
+        },
+        BlockItem::StructDeclaration(_) => block_item
+    })
+}
+pub fn fold_declaration_children<F: Fold + ?Sized>(
+    folder: &mut F, mut declaration: Declaration
+) -> Result<Declaration, F::Error> {
+    declaration.initializer = declaration.initializer
+        .map(|initializer| folder.fold_expression(initializer))
+        .transpose()?;
+    Ok(declaration)
+}
+pub fn fold_statement_children<F: Fold + ?Sized>(
+    folder: &mut F, mut statement: Statement
+) -> Result<Statement, F::Error> {
+    statement.variant = match statement.variant {
+        StatementVariant::Return(expression) => {
+            StatementVariant::Return(folder.fold_expression(expression)?)
+        },
+        StatementVariant::Expression(expression) => {
+            StatementVariant::Expression(folder.fold_expression(expression)?)
+        },
+        StatementVariant::Null => StatementVariant::Null
+    };
+    Ok(statement)
+}
+pub fn fold_expression_children<F: Fold + ?Sized>(
+    folder: &mut F, mut expression: Expression
+) -> Result<Expression, F::Error> {
+    let mut fold_boxed = |inner: Box<Expression>| -> Result<Box<Expression>, F::Error> {
+        Ok(Box::new(folder.fold_expression(*inner)?))
+    };
+    expression.expr_item = match expression.expr_item {
+        ExpressionVariant::Constant(_) | ExpressionVariant::Variable(_) => expression.expr_item,
+        ExpressionVariant::UnaryOperation(operator, inner) => {
+            ExpressionVariant::UnaryOperation(operator, fold_boxed(inner)?)
+        },
+        ExpressionVariant::Dereference(inner) => ExpressionVariant::Dereference(fold_boxed(inner)?),
+        ExpressionVariant::AddressOf(inner) => ExpressionVariant::AddressOf(fold_boxed(inner)?),
+        ExpressionVariant::MemberAccess(inner, member) => {
+            ExpressionVariant::MemberAccess(fold_boxed(inner)?, member)
+        },
+        ExpressionVariant::PointerMemberAccess(inner, member) => {
+            ExpressionVariant::PointerMemberAccess(fold_boxed(inner)?, member)
+        },
+        ExpressionVariant::Cast(target_type, inner) => {
+            ExpressionVariant::Cast(target_type, fold_boxed(inner)?)
+        },
+        ExpressionVariant::ParensWrapped(inner) => {
+            ExpressionVariant::ParensWrapped(fold_boxed(inner)?)
+        },
+        ExpressionVariant::BinaryOperation(operator, left, right) => {
+            let left = fold_boxed(left)?;
+            ExpressionVariant::BinaryOperation(operator, left, fold_boxed(right)?)
+        }
+    };
+    Ok(expression)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::convert::Infallible;
+    use super::*;
+    use crate::parser::ast_printer::format_c_expression;
+    use crate::parser::parse::parse_from_str;
+
+    struct VariableCounter {
+        names: Vec<String>,
+    }
+    impl Visit for VariableCounter {
+        fn visit_expression(&mut self, expression: &Expression) {
+            if let ExpressionVariant::Variable(identifier) = &expression.expr_item {
+                self.names.push(identifier.name.clone());
+            }
+            walk_expression(self, expression)
+        }
+    }
+
+    struct ParensRemover {}
+    impl Fold for ParensRemover {
+        type Error = Infallible;
+
+        fn fold_expression(&mut self, expression: Expression) -> Result<Expression, Infallible> {
+            let expression = fold_expression_children(self, expression)?;
+            match expression.expr_item {
+                ExpressionVariant::ParensWrapped(inner) => Ok(*inner),
+                _ => Ok(expression)
+            }
+        }
+    }
+
+    #[test]
+    fn test_visit_variables() {
+        let program = parse_from_str(
+            "int g = 2; int main(void) { int a = g; a = (a + g) * -a; return a; }"
+        ).unwrap();
+        let mut counter = VariableCounter { names: vec![] };
+        counter.visit_program(&program);
+        assert_eq!(counter.names, vec!["g", "a", "a", "g", "a", "a"]);
+    }
+
+    #[test]
+    fn test_fold_removes_parens() {
+        let program = parse_from_str("int main(void) { return ((1 + 2)) * (3); }").unwrap();
+        let program = ParensRemover {}.fold_program(program).unwrap();
+        match &program.function.body[0] {
+            BlockItem::Statement(Statement { variant: StatementVariant::Return(expression), .. }) => {
+                assert_eq!(format_c_expression(expression), "1 + 2 * 3");
+            },
+            other => panic!("Expected return statement, got {:?}", other)
+        }
+    }
+}
@@ -0,0 +1,546 @@
+use std::collections::HashMap;
+use std::fmt;
+use serde::{Deserialize, Serialize};
+use crate::lexer::lexer::Keywords;
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct StructMember {
+    pub name: String,
+    pub member_type: CType,
+}
+impl StructMember {
+    pub fn new(name: String, member_type: CType) -> StructMember {
+        StructMember { name, member_type }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct StructLayout {
+    // byte offset of each member, in declaration order
+    pub member_offsets: Vec<(StructMember, u64)>,
+    pub size: u64,
+    pub alignment: u64,
+}
+impl StructLayout {
+    pub fn get_member(&self, name: &str) -> Option<&(StructMember, u64)> {
+        self.member_offsets.iter().find(|(member, _)| member.name == name)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum AggregateKind {
+    Struct,
+    // all members of a union share the same storage
+    Union,
+}
+impl AggregateKind {
+    pub fn from_keyword(keyword: &Keywords) -> Option<AggregateKind> {
+        match keyword {
+            Keywords::Struct => Some(AggregateKind::Struct),
+            Keywords::Union => Some(AggregateKind::Union),
+            _ => None
+        }
+    }
+    pub fn to_keyword(&self) -> Keywords {
+        match self {
+            AggregateKind::Struct => Keywords::Struct,
+            AggregateKind::Union => Keywords::Union,
+        }
+    }
+}
+impl fmt::Display for AggregateKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.to_keyword())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct StructType {
+    pub kind: AggregateKind,
+    pub tag: String,
+    // None for a struct that is referenced (e.g. "struct point *p")
+    // but whose members haven't been resolved yet
+    pub members: Option<Vec<StructMember>>,
+}
+impl StructType {
+    pub fn new(
+        kind: AggregateKind, tag: String, members: Vec<StructMember>
+    ) -> StructType {
+        StructType { kind, tag, members: Some(members) }
+    }
+    pub fn new_incomplete(kind: AggregateKind, tag: String) -> StructType {
+        StructType { kind, tag, members: None }
+    }
+    pub fn is_complete(&self) -> bool {
+        self.members.is_some()
+    }
+    pub fn layout(&self) -> Result<StructLayout, String> {
+        /*
+        Each member is placed at the next offset that is a multiple
+        of its alignment, and the total size is padded to a multiple
+        of the alignment of the most strictly aligned member, e.g.
+        struct { char a; int b; char c; } has offsets 0, 4, 8 and size 12.
+        Union members all start at offset 0 instead, so a union is as
+        large as its largest member (padded to its alignment)
+        */
+        let members = match &self.members {
+            Some(members) => members,
+            None => return Err(format!("Incomplete type {}", self))
+        };
+
+        let mut member_offsets: Vec<(StructMember, u64)> = vec![];
+        let mut current_offset: u64 = 0;
+        let mut alignment: u64 = 1;
+        for member in members {
+            let member_alignment = member.member_type.alignment();
+            let member_size = member.member_type.size();
+            alignment = alignment.max(member_alignment);
+
+            match self.kind {
+                AggregateKind::Struct => {
+                    let offset =
+                        current_offset.div_ceil(member_alignment) * member_alignment;
+                    member_offsets.push((member.clone(), offset));
+                    current_offset = offset + member_size;
+                },
+                AggregateKind::Union => {
+                    member_offsets.push((member.clone(), 0));
+                    current_offset = current_offset.max(member_size);
+                }
+            }
+        }
+
+        let size = current_offset.div_ceil(alignment) * alignment;
+        Ok(StructLayout { member_offsets, size, alignment })
+    }
+}
+
+impl fmt::Display for StructType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{} {}", self.kind, self.tag)
+    }
+}
+
+// maps struct (and union) tags to their complete types
+#[derive(Clone, Debug, Default)]
+pub struct StructTable {
+    structs: HashMap<String, StructType>,
+}
+impl StructTable {
+    pub fn new() -> StructTable {
+        StructTable { structs: HashMap::new() }
+    }
+    pub fn declare(&mut self, struct_type: StructType) -> Result<(), String> {
+        // resolves the member types before adding the struct
+        let members = match &struct_type.members {
+            Some(members) => members,
+            None => return Err(format!(
+                "Cannot declare incomplete {}", struct_type
+            ))
+        };
+        let mut resolved_members: Vec<StructMember> = vec![];
+        for member in members {
+            if resolved_members.iter().any(|m| m.name == member.name) {
+                return Err(format!(
+                    "Duplicate member {} in {}", member.name, struct_type
+                ));
+            }
+            resolved_members.push(StructMember::new(
+                member.name.clone(), self.resolve(&member.member_type)?
+            ));
+        }
+        // structs and unions share the same tag namespace
+        let tag = struct_type.tag.clone();
+        let kind = struct_type.kind.clone();
+        self.structs.insert(tag.clone(), StructType::new(kind, tag, resolved_members));
+        Ok(())
+    }
+    pub fn resolve(&self, c_type: &CType) -> Result<CType, String> {
+        /*
+        Replaces a reference to an incomplete struct with its declaration.
+        Types behind pointers are left as they are, since a pointer to a
+        struct can be declared before (or inside) the struct itself
+        */
+        match c_type {
+            CType::Struct(struct_type) if !struct_type.is_complete() => {
+                match self.structs.get(&struct_type.tag) {
+                    Some(declared) if declared.kind != struct_type.kind => Err(format!(
+                        "{} was declared as {}", struct_type, declared
+                    )),
+                    Some(declared) => Ok(CType::Struct(declared.clone())),
+                    None => Err(format!("Unknown {}", struct_type))
+                }
+            },
+            other => Ok(other.clone())
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum CType {
+    // plain char is signed, as on x86-64 System V
+    Char,
+    SignedChar,
+    UnsignedChar,
+    Int,
+    Long,
+    UnsignedInt,
+    UnsignedLong,
+    // IEEE 754 double precision floating point
+    Double,
+    // pointer to a value of the inner type
+    Pointer(Box<CType>),
+    Struct(StructType),
+}
+impl CType {
+    pub fn size(&self) -> u64 {
+        // size of the type in bytes
+        match self {
+            CType::Char => 1,
+            CType::SignedChar => 1,
+            CType::UnsignedChar => 1,
+            CType::Int => 4,
+            CType::UnsignedInt => 4,
+            CType::Long => 8,
+            CType::UnsignedLong => 8,
+            CType::Double => 8,
+            CType::Pointer(_) => 8,
+            CType::Struct(struct_type) => match struct_type.layout() {
+                Ok(layout) => layout.size,
+                // incomplete structs have no known size
+                Err(_) => 0
+            },
+        }
+    }
+    pub fn alignment(&self) -> u64 {
+        // required alignment of the type in bytes
+        match self {
+            CType::Struct(struct_type) => match struct_type.layout() {
+                Ok(layout) => layout.alignment,
+                Err(_) => 1
+            },
+            other => other.size()
+        }
+    }
+    pub fn is_signed(&self) -> bool {
+        match self {
+            CType::Char => true,
+            CType::SignedChar => true,
+            CType::UnsignedChar => false,
+            CType::Int => true,
+            CType::Long => true,
+            CType::UnsignedInt => false,
+            CType::UnsignedLong => false,
+            CType::Double => true,
+            // addresses are compared as unsigned values
+            CType::Pointer(_) => false,
+            CType::Struct(_) => false,
+        }
+    }
+    pub fn is_character(&self) -> bool {
+        matches!(self, CType::Char | CType::SignedChar | CType::UnsignedChar)
+    }
+    pub fn is_double(&self) -> bool {
+        matches!(self, CType::Double)
+    }
+    pub fn is_struct(&self) -> bool {
+        matches!(self, CType::Struct(_))
+    }
+    pub fn is_pointer(&self) -> bool {
+        matches!(self, CType::Pointer(_))
+    }
+    pub fn pointer_to(&self) -> CType {
+        CType::Pointer(Box::new(self.clone()))
+    }
+    pub fn referenced_type(&self) -> Option<CType> {
+        // type of the value a pointer points to
+        match self {
+            CType::Pointer(inner) => Some(*inner.clone()),
+            _ => None
+        }
+    }
+    pub fn promoted(&self) -> CType {
+        // integer promotion: character types are promoted to int
+        if self.is_character() { CType::Int } else { self.clone() }
+    }
+    pub fn common_type(left: &CType, right: &CType) -> CType {
+        /*
+        usual arithmetic conversions:
+        - if either operand is a double, the other is converted to double
+        - character types are promoted to int first
+        - identical types need no conversion
+        - the wider of the two types wins
+        - on equal widths the unsigned type wins
+        */
+        let (left, right) = (&left.promoted(), &right.promoted());
+        if left.is_double() || right.is_double() {
+            CType::Double
+        } else if left == right {
+            left.clone()
+        } else if left.size() != right.size() {
+            if left.size() > right.size() { left.clone() } else { right.clone() }
+        } else if left.is_signed() {
+            right.clone()
+        } else {
+            left.clone()
+        }
+    }
+    pub fn from_specifiers(
+        specifiers: &Vec<Keywords>
+    ) -> Result<CType, String> {
+        /*
+        Resolves a list of type specifier keywords (in any order)
+        e.g. [unsigned, long, int] into a single CType
+        */
+        let count = |keyword: Keywords| {
+            specifiers.iter().filter(|k| **k == keyword).count()
+        };
+        let num_double = count(Keywords::Double);
+        let num_char = count(Keywords::Char);
+        let num_int = count(Keywords::Integer);
+        let num_long = count(Keywords::Long);
+        let num_signed = count(Keywords::Signed);
+        let num_unsigned = count(Keywords::Unsigned);
+        let num_recognized =
+            num_double + num_char + num_int + num_long + num_signed + num_unsigned;
+
+        if specifiers.is_empty() {
+            return Err("Missing type specifier".to_string());
+        } else if num_recognized != specifiers.len() {
+            return Err(format!("Invalid type specifiers {:?}", specifiers));
+        } else if num_double > 0 {
+            // double can't be combined with any other type specifier
+            return if specifiers.len() == 1 {
+                Ok(CType::Double)
+            } else {
+                Err(format!("Conflicting type specifiers {:?}", specifiers))
+            };
+        } else if num_char > 1 || num_int > 1 || num_long > 1 {
+            return Err(format!("Repeated type specifiers {:?}", specifiers));
+        } else if num_signed + num_unsigned > 1 || (num_char > 0 && num_int + num_long > 0) {
+            // e.g. "signed unsigned" or "char int"
+            return Err(format!("Conflicting type specifiers {:?}", specifiers));
+        }
+
+        if num_char == 1 {
+            return Ok(match (num_signed, num_unsigned) {
+                (1, _) => CType::SignedChar,
+                (_, 1) => CType::UnsignedChar,
+                _ => CType::Char,
+            });
+        }
+
+        let is_unsigned = num_unsigned == 1;
+        let is_long = num_long == 1;
+        Ok(match (is_unsigned, is_long) {
+            (false, false) => CType::Int,
+            (false, true) => CType::Long,
+            (true, false) => CType::UnsignedInt,
+            (true, true) => CType::UnsignedLong,
+        })
+    }
+    pub fn is_type_specifier(keyword: &Keywords) -> bool {
+        matches!(
+            keyword,
+            Keywords::Char | Keywords::Integer | Keywords::Long | Keywords::Double |
+            Keywords::Signed | Keywords::Unsigned |
+            Keywords::Struct | Keywords::Union
+        )
+    }
+    pub fn sign_extend_value(&self, value: u128) -> u128 {
+        /*
+        Reinterprets a bit pattern of this type as a 128-bit two's
+        complement value, e.g. 0xFF -> -1 (all ones) for signed chars
+        */
+        let num_bits = self.size() * 8;
+        let value = self.wrap_value(value) as u128;
+        let is_negative = (value >> (num_bits - 1)) & 1 == 1;
+        if self.is_signed() && is_negative {
+            value | !((1u128 << num_bits) - 1)
+        } else {
+            value
+        }
+    }
+    pub fn wrap_value(&self, value: u128) -> u64 {
+        /*
+        Reduces a (non-negative) value to the bit pattern it would
+        have when stored in this type, e.g. 2^32 + 1 -> 1 for ints
+        */
+        let mask: u128 = (1u128 << (self.size() * 8)) - 1;
+        (value & mask) as u64
+    }
+}
+impl fmt::Display for CType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if let CType::Pointer(inner) = self {
+            return write!(f, "{} *", inner);
+        } else if let CType::Struct(struct_type) = self {
+            return write!(f, "{}", struct_type);
+        }
+        let name = match self {
+            CType::Char => "char",
+            CType::SignedChar => "signed char",
+            CType::UnsignedChar => "unsigned char",
+            CType::Int => "int",
+            CType::Long => "long",
+            CType::UnsignedInt => "unsigned int",
+            CType::UnsignedLong => "unsigned long",
+            CType::Double => "double",
+            CType::Pointer(_) | CType::Struct(_) => unreachable!(),
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_specifiers() {
+        let resolve = |keywords: Vec<Keywords>| CType::from_specifiers(&keywords);
+        assert_eq!(resolve(vec![Keywords::Integer]), Ok(CType::Int));
+        assert_eq!(resolve(vec![Keywords::Long, Keywords::Integer]), Ok(CType::Long));
+        assert_eq!(resolve(vec![Keywords::Unsigned]), Ok(CType::UnsignedInt));
+        assert_eq!(
+            resolve(vec![Keywords::Integer, Keywords::Unsigned, Keywords::Long]),
+            Ok(CType::UnsignedLong)
+        );
+        assert!(resolve(vec![Keywords::Signed, Keywords::Unsigned]).is_err());
+        assert!(resolve(vec![Keywords::Long, Keywords::Long]).is_err());
+        assert!(resolve(vec![]).is_err());
+
+        assert_eq!(resolve(vec![Keywords::Char]), Ok(CType::Char));
+        assert_eq!(
+            resolve(vec![Keywords::Unsigned, Keywords::Char]),
+            Ok(CType::UnsignedChar)
+        );
+        assert!(resolve(vec![Keywords::Char, Keywords::Integer]).is_err());
+    }
+
+    #[test]
+    fn test_common_type() {
+        assert_eq!(CType::common_type(&CType::Int, &CType::Long), CType::Long);
+        assert_eq!(
+            CType::common_type(&CType::Int, &CType::UnsignedInt),
+            CType::UnsignedInt
+        );
+        assert_eq!(
+            CType::common_type(&CType::UnsignedInt, &CType::Long),
+            CType::Long
+        );
+        assert_eq!(
+            CType::common_type(&CType::UnsignedLong, &CType::Long),
+            CType::UnsignedLong
+        );
+        // character types are promoted before the conversion
+        assert_eq!(
+            CType::common_type(&CType::Char, &CType::UnsignedChar),
+            CType::Int
+        );
+        assert_eq!(
+            CType::common_type(&CType::UnsignedLong, &CType::Double),
+            CType::Double
+        );
+        assert_eq!(CType::common_type(&CType::Double, &CType::Char), CType::Double);
+    }
+
+    #[test]
+    fn test_double_specifiers() {
+        let resolve = |keywords: Vec<Keywords>| CType::from_specifiers(&keywords);
+        assert_eq!(resolve(vec![Keywords::Double]), Ok(CType::Double));
+        assert!(resolve(vec![Keywords::Long, Keywords::Double]).is_err());
+        assert!(resolve(vec![Keywords::Unsigned, Keywords::Double]).is_err());
+        assert_eq!(CType::Double.size(), 8);
+        assert_eq!(CType::Double.alignment(), 8);
+    }
+
+    #[test]
+    fn test_pointer_types() {
+        let int_pointer = CType::Int.pointer_to();
+        let pointer_pointer = int_pointer.pointer_to();
+        assert_eq!(int_pointer.size(), 8);
+        assert!(!int_pointer.is_signed());
+        assert_eq!(pointer_pointer.referenced_type(), Some(int_pointer.clone()));
+        assert_eq!(CType::Int.referenced_type(), None);
+        assert_eq!(pointer_pointer.to_string(), "int * *");
+    }
+
+    #[test]
+    fn test_struct_layout() {
+        let member = |name: &str, member_type: CType| {
+            StructMember::new(name.to_string(), member_type)
+        };
+        let struct_type = StructType::new(AggregateKind::Struct, "padded".to_string(), vec![
+            member("a", CType::Char),
+            member("b", CType::Int),
+            member("c", CType::Char),
+        ]);
+        let layout = struct_type.layout().unwrap();
+        let offsets: Vec<u64> = layout.member_offsets.iter().map(|(_, o)| *o).collect();
+        assert_eq!(offsets, vec![0, 4, 8]);
+        assert_eq!(layout.size, 12);
+        assert_eq!(layout.alignment, 4);
+
+        let nested_type = StructType::new(AggregateKind::Struct, "nested".to_string(), vec![
+            member("flag", CType::Char),
+            member("inner", CType::Struct(struct_type)),
+            member("next", CType::Long.pointer_to()),
+        ]);
+        let layout = nested_type.layout().unwrap();
+        assert_eq!(layout.get_member("inner").unwrap().1, 4);
+        assert_eq!(layout.get_member("next").unwrap().1, 16);
+        assert_eq!(layout.size, 24);
+        assert_eq!(layout.alignment, 8);
+        assert!(StructType::new_incomplete(AggregateKind::Struct, "node".to_string()).layout().is_err());
+    }
+
+    #[test]
+    fn test_struct_table() {
+        let mut struct_table = StructTable::new();
+        let node_ref = CType::Struct(StructType::new_incomplete(AggregateKind::Struct, "node".to_string()));
+        // self referential structs are fine behind a pointer
+        struct_table.declare(StructType::new(AggregateKind::Struct, "node".to_string(), vec![
+            StructMember::new("value".to_string(), CType::Int),
+            StructMember::new("next".to_string(), node_ref.pointer_to()),
+        ])).unwrap();
+        assert_eq!(struct_table.resolve(&node_ref).unwrap().size(), 16);
+
+        let unknown_ref = CType::Struct(StructType::new_incomplete(AggregateKind::Struct, "other".to_string()));
+        assert!(struct_table.resolve(&unknown_ref).is_err());
+        assert!(struct_table.declare(StructType::new(
+            AggregateKind::Struct, "duplicate".to_string(), vec![
+                StructMember::new("a".to_string(), CType::Int),
+                StructMember::new("a".to_string(), CType::Long),
+            ]
+        )).is_err());
+        // a struct tag can't be referred to as a union
+        let union_ref = CType::Struct(StructType::new_incomplete(
+            AggregateKind::Union, "node".to_string()
+        ));
+        assert!(struct_table.resolve(&union_ref).is_err());
+    }
+
+    #[test]
+    fn test_union_layout() {
+        let union_type = StructType::new(AggregateKind::Union, "number".to_string(), vec![
+            StructMember::new("small".to_string(), CType::Char),
+            StructMember::new("wide".to_string(), CType::Long),
+            StructMember::new("medium".to_string(), CType::Int),
+        ]);
+        let layout = union_type.layout().unwrap();
+        assert!(layout.member_offsets.iter().all(|(_, offset)| *offset == 0));
+        assert_eq!(layout.size, 8);
+        assert_eq!(layout.alignment, 8);
+        assert_eq!(CType::Struct(union_type).to_string(), "union number");
+
+        let padded_type = StructType::new(AggregateKind::Union, "padded".to_string(), vec![
+            StructMember::new("bytes".to_string(), CType::Struct(StructType::new(
+                AggregateKind::Struct, "five".to_string(),
+                (0..5).map(|i| StructMember::new(format!("b{i}"), CType::Char)).collect()
+            ))),
+            StructMember::new("word".to_string(), CType::Int),
+        ]);
+        assert_eq!(padded_type.layout().unwrap().size, 8);
+    }
+}
@@ -1,2 +1,5 @@
 pub mod parser_helpers;
-pub(crate) mod parse;
\ No newline at end of file
+pub mod parse;
+pub(crate) mod c_types;
+pub mod ast_printer;
+pub mod ast_visitor;pub mod py_ast;
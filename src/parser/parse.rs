@@ -1,13 +1,18 @@
-use std::collections::VecDeque;
-use std::num::ParseIntError;
-use crate::lexer::lexer::{lex_from_filepath, Keywords, Tokens};
+use std::num::{ParseFloatError, ParseIntError};
+use std::io::Read;
+use serde::{Deserialize, Serialize};
+use crate::lexer::lexer::{
+    decode_char_literal, lex_from_filepath, lex_from_reader, lex_from_str, Keywords,
+    LexerFromFileError, SourceLocation, Tokens, WrappedToken
+};
 use crate::lexer::tokens::{Operators, Punctuators};
+use crate::parser::c_types::{AggregateKind, CType, StructMember, StructType};
 use crate::parser::parser_helpers::{
-    ParseError, ParseErrorVariants, PoppedTokenContext, TokenStack
+    ParseError, ParseErrorContext, ParseErrorVariants, PoppedTokenContext, TokenStack
 };
 
 #[derive(Clone, Debug)]
-#[derive(PartialEq)]
+#[derive(PartialEq, Eq, Serialize, Deserialize)]
 pub struct Identifier {
     pub(crate) name: String,
 }
@@ -37,7 +42,7 @@ impl Identifier {
                 variant: ParseErrorVariants::NoMoreTokens(
                     "No identifier token found".to_string()
                 ),
-                token_stack: tokens.soft_copy()
+                context: tokens.error_context()
             }),
         };
         Ok(Identifier::new(identifier_name))
@@ -82,6 +87,12 @@ pub enum SupportedBinaryOperators {
     Divide,
     Modulo,
 
+    BitwiseAnd,
+    BitwiseOr,
+    BitwiseXor,
+    LeftShift,
+    RightShift,
+
     And,
     Or,
     CheckEqual,
@@ -101,6 +112,12 @@ impl SupportedBinaryOperators {
             Operators::Divide => Some(SupportedBinaryOperators::Divide),
             Operators::Modulo => Some(SupportedBinaryOperators::Modulo),
 
+            Operators::BitwiseAnd => Some(SupportedBinaryOperators::BitwiseAnd),
+            Operators::BitwiseOr => Some(SupportedBinaryOperators::BitwiseOr),
+            Operators::BitwiseXor => Some(SupportedBinaryOperators::BitwiseXor),
+            Operators::LeftShift => Some(SupportedBinaryOperators::LeftShift),
+            Operators::RightShift => Some(SupportedBinaryOperators::RightShift),
+
             Operators::LogicalAnd => Some(SupportedBinaryOperators::And),
             Operators::LogicalOr => Some(SupportedBinaryOperators::Or),
             Operators::EqualTo => Some(SupportedBinaryOperators::CheckEqual),
@@ -113,6 +130,33 @@ impl SupportedBinaryOperators {
             _ => None,
         }
     }
+    pub fn is_relational(&self) -> bool {
+        matches!(
+            self,
+            SupportedBinaryOperators::CheckEqual |
+            SupportedBinaryOperators::NotEqual |
+            SupportedBinaryOperators::LessThan |
+            SupportedBinaryOperators::LessOrEqual |
+            SupportedBinaryOperators::GreaterThan |
+            SupportedBinaryOperators::GreaterOrEqual
+        )
+    }
+    pub fn is_bitwise(&self) -> bool {
+        // operators that only accept integer operands
+        matches!(
+            self,
+            SupportedBinaryOperators::BitwiseAnd |
+            SupportedBinaryOperators::BitwiseOr |
+            SupportedBinaryOperators::BitwiseXor
+        ) || self.is_shift()
+    }
+    pub fn is_shift(&self) -> bool {
+        matches!(
+            self,
+            SupportedBinaryOperators::LeftShift |
+            SupportedBinaryOperators::RightShift
+        )
+    }
     pub fn is_short_circuit(&self) -> bool {
         match self {
             SupportedBinaryOperators::And => true,
@@ -129,6 +173,9 @@ impl SupportedBinaryOperators {
             SupportedBinaryOperators::Add => 45,
             SupportedBinaryOperators::Subtract => 45,
 
+            SupportedBinaryOperators::LeftShift => 40,
+            SupportedBinaryOperators::RightShift => 40,
+
             SupportedBinaryOperators::LessThan => 35,
             SupportedBinaryOperators::LessOrEqual => 35,
             SupportedBinaryOperators::GreaterThan => 35,
@@ -136,6 +183,10 @@ impl SupportedBinaryOperators {
 
             SupportedBinaryOperators::CheckEqual => 30,
             SupportedBinaryOperators::NotEqual => 30,
+
+            SupportedBinaryOperators::BitwiseAnd => 25,
+            SupportedBinaryOperators::BitwiseXor => 20,
+            SupportedBinaryOperators::BitwiseOr => 15,
             SupportedBinaryOperators::And => 10,
             SupportedBinaryOperators::Or => 5,
 
@@ -160,27 +211,147 @@ impl SupportedBinaryOperators {
 pub struct ASTConstant {
     // TODO: use bignum instead of string (?)
     pub(crate) value: String,
+    pub(crate) const_type: CType,
     pub(crate) pop_context: Option<PoppedTokenContext>
 }
 impl ASTConstant {
     pub fn new(value: &str) -> ASTConstant {
+        /*
+        Builds a constant from its source representation (e.g. "10ul",
+        "0x1F", "017", "0b101"), normalizing its value to decimal digits,
+        stripping the integer suffix and inferring the constant's type
+        from the suffix and its magnitude
+        */
+        let lowercase_value = value.to_lowercase();
+        let body = lowercase_value.trim_end_matches(['u', 'l']);
+        let suffix = &lowercase_value[body.len()..];
+        let (radix, digits) = if let Some(digits) = body.strip_prefix("0x") {
+            (16, digits)
+        } else if let Some(digits) = body.strip_prefix("0b") {
+            (2, digits)
+        } else if body.len() > 1 && body.starts_with('0') {
+            (8, &body[1..])
+        } else {
+            (10, body)
+        };
+        let magnitude = u128::from_str_radix(digits, radix).unwrap_or(u128::MAX);
+        let is_unsigned = suffix.contains('u');
+        let is_long = suffix.contains('l');
+        // non-decimal constants may also be unsigned ints, e.g. 0xFFFFFFFF
+        let allows_unsigned = radix != 10;
+
+        let const_type = if is_unsigned {
+            if !is_long && magnitude <= u32::MAX as u128 {
+                CType::UnsignedInt
+            } else {
+                CType::UnsignedLong
+            }
+        } else if !is_long && magnitude <= i32::MAX as u128 {
+            CType::Int
+        } else if !is_long && allows_unsigned && magnitude <= u32::MAX as u128 {
+            CType::UnsignedInt
+        } else if magnitude <= i64::MAX as u128 {
+            CType::Long
+        } else {
+            CType::UnsignedLong
+        };
+
+        Self::new_typed(&magnitude.to_string(), const_type)
+    }
+    pub fn new_double(value: &str) -> Option<ASTConstant> {
+        // builds a double constant from its source representation (e.g. "1e10")
+        let double_value = value.parse::<f64>().ok()?;
+        Some(Self::new_typed(&format!("{:?}", double_value), CType::Double))
+    }
+    pub fn new_typed(value: &str, const_type: CType) -> ASTConstant {
         ASTConstant {
             value: value.to_owned(),
+            const_type,
             pop_context: None
         }
     }
+    pub fn converted_to(&self, target_type: &CType) -> ASTConstant {
+        // constant with the value it would have when converted to target_type
+        let value = if self.const_type.is_double() {
+            let double_value = self.to_f64().unwrap();
+            if target_type.is_double() {
+                format!("{:?}", double_value)
+            } else if target_type.is_signed() {
+                // out of range conversions are undefined, so saturate
+                let truncated = double_value as i64 as i128 as u128;
+                target_type.wrap_value(truncated).to_string()
+            } else {
+                target_type.wrap_value(double_value as u64 as u128).to_string()
+            }
+        } else {
+            let magnitude = self.value.parse::<u128>().unwrap_or(u128::MAX);
+            let extended_value = self.const_type.sign_extend_value(magnitude);
+            if target_type.is_double() {
+                let double_value = if self.const_type.is_signed() {
+                    extended_value as i128 as f64
+                } else {
+                    extended_value as f64
+                };
+                format!("{:?}", double_value)
+            } else {
+                target_type.wrap_value(extended_value).to_string()
+            }
+        };
+        ASTConstant {
+            value,
+            const_type: target_type.clone(),
+            pop_context: self.pop_context.clone()
+        }
+    }
+    pub fn negated(&self) -> ASTConstant {
+        // constant with the value of -self, wrapped to the constant's type
+        let value = if self.const_type.is_double() {
+            format!("{:?}", -self.to_f64().unwrap())
+        } else {
+            let magnitude = self.value.parse::<u128>().unwrap_or(u128::MAX);
+            let extended_value = self.const_type.sign_extend_value(magnitude);
+            self.const_type.wrap_value(extended_value.wrapping_neg()).to_string()
+        };
+        ASTConstant {
+            value,
+            const_type: self.const_type.clone(),
+            pop_context: self.pop_context.clone()
+        }
+    }
     pub fn to_u64(&self) -> Result<u64, ParseIntError> {
         self.value.parse::<u64>()
     }
     pub fn to_usize(&self) -> Result<usize, ParseIntError> {
         self.value.parse::<usize>()
     }
+    pub fn to_f64(&self) -> Result<f64, ParseFloatError> {
+        self.value.parse::<f64>()
+    }
+    pub fn to_bits(&self) -> u64 {
+        // bit pattern of the constant as it is stored in memory
+        if self.const_type.is_double() {
+            self.to_f64().unwrap().to_bits()
+        } else {
+            self.to_u64().unwrap()
+        }
+    }
 }
 
 #[derive(Clone, Debug)]
 pub enum ExpressionVariant {
     Constant(ASTConstant),
+    Variable(Identifier),
     UnaryOperation(SupportedUnaryOperators, Box<Expression>),
+    // *<exp>
+    Dereference(Box<Expression>),
+    // &<exp>
+    AddressOf(Box<Expression>),
+    // <exp>.<member>
+    MemberAccess(Box<Expression>, Identifier),
+    // <exp>-><member>
+    PointerMemberAccess(Box<Expression>, Identifier),
+    // (<type>) <exp>
+    Cast(CType, Box<Expression>),
     ParensWrapped(Box<Expression>),
     BinaryOperation(SupportedBinaryOperators, Box<Expression>, Box<Expression>)
 }
@@ -237,9 +408,13 @@ impl Expression {
             ) {
                 // consume the binary operator
                 stack_popper.pop_front().expect("Failed to pop binary operator");
+                // assignment is right associative, so a = b = c parses as a = (b = c)
+                let right_min_precedence = match binary_operator {
+                    SupportedBinaryOperators::AssignEqual => binary_operator.to_precedence(),
+                    _ => binary_operator.to_precedence() + 1
+                };
                 let right_exp = Self::parse_as_exp(
-                    &mut stack_popper.token_stack,
-                    binary_operator.to_precedence() + 1
+                    stack_popper.token_stack, right_min_precedence
                 )?;
                 left_expr = Expression {
                     expr_item: ExpressionVariant::BinaryOperation(
@@ -262,7 +437,12 @@ impl Expression {
         tokens: &mut TokenStack
     ) -> Result<Expression, ParseError> {
         // TODO: precedence needs to be forwarded from previous calls
-        // <factor> ::= <int> | <unop> <factor> | "(" <exp> ")"
+        /*
+        <factor> ::= <postfix> | <unop> <factor> | "*" <factor> | "&" <factor>
+            | "(" <type> { "*" } ")" <factor>
+        <postfix> ::= <primary> { "." <identifier> | "->" <identifier> }
+        <primary> ::= <int> | <identifier> | "(" <exp> ")"
+        */
         let wrapped_front_code_token = tokens.peek_front(true)?;
         let front_code_token = wrapped_front_code_token.token.clone();
 
@@ -280,29 +460,80 @@ impl Expression {
                     variant: ParseErrorVariants::UnexpectedToken(format!(
                         "Unexpected token at factor: {token}"
                     )),
-                    token_stack: tokens.soft_copy()
+                    context: tokens.error_context()
                 })
             }
         };
 
+        if Self::is_cast_next(tokens) {
+            Self::parse_as_cast(tokens)
+        } else if Self::is_pointer_operator(&front_code_token) {
+            Self::parse_as_pointer_op(tokens)
+        } else if get_as_unop(&front_code_token).is_ok() {
+            Self::parse_as_unary_op(tokens)
+        } else {
+            Self::parse_as_postfix(tokens)
+        }
+    }
+    fn parse_as_postfix(
+        tokens: &mut TokenStack
+    ) -> Result<Expression, ParseError> {
+        // <postfix> ::= <primary> { "." <identifier> | "->" <identifier> }
+        tokens.run_with_rollback(|stack_popper| {
+            let mut expression = Self::parse_as_primary(stack_popper.token_stack)?;
+            while let Ok(wrapped_token) = stack_popper.token_stack.peek_front(true) {
+                let next_token = wrapped_token.token;
+                let is_pointer_access = match next_token {
+                    Tokens::Punctuator(Punctuators::Dot) => false,
+                    Tokens::Operator(Operators::Arrow) => true,
+                    _ => break
+                };
+
+                stack_popper.expect_pop_front(next_token)?;
+                let member = Identifier::parse_tokens(stack_popper.token_stack)?;
+                let base = Box::new(expression);
+                let expr_item = if is_pointer_access {
+                    ExpressionVariant::PointerMemberAccess(base, member)
+                } else {
+                    ExpressionVariant::MemberAccess(base, member)
+                };
+                expression = Expression {
+                    expr_item,
+                    pop_context: Some(stack_popper.build_pop_context())
+                };
+            }
+            Ok(expression)
+        })
+    }
+    fn parse_as_primary(
+        tokens: &mut TokenStack
+    ) -> Result<Expression, ParseError> {
+        // <primary> ::= <int> | <identifier> | "(" <exp> ")"
+        let wrapped_front_code_token = tokens.peek_front(true)?;
+        let front_code_token = wrapped_front_code_token.token.clone();
+
         if let Tokens::Constant(_) = front_code_token {
             Self::parse_as_constant(tokens)
-        } else if let Ok(_) = get_as_unop(&front_code_token) {
-            Self::parse_as_unary_op(tokens)
+        } else if let Tokens::FloatingConstant(_) = front_code_token {
+            Self::parse_as_constant(tokens)
+        } else if let Tokens::CharLiteral(_) = front_code_token {
+            Self::parse_as_constant(tokens)
+        } else if let Tokens::Identifier(_) = front_code_token {
+            Self::parse_as_variable(tokens)
         } else if let Tokens::Punctuator(Punctuators::OpenParens) = front_code_token {
             Self::parse_as_parens_wrapped(tokens)
         } else {
-            return Err(ParseError {
+            Err(ParseError {
                 variant: ParseErrorVariants::UnexpectedToken(format!(
                     "Unexpected token at factor start \
                     {wrapped_front_code_token}"
                 )),
-                token_stack: tokens.soft_copy()
-            });
+                context: tokens.error_context()
+            })
         }
     }
     fn parse_as_constant(tokens: &mut TokenStack) -> Result<Expression, ParseError> {
-        // <exp> ::= Constant(<int>)
+        // <exp> ::= Constant(<int> | <double>)
         tokens.run_with_rollback(|stack_popper| {
             let constant_wrapped_token_res = stack_popper.pop_front();
             let constant_token_res = match constant_wrapped_token_res {
@@ -311,27 +542,127 @@ impl Expression {
             };
 
             let constant_token = constant_token_res.token;
-            let constant = match constant_token {
-                Tokens::Constant(constant) => constant,
+            let mut ast_constant = match constant_token {
+                Tokens::Constant(constant) => ASTConstant::new(&constant),
+                Tokens::FloatingConstant(constant) => match ASTConstant::new_double(&constant) {
+                    Some(ast_constant) => ast_constant,
+                    None => return Err(ParseError {
+                        variant: ParseErrorVariants::UnexpectedToken(
+                            format!("Invalid floating constant {constant}")
+                        ),
+                        context: stack_popper.token_stack.error_context()
+                    }),
+                },
+                // character literals are just ints in C
+                Tokens::CharLiteral(literal) => match decode_char_literal(&literal) {
+                    Some(value) => ASTConstant::new(&value.to_string()),
+                    None => return Err(ParseError {
+                        variant: ParseErrorVariants::UnexpectedToken(
+                            format!("Invalid character literal {literal}")
+                        ),
+                        context: stack_popper.token_stack.error_context()
+                    }),
+                },
                 _ => return Err(ParseError {
                     variant: ParseErrorVariants::NoMoreTokens(
                         "Constant not found in factor".to_owned()
                     ),
-                    token_stack: stack_popper.token_stack.soft_copy()
+                    context: stack_popper.token_stack.error_context()
                 }),
             };
 
             let pop_context = stack_popper.build_pop_context();
-            let ast_constant = ASTConstant {
-                value: constant.clone(),
-                pop_context: Some(pop_context.clone())
-            };
+            ast_constant.pop_context = Some(pop_context.clone());
             Ok(Expression {
                 expr_item: ExpressionVariant::Constant(ast_constant),
                 pop_context: Some(pop_context.clone())
             })
         })
     }
+    fn parse_as_variable(tokens: &mut TokenStack) -> Result<Expression, ParseError> {
+        // <exp> ::= Variable(<identifier>)
+        tokens.run_with_rollback(|stack_popper| {
+            let identifier = Identifier::parse_tokens(stack_popper.token_stack)?;
+            Ok(Expression {
+                expr_item: ExpressionVariant::Variable(identifier),
+                pop_context: Some(stack_popper.build_pop_context())
+            })
+        })
+    }
+    fn is_cast_next(tokens: &TokenStack) -> bool {
+        // a cast is an open parens followed by a type specifier
+        matches!(
+            (tokens.peek_n(0), tokens.peek_n(1)),
+            (Ok(first), Ok(second))
+            if first.token == Tokens::Punctuator(Punctuators::OpenParens)
+                && matches!(
+                    second.token, Tokens::Keyword(keyword)
+                    if CType::is_type_specifier(&keyword)
+                )
+        )
+    }
+    fn parse_as_cast(
+        tokens: &mut TokenStack
+    ) -> Result<Expression, ParseError> {
+        // <exp> ::= Cast(<type>, <exp>)
+        tokens.run_with_rollback(|stack_popper| {
+            stack_popper.expect_pop_front(Tokens::Punctuator(Punctuators::OpenParens))?;
+            let mut target_type = parse_type_specifiers(stack_popper.token_stack)?;
+            while let Tokens::Operator(Operators::Multiply) =
+                stack_popper.token_stack.peek_front(true)?.token
+            {
+                stack_popper.expect_pop_front(Tokens::Operator(Operators::Multiply))?;
+                target_type = target_type.pointer_to();
+            }
+            stack_popper.expect_pop_front(Tokens::Punctuator(Punctuators::CloseParens))?;
+
+            let sub_expression = Expression::parse_as_factor(stack_popper.token_stack)?;
+            Ok(Expression {
+                expr_item: ExpressionVariant::Cast(target_type, Box::new(sub_expression)),
+                pop_context: Some(stack_popper.build_pop_context())
+            })
+        })
+    }
+    fn is_pointer_operator(token: &Tokens) -> bool {
+        matches!(
+            token,
+            Tokens::Operator(Operators::Multiply) |
+            Tokens::Operator(Operators::BitwiseAnd)
+        )
+    }
+    fn parse_as_pointer_op(
+        tokens: &mut TokenStack
+    ) -> Result<Expression, ParseError> {
+        /*
+        <exp> ::= Dereference(<exp>) | AddressOf(<exp>)
+        "*" and "&" are binary operators as well, so they only
+        act as pointer operators at the start of a factor
+        */
+        tokens.run_with_rollback(|stack_popper| {
+            let operator_token = stack_popper.pop_front()?.token;
+            let sub_expression = Expression::parse_as_factor(stack_popper.token_stack)?;
+            let sub_expression = Box::new(sub_expression);
+
+            let expr_item = match operator_token {
+                Tokens::Operator(Operators::Multiply) => {
+                    ExpressionVariant::Dereference(sub_expression)
+                },
+                Tokens::Operator(Operators::BitwiseAnd) => {
+                    ExpressionVariant::AddressOf(sub_expression)
+                },
+                _ => return Err(ParseError {
+                    variant: ParseErrorVariants::UnexpectedToken(format!(
+                        "Expected pointer operator, got {operator_token}"
+                    )),
+                    context: stack_popper.token_stack.error_context()
+                })
+            };
+            Ok(Self {
+                expr_item,
+                pop_context: Some(stack_popper.build_pop_context())
+            })
+        })
+    }
     fn parse_as_parens_wrapped(
         tokens: &mut TokenStack
     ) -> Result<Expression, ParseError> {
@@ -352,7 +683,7 @@ impl Expression {
                     variant: ParseErrorVariants::UnexpectedToken(
                         "Expected opening parenthesis".to_owned()
                     ),
-                    token_stack: stack_popper.token_stack.soft_copy()
+                    context: stack_popper.token_stack.error_context()
                 });
             }
 
@@ -393,7 +724,7 @@ impl Expression {
                     variant: ParseErrorVariants::NoMoreTokens(
                         "Unary operation not found in expression".to_owned()
                     ),
-                    token_stack: stack_popper.token_stack.soft_copy()
+                    context: stack_popper.token_stack.error_context()
                 }),
             };
 
@@ -410,24 +741,41 @@ impl Expression {
     }
 }
 
+#[derive(Clone, Debug)]
+pub enum StatementVariant {
+    Return(Expression),
+    Expression(Expression),
+    Null
+}
+
+#[derive(Clone, Debug)]
 pub struct Statement {
-    pub(crate) expression: Expression,
-    pop_context: Option<PoppedTokenContext>
+    pub(crate) variant: StatementVariant,
+    pub(crate) pop_context: Option<PoppedTokenContext>
 }
 impl Statement {
-    pub fn new(expression: Expression) -> Statement {
+    pub fn new(variant: StatementVariant) -> Statement {
         Statement {
-            expression,
+            variant,
             pop_context: None,
         }
     }
 
     fn parse(tokens: &mut TokenStack) -> Result<Statement, ParseError> {
         tokens.run_with_rollback(|stack_popper| {
-            // <statement> ::= "return" <exp> ";"
-            stack_popper.expect_pop_front(Tokens::Keyword(Keywords::Return))?;
+            // <statement> ::= "return" <exp> ";" | <exp> ";" | ";"
+            let front_token = stack_popper.token_stack.peek_front(true)?.token;
+            let variant = match front_token {
+                Tokens::Punctuator(Punctuators::Semicolon) => StatementVariant::Null,
+                Tokens::Keyword(Keywords::Return) => {
+                    stack_popper.expect_pop_front(Tokens::Keyword(Keywords::Return))?;
+                    StatementVariant::Return(Expression::parse(stack_popper.token_stack)?)
+                },
+                _ => StatementVariant::Expression(
+                    Expression::parse(stack_popper.token_stack)?
+                )
+            };
 
-            let expression = Expression::parse(stack_popper.token_stack)?;
             let punctuator_keyword_opt = stack_popper.pop_front();
             let punctuator_wrapped_keyword = match punctuator_keyword_opt {
                 Ok(token) => token,
@@ -435,7 +783,7 @@ impl Statement {
                     variant: ParseErrorVariants::NoMoreTokens(
                         "No semicolon token found".to_string()
                     ),
-                    token_stack: stack_popper.clone_stack()
+                    context: stack_popper.token_stack.error_context()
                 }),
             };
 
@@ -446,12 +794,214 @@ impl Statement {
                     variant: ParseErrorVariants::UnexpectedToken(
                         "Statement does not end with semicolon".to_string()
                     ),
-                    token_stack: stack_popper.clone_stack()
+                    context: stack_popper.token_stack.error_context_unpopped()
                 }),
             }
 
             Ok(Statement {
-                expression,
+                variant,
+                pop_context: Some(stack_popper.build_pop_context())
+            })
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Declaration {
+    pub(crate) name: Identifier,
+    pub(crate) var_type: CType,
+    pub(crate) initializer: Option<Expression>,
+    // where the declared name is, for errors about the declaration
+    pub(crate) name_location: Option<SourceLocation>,
+    pub(crate) pop_context: Option<PoppedTokenContext>
+}
+impl Declaration {
+    fn parse(tokens: &mut TokenStack) -> Result<Declaration, ParseError> {
+        tokens.run_with_rollback(|stack_popper| {
+            // <declaration> ::= <type> <declarator> [ "=" <exp> ] ";"
+            let base_type = parse_type_specifiers(stack_popper.token_stack)?;
+            let (var_type, name, name_location) =
+                parse_declarator(stack_popper.token_stack, base_type)?;
+
+            let initializer = match stack_popper.token_stack.peek_front(true)?.token {
+                Tokens::Operator(Operators::AssignEqual) => {
+                    stack_popper.expect_pop_front(
+                        Tokens::Operator(Operators::AssignEqual)
+                    )?;
+                    Some(Expression::parse(stack_popper.token_stack)?)
+                },
+                _ => None
+            };
+            stack_popper.expect_pop_front(Tokens::Punctuator(Punctuators::Semicolon))?;
+
+            Ok(Declaration {
+                name, var_type, initializer, name_location: Some(name_location),
+                pop_context: Some(stack_popper.build_pop_context())
+            })
+        })
+    }
+}
+
+fn parse_declarator(
+    tokens: &mut TokenStack, base_type: CType
+) -> Result<(CType, Identifier, SourceLocation), ParseError> {
+    // <declarator> ::= { "*" } <identifier>
+    tokens.run_with_rollback(|stack_popper| {
+        let mut declared_type = base_type;
+        while let Tokens::Operator(Operators::Multiply) =
+            stack_popper.token_stack.peek_front(true)?.token
+        {
+            stack_popper.expect_pop_front(Tokens::Operator(Operators::Multiply))?;
+            declared_type = declared_type.pointer_to();
+        }
+        let name_location = stack_popper.token_stack.peek_front(true)?.get_start_location();
+        let name = Identifier::parse_tokens(stack_popper.token_stack)?;
+        Ok((declared_type, name, name_location))
+    })
+}
+
+#[derive(Clone, Debug)]
+pub struct StructDeclaration {
+    pub(crate) struct_type: StructType,
+    pub(crate) pop_context: Option<PoppedTokenContext>
+}
+impl StructDeclaration {
+    fn is_next(tokens: &TokenStack) -> bool {
+        // whether the next tokens are ("struct" | "union") <identifier> "{"
+        matches!(
+            (tokens.peek_n(0), tokens.peek_n(1), tokens.peek_n(2)),
+            (Ok(first), Ok(second), Ok(third))
+            if parse_aggregate_kind(&first.token).is_some()
+                && matches!(second.token, Tokens::Identifier(_))
+                && third.token == Tokens::Punctuator(Punctuators::OpenBrace)
+        )
+    }
+    fn parse(tokens: &mut TokenStack) -> Result<StructDeclaration, ParseError> {
+        tokens.run_with_rollback(|stack_popper| {
+            /*
+            <struct_declaration> ::= ( "struct" | "union" ) <identifier>
+                "{" { <member_declaration> }+ "}" ";"
+            <member_declaration> ::= <type> <declarator> ";"
+            */
+            let kind_token = stack_popper.pop_front()?.token;
+            let kind = match parse_aggregate_kind(&kind_token) {
+                Some(kind) => kind,
+                None => return Err(ParseError {
+                    variant: ParseErrorVariants::UnexpectedToken(format!(
+                        "Expected struct or union, got {kind_token}"
+                    )),
+                    context: stack_popper.token_stack.error_context()
+                })
+            };
+            let tag = Identifier::parse_tokens(stack_popper.token_stack)?;
+            stack_popper.expect_pop_front(Tokens::Punctuator(Punctuators::OpenBrace))?;
+
+            let mut members: Vec<StructMember> = vec![];
+            const CLOSE_BRACE: Tokens = Tokens::Punctuator(Punctuators::CloseBrace);
+            while stack_popper.token_stack.peek_front(true)?.token != CLOSE_BRACE {
+                let base_type = parse_type_specifiers(stack_popper.token_stack)?;
+                let (member_type, name, _) =
+                    parse_declarator(stack_popper.token_stack, base_type)?;
+                stack_popper.expect_pop_front(Tokens::Punctuator(Punctuators::Semicolon))?;
+                members.push(StructMember::new(name.name_to_string(), member_type));
+            }
+            stack_popper.expect_pop_front(CLOSE_BRACE)?;
+            stack_popper.expect_pop_front(Tokens::Punctuator(Punctuators::Semicolon))?;
+
+            if members.is_empty() {
+                return Err(ParseError {
+                    variant: ParseErrorVariants::UnexpectedToken(format!(
+                        "{kind} {} has no members", tag.name
+                    )),
+                    context: stack_popper.token_stack.error_context()
+                });
+            }
+            Ok(StructDeclaration {
+                struct_type: StructType::new(kind, tag.name_to_string(), members),
+                pop_context: Some(stack_popper.build_pop_context())
+            })
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum BlockItem {
+    Statement(Statement),
+    Declaration(Declaration),
+    StructDeclaration(StructDeclaration)
+}
+impl BlockItem {
+    fn parse(tokens: &mut TokenStack) -> Result<BlockItem, ParseError> {
+        // <block_item> ::= <statement> | <declaration> | <struct_declaration>
+        if StructDeclaration::is_next(tokens) {
+            return Ok(BlockItem::StructDeclaration(StructDeclaration::parse(tokens)?));
+        }
+        let front_token = tokens.peek_front(true)?.token;
+        match front_token {
+            Tokens::Keyword(keyword) if CType::is_type_specifier(&keyword) => {
+                Ok(BlockItem::Declaration(Declaration::parse(tokens)?))
+            },
+            _ => Ok(BlockItem::Statement(Statement::parse(tokens)?))
+        }
+    }
+}
+
+fn synchronize_to_statement_end(tokens: &mut TokenStack) {
+    /*
+    Skips tokens until just after the next ";" or just before the "}"
+    that closes the current block, ignoring any nested braces.
+    At least one token is always skipped so that parsing makes progress
+    */
+    let mut brace_depth: usize = 0;
+    let mut skipped_any = false;
+    while let Ok(wrapped_token) = tokens.peek_front(false) {
+        match wrapped_token.token {
+            Tokens::Punctuator(Punctuators::CloseBrace) if brace_depth == 0 && skipped_any => {
+                return;
+            },
+            Tokens::Punctuator(Punctuators::CloseBrace) => {
+                brace_depth = brace_depth.saturating_sub(1);
+            },
+            Tokens::Punctuator(Punctuators::OpenBrace) => brace_depth += 1,
+            _ => {}
+        }
+        let is_statement_end = matches!(
+            wrapped_token.token, Tokens::Punctuator(Punctuators::Semicolon)
+        );
+        if tokens.pop_front().is_err() { return; }
+        skipped_any = true;
+        if is_statement_end && brace_depth == 0 { return; }
+    }
+}
+
+fn parse_function_signature(tokens: &mut TokenStack) -> Result<(CType, Identifier), ParseError> {
+    // <type> <identifier> "(" "void" ")"
+    tokens.run_with_rollback(|stack_popper| {
+        let return_type = parse_type_specifiers(stack_popper.token_stack)?;
+        let identifier = Identifier::parse_tokens(stack_popper.token_stack)?;
+
+        stack_popper.expect_pop_front(Tokens::Punctuator(Punctuators::OpenParens))?;
+        stack_popper.expect_pop_front(Tokens::Keyword(Keywords::Void))?;
+        stack_popper.expect_pop_front(Tokens::Punctuator(Punctuators::CloseParens))?;
+        Ok((return_type, identifier))
+    })
+}
+
+#[derive(Clone, Debug)]
+pub struct FunctionDeclaration {
+    // a function prototype without a body, which gets no code of its own
+    pub(crate) name: Identifier,
+    pub(crate) return_type: CType,
+    pub(crate) pop_context: Option<PoppedTokenContext>
+}
+impl FunctionDeclaration {
+    fn parse(tokens: &mut TokenStack) -> Result<FunctionDeclaration, ParseError> {
+        // <function_declaration> ::= <type> <identifier> "(" "void" ")" ";"
+        tokens.run_with_rollback(|stack_popper| {
+            let (return_type, name) = parse_function_signature(stack_popper.token_stack)?;
+            stack_popper.expect_pop_front(Tokens::Punctuator(Punctuators::Semicolon))?;
+            Ok(FunctionDeclaration {
+                name, return_type,
                 pop_context: Some(stack_popper.build_pop_context())
             })
         })
@@ -460,47 +1010,114 @@ impl Statement {
 
 pub struct ASTFunction {
     pub(crate) name: Identifier,
-    pub(crate) body: Statement,
+    pub(crate) return_type: CType,
+    pub(crate) body: Vec<BlockItem>,
     pub(crate) pop_context: Option<PoppedTokenContext>
 }
 impl ASTFunction {
-    pub fn new(name: Identifier, body: Statement) -> ASTFunction {
+    pub fn new(name: Identifier, body: Vec<BlockItem>) -> ASTFunction {
         ASTFunction {
             name,
+            return_type: CType::Int,
             body,
             pop_context: None,
         }
     }
 
-    fn parse(tokens: &mut TokenStack) -> Result<ASTFunction, ParseError> {
+    fn parse(
+        tokens: &mut TokenStack, errors: &mut Vec<ParseError>
+    ) -> Result<ASTFunction, ParseError> {
+        /*
+        Errors in block items are recovered from and added to errors,
+        the function itself only fails if its signature or braces are invalid
+        */
         tokens.run_with_rollback(|stack_popper| {
-            // <function> ::= "int" <identifier> "(" "void" ")" "{" <statement> "}"
-            stack_popper.expect_pop_front(Tokens::Keyword(Keywords::Integer))?;
-            let identifier = Identifier::parse_tokens(&mut stack_popper.token_stack)?;
-
-            stack_popper.expect_pop_front(Tokens::Punctuator(Punctuators::OpenParens))?;
-            stack_popper.expect_pop_front(Tokens::Keyword(Keywords::Void))?;
-            stack_popper.expect_pop_front(Tokens::Punctuator(Punctuators::CloseParens))?;
-
+            /*
+            <function> ::= <type> <identifier> "(" "void" ")"
+                "{" { <block_item> } "}"
+            */
+            let (return_type, identifier) = parse_function_signature(stack_popper.token_stack)?;
             stack_popper.expect_pop_front(Tokens::Punctuator(Punctuators::OpenBrace))?;
-            let statement = Statement::parse(&mut stack_popper.token_stack)?;
-            stack_popper.expect_pop_front(Tokens::Punctuator(Punctuators::CloseBrace))?;
+            let mut body: Vec<BlockItem> = vec![];
+            const CLOSE_BRACE: Tokens = Tokens::Punctuator(Punctuators::CloseBrace);
+            while stack_popper.token_stack.peek_front(true)?.token != CLOSE_BRACE {
+                match BlockItem::parse(stack_popper.token_stack) {
+                    Ok(block_item) => body.push(block_item),
+                    Err(error) => {
+                        // keep parsing from the next statement to find more errors
+                        errors.push(error);
+                        synchronize_to_statement_end(stack_popper.token_stack);
+                    }
+                }
+            }
+            stack_popper.expect_pop_front(CLOSE_BRACE)?;
 
             Ok(ASTFunction {
-                name: identifier, body: statement,
+                name: identifier, return_type, body,
                 pop_context: Some(stack_popper.build_pop_context())
             })
         })
     }
 }
 
+fn parse_aggregate_kind(token: &Tokens) -> Option<AggregateKind> {
+    match token {
+        Tokens::Keyword(keyword) => AggregateKind::from_keyword(keyword),
+        _ => None
+    }
+}
+
+fn parse_type_specifiers(tokens: &mut TokenStack) -> Result<CType, ParseError> {
+    /*
+    <type> ::= { "char" | "int" | "long" | "signed" | "unsigned" }+
+        | ( "struct" | "union" ) <identifier>
+    */
+    tokens.run_with_rollback(|stack_popper| {
+        let front_token = stack_popper.token_stack.peek_front(true)?.token;
+        if let Some(kind) = parse_aggregate_kind(&front_token) {
+            // the members of the struct are resolved during tacky generation
+            stack_popper.expect_pop_front(front_token)?;
+            let tag = Identifier::parse_tokens(stack_popper.token_stack)?;
+            return Ok(CType::Struct(
+                StructType::new_incomplete(kind, tag.name_to_string())
+            ));
+        }
+
+        let mut specifiers: Vec<Keywords> = vec![];
+        while let Ok(wrapped_token) = stack_popper.token_stack.peek_front(true) {
+            match wrapped_token.token {
+                Tokens::Keyword(keyword) if CType::is_type_specifier(&keyword) => {
+                    stack_popper.expect_pop_front(Tokens::Keyword(keyword))?;
+                    specifiers.push(keyword);
+                },
+                _ => break
+            }
+        }
+
+        match CType::from_specifiers(&specifiers) {
+            Ok(c_type) => Ok(c_type),
+            Err(message) => Err(ParseError {
+                variant: ParseErrorVariants::UnexpectedToken(message),
+                context: stack_popper.token_stack.error_context()
+            })
+        }
+    })
+}
+
 pub struct ASTProgram {
+    pub struct_declarations: Vec<StructDeclaration>,
+    // file scope variable definitions
+    pub global_declarations: Vec<Declaration>,
+    pub function_declarations: Vec<FunctionDeclaration>,
     pub function: ASTFunction,
     pub pop_context: Option<PoppedTokenContext>
 }
 impl ASTProgram {
     pub fn new(function: ASTFunction) -> ASTProgram {
         ASTProgram {
+            struct_declarations: vec![],
+            global_declarations: vec![],
+            function_declarations: vec![],
             function,
             pop_context: None,
         }
@@ -508,45 +1125,117 @@ impl ASTProgram {
 }
 
 pub fn parse(tokens: &mut TokenStack) -> Result<ASTProgram, ParseError> {
-    // <program> ::= <function>
-    tokens.run_with_rollback(|stack_popper| {
-        let function = ASTFunction::parse(stack_popper.token_stack)?;
+    // parses the program, returning only the first error encountered
+    parse_with_diagnostics(tokens).map_err(|mut errors| errors.remove(0))
+}
+
+pub fn parse_with_diagnostics(
+    tokens: &mut TokenStack
+) -> Result<ASTProgram, Vec<ParseError>> {
+    /*
+    <program> ::= { <struct_declaration> | <declaration> | <function_declaration> } <function>
+    Returns every error found in the program (in source order) if it is invalid
+    */
+    let mut errors: Vec<ParseError> = vec![];
+    let parse_result = tokens.run_with_rollback(|stack_popper| {
+        let mut struct_declarations: Vec<StructDeclaration> = vec![];
+        let mut global_declarations: Vec<Declaration> = vec![];
+        let mut function_declarations: Vec<FunctionDeclaration> = vec![];
+        loop {
+            if StructDeclaration::is_next(stack_popper.token_stack) {
+                struct_declarations.push(StructDeclaration::parse(stack_popper.token_stack)?);
+                continue;
+            }
+            if let Ok(declaration) = FunctionDeclaration::parse(stack_popper.token_stack) {
+                function_declarations.push(declaration);
+                continue;
+            }
+            // declarations are rolled back if the function definition comes next
+            match Declaration::parse(stack_popper.token_stack) {
+                Ok(declaration) => global_declarations.push(declaration),
+                Err(_) => break
+            }
+        }
+        let function = ASTFunction::parse(stack_popper.token_stack, &mut errors)?;
         if !stack_popper.is_empty() {
             return Err(ParseError {
                 variant: ParseErrorVariants::UnexpectedExtraTokens(
                     "Unexpected tokens after function".to_string()
                 ),
-                token_stack: stack_popper.clone_stack()
+                context: stack_popper.token_stack.error_context()
             });
         }
         Ok(ASTProgram {
+            struct_declarations,
+            global_declarations,
+            function_declarations,
             function,
             pop_context: Some(stack_popper.build_pop_context())
         })
-    })
+    });
+
+    match parse_result {
+        Ok(program) if errors.is_empty() => Ok(program),
+        Ok(_) => Err(errors),
+        Err(error) => {
+            errors.push(error);
+            Err(errors)
+        }
+    }
 }
 
 pub fn parse_from_filepath(file_path: &str, verbose: bool) -> Result<ASTProgram, ParseError> {
-    let lex_result = lex_from_filepath(file_path, verbose);
+    parse_from_filepath_with_diagnostics(file_path, verbose)
+        .map_err(|mut errors| errors.remove(0))
+}
+
+pub fn parse_from_filepath_with_diagnostics(
+    file_path: &str, verbose: bool
+) -> Result<ASTProgram, Vec<ParseError>> {
+    parse_lexed_with_diagnostics(lex_from_filepath(file_path, verbose))
+}
+
+pub fn parse_from_reader(reader: impl Read, verbose: bool) -> Result<ASTProgram, ParseError> {
+    parse_lexed_with_diagnostics(lex_from_reader(reader, verbose))
+        .map_err(|mut errors| errors.remove(0))
+}
+
+pub fn parse_from_str(source: &str) -> Result<ASTProgram, ParseError> {
+    parse_from_str_with_diagnostics(source)
+        .map_err(|mut errors| errors.remove(0))
+}
+
+pub fn parse_from_str_with_diagnostics(source: &str) -> Result<ASTProgram, Vec<ParseError>> {
+    parse_lexed_with_diagnostics(lex_from_str(source))
+}
+
+fn parse_lexed_with_diagnostics(
+    lex_result: Result<Vec<WrappedToken>, LexerFromFileError>
+) -> Result<ASTProgram, Vec<ParseError>> {
     if lex_result.is_err() {
-        return Err(ParseError {
+        return Err(vec![ParseError {
             variant: ParseErrorVariants::LexerError(lex_result.err().unwrap()),
-            token_stack: TokenStack::new(VecDeque::new())
-        })
+            context: ParseErrorContext::default()
+        }])
     }
 
     let tokens = lex_result.unwrap();
     let mut token_stack = TokenStack::new_from_vec(tokens);
-    let parse_result = parse(&mut token_stack);
-    parse_result
+    parse_with_diagnostics(&mut token_stack)
 }
 
 
 #[cfg(test)]
 mod tests {
-    use crate::lexer::lexer::lex_from_filepath;
-    use crate::parser::parse::{parse, parse_from_filepath};
-    use crate::parser::parser_helpers::TokenStack;
+    use crate::lexer::lexer::{lex_from_filepath, Lexer, SourceLocation, Tokens};
+    use crate::lexer::tokens::{Operators, Punctuators};
+    use crate::parser::c_types::CType;
+    use crate::parser::parse::{
+        parse, parse_from_filepath, parse_from_reader, parse_from_str, parse_with_diagnostics,
+        ASTConstant, BlockItem,
+        ExpressionVariant, StatementVariant, SupportedBinaryOperators
+    };
+    use crate::parser::parser_helpers::{ParseErrorVariants, TokenStack};
 
     #[test]
     fn test_parse_unop_parens() {
@@ -564,6 +1253,37 @@ mod tests {
         assert_eq!(program.function.name.name_to_string(), "main");
     }
     #[test]
+    fn test_parse_from_str() {
+        let program = parse_from_str("int main(void) { return 3; }").unwrap();
+        assert_eq!(program.function.name.name_to_string(), "main");
+        let program = parse_from_reader("int f(void) { return 1; }".as_bytes(), false).unwrap();
+        assert_eq!(program.function.name.name_to_string(), "f");
+
+        let err = parse_from_str("int main(void) { return @; }").err().unwrap();
+        assert!(matches!(err.variant, ParseErrorVariants::LexerError(_)));
+        assert!(parse_from_str("int main(void) { return 3 }").is_err());
+    }
+    #[test]
+    fn test_token_lookahead_and_checkpoints() {
+        let tokens = Lexer::new().tokenize("a /* note */ = b + 1;").unwrap();
+        let mut token_stack = TokenStack::new_from_vec(tokens);
+        assert_eq!(token_stack.peek_n(1).unwrap().token, Tokens::Operator(Operators::AssignEqual));
+        assert_eq!(token_stack.peek_n(3).unwrap().token, Tokens::Operator(Operators::Add));
+        assert!(token_stack.peek_n(6).is_err());
+
+        let checkpoint = token_stack.checkpoint();
+        token_stack.pop_front().unwrap();
+        token_stack.pop_front().unwrap();
+        let after_comment = token_stack.checkpoint();
+        token_stack.pop_front().unwrap();
+        assert_eq!(token_stack.peek_n(0).unwrap().token, Tokens::Identifier("b".to_string()));
+
+        token_stack.rollback_to(checkpoint).unwrap();
+        assert_eq!(token_stack.peek_n(0).unwrap().token, Tokens::Identifier("a".to_string()));
+        assert_eq!(token_stack.get_current_token_position(), 0);
+        assert!(token_stack.rollback_to(after_comment).is_err());
+    }
+    #[test]
     fn test_parse_sub_neg() {
         let file_path = "./writing-a-c-compiler-tests/tests/chapter_3/valid/sub_neg.c";
         let lex_result = lex_from_filepath(file_path, true);
@@ -598,4 +1318,239 @@ mod tests {
         let program = parse_result.unwrap();
         assert_eq!(program.function.name.name_to_string(), "main");
     }
+
+    #[test]
+    fn test_constant_bases() {
+        let constant = |value: &str| {
+            let ast_constant = ASTConstant::new(value);
+            (ast_constant.value, ast_constant.const_type)
+        };
+        assert_eq!(constant("0x1F"), ("31".to_string(), CType::Int));
+        assert_eq!(constant("017"), ("15".to_string(), CType::Int));
+        assert_eq!(constant("0b101"), ("5".to_string(), CType::Int));
+        assert_eq!(constant("0"), ("0".to_string(), CType::Int));
+        assert_eq!(constant("0XffUL"), ("255".to_string(), CType::UnsignedLong));
+        // hexadecimal constants that don't fit in an int can be unsigned
+        assert_eq!(constant("0xFFFFFFFF"), ("4294967295".to_string(), CType::UnsignedInt));
+        assert_eq!(constant("4294967295"), ("4294967295".to_string(), CType::Long));
+    }
+
+    #[test]
+    fn test_parse_bitwise_precedence() {
+        // 1 | (2 ^ (3 & (4 << 1)))
+        let source = "int main(void) { return 1 | 2 ^ 3 & 4 << 1; }";
+        let tokens = Lexer::new().tokenize(source).unwrap();
+        let mut token_stack = TokenStack::new_from_vec(tokens);
+        let program = parse(&mut token_stack).unwrap();
+        let mut expression = match &program.function.body[0] {
+            BlockItem::Statement(statement) => match &statement.variant {
+                StatementVariant::Return(expression) => expression.clone(),
+                other => panic!("Expected return statement, got {:?}", other)
+            },
+            other => panic!("Expected statement, got {:?}", other)
+        };
+
+        let expected_operators = [
+            SupportedBinaryOperators::BitwiseOr,
+            SupportedBinaryOperators::BitwiseXor,
+            SupportedBinaryOperators::BitwiseAnd,
+            SupportedBinaryOperators::LeftShift,
+        ];
+        for expected_operator in expected_operators {
+            expression = match expression.expr_item {
+                ExpressionVariant::BinaryOperation(operator, left, right) => {
+                    assert_eq!(operator, expected_operator);
+                    assert!(matches!(left.expr_item, ExpressionVariant::Constant(_)));
+                    *right
+                },
+                other => panic!("Expected binary operation, got {:?}", other)
+            };
+        }
+    }
+
+    #[test]
+    fn test_parse_assignment_right_associative() {
+        let program = parse_from_str("int main(void) { int a; int b; a = b = 3 + 4; }").unwrap();
+        let expression = match &program.function.body[2] {
+            BlockItem::Statement(statement) => match &statement.variant {
+                StatementVariant::Expression(expression) => expression.clone(),
+                other => panic!("Expected expression statement, got {:?}", other)
+            },
+            other => panic!("Expected statement, got {:?}", other)
+        };
+        match expression.expr_item {
+            ExpressionVariant::BinaryOperation(SupportedBinaryOperators::AssignEqual, left, right) => {
+                assert!(matches!(left.expr_item, ExpressionVariant::Variable(_)));
+                assert!(matches!(
+                    right.expr_item,
+                    ExpressionVariant::BinaryOperation(SupportedBinaryOperators::AssignEqual, _, _)
+                ));
+            },
+            other => panic!("Expected assignment, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn test_parse_casts() {
+        let source = "int main(void) { long x = 1; return (int)(long *)x + (x); }";
+        let tokens = Lexer::new().tokenize(source).unwrap();
+        let mut token_stack = TokenStack::new_from_vec(tokens);
+        let program = parse(&mut token_stack).unwrap();
+        let body = &program.function.body;
+        assert_eq!(body.len(), 2);
+
+        let return_expression = match &body[1] {
+            BlockItem::Statement(statement) => match &statement.variant {
+                StatementVariant::Return(expression) => expression,
+                other => panic!("Expected return statement, got {:?}", other)
+            },
+            other => panic!("Expected statement, got {:?}", other)
+        };
+        let (left, right) = match &return_expression.expr_item {
+            ExpressionVariant::BinaryOperation(_, left, right) => (left, right),
+            other => panic!("Expected binary operation, got {:?}", other)
+        };
+        match &left.expr_item {
+            ExpressionVariant::Cast(outer_type, inner) => {
+                assert_eq!(*outer_type, CType::Int);
+                assert!(matches!(
+                    &inner.expr_item,
+                    ExpressionVariant::Cast(inner_type, _)
+                    if *inner_type == CType::Long.pointer_to()
+                ));
+            },
+            other => panic!("Expected cast, got {:?}", other)
+        }
+        // a parenthesized expression is not a cast
+        assert!(matches!(right.expr_item, ExpressionVariant::ParensWrapped(_)));
+    }
+
+    #[test]
+    fn test_parse_pointer_declarations() {
+        let source = "int main(void) { int x = 1; int **p; *p = &x; return *x; }";
+        let tokens = Lexer::new().tokenize(source).unwrap();
+        let mut token_stack = TokenStack::new_from_vec(tokens);
+        let program = parse(&mut token_stack).unwrap();
+        let body = &program.function.body;
+        assert_eq!(body.len(), 4);
+
+        match &body[1] {
+            BlockItem::Declaration(declaration) => {
+                assert_eq!(declaration.name.name_to_string(), "p");
+                assert_eq!(declaration.var_type, CType::Int.pointer_to().pointer_to());
+                assert!(declaration.initializer.is_none());
+            },
+            other => panic!("Expected declaration, got {:?}", other)
+        }
+        match &body[2] {
+            BlockItem::Statement(statement) => match &statement.variant {
+                StatementVariant::Expression(expression) => match &expression.expr_item {
+                    ExpressionVariant::BinaryOperation(_, left, right) => {
+                        assert!(matches!(left.expr_item, ExpressionVariant::Dereference(_)));
+                        assert!(matches!(right.expr_item, ExpressionVariant::AddressOf(_)));
+                    },
+                    other => panic!("Expected assignment, got {:?}", other)
+                },
+                other => panic!("Expected expression statement, got {:?}", other)
+            },
+            other => panic!("Expected statement, got {:?}", other)
+        }
+    }
+
+    #[test]
+    fn test_parse_global_declarations() {
+        let source = "struct pair { int a; int b; };
+            long total = -3; struct pair p; char *name;
+            int main(void) { return total; }";
+        let tokens = Lexer::new().tokenize(source).unwrap();
+        let mut token_stack = TokenStack::new_from_vec(tokens);
+        let program = parse(&mut token_stack).unwrap();
+
+        assert_eq!(program.struct_declarations.len(), 1);
+        let globals = &program.global_declarations;
+        assert_eq!(globals.len(), 3);
+        assert_eq!(globals[0].name.name_to_string(), "total");
+        assert_eq!(globals[0].var_type, CType::Long);
+        assert!(globals[0].initializer.is_some());
+        assert_eq!(globals[1].var_type.to_string(), "struct pair");
+        assert!(globals[2].initializer.is_none());
+        assert_eq!(program.function.name.name_to_string(), "main");
+    }
+
+    #[test]
+    fn test_parse_error_location() {
+        let source = "int main(void) {\n    return 1\n}";
+        let tokens = Lexer::new().tokenize(source).unwrap();
+        let mut token_stack = TokenStack::new_from_vec(tokens);
+        let error = parse(&mut token_stack).err().unwrap();
+        // the error points at the brace where the semicolon was expected
+        assert_eq!(error.location(), Some(SourceLocation::new(3, 1)));
+        assert!(error.to_string().starts_with("ParseError at line 3:1"));
+        assert_eq!(error.context.upcoming_tokens, vec![Tokens::Punctuator(Punctuators::CloseBrace)]);
+
+        let source = "int main(void) {\n    return 1;\n}";
+        let tokens = Lexer::new().tokenize(source).unwrap();
+        let mut token_stack = TokenStack::new_from_vec(tokens);
+        let program = parse(&mut token_stack).unwrap();
+        let pop_context = program.function.pop_context.unwrap();
+        assert_eq!(pop_context.start_location, Some(SourceLocation::new(1, 1)));
+        assert_eq!(pop_context.end_location, Some(SourceLocation::new(3, 2)));
+    }
+
+    #[test]
+    fn test_parse_multiple_errors() {
+        let source = "int main(void) {\n    int a = 1 +;\n    a;\n    return a *;\n    { ; }\n}";
+        let tokens = Lexer::new().tokenize(source).unwrap();
+        let mut token_stack = TokenStack::new_from_vec(tokens);
+        let errors = parse_with_diagnostics(&mut token_stack).err().unwrap();
+        let lines: Vec<usize> = errors.iter().map(
+            |error| error.location().unwrap().line
+        ).collect();
+        // the stray block is skipped over as a single statement
+        assert_eq!(lines, vec![2, 4, 5]);
+
+        let source = "int main(void) { return 1; }";
+        let tokens = Lexer::new().tokenize(source).unwrap();
+        let mut token_stack = TokenStack::new_from_vec(tokens);
+        assert!(parse_with_diagnostics(&mut token_stack).is_ok());
+    }
+
+    #[test]
+    fn test_parse_struct_member_access() {
+        let source = "struct pair { int a; long *b; };
+            int main(void) { struct pair p; return p.a + *(&p)->b; }";
+        let tokens = Lexer::new().tokenize(source).unwrap();
+        let mut token_stack = TokenStack::new_from_vec(tokens);
+        let program = parse(&mut token_stack).unwrap();
+
+        let struct_type = &program.struct_declarations[0].struct_type;
+        assert_eq!(struct_type.tag, "pair");
+        assert_eq!(struct_type.layout().unwrap().size, 16);
+        match &program.function.body[0] {
+            BlockItem::Declaration(declaration) => {
+                assert_eq!(declaration.var_type.to_string(), "struct pair");
+            },
+            other => panic!("Expected declaration, got {:?}", other)
+        }
+        let return_expression = match &program.function.body[1] {
+            BlockItem::Statement(statement) => match &statement.variant {
+                StatementVariant::Return(expression) => expression.clone(),
+                other => panic!("Expected return statement, got {:?}", other)
+            },
+            other => panic!("Expected statement, got {:?}", other)
+        };
+        match return_expression.expr_item {
+            ExpressionVariant::BinaryOperation(_, left, right) => {
+                assert!(matches!(left.expr_item, ExpressionVariant::MemberAccess(_, _)));
+                // the postfix -> binds tighter than the unary *
+                match right.expr_item {
+                    ExpressionVariant::Dereference(inner) => assert!(matches!(
+                        inner.expr_item, ExpressionVariant::PointerMemberAccess(_, _)
+                    )),
+                    other => panic!("Expected dereference, got {:?}", other)
+                }
+            },
+            other => panic!("Expected binary operation, got {:?}", other)
+        }
+    }
 }
@@ -1,8 +1,10 @@
 use std::collections::VecDeque;
 use std::fmt::{Debug, Display, Formatter};
+use serde::{Deserialize, Serialize};
 use crate::lexer::lexer::{
-    LexerFromFileError, Tokens, WrappedToken
+    LexerFromFileError, SourceLocation, Tokens, WrappedToken
 };
+use crate::semantic::identifier_resolution::SemanticError;
 
 /*
 Recursive descent parser_helpers implementation
@@ -16,25 +18,51 @@ pub enum ParseErrorVariants {
     NoMoreTokens(String),
     UnexpectedToken(String),
     UnexpectedExtraTokens(String),
-    LexerError(LexerFromFileError)
+    LexerError(LexerFromFileError),
+    SemanticError(SemanticError)
+}
+
+// number of upcoming tokens kept in a ParseErrorContext
+const ERROR_CONTEXT_WINDOW: usize = 4;
+
+#[derive(Clone, Debug, Default)]
+pub struct ParseErrorContext {
+    /*
+    Where in the token stack a ParseError happened. This is taken on
+    every failed parse attempt, including the ones that get backtracked
+    out of, so it only keeps a few tokens instead of the whole stack
+    */
+    pub token_position: usize,
+    pub location: Option<SourceLocation>,
+    pub upcoming_tokens: Vec<Tokens>,
 }
 
 #[derive(Debug)]
 pub struct ParseError {
     pub(crate) variant: ParseErrorVariants,
-    pub(crate) token_stack: TokenStack
+    pub(crate) context: ParseErrorContext
 }
 impl ParseError {
     pub fn new(message: String, token_stack: &TokenStack) -> ParseError {
         ParseError {
             variant: ParseErrorVariants::GenericError(message),
-            token_stack: token_stack.soft_copy(),
+            context: token_stack.error_context(),
         }
     }
     pub fn new_without_stack(error_variant: ParseErrorVariants) -> ParseError {
         ParseError {
             variant: error_variant,
-            token_stack: TokenStack::new(VecDeque::new()),
+            context: ParseErrorContext::default(),
+        }
+    }
+    pub fn location(&self) -> Option<SourceLocation> {
+        // location of the token that the error points at, if any
+        match &self.variant {
+            ParseErrorVariants::LexerError(LexerFromFileError::InvalidToken(token)) => {
+                Some(token.location())
+            },
+            ParseErrorVariants::SemanticError(err) => err.location().or(self.context.location),
+            _ => self.context.location
         }
     }
     pub fn message(&self) -> String {
@@ -44,14 +72,25 @@ impl ParseError {
             ParseErrorVariants::UnexpectedToken(msg) => msg.clone(),
             ParseErrorVariants::UnexpectedExtraTokens(msg) => msg.clone(),
             ParseErrorVariants::LexerError(err) => format!("Lexer error: {}", err),
+            ParseErrorVariants::SemanticError(err) => format!("Semantic error: {}", err.message()),
         }
     }
 }
 impl Display for ParseError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "ParseError: {}", self.message())
+        match self.location() {
+            Some(location) => write!(f, "ParseError at line {}: {}", location, self.message()),
+            None => write!(f, "ParseError: {}", self.message())
+        }
     }
 }
+impl std::error::Error for ParseError {}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TokenCheckpoint {
+    // number of tokens that had been popped when the checkpoint was taken
+    token_position: usize,
+}
 
 #[derive(Debug)]
 pub struct TokenStack {
@@ -64,7 +103,7 @@ impl TokenStack {
             None => {
                 Err(ParseError {
                     variant: ParseErrorVariants::NoMoreTokens("".to_owned()),
-                    token_stack: self.soft_copy()
+                    context: self.error_context()
                 })
             }
             Some(token) => { Ok(token) }
@@ -89,7 +128,7 @@ impl TokenStack {
         } else {
             Err(ParseError {
                 variant: ParseErrorVariants::NoMoreTokens("No tokens to rollback".to_string()),
-                token_stack: self.soft_copy()
+                context: self.error_context()
             })
         }
     }
@@ -102,6 +141,33 @@ impl TokenStack {
         }
     }
 
+    pub fn error_context(&self) -> ParseErrorContext {
+        ParseErrorContext {
+            token_position: self.get_current_token_position(),
+            location: self.get_current_source_location(),
+            upcoming_tokens: self.tokens.iter().take(ERROR_CONTEXT_WINDOW)
+                .map(|wrapped_token| wrapped_token.token.clone()).collect(),
+        }
+    }
+
+    pub fn error_context_unpopped(&self) -> ParseErrorContext {
+        /*
+        error context with the last popped token put back, so that
+        errors about an unexpected token point at the token itself
+        */
+        let Some(last_popped) = self.popped_tokens.last() else {
+            return self.error_context();
+        };
+        let upcoming_tokens = std::iter::once(last_popped)
+            .chain(self.tokens.iter().take(ERROR_CONTEXT_WINDOW - 1))
+            .map(|wrapped_token| wrapped_token.token.clone()).collect();
+        ParseErrorContext {
+            token_position: self.get_current_token_position() - 1,
+            location: Some(last_popped.get_start_location()),
+            upcoming_tokens,
+        }
+    }
+
     pub fn is_empty(&self) -> bool {
         self.tokens.is_empty()
     }
@@ -124,7 +190,7 @@ impl TokenStack {
                         variant: ParseErrorVariants::NoMoreTokens(
                             "No more tokens available".to_string()
                         ),
-                        token_stack: self.soft_copy()
+                        context: self.error_context()
                     });
                 }
             };
@@ -147,7 +213,7 @@ impl TokenStack {
                 variant: ParseErrorVariants::UnexpectedToken(format!(
                     "Unexpected token [{}]", popped_token
                 ).to_string()),
-                token_stack: self.soft_copy()
+                context: self.error_context_unpopped()
             })
         }
     }
@@ -166,10 +232,47 @@ impl TokenStack {
             variant: ParseErrorVariants::NoMoreTokens(
                 "No non-comment tokens available".to_string()
             ),
-            token_stack: self.soft_copy()
+            context: self.error_context()
         })
     }
 
+    pub fn peek_n(&self, k: usize) -> Result<&WrappedToken, ParseError> {
+        // k-th upcoming non-comment token, with peek_n(0) being the front token
+        let code_token = self.tokens.iter().filter(
+            |wrapped_token| !matches!(wrapped_token.token, Tokens::Comment(_))
+        ).nth(k);
+        code_token.ok_or_else(|| ParseError {
+            variant: ParseErrorVariants::NoMoreTokens(format!(
+                "Fewer than {} non-comment tokens available", k + 1
+            )),
+            context: self.error_context()
+        })
+    }
+
+    pub fn checkpoint(&self) -> TokenCheckpoint {
+        TokenCheckpoint { token_position: self.get_current_token_position() }
+    }
+
+    pub fn rollback_to(&mut self, checkpoint: TokenCheckpoint) -> Result<(), ParseError> {
+        /*
+        puts back every token popped since the checkpoint was taken,
+        which is cheaper than restoring from a soft_copy of the stack
+        */
+        let current_position = self.get_current_token_position();
+        if checkpoint.token_position > current_position {
+            return Err(ParseError {
+                variant: ParseErrorVariants::GenericError(
+                    "Checkpoint is ahead of the token stack".to_string()
+                ),
+                context: self.error_context()
+            });
+        }
+        for _ in checkpoint.token_position..current_position {
+            self.rollback_once()?;
+        }
+        Ok(())
+    }
+
     pub fn new(tokens: VecDeque<WrappedToken>) -> TokenStack {
         TokenStack { tokens, popped_tokens: vec![] }
     }
@@ -186,6 +289,19 @@ impl TokenStack {
         }
     }
 
+    pub fn get_current_source_location(&self) -> Option<SourceLocation> {
+        /*
+        line and column of the next token, or the end of the
+        last popped token if there are no tokens left
+        */
+        match self.tokens.front() {
+            Some(wrapped_token) => Some(wrapped_token.get_start_location()),
+            None => self.popped_tokens.last().map(
+                |wrapped_token| wrapped_token.get_end_location()
+            )
+        }
+    }
+
     pub fn get_current_token_position(&self) -> usize {
         // current position in the original token stack
         self.popped_tokens.len()
@@ -208,12 +324,43 @@ impl TokenStack {
     }
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct PoppedTokenContext {
     pub start_token_position: usize,
     pub end_token_position: usize,
     pub start_source_position: usize,
     pub end_source_position: usize,
+    pub start_location: Option<SourceLocation>,
+    pub end_location: Option<SourceLocation>,
+}
+impl PoppedTokenContext {
+    pub fn merge(&self, other: &PoppedTokenContext) -> PoppedTokenContext {
+        // smallest context that covers both contexts and anything between them
+        let first = match self.start_token_position <= other.start_token_position {
+            true => self,
+            false => other
+        };
+        let last = match self.end_token_position >= other.end_token_position {
+            true => self,
+            false => other
+        };
+        PoppedTokenContext {
+            start_token_position: first.start_token_position,
+            end_token_position: last.end_token_position,
+            start_source_position: first.start_source_position,
+            end_source_position: last.end_source_position,
+            start_location: first.start_location,
+            end_location: last.end_location,
+        }
+    }
+    pub fn merge_options(
+        first: &Option<PoppedTokenContext>, second: &Option<PoppedTokenContext>
+    ) -> Option<PoppedTokenContext> {
+        match (first, second) {
+            (Some(first), Some(second)) => Some(first.merge(second)),
+            _ => first.clone().or_else(|| second.clone())
+        }
+    }
 }
 
 
@@ -222,15 +369,18 @@ pub struct StackPopper<'a> {
     pub(crate) token_stack: &'a mut TokenStack,
     start_source_position: usize,
     start_token_position: usize,
+    start_location: Option<SourceLocation>,
 }
 impl StackPopper<'_> {
     pub fn new(token_stack: &mut TokenStack) -> StackPopper {
         let start_source_position = token_stack.get_current_source_position();
         let start_token_position = token_stack.get_current_token_position();
+        let start_location = token_stack.get_current_source_location();
         StackPopper {
             token_stack,
             start_source_position,
             start_token_position,
+            start_location,
         }
     }
 
@@ -238,10 +388,6 @@ impl StackPopper<'_> {
         self.token_stack.is_empty()
     }
 
-    pub fn clone_stack(&self) -> TokenStack {
-        self.token_stack.soft_copy()
-    }
-
     pub fn pop_front(&mut self) -> Result<WrappedToken, ParseError> {
         self.token_stack.pop_front()
     }
@@ -261,6 +407,11 @@ impl StackPopper<'_> {
             end_token_position: current_token_position,
             start_source_position: self.start_source_position,
             end_source_position: current_source_position,
+            start_location: self.start_location,
+            // end of the last consumed token rather than the start of the next one
+            end_location: self.token_stack.popped_tokens.last().map(
+                |wrapped_token| wrapped_token.get_end_location()
+            ),
         }
     }
 
@@ -271,14 +422,9 @@ impl StackPopper<'_> {
 
     pub fn rollback(&mut self) -> Result<(), ParseError> {
         // Rollback the token stack to the state before this popper was created
-        let rollback_count =
-            self.token_stack.popped_tokens.len() - self.start_token_position;
-        for _ in 0..rollback_count {
-            if let Err(err) = self.token_stack.rollback_once() {
-                eprintln!("Error during rollback: {}", err);
-                return Err(err);
-            }
-        }
-        Ok(())
+        let start_checkpoint = TokenCheckpoint { token_position: self.start_token_position };
+        self.token_stack.rollback_to(start_checkpoint).inspect_err(
+            |err| eprintln!("Error during rollback: {}", err)
+        )
     }
 }
@@ -0,0 +1,282 @@
+use pyo3::{pyclass, pymethods, Bound, IntoPyObject, PyAny, PyErr, PyResult, Python};
+use pyo3::types::{PyDict, PyDictMethods, PyType};
+use pyo3_stub_gen::{PyStubType, TypeInfo};
+use pyo3_stub_gen::derive::{gen_stub_pyclass, gen_stub_pymethods};
+use crate::parser::parse::{
+    parse_from_filepath, parse_from_str, ASTFunction, ASTProgram, BlockItem, Declaration,
+    Expression, ExpressionVariant, FunctionDeclaration, Statement, StatementVariant,
+    StructDeclaration
+};
+use crate::errors::CompilerError;
+use crate::parser::parser_helpers::ParseError;
+use crate::py_errors::compiler_error_to_py;
+
+/*
+The AST as plain nested values, which become dicts, lists and strings
+in Python (and so can be dumped with json.dumps as is). Every node is
+an object whose "kind" is the node name used by ast_printer
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub enum AstNode {
+    Null,
+    Text(String),
+    List(Vec<AstNode>),
+    Object(Vec<(&'static str, AstNode)>),
+}
+impl AstNode {
+    fn node(kind: &str, fields: Vec<(&'static str, AstNode)>) -> AstNode {
+        let mut all_fields = vec![("kind", AstNode::Text(kind.to_string()))];
+        all_fields.extend(fields);
+        AstNode::Object(all_fields)
+    }
+    fn text(value: impl ToString) -> AstNode {
+        AstNode::Text(value.to_string())
+    }
+
+    pub fn to_json(&self) -> String {
+        match self {
+            AstNode::Null => "null".to_string(),
+            AstNode::Text(text) => json_string(text),
+            AstNode::List(items) => format!(
+                "[{}]", items.iter().map(AstNode::to_json).collect::<Vec<String>>().join(", ")
+            ),
+            AstNode::Object(fields) => format!(
+                "{{{}}}",
+                fields.iter()
+                    .map(|(key, value)| format!("{}: {}", json_string(key), value.to_json()))
+                    .collect::<Vec<String>>().join(", ")
+            ),
+        }
+    }
+}
+impl<'py> IntoPyObject<'py> for AstNode {
+    type Target = PyAny;
+    type Output = Bound<'py, PyAny>;
+    type Error = PyErr;
+
+    fn into_pyobject(self, py: Python<'py>) -> Result<Self::Output, Self::Error> {
+        match self {
+            AstNode::Null => Ok(py.None().into_bound(py)),
+            AstNode::Text(text) => Ok(text.into_pyobject(py)?.into_any()),
+            AstNode::List(items) => items.into_pyobject(py),
+            AstNode::Object(fields) => {
+                let dict = PyDict::new(py);
+                for (key, value) in fields {
+                    dict.set_item(key, value)?;
+                }
+                Ok(dict.into_any())
+            }
+        }
+    }
+}
+impl PyStubType for AstNode {
+    fn type_output() -> TypeInfo {
+        TypeInfo::any()
+    }
+}
+
+fn json_string(text: &str) -> String {
+    let mut escaped = String::from("\"");
+    for character in text.chars() {
+        match character {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            character if (character as u32) < 0x20 => {
+                escaped.push_str(&format!("\\u{:04x}", character as u32))
+            },
+            character => escaped.push(character),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+fn expression_node(expression: &Expression) -> AstNode {
+    match &expression.expr_item {
+        ExpressionVariant::Constant(ast_constant) => AstNode::node("Constant", vec![
+            ("value", AstNode::text(&ast_constant.value)),
+            ("type", AstNode::text(&ast_constant.const_type)),
+        ]),
+        ExpressionVariant::Variable(identifier) => {
+            AstNode::node("Variable", vec![("name", AstNode::text(&identifier.name))])
+        },
+        ExpressionVariant::UnaryOperation(operator, inner) => AstNode::node("UnaryOperation", vec![
+            ("operator", AstNode::text(format!("{:?}", operator))),
+            ("operand", expression_node(inner)),
+        ]),
+        ExpressionVariant::Dereference(inner) => {
+            AstNode::node("Dereference", vec![("operand", expression_node(inner))])
+        },
+        ExpressionVariant::AddressOf(inner) => {
+            AstNode::node("AddressOf", vec![("operand", expression_node(inner))])
+        },
+        ExpressionVariant::MemberAccess(inner, member) => AstNode::node("MemberAccess", vec![
+            ("member", AstNode::text(&member.name)),
+            ("operand", expression_node(inner)),
+        ]),
+        ExpressionVariant::PointerMemberAccess(inner, member) => {
+            AstNode::node("PointerMemberAccess", vec![
+                ("member", AstNode::text(&member.name)),
+                ("operand", expression_node(inner)),
+            ])
+        },
+        ExpressionVariant::Cast(target_type, inner) => AstNode::node("Cast", vec![
+            ("type", AstNode::text(target_type)),
+            ("operand", expression_node(inner)),
+        ]),
+        ExpressionVariant::ParensWrapped(inner) => {
+            AstNode::node("ParensWrapped", vec![("operand", expression_node(inner))])
+        },
+        ExpressionVariant::BinaryOperation(operator, left, right) => {
+            AstNode::node("BinaryOperation", vec![
+                ("operator", AstNode::text(format!("{:?}", operator))),
+                ("left", expression_node(left)),
+                ("right", expression_node(right)),
+            ])
+        },
+    }
+}
+fn statement_node(statement: &Statement) -> AstNode {
+    match &statement.variant {
+        StatementVariant::Return(expression) => {
+            AstNode::node("Return", vec![("expression", expression_node(expression))])
+        },
+        StatementVariant::Expression(expression) => {
+            AstNode::node("ExpressionStatement", vec![("expression", expression_node(expression))])
+        },
+        StatementVariant::Null => AstNode::node("NullStatement", vec![]),
+    }
+}
+fn declaration_node(declaration: &Declaration) -> AstNode {
+    AstNode::node("Declaration", vec![
+        ("name", AstNode::text(&declaration.name.name)),
+        ("type", AstNode::text(&declaration.var_type)),
+        ("initializer", declaration.initializer.as_ref().map_or(AstNode::Null, expression_node)),
+    ])
+}
+fn struct_declaration_node(struct_declaration: &StructDeclaration) -> AstNode {
+    let members = struct_declaration.struct_type.members.iter().flatten()
+        .map(|member| AstNode::node("Member", vec![
+            ("name", AstNode::text(&member.name)),
+            ("type", AstNode::text(&member.member_type)),
+        ]))
+        .collect();
+    AstNode::node("StructDeclaration", vec![
+        ("type", AstNode::text(&struct_declaration.struct_type)),
+        ("members", AstNode::List(members)),
+    ])
+}
+fn function_declaration_node(function_declaration: &FunctionDeclaration) -> AstNode {
+    AstNode::node("FunctionDeclaration", vec![
+        ("name", AstNode::text(&function_declaration.name.name)),
+        ("return_type", AstNode::text(&function_declaration.return_type)),
+    ])
+}
+fn block_item_node(block_item: &BlockItem) -> AstNode {
+    match block_item {
+        BlockItem::Statement(statement) => statement_node(statement),
+        BlockItem::Declaration(declaration) => declaration_node(declaration),
+        BlockItem::StructDeclaration(struct_declaration) => {
+            struct_declaration_node(struct_declaration)
+        },
+    }
+}
+fn function_body_node(function: &ASTFunction) -> AstNode {
+    AstNode::List(function.body.iter().map(block_item_node).collect())
+}
+pub fn program_node(program: &ASTProgram) -> AstNode {
+    let function = &program.function;
+    AstNode::node("ASTProgram", vec![
+        ("struct_declarations", AstNode::List(
+            program.struct_declarations.iter().map(struct_declaration_node).collect()
+        )),
+        ("global_declarations", AstNode::List(
+            program.global_declarations.iter().map(declaration_node).collect()
+        )),
+        ("function_declarations", AstNode::List(
+            program.function_declarations.iter().map(function_declaration_node).collect()
+        )),
+        ("function", AstNode::node("ASTFunction", vec![
+            ("name", AstNode::text(&function.name.name)),
+            ("return_type", AstNode::text(&function.return_type)),
+            ("body", function_body_node(function)),
+        ])),
+    ])
+}
+
+fn to_py_parse_error(err: ParseError) -> PyErr {
+    // lexer and semantic errors get passed on by the parser
+    compiler_error_to_py(CompilerError::from(err))
+}
+
+#[gen_stub_pyclass]
+#[pyclass(module = "py_ca_compiler.compiler")]
+pub struct PyAst {
+    program: ASTProgram
+}
+#[gen_stub_pymethods]
+#[pymethods]
+impl PyAst {
+    #[classmethod]
+    pub fn parse_from_source(
+        _cls: &Bound<'_, PyType>, source_filepath: String
+    ) -> PyResult<Self> {
+        let program = parse_from_filepath(&source_filepath, false).map_err(to_py_parse_error)?;
+        Ok(Self { program })
+    }
+    #[classmethod]
+    pub fn parse_from_string(_cls: &Bound<'_, PyType>, source: String) -> PyResult<Self> {
+        let program = parse_from_str(&source).map_err(to_py_parse_error)?;
+        Ok(Self { program })
+    }
+
+    // the whole program as nested dicts and lists
+    pub fn to_dict(&self) -> PyResult<AstNode> {
+        Ok(program_node(&self.program))
+    }
+    pub fn to_json(&self) -> PyResult<String> {
+        Ok(program_node(&self.program).to_json())
+    }
+
+    pub fn function_name(&self) -> PyResult<String> {
+        Ok(self.program.function.name.name.clone())
+    }
+    pub fn function_return_type(&self) -> PyResult<String> {
+        Ok(self.program.function.return_type.to_string())
+    }
+    // the statements and declarations of the function, as in to_dict
+    pub fn function_body(&self) -> PyResult<AstNode> {
+        Ok(function_body_node(&self.program.function))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ast_to_json() {
+        let program = parse_from_str(
+            "struct pair { int a; };
+            int main(void) { long x = 2; return -(x + 1); }"
+        ).unwrap();
+        let expected = concat!(
+            r#"{"kind": "ASTProgram", "struct_declarations": [{"kind": "StructDeclaration", "#,
+            r#""type": "struct pair", "members": "#,
+            r#"[{"kind": "Member", "name": "a", "type": "int"}]}], "#,
+            r#""global_declarations": [], "function_declarations": [], "function": "#,
+            r#"{"kind": "ASTFunction", "name": "main", "return_type": "int", "body": ["#,
+            r#"{"kind": "Declaration", "name": "x", "type": "long", "initializer": "#,
+            r#"{"kind": "Constant", "value": "2", "type": "int"}}, "#,
+            r#"{"kind": "Return", "expression": {"kind": "UnaryOperation", "#,
+            r#""operator": "Subtract", "operand": {"kind": "ParensWrapped", "operand": "#,
+            r#"{"kind": "BinaryOperation", "operator": "Add", "#,
+            r#""left": {"kind": "Variable", "name": "x"}, "#,
+            r#""right": {"kind": "Constant", "value": "1", "type": "int"}}}}}]}}"#,
+        );
+        assert_eq!(program_node(&program).to_json(), expected);
+
+        assert_eq!(json_string("a \"b\"\\\n\t"), r#""a \"b\"\\\n\u0009""#);
+    }
+}
@@ -1,7 +1,12 @@
+use std::cmp::Ordering;
+use std::fmt::{Binary, Display, Formatter, LowerHex};
+use std::iter::Copied;
 use std::ops::{Add, Shl, Shr};
+use std::slice;
 use arbitrary_int::u4;
 use num_bigint::BigUint;
 use num_traits::ToPrimitive;
+use serde::{Deserialize, Serialize};
 
 pub trait BitAllocation {
     fn get_length(&self) -> usize;
@@ -15,9 +20,25 @@ pub trait BitAllocation {
     fn get(&self, index: usize) -> bool {
         self.get_bits()[index]
     }
+    // bits from least to most significant
+    fn iter(&self) -> Copied<slice::Iter<'_, bool>> {
+        self.get_bits().iter().copied()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ValueTooLargeError {
+    // bits needed to hold the value without its leading zeros
+    pub num_bits: u64
+}
+impl Display for ValueTooLargeError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "Value needs {} bits, which doesn't fit in a u64", self.num_bits)
+    }
 }
+impl std::error::Error for ValueTooLargeError {}
 
-#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Serialize, Deserialize)]
 pub struct FixedBitAllocation {
     bit_allocation: GrowableBitAllocation
 }
@@ -43,6 +64,22 @@ impl FixedBitAllocation {
     fn to_growable(&self) -> GrowableBitAllocation {
         self.bit_allocation.clone()
     }
+    // signed operations of GrowableBitAllocation, at the larger of the two widths
+    pub fn negate(&self) -> Self {
+        self.bit_allocation.negate().to_fixed_allocation()
+    }
+    pub fn overflowing_signed_add(&self, other: &FixedBitAllocation) -> (Self, bool) {
+        let (sum, overflowed) = self.bit_allocation.overflowing_signed_add(&other.bit_allocation);
+        (sum.to_fixed_allocation(), overflowed)
+    }
+    pub fn overflowing_signed_sub(&self, other: &FixedBitAllocation) -> (Self, bool) {
+        let (difference, overflowed) =
+            self.bit_allocation.overflowing_signed_sub(&other.bit_allocation);
+        (difference.to_fixed_allocation(), overflowed)
+    }
+    pub fn signed_cmp(&self, other: &FixedBitAllocation) -> Ordering {
+        self.bit_allocation.signed_cmp(&other.bit_allocation)
+    }
 }
 impl BitAllocation for FixedBitAllocation {
     fn get_length(&self) -> usize {
@@ -65,7 +102,7 @@ impl BitAllocation for FixedBitAllocation {
     }
 }
 
-#[derive(Debug, Clone, Hash, PartialEq, Eq)]
+#[derive(Debug, Clone, Hash, PartialEq, Eq, Serialize, Deserialize)]
 pub struct GrowableBitAllocation {
     bits: Vec<bool>
 }
@@ -87,7 +124,7 @@ impl GrowableBitAllocation {
         }
         result
     }
-    pub(crate) fn from_big_num(num: &BigUint) -> Self {
+    pub fn from_big_num(num: &BigUint) -> Self {
         let mut allocation = Self::new(0);
         allocation.apply_big_num(num);
         allocation
@@ -105,10 +142,32 @@ impl GrowableBitAllocation {
             allocation
         }
     }
+    pub fn from_signed_i64(num: i64, width: usize) -> Self {
+        // two's complement bit pattern of num, wrapped or sign extended to width
+        GrowableBitAllocation::new_from(
+            (0..width).map(|i| (num >> usize::min(i, 63)) & 1 == 1).collect()
+        )
+    }
     pub fn to_i64(&self) -> Option<i64> {
+        // reads the bits as an unsigned number
         let big_num = self.to_big_num();
         big_num.to_i64()
     }
+    pub fn to_signed_i64(&self, width: usize) -> Option<i64> {
+        /*
+        Reads the lowest width bits as a two's complement number,
+        e.g. 0b1110 is -2 with a width of 4 but 14 with a width of 5.
+        Missing bits above the allocation count as zeros
+        */
+        if width == 0 || width > 64 {
+            return None;
+        }
+        let mut bits = self.clone();
+        bits.resize(width);
+        let unsigned_value = bits.to_big_num().to_u64()?;
+        let unused_bits = 64 - width;
+        Some(((unsigned_value << unused_bits) as i64) >> unused_bits)
+    }
     pub fn new_from_bool(value: bool) -> Self {
         GrowableBitAllocation::new_from(vec![value])
     }
@@ -127,11 +186,6 @@ impl GrowableBitAllocation {
         self.increment()
     }
     pub fn increment(&mut self) -> &mut Self {
-        /*
-        TODO: I feel like implementing addition directly would be relatively
-            straightforward and more efficient than converting to BigUint
-            (and needing to import num-bigint just for that)
-        */
         let mut carry = true;
 
         for bit in self.bits.iter_mut() {
@@ -151,6 +205,77 @@ impl GrowableBitAllocation {
         }
         self
     }
+    fn ripple_carry_add(
+        &self, other: &GrowableBitAllocation, invert_other: bool, carry_in: bool
+    ) -> (Vec<bool>, bool) {
+        /*
+        Adds the bits of both allocations at the larger of their widths,
+        treating missing bits as zeros (before other is inverted).
+        Returns the sum bits along with the carry out of the top bit
+        */
+        let max_length = usize::max(self.get_length(), other.get_length());
+        let mut sum_bits = Vec::with_capacity(max_length + 1);
+        let mut carry = carry_in;
+
+        for i in 0..max_length {
+            let a_bit = self.bits.get(i).copied().unwrap_or(false);
+            let b_bit = other.bits.get(i).copied().unwrap_or(false) ^ invert_other;
+            sum_bits.push(a_bit ^ b_bit ^ carry);
+            carry = (a_bit & b_bit) | (carry & (a_bit ^ b_bit));
+        }
+        (sum_bits, carry)
+    }
+    pub fn overflowing_sub(&self, other: &GrowableBitAllocation) -> (Self, bool) {
+        /*
+        Subtracts at the larger of the two widths, wrapping around like
+        u64::overflowing_sub. The flag is set when other is larger than self
+        */
+        let (difference_bits, carry) = self.ripple_carry_add(other, true, true);
+        (GrowableBitAllocation::new_from(difference_bits), !carry)
+    }
+    /*
+    Signed operations read both allocations as two's complement numbers,
+    sign extending the narrower one to the width of the wider one.
+    Results keep that width and wrap around like the wrapping and
+    overflowing operations on i64, with empty allocations counting as zero
+    */
+    fn sign_bit(&self) -> bool {
+        self.bits.last().copied().unwrap_or(false)
+    }
+    fn sign_extended(&self, width: usize) -> Self {
+        let sign_bit = self.sign_bit();
+        let mut extended = self.clone();
+        extended.bits.resize(usize::max(width, self.get_length()), sign_bit);
+        extended
+    }
+    pub fn negate(&self) -> Self {
+        GrowableBitAllocation::new(0).overflowing_sub(self).0
+    }
+    pub fn overflowing_signed_add(&self, other: &GrowableBitAllocation) -> (Self, bool) {
+        let width = usize::max(self.get_length(), other.get_length());
+        let (a, b) = (self.sign_extended(width), other.sign_extended(width));
+        let (sum_bits, _) = a.ripple_carry_add(&b, false, false);
+        let sum = GrowableBitAllocation::new_from(sum_bits);
+        let overflowed = a.sign_bit() == b.sign_bit() && sum.sign_bit() != a.sign_bit();
+        (sum, overflowed)
+    }
+    pub fn overflowing_signed_sub(&self, other: &GrowableBitAllocation) -> (Self, bool) {
+        let width = usize::max(self.get_length(), other.get_length());
+        let (a, b) = (self.sign_extended(width), other.sign_extended(width));
+        let (difference, _) = a.overflowing_sub(&b);
+        let overflowed = a.sign_bit() != b.sign_bit() && difference.sign_bit() != a.sign_bit();
+        (difference, overflowed)
+    }
+    pub fn signed_cmp(&self, other: &GrowableBitAllocation) -> Ordering {
+        let width = usize::max(self.get_length(), other.get_length());
+        let (a, b) = (self.sign_extended(width), other.sign_extended(width));
+        match (a.sign_bit(), b.sign_bit()) {
+            (true, false) => Ordering::Less,
+            (false, true) => Ordering::Greater,
+            // values with the same sign are ordered like their bit patterns
+            _ => a.to_big_num().cmp(&b.to_big_num())
+        }
+    }
     pub fn clear_values(&mut self) {
         for bit in self.bits.iter_mut() {
             *bit = false;
@@ -255,6 +380,12 @@ impl GrowableBitAllocation {
     pub fn append(&mut self, other: &FixedBitAllocation) {
         self.bits.extend_from_slice(other.get_bits());
     }
+    pub fn rotate_left(&mut self, num_places: usize) -> &mut Self {
+        // moves every bit num_places towards the start, wrapping around to the end
+        let num_places = num_places % usize::max(self.bits.len(), 1);
+        self.bits.rotate_left(num_places);
+        self
+    }
     pub fn reverse(&mut self) -> &mut Self {
         self.bits.reverse();
         self
@@ -314,21 +445,106 @@ impl BitAllocation for GrowableBitAllocation {
         self.bits[index] = value;
     }
 }
+impl From<u64> for GrowableBitAllocation {
+    fn from(num: u64) -> Self {
+        GrowableBitAllocation::from_big_num(&BigUint::from(num))
+    }
+}
+impl From<u64> for FixedBitAllocation {
+    fn from(num: u64) -> Self {
+        let mut allocation = GrowableBitAllocation::from(num);
+        allocation.resize(64);
+        allocation.to_fixed_allocation()
+    }
+}
+impl TryFrom<&GrowableBitAllocation> for u64 {
+    type Error = ValueTooLargeError;
+
+    fn try_from(allocation: &GrowableBitAllocation) -> Result<u64, ValueTooLargeError> {
+        let big_num = allocation.to_big_num();
+        big_num.to_u64().ok_or(ValueTooLargeError { num_bits: big_num.bits() })
+    }
+}
+impl TryFrom<&FixedBitAllocation> for u64 {
+    type Error = ValueTooLargeError;
+
+    fn try_from(allocation: &FixedBitAllocation) -> Result<u64, ValueTooLargeError> {
+        u64::try_from(&allocation.bit_allocation)
+    }
+}
+impl<'a> IntoIterator for &'a GrowableBitAllocation {
+    type Item = bool;
+    type IntoIter = Copied<slice::Iter<'a, bool>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+impl<'a> IntoIterator for &'a FixedBitAllocation {
+    type Item = bool;
+    type IntoIter = Copied<slice::Iter<'a, bool>>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+/*
+Display prints the unsigned value in decimal, while Binary and LowerHex
+print every bit of the allocation (most significant first), including
+leading zeros, so that the width stays visible
+*/
+impl Display for GrowableBitAllocation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        f.pad_integral(true, "", &self.to_big_num().to_string())
+    }
+}
+impl Binary for GrowableBitAllocation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let digits: String = self.iter().rev().map(|bit| if bit { '1' } else { '0' }).collect();
+        f.pad_integral(true, "0b", &digits)
+    }
+}
+impl LowerHex for GrowableBitAllocation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let num_digits = self.get_length().div_ceil(4);
+        let digits = format!("{:0>width$x}", self.to_big_num(), width = num_digits);
+        f.pad_integral(true, "0x", &digits)
+    }
+}
+impl Display for FixedBitAllocation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Display::fmt(&self.bit_allocation, f)
+    }
+}
+impl Binary for FixedBitAllocation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        Binary::fmt(&self.bit_allocation, f)
+    }
+}
+impl LowerHex for FixedBitAllocation {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        LowerHex::fmt(&self.bit_allocation, f)
+    }
+}
+
 impl Add for GrowableBitAllocation {
     type Output = GrowableBitAllocation;
 
     fn add(self, other: GrowableBitAllocation) -> GrowableBitAllocation {
-        // TODO: in retrospect was bignum actually necessary
-        let sum = self.to_big_num() + other.to_big_num();
-        GrowableBitAllocation::from_big_num(&sum)
+        &self + &other
     }
 }
 impl Add for &GrowableBitAllocation {
     type Output = GrowableBitAllocation;
 
     fn add(self, other: &GrowableBitAllocation) -> GrowableBitAllocation {
-        let sum = self.to_big_num() + other.to_big_num();
-        GrowableBitAllocation::from_big_num(&sum)
+        // sums are only as wide as their value needs, like from_big_num
+        let (mut sum_bits, carry) = self.ripple_carry_add(other, false, false);
+        sum_bits.push(carry);
+        let mut sum = GrowableBitAllocation::new_from(sum_bits);
+        sum.auto_shrink();
+        sum
     }
 }
 impl Shl for &GrowableBitAllocation {
@@ -358,4 +574,111 @@ impl Shr for &GrowableBitAllocation {
         result_bits.extend_from_slice(&self.bits);
         GrowableBitAllocation::new_from(result_bits)
     }
-}
\ No newline at end of file
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_signed_interpretation() {
+        let bits = GrowableBitAllocation::from_num(0b1110);
+        assert_eq!(bits.to_i64(), Some(14));
+        assert_eq!(bits.to_signed_i64(4), Some(-2));
+        assert_eq!(bits.to_signed_i64(5), Some(14));
+        // only the lowest bits are read
+        assert_eq!(bits.to_signed_i64(2), Some(-2));
+        assert_eq!(GrowableBitAllocation::from_i64(-2).to_signed_i64(2), Some(-2));
+        let min_long = GrowableBitAllocation::from_num(1 << 63);
+        assert_eq!(min_long.to_signed_i64(64), Some(i64::MIN));
+        assert_eq!(min_long.to_signed_i64(65), None);
+    }
+
+    #[test]
+    fn test_ripple_carry_arithmetic() {
+        let values: Vec<u64> = vec![0, 1, 2, 7, 8, 255, 256, 0xFFFF_FFFF, 1 << 40, u64::MAX];
+        for a in &values {
+            for b in &values {
+                let a_bits = GrowableBitAllocation::from_num(*a as usize);
+                let b_bits = GrowableBitAllocation::from_num(*b as usize);
+                let expected_sum = BigUint::from(*a) + BigUint::from(*b);
+                assert_eq!(&a_bits + &b_bits, GrowableBitAllocation::from_big_num(&expected_sum));
+
+                let (difference, overflowed) = a_bits.overflowing_sub(&b_bits);
+                let width = usize::max(a_bits.get_length(), b_bits.get_length());
+                let mut expected_difference = GrowableBitAllocation::from_num(
+                    a.wrapping_sub(*b) as usize
+                );
+                expected_difference.resize(width);
+                assert_eq!(difference, expected_difference, "{} - {}", a, b);
+                assert_eq!(overflowed, b > a, "{} - {}", a, b);
+            }
+        }
+        // missing bits count as zeros, so the difference keeps the larger width
+        let (difference, overflowed) = GrowableBitAllocation::new(0)
+            .overflowing_sub(&GrowableBitAllocation::new(3));
+        assert_eq!(difference, GrowableBitAllocation::new(3));
+        assert!(!overflowed);
+    }
+
+    #[test]
+    fn test_signed_arithmetic() {
+        for a in i8::MIN..=i8::MAX {
+            for b in i8::MIN..=i8::MAX {
+                let a_bits = GrowableBitAllocation::from_signed_i64(a as i64, 8);
+                let b_bits = GrowableBitAllocation::from_signed_i64(b as i64, 8);
+                let (sum, add_overflowed) = a_bits.overflowing_signed_add(&b_bits);
+                let (difference, sub_overflowed) = a_bits.overflowing_signed_sub(&b_bits);
+
+                assert_eq!(sum.to_signed_i64(8), Some(a.wrapping_add(b) as i64));
+                assert_eq!(add_overflowed, a.checked_add(b).is_none());
+                assert_eq!(difference.to_signed_i64(8), Some(a.wrapping_sub(b) as i64));
+                assert_eq!(sub_overflowed, a.checked_sub(b).is_none());
+                assert_eq!(a_bits.signed_cmp(&b_bits), a.cmp(&b));
+            }
+            let negated = GrowableBitAllocation::from_signed_i64(a as i64, 8).negate();
+            assert_eq!(negated.to_signed_i64(8), Some(a.wrapping_neg() as i64));
+        }
+
+        // the narrower value is sign extended, so 0b1111 is -1 rather than 15
+        let minus_one = GrowableBitAllocation::from_num(0b1111);
+        let three = GrowableBitAllocation::from_signed_i64(3, 8);
+        let (sum, overflowed) = minus_one.overflowing_signed_add(&three);
+        assert_eq!(sum, GrowableBitAllocation::from_signed_i64(2, 8));
+        assert!(!overflowed);
+        assert_eq!(minus_one.signed_cmp(&three), Ordering::Less);
+        assert_eq!(
+            minus_one.to_fixed_allocation().negate(),
+            GrowableBitAllocation::from_signed_i64(1, 4).to_fixed_allocation()
+        );
+        assert_eq!(GrowableBitAllocation::new(0).negate(), GrowableBitAllocation::new(0));
+    }
+
+    #[test]
+    fn test_conversions_and_formatting() {
+        let bits = GrowableBitAllocation::from(0b1010u64);
+        assert_eq!(bits, GrowableBitAllocation::from_num(0b1010));
+        assert_eq!(u64::try_from(&bits), Ok(10));
+        assert_eq!(bits.iter().collect::<Vec<bool>>(), vec![false, true, false, true]);
+        assert_eq!((&bits).into_iter().filter(|bit| *bit).count(), 2);
+
+        let mut wide = bits.clone();
+        wide.resize(10);
+        assert_eq!(format!("{}", wide), "10");
+        assert_eq!(format!("{:b}", wide), "0000001010");
+        assert_eq!(format!("{:#b}", wide), "0b0000001010");
+        assert_eq!(format!("{:x}", wide), "00a");
+        assert_eq!(format!("{:#x}", wide), "0x00a");
+        assert_eq!(format!("{:>4}", bits), "  10");
+        assert_eq!(format!("{:b}", GrowableBitAllocation::new(0)), "");
+
+        let fixed = FixedBitAllocation::from(u64::MAX);
+        assert_eq!(fixed.get_length(), 64);
+        assert_eq!(u64::try_from(&fixed), Ok(u64::MAX));
+        assert_eq!(format!("{:x}", fixed), "ffffffffffffffff");
+        assert_eq!(format!("{}", FixedBitAllocation::new_one(8)), "1");
+
+        let too_large = GrowableBitAllocation::new_from(vec![true; 65]);
+        assert_eq!(u64::try_from(&too_large), Err(ValueTooLargeError { num_bits: 65 }));
+    }
+}
@@ -1,4 +1,9 @@
 pub mod potato_cpu;
-mod bit_allocation;
-mod potato_asm;
+pub mod bit_allocation;
+pub mod potato_asm;
+pub mod potato_asm_text;
+pub mod potato_vm;
 pub mod py_potato_cpu_tester;
+pub mod py_potato_cpu;
+pub mod py_cancellation;
+pub mod py_pickle;
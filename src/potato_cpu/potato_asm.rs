@@ -1,8 +1,52 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use arbitrary_int::u4;
+use num_bigint::BigUint;
+use serde::{Deserialize, Serialize};
+use crate::parser::c_types::CType;
+use crate::parser::parse::{Identifier, SupportedBinaryOperators, SupportedUnaryOperators};
 use crate::parser::parser_helpers::PoppedTokenContext;
 use crate::potato_cpu::bit_allocation::GrowableBitAllocation;
-use crate::potato_cpu::potato_cpu::{PotatoCPU, PotatoCodes, PotatoSpec, Registers};
-use crate::tacky::tacky_symbols::{TackyFunction, TackyInstruction, TackyProgram, TackyValue};
+use crate::potato_cpu::potato_cpu::{
+    ALUOperations, Cpu, ExecutionTrace, Flags, MovStackToRegister, PotatoCPU, PotatoCodes,
+    PotatoSpec, PotatoSpecError, Registers, StopReason
+};
+use crate::tacky::tacky_symbols::{
+    BinaryInstruction, ConversionKind, ConvertInstruction, TackyFunction, TackyInstruction,
+    TackyProgram, TackyStaticVariable, TackyValue, TackyVariable, UnaryInstruction
+};
 
+pub const NUM_SCRATCH_REGISTERS: u8 = 9;
+pub const STACK_WIDTH: usize = 32;
+// multiplications and divisions loop over every bit of their operands
+pub const MAX_EXECUTION_STEPS: usize = 1_000_000;
+// wide enough to count the bits of any supported type
+const COUNTER_WIDTH: usize = 8;
+
+// truth tables of BitwiseNOperation, see translate_bool_op
+const AND_TABLE: u8 = 0b0001;
+const XOR_TABLE: u8 = 0b0110;
+const OR_TABLE: u8 = 0b0111;
+const NOT_A_TABLE: u8 = 0b1100;
+
+/*
+Scratch registers, named after what the lowering of an instruction
+keeps in them. Operands are loaded into LEFT and RIGHT, and every
+value is kept as a two's complement bit pattern that is exactly as
+wide as its C type, since the cpu's registers are unsigned and grow
+without bound
+*/
+const LEFT: Registers = Registers::Scratch(0);
+const RIGHT: Registers = Registers::Scratch(1);
+const RESULT: Registers = Registers::Scratch(2);
+const REMAINDER: Registers = Registers::Scratch(3);
+const TEMP: Registers = Registers::Scratch(4);
+const TEMP_2: Registers = Registers::Scratch(5);
+const LEFT_SIGN: Registers = Registers::Scratch(6);
+const RIGHT_SIGN: Registers = Registers::Scratch(7);
+const COUNTER: Registers = Registers::Scratch(8);
+
+#[derive(Serialize, Deserialize)]
 pub struct PotatoProgram {
     // Define the structure of a Potato assembly program
     // TODO: asm instructions should be before raw cpu codes
@@ -16,29 +60,175 @@ impl PotatoProgram {
     pub fn from_tacky_program(
         tacky_program: TackyProgram
     ) -> Self {
-        Self::new(PotatoFunction::from_tacky_function(tacky_program.function))
+        Self::try_from_tacky_program(tacky_program).unwrap_or_else(|err| panic!("{}", err))
+    }
+    // fails on programs that use features the PotatoCPU doesn't support
+    pub fn try_from_tacky_program(
+        tacky_program: TackyProgram
+    ) -> Result<Self, PotatoCodegenError> {
+        let program = Self::new(PotatoFunction::try_from_tacky_function(
+            tacky_program.function, &tacky_program.static_variables
+        )?);
+        // links and validates the code once, so that spec can't fail afterwards
+        program.try_spec()?;
+        Ok(program)
     }
     pub fn execute(&self) -> i64 {
+        self.try_execute().unwrap_or_else(|err| panic!("{}", err))
+    }
+    pub fn try_execute(&self) -> Result<i64, PotatoExecutionError> {
+        self.try_execute_cancellable(&mut || false)
+    }
+    // fails with PotatoExecutionError::Cancelled once should_cancel returns true
+    pub fn try_execute_cancellable(
+        &self, should_cancel: &mut dyn FnMut() -> bool
+    ) -> Result<i64, PotatoExecutionError> {
+        let cpu = self.try_run(self.spawn_cpu(), MAX_EXECUTION_STEPS, should_cancel)?;
+        self.function.return_value_of(&cpu.register_value(&Registers::FunctionReturn))
+    }
+    // same as execute, but on any machine that runs potato code (e.g. potato_vm::PotatoCPU)
+    pub fn execute_on<C: Cpu>(&self) -> i64 {
+        self.try_execute_on::<C>().unwrap_or_else(|err| panic!("{}", err))
+    }
+    pub fn try_execute_on<C: Cpu>(&self) -> Result<i64, PotatoExecutionError> {
+        self.try_execute_on_cancellable::<C>(&mut || false)
+    }
+    pub fn try_execute_on_cancellable<C: Cpu>(
+        &self, should_cancel: &mut dyn FnMut() -> bool
+    ) -> Result<i64, PotatoExecutionError> {
+        let cpu = self.try_run(C::load_program(&self.spec()), MAX_EXECUTION_STEPS, should_cancel)?;
+        let return_register = cpu.register_value(&Registers::FunctionReturn);
+        self.function.return_value_of(&return_register)
+    }
+    // same as execute, but also returns every instruction the cpu executed
+    pub fn execute_with_trace(&self) -> (i64, ExecutionTrace) {
+        self.try_execute_with_trace().unwrap_or_else(|err| panic!("{}", err))
+    }
+    pub fn try_execute_with_trace(
+        &self
+    ) -> Result<(i64, ExecutionTrace), PotatoExecutionError> {
+        self.try_execute_with_trace_cancellable(MAX_EXECUTION_STEPS, &mut || false)
+    }
+    // fails with TimeLimitExceeded if the program is still running after max_steps
+    pub fn try_execute_with_trace_cancellable(
+        &self, max_steps: usize, should_cancel: &mut dyn FnMut() -> bool
+    ) -> Result<(i64, ExecutionTrace), PotatoExecutionError> {
+        let tracing_cpu = self.spawn_cpu().with_tracing();
+        let mut cpu = self.try_run(tracing_cpu, max_steps, should_cancel)?;
+        let return_register = cpu.read_register(Registers::FunctionReturn);
+        let return_value = self.function.return_value_of(return_register)?;
+        Ok((return_value, cpu.trace.take().unwrap()))
+    }
+    // linked code of the program, along with the cpu it targets
+    pub fn spec(&self) -> PotatoSpec {
+        self.try_spec().unwrap_or_else(|err| panic!("{}", err))
+    }
+    pub fn try_spec(&self) -> Result<PotatoSpec, PotatoCodegenError> {
         let spec = PotatoSpec::new(
-            self.function.instructions.clone(),
-            4, 32,
+            self.function.try_link()?,
+            NUM_SCRATCH_REGISTERS, STACK_WIDTH as u16,
         );
-        let mut cpu = PotatoCPU::new(&spec);
-        let run_result = cpu.run(10000);
+        spec.validate().map_err(PotatoCodegenError::InvalidCode)?;
+        Ok(spec)
+    }
+    fn spawn_cpu(&self) -> PotatoCPU {
+        // so that programs that loop forever fail without running out the time limit
+        PotatoCPU::new(&self.spec()).with_loop_detection()
+    }
+    fn try_run<C: Cpu>(
+        &self, mut cpu: C, max_steps: usize, should_cancel: &mut dyn FnMut() -> bool
+    ) -> Result<C, PotatoExecutionError> {
+        let run_result = cpu.run_cancellable(max_steps, should_cancel)
+            .ok_or(PotatoExecutionError::Cancelled)?;
+        if let Some(StopReason::LoopDetected(instruction_no)) = run_result.stop_reason {
+            return Err(PotatoExecutionError::LoopDetected(instruction_no));
+        }
         if !run_result.halted {
-            panic!("Program did not halt within the time limit");
+            return Err(PotatoExecutionError::TimeLimitExceeded);
+        }
+        Ok(cpu)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PotatoExecutionError {
+    // instruction from which the program repeats a state it was already in
+    LoopDetected(usize),
+    // still running after MAX_EXECUTION_STEPS (or the given step limit)
+    TimeLimitExceeded,
+    // stopped early by the caller, e.g. with Ctrl-C from Python
+    Cancelled,
+    // the return register holds a value that can't be read as the return type
+    InvalidReturnValue(String),
+}
+impl Display for PotatoExecutionError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PotatoExecutionError::LoopDetected(instruction_no) => {
+                write!(f, "Program loops forever from instruction {}", instruction_no)
+            },
+            PotatoExecutionError::TimeLimitExceeded => {
+                write!(f, "Program did not halt within the time limit")
+            },
+            PotatoExecutionError::Cancelled => write!(f, "Program execution was cancelled"),
+            PotatoExecutionError::InvalidReturnValue(message) => {
+                write!(f, "Invalid return value: {}", message)
+            },
         }
+    }
+}
+impl std::error::Error for PotatoExecutionError {}
 
-        let return_register = cpu.read_register(Registers::FunctionReturn);
-        let return_value = return_register.to_i64().unwrap();
-        return_value
+#[derive(Clone, Debug, PartialEq)]
+pub enum PotatoCodegenError {
+    // tacky instructions (e.g. pointer loads and stores) that have no lowering yet
+    UnsupportedInstruction(String),
+    UnsupportedType(CType),
+    UnsupportedOperator(SupportedBinaryOperators),
+    UnsupportedConversion(ConversionKind),
+    DuplicateLabel(String),
+    UndefinedLabel(String),
+    InvalidCode(PotatoSpecError),
+}
+impl Display for PotatoCodegenError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PotatoCodegenError::UnsupportedInstruction(instruction) => {
+                write!(f, "Unsupported instruction on the PotatoCPU: {}", instruction)
+            },
+            PotatoCodegenError::UnsupportedType(c_type) => {
+                write!(f, "{} values are not supported on the PotatoCPU", c_type)
+            },
+            PotatoCodegenError::UnsupportedOperator(operator) => {
+                write!(f, "Unsupported binary operator on the PotatoCPU: {:?}", operator)
+            },
+            PotatoCodegenError::UnsupportedConversion(kind) => {
+                write!(f, "Unsupported conversion on the PotatoCPU: {:?}", kind)
+            },
+            PotatoCodegenError::DuplicateLabel(name) => {
+                write!(f, "Duplicate potato label {}", name)
+            },
+            PotatoCodegenError::UndefinedLabel(name) => {
+                write!(f, "Reference to undefined potato label {}", name)
+            },
+            PotatoCodegenError::InvalidCode(err) => {
+                write!(f, "Generated invalid potato code: {}", err)
+            },
+        }
     }
 }
+impl std::error::Error for PotatoCodegenError {}
 
+#[derive(Serialize, Deserialize)]
 pub struct PotatoFunction {
     name: String,
-    instructions: Vec<PotatoCodes>,
+    instructions: Vec<PotatoCodes<Identifier>>,
     pub(crate) pop_contexts: Vec<PoppedTokenContext>,
+    // type of the returned values, used to read back the return register
+    return_type: CType,
+    // first stack address of each tacky variable, by variable id
+    stack_addresses: HashMap<u64, usize>,
+    next_stack_address: usize,
 }
 impl PotatoFunction {
     pub fn new(name: String) -> Self {
@@ -46,54 +236,568 @@ impl PotatoFunction {
             name,
             instructions: vec![],
             pop_contexts: vec![],
+            return_type: CType::Int,
+            stack_addresses: HashMap::new(),
+            next_stack_address: 0,
         }
     }
-    pub fn from_tacky_function(tacky_function: TackyFunction) -> Self {
-        // TODO: this is about as barebones as it gets rn
+    pub fn from_tacky_function(
+        tacky_function: TackyFunction, static_variables: &[TackyStaticVariable]
+    ) -> Self {
+        Self::try_from_tacky_function(tacky_function, static_variables)
+            .unwrap_or_else(|err| panic!("{}", err))
+    }
+    pub fn try_from_tacky_function(
+        tacky_function: TackyFunction, static_variables: &[TackyStaticVariable]
+    ) -> Result<Self, PotatoCodegenError> {
         let mut asm_function = Self::new(tacky_function.name_to_string());
 
+        // static variables just live at the bottom of the stack
+        for static_variable in static_variables {
+            let initial_value = static_variable.initial_value.as_ref()
+                .map_or(0, |constant| constant.to_bits());
+            let width = Self::bit_width(&static_variable.variable.var_type)?;
+            asm_function.load_constant(initial_value as u128, width, LEFT);
+            asm_function.store(LEFT, &static_variable.variable)?;
+        }
+
         for tacky_instruction in tacky_function.instructions {
-            let return_val = match tacky_instruction {
+            match tacky_instruction {
                 TackyInstruction::Return(tacky_value) => {
-                    match tacky_value {
-                        TackyValue::Constant(ast_constant) => {
-                            // TODO have to deal with negative numbers at some point
-                            let value = ast_constant.to_usize().unwrap();
-                            value
-                        }
-                        _ => {
-                            panic!(
-                                "Unsupported return value type in \
-                                PotatoFunction::from_tacky_function"
-                            );
-                        }
-                    }
+                    asm_function.return_type = tacky_value.get_type();
+                    asm_function.load(&tacky_value, LEFT)?;
+                    asm_function.emit(PotatoCodes::CopyRegisterToRegister(
+                        LEFT, Registers::FunctionReturn
+                    ));
+                    asm_function.emit(PotatoCodes::Return);
+                },
+                TackyInstruction::UnaryInstruction(unary_instruction) => {
+                    asm_function.emit_unary(unary_instruction)?
+                },
+                TackyInstruction::BinaryInstruction(binary_instruction) => {
+                    asm_function.emit_binary(binary_instruction)?
+                },
+                TackyInstruction::CopyInstruction(copy_instruction) => {
+                    asm_function.load(&copy_instruction.src, LEFT)?;
+                    asm_function.store(LEFT, &copy_instruction.dst)?;
                 },
-                _ => {
-                    panic!(
-                        "Unsupported instruction type in \
-                        PotatoFunction::from_tacky_function"
-                    );
+                TackyInstruction::ConvertInstruction(convert_instruction) => {
+                    asm_function.emit_conversion(convert_instruction)?
+                },
+                TackyInstruction::LabelInstruction(label_instruction) => {
+                    asm_function.emit(PotatoCodes::Label(label_instruction.label))
+                },
+                TackyInstruction::JumpInstruction(jump_instruction) => {
+                    asm_function.emit(PotatoCodes::Jump(jump_instruction.target))
+                },
+                TackyInstruction::JumpIfZeroInstruction(jump_instruction) => {
+                    // copy the condition into the output register that is tested
+                    asm_function.load(&jump_instruction.condition, LEFT)?;
+                    asm_function.bitwise(OR_TABLE, LEFT, LEFT, Registers::Output);
+                    asm_function.emit(PotatoCodes::JumpIfZero(jump_instruction.target))
+                },
+                TackyInstruction::JumpIfNotZeroInstruction(jump_instruction) => {
+                    // passing the condition through the ALU sets the zero flag
+                    asm_function.load(&jump_instruction.condition, LEFT)?;
+                    asm_function.bitwise(OR_TABLE, LEFT, LEFT, Registers::Output);
+                    asm_function.emit(PotatoCodes::JumpIfNotFlag(
+                        Flags::Zero, jump_instruction.target
+                    ))
+                },
+                // annotations only label the generated code
+                TackyInstruction::AnnotationStartInstruction(_) |
+                TackyInstruction::AnnotationEndInstruction(_) => continue,
+                other => {
+                    return Err(PotatoCodegenError::UnsupportedInstruction(format!("{:?}", other)));
                 }
             };
+        }
+        Ok(asm_function)
+    }
+    pub fn link(&self) -> Vec<PotatoCodes> {
+        link(&self.instructions)
+    }
+    pub fn try_link(&self) -> Result<Vec<PotatoCodes>, PotatoCodegenError> {
+        try_link(&self.instructions)
+    }
 
-            let data_value = GrowableBitAllocation::from_num(return_val);
-            let instructions = vec![
-                PotatoCodes::DataValue(data_value),
-                PotatoCodes::MovDataValueToRegister(
-                    0, Registers::FunctionReturn
-                )
-            ];
-            asm_function.instructions.extend(instructions);
+    fn bit_width(c_type: &CType) -> Result<usize, PotatoCodegenError> {
+        if c_type.is_double() || c_type.is_struct() {
+            return Err(PotatoCodegenError::UnsupportedType(c_type.clone()));
+        }
+        Ok(c_type.size() as usize * 8)
+    }
+    fn return_value_of(
+        &self, return_register: &GrowableBitAllocation
+    ) -> Result<i64, PotatoExecutionError> {
+        // reinterpret the returned bit pattern as a value of the return type
+        let width = Self::bit_width(&self.return_type)
+            .expect("return types are checked when lowering the return");
+        if self.return_type.is_signed() {
+            return return_register.to_signed_i64(width).ok_or_else(|| {
+                PotatoExecutionError::InvalidReturnValue(format!(
+                    "Can't read a {} bit signed return value", width
+                ))
+            });
         }
-        asm_function
+        // unsigned longs above i64::MAX wrap around like exit codes do
+        let unsigned_value = u64::try_from(return_register)
+            .map_err(|err| PotatoExecutionError::InvalidReturnValue(err.to_string()))?;
+        Ok(unsigned_value as i64)
+    }
+
+    fn emit(&mut self, code: PotatoCodes<Identifier>) {
+        self.instructions.push(code);
+    }
+    fn unique_label(&self, name: &str) -> Identifier {
+        // dots keep generated labels apart from the labels of the tacky code
+        Identifier::new(format!("potato.{}.{}", name, self.instructions.len()))
     }
+    fn emit_counted_loop(&mut self, iterations: usize, body: impl Fn(&mut Self)) {
+        /*
+        COUNTER = iterations
+        start:
+        JumpIfZero(COUNTER, end)
+        <body>
+        COUNTER -= 1
+        Jump(start)
+        end:
+        */
+        let start_label = self.unique_label("loop_start");
+        let end_label = self.unique_label("loop_end");
+        self.load_constant(iterations as u128, COUNTER_WIDTH, COUNTER);
+        self.emit(PotatoCodes::Label(start_label.clone()));
+        self.bitwise(OR_TABLE, COUNTER, COUNTER, Registers::Output);
+        self.emit(PotatoCodes::JumpIfZero(end_label.clone()));
+        body(self);
+        // adding -1 wraps around to COUNTER - 1
+        self.load_bits(
+            GrowableBitAllocation::from_signed_i64(-1, COUNTER_WIDTH), Registers::InputB
+        );
+        self.add(COUNTER, Registers::InputB, COUNTER, COUNTER_WIDTH);
+        self.emit(PotatoCodes::Jump(start_label));
+        self.emit(PotatoCodes::Label(end_label));
+    }
+    fn load_bits(&mut self, bits: GrowableBitAllocation, register: Registers) {
+        // data values are no-ops when executed, so they can sit inline
+        let data_label = self.unique_label("data");
+        self.emit(PotatoCodes::Label(data_label.clone()));
+        self.emit(PotatoCodes::DataValue(bits));
+        self.emit(PotatoCodes::MovDataValueToRegister(data_label, register));
+    }
+    fn load_constant(&mut self, value: u128, width: usize, register: Registers) {
+        // value wrapped to a bit pattern of the given width
+        let mut bits = GrowableBitAllocation::from_big_num(&BigUint::from(value));
+        bits.resize(width);
+        self.load_bits(bits, register);
+    }
+    fn load_number(&mut self, value: usize, register: Registers) {
+        // shift counts and sizes, which are read as unsigned numbers
+        self.load_bits(GrowableBitAllocation::from_num(value), register);
+    }
+
+    fn stack_address_of(&mut self, variable: &TackyVariable) -> Result<usize, PotatoCodegenError> {
+        if let Some(address) = self.stack_addresses.get(&variable.id) {
+            return Ok(*address);
+        }
+        let address = self.next_stack_address;
+        self.next_stack_address += Self::stack_slots(&variable.var_type)?;
+        self.stack_addresses.insert(variable.id, address);
+        Ok(address)
+    }
+    fn stack_slots(c_type: &CType) -> Result<usize, PotatoCodegenError> {
+        Ok(Self::bit_width(c_type)?.div_ceil(STACK_WIDTH))
+    }
+    fn load(&mut self, value: &TackyValue, register: Registers) -> Result<(), PotatoCodegenError> {
+        let width = Self::bit_width(&value.get_type())?;
+        match value {
+            TackyValue::Constant(ast_constant) => {
+                self.load_constant(ast_constant.to_bits() as u128, width, register)
+            },
+            TackyValue::Var(variable) => {
+                let address = self.stack_address_of(variable)?;
+                let num_slots = Self::stack_slots(&variable.var_type)?;
+                self.emit(PotatoCodes::MovStackToRegister(MovStackToRegister::new(
+                    address, num_slots, register.clone()
+                )));
+                if !width.is_multiple_of(STACK_WIDTH) {
+                    self.truncate(register, width);
+                }
+            }
+        }
+        Ok(())
+    }
+    fn store(
+        &mut self, register: Registers, variable: &TackyVariable
+    ) -> Result<(), PotatoCodegenError> {
+        /*
+        Registers are split into stack sized chunks that are padded with
+        their last bit, so narrower values are zero extended to whole
+        stack slots first
+        */
+        let address = self.stack_address_of(variable)?;
+        let num_slots = Self::stack_slots(&variable.var_type)?;
+        self.truncate(register.clone(), num_slots * STACK_WIDTH);
+        self.emit(PotatoCodes::MovRegisterToStack(register, address));
+        Ok(())
+    }
+
+    fn alu(&mut self, operation: ALUOperations, a: Registers, b: Registers, dst: Registers) {
+        if a != Registers::InputA {
+            self.emit(PotatoCodes::CopyRegisterToRegister(a, Registers::InputA));
+        }
+        if b != Registers::InputB {
+            self.emit(PotatoCodes::CopyRegisterToRegister(b, Registers::InputB));
+        }
+        self.emit(PotatoCodes::Operate(operation));
+        if dst != Registers::Output {
+            self.emit(PotatoCodes::CopyRegisterToRegister(Registers::Output, dst));
+        }
+    }
+    fn bitwise(&mut self, truth_table: u8, a: Registers, b: Registers, dst: Registers) {
+        let operation = ALUOperations::BitwiseNOperation(u4::new(truth_table));
+        self.alu(operation, a, b, dst);
+    }
+    fn truncate(&mut self, register: Registers, width: usize) {
+        // zero extends the register instead if it is narrower than width
+        self.load_number(width, Registers::InputB);
+        self.alu(ALUOperations::Resize, register.clone(), Registers::InputB, register);
+    }
+    fn add(&mut self, a: Registers, b: Registers, dst: Registers, width: usize) {
+        self.alu(ALUOperations::Add, a, b, dst.clone());
+        // drop the carry out of the top bit
+        self.truncate(dst, width);
+    }
+    fn negate(&mut self, a: Registers, dst: Registers, width: usize) {
+        // -a = ~a + 1
+        self.bitwise(NOT_A_TABLE, a.clone(), a, dst.clone());
+        self.load_constant(1, width, Registers::InputB);
+        self.add(dst.clone(), Registers::InputB, dst, width);
+    }
+    fn subtract(&mut self, a: Registers, b: Registers, dst: Registers, width: usize) {
+        // a - b = a + -b, which overwrites b
+        self.negate(b.clone(), b.clone(), width);
+        self.add(a, b, dst, width);
+    }
+    fn shift_left_by(&mut self, a: Registers, count: usize, dst: Registers, width: usize) {
+        self.load_number(count, Registers::InputB);
+        self.shift_left(a, Registers::InputB, dst, width);
+    }
+    fn shift_left(&mut self, a: Registers, count: Registers, dst: Registers, width: usize) {
+        // ShiftRight moves bits towards the end of the (little endian) bit vector
+        self.alu(ALUOperations::ShiftRight, a, count, dst.clone());
+        self.truncate(dst, width);
+    }
+    fn logical_shift_right_by(
+        &mut self, a: Registers, count: usize, dst: Registers, width: usize
+    ) {
+        self.load_number(count, Registers::InputB);
+        self.logical_shift_right(a, Registers::InputB, dst, width);
+    }
+    fn logical_shift_right(
+        &mut self, a: Registers, count: Registers, dst: Registers, width: usize
+    ) {
+        // ShiftLeft drops bits from the start of the (little endian) bit vector
+        self.alu(ALUOperations::ShiftLeft, a, count, dst.clone());
+        self.truncate(dst, width);
+    }
+    fn sign_mask(&mut self, a: Registers, dst: Registers, width: usize) {
+        // all ones if a is negative, zero otherwise
+        self.logical_shift_right_by(a, width - 1, dst.clone(), width);
+        self.negate(dst.clone(), dst, width);
+    }
+    fn conditional_negate(&mut self, register: Registers, mask: Registers, width: usize) {
+        // (x ^ mask) - mask negates x only if mask is all ones
+        self.bitwise(XOR_TABLE, register.clone(), mask.clone(), register.clone());
+        self.emit(PotatoCodes::CopyRegisterToRegister(mask, TEMP_2));
+        self.subtract(register.clone(), TEMP_2, register, width);
+    }
+    fn logical_not(&mut self, register: Registers) {
+        // flips a single bit truth value
+        self.load_constant(1, 1, Registers::InputB);
+        self.bitwise(XOR_TABLE, register.clone(), Registers::InputB, register);
+    }
+    fn flip_sign_bit(&mut self, register: Registers, width: usize) {
+        // maps signed values onto unsigned ones with the same ordering
+        self.load_constant(1 << (width - 1), width, Registers::InputB);
+        self.bitwise(XOR_TABLE, register.clone(), Registers::InputB, register);
+    }
+
+    fn emit_unary(
+        &mut self, unary_instruction: UnaryInstruction
+    ) -> Result<(), PotatoCodegenError> {
+        let width = Self::bit_width(&unary_instruction.src.get_type())?;
+        let dst_width = Self::bit_width(&unary_instruction.dst.var_type)?;
+        self.load(&unary_instruction.src, LEFT)?;
+        match unary_instruction.operator {
+            SupportedUnaryOperators::Subtract => self.negate(LEFT, RESULT, width),
+            SupportedUnaryOperators::BitwiseNot => {
+                self.bitwise(NOT_A_TABLE, LEFT, LEFT, RESULT)
+            },
+            SupportedUnaryOperators::Not => {
+                // !x is whether x is not greater than zero (as an unsigned value)
+                self.load_constant(0, width, RIGHT);
+                self.alu(ALUOperations::CompareGreaterThan, LEFT, RIGHT, RESULT);
+                self.logical_not(RESULT);
+                self.truncate(RESULT, dst_width);
+            }
+        }
+        self.store(RESULT, &unary_instruction.dst)
+    }
+    fn emit_binary(
+        &mut self, binary_instruction: BinaryInstruction
+    ) -> Result<(), PotatoCodegenError> {
+        let operand_type = binary_instruction.left.get_type();
+        let width = Self::bit_width(&operand_type)?;
+        let is_signed = operand_type.is_signed();
+        self.load(&binary_instruction.left, LEFT)?;
+        self.load(&binary_instruction.right, RIGHT)?;
+
+        match binary_instruction.operator {
+            SupportedBinaryOperators::Add => self.add(LEFT, RIGHT, RESULT, width),
+            SupportedBinaryOperators::Subtract => self.subtract(LEFT, RIGHT, RESULT, width),
+            SupportedBinaryOperators::Multiply => {
+                self.shift_add_multiply(LEFT, RIGHT, RESULT, width)
+            },
+            SupportedBinaryOperators::Divide | SupportedBinaryOperators::Modulo => {
+                self.divide(width, is_signed);
+                if binary_instruction.operator == SupportedBinaryOperators::Modulo {
+                    self.emit(PotatoCodes::CopyRegisterToRegister(REMAINDER, RESULT));
+                }
+            },
+            SupportedBinaryOperators::BitwiseAnd => self.bitwise(AND_TABLE, LEFT, RIGHT, RESULT),
+            SupportedBinaryOperators::BitwiseOr => self.bitwise(OR_TABLE, LEFT, RIGHT, RESULT),
+            SupportedBinaryOperators::BitwiseXor => self.bitwise(XOR_TABLE, LEFT, RIGHT, RESULT),
+            SupportedBinaryOperators::LeftShift => self.shift_left(LEFT, RIGHT, RESULT, width),
+            SupportedBinaryOperators::RightShift if is_signed => {
+                // ((x ^ sign) >> n) ^ sign shifts copies of the sign bit in
+                self.sign_mask(LEFT, TEMP, width);
+                self.bitwise(XOR_TABLE, LEFT, TEMP, RESULT);
+                self.logical_shift_right(RESULT, RIGHT, RESULT, width);
+                self.bitwise(XOR_TABLE, RESULT, TEMP, RESULT);
+            },
+            SupportedBinaryOperators::RightShift => {
+                self.logical_shift_right(LEFT, RIGHT, RESULT, width)
+            },
+            ref operator if operator.is_relational() => {
+                let dst_width = Self::bit_width(&binary_instruction.dst.var_type)?;
+                self.compare(operator, width, is_signed);
+                self.truncate(RESULT, dst_width);
+            },
+            other => return Err(PotatoCodegenError::UnsupportedOperator(other))
+        }
+        self.store(RESULT, &binary_instruction.dst)
+    }
+    fn compare(&mut self, operator: &SupportedBinaryOperators, width: usize, is_signed: bool) {
+        // leaves the single bit result of LEFT <operator> RIGHT in RESULT
+        if is_signed {
+            self.flip_sign_bit(LEFT, width);
+            self.flip_sign_bit(RIGHT, width);
+        }
+        let greater_than = ALUOperations::CompareGreaterThan;
+        match operator {
+            SupportedBinaryOperators::GreaterThan => self.alu(greater_than, LEFT, RIGHT, RESULT),
+            SupportedBinaryOperators::LessThan => self.alu(greater_than, RIGHT, LEFT, RESULT),
+            SupportedBinaryOperators::LessOrEqual => {
+                self.alu(greater_than, LEFT, RIGHT, RESULT);
+                self.logical_not(RESULT);
+            },
+            SupportedBinaryOperators::GreaterOrEqual => {
+                self.alu(greater_than, RIGHT, LEFT, RESULT);
+                self.logical_not(RESULT);
+            },
+            SupportedBinaryOperators::CheckEqual | SupportedBinaryOperators::NotEqual => {
+                self.alu(greater_than.clone(), LEFT, RIGHT, RESULT);
+                self.alu(greater_than, RIGHT, LEFT, TEMP);
+                self.bitwise(OR_TABLE, RESULT, TEMP, RESULT);
+                if *operator == SupportedBinaryOperators::CheckEqual {
+                    self.logical_not(RESULT);
+                }
+            },
+            other => panic!("Not a comparison operator: {:?}", other)
+        }
+    }
+    fn shift_add_multiply(&mut self, a: Registers, b: Registers, dst: Registers, width: usize) {
+        /*
+        Shift and add, consuming one bit of b per iteration until b is zero,
+        so multiplying by small (non negative) values takes fewer iterations:
+        dst = 0
+        start:
+        JumpIfZero(b, end)
+        dst += a & -(b & 1)
+        a <<= 1
+        b >>= 1
+        Jump(start)
+        end:
+        The lower bits of the product are the same for signed values.
+        Overwrites a, b and TEMP
+        */
+        let start_label = self.unique_label("multiply_start");
+        let end_label = self.unique_label("multiply_end");
+        self.load_constant(0, width, dst.clone());
+        self.emit(PotatoCodes::Label(start_label.clone()));
+        self.bitwise(OR_TABLE, b.clone(), b.clone(), Registers::Output);
+        self.emit(PotatoCodes::JumpIfZero(end_label.clone()));
+
+        self.load_constant(1, width, Registers::InputB);
+        self.bitwise(AND_TABLE, b.clone(), Registers::InputB, TEMP);
+        self.negate(TEMP, TEMP, width);
+        self.bitwise(AND_TABLE, TEMP, a.clone(), TEMP);
+        self.add(dst.clone(), TEMP, dst, width);
+        self.shift_left_by(a.clone(), 1, a, width);
+        self.logical_shift_right_by(b.clone(), 1, b, width);
+        self.emit(PotatoCodes::Jump(start_label));
+        self.emit(PotatoCodes::Label(end_label));
+    }
+    fn divide(&mut self, width: usize, is_signed: bool) {
+        /*
+        Leaves LEFT / RIGHT in RESULT and LEFT % RIGHT in REMAINDER.
+        Signed operands are divided by their magnitudes, after which
+        the quotient is negated if the signs differ and the remainder
+        takes the sign of the dividend
+        */
+        if is_signed {
+            self.sign_mask(LEFT, LEFT_SIGN, width);
+            self.sign_mask(RIGHT, RIGHT_SIGN, width);
+            self.conditional_negate(LEFT, LEFT_SIGN, width);
+            self.conditional_negate(RIGHT, RIGHT_SIGN, width);
+        }
+        self.unsigned_divide(width);
+        if is_signed {
+            self.bitwise(XOR_TABLE, LEFT_SIGN, RIGHT_SIGN, RIGHT_SIGN);
+            self.conditional_negate(RESULT, RIGHT_SIGN, width);
+            self.conditional_negate(REMAINDER, LEFT_SIGN, width);
+        }
+    }
+    fn unsigned_divide(&mut self, width: usize) {
+        /*
+        Restoring division, shifting the bits of LEFT out from the top:
+        REMAINDER = (REMAINDER << 1) | (LEFT >> (width - 1))
+        LEFT <<= 1
+        TEMP = REMAINDER >= RIGHT
+        RESULT = (RESULT << 1) | TEMP
+        REMAINDER -= RIGHT & -TEMP
+        REMAINDER is one bit wider so that the shift can't overflow
+        */
+        let remainder_width = width + 1;
+        self.load_constant(0, width, RESULT);
+        self.load_constant(0, remainder_width, REMAINDER);
+        self.emit_counted_loop(width, |function| {
+            function.logical_shift_right_by(LEFT, width - 1, TEMP, width);
+            function.shift_left_by(REMAINDER, 1, REMAINDER, remainder_width);
+            function.bitwise(OR_TABLE, REMAINDER, TEMP, REMAINDER);
+            function.shift_left_by(LEFT, 1, LEFT, width);
+
+            function.alu(ALUOperations::CompareGreaterThan, RIGHT, REMAINDER, TEMP);
+            function.logical_not(TEMP);
+            function.shift_left_by(RESULT, 1, RESULT, width);
+            function.bitwise(OR_TABLE, RESULT, TEMP, RESULT);
+
+            function.truncate(TEMP, remainder_width);
+            function.negate(TEMP, TEMP, remainder_width);
+            function.bitwise(AND_TABLE, TEMP, RIGHT, TEMP);
+            function.subtract(REMAINDER, TEMP, REMAINDER, remainder_width);
+        });
+        self.truncate(REMAINDER, width);
+    }
+    fn emit_conversion(
+        &mut self, convert_instruction: ConvertInstruction
+    ) -> Result<(), PotatoCodegenError> {
+        let src_width = Self::bit_width(&convert_instruction.src.get_type())?;
+        let dst_width = Self::bit_width(&convert_instruction.dst.var_type)?;
+        self.load(&convert_instruction.src, LEFT)?;
+        match convert_instruction.kind {
+            ConversionKind::ZeroExtend | ConversionKind::Truncate => {
+                self.truncate(LEFT, dst_width)
+            },
+            ConversionKind::SignExtend => {
+                // (x ^ s) - s with s = 1 << (src_width - 1) copies the sign bit up
+                self.truncate(LEFT, dst_width);
+                self.load_constant(1 << (src_width - 1), dst_width, RIGHT);
+                self.bitwise(XOR_TABLE, LEFT, RIGHT, LEFT);
+                self.subtract(LEFT, RIGHT, LEFT, dst_width);
+            },
+            kind => return Err(PotatoCodegenError::UnsupportedConversion(kind))
+        }
+        self.store(LEFT, &convert_instruction.dst)
+    }
+}
+
+pub fn link(codes: &[PotatoCodes<Identifier>]) -> Vec<PotatoCodes> {
+    try_link(codes).unwrap_or_else(|err| panic!("{}", err))
+}
+pub fn try_link(
+    codes: &[PotatoCodes<Identifier>]
+) -> Result<Vec<PotatoCodes>, PotatoCodegenError> {
+    /*
+    Removes the labels from the codes, and replaces every reference to
+    a label with the index of the instruction that follows the label.
+    Label names have to be unique across all the codes being linked
+    */
+    let mut label_indices: HashMap<String, usize> = HashMap::new();
+    let mut num_instructions = 0;
+    for code in codes {
+        match code {
+            PotatoCodes::Label(label) => {
+                if label_indices.insert(label.name.clone(), num_instructions).is_some() {
+                    return Err(PotatoCodegenError::DuplicateLabel(label.name.clone()));
+                }
+            },
+            _ => num_instructions += 1
+        }
+    }
+
+    let resolve = |label: &Identifier| match label_indices.get(&label.name) {
+        Some(index) => Ok(*index),
+        None => Err(PotatoCodegenError::UndefinedLabel(label.name.clone()))
+    };
+    codes.iter().map(|code| Ok(Some(match code {
+        PotatoCodes::Label(_) => return Ok(None),
+        PotatoCodes::MovDataValueToRegister(label, register) => {
+            PotatoCodes::MovDataValueToRegister(resolve(label)?, register.clone())
+        },
+        PotatoCodes::JumpIfZero(label) => PotatoCodes::JumpIfZero(resolve(label)?),
+        PotatoCodes::Jump(label) => PotatoCodes::Jump(resolve(label)?),
+        PotatoCodes::JumpIfFlag(flag, label) => {
+            PotatoCodes::JumpIfFlag(flag.clone(), resolve(label)?)
+        },
+        PotatoCodes::JumpIfNotFlag(flag, label) => {
+            PotatoCodes::JumpIfNotFlag(flag.clone(), resolve(label)?)
+        },
+        PotatoCodes::Call(label) => PotatoCodes::Call(resolve(label)?),
+        PotatoCodes::Return => PotatoCodes::Return,
+        PotatoCodes::MovRegisterToStack(register, address) => {
+            PotatoCodes::MovRegisterToStack(register.clone(), *address)
+        },
+        PotatoCodes::MovStackToRegister(params) => {
+            PotatoCodes::MovStackToRegister(params.clone())
+        },
+        PotatoCodes::CopyRegisterToRegister(src, dst) => {
+            PotatoCodes::CopyRegisterToRegister(src.clone(), dst.clone())
+        },
+        PotatoCodes::StrideMovRegisterToStack(params) => {
+            PotatoCodes::StrideMovRegisterToStack(params.clone())
+        },
+        PotatoCodes::StrideMovStackToRegister(params) => {
+            PotatoCodes::StrideMovStackToRegister(params.clone())
+        },
+        PotatoCodes::Operate(operation) => PotatoCodes::Operate(operation.clone()),
+        PotatoCodes::DataValue(value) => PotatoCodes::DataValue(value.clone()),
+    }))).filter_map(Result::transpose).collect()
 }
 
 #[cfg(test)]
 mod tests {
+    use std::path::Path;
+    use std::process::Command;
+    use crate::assembler::{build_from_filepath, OutputKind};
     use crate::potato_cpu::potato_asm::{PotatoFunction, PotatoProgram};
+    use crate::potato_cpu::potato_cpu::{
+        FlagsRegister, OverflowPolicy, PotatoSpecError, StopReason, StrideMovRegisterToStack,
+        StrideMovStackToRegister, Watchpoint
+    };
     use crate::tacky;
+    use crate::tacky::tacky_symbols::tacky_gen_from_str;
     use super::*;
 
     #[test]
@@ -117,4 +821,633 @@ mod tests {
         let return_value = potato_program.execute();
         assert_eq!(return_value, 100);
     }
-}
\ No newline at end of file
+
+    fn potato_result(source: &str) -> i64 {
+        PotatoProgram::from_tacky_program(tacky_gen_from_str(source).unwrap()).execute()
+    }
+    fn x86_exit_code(source: &str, name: &str) -> i32 {
+        let source_path = std::env::temp_dir().join(format!("{name}.c"));
+        std::fs::write(&source_path, source).unwrap();
+        let exe_path = build_from_filepath(
+            &source_path, &OutputKind::Executable, None, false
+        ).unwrap();
+        let status = Command::new(Path::new(&exe_path)).status().unwrap();
+        std::fs::remove_file(&source_path).unwrap();
+        std::fs::remove_file(&exe_path).unwrap();
+        status.code().unwrap()
+    }
+    fn assert_matches_x86(source: &str, name: &str) {
+        // exit codes only keep the lowest byte of the return value
+        let potato_value = potato_result(source);
+        assert_eq!(potato_value & 0xff, x86_exit_code(source, name) as i64);
+    }
+
+    #[test]
+    fn test_taken_jumps_land_on_target() {
+        let spec = PotatoSpec::new(vec![
+            PotatoCodes::DataValue(GrowableBitAllocation::from_num(7)),
+            PotatoCodes::DataValue(GrowableBitAllocation::from_num(9)),
+            PotatoCodes::Jump(4),
+            PotatoCodes::MovDataValueToRegister(1, Registers::FunctionReturn),
+            PotatoCodes::MovDataValueToRegister(0, Registers::FunctionReturn),
+            // past the last instruction
+            PotatoCodes::Jump(7),
+            PotatoCodes::MovDataValueToRegister(1, Registers::FunctionReturn),
+        ], NUM_SCRATCH_REGISTERS, STACK_WIDTH as u16);
+        let mut cpu = PotatoCPU::new(&spec);
+        assert!(cpu.run(100).halted);
+        assert_eq!(cpu.read_register(Registers::FunctionReturn).to_i64(), Some(7));
+    }
+
+    #[test]
+    fn test_link_composed_codes() {
+        let label = |name: &str| Identifier::new(name.to_string());
+        let data = GrowableBitAllocation::from_num(3);
+        let mut codes = vec![
+            PotatoCodes::Jump(label("skip")),
+            PotatoCodes::Label(label("value")),
+            PotatoCodes::DataValue(data.clone()),
+        ];
+        // a second piece of code that refers back to the first one
+        codes.extend(vec![
+            PotatoCodes::Label(label("skip")),
+            PotatoCodes::MovDataValueToRegister(label("value"), Registers::FunctionReturn),
+            PotatoCodes::JumpIfZero(label("end")),
+            PotatoCodes::Label(label("end")),
+        ]);
+        assert_eq!(link(&codes), vec![
+            PotatoCodes::Jump(2),
+            PotatoCodes::DataValue(data),
+            PotatoCodes::MovDataValueToRegister(1, Registers::FunctionReturn),
+            PotatoCodes::JumpIfZero(4),
+        ]);
+    }
+
+    #[test]
+    #[should_panic(expected = "Reference to undefined potato label missing")]
+    fn test_link_undefined_label() {
+        link(&[PotatoCodes::Jump(Identifier::new("missing".to_string()))]);
+    }
+
+    #[test]
+    fn test_unsupported_programs() {
+        let lower = |source: &str| {
+            PotatoProgram::try_from_tacky_program(tacky_gen_from_str(source).unwrap()).err()
+        };
+        assert!(matches!(
+            lower("int main(void) { int a = 3; int *p = &a; return *p; }"),
+            Some(PotatoCodegenError::UnsupportedInstruction(_))
+        ));
+        assert_eq!(
+            lower("int main(void) { double d = 1.5; return d > 1.0; }"),
+            Some(PotatoCodegenError::UnsupportedType(CType::Double))
+        );
+        let label = Identifier::new("twice".to_string());
+        assert_eq!(
+            try_link(&[PotatoCodes::Label(label.clone()), PotatoCodes::Label(label)]),
+            Err(PotatoCodegenError::DuplicateLabel("twice".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_alu_flags() {
+        let label = |name: &str| Identifier::new(name.to_string());
+        let four_bits = |value: usize| {
+            let mut bits = GrowableBitAllocation::from_num(value);
+            bits.resize(4);
+            PotatoCodes::DataValue(bits)
+        };
+        let codes = link(&[
+            PotatoCodes::Label(label("fifteen")),
+            four_bits(15),
+            PotatoCodes::Label(label("one")),
+            four_bits(1),
+            PotatoCodes::MovDataValueToRegister(label("fifteen"), Registers::InputA),
+            PotatoCodes::MovDataValueToRegister(label("one"), Registers::InputB),
+            // 15 + 1 overflows 4 bits into zero
+            PotatoCodes::Operate(ALUOperations::Add),
+            PotatoCodes::JumpIfNotFlag(Flags::Carry, label("end")),
+            PotatoCodes::JumpIfNotFlag(Flags::Zero, label("end")),
+            PotatoCodes::JumpIfFlag(Flags::Negative, label("end")),
+            PotatoCodes::MovDataValueToRegister(label("one"), Registers::FunctionReturn),
+            PotatoCodes::Label(label("end")),
+        ]);
+        let spec = PotatoSpec::new(codes, NUM_SCRATCH_REGISTERS, STACK_WIDTH as u16);
+        let mut cpu = PotatoCPU::new(&spec);
+        assert!(cpu.run(100).halted);
+        assert_eq!(cpu.read_register(Registers::FunctionReturn).to_i64(), Some(1));
+
+        let flags = FlagsRegister::from_result(&GrowableBitAllocation::from_num(0b1000), 4);
+        assert_eq!(flags, FlagsRegister { zero: false, negative: true, carry: false });
+    }
+
+    #[test]
+    fn test_overflow_policies() {
+        let add = |a: usize, b: usize, overflow_policy: OverflowPolicy| {
+            let spec = PotatoSpec::new(
+                vec![PotatoCodes::Operate(ALUOperations::Add)],
+                NUM_SCRATCH_REGISTERS, STACK_WIDTH as u16
+            ).with_max_register_width(8, overflow_policy);
+            let mut cpu = PotatoCPU::new(&spec);
+            *cpu.load_register(Registers::InputA) = GrowableBitAllocation::from_num(a);
+            *cpu.load_register(Registers::InputB) = GrowableBitAllocation::from_num(b);
+            cpu.step();
+            (cpu.read_register(Registers::Output).clone(), cpu.flags.carry)
+        };
+        let eight_bits = |value: usize| {
+            let mut bits = GrowableBitAllocation::from_num(value);
+            bits.resize(8);
+            bits
+        };
+
+        assert_eq!(add(255, 1, OverflowPolicy::Wrap), (eight_bits(0), true));
+        assert_eq!(add(255, 1, OverflowPolicy::Saturate), (eight_bits(255), true));
+        let grown = GrowableBitAllocation::from_num(256);
+        assert_eq!(add(255, 1, OverflowPolicy::Grow), (grown, true));
+        // results that fit in the max width are left alone
+        let sum = GrowableBitAllocation::from_num(127);
+        assert_eq!(add(100, 27, OverflowPolicy::Wrap), (sum, false));
+
+        let spec = PotatoSpec::new(vec![], NUM_SCRATCH_REGISTERS, STACK_WIDTH as u16)
+            .with_max_register_width(0, OverflowPolicy::Wrap);
+        assert_eq!(spec.validate(), Err(PotatoSpecError::ZeroMaxRegisterWidth));
+    }
+
+    #[test]
+    fn test_rotate_left() {
+        let spec = PotatoSpec::new(
+            vec![PotatoCodes::Operate(ALUOperations::RotateLeft)],
+            NUM_SCRATCH_REGISTERS, STACK_WIDTH as u16
+        );
+        let rotate = |value: u16, num_places: usize| {
+            let mut cpu = PotatoCPU::new(&spec);
+            *cpu.load_register(Registers::InputA) = GrowableBitAllocation::from_signed_i64(
+                value as i64, 16
+            );
+            *cpu.load_register(Registers::InputB) = GrowableBitAllocation::from_num(num_places);
+            cpu.step();
+            let result = cpu.read_register(Registers::Output);
+            assert_eq!(result.get_length(), 16);
+            u64::try_from(result).unwrap() as u16
+        };
+
+        for value in [0u16, 1, 0x8001, 0xBEEF, 0xFFFF] {
+            for num_places in 0..40 {
+                // bits move towards the start of the (little endian) bit vector
+                let expected = value.rotate_right(num_places as u32);
+                assert_eq!(rotate(value, num_places), expected, "{:x} by {}", value, num_places);
+                // rotating right by k is rotating left by (n - k)
+                let expected = value.rotate_left(num_places as u32);
+                assert_eq!(rotate(value, 16 - num_places % 16), expected);
+            }
+        }
+        let mut empty = PotatoCPU::new(&spec);
+        *empty.load_register(Registers::InputB) = GrowableBitAllocation::from_num(3);
+        empty.step();
+        assert_eq!(empty.read_register(Registers::Output).get_length(), 0);
+    }
+
+    #[test]
+    fn test_call_and_return() {
+        let label = |name: &str| Identifier::new(name.to_string());
+        let codes = link(&[
+            PotatoCodes::Call(label("set_return")),
+            // the subroutine returns here, and returning again halts
+            PotatoCodes::Return,
+            PotatoCodes::Label(label("set_return")),
+            PotatoCodes::Call(label("load_value")),
+            PotatoCodes::Return,
+            PotatoCodes::Label(label("load_value")),
+            PotatoCodes::Label(label("value")),
+            PotatoCodes::DataValue(GrowableBitAllocation::from_num(6)),
+            PotatoCodes::MovDataValueToRegister(label("value"), Registers::FunctionReturn),
+            PotatoCodes::Return,
+        ]);
+        assert_eq!(codes[0], PotatoCodes::Call(2));
+        let spec = PotatoSpec::new(codes, NUM_SCRATCH_REGISTERS, STACK_WIDTH as u16);
+        let mut cpu = PotatoCPU::new(&spec);
+        let run_result = cpu.run(100);
+        assert!(run_result.halted);
+        assert_eq!(run_result.time_steps, 7);
+        assert!(cpu.call_stack.is_empty());
+        assert_eq!(cpu.read_register(Registers::FunctionReturn).to_i64(), Some(6));
+    }
+
+    #[test]
+    fn test_execution_trace() {
+        let label = |name: &str| Identifier::new(name.to_string());
+        let codes = link(&[
+            PotatoCodes::Jump(label("start")),
+            PotatoCodes::Label(label("value")),
+            PotatoCodes::DataValue(GrowableBitAllocation::from_num(5)),
+            PotatoCodes::Label(label("start")),
+            PotatoCodes::MovDataValueToRegister(label("value"), Registers::FunctionReturn),
+            // doesn't change any register
+            PotatoCodes::MovDataValueToRegister(label("value"), Registers::FunctionReturn),
+        ]);
+        let spec = PotatoSpec::new(codes, NUM_SCRATCH_REGISTERS, STACK_WIDTH as u16);
+        let mut cpu = PotatoCPU::new(&spec).with_tracing();
+        assert!(cpu.run(100).halted);
+
+        let trace = cpu.trace.unwrap();
+        let program_counters: Vec<usize> = trace.entries.iter()
+            .map(|entry| entry.program_counter)
+            .collect();
+        assert_eq!(program_counters, vec![0, 2, 3]);
+        assert_eq!(trace.entries[0].instruction, PotatoCodes::Jump(2));
+        assert!(trace.entries[0].register_deltas.is_empty());
+        assert_eq!(trace.entries[1].register_deltas, vec![
+            (Registers::FunctionReturn, GrowableBitAllocation::from_num(5))
+        ]);
+        assert!(trace.entries[2].register_deltas.is_empty());
+
+        // tracing is off unless asked for
+        let mut cpu = PotatoCPU::new(&spec);
+        cpu.run(100);
+        assert!(cpu.trace.is_none());
+
+        let program = PotatoProgram::from_tacky_program(
+            tacky_gen_from_str("int main(void) { int a = 3; return a * 2; }").unwrap()
+        );
+        let (return_value, trace) = program.execute_with_trace();
+        assert_eq!(return_value, 6);
+        assert_eq!(return_value, program.execute());
+        assert!(!trace.is_empty());
+        assert_eq!(trace.entries.last().unwrap().instruction, PotatoCodes::Return);
+    }
+
+    #[test]
+    fn test_breakpoints_and_watchpoints() {
+        let label = |name: &str| Identifier::new(name.to_string());
+        let codes = link(&[
+            PotatoCodes::Jump(label("start")),
+            PotatoCodes::Label(label("value")),
+            PotatoCodes::DataValue(GrowableBitAllocation::from_num(5)),
+            PotatoCodes::Label(label("start")),
+            PotatoCodes::MovDataValueToRegister(label("value"), LEFT),
+            PotatoCodes::MovRegisterToStack(LEFT, 3),
+            PotatoCodes::MovDataValueToRegister(label("value"), Registers::FunctionReturn),
+        ]);
+        let spec = PotatoSpec::new(codes, NUM_SCRATCH_REGISTERS, STACK_WIDTH as u16);
+        let mut cpu = PotatoCPU::new(&spec);
+        cpu.add_breakpoint(3);
+        cpu.add_watchpoint(Watchpoint::StackAddress(3));
+        cpu.add_watchpoint(Watchpoint::Register(Registers::FunctionReturn));
+
+        let run_result = cpu.run(100);
+        assert_eq!(run_result.stop_reason, Some(StopReason::Breakpoint(3)));
+        assert_eq!(run_result.time_steps, 2);
+        // resuming executes the instruction at the breakpoint
+        let run_result = cpu.run(100);
+        assert_eq!(
+            run_result.stop_reason, Some(StopReason::Watchpoint(Watchpoint::StackAddress(3), 3))
+        );
+        let run_result = cpu.run(100);
+        assert_eq!(run_result.stop_reason, Some(StopReason::Watchpoint(
+            Watchpoint::Register(Registers::FunctionReturn), 4
+        )));
+
+        assert!(cpu.remove_watchpoint(&Watchpoint::StackAddress(3)));
+        assert!(!cpu.remove_watchpoint(&Watchpoint::StackAddress(3)));
+        let run_result = cpu.run(100);
+        assert!(run_result.halted);
+        assert_eq!(run_result.stop_reason, None);
+    }
+
+    #[test]
+    fn test_loop_detection() {
+        let label = |name: &str| Identifier::new(name.to_string());
+        let codes = link(&[
+            PotatoCodes::Jump(label("start")),
+            PotatoCodes::Label(label("one")),
+            PotatoCodes::DataValue(GrowableBitAllocation::from_num(1)),
+            PotatoCodes::Label(label("start")),
+            PotatoCodes::MovDataValueToRegister(label("one"), Registers::InputB),
+            PotatoCodes::Label(label("loop")),
+            PotatoCodes::Operate(ALUOperations::Add),
+            PotatoCodes::CopyRegisterToRegister(Registers::Output, Registers::InputA),
+            PotatoCodes::Jump(label("loop")),
+        ]);
+        let spec = PotatoSpec::new(codes.clone(), NUM_SCRATCH_REGISTERS, STACK_WIDTH as u16);
+        // the counter in InputA keeps growing, so no state repeats
+        let mut cpu = PotatoCPU::new(&spec).with_loop_detection();
+        let run_result = cpu.run(1000);
+        assert!(!run_result.halted);
+        assert_eq!(run_result.stop_reason, None);
+
+        // without the copy, every iteration starts from the same state
+        let mut codes = codes;
+        codes[4] = PotatoCodes::CopyRegisterToRegister(Registers::Output, TEMP);
+        let spec = PotatoSpec::new(codes, NUM_SCRATCH_REGISTERS, STACK_WIDTH as u16);
+        let mut cpu = PotatoCPU::new(&spec).with_loop_detection();
+        let run_result = cpu.run(1000);
+        assert!(run_result.halted);
+        // the second pass through the jump back is the first repeated state
+        assert_eq!(run_result.stop_reason, Some(StopReason::LoopDetected(5)));
+        assert_eq!(run_result.time_steps, 7);
+        // loop detection is off by default
+        assert!(!PotatoCPU::new(&spec).run(1000).halted);
+    }
+
+    #[test]
+    fn test_modeled_ca_time() {
+        // adding two n bit values should take O(n) time
+        let ca_time_of_add = |width: usize| {
+            let label = |name: &str| Identifier::new(name.to_string());
+            let mut value = GrowableBitAllocation::from_num(1);
+            value.resize(width);
+            let codes = link(&[
+                PotatoCodes::Jump(label("start")),
+                PotatoCodes::Label(label("value")),
+                PotatoCodes::DataValue(value),
+                PotatoCodes::Label(label("start")),
+                PotatoCodes::MovDataValueToRegister(label("value"), Registers::InputA),
+                PotatoCodes::MovDataValueToRegister(label("value"), Registers::InputB),
+                PotatoCodes::Operate(ALUOperations::Add),
+            ]);
+            let spec = PotatoSpec::new(codes, NUM_SCRATCH_REGISTERS, STACK_WIDTH as u16);
+            let mut cpu = PotatoCPU::new(&spec);
+            let run_result = cpu.run(100);
+            assert!(run_result.halted);
+            assert_eq!(run_result.ca_time, cpu.ca_time);
+            run_result.ca_time
+        };
+        // one unit for the jump, then the width for each of the moves and the add
+        assert_eq!(ca_time_of_add(8), 1 + 3 * 8);
+        assert_eq!(ca_time_of_add(64), 1 + 3 * 64);
+
+        let a = GrowableBitAllocation::from_num(0b101);
+        let shift = GrowableBitAllocation::from_num(4);
+        assert_eq!(ALUOperations::ShiftLeft.cost(&a, &shift), 7);
+        assert_eq!(ALUOperations::GetLength.cost(&GrowableBitAllocation::new(0), &shift), 1);
+    }
+
+    #[test]
+    fn test_shift_add_multiply_cost() {
+        /*
+        Without a native multiply ALU operation, products are computed
+        with one shift and add iteration per bit of the multiplier.
+        A native operation would still be O(n^2) in the cellular automaton
+        (n additions of n bits), so the loop only costs a constant factor more
+        */
+        let ca_time_of_product = |left: i32, right: i32| {
+            let source = format!(
+                "int main(void) {{ int a = {}; int b = {}; return a * b; }}", left, right
+            );
+            let program = PotatoProgram::from_tacky_program(tacky_gen_from_str(&source).unwrap());
+            assert_eq!(program.execute(), left.wrapping_mul(right) as i64, "{}", source);
+            let mut cpu = PotatoCPU::new(&program.spec());
+            cpu.run(MAX_EXECUTION_STEPS).ca_time
+        };
+        // the loop stops once the multiplier has no bits left
+        let small = ca_time_of_product(-12345, 3);
+        let large = ca_time_of_product(7, 1 << 20);
+        let negative = ca_time_of_product(7, -3);
+        assert!(small < large && large < negative);
+        // negative multipliers take all 32 iterations, at roughly 43 times the native estimate
+        let native_ca_time = 32 * 32;
+        assert!(negative < 64 * native_ca_time);
+    }
+
+    #[test]
+    fn test_output_port() {
+        let label = |name: &str| Identifier::new(name.to_string());
+        let byte = |value: usize| {
+            let mut bits = GrowableBitAllocation::from_num(value);
+            bits.resize(STACK_WIDTH);
+            PotatoCodes::DataValue(bits)
+        };
+        // two chunks, whose lowest bytes are 'i' and '!'
+        let mut two_chunks = GrowableBitAllocation::from_num(
+            (('!' as usize) << STACK_WIDTH) | 'i' as usize
+        );
+        two_chunks.resize(2 * STACK_WIDTH);
+        let codes = link(&[
+            PotatoCodes::Jump(label("start")),
+            PotatoCodes::Label(label("letter")),
+            byte('H' as usize),
+            PotatoCodes::Label(label("letters")),
+            PotatoCodes::DataValue(two_chunks),
+            PotatoCodes::Label(label("start")),
+            PotatoCodes::MovDataValueToRegister(label("letter"), LEFT),
+            // outside of the output port
+            PotatoCodes::MovRegisterToStack(LEFT, 7),
+            PotatoCodes::MovRegisterToStack(LEFT, 8),
+            PotatoCodes::MovDataValueToRegister(label("letters"), LEFT),
+            PotatoCodes::MovRegisterToStack(LEFT, 8),
+        ]);
+        let spec = PotatoSpec::new(codes, NUM_SCRATCH_REGISTERS, STACK_WIDTH as u16)
+            .with_output_port(8..10);
+        let mut cpu = PotatoCPU::new(&spec);
+        assert!(cpu.run(100).halted);
+        assert_eq!(String::from_utf8(cpu.output).unwrap(), "Hi!");
+    }
+
+    #[test]
+    fn test_spec_validation() {
+        let validate = |codes: Vec<PotatoCodes>| {
+            PotatoSpec::new(codes, 2, STACK_WIDTH as u16).validate()
+        };
+        let data_value = PotatoCodes::DataValue(GrowableBitAllocation::from_num(1));
+        // jumping one past the last instruction halts the cpu
+        assert_eq!(validate(vec![PotatoCodes::Jump(1)]), Ok(()));
+        assert_eq!(validate(vec![
+            data_value.clone(), PotatoCodes::MovDataValueToRegister(0, Registers::Scratch(1))
+        ]), Ok(()));
+
+        assert_eq!(
+            validate(vec![PotatoCodes::Return, PotatoCodes::Call(3)]),
+            Err(PotatoSpecError::JumpTargetOutOfRange { instruction_no: 1, target: 3 })
+        );
+        assert_eq!(
+            validate(vec![PotatoCodes::MovDataValueToRegister(0, Registers::InputA)]),
+            Err(PotatoSpecError::NotADataValue { instruction_no: 0, target: 0 })
+        );
+        assert_eq!(
+            validate(vec![PotatoCodes::CopyRegisterToRegister(
+                Registers::Output, Registers::Scratch(2)
+            )]),
+            Err(PotatoSpecError::ScratchRegisterOutOfRange {
+                instruction_no: 0, scratch_register_no: 2
+            })
+        );
+        let error = validate(vec![PotatoCodes::StrideMovStackToRegister(
+            StrideMovStackToRegister::new(0, 0, 4, Registers::InputA)
+        )]).err().unwrap();
+        assert_eq!(error, PotatoSpecError::ZeroStride { instruction_no: 0 });
+        assert_eq!(error.to_string(), "Instruction 0 has a stride of zero");
+        assert_eq!(
+            validate(vec![PotatoCodes::StrideMovStackToRegister(
+                StrideMovStackToRegister::new(0, 1, 0, Registers::InputA)
+            )]),
+            Err(PotatoSpecError::ZeroMaxChunks { instruction_no: 0 })
+        );
+    }
+
+    #[test]
+    fn test_stride_moves() {
+        let label = |name: &str| Identifier::new(name.to_string());
+        let value_of_width = |value: usize, width: usize| {
+            let mut bits = GrowableBitAllocation::from_num(value);
+            bits.resize(width);
+            bits
+        };
+        // three chunks and two chunks, interleaved on the stack
+        let long_value = value_of_width(0x1234_5678_9abc_def0, 3 * STACK_WIDTH);
+        let short_value = value_of_width(0x0fed_cba9_8765_4321, 2 * STACK_WIDTH);
+        let codes = link(&[
+            PotatoCodes::Jump(label("start")),
+            PotatoCodes::Label(label("long")),
+            PotatoCodes::DataValue(long_value.clone()),
+            PotatoCodes::Label(label("short")),
+            PotatoCodes::DataValue(short_value.clone()),
+            PotatoCodes::Label(label("start")),
+            PotatoCodes::MovDataValueToRegister(label("long"), LEFT),
+            PotatoCodes::StrideMovRegisterToStack(StrideMovRegisterToStack::new(LEFT, 10, 2)),
+            PotatoCodes::MovDataValueToRegister(label("short"), LEFT),
+            PotatoCodes::StrideMovRegisterToStack(StrideMovRegisterToStack::new(LEFT, 12, 2)),
+            PotatoCodes::StrideMovStackToRegister(
+                StrideMovStackToRegister::new(10, 2, 8, RIGHT)
+            ),
+            PotatoCodes::StrideMovStackToRegister(
+                StrideMovStackToRegister::new(12, 2, 8, RESULT)
+            ),
+            // the continue flags would carry on past two chunks
+            PotatoCodes::StrideMovStackToRegister(
+                StrideMovStackToRegister::new(10, 2, 2, TEMP)
+            ),
+        ]);
+        let spec = PotatoSpec::new(codes, NUM_SCRATCH_REGISTERS, STACK_WIDTH as u16);
+        assert_eq!(spec.validate(), Ok(()));
+        let mut cpu = PotatoCPU::new(&spec);
+        assert!(cpu.run(100).halted);
+        assert_eq!(cpu.read_register(RIGHT), &long_value);
+        assert_eq!(cpu.read_register(RESULT), &short_value);
+        assert_eq!(
+            cpu.read_register(TEMP), &value_of_width(0x1234_5678_9abc_def0, 2 * STACK_WIDTH)
+        );
+        // chunk k of a value is 2 * stride stack addresses after chunk k - 1
+        assert_eq!(cpu.stack.len(), 10 + 2 * 4 + 2);
+    }
+
+    #[test]
+    fn test_short_circuit_operators() {
+        assert_matches_x86(
+            "int main(void) {
+                int a = 0; int b = 3; long l = 4294967296l;
+                return (a && b) + (b && 2) * 2 + (a || 0) * 4 + (a || b) * 8
+                    + (b || (a = 5)) * 16 + (a && (a = 7)) + a * 32 + (l && 1) * 64;
+            }",
+            "ca_compiler_potato_short_circuit"
+        );
+        // nested operators each need their own labels
+        assert_matches_x86(
+            "int main(void) {
+                int a = 1; int b = 0; int c = 2;
+                return ((a && (b || c)) || (b && (a || c))) + ((a && b) || a) * 2;
+            }",
+            "ca_compiler_potato_nested_short_circuit"
+        );
+    }
+
+    #[test]
+    fn test_signed_return_values() {
+        assert_eq!(potato_result("int main(void) { return -5; }"), -5);
+        assert_eq!(potato_result("char main(void) { return -2; }"), -2);
+        assert_eq!(potato_result("unsigned char main(void) { return -2; }"), 254);
+        assert_eq!(potato_result("long main(void) { return -2l - 9223372036854775806l; }"), i64::MIN);
+        assert_eq!(potato_result("unsigned int main(void) { return -1; }"), 4294967295);
+        assert_eq!(potato_result("long main(void) { long a = -3; return a - 4000000000; }"), -4000000003);
+    }
+
+    #[test]
+    fn test_unary_and_additive_expressions() {
+        assert_matches_x86(
+            "int main(void) { int a = 7; int b = -3; return (a - b) * 2 + -a + ~b + !a + !0; }",
+            "ca_compiler_potato_unary"
+        );
+    }
+
+    #[test]
+    fn test_cancelled_execution() {
+        let tacky_program = tacky_gen_from_str("int main(void) { return 4 * 5; }").unwrap();
+        let program = PotatoProgram::from_tacky_program(tacky_program);
+        let mut num_checks = 0;
+        let result = program.try_execute_cancellable(&mut || {
+            num_checks += 1;
+            true
+        });
+        assert_eq!(result, Err(PotatoExecutionError::Cancelled));
+        assert_eq!(num_checks, 1);
+        assert_eq!(program.try_execute_cancellable(&mut || false), Ok(20));
+        let result = program
+            .try_execute_on_cancellable::<crate::potato_cpu::potato_vm::PotatoCPU>(&mut || false);
+        assert_eq!(result, Ok(20));
+    }
+
+    #[test]
+    fn test_trace_of_looping_program() {
+        // jumps back to itself forever
+        let start = Identifier::new("start".to_string());
+        let mut function = PotatoFunction::new("main".to_string());
+        function.emit(PotatoCodes::Label(start.clone()));
+        function.emit(PotatoCodes::Jump(start));
+        let program = PotatoProgram::new(function);
+        let result = program.try_execute_with_trace();
+        assert_eq!(result.err(), Some(PotatoExecutionError::LoopDetected(0)));
+    }
+
+    #[test]
+    fn test_invalid_return_value() {
+        let mut function = PotatoFunction::new("main".to_string());
+        function.return_type = CType::UnsignedLong;
+        let too_large = GrowableBitAllocation::from_big_num(&(BigUint::from(1u8) << 70));
+        assert_eq!(
+            function.return_value_of(&too_large),
+            Err(PotatoExecutionError::InvalidReturnValue(
+                "Value needs 71 bits, which doesn't fit in a u64".to_string()
+            ))
+        );
+        assert_eq!(function.return_value_of(&GrowableBitAllocation::from_num(7)), Ok(7));
+    }
+
+    #[test]
+    fn test_multiplication_and_division() {
+        assert_matches_x86(
+            "int main(void) {
+                int a = -17; int b = 5; unsigned int u = 4000000000u;
+                return a * b + a / b + a % b * 10 + (int) (u / 7u % 100u) + 17 % -5;
+            }",
+            "ca_compiler_potato_division"
+        );
+    }
+
+    #[test]
+    fn test_long_multiplication_and_division() {
+        let source = "long main(void) {
+            long a = -9000000000l; long b = 7l; unsigned long u = 18000000000000000000ul;
+            return a / b + a % b * 3 + a * 5 / 1000000 + (long) (u / 3ul % 1000ul) + b * b;
+        }";
+        assert_matches_x86(source, "ca_compiler_potato_long_division");
+        // the multiplication and division loops aren't unrolled
+        let tacky_program = tacky_gen_from_str(source).unwrap();
+        let function = PotatoFunction::from_tacky_function(
+            tacky_program.function, &tacky_program.static_variables
+        );
+        assert!(function.link().len() < 2000);
+    }
+
+    #[test]
+    fn test_bitwise_comparisons_and_conversions() {
+        assert_matches_x86(
+            "int g = 6;
+            int main(void) {
+                int a = -20; long l = 3l; unsigned int u = 5u; char c = (char) 200;
+                return (a < 3) + (a >= -20) * 2 + (u > 4294967295u) * 4 + (a < u) * 8
+                    + ((a >> 2) & 255) + (int) (l << 4) + (u >> 1) + (a != a) + (a == -20)
+                    + (a ^ g | 1) + c + (long) a * 0;
+            }",
+            "ca_compiler_potato_bitwise"
+        );
+    }
+}
@@ -0,0 +1,383 @@
+use std::fmt::{Display, Formatter};
+use arbitrary_int::u4;
+use crate::parser::parse::Identifier;
+use crate::potato_cpu::bit_allocation::GrowableBitAllocation;
+use crate::potato_cpu::potato_cpu::{
+    ALUOperations, Flags, MovStackToRegister, PotatoCodes, Registers,
+    StrideMovRegisterToStack, StrideMovStackToRegister
+};
+
+/*
+Textual syntax for linked potato programs, one instruction per line,
+so that they can be written, diffed and stored outside of Rust code.
+
+    jump 2
+    data 0b0101
+    mov_data 1, s0
+    copy s0, in_a
+    copy s0, in_b
+    op add
+    op bitwise 0b0110
+    jump_if_not_flag carry, 9
+    mov_reg_stack out, 4
+    mov_stack_reg 4, 2, fn_ret
+    return
+
+Jump targets are instruction indices, and data values are written
+most significant bit first, keeping their leading zeros since the
+width of a value matters to the ALU. Registers are pc, in_a, in_b,
+fn_in, sp, bp, out, fn_ret and s<n> for scratch register n.
+Lines starting with // are comments
+*/
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct PotatoAsmTextError {
+    // 1-indexed line of the text that the error was found on
+    pub line: usize,
+    pub message: String,
+}
+impl Display for PotatoAsmTextError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "PotatoAsmTextError at line {}: {}", self.line, self.message)
+    }
+}
+
+const REGISTER_NAMES: [(&str, Registers); 8] = [
+    ("pc", Registers::ProgramCounter),
+    ("in_a", Registers::InputA),
+    ("in_b", Registers::InputB),
+    ("fn_in", Registers::FunctionInput),
+    ("sp", Registers::StackPointer),
+    ("bp", Registers::BasePointer),
+    ("out", Registers::Output),
+    ("fn_ret", Registers::FunctionReturn),
+];
+const ALU_OPERATION_NAMES: [(&str, ALUOperations); 9] = [
+    ("add", ALUOperations::Add),
+    ("reverse_bits", ALUOperations::ReverseBits),
+    ("shift_left", ALUOperations::ShiftLeft),
+    ("shift_right", ALUOperations::ShiftRight),
+    ("rotate_left", ALUOperations::RotateLeft),
+    ("compare_gt", ALUOperations::CompareGreaterThan),
+    ("get_length", ALUOperations::GetLength),
+    ("resize", ALUOperations::Resize),
+    ("resize_modulo", ALUOperations::ResizeModulo),
+];
+const FLAG_NAMES: [(&str, Flags); 3] = [
+    ("zero", Flags::Zero),
+    ("negative", Flags::Negative),
+    ("carry", Flags::Carry),
+];
+
+/*
+Emitter
+*/
+
+pub fn emit_potato_register(register: &Registers) -> String {
+    match register {
+        Registers::Scratch(scratch_register_no) => format!("s{}", scratch_register_no),
+        _ => REGISTER_NAMES.iter().find(|(_, candidate)| candidate == register)
+            .unwrap().0.to_string()
+    }
+}
+fn emit_flag(flag: &Flags) -> &'static str {
+    FLAG_NAMES.iter().find(|(_, candidate)| candidate == flag).unwrap().0
+}
+fn emit_bits(bits: &[bool]) -> String {
+    let digits: String = bits.iter().rev().map(|bit| if *bit { '1' } else { '0' }).collect();
+    format!("0b{}", digits)
+}
+
+fn emit_alu_operation(operation: &ALUOperations) -> String {
+    match operation {
+        ALUOperations::BitwiseNOperation(truth_table) => {
+            let bits: Vec<bool> = (0..4).map(|index| (truth_table.value() >> index) & 1 == 1)
+                .collect();
+            format!("bitwise {}", emit_bits(&bits))
+        },
+        _ => ALU_OPERATION_NAMES.iter().find(|(_, candidate)| candidate == operation)
+            .unwrap().0.to_string()
+    }
+}
+
+pub fn emit_potato_instruction(instruction: &PotatoCodes) -> String {
+    match instruction {
+        PotatoCodes::MovRegisterToStack(register, stack_address) => format!(
+            "mov_reg_stack {}, {}", emit_potato_register(register), stack_address
+        ),
+        PotatoCodes::MovStackToRegister(params) => format!(
+            "mov_stack_reg {}, {}, {}", params.stack_address,
+            params.num_stack_addresses, emit_potato_register(&params.register)
+        ),
+        PotatoCodes::CopyRegisterToRegister(src, dst) => format!(
+            "copy {}, {}", emit_potato_register(src), emit_potato_register(dst)
+        ),
+        PotatoCodes::StrideMovRegisterToStack(params) => format!(
+            "stride_mov_reg_stack {}, {}, {}", emit_potato_register(&params.register),
+            params.start_stack_address, params.stride
+        ),
+        PotatoCodes::StrideMovStackToRegister(params) => format!(
+            "stride_mov_stack_reg {}, {}, {}, {}", params.start_stack_address,
+            params.stride, params.max_chunks, emit_potato_register(&params.register)
+        ),
+        PotatoCodes::Operate(operation) => format!("op {}", emit_alu_operation(operation)),
+        PotatoCodes::DataValue(value) => format!("data {:#b}", value),
+        PotatoCodes::MovDataValueToRegister(target, register) => format!(
+            "mov_data {}, {}", target, emit_potato_register(register)
+        ),
+        PotatoCodes::JumpIfZero(target) => format!("jump_if_zero {}", target),
+        PotatoCodes::Jump(target) => format!("jump {}", target),
+        PotatoCodes::JumpIfFlag(flag, target) => format!(
+            "jump_if_flag {}, {}", emit_flag(flag), target
+        ),
+        PotatoCodes::JumpIfNotFlag(flag, target) => format!(
+            "jump_if_not_flag {}, {}", emit_flag(flag), target
+        ),
+        PotatoCodes::Call(target) => format!("call {}", target),
+        PotatoCodes::Return => "return".to_string(),
+        PotatoCodes::Label(label) => format!("label {}", label.name_to_string()),
+    }
+}
+
+pub fn emit_potato_asm(instructions: &[PotatoCodes]) -> String {
+    let mut text = String::new();
+    for instruction in instructions {
+        text.push_str(&format!("{}\n", emit_potato_instruction(instruction)));
+    }
+    text
+}
+
+/*
+Parser
+*/
+
+struct PotatoAsmTextParser {
+    line_number: usize,
+}
+impl PotatoAsmTextParser {
+    fn error<T>(&self, message: String) -> Result<T, PotatoAsmTextError> {
+        Err(PotatoAsmTextError { line: self.line_number, message })
+    }
+
+    fn parse_register(&self, text: &str) -> Result<Registers, PotatoAsmTextError> {
+        match parse_potato_register(text) {
+            Some(register) => Ok(register),
+            None => self.error(format!("Unknown register {}", text))
+        }
+    }
+    fn parse_flag(&self, text: &str) -> Result<Flags, PotatoAsmTextError> {
+        match FLAG_NAMES.iter().find(|(name, _)| *name == text) {
+            Some((_, flag)) => Ok(flag.clone()),
+            None => self.error(format!("Unknown flag {}", text))
+        }
+    }
+    fn parse_number(&self, text: &str) -> Result<usize, PotatoAsmTextError> {
+        match text.parse::<usize>() {
+            Ok(number) => Ok(number),
+            Err(_) => self.error(format!("Invalid number {}", text))
+        }
+    }
+    fn parse_bits(&self, text: &str) -> Result<Vec<bool>, PotatoAsmTextError> {
+        let digits = match text.strip_prefix("0b") {
+            Some(digits) => digits,
+            None => return self.error(format!("Expected binary value, got {}", text))
+        };
+        // written most significant bit first, but stored least significant bit first
+        digits.chars().rev().map(|digit| match digit {
+            '0' => Ok(false),
+            '1' => Ok(true),
+            _ => self.error(format!("Invalid binary value {}", text))
+        }).collect()
+    }
+    fn split_operands<'a>(
+        &self, operands: &'a str, count: usize
+    ) -> Result<Vec<&'a str>, PotatoAsmTextError> {
+        let operands: Vec<&str> = match operands {
+            "" => vec![],
+            _ => operands.split(',').map(|operand| operand.trim()).collect()
+        };
+        if operands.len() != count {
+            return self.error(format!(
+                "Expected {} operands, got {}", count, operands.len()
+            ));
+        }
+        Ok(operands)
+    }
+
+    fn parse_alu_operation(&self, text: &str) -> Result<ALUOperations, PotatoAsmTextError> {
+        let (name, operand) = text.split_once(char::is_whitespace).unwrap_or((text, ""));
+        if name == "bitwise" {
+            let bits = self.parse_bits(operand.trim())?;
+            if bits.len() != 4 {
+                return self.error(format!("Expected 4 bit truth table, got {}", operand));
+            }
+            let truth_table = bits.iter().enumerate()
+                .fold(0u8, |table, (index, bit)| table | ((*bit as u8) << index));
+            return Ok(ALUOperations::BitwiseNOperation(u4::new(truth_table)));
+        }
+        match ALU_OPERATION_NAMES.iter().find(|(candidate, _)| *candidate == name) {
+            Some((_, operation)) => Ok(operation.clone()),
+            None => self.error(format!("Unknown ALU operation {}", text))
+        }
+    }
+
+    fn parse_instruction(&self, line: &str) -> Result<PotatoCodes, PotatoAsmTextError> {
+        let (opcode, operands) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let operands = operands.trim();
+        let instruction = match opcode {
+            "mov_reg_stack" => {
+                let operands = self.split_operands(operands, 2)?;
+                PotatoCodes::MovRegisterToStack(
+                    self.parse_register(operands[0])?, self.parse_number(operands[1])?
+                )
+            },
+            "mov_stack_reg" => {
+                let operands = self.split_operands(operands, 3)?;
+                PotatoCodes::MovStackToRegister(MovStackToRegister::new(
+                    self.parse_number(operands[0])?, self.parse_number(operands[1])?,
+                    self.parse_register(operands[2])?
+                ))
+            },
+            "copy" => {
+                let operands = self.split_operands(operands, 2)?;
+                PotatoCodes::CopyRegisterToRegister(
+                    self.parse_register(operands[0])?, self.parse_register(operands[1])?
+                )
+            },
+            "stride_mov_reg_stack" => {
+                let operands = self.split_operands(operands, 3)?;
+                PotatoCodes::StrideMovRegisterToStack(StrideMovRegisterToStack::new(
+                    self.parse_register(operands[0])?, self.parse_number(operands[1])?,
+                    self.parse_number(operands[2])?
+                ))
+            },
+            "stride_mov_stack_reg" => {
+                let operands = self.split_operands(operands, 4)?;
+                PotatoCodes::StrideMovStackToRegister(StrideMovStackToRegister::new(
+                    self.parse_number(operands[0])?, self.parse_number(operands[1])?,
+                    self.parse_number(operands[2])?, self.parse_register(operands[3])?
+                ))
+            },
+            "op" => PotatoCodes::Operate(self.parse_alu_operation(operands)?),
+            "data" => PotatoCodes::DataValue(
+                GrowableBitAllocation::new_from(self.parse_bits(operands)?)
+            ),
+            "mov_data" => {
+                let operands = self.split_operands(operands, 2)?;
+                PotatoCodes::MovDataValueToRegister(
+                    self.parse_number(operands[0])?, self.parse_register(operands[1])?
+                )
+            },
+            "jump_if_zero" => PotatoCodes::JumpIfZero(self.parse_number(operands)?),
+            "jump" => PotatoCodes::Jump(self.parse_number(operands)?),
+            "jump_if_flag" | "jump_if_not_flag" => {
+                let operands = self.split_operands(operands, 2)?;
+                let flag = self.parse_flag(operands[0])?;
+                let target = self.parse_number(operands[1])?;
+                if opcode == "jump_if_flag" {
+                    PotatoCodes::JumpIfFlag(flag, target)
+                } else {
+                    PotatoCodes::JumpIfNotFlag(flag, target)
+                }
+            },
+            "call" => PotatoCodes::Call(self.parse_number(operands)?),
+            "return" => {
+                self.split_operands(operands, 0)?;
+                PotatoCodes::Return
+            },
+            "label" => PotatoCodes::Label(Identifier::new(operands.to_string())),
+            _ => return self.error(format!("Unknown instruction {}", opcode))
+        };
+        Ok(instruction)
+    }
+}
+
+pub fn parse_potato_register(text: &str) -> Option<Registers> {
+    if let Some((_, register)) = REGISTER_NAMES.iter().find(|(name, _)| *name == text) {
+        return Some(register.clone());
+    }
+    text.strip_prefix('s')
+        .and_then(|number| number.parse::<u8>().ok())
+        .map(Registers::Scratch)
+}
+
+// an ALU operation as written after op, e.g. add or bitwise 0b0110
+pub fn parse_potato_alu_operation(text: &str) -> Result<ALUOperations, PotatoAsmTextError> {
+    PotatoAsmTextParser { line_number: 1 }.parse_alu_operation(text.trim())
+}
+
+pub fn parse_potato_asm(text: &str) -> Result<Vec<PotatoCodes>, PotatoAsmTextError> {
+    let mut parser = PotatoAsmTextParser { line_number: 0 };
+    let mut instructions: Vec<PotatoCodes> = vec![];
+    for (index, raw_line) in text.lines().enumerate() {
+        parser.line_number = index + 1;
+        let line = raw_line.trim();
+        if line.is_empty() || line.starts_with("//") { continue; }
+        instructions.push(parser.parse_instruction(line)?);
+    }
+    Ok(instructions)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::potato_cpu::potato_asm::PotatoFunction;
+    use crate::potato_cpu::potato_cpu::{PotatoCPU, PotatoSpec};
+    use crate::tacky::tacky_symbols::tacky_gen_from_str;
+
+    #[test]
+    fn test_round_trip_compiled_program() {
+        let tacky_program = tacky_gen_from_str(
+            "int main(void) { int a = 7; int b = -3; return (a * b) / 2 + (a > b) + (a ^ 5); }"
+        ).unwrap();
+        let function = PotatoFunction::from_tacky_function(
+            tacky_program.function, &tacky_program.static_variables
+        );
+        let instructions = function.link();
+        let text = emit_potato_asm(&instructions);
+        assert!(text.contains("op bitwise 0b0110\n"));
+
+        let reloaded = parse_potato_asm(&text).unwrap();
+        assert_eq!(reloaded, instructions);
+        assert_eq!(emit_potato_asm(&reloaded), text);
+    }
+
+    #[test]
+    fn test_parse_hand_written_potato_asm() {
+        let text = "
+            // adds 3 and 1 in 2 bits, which carries out
+            jump 3
+            data 0b11
+            data 0b01
+            mov_data 1, in_a
+            mov_data 2, in_b
+            op add
+            jump_if_not_flag carry, 9
+            mov_stack_reg 0, 1, fn_ret
+            copy out, fn_ret
+            return
+        ";
+        let instructions = parse_potato_asm(text).unwrap();
+        assert_eq!(instructions.len(), 10);
+        assert_eq!(instructions[1], PotatoCodes::DataValue(GrowableBitAllocation::from_num(3)));
+        assert_eq!(instructions[6], PotatoCodes::JumpIfNotFlag(Flags::Carry, 9));
+
+        let spec = PotatoSpec::new(instructions, 1, 32);
+        let mut cpu = PotatoCPU::new(&spec);
+        assert!(cpu.run(100).halted);
+        assert_eq!(cpu.read_register(Registers::FunctionReturn).to_i64(), Some(4));
+    }
+
+    #[test]
+    fn test_potato_asm_text_errors() {
+        let error = parse_potato_asm("jump 1\ncopy s0, r9\n").err().unwrap();
+        assert_eq!(error.line, 2);
+        assert!(error.message.contains("Unknown register r9"));
+
+        let error = parse_potato_asm("\n\nop bitwise 0b011\n").err().unwrap();
+        assert_eq!(error.line, 3);
+        assert!(parse_potato_asm("data 0b102").is_err());
+        assert!(parse_potato_asm("mov_data 1").is_err());
+        assert!(parse_potato_asm("frobnicate").is_err());
+    }
+}
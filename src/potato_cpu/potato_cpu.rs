@@ -7,10 +7,18 @@ use crate::potato_cpu::bit_allocation::{
 use arbitrary_int::{u4, UInt};
 use strum::IntoEnumIterator;
 use std::cmp::{Ordering, PartialEq, PartialOrd};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::fmt::{Display, Formatter};
+use std::ops::Range;
+use num_bigint::BigUint;
 use num_traits::{ToPrimitive, Zero};
+use serde::{Deserialize, Serialize};
 use strum_macros::EnumIter;
+use crate::parser::parse::Identifier;
 
+// how many steps run_cancellable runs between checks for cancellation
+pub const CANCELLATION_CHECK_INTERVAL: usize = 10_000;
 const AND_OP: UInt<u8, 4> = u4::new(0b1000);
 const OR_OP: UInt<u8, 4> = u4::new(0b1110);
 /*
@@ -18,7 +26,7 @@ If an operation has a worse time complexity when implemented
 using assembly instead of directly implementing it in the cellular automaton,
 then it should be supported natively as an ALU operation.
 */
-#[derive(Clone, Debug, Eq, PartialEq)]
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
 pub enum ALUOperations {
     // O(n), assembly is O(n^2) cause n reapplications of carry
     Add,
@@ -33,6 +41,12 @@ pub enum ALUOperations {
     ShiftLeft,
     // O(n), assembly implementation would be O(n^2)
     ShiftRight,
+    /*
+    Rotates the (little endian) bit vector of A towards its start by
+    B mod len(A) places, so bits dropped by ShiftLeft wrap around to the end.
+    O(n), assembly would need two shifts, a resize and an or
+    */
+    RotateLeft,
     CompareGreaterThan,
     /*
     Return the length of the input register data in bits
@@ -49,8 +63,8 @@ pub enum ALUOperations {
       so O(n) + O(n) = O(n)
     - subtract is just a + b's twos complement
       also O(n) + O(n) = O(n)
-    - I think circular right is not needed since
-      circular left by k is the same as circular right by (n - k)
+    - circular right is not needed since circular left by k
+      is the same as circular right by (n - k)
     - use assembly to implement times, divide, modulo lol
       O(n^2) in both native CA and assembly
     - write-through is just input | 0
@@ -58,8 +72,33 @@ pub enum ALUOperations {
     - truthiness is just checking if input > 0
     */
 }
+impl ALUOperations {
+    /*
+    Modeled cellular automaton time of the operation, which is O(n)
+    in the number of bits it has to sweep over for every operation
+    */
+    pub fn cost(&self, a: &GrowableBitAllocation, b: &GrowableBitAllocation) -> usize {
+        let b_value = b.to_big_num().to_usize().unwrap_or(usize::MAX);
+        let num_bits = match self {
+            ALUOperations::Add |
+            ALUOperations::BitwiseNOperation(_) |
+            ALUOperations::CompareGreaterThan => {
+                usize::max(a.get_length(), b.get_length())
+            },
+            ALUOperations::ReverseBits |
+            ALUOperations::GetLength |
+            ALUOperations::ShiftRight |
+            ALUOperations::RotateLeft => a.get_length(),
+            // the result grows by up to b bits
+            ALUOperations::ShiftLeft |
+            ALUOperations::ResizeModulo => a.get_length().saturating_add(b_value),
+            ALUOperations::Resize => usize::max(a.get_length(), b_value),
+        };
+        usize::max(num_bits, 1)
+    }
+}
 
-#[derive(Clone, Debug, Eq, PartialEq, Hash, EnumIter)]
+#[derive(Clone, Debug, Eq, PartialEq, Hash, PartialOrd, Ord, EnumIter, Serialize, Deserialize)]
 pub enum Registers {
     ProgramCounter,
     InputA,
@@ -73,27 +112,103 @@ pub enum Registers {
     FunctionReturn
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Flags {
+    Zero,
+    Negative,
+    Carry,
+}
+
+/*
+Set by every ALU operation, relative to the width of its widest
+input (as the registers themselves have no fixed width):
+- zero: the result has no set bits within that width
+- negative: the top bit within that width is set
+- carry: the result has set bits beyond that width, e.g. after an
+  Add that overflows or a ShiftRight that moves bits out
+*/
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub struct FlagsRegister {
+    pub zero: bool,
+    pub negative: bool,
+    pub carry: bool,
+}
+impl FlagsRegister {
+    pub fn from_result(result: &GrowableBitAllocation, width: usize) -> FlagsRegister {
+        let bits = result.get_bits();
+        let within_width = &bits[..usize::min(width, bits.len())];
+        FlagsRegister {
+            zero: !within_width.iter().any(|bit| *bit),
+            negative: width > 0 && bits.get(width - 1) == Some(&true),
+            carry: bits.iter().skip(width).any(|bit| *bit),
+        }
+    }
+    pub fn get(&self, flag: &Flags) -> bool {
+        match flag {
+            Flags::Zero => self.zero,
+            Flags::Negative => self.negative,
+            Flags::Carry => self.carry,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct MovStackToRegister {
-    stack_address: usize,
-    num_stack_addresses: usize,
-    register: Registers
+    pub(crate) stack_address: usize,
+    pub(crate) num_stack_addresses: usize,
+    pub(crate) register: Registers
 }
-#[derive(Clone, Debug, PartialEq, Eq)]
+impl MovStackToRegister {
+    pub fn new(
+        stack_address: usize, num_stack_addresses: usize, register: Registers
+    ) -> MovStackToRegister {
+        MovStackToRegister { stack_address, num_stack_addresses, register }
+    }
+}
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct StrideMovRegisterToStack {
-    register: Registers,
-    start_stack_address: usize,
-    stride: usize
+    pub(crate) register: Registers,
+    pub(crate) start_stack_address: usize,
+    pub(crate) stride: usize
 }
-#[derive(Clone, Debug, PartialEq, Eq)]
+impl StrideMovRegisterToStack {
+    pub fn new(
+        register: Registers, start_stack_address: usize, stride: usize
+    ) -> StrideMovRegisterToStack {
+        StrideMovRegisterToStack { register, start_stack_address, stride }
+    }
+}
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct StrideMovStackToRegister {
-    start_stack_address: usize,
-    stride: usize,
-    register: Registers
+    pub(crate) start_stack_address: usize,
+    pub(crate) stride: usize,
+    // most chunks to read, in case the continue flags are never cleared
+    pub(crate) max_chunks: usize,
+    pub(crate) register: Registers
+}
+impl StrideMovStackToRegister {
+    pub fn new(
+        start_stack_address: usize, stride: usize, max_chunks: usize, register: Registers
+    ) -> StrideMovStackToRegister {
+        StrideMovStackToRegister { start_stack_address, stride, max_chunks, register }
+    }
+}
+// stack address of chunk k of a value moved with a stride
+pub(crate) fn stride_chunk_address(
+    start_stack_address: usize, stride: usize, chunk_index: usize
+) -> usize {
+    start_stack_address + chunk_index * stride * 2
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
-pub enum PotatoCodes {
+/*
+Instructions refer to other instructions through Target, which is an
+instruction index once a program has been linked. Code generation
+emits PotatoCodes<Identifier> that refer to Labels instead, so that
+pieces of code can be composed before the indices are known
+(see potato_asm::link)
+*/
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum PotatoCodes<Target = usize> {
     // register, stack address
     MovRegisterToStack(Registers, usize),
     // stack address, num stack addresses to copy, register
@@ -112,22 +227,180 @@ pub enum PotatoCodes {
     Operate(ALUOperations),
     DataValue(GrowableBitAllocation),
     // move instruction data value to register
-    MovDataValueToRegister(usize, Registers),
+    MovDataValueToRegister(Target, Registers),
     // jump to instruction index if Registers::Output is zero
-    JumpIfZero(usize),
+    JumpIfZero(Target),
+    // jump to instruction index unconditionally
+    Jump(Target),
+    // jump to instruction index if the flag was set by the last ALU operation
+    JumpIfFlag(Flags, Target),
+    // jump to instruction index if the flag was cleared by the last ALU operation
+    JumpIfNotFlag(Flags, Target),
+    // jump to instruction index, saving the index of the next instruction
+    Call(Target),
+    // jump back to the index saved by the latest Call, halting if there is none
+    Return,
+    // names the next instruction, removed when the program is linked
+    Label(Identifier),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Watchpoint {
+    Register(Registers),
+    StackAddress(usize),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StopReason {
+    // instruction index of the breakpoint, which hasn't been executed yet
+    Breakpoint(usize),
+    // watched location, instruction index of the instruction that changed it
+    Watchpoint(Watchpoint, usize),
+    /*
+    Instruction index at which the cpu reached a state it had already
+    been in, so it would repeat the same steps forever (halts the cpu)
+    */
+    LoopDetected(usize),
 }
 
 #[derive(Clone, Debug)]
 pub struct StepResult {
     pub halted: bool,
-    pub time_steps: usize
+    pub time_steps: usize,
+    // modeled time taken by the cellular automaton, see PotatoCPU::instruction_cost
+    pub ca_time: usize,
+    // why run stopped before the cpu halted or ran out of steps
+    pub stop_reason: Option<StopReason>,
 }
 
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TraceEntry {
+    pub program_counter: usize,
+    pub instruction: PotatoCodes,
+    // new values of the registers the instruction changed (other than
+    // the program counter), ordered by register
+    pub register_deltas: Vec<(Registers, GrowableBitAllocation)>,
+}
+
+// record of every instruction executed by a PotatoCPU with tracing enabled
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ExecutionTrace {
+    pub entries: Vec<TraceEntry>,
+}
+impl ExecutionTrace {
+    fn record(
+        &mut self, program_counter: usize, instruction: PotatoCodes,
+        registers_before: &HashMap<Registers, GrowableBitAllocation>,
+        registers_after: &HashMap<Registers, GrowableBitAllocation>
+    ) {
+        let mut register_deltas: Vec<(Registers, GrowableBitAllocation)> = registers_after
+            .iter()
+            .filter(|(register, value)| {
+                **register != Registers::ProgramCounter &&
+                registers_before.get(*register) != Some(*value)
+            })
+            .map(|(register, value)| (register.clone(), value.clone()))
+            .collect();
+        register_deltas.sort_by(|(left, _), (right, _)| left.cmp(right));
+        self.entries.push(TraceEntry { program_counter, instruction, register_deltas });
+    }
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+}
+
+/*
+What happens to ALU results that are wider than the spec's max register
+width, so that the cpu can model fixed width hardware
+*/
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OverflowPolicy {
+    // keep only the lowest bits, like unsigned integer overflow
+    Wrap,
+    // clamp values that don't fit to the largest value that does
+    Saturate,
+    // registers keep growing past the max width (unbounded registers)
+    #[default]
+    Grow
+}
+impl OverflowPolicy {
+    pub fn limit(
+        &self, result: GrowableBitAllocation, max_width: usize
+    ) -> GrowableBitAllocation {
+        if result.get_length() <= max_width {
+            return result;
+        }
+        let mut limited = result;
+        match self {
+            OverflowPolicy::Grow => {},
+            OverflowPolicy::Wrap => { limited.resize(max_width); },
+            OverflowPolicy::Saturate if limited.to_big_num().bits() > max_width as u64 => {
+                limited = GrowableBitAllocation::new_from(vec![true; max_width]);
+            },
+            // only leading zeros are dropped
+            OverflowPolicy::Saturate => { limited.resize(max_width); }
+        }
+        limited
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum PotatoSpecError {
+    // jumps and calls may target one past the last instruction to halt
+    JumpTargetOutOfRange { instruction_no: usize, target: usize },
+    NotADataValue { instruction_no: usize, target: usize },
+    ScratchRegisterOutOfRange { instruction_no: usize, scratch_register_no: u8 },
+    ZeroStride { instruction_no: usize },
+    ZeroMaxChunks { instruction_no: usize },
+    ZeroMaxRegisterWidth,
+}
+impl Display for PotatoSpecError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PotatoSpecError::JumpTargetOutOfRange { instruction_no, target } => write!(
+                f, "Instruction {} jumps to out of range instruction {}",
+                instruction_no, target
+            ),
+            PotatoSpecError::NotADataValue { instruction_no, target } => write!(
+                f, "Instruction {} loads instruction {}, which isn't a data value",
+                instruction_no, target
+            ),
+            PotatoSpecError::ScratchRegisterOutOfRange {
+                instruction_no, scratch_register_no
+            } => write!(
+                f, "Instruction {} uses out of range scratch register {}",
+                instruction_no, scratch_register_no
+            ),
+            PotatoSpecError::ZeroStride { instruction_no } => {
+                write!(f, "Instruction {} has a stride of zero", instruction_no)
+            },
+            PotatoSpecError::ZeroMaxChunks { instruction_no } => {
+                write!(f, "Instruction {} can't read any chunks", instruction_no)
+            },
+            PotatoSpecError::ZeroMaxRegisterWidth => {
+                write!(f, "Registers can't have a max width of zero")
+            },
+        }
+    }
+}
+impl std::error::Error for PotatoSpecError {}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub struct PotatoSpec {
     instructions: Vec<PotatoCodes>,
     num_scratch_registers: u8,
     stack_width: u16,
+    /*
+    Stack addresses that act as an output port: the lowest byte of
+    every value written to them is appended to the cpu's output
+    */
+    output_port: Option<Range<usize>>,
+    // widest ALU result, beyond which the overflow policy applies
+    max_register_width: Option<usize>,
+    overflow_policy: OverflowPolicy,
 }
 impl PotatoSpec {
     pub fn new(
@@ -138,9 +411,23 @@ impl PotatoSpec {
         PotatoSpec {
             instructions,
             num_scratch_registers,
-            stack_width
+            stack_width,
+            output_port: None,
+            max_register_width: None,
+            overflow_policy: OverflowPolicy::default()
         }
     }
+    pub fn with_output_port(mut self, stack_addresses: Range<usize>) -> Self {
+        self.output_port = Some(stack_addresses);
+        self
+    }
+    pub fn with_max_register_width(
+        mut self, max_register_width: usize, overflow_policy: OverflowPolicy
+    ) -> Self {
+        self.max_register_width = Some(max_register_width);
+        self.overflow_policy = overflow_policy;
+        self
+    }
     pub fn set_instructions(mut self, instructions: Vec<PotatoCodes>) -> Self {
         self.instructions = instructions;
         self
@@ -154,16 +441,111 @@ impl PotatoSpec {
     pub fn get_stack_width(&self) -> u16 {
         self.stack_width
     }
+    pub fn get_output_port(&self) -> Option<&Range<usize>> {
+        self.output_port.as_ref()
+    }
+    pub fn get_max_register_width(&self) -> Option<usize> {
+        self.max_register_width
+    }
+    pub fn get_overflow_policy(&self) -> &OverflowPolicy {
+        &self.overflow_policy
+    }
+    pub fn limit_register_width(&self, result: GrowableBitAllocation) -> GrowableBitAllocation {
+        match self.max_register_width {
+            Some(max_width) => self.overflow_policy.limit(result, max_width),
+            None => result
+        }
+    }
+
+    /*
+    Checks the instructions for mistakes that would otherwise only
+    make the cpu panic (or misbehave) once they are executed
+    */
+    pub fn validate(&self) -> Result<(), PotatoSpecError> {
+        if self.max_register_width == Some(0) {
+            return Err(PotatoSpecError::ZeroMaxRegisterWidth);
+        }
+        for (instruction_no, instruction) in self.instructions.iter().enumerate() {
+            for register in Self::registers_of(instruction) {
+                if let Registers::Scratch(scratch_register_no) = register {
+                    if *scratch_register_no >= self.num_scratch_registers {
+                        return Err(PotatoSpecError::ScratchRegisterOutOfRange {
+                            instruction_no, scratch_register_no: *scratch_register_no
+                        });
+                    }
+                }
+            }
+
+            match instruction {
+                PotatoCodes::JumpIfZero(target) |
+                PotatoCodes::Jump(target) |
+                PotatoCodes::JumpIfFlag(_, target) |
+                PotatoCodes::JumpIfNotFlag(_, target) |
+                PotatoCodes::Call(target) if *target > self.instructions.len() => {
+                    return Err(PotatoSpecError::JumpTargetOutOfRange {
+                        instruction_no, target: *target
+                    });
+                },
+                PotatoCodes::MovDataValueToRegister(target, _) if !matches!(
+                    self.instructions.get(*target), Some(PotatoCodes::DataValue(_))
+                ) => {
+                    return Err(PotatoSpecError::NotADataValue {
+                        instruction_no, target: *target
+                    });
+                },
+                PotatoCodes::StrideMovRegisterToStack(StrideMovRegisterToStack {
+                    stride: 0, ..
+                }) |
+                PotatoCodes::StrideMovStackToRegister(StrideMovStackToRegister {
+                    stride: 0, ..
+                }) => {
+                    return Err(PotatoSpecError::ZeroStride { instruction_no });
+                },
+                PotatoCodes::StrideMovStackToRegister(StrideMovStackToRegister {
+                    max_chunks: 0, ..
+                }) => {
+                    return Err(PotatoSpecError::ZeroMaxChunks { instruction_no });
+                },
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+    fn registers_of(instruction: &PotatoCodes) -> Vec<&Registers> {
+        match instruction {
+            PotatoCodes::MovRegisterToStack(register, _) |
+            PotatoCodes::MovDataValueToRegister(_, register) => vec![register],
+            PotatoCodes::MovStackToRegister(params) => vec![&params.register],
+            PotatoCodes::CopyRegisterToRegister(src, dst) => vec![src, dst],
+            PotatoCodes::StrideMovRegisterToStack(params) => vec![&params.register],
+            PotatoCodes::StrideMovStackToRegister(params) => vec![&params.register],
+            _ => vec![]
+        }
+    }
 }
 
 /*
 Stack width is finite but registers are infinite size
 */
+#[derive(Serialize, Deserialize)]
 pub struct PotatoCPU {
     pub spec: PotatoSpec,
     pub stack: Vec<FixedBitAllocation>,
+    // bytes written to the output port, in the order they were written
+    pub output: Vec<u8>,
     pub time_steps: usize,
+    pub ca_time: usize,
     pub registers: HashMap<Registers, GrowableBitAllocation>,
+    // return addresses of the calls that haven't returned yet
+    pub call_stack: Vec<usize>,
+    pub flags: FlagsRegister,
+    // only recorded once enabled through with_tracing
+    pub trace: Option<ExecutionTrace>,
+    // instruction indices and locations that make run stop early
+    pub breakpoints: HashSet<usize>,
+    pub watchpoints: Vec<Watchpoint>,
+    // hashes of every state run has seen, once enabled through with_loop_detection
+    pub seen_states: Option<HashSet<u64>>,
     pub halted: bool
 }
 
@@ -178,12 +560,30 @@ impl PotatoCPU {
         let registers = Self::init_registers(&spec);
         PotatoCPU {
             stack: vec![],
+            output: vec![],
             spec: spec.clone(),
             time_steps: 0,
+            ca_time: 0,
             registers,
+            call_stack: vec![],
+            flags: FlagsRegister::default(),
+            trace: None,
+            breakpoints: HashSet::new(),
+            watchpoints: vec![],
+            seen_states: None,
             halted: false
         }
     }
+    pub fn with_tracing(mut self) -> Self {
+        assert_eq!(self.time_steps, 0);
+        self.trace = Some(ExecutionTrace::default());
+        self
+    }
+    pub fn with_loop_detection(mut self) -> Self {
+        assert_eq!(self.time_steps, 0);
+        self.seen_states = Some(HashSet::new());
+        self
+    }
     pub fn set_instructions(mut self, instructions: Vec<PotatoCodes>) -> Self {
         assert_eq!(self.time_steps, 0);
         self.spec = self.spec.set_instructions(instructions);
@@ -224,6 +624,13 @@ impl PotatoCPU {
             self.stack.resize(index + 1, blank_stack_value);
         }
         self.stack[index].copy_from(&value);
+
+        let is_output_port = self.spec.output_port.as_ref()
+            .is_some_and(|output_port| output_port.contains(&index));
+        if is_output_port {
+            let low_byte = value.to_big_num() & BigUint::from(0xFFu8);
+            self.output.push(low_byte.to_u8().unwrap());
+        }
     }
     pub fn read_from_stack(&self, index: usize) -> FixedBitAllocation {
         if index < self.stack.len() {
@@ -253,16 +660,96 @@ impl PotatoCPU {
         self.registers.get(&reg).unwrap()
     }
 
+    pub fn add_breakpoint(&mut self, instruction_no: usize) {
+        self.breakpoints.insert(instruction_no);
+    }
+    pub fn remove_breakpoint(&mut self, instruction_no: usize) -> bool {
+        self.breakpoints.remove(&instruction_no)
+    }
+    pub fn add_watchpoint(&mut self, watchpoint: Watchpoint) {
+        if !self.watchpoints.contains(&watchpoint) {
+            self.watchpoints.push(watchpoint);
+        }
+    }
+    pub fn remove_watchpoint(&mut self, watchpoint: &Watchpoint) -> bool {
+        let num_watchpoints = self.watchpoints.len();
+        self.watchpoints.retain(|existing| existing != watchpoint);
+        self.watchpoints.len() != num_watchpoints
+    }
+    fn read_watched_value(&self, watchpoint: &Watchpoint) -> Option<BigUint> {
+        match watchpoint {
+            // registers that haven't been written to yet don't have a value
+            Watchpoint::Register(register) => {
+                self.registers.get(register).map(|value| value.to_big_num())
+            },
+            Watchpoint::StackAddress(index) => Some(self.read_from_stack(*index).to_big_num()),
+        }
+    }
+
+    fn state_hash(&self) -> u64 {
+        // everything that decides what the following steps do
+        let mut hasher = DefaultHasher::new();
+        let mut registers: Vec<_> = self.registers.iter().collect();
+        registers.sort_by_key(|(register, _)| *register);
+        registers.hash(&mut hasher);
+        self.stack.hash(&mut hasher);
+        self.call_stack.hash(&mut hasher);
+        self.flags.hash(&mut hasher);
+        hasher.finish()
+    }
+
+    /*
+    Stops before executing an instruction at a breakpoint, or after
+    executing an instruction that changes a watched location.
+    Breakpoints are only checked from the second step onwards, so that
+    calling run again resumes past the breakpoint that stopped it.
+    With loop detection, halts before repeating a state it has seen before
+    */
     pub fn run(&mut self, max_steps: usize) -> StepResult {
-        for _ in 0..max_steps {
+        for step_no in 0..max_steps {
+            let program_counter = self.read_program_counter();
+            if step_no > 0 && !self.halted && self.breakpoints.contains(&program_counter) {
+                return self.stopped_result(StopReason::Breakpoint(program_counter));
+            }
+            let state_hash = match self.seen_states {
+                Some(_) if !self.halted => Some(self.state_hash()),
+                _ => None
+            };
+            if let (Some(state_hash), Some(seen_states)) = (state_hash, &mut self.seen_states) {
+                if !seen_states.insert(state_hash) {
+                    self.halted = true;
+                    return self.stopped_result(StopReason::LoopDetected(program_counter));
+                }
+            }
+
+            let watched_values: Vec<Option<BigUint>> = self.watchpoints.iter()
+                .map(|watchpoint| self.read_watched_value(watchpoint))
+                .collect();
             let step_result = self.step();
             if step_result.halted {
                 return step_result;
             }
+            for (watchpoint, old_value) in self.watchpoints.iter().zip(watched_values) {
+                if self.read_watched_value(watchpoint) != old_value {
+                    return self.stopped_result(
+                        StopReason::Watchpoint(watchpoint.clone(), program_counter)
+                    );
+                }
+            }
+        }
+        StepResult {
+            halted: self.halted,
+            time_steps: self.time_steps,
+            ca_time: self.ca_time,
+            stop_reason: None
         }
+    }
+    fn stopped_result(&self, stop_reason: StopReason) -> StepResult {
         StepResult {
             halted: self.halted,
-            time_steps: self.time_steps
+            time_steps: self.time_steps,
+            ca_time: self.ca_time,
+            stop_reason: Some(stop_reason)
         }
     }
     pub fn read_program_counter(&self) -> usize {
@@ -282,6 +769,8 @@ impl PotatoCPU {
             return StepResult {
                 halted: true,
                 time_steps: self.time_steps,
+                ca_time: self.ca_time,
+                stop_reason: None
             };
         }
 
@@ -292,13 +781,19 @@ impl PotatoCPU {
             self.halted = true;
             return StepResult {
                 halted: true,
-                time_steps: self.time_steps
+                time_steps: self.time_steps,
+                ca_time: self.ca_time,
+                stop_reason: None
             }
         }
 
         let instruction = instructions[program_counter].clone();
+        let cost = self.instruction_cost(&instruction);
+        // taken jumps have already set the next program counter
+        let mut jumped = false;
+        let registers_before = self.trace.as_ref().map(|_| self.registers.clone());
 
-        match instruction {
+        match instruction.clone() {
             PotatoCodes::MovRegisterToStack(reg, index) => {
                 let register_value = self.read_register(reg);
                 let chunks = register_value.split(self.spec.stack_width as usize);
@@ -322,13 +817,18 @@ impl PotatoCPU {
             },
             PotatoCodes::StrideMovRegisterToStack(params) => {
                 let register_value = self.read_register(params.register);
-                let chunks = register_value.split(self.spec.stack_width as usize);
-                let data_stride = params.stride * 2;
+                let mut chunks = register_value.split(self.spec.stack_width as usize);
+                if chunks.is_empty() {
+                    // empty registers are still written as a single (zero) chunk
+                    chunks.push(self.spawn_new_stack_value());
+                }
                 let is_last_chunk_index = chunks.len() - 1;
 
                 for (k, chunk) in chunks.into_iter().enumerate() {
                     // stack position where current chunk's value is written
-                    let data_pos = params.start_stack_address + k * data_stride;
+                    let data_pos = stride_chunk_address(
+                        params.start_stack_address, params.stride, k
+                    );
                     // stack position where current chunk's continue value is written
                     // this flags whether there is still more chunks after the current one
                     let data_cont_pos = data_pos + 1;
@@ -345,33 +845,28 @@ impl PotatoCPU {
                 }
             }
             PotatoCodes::StrideMovStackToRegister(params) => {
-                let data_stride = params.stride * 2;
-                let mut chunks: Vec<FixedBitAllocation> = vec![];
-                let chunk_index: usize = 0;
-
-                loop {
-                    let data_pos = params.start_stack_address + chunk_index * data_stride;
-                    let data_cont_pos = data_pos + 1;
-                    let stack_value = self.read_from_stack(data_pos);
-                    chunks.push(stack_value);
-
-                    let cont_stack_value = self.read_from_stack(data_cont_pos);
-                    if !cont_stack_value.get(0) {
-                        // no more data after this chunk index
-                        // this is like reaching a NULL terminator in a C array
-                        break;
-                    }
-                }
+                let chunks: Vec<FixedBitAllocation> = (0..self.count_stride_chunks(&params))
+                    .map(|chunk_index| self.read_from_stack(stride_chunk_address(
+                        params.start_stack_address, params.stride, chunk_index
+                    )))
+                    .collect();
 
                 let new_register_value =
                     GrowableBitAllocation::from_fixed_allocations(&chunks);
                 self.registers.insert(params.register, new_register_value);
             },
             PotatoCodes::Operate(op) => {
-                let result = self.process_alu_op(op);
+                let unlimited_result = self.compute_alu_op(op);
+                let width = usize::max(
+                    self.read_register(Registers::InputA).get_length(),
+                    self.read_register(Registers::InputB).get_length()
+                );
+                // the carry flag still records results that overflow the max register width
+                self.flags = FlagsRegister::from_result(&unlimited_result, width);
+                let result = self.spec.limit_register_width(unlimited_result);
                 self.registers.insert(Registers::Output, result);
             },
-            PotatoCodes::DataValue(..) => {
+            PotatoCodes::DataValue(..) | PotatoCodes::Label(..) => {
                 // no-op
             }
             PotatoCodes::MovDataValueToRegister(index, reg) => {
@@ -385,24 +880,138 @@ impl PotatoCPU {
             PotatoCodes::JumpIfZero(target_instruction_no) => {
                 let output_value = self.read_register(Registers::Output);
                 if output_value.to_big_num().is_zero() {
-                    if target_instruction_no >= instructions.len() {
-                        self.halted = true;
-                    } else {
-                        self.set_program_counter(target_instruction_no)
-                    }
+                    self.jump_to(target_instruction_no);
+                    jumped = true;
+                }
+            }
+            PotatoCodes::Jump(target_instruction_no) => {
+                self.jump_to(target_instruction_no);
+                jumped = true;
+            }
+            PotatoCodes::JumpIfFlag(flag, target_instruction_no) => {
+                if self.flags.get(&flag) {
+                    self.jump_to(target_instruction_no);
+                    jumped = true;
+                }
+            }
+            PotatoCodes::JumpIfNotFlag(flag, target_instruction_no) => {
+                if !self.flags.get(&flag) {
+                    self.jump_to(target_instruction_no);
+                    jumped = true;
+                }
+            }
+            PotatoCodes::Call(target_instruction_no) => {
+                self.call_stack.push(program_counter + 1);
+                self.jump_to(target_instruction_no);
+                jumped = true;
+            }
+            PotatoCodes::Return => {
+                // returning from the entry point ends the program
+                match self.call_stack.pop() {
+                    Some(return_instruction_no) => self.jump_to(return_instruction_no),
+                    None => self.halted = true
                 }
+                jumped = true;
             }
         }
 
         self.time_steps += 1;
-        self.increment_program_counter();
+        self.ca_time += cost;
+        if !jumped {
+            self.increment_program_counter();
+        }
+        if let (Some(trace), Some(registers_before)) = (&mut self.trace, registers_before) {
+            trace.record(program_counter, instruction, &registers_before, &self.registers);
+        }
 
         StepResult {
             halted: self.halted,
-            time_steps: self.time_steps
+            time_steps: self.time_steps,
+            ca_time: self.ca_time,
+            stop_reason: None
+        }
+    }
+    /*
+    Modeled cellular automaton time of an instruction, measured before it
+    executes. Moves take time linear in the number of bits they move,
+    ALU operations are costed by ALUOperations::cost, and everything
+    else takes a single unit of time
+    */
+    pub fn instruction_cost(&self, instruction: &PotatoCodes) -> usize {
+        let stack_width = self.spec.stack_width as usize;
+        let num_bits = match instruction {
+            PotatoCodes::MovRegisterToStack(register, _) => {
+                let num_chunks = self.read_register(register.clone()).get_length()
+                    .div_ceil(stack_width);
+                num_chunks * stack_width
+            },
+            PotatoCodes::MovStackToRegister(params) => {
+                params.num_stack_addresses * stack_width
+            },
+            PotatoCodes::CopyRegisterToRegister(src, _) => {
+                self.read_register(src.clone()).get_length()
+            },
+            // each chunk is stored alongside a continue flag
+            PotatoCodes::StrideMovRegisterToStack(params) => {
+                let num_chunks = self.read_register(params.register.clone()).get_length()
+                    .div_ceil(stack_width);
+                2 * usize::max(num_chunks, 1) * stack_width
+            },
+            PotatoCodes::StrideMovStackToRegister(params) => {
+                2 * self.count_stride_chunks(params) * stack_width
+            },
+            PotatoCodes::Operate(op) => op.cost(
+                self.read_register(Registers::InputA), self.read_register(Registers::InputB)
+            ),
+            PotatoCodes::MovDataValueToRegister(target, _) => {
+                match self.get_instructions().get(*target) {
+                    Some(PotatoCodes::DataValue(value)) => value.get_length(),
+                    _ => 1
+                }
+            },
+            // every bit of the output register has to be checked
+            PotatoCodes::JumpIfZero(_) => self.read_register(Registers::Output).get_length(),
+            PotatoCodes::DataValue(_) |
+            PotatoCodes::Jump(_) |
+            PotatoCodes::JumpIfFlag(_, _) |
+            PotatoCodes::JumpIfNotFlag(_, _) |
+            PotatoCodes::Call(_) |
+            PotatoCodes::Return |
+            PotatoCodes::Label(_) => 1,
+        };
+        usize::max(num_bits, 1)
+    }
+    /*
+    Number of chunks a stride read loads, which ends at the first chunk
+    whose continue flag is cleared (like reaching a NULL terminator in
+    a C array), or after max_chunks chunks if none of them are cleared
+    */
+    fn count_stride_chunks(&self, params: &StrideMovStackToRegister) -> usize {
+        let mut num_chunks = 0;
+        while num_chunks < params.max_chunks {
+            let data_pos = stride_chunk_address(
+                params.start_stack_address, params.stride, num_chunks
+            );
+            num_chunks += 1;
+            if !self.read_from_stack(data_pos + 1).get(0) {
+                break;
+            }
+        }
+        num_chunks
+    }
+    fn jump_to(&mut self, target_instruction_no: usize) {
+        // jumping past the last instruction halts the cpu
+        if target_instruction_no >= self.get_instructions().len() {
+            self.halted = true;
+        } else {
+            self.set_program_counter(target_instruction_no)
         }
     }
     pub fn process_alu_op(&self, op: ALUOperations) -> GrowableBitAllocation {
+        // result of the operation, limited to the spec's max register width
+        self.spec.limit_register_width(self.compute_alu_op(op))
+    }
+    fn compute_alu_op(&self, op: ALUOperations) -> GrowableBitAllocation {
         let a = self.read_register(Registers::InputA);
         let b = self.read_register(Registers::InputB);
         // let a_size = a.get_length();
@@ -425,6 +1034,14 @@ impl PotatoCPU {
             ALUOperations::ShiftRight => {
                 a >> b
             },
+            ALUOperations::RotateLeft => {
+                let mut rotated = a.clone();
+                if a.get_length() > 0 {
+                    let num_places = b.to_big_num() % a.get_length();
+                    rotated.rotate_left(num_places.to_usize().unwrap());
+                }
+                rotated
+            },
             ALUOperations::CompareGreaterThan => {
                 GrowableBitAllocation::new_from_bool(a > b)
             },
@@ -448,3 +1065,59 @@ impl PotatoCPU {
         result
     }
 }
+
+/*
+Operations shared by this PotatoCPU and the reference VM in potato_vm,
+so that either machine can run a program and be checked against the other
+*/
+pub trait Cpu {
+    fn load_program(spec: &PotatoSpec) -> Self where Self: Sized;
+    fn step(&mut self) -> StepResult;
+    fn run(&mut self, max_steps: usize) -> StepResult;
+    fn register_value(&self, register: &Registers) -> GrowableBitAllocation;
+    fn stack_value(&self, index: usize) -> BigUint;
+    fn is_halted(&self) -> bool;
+
+    /*
+    Same as run, but checks should_cancel before every
+    CANCELLATION_CHECK_INTERVAL steps, and returns None once it
+    says to stop (leaving the cpu where it got to)
+    */
+    fn run_cancellable(
+        &mut self, max_steps: usize, should_cancel: &mut dyn FnMut() -> bool
+    ) -> Option<StepResult> {
+        let mut steps_left = max_steps;
+        loop {
+            if should_cancel() {
+                return None;
+            }
+            let num_steps = usize::min(steps_left, CANCELLATION_CHECK_INTERVAL);
+            let run_result = self.run(num_steps);
+            steps_left -= num_steps;
+            if run_result.halted || run_result.stop_reason.is_some() || steps_left == 0 {
+                return Some(run_result);
+            }
+        }
+    }
+}
+
+impl Cpu for PotatoCPU {
+    fn load_program(spec: &PotatoSpec) -> Self {
+        PotatoCPU::new(spec)
+    }
+    fn step(&mut self) -> StepResult {
+        PotatoCPU::step(self)
+    }
+    fn run(&mut self, max_steps: usize) -> StepResult {
+        PotatoCPU::run(self, max_steps)
+    }
+    fn register_value(&self, register: &Registers) -> GrowableBitAllocation {
+        self.read_register(register.clone()).clone()
+    }
+    fn stack_value(&self, index: usize) -> BigUint {
+        self.read_from_stack(index).to_big_num()
+    }
+    fn is_halted(&self) -> bool {
+        self.halted
+    }
+}
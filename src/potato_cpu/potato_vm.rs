@@ -0,0 +1,542 @@
+use std::collections::HashMap;
+use num_bigint::BigUint;
+use num_traits::{One, ToPrimitive, Zero};
+use crate::potato_cpu::bit_allocation::{BitAllocation, GrowableBitAllocation};
+use crate::potato_cpu::potato_cpu::{
+    stride_chunk_address, ALUOperations, Cpu, FlagsRegister, OverflowPolicy, PotatoCodes,
+    PotatoSpec, Registers, StepResult
+};
+
+/*
+Reference virtual machine for potato programs. It runs the same linked
+PotatoCodes as the bit level PotatoCPU, but keeps every register as an
+arbitrary precision number along with its width in bits, so that it
+is simple and fast enough to check the PotatoCPU against.
+Widths follow the PotatoCPU exactly (e.g. sums are as wide as their
+value needs), since the ALU flags and Resize depend on them.
+The cellular automaton time isn't modeled, so ca_time is always 0
+*/
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct VMValue {
+    pub value: BigUint,
+    pub width: usize,
+}
+impl VMValue {
+    pub fn new(value: BigUint, width: usize) -> VMValue {
+        // drops any bits that don't fit in the width
+        VMValue { value: value & Self::mask(width), width }
+    }
+    pub fn zero(width: usize) -> VMValue {
+        VMValue { value: BigUint::zero(), width }
+    }
+    // as wide as the value needs, like a GrowableBitAllocation made from a number
+    fn minimal(value: BigUint) -> VMValue {
+        let width = usize::max(value.bits() as usize, 1);
+        VMValue { value, width }
+    }
+    fn from_usize(value: usize) -> VMValue {
+        Self::minimal(BigUint::from(value))
+    }
+    fn from_bool(value: bool) -> VMValue {
+        VMValue { value: BigUint::from(value as u8), width: 1 }
+    }
+    fn mask(width: usize) -> BigUint {
+        (BigUint::one() << width) - BigUint::one()
+    }
+    fn bit(&self, index: usize) -> bool {
+        self.value.bit(index as u64)
+    }
+
+    pub fn from_bit_allocation(allocation: &GrowableBitAllocation) -> VMValue {
+        VMValue { value: allocation.to_big_num(), width: allocation.get_length() }
+    }
+    pub fn to_bit_allocation(&self) -> GrowableBitAllocation {
+        GrowableBitAllocation::new_from((0..self.width).map(|index| self.bit(index)).collect())
+    }
+}
+
+pub struct PotatoCPU {
+    pub spec: PotatoSpec,
+    // values of the stack addresses, which are all spec.stack_width bits wide
+    pub stack: Vec<BigUint>,
+    pub time_steps: usize,
+    pub registers: HashMap<Registers, VMValue>,
+    pub program_counter: usize,
+    // return addresses of the calls that haven't returned yet
+    pub call_stack: Vec<usize>,
+    pub flags: FlagsRegister,
+    // bytes written to the output port, in the order they were written
+    pub output: Vec<u8>,
+    pub halted: bool
+}
+impl PotatoCPU {
+    pub fn new(spec: &PotatoSpec) -> PotatoCPU {
+        PotatoCPU {
+            spec: spec.clone(),
+            stack: vec![],
+            time_steps: 0,
+            registers: HashMap::new(),
+            program_counter: 0,
+            call_stack: vec![],
+            flags: FlagsRegister::default(),
+            output: vec![],
+            halted: false
+        }
+    }
+
+    fn stack_width(&self) -> usize {
+        self.spec.get_stack_width() as usize
+    }
+    // registers that haven't been written to yet are empty
+    pub fn read_register(&self, register: &Registers) -> VMValue {
+        if register == &Registers::ProgramCounter {
+            return VMValue::from_usize(self.program_counter);
+        }
+        self.registers.get(register).cloned().unwrap_or(VMValue::zero(0))
+    }
+    fn write_register(&mut self, register: Registers, value: VMValue) {
+        if let Registers::Scratch(scratch_register_no) = register {
+            if scratch_register_no >= self.spec.get_num_scratch_registers() {
+                panic!("Scratch register number {} out of bounds", scratch_register_no);
+            }
+        }
+        self.registers.insert(register, value);
+    }
+    pub fn read_from_stack(&self, index: usize) -> BigUint {
+        self.stack.get(index).cloned().unwrap_or_default()
+    }
+    fn assign_to_stack(&mut self, index: usize, value: BigUint) {
+        if index >= self.stack.len() {
+            self.stack.resize(index + 1, BigUint::zero());
+        }
+        let is_output_port = self.spec.get_output_port()
+            .is_some_and(|output_port| output_port.contains(&index));
+        if is_output_port {
+            let low_byte = &value & BigUint::from(0xFFu8);
+            self.output.push(low_byte.to_u8().unwrap());
+        }
+        self.stack[index] = value;
+    }
+
+    fn split(&self, value: &VMValue) -> Vec<BigUint> {
+        // the last chunk is padded with its most significant bit, like GrowableBitAllocation::split
+        let stack_width = self.stack_width();
+        (0..value.width.div_ceil(stack_width)).map(|chunk_index| {
+            let start = chunk_index * stack_width;
+            let chunk_width = usize::min(stack_width, value.width - start);
+            let chunk = (&value.value >> start) & VMValue::mask(chunk_width);
+            if chunk.bit(chunk_width as u64 - 1) {
+                chunk | (VMValue::mask(stack_width) ^ VMValue::mask(chunk_width))
+            } else {
+                chunk
+            }
+        }).collect()
+    }
+    fn concatenate(&self, chunks: &[BigUint]) -> VMValue {
+        let stack_width = self.stack_width();
+        let value = chunks.iter().enumerate().fold(BigUint::zero(), |value, (index, chunk)| {
+            value | (chunk << (index * stack_width))
+        });
+        VMValue { value, width: chunks.len() * stack_width }
+    }
+
+    pub fn run(&mut self, max_steps: usize) -> StepResult {
+        for _ in 0..max_steps {
+            let step_result = self.step();
+            if step_result.halted {
+                return step_result;
+            }
+        }
+        self.step_result()
+    }
+    fn step_result(&self) -> StepResult {
+        StepResult {
+            halted: self.halted,
+            time_steps: self.time_steps,
+            ca_time: 0,
+            stop_reason: None
+        }
+    }
+    fn jump_to(&mut self, target_instruction_no: usize) {
+        // jumping past the last instruction halts the cpu
+        if target_instruction_no >= self.spec.get_instructions().len() {
+            self.halted = true;
+        } else {
+            self.program_counter = target_instruction_no;
+        }
+    }
+
+    pub fn step(&mut self) -> StepResult {
+        if self.halted {
+            return self.step_result();
+        }
+        let program_counter = self.program_counter;
+        let instruction = match self.spec.get_instructions().get(program_counter) {
+            Some(instruction) => instruction.clone(),
+            None => {
+                self.halted = true;
+                return self.step_result();
+            }
+        };
+        // taken jumps have already set the next program counter
+        let mut jumped = false;
+
+        match instruction {
+            PotatoCodes::MovRegisterToStack(register, index) => {
+                let chunks = self.split(&self.read_register(&register));
+                for (offset, chunk) in chunks.into_iter().enumerate() {
+                    self.assign_to_stack(index + offset, chunk);
+                }
+            },
+            PotatoCodes::MovStackToRegister(params) => {
+                let chunks: Vec<BigUint> = (0..params.num_stack_addresses)
+                    .map(|offset| self.read_from_stack(params.stack_address + offset))
+                    .collect();
+                let value = self.concatenate(&chunks);
+                self.write_register(params.register, value);
+            },
+            PotatoCodes::CopyRegisterToRegister(src, dst) => {
+                let value = self.read_register(&src);
+                self.write_register(dst, value);
+            },
+            PotatoCodes::StrideMovRegisterToStack(params) => {
+                let mut chunks = self.split(&self.read_register(&params.register));
+                if chunks.is_empty() {
+                    chunks.push(BigUint::zero());
+                }
+                let num_chunks = chunks.len();
+                for (chunk_index, chunk) in chunks.into_iter().enumerate() {
+                    let data_pos = stride_chunk_address(
+                        params.start_stack_address, params.stride, chunk_index
+                    );
+                    let has_more_chunks = chunk_index + 1 < num_chunks;
+                    self.assign_to_stack(data_pos, chunk);
+                    self.assign_to_stack(data_pos + 1, BigUint::from(has_more_chunks as u8));
+                }
+            },
+            PotatoCodes::StrideMovStackToRegister(params) => {
+                let mut chunks: Vec<BigUint> = vec![];
+                while chunks.len() < params.max_chunks {
+                    let data_pos = stride_chunk_address(
+                        params.start_stack_address, params.stride, chunks.len()
+                    );
+                    chunks.push(self.read_from_stack(data_pos));
+                    if !self.read_from_stack(data_pos + 1).bit(0) {
+                        break;
+                    }
+                }
+                let value = self.concatenate(&chunks);
+                self.write_register(params.register, value);
+            },
+            PotatoCodes::Operate(op) => {
+                let a = self.read_register(&Registers::InputA);
+                let b = self.read_register(&Registers::InputB);
+                let result = Self::process_alu_op(&op, &a, &b);
+
+                let width = usize::max(a.width, b.width);
+                self.flags = FlagsRegister {
+                    zero: (&result.value & VMValue::mask(width)).is_zero(),
+                    negative: width > 0 && result.bit(width - 1),
+                    carry: !(&result.value >> width).is_zero(),
+                };
+                let result = self.limit_register_width(result);
+                self.write_register(Registers::Output, result);
+            },
+            PotatoCodes::DataValue(_) | PotatoCodes::Label(_) => {},
+            PotatoCodes::MovDataValueToRegister(index, register) => {
+                match &self.spec.get_instructions()[index] {
+                    PotatoCodes::DataValue(value) => {
+                        let value = VMValue::from_bit_allocation(value);
+                        self.write_register(register, value);
+                    },
+                    _ => panic!("Expected DataValue at index {}", index)
+                }
+            },
+            PotatoCodes::JumpIfZero(target_instruction_no) => {
+                if self.read_register(&Registers::Output).value.is_zero() {
+                    self.jump_to(target_instruction_no);
+                    jumped = true;
+                }
+            },
+            PotatoCodes::Jump(target_instruction_no) => {
+                self.jump_to(target_instruction_no);
+                jumped = true;
+            },
+            PotatoCodes::JumpIfFlag(flag, target_instruction_no) => {
+                if self.flags.get(&flag) {
+                    self.jump_to(target_instruction_no);
+                    jumped = true;
+                }
+            },
+            PotatoCodes::JumpIfNotFlag(flag, target_instruction_no) => {
+                if !self.flags.get(&flag) {
+                    self.jump_to(target_instruction_no);
+                    jumped = true;
+                }
+            },
+            PotatoCodes::Call(target_instruction_no) => {
+                self.call_stack.push(program_counter + 1);
+                self.jump_to(target_instruction_no);
+                jumped = true;
+            },
+            PotatoCodes::Return => {
+                match self.call_stack.pop() {
+                    Some(return_instruction_no) => self.jump_to(return_instruction_no),
+                    None => self.halted = true
+                }
+                jumped = true;
+            },
+        }
+
+        self.time_steps += 1;
+        if !jumped {
+            self.program_counter += 1;
+        }
+        self.step_result()
+    }
+
+    fn limit_register_width(&self, result: VMValue) -> VMValue {
+        let max_width = match self.spec.get_max_register_width() {
+            Some(max_width) if result.width > max_width => max_width,
+            _ => return result
+        };
+        match self.spec.get_overflow_policy() {
+            OverflowPolicy::Grow => result,
+            OverflowPolicy::Wrap => VMValue::new(result.value, max_width),
+            OverflowPolicy::Saturate if result.value.bits() > max_width as u64 => {
+                VMValue { value: VMValue::mask(max_width), width: max_width }
+            },
+            OverflowPolicy::Saturate => VMValue { value: result.value, width: max_width }
+        }
+    }
+
+    pub fn process_alu_op(op: &ALUOperations, a: &VMValue, b: &VMValue) -> VMValue {
+        let b_amount = b.value.to_usize();
+        match op {
+            ALUOperations::Add => VMValue::minimal(&a.value + &b.value),
+            ALUOperations::ReverseBits => {
+                let value = (0..a.width).filter(|index| a.bit(*index))
+                    .fold(BigUint::zero(), |value, index| {
+                        value | (BigUint::one() << (a.width - 1 - index))
+                    });
+                VMValue { value, width: a.width }
+            },
+            ALUOperations::BitwiseNOperation(truth_table) => {
+                /*
+                Each bit of the truth table selects one combination of
+                input bits: a & b, a & !b, !a & b and !a & !b respectively
+                */
+                let width = usize::max(a.width, b.width);
+                let mask = VMValue::mask(width);
+                let not_a = &mask ^ &a.value;
+                let not_b = &mask ^ &b.value;
+                let minterms = [
+                    &a.value & &b.value, &a.value & &not_b, &not_a & &b.value, &not_a & &not_b
+                ];
+                let value = minterms.into_iter().enumerate()
+                    .filter(|(index, _)| (truth_table.value() >> index) & 1 == 1)
+                    .fold(BigUint::zero(), |value, (_, minterm)| value | minterm);
+                VMValue { value, width }
+            },
+            // the PotatoCPU's shift left moves bits towards the least significant end
+            ALUOperations::ShiftLeft => match b_amount {
+                Some(shift) if shift <= a.width => {
+                    VMValue { value: &a.value >> shift, width: a.width - shift }
+                },
+                Some(shift) => panic!("Shift by {} exceeds register width {}", shift, a.width),
+                None => VMValue::zero(1)
+            },
+            ALUOperations::ShiftRight => match b_amount {
+                Some(shift) => VMValue { value: &a.value << shift, width: a.width + shift },
+                None => VMValue::zero(1)
+            },
+            ALUOperations::RotateLeft => match a.width {
+                0 => a.clone(),
+                width => {
+                    let num_places = (&b.value % width).to_usize().unwrap();
+                    let value = (&a.value >> num_places) | (&a.value << (width - num_places));
+                    VMValue::new(value, width)
+                }
+            },
+            ALUOperations::CompareGreaterThan => VMValue::from_bool(a.value > b.value),
+            ALUOperations::GetLength => VMValue::from_usize(a.width),
+            ALUOperations::Resize => VMValue::new(a.value.clone(), b_amount.unwrap()),
+            ALUOperations::ResizeModulo => {
+                let size_modulo = b_amount.unwrap();
+                let remainder = a.width % size_modulo;
+                let width = match remainder {
+                    0 => a.width,
+                    _ => a.width + (size_modulo - remainder)
+                };
+                VMValue { value: a.value.clone(), width }
+            },
+        }
+    }
+}
+
+impl Cpu for PotatoCPU {
+    fn load_program(spec: &PotatoSpec) -> Self {
+        PotatoCPU::new(spec)
+    }
+    fn step(&mut self) -> StepResult {
+        PotatoCPU::step(self)
+    }
+    fn run(&mut self, max_steps: usize) -> StepResult {
+        PotatoCPU::run(self, max_steps)
+    }
+    fn register_value(&self, register: &Registers) -> GrowableBitAllocation {
+        self.read_register(register).to_bit_allocation()
+    }
+    fn stack_value(&self, index: usize) -> BigUint {
+        self.read_from_stack(index)
+    }
+    fn is_halted(&self) -> bool {
+        self.halted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use arbitrary_int::u4;
+    use super::*;
+    use crate::potato_cpu::potato_asm::{PotatoProgram, MAX_EXECUTION_STEPS};
+    use crate::potato_cpu::potato_cpu;
+    use crate::tacky::tacky_symbols::tacky_gen_from_str;
+
+    fn value_of_width(value: usize, width: usize) -> GrowableBitAllocation {
+        let mut bits = GrowableBitAllocation::from_num(value);
+        bits.resize(width);
+        bits
+    }
+
+    #[test]
+    fn test_alu_operations_match_potato_cpu() {
+        let mut operations = vec![
+            ALUOperations::Add, ALUOperations::ReverseBits, ALUOperations::ShiftLeft,
+            ALUOperations::ShiftRight, ALUOperations::RotateLeft,
+            ALUOperations::CompareGreaterThan, ALUOperations::GetLength, ALUOperations::Resize,
+            ALUOperations::ResizeModulo
+        ];
+        operations.extend((0..16).map(|table| ALUOperations::BitwiseNOperation(u4::new(table))));
+        let inputs = [
+            (value_of_width(0b1011, 4), value_of_width(3, 2)),
+            (value_of_width(0xFF, 8), value_of_width(1, 8)),
+            (value_of_width(0x80_0000_0001, 40), value_of_width(12, 4)),
+            (value_of_width(5, 3), value_of_width(2, 32)),
+        ];
+
+        let overflow_policies = [
+            None, Some(OverflowPolicy::Wrap), Some(OverflowPolicy::Saturate),
+            Some(OverflowPolicy::Grow)
+        ];
+
+        for op in &operations {
+            for (a, b) in &inputs {
+                for overflow_policy in &overflow_policies {
+                    let mut spec = PotatoSpec::new(vec![PotatoCodes::Operate(op.clone())], 1, 32);
+                    if let Some(overflow_policy) = overflow_policy {
+                        spec = spec.with_max_register_width(6, overflow_policy.clone());
+                    }
+                    let mut cpu = potato_cpu::PotatoCPU::new(&spec);
+                    *cpu.load_register(Registers::InputA) = a.clone();
+                    *cpu.load_register(Registers::InputB) = b.clone();
+                    cpu.step();
+
+                    let mut vm = PotatoCPU::new(&spec);
+                    vm.registers.insert(Registers::InputA, VMValue::from_bit_allocation(a));
+                    vm.registers.insert(Registers::InputB, VMValue::from_bit_allocation(b));
+                    vm.step();
+
+                    assert_eq!(
+                        vm.read_register(&Registers::Output).to_bit_allocation(),
+                        *cpu.read_register(Registers::Output),
+                        "{:?} of {:?} and {:?} with {:?}", op, a, b, overflow_policy
+                    );
+                    assert_eq!(vm.flags, cpu.flags, "{:?} of {:?} and {:?}", op, a, b);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_compiled_programs_match_potato_cpu() {
+        let sources = [
+            "int main(void) { int a = 7; int b = -3; return (a * b) / 2 + (a > b) + (a ^ 5); }",
+            "int main(void) { long l = 4294967296l; unsigned char c = 250; return l / 65536 + c; }",
+            "int main(void) { int a = 0; int b = 3; return (a && b) + (a || b) * 2 + !a * 4; }",
+        ];
+        for source in sources {
+            let spec = PotatoProgram::from_tacky_program(tacky_gen_from_str(source).unwrap())
+                .spec();
+            let mut cpu = potato_cpu::PotatoCPU::new(&spec);
+            let cpu_result = cpu.run(MAX_EXECUTION_STEPS);
+            let mut vm = PotatoCPU::new(&spec);
+            let vm_result = vm.run(MAX_EXECUTION_STEPS);
+
+            assert!(cpu_result.halted && vm_result.halted, "{}", source);
+            assert_eq!(vm_result.time_steps, cpu_result.time_steps, "{}", source);
+            for (register, value) in &cpu.registers {
+                if register != &Registers::ProgramCounter {
+                    assert_eq!(
+                        vm.read_register(register).to_bit_allocation(), *value,
+                        "{:?} of {}", register, source
+                    );
+                }
+            }
+            let cpu_stack: Vec<BigUint> = cpu.stack.iter().map(|value| value.to_big_num())
+                .collect();
+            assert_eq!(vm.stack, cpu_stack, "{}", source);
+        }
+    }
+
+    fn run_to_halt<C: Cpu>(spec: &PotatoSpec) -> C {
+        let mut cpu = C::load_program(spec);
+        cpu.run(MAX_EXECUTION_STEPS);
+        assert!(cpu.is_halted());
+        cpu
+    }
+
+    #[test]
+    fn test_either_cpu_through_trait() {
+        let source = "int main(void) { unsigned int u = 4000000000u; return u / 3 - (u > 7); }";
+        let program = PotatoProgram::from_tacky_program(tacky_gen_from_str(source).unwrap());
+        assert_eq!(program.execute_on::<PotatoCPU>(), program.execute());
+
+        let spec = program.spec();
+        let cpu: potato_cpu::PotatoCPU = run_to_halt(&spec);
+        let vm: PotatoCPU = run_to_halt(&spec);
+        for register in [Registers::FunctionReturn, Registers::StackPointer, Registers::Output] {
+            assert_eq!(vm.register_value(&register), cpu.register_value(&register));
+        }
+        for index in 0..cpu.stack.len() + 1 {
+            assert_eq!(vm.stack_value(index), cpu.stack_value(index));
+        }
+    }
+
+    #[test]
+    fn test_stride_moves_and_output() {
+        let spec = PotatoSpec::new(vec![
+            PotatoCodes::Jump(2),
+            PotatoCodes::DataValue(value_of_width(0x2148_6921, 40)),
+            PotatoCodes::MovDataValueToRegister(1, Registers::Scratch(0)),
+            PotatoCodes::MovRegisterToStack(Registers::Scratch(0), 0),
+            PotatoCodes::StrideMovRegisterToStack(
+                potato_cpu::StrideMovRegisterToStack::new(Registers::Scratch(0), 4, 1)
+            ),
+            PotatoCodes::StrideMovStackToRegister(
+                potato_cpu::StrideMovStackToRegister::new(4, 1, 8, Registers::Output)
+            ),
+        ], 1, 32).with_output_port(0..2);
+        let mut vm = PotatoCPU::new(&spec);
+        assert!(vm.run(100).halted);
+        // the lowest byte of each chunk, with the second chunk sign extended from 8 bits
+        assert_eq!(vm.output, vec![0x21, 0x00]);
+        assert_eq!(vm.stack[5], BigUint::one());
+        assert_eq!(vm.stack[7], BigUint::zero());
+        assert_eq!(
+            vm.read_register(&Registers::Output).to_bit_allocation(),
+            value_of_width(0x2148_6921, 64)
+        );
+    }
+}
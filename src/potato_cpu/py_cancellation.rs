@@ -0,0 +1,106 @@
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
+use pyo3::{pyclass, pymethods, PyResult, Python};
+use pyo3_stub_gen::derive::{gen_stub_pyclass, gen_stub_pymethods};
+use crate::potato_cpu::potato_asm::PotatoExecutionError;
+use crate::potato_cpu::potato_cpu::{Cpu, PotatoCPU};
+use crate::py_errors::execution_error_to_py;
+
+/*
+Stops a running program from another Python thread, which can run
+since the GIL is released while potato programs execute
+*/
+#[gen_stub_pyclass]
+#[pyclass(module = "py_ca_compiler.potato")]
+#[derive(Clone, Default)]
+pub struct PyCancellationToken {
+    cancelled: Arc<AtomicBool>
+}
+#[gen_stub_pymethods]
+#[pymethods]
+impl PyCancellationToken {
+    #[new]
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+}
+
+/*
+Calls run without holding the GIL, passing it a should_cancel callback
+for Cpu::run_cancellable that says to stop once the token is cancelled
+or Python has a pending signal. The error from the signal handler
+(KeyboardInterrupt for Ctrl-C) is raised instead of what run returns
+*/
+pub(crate) fn allow_threads_cancellable<T: Send>(
+    py: Python<'_>, cancel_token: Option<&PyCancellationToken>,
+    run: impl FnOnce(&mut dyn FnMut() -> bool) -> T + Send
+) -> PyResult<T> {
+    let cancelled = cancel_token.map(|token| token.cancelled.clone());
+    let mut signal_error = None;
+    let result = py.allow_threads(|| {
+        let mut should_cancel = || {
+            if cancelled.as_ref().is_some_and(|cancelled| cancelled.load(Ordering::SeqCst)) {
+                return true;
+            }
+            match Python::with_gil(|py| py.check_signals()) {
+                Ok(()) => false,
+                Err(err) => {
+                    signal_error = Some(err);
+                    true
+                }
+            }
+        };
+        run(&mut should_cancel)
+    });
+    match signal_error {
+        Some(err) => Err(err),
+        None => Ok(result)
+    }
+}
+
+// runs the cpu like PotatoCPU::run, returning whether it has halted
+pub(crate) fn run_cpu_cancellable(
+    py: Python<'_>, cpu: &mut PotatoCPU, max_steps: usize,
+    cancel_token: Option<&PyCancellationToken>
+) -> PyResult<bool> {
+    let run_result = allow_threads_cancellable(py, cancel_token, |should_cancel| {
+        cpu.run_cancellable(max_steps, should_cancel)
+    })?;
+    match run_result {
+        Some(run_result) => Ok(run_result.halted),
+        None => Err(execution_error_to_py(PotatoExecutionError::Cancelled))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::potato_cpu::potato_asm_text::parse_potato_asm;
+    use crate::potato_cpu::potato_cpu::PotatoSpec;
+
+    #[test]
+    fn test_cancelled_run() {
+        pyo3::prepare_freethreaded_python();
+        // jumps back to itself forever
+        let spec = PotatoSpec::new(parse_potato_asm("jump 0").unwrap(), 1, 32);
+        let mut cpu = PotatoCPU::new(&spec);
+        let token = PyCancellationToken::new();
+
+        Python::with_gil(|py| {
+            assert!(!run_cpu_cancellable(py, &mut cpu, 25_000, Some(&token)).unwrap());
+            assert_eq!(cpu.time_steps, 25_000);
+
+            token.cancel();
+            assert!(token.clone().is_cancelled());
+            let err = run_cpu_cancellable(py, &mut cpu, 25_000, Some(&token)).unwrap_err();
+            assert!(err.is_instance_of::<crate::py_errors::CaExecutionError>(py));
+        });
+        assert_eq!(cpu.time_steps, 25_000);
+    }
+}
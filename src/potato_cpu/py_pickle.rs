@@ -0,0 +1,22 @@
+use pyo3::{Bound, PyResult, Python};
+use pyo3::exceptions::PyValueError;
+use pyo3::types::PyBytes;
+use serde::de::DeserializeOwned;
+use serde::Serialize;
+
+/*
+Pickled state of the Python classes, as the bincode encoding of the
+Rust values behind them. It's only meant to be unpickled by the same
+build of the module, as the encoding has no versioning of its own
+*/
+pub(crate) fn to_pickle_state<'py, T: Serialize>(
+    py: Python<'py>, value: &T
+) -> PyResult<Bound<'py, PyBytes>> {
+    let state = bincode::serialize(value)
+        .map_err(|err| PyValueError::new_err(format!("Can't pickle: {}", err)))?;
+    Ok(PyBytes::new(py, &state))
+}
+pub(crate) fn from_pickle_state<T: DeserializeOwned>(state: &[u8]) -> PyResult<T> {
+    bincode::deserialize(state)
+        .map_err(|err| PyValueError::new_err(format!("Invalid pickled state: {}", err)))
+}
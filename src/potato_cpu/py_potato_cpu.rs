@@ -0,0 +1,164 @@
+use numpy::PyArray1;
+use pyo3::{pyclass, pymethods, Bound, PyAny, PyRef, PyResult, Python};
+use pyo3::exceptions::PyValueError;
+use pyo3::prelude::PyAnyMethods;
+use pyo3::types::{PyBytes, PyBytesMethods, PyType};
+use pyo3_stub_gen::derive::{gen_stub_pyclass, gen_stub_pymethods};
+use crate::potato_cpu::bit_allocation::BitAllocation;
+use crate::potato_cpu::potato_asm_text::{
+    emit_potato_instruction, parse_potato_asm, parse_potato_register
+};
+use crate::potato_cpu::potato_cpu::{PotatoCPU, PotatoSpec};
+use crate::potato_cpu::py_cancellation::{run_cpu_cancellable, PyCancellationToken};
+use crate::potato_cpu::py_pickle::{from_pickle_state, to_pickle_state};
+use crate::potato_cpu::py_potato_cpu_tester::compile_potato_program;
+use crate::py_errors::{execution_error_to_py, potato_asm_error_to_py, CaParseError};
+
+// register values are decimal strings since registers have no maximum width
+pub(crate) fn read_register_by_name(cpu: &PotatoCPU, name: &str) -> PyResult<String> {
+    let register = match parse_potato_register(name) {
+        Some(register) => register,
+        None => return Err(PyValueError::new_err(format!("Unknown register {}", name)))
+    };
+    match cpu.registers.get(&register) {
+        Some(value) => Ok(value.to_big_num().to_string()),
+        None => Err(PyValueError::new_err(format!("No register {} in this cpu", name)))
+    }
+}
+
+// every stack value, as long as they all fit in a u32
+pub(crate) fn stack_values(cpu: &PotatoCPU) -> Result<Vec<u32>, String> {
+    cpu.stack.iter().enumerate().map(|(address, value)| {
+        u64::try_from(value).ok()
+            .and_then(|value| u32::try_from(value).ok())
+            .ok_or_else(|| format!("Stack value at @{} doesn't fit in a u32", address))
+    }).collect()
+}
+
+#[gen_stub_pyclass]
+#[pyclass(module = "py_ca_compiler.potato")]
+pub struct PyPotatoCPU {
+    cpu: PotatoCPU
+}
+#[gen_stub_pymethods]
+#[pymethods]
+impl PyPotatoCPU {
+    // loads a program written in textual potato assembly (see potato_asm_text)
+    #[new]
+    pub fn new(asm_text: String, num_scratch_registers: u8, stack_width: u16) -> PyResult<Self> {
+        let instructions = parse_potato_asm(&asm_text)
+            .map_err(potato_asm_error_to_py)?;
+        let spec = PotatoSpec::new(instructions, num_scratch_registers, stack_width);
+        spec.validate().map_err(|err| CaParseError::new_err(err.to_string()))?;
+        Ok(Self { cpu: PotatoCPU::new(&spec) })
+    }
+
+    #[classmethod]
+    pub fn compile_from_source(
+        _cls: &Bound<'_, PyType>, source_filepath: String
+    ) -> PyResult<Self> {
+        let potato_program = compile_potato_program(&source_filepath)?;
+        Ok(Self { cpu: PotatoCPU::new(&potato_program.spec()) })
+    }
+
+    // executes the next instruction, returning whether the cpu has halted
+    pub fn step(&mut self) -> PyResult<bool> {
+        Ok(self.cpu.step().halted)
+    }
+    // runs without the GIL, raising if cancel_token gets cancelled or on Ctrl-C
+    #[pyo3(signature = (max_steps, cancel_token=None))]
+    pub fn run(
+        &mut self, py: Python<'_>, max_steps: usize,
+        cancel_token: Option<PyRef<'_, PyCancellationToken>>
+    ) -> PyResult<bool> {
+        run_cpu_cancellable(py, &mut self.cpu, max_steps, cancel_token.as_deref())
+    }
+
+    pub fn is_halted(&self) -> PyResult<bool> {
+        Ok(self.cpu.halted)
+    }
+    pub fn time_steps(&self) -> PyResult<usize> {
+        Ok(self.cpu.time_steps)
+    }
+    pub fn program_counter(&self) -> PyResult<usize> {
+        Ok(self.cpu.read_program_counter())
+    }
+    // the instruction at the program counter, or None past the end of the program
+    pub fn next_instruction(&self) -> PyResult<Option<String>> {
+        let instructions = self.cpu.get_instructions();
+        Ok(instructions.get(self.cpu.read_program_counter()).map(emit_potato_instruction))
+    }
+
+    // register names as in potato assembly, e.g. fn_ret or s0
+    pub fn read_register(&self, name: String) -> PyResult<String> {
+        read_register_by_name(&self.cpu, &name)
+    }
+    pub fn read_stack(&self, index: usize) -> PyResult<String> {
+        Ok(self.cpu.read_from_stack(index).to_big_num().to_string())
+    }
+    pub fn stack_array<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyArray1<u32>>> {
+        let values = stack_values(&self.cpu).map_err(execution_error_to_py)?;
+        Ok(PyArray1::from_vec(py, values))
+    }
+    pub fn stack_size(&self) -> PyResult<usize> {
+        Ok(self.cpu.stack.len())
+    }
+    pub fn output(&self) -> PyResult<Vec<u8>> {
+        Ok(self.cpu.output.clone())
+    }
+
+    pub fn __getstate__<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyBytes>> {
+        to_pickle_state(py, &self.cpu)
+    }
+    pub fn __setstate__(&mut self, state: &Bound<'_, PyBytes>) -> PyResult<()> {
+        self.cpu = from_pickle_state(state.as_bytes())?;
+        Ok(())
+    }
+    #[classmethod]
+    pub fn from_state(
+        _cls: &Bound<'_, PyType>, state: &Bound<'_, PyBytes>
+    ) -> PyResult<Self> {
+        Ok(Self { cpu: from_pickle_state(state.as_bytes())? })
+    }
+    // unpickles through from_state, which doesn't need the asm text again
+    pub fn __reduce__<'py>(
+        &self, py: Python<'py>
+    ) -> PyResult<(Bound<'py, PyAny>, (Bound<'py, PyBytes>,))> {
+        let from_state = py.get_type::<Self>().getattr("from_state")?;
+        Ok((from_state, (self.__getstate__(py)?,)))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_step_through_asm() {
+        // errors get raised as exceptions, which need the interpreter
+        pyo3::prepare_freethreaded_python();
+        let mut cpu = PyPotatoCPU::new("
+            jump 2
+            data 0b0110
+            mov_data 1, s0
+            mov_reg_stack s0, 2
+            copy s0, fn_ret
+        ".to_string(), 2, 32).unwrap();
+        assert_eq!(cpu.next_instruction().unwrap().unwrap(), "jump 2");
+        assert!(!cpu.step().unwrap());
+        assert!(!cpu.step().unwrap());
+        assert_eq!(cpu.read_register("s0".to_string()).unwrap(), "6");
+        assert_eq!(cpu.program_counter().unwrap(), 3);
+
+        assert!(Python::with_gil(|py| cpu.run(py, 100, None)).unwrap());
+        assert_eq!(cpu.read_stack(2).unwrap(), "6");
+        assert_eq!(stack_values(&cpu.cpu).unwrap(), [0, 0, 6]);
+        assert_eq!(cpu.read_register("fn_ret".to_string()).unwrap(), "6");
+        assert_eq!(cpu.time_steps().unwrap(), 4);
+        assert_eq!(cpu.next_instruction().unwrap(), None);
+
+        assert!(cpu.read_register("s7".to_string()).is_err());
+        assert!(cpu.read_register("r1".to_string()).is_err());
+        assert!(PyPotatoCPU::new("frobnicate".to_string(), 2, 32).is_err());
+    }
+}
@@ -1,15 +1,124 @@
-use pyo3::{pyclass, pymethods, Bound, PyResult};
+use std::collections::HashMap;
+use pyo3::{pyclass, pyfunction, pymethods, Bound, IntoPyObject, PyAny, PyRef, PyResult, Python};
 use pyo3::exceptions::PyValueError;
-use pyo3::types::PyType;
-use pyo3_stub_gen::define_stub_info_gatherer;
-use pyo3_stub_gen::derive::{gen_stub_pyclass, gen_stub_pymethods};
-use crate::potato_cpu::potato_asm::PotatoProgram;
+use pyo3::prelude::PyAnyMethods;
+use pyo3::types::{PyBytes, PyBytesMethods, PyType};
+use pyo3_stub_gen::{define_stub_info_gatherer, PyStubType, TypeInfo};
+use pyo3_stub_gen::derive::{gen_stub_pyclass, gen_stub_pyfunction, gen_stub_pymethods};
+use crate::potato_cpu::bit_allocation::BitAllocation;
+use crate::potato_cpu::potato_asm::{PotatoProgram, MAX_EXECUTION_STEPS};
+use crate::potato_cpu::potato_cpu::{ExecutionTrace, PotatoCPU, TraceEntry};
+use crate::potato_cpu::potato_vm;
+use crate::potato_cpu::py_cancellation::{
+    allow_threads_cancellable, run_cpu_cancellable, PyCancellationToken
+};
+use crate::potato_cpu::py_pickle::{from_pickle_state, to_pickle_state};
+use crate::potato_cpu::py_potato_cpu::read_register_by_name;
+use crate::py_errors::{codegen_error_to_py, compiler_error_to_py, execution_error_to_py};
 use crate::tacky;
 
+// one executed instruction, with register values as decimal strings
+// since the cpu's registers have no maximum width
 #[gen_stub_pyclass]
-#[pyclass]
+#[pyclass(module = "py_ca_compiler.potato")]
+pub struct PyTraceEntry {
+    #[pyo3(get)]
+    program_counter: usize,
+    #[pyo3(get)]
+    instruction: String,
+    #[pyo3(get)]
+    register_deltas: Vec<(String, String)>,
+}
+impl PyTraceEntry {
+    fn from_trace_entry(entry: &TraceEntry) -> Self {
+        PyTraceEntry {
+            program_counter: entry.program_counter,
+            instruction: format!("{:?}", entry.instruction),
+            register_deltas: entry.register_deltas.iter()
+                .map(|(register, value)| {
+                    (format!("{:?}", register), value.to_big_num().to_string())
+                })
+                .collect(),
+        }
+    }
+}
+
+// one step of a PyTrace, which becomes a dict with these keys in Python
+#[derive(IntoPyObject)]
+pub struct PyTraceStep {
+    pc: usize,
+    instruction: String,
+    changed_registers: HashMap<String, String>,
+}
+impl PyTraceStep {
+    fn from_trace_entry(entry: &TraceEntry) -> Self {
+        let entry = PyTraceEntry::from_trace_entry(entry);
+        PyTraceStep {
+            pc: entry.program_counter,
+            instruction: entry.instruction,
+            changed_registers: entry.register_deltas.into_iter().collect(),
+        }
+    }
+}
+impl PyStubType for PyTraceStep {
+    fn type_output() -> TypeInfo {
+        TypeInfo {
+            name: "builtins.dict[builtins.str, typing.Any]".to_string(),
+            import: ["builtins".into(), "typing".into()].into_iter().collect(),
+        }
+    }
+}
+
+// iterates over the steps of an execution trace, e.g. to load them into pandas
+#[gen_stub_pyclass]
+#[pyclass(module = "py_ca_compiler.potato")]
+pub struct PyTrace {
+    entries: Vec<TraceEntry>,
+    position: usize,
+}
+#[gen_stub_pymethods]
+#[pymethods]
+impl PyTrace {
+    pub fn __iter__(slf: PyRef<'_, Self>) -> PyRef<'_, Self> {
+        slf
+    }
+    pub fn __next__(&mut self) -> Option<PyTraceStep> {
+        let entry = self.entries.get(self.position)?;
+        self.position += 1;
+        Some(PyTraceStep::from_trace_entry(entry))
+    }
+    pub fn __len__(&self) -> usize {
+        self.entries.len()
+    }
+}
+
+pub(crate) fn compile_potato_program(source_filepath: &str) -> PyResult<PotatoProgram> {
+    let tacky_program = tacky::tacky_symbols::tacky_gen_from_filepath(source_filepath, true)
+        .map_err(compiler_error_to_py)?;
+    PotatoProgram::try_from_tacky_program(tacky_program).map_err(codegen_error_to_py)
+}
+pub(crate) fn compile_potato_program_from_str(source: &str) -> PyResult<PotatoProgram> {
+    let tacky_program = tacky::tacky_symbols::tacky_gen_from_str(source)
+        .map_err(compiler_error_to_py)?;
+    PotatoProgram::try_from_tacky_program(tacky_program).map_err(codegen_error_to_py)
+}
+
+#[gen_stub_pyclass]
+#[pyclass(module = "py_ca_compiler.potato")]
 pub struct PyPotatoCPUTester {
-    program: PotatoProgram
+    program: PotatoProgram,
+    // stepped through by step and run, separately from the execute methods
+    cpu: PotatoCPU
+}
+impl PyPotatoCPUTester {
+    // runs without the GIL, raising if cancel_token gets cancelled or on Ctrl-C
+    fn execute_with_trace_cancellable(
+        &self, py: Python<'_>, max_steps: usize, cancel_token: Option<&PyCancellationToken>
+    ) -> PyResult<(i64, ExecutionTrace)> {
+        allow_threads_cancellable(py, cancel_token, |should_cancel| {
+            self.program.try_execute_with_trace_cancellable(max_steps, should_cancel)
+        })?.map_err(execution_error_to_py)
+    }
 }
 #[gen_stub_pymethods]
 #[pymethods]
@@ -18,24 +127,251 @@ impl PyPotatoCPUTester {
     pub fn compile_from_source(
         _cls: &Bound<'_, PyType>, source_filepath: String
     ) -> PyResult<Self> {
-        let tacky_gen_result =
-            tacky::tacky_symbols::tacky_gen_from_filepath(&*source_filepath, true);
-        let tacky_program = match tacky_gen_result {
-            Ok(program) => { program }
-            Err(_) => {
-                return Err(PyValueError::new_err(format!(
-                    "Tacky Generation Error: {}", tacky_gen_result.err().unwrap()
-                )));
-            }
-        };
-        let potato_program = PotatoProgram::from_tacky_program(tacky_program);
-        Ok(Self { program: potato_program })
+        let potato_program = compile_potato_program(&source_filepath)?;
+        let cpu = PotatoCPU::new(&potato_program.spec());
+        Ok(Self { program: potato_program, cpu })
+    }
+    #[classmethod]
+    pub fn compile_from_string(_cls: &Bound<'_, PyType>, source: String) -> PyResult<Self> {
+        let potato_program = compile_potato_program_from_str(&source)?;
+        let cpu = PotatoCPU::new(&potato_program.spec());
+        Ok(Self { program: potato_program, cpu })
+    }
+
+    // runs without the GIL, raising if cancel_token gets cancelled or on Ctrl-C
+    #[pyo3(signature = (cancel_token=None))]
+    pub fn execute(
+        &self, py: Python<'_>, cancel_token: Option<PyRef<'_, PyCancellationToken>>
+    ) -> PyResult<i64> {
+        allow_threads_cancellable(py, cancel_token.as_deref(), |should_cancel| {
+            self.program.try_execute_cancellable(should_cancel)
+        })?.map_err(execution_error_to_py)
+    }
+
+    // runs the program on the BigUint reference VM instead of the PotatoCPU
+    #[pyo3(signature = (cancel_token=None))]
+    pub fn execute_on_reference_vm(
+        &self, py: Python<'_>, cancel_token: Option<PyRef<'_, PyCancellationToken>>
+    ) -> PyResult<i64> {
+        allow_threads_cancellable(py, cancel_token.as_deref(), |should_cancel| {
+            self.program.try_execute_on_cancellable::<potato_vm::PotatoCPU>(should_cancel)
+        })?.map_err(execution_error_to_py)
+    }
+
+    // also stops with an error if the program is still running after max_steps
+    #[pyo3(signature = (max_steps=MAX_EXECUTION_STEPS, cancel_token=None))]
+    pub fn execute_with_trace(
+        &self, py: Python<'_>, max_steps: usize,
+        cancel_token: Option<PyRef<'_, PyCancellationToken>>
+    ) -> PyResult<(i64, Vec<PyTraceEntry>)> {
+        let (result, trace) = self.execute_with_trace_cancellable(
+            py, max_steps, cancel_token.as_deref()
+        )?;
+        let entries = trace.entries.iter().map(PyTraceEntry::from_trace_entry).collect();
+        Ok((result, entries))
+    }
+    #[pyo3(signature = (max_steps=MAX_EXECUTION_STEPS, cancel_token=None))]
+    pub fn trace(
+        &self, py: Python<'_>, max_steps: usize,
+        cancel_token: Option<PyRef<'_, PyCancellationToken>>
+    ) -> PyResult<PyTrace> {
+        let (_, trace) = self.execute_with_trace_cancellable(
+            py, max_steps, cancel_token.as_deref()
+        )?;
+        Ok(PyTrace { entries: trace.entries, position: 0 })
+    }
+
+    // executes the next instruction, returning whether the cpu has halted
+    pub fn step(&mut self) -> PyResult<bool> {
+        Ok(self.cpu.step().halted)
+    }
+    #[pyo3(signature = (max_steps, cancel_token=None))]
+    pub fn run(
+        &mut self, py: Python<'_>, max_steps: usize,
+        cancel_token: Option<PyRef<'_, PyCancellationToken>>
+    ) -> PyResult<bool> {
+        run_cpu_cancellable(py, &mut self.cpu, max_steps, cancel_token.as_deref())
+    }
+
+    // register names as in potato assembly, e.g. fn_ret or s0
+    pub fn read_register(&self, name: String) -> PyResult<String> {
+        read_register_by_name(&self.cpu, &name)
+    }
+    pub fn read_stack(&self, index: usize) -> PyResult<String> {
+        Ok(self.cpu.read_from_stack(index).to_big_num().to_string())
+    }
+    pub fn program_counter(&self) -> PyResult<usize> {
+        Ok(self.cpu.read_program_counter())
+    }
+
+    // the compiled program along with the cpu that step and run go through
+    pub fn __getstate__<'py>(&self, py: Python<'py>) -> PyResult<Bound<'py, PyBytes>> {
+        to_pickle_state(py, &(&self.program, &self.cpu))
+    }
+    pub fn __setstate__(&mut self, state: &Bound<'_, PyBytes>) -> PyResult<()> {
+        (self.program, self.cpu) = from_pickle_state(state.as_bytes())?;
+        Ok(())
+    }
+    #[classmethod]
+    pub fn from_state(
+        _cls: &Bound<'_, PyType>, state: &Bound<'_, PyBytes>
+    ) -> PyResult<Self> {
+        let (program, cpu) = from_pickle_state(state.as_bytes())?;
+        Ok(Self { program, cpu })
+    }
+    // unpickles through from_state, as there's no constructor to set the state on
+    pub fn __reduce__<'py>(
+        &self, py: Python<'py>
+    ) -> PyResult<(Bound<'py, PyAny>, (Bound<'py, PyBytes>,))> {
+        let from_state = py.get_type::<Self>().getattr("from_state")?;
+        Ok((from_state, (self.__getstate__(py)?,)))
     }
+}
 
-    pub fn execute(&self) -> PyResult<i64> {
-        let result = self.program.execute();
-        Ok(result)
+/*
+Compiles C source code and runs it, returning what main returns.
+The backend is either "potato" for the PotatoCPU or "reference_vm" for
+the BigUint reference VM; tacky has no interpreter to run it on yet.
+Runs without the GIL, and can be stopped with cancel_token or Ctrl-C
+*/
+#[gen_stub_pyfunction(module = "py_ca_compiler.compiler")]
+#[pyfunction]
+#[pyo3(signature = (source, backend, cancel_token=None))]
+pub fn compile_and_run(
+    py: Python<'_>, source: String, backend: String,
+    cancel_token: Option<PyRef<'_, PyCancellationToken>>
+) -> PyResult<i64> {
+    if !["potato", "reference_vm"].contains(&backend.as_str()) {
+        return Err(PyValueError::new_err(format!("Unknown backend {}", backend)));
     }
+    let potato_program = compile_potato_program_from_str(&source)?;
+    let result = allow_threads_cancellable(py, cancel_token.as_deref(), |should_cancel| {
+        match backend.as_str() {
+            "potato" => potato_program.try_execute_cancellable(should_cancel),
+            _ => potato_program.try_execute_on_cancellable::<potato_vm::PotatoCPU>(should_cancel)
+        }
+    })?;
+    result.map_err(execution_error_to_py)
 }
 
 define_stub_info_gatherer!(stub_info);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use pyo3::Py;
+    use crate::py_errors::{CaCodegenError, CaExecutionError};
+
+    #[test]
+    fn test_compile_and_run() {
+        // errors get raised as exceptions, which need the interpreter
+        pyo3::prepare_freethreaded_python();
+        let source = "int main(void) { int x = 6; return x * 7 - 2; }";
+        let run = |source: &str, backend: &str| Python::with_gil(|py| {
+            compile_and_run(py, source.to_string(), backend.to_string(), None)
+        });
+        assert_eq!(run(source, "potato").unwrap(), 40);
+        assert_eq!(run(source, "reference_vm").unwrap(), 40);
+
+        assert!(run(source, "tacky").is_err());
+        assert!(run("int main(void) {", "potato").is_err());
+    }
+
+    #[test]
+    fn test_compile_from_string() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let cls = py.get_type::<PyPotatoCPUTester>();
+            let source = "int main(void) { return 3 - 5; }".to_string();
+            let tester = PyPotatoCPUTester::compile_from_string(&cls, source).unwrap();
+            assert_eq!(tester.execute(py, None).unwrap(), -2);
+
+            let source = "int main(void) { return 3 - ; }".to_string();
+            assert!(PyPotatoCPUTester::compile_from_string(&cls, source).is_err());
+        });
+    }
+
+    #[test]
+    fn test_unsupported_program_raises() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let cls = py.get_type::<PyPotatoCPUTester>();
+            // valid C that the PotatoCPU can't run, as it has no memory to point into
+            let source = "int main(void) { int a = 3; int *p = &a; return *p; }".to_string();
+            let err = PyPotatoCPUTester::compile_from_string(&cls, source).err().unwrap();
+            assert!(err.is_instance_of::<CaCodegenError>(py));
+
+            let source = "int main(void) { double d = 1.5; return d > 1.0; }".to_string();
+            let err = compile_and_run(py, source, "potato".to_string(), None).err().unwrap();
+            assert!(err.is_instance_of::<CaCodegenError>(py));
+        });
+    }
+
+    #[test]
+    fn test_trace_steps() {
+        let tacky_program = tacky::tacky_symbols::tacky_gen_from_str(
+            "int main(void) { return 3; }"
+        ).unwrap();
+        let program = PotatoProgram::from_tacky_program(tacky_program);
+        let (_, trace) = program.execute_with_trace();
+        let num_entries = trace.entries.len();
+        let mut py_trace = PyTrace { entries: trace.entries.clone(), position: 0 };
+        assert_eq!(py_trace.__len__(), num_entries);
+
+        let steps: Vec<PyTraceStep> = std::iter::from_fn(|| py_trace.__next__()).collect();
+        assert_eq!(steps.len(), num_entries);
+        for (step, entry) in steps.iter().zip(&trace.entries) {
+            assert_eq!(step.pc, entry.program_counter);
+            assert_eq!(step.changed_registers.len(), entry.register_deltas.len());
+        }
+        assert!(steps.iter().any(|step| step.changed_registers.get("FunctionReturn").is_some()));
+        assert!(py_trace.__next__().is_none());
+    }
+    #[test]
+    fn test_trace_step_limit_and_cancellation() {
+        pyo3::prepare_freethreaded_python();
+        Python::with_gil(|py| {
+            let cls = py.get_type::<PyPotatoCPUTester>();
+            let source = "int main(void) { int x = 6; return x * 7 - 2; }".to_string();
+            let tester = PyPotatoCPUTester::compile_from_string(&cls, source).unwrap();
+            let num_steps = tester.trace(py, MAX_EXECUTION_STEPS, None).unwrap().__len__();
+
+            let err = tester.trace(py, num_steps - 1, None).err().unwrap();
+            assert!(err.is_instance_of::<CaExecutionError>(py));
+            let (result, entries) = tester.execute_with_trace(py, num_steps, None).unwrap();
+            assert_eq!((result, entries.len()), (40, num_steps));
+
+            let token = Py::new(py, PyCancellationToken::new()).unwrap();
+            token.borrow(py).cancel();
+            let err = tester.trace(py, num_steps, Some(token.borrow(py))).err().unwrap();
+            assert!(err.is_instance_of::<CaExecutionError>(py));
+        });
+    }
+    #[test]
+    fn test_pickle_state() {
+        pyo3::prepare_freethreaded_python();
+        let tacky_program = tacky::tacky_symbols::tacky_gen_from_str(
+            "int main(void) { int x = 6; return x * 7 - 2; }"
+        ).unwrap();
+        let program = PotatoProgram::from_tacky_program(tacky_program);
+        let cpu = PotatoCPU::new(&program.spec());
+        let mut tester = PyPotatoCPUTester { program, cpu };
+        for _ in 0..5 {
+            tester.step().unwrap();
+        }
+
+        Python::with_gil(|py| {
+            let state = tester.__getstate__(py).unwrap();
+            let cls = py.get_type::<PyPotatoCPUTester>();
+            let mut restored = PyPotatoCPUTester::from_state(&cls, &state).unwrap();
+            assert_eq!(restored.program_counter().unwrap(), tester.program_counter().unwrap());
+            assert_eq!(restored.cpu.registers, tester.cpu.registers);
+            assert_eq!(restored.program.execute(), 40);
+
+            assert!(restored.run(py, 1000, None).unwrap());
+            assert_eq!(restored.read_register("fn_ret".to_string()).unwrap(), "40");
+            let truncated = PyBytes::new(py, &state.as_bytes()[..state.as_bytes().len() / 2]);
+            assert!(restored.__setstate__(&truncated).is_err());
+        });
+    }
+}
@@ -0,0 +1,456 @@
+use std::collections::{HashMap, HashSet};
+use std::error::Error;
+use std::fmt::{Display, Formatter};
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+/*
+Minimal C preprocessor that runs on the source text before it is lexed.
+Supports comment stripping, #include "file" / #include <file>,
+object-like #define / #undef and #ifdef / #ifndef / #else / #endif.
+Directive lines are replaced by empty lines so that line numbers in
+the main source file still match for diagnostics and debug info.
+Included files are wrapped in gcc style line markers (# <line> "<file>"),
+which tell the lexer which line the line after the marker comes from
+*/
+
+// same limit as gcc, to catch include cycles that aren't guarded
+const MAX_INCLUDE_DEPTH: usize = 200;
+
+#[derive(Debug)]
+pub enum PreprocessorError {
+    IoError(PathBuf, io::Error),
+    IncludeNotFound(String),
+    IncludeDepthExceeded(PathBuf),
+    // directive that is missing its arguments or has malformed ones
+    InvalidDirective(String),
+    // valid C that this preprocessor doesn't handle, e.g. function-like macros
+    UnsupportedDirective(String),
+    // #else or #endif without an opening #ifdef
+    UnmatchedConditional(String),
+    UnterminatedConditional,
+    UnterminatedComment,
+}
+impl Display for PreprocessorError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            PreprocessorError::IoError(path, err) => {
+                write!(f, "I/O error reading {}: {}", path.display(), err)
+            },
+            PreprocessorError::IncludeNotFound(name) => {
+                write!(f, "Included file not found: {}", name)
+            },
+            PreprocessorError::IncludeDepthExceeded(path) => {
+                write!(f, "#include nested too deeply at {}", path.display())
+            },
+            PreprocessorError::InvalidDirective(line) => write!(f, "Invalid directive: {}", line),
+            PreprocessorError::UnsupportedDirective(line) => {
+                write!(f, "Unsupported directive: {}", line)
+            },
+            PreprocessorError::UnmatchedConditional(line) => {
+                write!(f, "{} without matching #ifdef", line)
+            },
+            PreprocessorError::UnterminatedConditional => write!(f, "Unterminated #ifdef"),
+            PreprocessorError::UnterminatedComment => write!(f, "Unterminated comment"),
+        }
+    }
+}
+impl Error for PreprocessorError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            PreprocessorError::IoError(_, err) => Some(err),
+            _ => None
+        }
+    }
+}
+
+struct Conditional {
+    // whether lines in the current branch are kept
+    taking: bool,
+    seen_else: bool,
+}
+
+pub struct Preprocessor {
+    include_paths: Vec<PathBuf>,
+    defines: HashMap<String, String>,
+}
+impl Preprocessor {
+    pub fn new(include_paths: Vec<PathBuf>) -> Self {
+        Preprocessor { include_paths, defines: HashMap::new() }
+    }
+    pub fn preprocess_file(&mut self, file_path: &Path) -> Result<String, PreprocessorError> {
+        self.preprocess_file_at_depth(file_path, 0)
+    }
+    pub fn preprocess_str(&mut self, source: &str) -> Result<String, PreprocessorError> {
+        // without a file of its own, quoted includes are only searched for in the include paths
+        self.preprocess_source(source, None, 0)
+    }
+    pub fn parse_line_marker(line: &str) -> Option<usize> {
+        // line number set by a line marker, ignoring the file it names
+        let marker = line.trim().strip_prefix('#')?.trim_start();
+        let (number, file) = match marker.find(char::is_whitespace) {
+            Some(index) => (&marker[..index], marker[index..].trim()),
+            None => (marker, "")
+        };
+        let is_file = file.is_empty()
+            || (file.len() > 1 && file.starts_with('"') && file.ends_with('"'));
+        match number.chars().all(|c| c.is_ascii_digit()) && is_file {
+            true => number.parse().ok(),
+            false => None
+        }
+    }
+
+    fn preprocess_file_at_depth(
+        &mut self, file_path: &Path, depth: usize
+    ) -> Result<String, PreprocessorError> {
+        if depth > MAX_INCLUDE_DEPTH {
+            return Err(PreprocessorError::IncludeDepthExceeded(file_path.to_path_buf()));
+        }
+        let source = fs::read_to_string(file_path)
+            .map_err(|err| PreprocessorError::IoError(file_path.to_path_buf(), err))?;
+        self.preprocess_source(&source, Some(file_path), depth)
+    }
+
+    fn preprocess_source(
+        &mut self, source: &str, file_path: Option<&Path>, depth: usize
+    ) -> Result<String, PreprocessorError> {
+        let source = strip_comments(source)?;
+        let current_dir = file_path.and_then(Path::parent);
+        let mut output_lines: Vec<String> = Vec::new();
+        let mut conditionals: Vec<Conditional> = Vec::new();
+
+        for (line_index, line) in source.lines().enumerate() {
+            let taking = conditionals.iter().all(|conditional| conditional.taking);
+            let trimmed_line = line.trim();
+            let Some(directive) = trimmed_line.strip_prefix('#') else {
+                output_lines.push(match taking {
+                    true => self.expand_macros(line, &mut HashSet::new()),
+                    false => String::new()
+                });
+                continue;
+            };
+
+            let directive = directive.trim_start();
+            let (name, arguments) = match directive.find(char::is_whitespace) {
+                Some(index) => (&directive[..index], directive[index..].trim()),
+                None => (directive, "")
+            };
+            let mut included_source = String::new();
+            match name {
+                "ifdef" | "ifndef" => {
+                    let is_defined = self.defines.contains_key(macro_name(trimmed_line, arguments)?);
+                    conditionals.push(Conditional {
+                        taking: is_defined == (name == "ifdef"),
+                        seen_else: false
+                    });
+                },
+                "else" => match conditionals.last_mut() {
+                    Some(conditional) if !conditional.seen_else => {
+                        conditional.taking = !conditional.taking;
+                        conditional.seen_else = true;
+                    },
+                    _ => return Err(PreprocessorError::UnmatchedConditional(
+                        trimmed_line.to_string()
+                    ))
+                },
+                "endif" => if conditionals.pop().is_none() {
+                    return Err(PreprocessorError::UnmatchedConditional(
+                        trimmed_line.to_string()
+                    ));
+                },
+                // the remaining directives are skipped along with the rest of the branch
+                _ if !taking => {},
+                "define" => {
+                    let (macro_name, replacement) = match arguments.find(|c: char| {
+                        !is_identifier_char(c)
+                    }) {
+                        Some(index) => (&arguments[..index], &arguments[index..]),
+                        None => (arguments, "")
+                    };
+                    if replacement.starts_with('(') {
+                        return Err(PreprocessorError::UnsupportedDirective(
+                            trimmed_line.to_string()
+                        ));
+                    }
+                    let macro_name = self::macro_name(trimmed_line, macro_name)?;
+                    self.defines.insert(macro_name.to_string(), replacement.trim().to_string());
+                },
+                "undef" => {
+                    self.defines.remove(macro_name(trimmed_line, arguments)?);
+                },
+                "include" => {
+                    let include_path = self.resolve_include(trimmed_line, arguments, current_dir)?;
+                    let source = self.preprocess_file_at_depth(&include_path, depth + 1)?;
+                    // numbering picks up again at the line after the #include
+                    included_source = [
+                        line_marker(1, Some(&include_path)), source,
+                        line_marker(line_index + 2, file_path)
+                    ].join("\n");
+                },
+                // a lone # is a null directive
+                "" => {},
+                _ => return Err(PreprocessorError::UnsupportedDirective(
+                    trimmed_line.to_string()
+                ))
+            }
+            output_lines.push(included_source);
+        }
+
+        if !conditionals.is_empty() {
+            return Err(PreprocessorError::UnterminatedConditional);
+        }
+        Ok(output_lines.join("\n"))
+    }
+
+    fn resolve_include(
+        &self, line: &str, arguments: &str, current_dir: Option<&Path>
+    ) -> Result<PathBuf, PreprocessorError> {
+        // quoted includes are looked up next to the including file first
+        let (name, search_current_dir) = if let Some(quoted) = arguments.strip_prefix('"') {
+            (quoted.strip_suffix('"'), true)
+        } else if let Some(bracketed) = arguments.strip_prefix('<') {
+            (bracketed.strip_suffix('>'), false)
+        } else {
+            (None, false)
+        };
+        let name = match name {
+            Some(name) if !name.is_empty() => name,
+            _ => return Err(PreprocessorError::InvalidDirective(line.to_string()))
+        };
+
+        let current_dir = current_dir.filter(|_| search_current_dir);
+        current_dir.into_iter().chain(self.include_paths.iter().map(PathBuf::as_path))
+            .map(|directory| directory.join(name))
+            .find(|candidate| candidate.is_file())
+            .ok_or_else(|| PreprocessorError::IncludeNotFound(name.to_string()))
+    }
+
+    fn expand_macros(&self, text: &str, expanding: &mut HashSet<String>) -> String {
+        /*
+        Replaces defined identifiers with their (recursively expanded)
+        replacement text. A macro is not expanded inside its own
+        expansion, which stops self-referencing macros from looping
+        */
+        let chars: Vec<char> = text.chars().collect();
+        let mut expanded = String::new();
+        let mut index = 0;
+
+        while index < chars.len() {
+            let c = chars[index];
+            let start = index;
+            if c == '"' || c == '\'' {
+                index = skip_literal(&chars, index);
+                expanded.extend(&chars[start..index]);
+            } else if c.is_ascii_digit() || (c == '.' && chars.get(index + 1).is_some_and(
+                |next| next.is_ascii_digit()
+            )) {
+                // numbers like 0x1F or 1e10 contain identifier-like suffixes
+                index += 1;
+                while index < chars.len() && (is_identifier_char(chars[index]) || chars[index] == '.') {
+                    index += 1;
+                }
+                expanded.extend(&chars[start..index]);
+            } else if is_identifier_char(c) {
+                while index < chars.len() && is_identifier_char(chars[index]) {
+                    index += 1;
+                }
+                let identifier: String = chars[start..index].iter().collect();
+                match self.defines.get(&identifier) {
+                    Some(replacement) if !expanding.contains(&identifier) => {
+                        expanding.insert(identifier.clone());
+                        expanded.push_str(&self.expand_macros(replacement, expanding));
+                        expanding.remove(&identifier);
+                    },
+                    _ => expanded.push_str(&identifier)
+                }
+            } else {
+                expanded.push(c);
+                index += 1;
+            }
+        }
+        expanded
+    }
+}
+
+fn line_marker(line: usize, file_path: Option<&Path>) -> String {
+    match file_path {
+        Some(file_path) => format!("# {} {:?}", line, file_path.display().to_string()),
+        None => format!("# {}", line)
+    }
+}
+
+fn is_identifier_char(c: char) -> bool {
+    c.is_ascii_alphanumeric() || c == '_'
+}
+
+fn macro_name<'a>(line: &str, name: &'a str) -> Result<&'a str, PreprocessorError> {
+    let is_valid = name.chars().next().is_some_and(|c| !c.is_ascii_digit())
+        && name.chars().all(is_identifier_char);
+    match is_valid {
+        true => Ok(name),
+        false => Err(PreprocessorError::InvalidDirective(line.to_string()))
+    }
+}
+
+fn skip_literal(chars: &[char], start: usize) -> usize {
+    // index just past the char or string literal starting at start
+    let quote = chars[start];
+    let mut index = start + 1;
+    while index < chars.len() && chars[index] != quote && chars[index] != '\n' {
+        if chars[index] == '\\' { index += 1; }
+        index += 1;
+    }
+    (index + 1).min(chars.len())
+}
+
+fn strip_comments(source: &str) -> Result<String, PreprocessorError> {
+    /*
+    Replaces each comment with a single space, keeping the newlines
+    inside block comments so later lines don't change line numbers
+    */
+    let chars: Vec<char> = source.chars().collect();
+    let mut stripped = String::new();
+    let mut index = 0;
+
+    while index < chars.len() {
+        let c = chars[index];
+        let start = index;
+        match (c, chars.get(index + 1)) {
+            ('"' | '\'', _) => {
+                index = skip_literal(&chars, index);
+                stripped.extend(&chars[start..index]);
+            },
+            ('/', Some('/')) => {
+                while index < chars.len() && chars[index] != '\n' { index += 1; }
+                stripped.push(' ');
+            },
+            ('/', Some('*')) => {
+                index += 2;
+                stripped.push(' ');
+                loop {
+                    match (chars.get(index), chars.get(index + 1)) {
+                        (Some('*'), Some('/')) => break,
+                        (Some('\n'), _) => stripped.push('\n'),
+                        (Some(_), _) => {},
+                        (None, _) => return Err(PreprocessorError::UnterminatedComment)
+                    }
+                    index += 1;
+                }
+                index += 2;
+            },
+            _ => {
+                stripped.push(c);
+                index += 1;
+            }
+        }
+    }
+    Ok(stripped)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::lexer::Lexer;
+    use crate::lexer::tokens::SourceLocation;
+
+    #[test]
+    fn test_defines_and_conditionals() {
+        let source = "#define LIMIT 10 // upper bound
+            #define TWICE_LIMIT (LIMIT * 2)
+            #ifdef LIMIT
+            int a = TWICE_LIMIT; /* multi
+            line */ int b = 0x1F + 1e10 + LIMITED;
+            #else
+            int c = 1;
+            #endif
+            #ifndef LIMIT
+            int d = 2;
+            #endif
+            char e = 'L';";
+        let output = Preprocessor::new(vec![]).preprocess_str(source).unwrap();
+        let lines: Vec<&str> = output.lines().map(str::trim).collect();
+        assert_eq!(lines, vec![
+            "", "", "", "int a = (10 * 2);", "int b = 0x1F + 1e10 + LIMITED;",
+            "", "", "", "", "", "", "char e = 'L';"
+        ]);
+    }
+
+    #[test]
+    fn test_self_referencing_macro() {
+        let source = "#define x (x + 1)\nreturn x;";
+        let output = Preprocessor::new(vec![]).preprocess_str(source).unwrap();
+        assert_eq!(output, "\nreturn (x + 1);");
+    }
+
+    #[test]
+    fn test_include_paths() {
+        let include_dir = std::env::temp_dir().join("ca_compiler_preprocessor_include");
+        fs::create_dir_all(&include_dir).unwrap();
+        fs::write(
+            include_dir.join("limits.h"),
+            "#ifndef LIMITS_H\n#define LIMITS_H\n#define LIMIT 7\n#endif\n"
+        ).unwrap();
+
+        let source = "#include \"limits.h\"\n#include <limits.h>\nint a = LIMIT;";
+        let mut preprocessor = Preprocessor::new(vec![include_dir.clone()]);
+        let output = preprocessor.preprocess_str(source).unwrap();
+        let code_lines: Vec<&str> = output.lines()
+            .filter(|line| Preprocessor::parse_line_marker(line).is_none())
+            .filter(|line| !line.is_empty())
+            .collect();
+        assert_eq!(code_lines, vec!["int a = 7;"]);
+        let limits_marker = format!("# 1 {:?}", include_dir.join("limits.h").display().to_string());
+        assert!(output.starts_with(&format!("{limits_marker}\n\n\n\n\n# 2\n")));
+        assert!(output.ends_with("# 3\nint a = 7;"));
+
+        let err = Preprocessor::new(vec![]).preprocess_str(source).unwrap_err();
+        assert!(matches!(err, PreprocessorError::IncludeNotFound(name) if name == "limits.h"));
+        fs::remove_dir_all(&include_dir).unwrap();
+    }
+
+    #[test]
+    fn test_locations_after_include() {
+        let include_dir = std::env::temp_dir().join("ca_compiler_preprocessor_locations");
+        fs::create_dir_all(&include_dir).unwrap();
+        fs::write(include_dir.join("values.h"), "int a = 1;\nint b = 2;\nint c = 3;\n").unwrap();
+
+        let source = "#include <values.h>\nint main(void) {\n    return a @ b;\n}";
+        let output = Preprocessor::new(vec![include_dir.clone()]).preprocess_str(source).unwrap();
+        let err = Lexer::new().tokenize(&output).unwrap_err();
+        // the @ is on the third line of the source, not of the preprocessed output
+        assert_eq!(err.location(), SourceLocation::new(3, 14));
+
+        let tokens = Lexer::new().tokenize(&output.replace('@', "+")).unwrap();
+        let locations: Vec<SourceLocation> = tokens.iter()
+            .map(|token| token.get_start_location())
+            .collect();
+        assert_eq!(locations[0], SourceLocation::new(1, 1));
+        assert_eq!(locations.last(), Some(&SourceLocation::new(4, 1)));
+        fs::remove_dir_all(&include_dir).unwrap();
+    }
+
+    #[test]
+    fn test_line_markers() {
+        assert_eq!(Preprocessor::parse_line_marker("# 12 \"a.h\""), Some(12));
+        assert_eq!(Preprocessor::parse_line_marker("  #3"), Some(3));
+        assert_eq!(Preprocessor::parse_line_marker("#include \"a.h\""), None);
+        assert_eq!(Preprocessor::parse_line_marker("# 3 a.h"), None);
+    }
+
+    #[test]
+    fn test_invalid_directives() {
+        let preprocess = |source: &str| Preprocessor::new(vec![]).preprocess_str(source);
+        assert!(matches!(
+            preprocess("#define ADD(a, b) a + b"),
+            Err(PreprocessorError::UnsupportedDirective(_))
+        ));
+        assert!(matches!(preprocess("#pragma once"), Err(PreprocessorError::UnsupportedDirective(_))));
+        assert!(matches!(preprocess("#define 1x"), Err(PreprocessorError::InvalidDirective(_))));
+        assert!(matches!(preprocess("#endif"), Err(PreprocessorError::UnmatchedConditional(_))));
+        assert!(matches!(preprocess("#ifdef A\n"), Err(PreprocessorError::UnterminatedConditional)));
+        assert!(matches!(preprocess("int a; /* "), Err(PreprocessorError::UnterminatedComment)));
+        // directives in skipped branches aren't checked
+        assert!(preprocess("#ifdef A\n#pragma once\n#endif").is_ok());
+    }
+}
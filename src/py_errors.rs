@@ -0,0 +1,163 @@
+use std::fmt::Display;
+use pyo3::{PyErr, Python};
+use pyo3::exceptions::PyException;
+use pyo3::prelude::PyAnyMethods;
+use pyo3_stub_gen::create_exception;
+use crate::errors::CompilerError;
+use crate::lexer::lexer::LexerFromFileError;
+use crate::lexer::tokens::SourceLocation;
+use crate::potato_cpu::potato_asm_text::PotatoAsmTextError;
+use crate::tacky::tacky_symbols::{TackyError, TackyErrorVariants};
+
+/*
+Exceptions raised by the Python bindings, one per stage of the pipeline.
+Errors that point at the source code have line and column attributes
+(None when the location isn't known), so that tooling doesn't have to
+parse them back out of the message
+*/
+create_exception!(
+    py_ca_compiler.errors, CaError, PyException,
+    "Base class of the errors raised by py_ca_compiler"
+);
+/*
+pyo3_stub_gen::create_exception writes the base of every exception into
+the stubs as a builtin, which CaError isn't, so the subclasses are
+declared here with CaError as an unqualified name from the same module
+*/
+macro_rules! create_ca_exception {
+    ($name: ident, $doc: expr) => {
+        pyo3::create_exception!(py_ca_compiler.errors, $name, CaError, $doc);
+
+        impl pyo3_stub_gen::PyStubType for $name {
+            fn type_output() -> pyo3_stub_gen::TypeInfo {
+                pyo3_stub_gen::TypeInfo::unqualified(stringify!($name))
+            }
+        }
+        pyo3_stub_gen::inventory::submit! {
+            pyo3_stub_gen::type_info::PyClassInfo {
+                pyclass_name: stringify!($name),
+                struct_id: std::any::TypeId::of::<$name>,
+                getters: &[],
+                setters: &[],
+                module: Some("py_ca_compiler.errors"),
+                doc: $doc,
+                bases: &[|| pyo3_stub_gen::TypeInfo::unqualified("CaError")],
+                has_eq: false,
+                has_ord: false,
+                has_hash: false,
+                has_str: false,
+            }
+        }
+    };
+}
+create_ca_exception!(CaLexError, "Invalid token in the source");
+create_ca_exception!(
+    CaParseError, "Source (or potato assembly) that doesn't parse, or fails semantic analysis"
+);
+create_ca_exception!(CaCodegenError, "Failure to generate (or assemble) code for a valid program");
+create_ca_exception!(
+    CaExecutionError, "Program that failed while running, e.g. one that loops forever"
+);
+
+fn with_location(err: PyErr, line: Option<usize>, column: Option<usize>) -> PyErr {
+    Python::with_gil(|py| {
+        let value = err.value(py);
+        value.setattr("line", line).and_then(|_| value.setattr("column", column))
+    }).expect("exceptions accept new attributes");
+    err
+}
+fn with_source_location(err: PyErr, location: Option<SourceLocation>) -> PyErr {
+    with_location(
+        err, location.map(|location| location.line), location.map(|location| location.column)
+    )
+}
+
+pub fn compiler_error_to_py(err: CompilerError) -> PyErr {
+    match err {
+        CompilerError::LexerError(lexer_error) => {
+            let location = match &lexer_error {
+                LexerFromFileError::InvalidToken(token) => Some(token.location()),
+                LexerFromFileError::IoError(_) => None
+            };
+            with_source_location(CaLexError::new_err(lexer_error.to_string()), location)
+        },
+        CompilerError::ParseError(parse_error) => {
+            let location = parse_error.location();
+            with_source_location(CaParseError::new_err(parse_error.to_string()), location)
+        },
+        CompilerError::SemanticError(semantic_error) => {
+            let location = semantic_error.location();
+            with_source_location(CaParseError::new_err(semantic_error.to_string()), location)
+        },
+        CompilerError::TackyError(tacky_error) => tacky_error_to_py(tacky_error),
+        CompilerError::AsmGenError(_) | CompilerError::AssembleError(_) => {
+            codegen_error_to_py(err)
+        },
+    }
+}
+fn tacky_error_to_py(err: TackyError) -> PyErr {
+    // type errors are reported like semantic errors, the rest is valid C that can't be compiled
+    let location = err.location();
+    let py_err = match err.variant {
+        TackyErrorVariants::Unsupported(_) => CaCodegenError::new_err(err.to_string()),
+        _ => CaParseError::new_err(err.to_string()),
+    };
+    with_source_location(py_err, location)
+}
+pub fn codegen_error_to_py(err: impl Display) -> PyErr {
+    CaCodegenError::new_err(err.to_string())
+}
+pub fn potato_asm_error_to_py(err: PotatoAsmTextError) -> PyErr {
+    // the assembly parser only knows which line was wrong
+    with_location(CaParseError::new_err(err.to_string()), Some(err.line), None)
+}
+pub fn execution_error_to_py(err: impl Display) -> PyErr {
+    CaExecutionError::new_err(err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use pyo3::PyTypeInfo;
+    use super::*;
+    use crate::potato_cpu::potato_asm::PotatoExecutionError;
+    use crate::tacky::tacky_symbols::tacky_gen_from_str;
+
+    fn tacky_error(source: &str) -> PyErr {
+        compiler_error_to_py(tacky_gen_from_str(source).err().unwrap())
+    }
+    fn location_of(err: &PyErr) -> (Option<usize>, Option<usize>) {
+        Python::with_gil(|py| {
+            let value = err.value(py);
+            (
+                value.getattr("line").unwrap().extract().unwrap(),
+                value.getattr("column").unwrap().extract().unwrap()
+            )
+        })
+    }
+
+    #[test]
+    fn test_errors_by_stage() {
+        pyo3::prepare_freethreaded_python();
+        let lex_error = tacky_error("int main(void) {\n    return 1 @ 2;\n}");
+        let parse_error = tacky_error("int main(void) {\n    return 1 +;\n}");
+        let semantic_error = tacky_error("int main(void) {\n    return x;\n}");
+        let type_error = tacky_error("int main(void) {\n    int a = 1;\n    return *a;\n}");
+        let execution_error = execution_error_to_py(PotatoExecutionError::LoopDetected(3));
+
+        Python::with_gil(|py| {
+            assert!(lex_error.is_instance_of::<CaLexError>(py));
+            assert!(parse_error.is_instance_of::<CaParseError>(py));
+            assert!(semantic_error.is_instance_of::<CaParseError>(py));
+            assert!(type_error.is_instance_of::<CaParseError>(py));
+            assert!(execution_error.is_instance_of::<CaExecutionError>(py));
+            for err in [&lex_error, &parse_error, &execution_error] {
+                assert!(err.is_instance_of::<CaError>(py));
+                assert!(!err.is_instance(py, &pyo3::exceptions::PyValueError::type_object(py)));
+            }
+        });
+        assert_eq!(location_of(&lex_error), (Some(2), Some(14)));
+        assert_eq!(location_of(&parse_error).0, Some(2));
+        assert_eq!(location_of(&semantic_error), (Some(2), Some(12)));
+        assert_eq!(location_of(&type_error), (Some(3), Some(12)));
+    }
+}
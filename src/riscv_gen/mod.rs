@@ -0,0 +1 @@
+pub mod riscv_asm;
@@ -0,0 +1,730 @@
+use std::collections::HashMap;
+use std::io::Write;
+use crate::asm_gen::asm_symbols::{AsmGenError, LOCAL_LABEL_PREFIX};
+use crate::parser::c_types::CType;
+use crate::parser::parse::{ASTConstant, SupportedBinaryOperators, SupportedUnaryOperators};
+use crate::parser::parser_helpers::PoppedTokenContext;
+use crate::tacky::tacky_symbols::{
+    BinaryInstruction, ConversionKind, ConvertInstruction, TackyFunction, TackyInstruction,
+    TackyProgram, TackyStaticVariable, TackyValue, TackyVariable
+};
+
+/*
+RV64 (RV64IMD) backend that translates tacky directly into RISC-V
+assembly text. Every local variable lives in its own stack slot below
+the frame pointer (s0), so each tacky instruction loads its operands
+into temporary registers, computes the result and stores it back:
+    lw t0, -4(s0)
+    lw t1, -8(s0)
+    addw t0, t0, t1
+    sw t0, -12(s0)
+
+Values are kept in registers the same way lw / lwu would load them:
+signed values are sign extended and unsigned values are zero extended
+to 64 bits, which keeps the 64-bit comparisons (slt / sltu) correct
+
+Register usage:
+    t0, t1 - operands and results
+    t2 - addresses of static variables and far away stack slots
+    t3 - pointer operands of loads and stores
+    f0, f1 - double operands and results
+*/
+
+const TAB: &str = "    ";
+// offsets outside this range don't fit in a 12-bit immediate
+const MIN_IMMEDIATE: i64 = -2048;
+const MAX_IMMEDIATE: i64 = 2047;
+// bytes of the frame used to save ra and the caller's s0
+const SAVED_REGISTERS_SIZE: i64 = 16;
+// .file number of the source file that .loc directives refer to
+const DEBUG_FILE_NUMBER: u64 = 1;
+
+fn fits_in_immediate(value: i64) -> bool {
+    (MIN_IMMEDIATE..=MAX_IMMEDIATE).contains(&value)
+}
+
+fn canonical_value(bits: u64, c_type: &CType) -> i64 {
+    // value of the constant as it would be held in a register
+    match (c_type.size(), c_type.is_signed()) {
+        (1, true) => bits as u8 as i8 as i64,
+        (1, false) => bits as u8 as i64,
+        (4, true) => bits as u32 as i32 as i64,
+        (4, false) => bits as u32 as i64,
+        _ => bits as i64
+    }
+}
+
+fn load_mnemonic(c_type: &CType) -> &'static str {
+    match (c_type.size(), c_type.is_signed()) {
+        (1, true) => "lb",
+        (1, false) => "lbu",
+        (4, true) => "lw",
+        (4, false) => "lwu",
+        _ => "ld"
+    }
+}
+
+fn store_mnemonic(size: u64) -> &'static str {
+    match size {
+        1 => "sb",
+        4 => "sw",
+        _ => "sd"
+    }
+}
+
+fn word_suffix(c_type: &CType) -> &'static str {
+    // 32-bit arithmetic uses the *w instructions, which sign extend their result
+    match c_type.size() {
+        8 => "",
+        _ => "w"
+    }
+}
+
+fn is_scalar(c_type: &CType) -> bool {
+    !c_type.is_struct()
+}
+
+pub struct RiscvStaticVariable {
+    name: String,
+    var_type: CType,
+    // bit pattern of the initializer, None for zero initialized variables
+    initial_value: Option<u64>,
+}
+impl RiscvStaticVariable {
+    pub fn from_tacky_static_variable(
+        static_variable: TackyStaticVariable
+    ) -> RiscvStaticVariable {
+        RiscvStaticVariable {
+            name: static_variable.variable.name,
+            var_type: static_variable.variable.var_type,
+            initial_value: static_variable.initial_value.map(
+                |ast_constant| ast_constant.to_bits()
+            )
+        }
+    }
+    pub fn to_asm_code(&self) -> Result<String, AsmGenError> {
+        let size = self.var_type.size();
+        let mut code = String::new();
+        code.push_str(&format!("{TAB}.globl {}\n", self.name));
+        let initial_value = self.initial_value.filter(|value| *value != 0);
+        match initial_value {
+            Some(_) => code.push_str(&format!("{TAB}.data\n")),
+            None => code.push_str(&format!("{TAB}.bss\n"))
+        }
+        code.push_str(&format!("{TAB}.balign {}\n", self.var_type.alignment()));
+        code.push_str(&format!("{}:\n", self.name));
+        match initial_value {
+            Some(value) => {
+                let directive = match size {
+                    1 => ".byte",
+                    4 => ".word",
+                    8 => ".dword",
+                    _ => return Err(AsmGenError::UnsupportedInstruction(format!(
+                        "Can't initialize {} bytes for {}", size, self.name
+                    )))
+                };
+                code.push_str(&format!("{TAB}{directive} {}\n", value as i64));
+            },
+            None => code.push_str(&format!("{TAB}.zero {size}\n"))
+        }
+        Ok(code)
+    }
+}
+
+pub struct RiscvProgram {
+    static_variables: Vec<RiscvStaticVariable>,
+    function: TackyFunction,
+    // emit DWARF line info pointing into this source file
+    debug_source_file: Option<String>,
+    // keep the output linkable into shared objects
+    position_independent: bool,
+}
+impl RiscvProgram {
+    pub fn from_tacky_program(tacky_program: TackyProgram) -> RiscvProgram {
+        RiscvProgram {
+            static_variables: tacky_program.static_variables.into_iter().map(
+                RiscvStaticVariable::from_tacky_static_variable
+            ).collect(),
+            function: tacky_program.function,
+            debug_source_file: None,
+            position_independent: false,
+        }
+    }
+    pub fn with_debug_info(mut self, source_file: String) -> Self {
+        self.debug_source_file = Some(source_file);
+        self
+    }
+    pub fn with_position_independent_code(mut self) -> Self {
+        self.position_independent = true;
+        self
+    }
+    pub fn to_asm_code(&self) -> Result<String, AsmGenError> {
+        let mut buffer: Vec<u8> = vec![];
+        self.emit_asm_code(&mut buffer)?;
+        Ok(String::from_utf8(buffer).expect("assembly code is always valid UTF-8"))
+    }
+    pub fn emit_asm_code(&self, writer: &mut impl Write) -> Result<(), AsmGenError> {
+        // static variables switch sections, so they are emitted after the function
+        if let Some(source_file) = &self.debug_source_file {
+            writeln!(writer, "{TAB}.file {DEBUG_FILE_NUMBER} {:?}", source_file)?;
+        }
+        if self.position_independent {
+            // la loads addresses of globals from the GOT under this option
+            writeln!(writer, "{TAB}.option pic")?;
+        }
+        let emit_line_info = self.debug_source_file.is_some();
+        for line in RiscvFunctionBuilder::new(&self.function, emit_line_info).build()? {
+            writeln!(writer, "{line}")?;
+        }
+        for static_variable in &self.static_variables {
+            writer.write_all(static_variable.to_asm_code()?.as_bytes())?;
+        }
+        writeln!(writer, ".section .note.GNU-stack,\"\",@progbits")?;
+        Ok(())
+    }
+}
+
+struct RiscvFunctionBuilder<'a> {
+    function: &'a TackyFunction,
+    // offset below s0 of the stack slot of each local variable
+    slots: HashMap<u64, i64>,
+    frame_size: i64,
+    // emit .loc directives for annotated statements
+    emit_line_info: bool,
+    lines: Vec<String>,
+}
+impl<'a> RiscvFunctionBuilder<'a> {
+    fn new(function: &'a TackyFunction, emit_line_info: bool) -> Self {
+        let mut builder = RiscvFunctionBuilder {
+            function, slots: HashMap::new(), frame_size: 0, emit_line_info, lines: vec![]
+        };
+        builder.allocate_slots();
+        builder
+    }
+
+    fn allocate_slots(&mut self) {
+        // gives every local variable an aligned slot below the frame pointer
+        let mut offset: i64 = 0;
+        for instruction in &self.function.instructions {
+            let mut variables: Vec<&TackyVariable> = instruction.get_src_variables();
+            variables.extend(instruction.get_dst());
+            for variable in variables {
+                if variable.is_static || self.slots.contains_key(&variable.id) {
+                    continue;
+                }
+                let size = variable.var_type.size() as i64;
+                let alignment = variable.var_type.alignment().max(1) as i64;
+                offset = (offset + size + alignment - 1) / alignment * alignment;
+                self.slots.insert(variable.id, offset);
+            }
+        }
+        self.frame_size = (offset + 15) / 16 * 16;
+    }
+
+    fn emit(&mut self, line: String) {
+        self.lines.push(format!("{TAB}{line}"));
+    }
+
+    fn variable_address(&mut self, variable: &TackyVariable) -> String {
+        // memory operand for the variable, setting up t2 if needed
+        if variable.is_static {
+            self.emit(format!("la t2, {}", variable.name));
+            return "0(t2)".to_string();
+        }
+        let offset = -self.slots[&variable.id];
+        if fits_in_immediate(offset) {
+            format!("{offset}(s0)")
+        } else {
+            self.emit(format!("li t2, {offset}"));
+            self.emit("add t2, s0, t2".to_string());
+            "0(t2)".to_string()
+        }
+    }
+
+    fn load_address_of(&mut self, variable: &TackyVariable, register: &str) {
+        if variable.is_static {
+            self.emit(format!("la {register}, {}", variable.name));
+            return;
+        }
+        let offset = -self.slots[&variable.id];
+        if fits_in_immediate(offset) {
+            self.emit(format!("addi {register}, s0, {offset}"));
+        } else {
+            self.emit(format!("li {register}, {offset}"));
+            self.emit(format!("add {register}, s0, {register}"));
+        }
+    }
+
+    fn pointer_address(&mut self, pointer: &TackyValue, offset: u64) -> String {
+        // memory operand at offset bytes past the pointer's value, kept in t3
+        self.load_value(pointer, "t3");
+        let offset = offset as i64;
+        if fits_in_immediate(offset) {
+            format!("{offset}(t3)")
+        } else {
+            self.emit(format!("li t2, {offset}"));
+            self.emit("add t3, t3, t2".to_string());
+            "0(t3)".to_string()
+        }
+    }
+
+    fn load_pointer_address(&mut self, pointer: &TackyValue, offset: u64, register: &str) {
+        // puts the address offset bytes past the pointer's value in the register
+        self.load_value(pointer, register);
+        let offset = offset as i64;
+        if fits_in_immediate(offset) {
+            self.emit(format!("addi {register}, {register}, {offset}"));
+        } else {
+            self.emit(format!("li t2, {offset}"));
+            self.emit(format!("add {register}, {register}, t2"));
+        }
+    }
+
+    fn load_constant(&mut self, constant: &ASTConstant, register: &str) {
+        let bits = constant.to_bits();
+        if constant.const_type.is_double() {
+            // doubles are moved over from their bit pattern
+            self.emit(format!("li t2, {}", bits as i64));
+            self.emit(format!("fmv.d.x {register}, t2"));
+        } else {
+            let value = canonical_value(bits, &constant.const_type);
+            self.emit(format!("li {register}, {value}"));
+        }
+    }
+
+    fn load_value(&mut self, value: &TackyValue, register: &str) {
+        // integer values go in general purpose registers, doubles in f registers
+        match value {
+            TackyValue::Constant(constant) => self.load_constant(constant, register),
+            TackyValue::Var(variable) => {
+                let address = self.variable_address(variable);
+                let mnemonic = match variable.var_type.is_double() {
+                    true => "fld",
+                    false => load_mnemonic(&variable.var_type)
+                };
+                self.emit(format!("{mnemonic} {register}, {address}"));
+            }
+        }
+    }
+
+    fn store_variable(&mut self, register: &str, variable: &TackyVariable) {
+        let address = self.variable_address(variable);
+        let mnemonic = match variable.var_type.is_double() {
+            true => "fsd",
+            false => store_mnemonic(variable.var_type.size())
+        };
+        self.emit(format!("{mnemonic} {register}, {address}"));
+    }
+
+    fn copy_bytes(&mut self, source_base: &str, destination_base: &str, c_type: &CType) {
+        // copies a struct in chunks as large as its alignment allows
+        let (chunk_size, load, store) = match c_type.alignment() {
+            8 => (8, "ld", "sd"),
+            4 => (4, "lw", "sw"),
+            _ => (1, "lb", "sb")
+        };
+        let mut offset = 0;
+        while offset < c_type.size() {
+            self.emit(format!("{load} t0, {offset}({source_base})"));
+            self.emit(format!("{store} t0, {offset}({destination_base})"));
+            offset += chunk_size;
+        }
+    }
+
+    fn emit_return(&mut self, value: &TackyValue) {
+        match value.get_type().is_double() {
+            true => self.load_value(value, "fa0"),
+            false => self.load_value(value, "a0"),
+        }
+        self.emit("mv sp, s0".to_string());
+        self.emit("ld ra, 8(sp)".to_string());
+        self.emit("ld s0, 0(sp)".to_string());
+        self.emit(format!("addi sp, sp, {SAVED_REGISTERS_SIZE}"));
+        self.emit("ret".to_string());
+    }
+
+    fn emit_unary(
+        &mut self, operator: &SupportedUnaryOperators, src: &TackyValue, dst: &TackyVariable
+    ) -> Result<(), AsmGenError> {
+        let src_type = src.get_type();
+        if src_type.is_double() {
+            self.load_value(src, "f0");
+            match operator {
+                SupportedUnaryOperators::Subtract => {
+                    self.emit("fneg.d f0, f0".to_string());
+                    self.store_variable("f0", dst);
+                },
+                SupportedUnaryOperators::Not => {
+                    self.emit("fmv.d.x f1, zero".to_string());
+                    self.emit("feq.d t0, f0, f1".to_string());
+                    self.store_variable("t0", dst);
+                },
+                SupportedUnaryOperators::BitwiseNot => {
+                    return Err(AsmGenError::UnsupportedInstruction(
+                        "Cannot complement a double".to_string()
+                    ));
+                }
+            }
+            return Ok(());
+        }
+
+        self.load_value(src, "t0");
+        let suffix = word_suffix(&src_type);
+        match operator {
+            SupportedUnaryOperators::Subtract => self.emit(format!("neg{suffix} t0, t0")),
+            SupportedUnaryOperators::BitwiseNot => self.emit("not t0, t0".to_string()),
+            SupportedUnaryOperators::Not => self.emit("seqz t0, t0".to_string()),
+        }
+        self.store_variable("t0", dst);
+        Ok(())
+    }
+
+    fn emit_double_binary(&mut self, binary: &BinaryInstruction) -> Result<(), AsmGenError> {
+        self.load_value(&binary.left, "f0");
+        self.load_value(&binary.right, "f1");
+        let (mnemonic, is_comparison) = match binary.operator {
+            SupportedBinaryOperators::Add => ("fadd.d f0, f0, f1", false),
+            SupportedBinaryOperators::Subtract => ("fsub.d f0, f0, f1", false),
+            SupportedBinaryOperators::Multiply => ("fmul.d f0, f0, f1", false),
+            SupportedBinaryOperators::Divide => ("fdiv.d f0, f0, f1", false),
+            SupportedBinaryOperators::CheckEqual |
+            SupportedBinaryOperators::NotEqual => ("feq.d t0, f0, f1", true),
+            SupportedBinaryOperators::LessThan => ("flt.d t0, f0, f1", true),
+            SupportedBinaryOperators::LessOrEqual => ("fle.d t0, f0, f1", true),
+            SupportedBinaryOperators::GreaterThan => ("flt.d t0, f1, f0", true),
+            SupportedBinaryOperators::GreaterOrEqual => ("fle.d t0, f1, f0", true),
+            ref operator => return Err(AsmGenError::UnsupportedInstruction(
+                format!("Unsupported binary operator for doubles: {:?}", operator)
+            ))
+        };
+        self.emit(mnemonic.to_string());
+        if binary.operator == SupportedBinaryOperators::NotEqual {
+            self.emit("xori t0, t0, 1".to_string());
+        }
+        match is_comparison {
+            true => self.store_variable("t0", &binary.dst),
+            false => self.store_variable("f0", &binary.dst),
+        }
+        Ok(())
+    }
+
+    fn emit_binary(&mut self, binary: &BinaryInstruction) -> Result<(), AsmGenError> {
+        let operand_type = binary.left.get_type();
+        if operand_type.is_double() {
+            return self.emit_double_binary(binary);
+        }
+        self.load_value(&binary.left, "t0");
+        self.load_value(&binary.right, "t1");
+        let suffix = word_suffix(&operand_type);
+        let is_signed = operand_type.is_signed();
+        let unsigned = if is_signed { "" } else { "u" };
+
+        let code: Vec<String> = match binary.operator {
+            SupportedBinaryOperators::Add => vec![format!("add{suffix} t0, t0, t1")],
+            SupportedBinaryOperators::Subtract => vec![format!("sub{suffix} t0, t0, t1")],
+            SupportedBinaryOperators::Multiply => vec![format!("mul{suffix} t0, t0, t1")],
+            SupportedBinaryOperators::Divide => vec![format!("div{unsigned}{suffix} t0, t0, t1")],
+            SupportedBinaryOperators::Modulo => vec![format!("rem{unsigned}{suffix} t0, t0, t1")],
+            SupportedBinaryOperators::BitwiseAnd => vec!["and t0, t0, t1".to_string()],
+            SupportedBinaryOperators::BitwiseOr => vec!["or t0, t0, t1".to_string()],
+            SupportedBinaryOperators::BitwiseXor => vec!["xor t0, t0, t1".to_string()],
+            SupportedBinaryOperators::LeftShift => vec![format!("sll{suffix} t0, t0, t1")],
+            SupportedBinaryOperators::RightShift if is_signed => {
+                vec![format!("sra{suffix} t0, t0, t1")]
+            },
+            SupportedBinaryOperators::RightShift => vec![format!("srl{suffix} t0, t0, t1")],
+            SupportedBinaryOperators::CheckEqual => {
+                vec!["xor t0, t0, t1".to_string(), "seqz t0, t0".to_string()]
+            },
+            SupportedBinaryOperators::NotEqual => {
+                vec!["xor t0, t0, t1".to_string(), "snez t0, t0".to_string()]
+            },
+            SupportedBinaryOperators::LessThan => vec![format!("slt{unsigned} t0, t0, t1")],
+            SupportedBinaryOperators::GreaterThan => vec![format!("slt{unsigned} t0, t1, t0")],
+            SupportedBinaryOperators::LessOrEqual => vec![
+                format!("slt{unsigned} t0, t1, t0"), "xori t0, t0, 1".to_string()
+            ],
+            SupportedBinaryOperators::GreaterOrEqual => vec![
+                format!("slt{unsigned} t0, t0, t1"), "xori t0, t0, 1".to_string()
+            ],
+            ref operator => return Err(AsmGenError::UnsupportedInstruction(
+                format!("Unsupported binary operator: {:?}", operator)
+            ))
+        };
+        for line in code {
+            self.emit(line);
+        }
+        self.store_variable("t0", &binary.dst);
+        Ok(())
+    }
+
+    fn emit_convert(&mut self, convert: &ConvertInstruction) -> Result<(), AsmGenError> {
+        let src_type = convert.src.get_type();
+        let dst_type = &convert.dst.var_type;
+        match convert.kind {
+            ConversionKind::SignExtend | ConversionKind::Truncate => {
+                self.load_value(&convert.src, "t0");
+            },
+            ConversionKind::ZeroExtend => {
+                // the source is read as unsigned regardless of its type
+                self.load_value(&convert.src, "t0");
+                match src_type.size() {
+                    1 => self.emit("andi t0, t0, 255".to_string()),
+                    4 => {
+                        self.emit("slli t0, t0, 32".to_string());
+                        self.emit("srli t0, t0, 32".to_string());
+                    },
+                    _ => {}
+                }
+            },
+            ConversionKind::IntToDouble | ConversionKind::UIntToDouble => {
+                let unsigned = if convert.kind == ConversionKind::UIntToDouble { "u" } else { "" };
+                let width = if src_type.size() == 8 { "l" } else { "w" };
+                self.load_value(&convert.src, "t0");
+                self.emit(format!("fcvt.d.{width}{unsigned} f0, t0"));
+                self.store_variable("f0", &convert.dst);
+                return Ok(());
+            },
+            ConversionKind::DoubleToInt | ConversionKind::DoubleToUInt => {
+                let unsigned = if convert.kind == ConversionKind::DoubleToUInt { "u" } else { "" };
+                let width = if dst_type.size() == 8 { "l" } else { "w" };
+                self.load_value(&convert.src, "f0");
+                // C conversions round towards zero
+                self.emit(format!("fcvt.{width}{unsigned}.d t0, f0, rtz"));
+            },
+        }
+        self.store_variable("t0", &convert.dst);
+        Ok(())
+    }
+
+    fn emit_location(&mut self, pop_context: Option<&PoppedTokenContext>) {
+        let start_location = pop_context.and_then(|pop_context| pop_context.start_location);
+        if let (true, Some(location)) = (self.emit_line_info, start_location) {
+            self.emit(format!(
+                ".loc {DEBUG_FILE_NUMBER} {} {}", location.line, location.column
+            ));
+        }
+    }
+
+    fn emit_instruction(&mut self, instruction: &TackyInstruction) -> Result<(), AsmGenError> {
+        match instruction {
+            TackyInstruction::Return(value) => self.emit_return(value),
+            TackyInstruction::UnaryInstruction(unary) => {
+                self.emit_unary(&unary.operator, &unary.src, &unary.dst)?;
+            },
+            TackyInstruction::BinaryInstruction(binary) => {
+                // points debuggers at the operation within a longer statement
+                self.emit_location(binary.pop_context.as_ref());
+                self.emit_binary(binary)?;
+            },
+            TackyInstruction::CopyInstruction(copy) => {
+                if is_scalar(&copy.dst.var_type) {
+                    let register = if copy.dst.var_type.is_double() { "f0" } else { "t0" };
+                    self.load_value(&copy.src, register);
+                    self.store_variable(register, &copy.dst);
+                } else if let TackyValue::Var(src) = &copy.src {
+                    self.load_address_of(src, "t3");
+                    self.load_address_of(&copy.dst, "t4");
+                    self.copy_bytes("t3", "t4", &copy.dst.var_type);
+                }
+            },
+            TackyInstruction::ConvertInstruction(convert) => self.emit_convert(convert)?,
+            TackyInstruction::GetAddressInstruction(get_address) => {
+                self.load_address_of(&get_address.src, "t0");
+                self.store_variable("t0", &get_address.dst);
+            },
+            TackyInstruction::LoadInstruction(load) => {
+                let dst_type = &load.dst.var_type;
+                if is_scalar(dst_type) {
+                    let source = self.pointer_address(&load.src_pointer, load.offset);
+                    let (mnemonic, register) = match dst_type.is_double() {
+                        true => ("fld", "f0"),
+                        false => (load_mnemonic(dst_type), "t0")
+                    };
+                    self.emit(format!("{mnemonic} {register}, {source}"));
+                    self.store_variable(register, &load.dst);
+                } else {
+                    self.load_pointer_address(&load.src_pointer, load.offset, "t3");
+                    self.load_address_of(&load.dst, "t4");
+                    self.copy_bytes("t3", "t4", dst_type);
+                }
+            },
+            TackyInstruction::StoreInstruction(store) => {
+                let src_type = store.src.get_type();
+                if is_scalar(&src_type) {
+                    let (mnemonic, register) = match src_type.is_double() {
+                        true => ("fsd", "f0"),
+                        false => (store_mnemonic(src_type.size()), "t0")
+                    };
+                    self.load_value(&store.src, register);
+                    let destination = self.pointer_address(&store.dst_pointer, store.offset);
+                    self.emit(format!("{mnemonic} {register}, {destination}"));
+                } else if let TackyValue::Var(src) = &store.src {
+                    self.load_pointer_address(&store.dst_pointer, store.offset, "t4");
+                    self.load_address_of(src, "t3");
+                    self.copy_bytes("t3", "t4", &src_type);
+                }
+            },
+            TackyInstruction::JumpInstruction(jump) => {
+                self.emit(format!("j {LOCAL_LABEL_PREFIX}{}", jump.target.name));
+            },
+            TackyInstruction::JumpIfZeroInstruction(jump) => {
+                self.load_condition(&jump.condition);
+                self.emit(format!("beqz t0, {LOCAL_LABEL_PREFIX}{}", jump.target.name));
+            },
+            TackyInstruction::JumpIfNotZeroInstruction(jump) => {
+                self.load_condition(&jump.condition);
+                self.emit(format!("bnez t0, {LOCAL_LABEL_PREFIX}{}", jump.target.name));
+            },
+            TackyInstruction::LabelInstruction(label) => {
+                self.lines.push(format!("{LOCAL_LABEL_PREFIX}{}:", label.label.name));
+            },
+            TackyInstruction::AnnotationStartInstruction(annotation) => {
+                self.emit(format!("# begin: {}", annotation.description));
+                self.emit_location(annotation.pop_context.as_ref());
+            },
+            TackyInstruction::AnnotationEndInstruction(annotation) => {
+                self.emit(format!("# end: {}", annotation.description));
+            },
+        }
+        Ok(())
+    }
+
+    fn load_condition(&mut self, condition: &TackyValue) {
+        // loads a value into t0 that is zero exactly when the condition is
+        if condition.get_type().is_double() {
+            self.load_value(condition, "f0");
+            self.emit("fmv.d.x f1, zero".to_string());
+            self.emit("feq.d t0, f0, f1".to_string());
+            self.emit("xori t0, t0, 1".to_string());
+        } else {
+            self.load_value(condition, "t0");
+        }
+    }
+
+    fn build(mut self) -> Result<Vec<String>, AsmGenError> {
+        let name = self.function.name_to_string();
+        self.lines.push(format!("{TAB}.globl {name}"));
+        self.lines.push(format!("{name}:"));
+        // save the return address and frame pointer, then make room for the locals
+        self.emit(format!("addi sp, sp, -{SAVED_REGISTERS_SIZE}"));
+        self.emit("sd ra, 8(sp)".to_string());
+        self.emit("sd s0, 0(sp)".to_string());
+        self.emit("mv s0, sp".to_string());
+        if fits_in_immediate(self.frame_size) {
+            self.emit(format!("addi sp, sp, -{}", self.frame_size));
+        } else {
+            self.emit(format!("li t0, {}", self.frame_size));
+            self.emit("sub sp, sp, t0".to_string());
+        }
+
+        let function = self.function;
+        for instruction in &function.instructions {
+            self.emit_instruction(instruction)?;
+        }
+        Ok(self.lines)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::process::Command;
+    use super::*;
+    use crate::assembler::{build_from_asm_code, OutputKind, Target};
+    use crate::tacky::tacky_text::parse_tacky_text;
+
+    fn to_riscv_code(text: &str) -> String {
+        let program = parse_tacky_text(text).unwrap();
+        RiscvProgram::from_tacky_program(program).to_asm_code().unwrap()
+    }
+    fn to_riscv_lines(text: &str) -> Vec<String> {
+        to_riscv_code(text).lines().map(|line| line.trim().to_string()).collect()
+    }
+    fn has_command(command: &str) -> bool {
+        Command::new(command).arg("--version").output().is_ok()
+    }
+
+    #[test]
+    fn test_arithmetic_on_stack_slots() {
+        let lines = to_riscv_lines("
+            function main {
+                var %1: int
+                var %2: unsigned int
+                %1 = add (int) 2, (int) 4294967293
+                %2 = div (unsigned int) 4294967295, (unsigned int) 2
+                return %1
+            }
+        ");
+        assert_eq!(lines[..7], [
+            ".globl main", "main:", "addi sp, sp, -16",
+            "sd ra, 8(sp)", "sd s0, 0(sp)", "mv s0, sp", "addi sp, sp, -16"
+        ]);
+        assert_eq!(lines[7..11], ["li t0, 2", "li t1, -3", "addw t0, t0, t1", "sw t0, -4(s0)"]);
+        // unsigned values are held zero extended
+        assert_eq!(lines[11..15], [
+            "li t0, 4294967295", "li t1, 2", "divuw t0, t0, t1", "sw t0, -8(s0)"
+        ]);
+        assert_eq!(lines[15..], [
+            "lw a0, -4(s0)", "mv sp, s0", "ld ra, 8(sp)", "ld s0, 0(sp)",
+            "addi sp, sp, 16", "ret", ".section .note.GNU-stack,\"\",@progbits"
+        ]);
+    }
+
+    #[test]
+    fn test_jumps_and_statics() {
+        let lines = to_riscv_lines("
+            static @total: long = 7
+            static @count: unsigned char
+            function main {
+                var %3: int
+                %3 = copy (int) 3
+                label loop
+                %3 = sub %3, (int) 1
+                jump_if_not_zero %3, loop
+                @count = copy (unsigned char) 200
+                return %3
+            }
+        ");
+        assert!(lines.contains(&".Lloop:".to_string()));
+        assert!(lines.contains(&"bnez t0, .Lloop".to_string()));
+        let store_position = lines.iter().position(|line| line == "la t2, count").unwrap();
+        assert_eq!(lines[store_position - 1], "li t0, 200");
+        assert_eq!(lines[store_position + 1], "sb t0, 0(t2)");
+
+        let statics_position = lines.iter().position(|line| line == ".globl total").unwrap();
+        assert_eq!(lines[statics_position..statics_position + 9], [
+            ".globl total", ".data", ".balign 8", "total:", ".dword 7",
+            ".globl count", ".bss", ".balign 1", "count:"
+        ]);
+    }
+
+    #[test]
+    fn test_run_with_qemu() {
+        // needs the riscv64 cross compiler and user mode qemu
+        if !has_command(Target::Riscv64.gcc_command()) || !has_command("qemu-riscv64") {
+            return;
+        }
+        let code = to_riscv_code("
+            function main {
+                var %1: int
+                var %2: double
+                var %3: int
+                %1 = mul (int) 6, (int) 7
+                %2 = int_to_double %1
+                %2 = div %2, (double) 2.0
+                %3 = double_to_int %2
+                return %3
+            }
+        ");
+        let temp_dir = std::env::temp_dir();
+        let exe_path = temp_dir.join("ca_compiler_riscv_test");
+        build_from_asm_code(
+            &Target::Riscv64, &code, &temp_dir.join("ca_compiler_riscv_test.s"),
+            &OutputKind::Executable, &exe_path
+        ).unwrap();
+        let status = Command::new("qemu-riscv64").arg(Path::new(&exe_path)).status().unwrap();
+        std::fs::remove_file(&exe_path).unwrap();
+        assert_eq!(status.code(), Some(21));
+    }
+}
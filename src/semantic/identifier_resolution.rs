@@ -0,0 +1,399 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::fmt::{Display, Formatter};
+use crate::parser::ast_visitor::{
+    fold_declaration_children, fold_expression_children, fold_function_children,
+    fold_program_children, Fold
+};
+use crate::lexer::tokens::SourceLocation;
+use crate::parser::c_types::CType;
+use crate::parser::parser_helpers::PoppedTokenContext;
+use crate::parser::parse::{
+    ASTFunction, ASTProgram, Declaration, Expression, ExpressionVariant, Identifier
+};
+
+/*
+Identifier resolution pass that runs between parsing and tacky generation.
+Every user declared local variable is renamed to a unique name
+(e.g. x -> x.0) so that later stages don't have to deal with shadowing.
+File scope variables and functions keep their names since they are
+visible to the linker. Function prototypes are checked against each other
+and the definition, but get no code of their own. File scope variables
+can be declared more than once (e.g. int g = 2; int g;) as long as the
+types agree and only one declaration has an initializer, in which case
+the declarations are merged into the first one.
+The pass keeps going after an error, so that every error is reported
+*/
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum SemanticErrorVariants {
+    UndeclaredVariable(String),
+    DuplicateDeclaration(String),
+    // file scope declarations of the same variable with different types
+    ConflictingVariableDeclaration(String),
+    // file scope declarations of the same variable that both have an initializer
+    VariableRedefinition(String),
+    // declarations of the same function with different return types
+    ConflictingFunctionDeclaration(String),
+    // functions can only be declared or defined until calls are supported
+    FunctionUsedAsVariable(String),
+    // assignment to (or address of) an expression that doesn't designate an object
+    InvalidLvalue(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SemanticError {
+    pub(crate) variant: SemanticErrorVariants,
+    // location of the name or expression that the error is about, if known
+    pub(crate) location: Option<SourceLocation>,
+}
+impl SemanticError {
+    pub fn new(variant: SemanticErrorVariants, location: Option<SourceLocation>) -> Self {
+        SemanticError { variant, location }
+    }
+    pub fn location(&self) -> Option<SourceLocation> {
+        self.location
+    }
+    pub fn message(&self) -> String {
+        match &self.variant {
+            SemanticErrorVariants::UndeclaredVariable(name) => {
+                format!("Use of undeclared variable {}", name)
+            },
+            SemanticErrorVariants::DuplicateDeclaration(name) => {
+                format!("Duplicate declaration of variable {}", name)
+            },
+            SemanticErrorVariants::ConflictingVariableDeclaration(name) => {
+                format!("Conflicting types for variable {}", name)
+            },
+            SemanticErrorVariants::VariableRedefinition(name) => {
+                format!("Redefinition of variable {}", name)
+            },
+            SemanticErrorVariants::ConflictingFunctionDeclaration(name) => {
+                format!("Conflicting declarations of function {}", name)
+            },
+            SemanticErrorVariants::FunctionUsedAsVariable(name) => {
+                format!("Function {} used as a variable", name)
+            },
+            SemanticErrorVariants::InvalidLvalue(expression) => {
+                format!("Expression {} is not an lvalue", expression)
+            }
+        }
+    }
+}
+impl Display for SemanticError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.location {
+            Some(location) => write!(f, "SemanticError at line {}: {}", location, self.message()),
+            None => write!(f, "SemanticError: {}", self.message())
+        }
+    }
+}
+impl std::error::Error for SemanticError {}
+
+fn expression_location(expression: &Expression) -> Option<SourceLocation> {
+    expression.pop_context.as_ref().and_then(|pop_context| pop_context.start_location)
+}
+
+// maps the names declared in a scope to their resolved names
+type SymbolTable = HashMap<String, String>;
+
+pub struct IdentifierResolver {
+    // innermost scope last
+    scopes: Vec<SymbolTable>,
+    // return types of the functions declared so far, for checking calls
+    functions: HashMap<String, CType>,
+    next_unique_id: u64,
+    // every error found so far, resolution carries on past them
+    errors: Vec<SemanticError>,
+}
+impl IdentifierResolver {
+    pub fn new() -> IdentifierResolver {
+        IdentifierResolver {
+            scopes: vec![SymbolTable::new()], functions: HashMap::new(), next_unique_id: 0,
+            errors: vec![]
+        }
+    }
+
+    fn report(&mut self, variant: SemanticErrorVariants, location: Option<SourceLocation>) {
+        self.errors.push(SemanticError::new(variant, location));
+    }
+    fn declare(
+        &mut self, name: &str, is_file_scope: bool, location: Option<SourceLocation>
+    ) -> String {
+        // a duplicate is reported, then shadows the earlier declaration to carry on
+        if self.scopes.last().unwrap().contains_key(name) {
+            self.report(SemanticErrorVariants::DuplicateDeclaration(name.to_string()), location);
+        }
+        let unique_name = if is_file_scope {
+            name.to_string()
+        } else {
+            let unique_name = format!("{}.{}", name, self.next_unique_id);
+            self.next_unique_id += 1;
+            unique_name
+        };
+        self.scopes.last_mut().unwrap().insert(name.to_string(), unique_name.clone());
+        unique_name
+    }
+    fn declare_function(
+        &mut self, name: &str, return_type: &CType, pop_context: &Option<PoppedTokenContext>
+    ) {
+        // a function can be declared any number of times, as long as the types agree
+        let location = pop_context.as_ref().and_then(|pop_context| pop_context.start_location);
+        match self.functions.get(name) {
+            Some(declared_type) if declared_type != return_type => self.report(
+                SemanticErrorVariants::ConflictingFunctionDeclaration(name.to_string()), location
+            ),
+            Some(_) => {},
+            None => {
+                self.declare(name, true, location);
+                self.functions.insert(name.to_string(), return_type.clone());
+            }
+        }
+    }
+    fn lookup(&self, name: &str) -> Option<String> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name).cloned())
+    }
+    fn merge_file_scope_declarations(
+        &mut self, declarations: Vec<Declaration>
+    ) -> Vec<Declaration> {
+        // later declarations of a variable only contribute their initializer to the first one
+        let mut merged: Vec<Declaration> = vec![];
+        let mut indices: HashMap<String, usize> = HashMap::new();
+        for declaration in declarations {
+            let name = declaration.name.name_to_string();
+            let Some(&index) = indices.get(&name) else {
+                indices.insert(name, merged.len());
+                merged.push(declaration);
+                continue;
+            };
+            let first = &mut merged[index];
+            if first.var_type != declaration.var_type {
+                self.report(
+                    SemanticErrorVariants::ConflictingVariableDeclaration(name),
+                    declaration.name_location
+                );
+            } else if first.initializer.is_some() && declaration.initializer.is_some() {
+                self.report(
+                    SemanticErrorVariants::VariableRedefinition(name), declaration.name_location
+                );
+            } else if declaration.initializer.is_some() {
+                first.initializer = declaration.initializer;
+            }
+        }
+        merged
+    }
+
+    pub fn resolve_program(
+        &mut self, program: ASTProgram
+    ) -> Result<ASTProgram, Vec<SemanticError>> {
+        let program = self.fold_program(program).unwrap_or_else(|err| match err {});
+        if self.errors.is_empty() {
+            return Ok(program);
+        }
+        // in the order they appear in the source, with errors that have no location last
+        let mut errors = std::mem::take(&mut self.errors);
+        errors.sort_by_key(|err| err.location.map_or(
+            (usize::MAX, usize::MAX), |location| (location.line, location.column)
+        ));
+        Err(errors)
+    }
+}
+impl Fold for IdentifierResolver {
+    // errors are collected instead, see resolve_program
+    type Error = Infallible;
+
+    fn fold_program(&mut self, mut program: ASTProgram) -> Result<ASTProgram, Infallible> {
+        for function_declaration in &program.function_declarations {
+            self.declare_function(
+                &function_declaration.name.name, &function_declaration.return_type,
+                &function_declaration.pop_context
+            );
+        }
+        self.declare_function(
+            &program.function.name.name, &program.function.return_type,
+            &program.function.pop_context
+        );
+        program.global_declarations =
+            self.merge_file_scope_declarations(program.global_declarations);
+        fold_program_children(self, program)
+    }
+    fn fold_function(&mut self, function: ASTFunction) -> Result<ASTFunction, Infallible> {
+        self.scopes.push(SymbolTable::new());
+        let function = fold_function_children(self, function)?;
+        self.scopes.pop();
+        Ok(function)
+    }
+    fn fold_declaration(
+        &mut self, mut declaration: Declaration
+    ) -> Result<Declaration, Infallible> {
+        // the declared variable is already in scope within its own initializer
+        let is_file_scope = self.scopes.len() == 1;
+        let unique_name = self.declare(
+            &declaration.name.name, is_file_scope, declaration.name_location
+        );
+        declaration.name = Identifier::new(unique_name);
+        fold_declaration_children(self, declaration)
+    }
+    fn fold_expression(&mut self, mut expression: Expression) -> Result<Expression, Infallible> {
+        // member names belong to the struct, not the symbol table
+        let location = expression_location(&expression);
+        if let ExpressionVariant::Variable(identifier) = &mut expression.expr_item {
+            match self.lookup(&identifier.name) {
+                // locals are renamed, so an unchanged name can only refer to a file scope symbol
+                Some(unique_name) if self.functions.contains_key(&unique_name) => self.report(
+                    SemanticErrorVariants::FunctionUsedAsVariable(unique_name), location
+                ),
+                Some(unique_name) => *identifier = Identifier::new(unique_name),
+                None => self.report(
+                    SemanticErrorVariants::UndeclaredVariable(identifier.name.clone()), location
+                )
+            }
+        }
+        fold_expression_children(self, expression)
+    }
+}
+impl Default for IdentifierResolver {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn resolve_identifiers(program: ASTProgram) -> Result<ASTProgram, SemanticError> {
+    resolve_identifiers_with_diagnostics(program).map_err(|mut errors| errors.remove(0))
+}
+
+pub fn resolve_identifiers_with_diagnostics(
+    program: ASTProgram
+) -> Result<ASTProgram, Vec<SemanticError>> {
+    // every error found in the program (in source order) if it is invalid
+    IdentifierResolver::new().resolve_program(program)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::lexer::Lexer;
+    use crate::parser::parse::{parse, BlockItem, StatementVariant};
+    use crate::parser::parser_helpers::TokenStack;
+
+    fn resolve_source(source: &str) -> Result<ASTProgram, SemanticError> {
+        let tokens = Lexer::new().tokenize(source).unwrap();
+        let mut token_stack = TokenStack::new_from_vec(tokens);
+        resolve_identifiers(parse(&mut token_stack).unwrap())
+    }
+
+    #[test]
+    fn test_locals_are_renamed() {
+        let program = resolve_source(
+            "int total = 1; int main(void) { int x = total; long total = x; return total; }"
+        ).unwrap();
+        assert_eq!(program.global_declarations[0].name.name, "total");
+        let names: Vec<String> = program.function.body.iter().filter_map(|item| match item {
+            BlockItem::Declaration(declaration) => Some(declaration.name.name.clone()),
+            _ => None
+        }).collect();
+        assert_eq!(names, vec!["x.0", "total.1"]);
+
+        // the local total shadows the file scope one after its declaration
+        match &program.function.body[2] {
+            BlockItem::Statement(statement) => match &statement.variant {
+                StatementVariant::Return(expression) => assert!(matches!(
+                    &expression.expr_item,
+                    ExpressionVariant::Variable(identifier) if identifier.name == "total.1"
+                )),
+                other => panic!("Expected return statement, got {:?}", other)
+            },
+            other => panic!("Expected statement, got {:?}", other)
+        }
+    }
+
+    fn error_variant(source: &str) -> Option<SemanticErrorVariants> {
+        resolve_source(source).err().map(|err| err.variant)
+    }
+
+    #[test]
+    fn test_undeclared_variable() {
+        let variant = error_variant("int main(void) { int a = b; return a; }");
+        assert_eq!(variant, Some(SemanticErrorVariants::UndeclaredVariable("b".to_string())));
+        let variant = error_variant("int main(void) { return a; int a; }");
+        assert_eq!(variant, Some(SemanticErrorVariants::UndeclaredVariable("a".to_string())));
+    }
+
+    #[test]
+    fn test_duplicate_declaration() {
+        let variant = error_variant("int main(void) { int a; long a; return 0; }");
+        assert_eq!(variant, Some(SemanticErrorVariants::DuplicateDeclaration("a".to_string())));
+    }
+
+    #[test]
+    fn test_file_scope_redeclarations() {
+        let program = resolve_source(
+            "int g; int g = 2; int g; long total; int main(void) { return g; }"
+        ).unwrap();
+        let names: Vec<String> = program.global_declarations.iter()
+            .map(|declaration| declaration.name.name.clone()).collect();
+        assert_eq!(names, vec!["g", "total"]);
+        assert!(program.global_declarations[0].initializer.is_some());
+
+        let variant = error_variant("int g = 2; int g = 3; int main(void) { return g; }");
+        assert_eq!(variant, Some(SemanticErrorVariants::VariableRedefinition("g".to_string())));
+        let variant = error_variant("int g; long g; int main(void) { return g; }");
+        assert_eq!(
+            variant, Some(SemanticErrorVariants::ConflictingVariableDeclaration("g".to_string()))
+        );
+    }
+
+    #[test]
+    fn test_error_locations() {
+        let err = resolve_source("int main(void) {\n    int a = 1;\n    return a + b;\n}")
+            .err().unwrap();
+        assert_eq!(err.location(), Some(SourceLocation::new(3, 16)));
+        assert_eq!(err.to_string(), "SemanticError at line 3:16: Use of undeclared variable b");
+
+        let err = resolve_source(
+            "int g = 1;\nint g = 2;\nint main(void) { int a; int a; return g; }"
+        ).err().unwrap();
+        assert_eq!(err.location(), Some(SourceLocation::new(2, 5)));
+    }
+
+    #[test]
+    fn test_every_error_is_reported() {
+        let tokens = Lexer::new().tokenize(
+            "int main(void) {\n    int a = b;\n    int a;\n    return c;\n}"
+        ).unwrap();
+        let program = parse(&mut TokenStack::new_from_vec(tokens)).unwrap();
+        let errors = resolve_identifiers_with_diagnostics(program).err().unwrap();
+        let messages: Vec<String> = errors.iter().map(|err| err.to_string()).collect();
+        assert_eq!(messages, vec![
+            "SemanticError at line 2:13: Use of undeclared variable b",
+            "SemanticError at line 3:9: Duplicate declaration of variable a",
+            "SemanticError at line 4:12: Use of undeclared variable c",
+        ]);
+    }
+
+    #[test]
+    fn test_function_declarations() {
+        let program = resolve_source(
+            "int helper(void); long total; int helper(void); int main(void);
+            int main(void) { int helper = 2; return helper; }"
+        ).unwrap();
+        assert_eq!(program.function_declarations.len(), 3);
+
+        let variant = error_variant("int main(void); long main(void) { return 0; }");
+        assert_eq!(
+            variant,
+            Some(SemanticErrorVariants::ConflictingFunctionDeclaration("main".to_string()))
+        );
+        let variant = error_variant("long helper; int helper(void); int main(void) { return 0; }");
+        assert_eq!(
+            variant, Some(SemanticErrorVariants::DuplicateDeclaration("helper".to_string()))
+        );
+        let variant = error_variant("int helper(void); int main(void) { return helper; }");
+        assert_eq!(
+            variant, Some(SemanticErrorVariants::FunctionUsedAsVariable("helper".to_string()))
+        );
+
+        let err = resolve_source("int main(void);\nlong main(void) { return 0; }").err().unwrap();
+        assert_eq!(err.location(), Some(SourceLocation::new(2, 1)));
+    }
+}
@@ -0,0 +1,90 @@
+use crate::parser::ast_printer::format_c_expression;
+use crate::parser::ast_visitor::{walk_expression, Visit};
+use crate::parser::parse::{
+    ASTProgram, Expression, ExpressionVariant, SupportedBinaryOperators
+};
+use crate::semantic::identifier_resolution::{SemanticError, SemanticErrorVariants};
+
+/*
+Checks that every expression that is assigned to or has its address
+taken designates an object, i.e. is an lvalue. Runs after identifier
+resolution, so that undeclared variables are reported first
+*/
+
+pub fn is_lvalue(expression: &Expression) -> bool {
+    match &expression.expr_item {
+        ExpressionVariant::Variable(_) |
+        ExpressionVariant::Dereference(_) |
+        ExpressionVariant::MemberAccess(_, _) |
+        ExpressionVariant::PointerMemberAccess(_, _) => true,
+        ExpressionVariant::ParensWrapped(inner) => is_lvalue(inner),
+        _ => false
+    }
+}
+
+struct LvalueValidator {
+    // only the first invalid lvalue is reported
+    error: Option<SemanticError>,
+}
+impl LvalueValidator {
+    fn check_lvalue(&mut self, expression: &Expression) {
+        if self.error.is_none() && !is_lvalue(expression) {
+            let location = expression.pop_context.as_ref()
+                .and_then(|pop_context| pop_context.start_location);
+            self.error = Some(SemanticError::new(
+                SemanticErrorVariants::InvalidLvalue(format_c_expression(expression)), location
+            ));
+        }
+    }
+}
+impl Visit for LvalueValidator {
+    fn visit_expression(&mut self, expression: &Expression) {
+        match &expression.expr_item {
+            ExpressionVariant::BinaryOperation(
+                SupportedBinaryOperators::AssignEqual, left, _
+            ) => self.check_lvalue(left),
+            ExpressionVariant::AddressOf(inner) => self.check_lvalue(inner),
+            _ => {}
+        }
+        walk_expression(self, expression)
+    }
+}
+
+pub fn validate_lvalues(program: &ASTProgram) -> Result<(), SemanticError> {
+    let mut validator = LvalueValidator { error: None };
+    validator.visit_program(program);
+    match validator.error {
+        Some(error) => Err(error),
+        None => Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::parse::parse_from_str;
+
+    fn validate_source(source: &str) -> Result<(), SemanticError> {
+        validate_lvalues(&parse_from_str(source).unwrap())
+    }
+
+    #[test]
+    fn test_valid_lvalues() {
+        assert!(validate_source(
+            "int main(void) { int a = 1; int *p = &a; (a) = 2; *p = a = 3; return a; }"
+        ).is_ok());
+    }
+
+    #[test]
+    fn test_invalid_lvalues() {
+        let err = validate_source("int main(void) { int a = 1; 2 = a; return a; }");
+        assert_eq!(err.unwrap_err().message(), "Expression 2 is not an lvalue");
+        let err = validate_source("int main(void) { int a = 1; a + 1 = 3; return a; }");
+        assert_eq!(
+            err.unwrap_err().to_string(),
+            "SemanticError at line 1:29: Expression a + 1 is not an lvalue"
+        );
+        let err = validate_source("int main(void) { int a = 1; int *p = &(a = 2); return a; }");
+        assert_eq!(err.unwrap_err().message(), "Expression (a = 2) is not an lvalue");
+    }
+}
@@ -0,0 +1,2 @@
+pub mod identifier_resolution;
+pub mod lvalue_validation;
@@ -1 +1,3 @@
-pub(crate) mod tacky_symbols;
\ No newline at end of file
+pub mod tacky_symbols;
+pub mod tacky_text;
+pub mod tacky_verifier;pub mod py_tacky;
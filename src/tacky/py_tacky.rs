@@ -0,0 +1,160 @@
+use std::collections::HashMap;
+use pyo3::{pyclass, pymethods, Bound, PyResult};
+use pyo3::types::PyType;
+use pyo3_stub_gen::derive::{gen_stub_pyclass, gen_stub_pymethods};
+use crate::py_errors::compiler_error_to_py;
+use crate::tacky::tacky_symbols::{
+    tacky_gen_from_filepath, tacky_gen_from_str, PrintableTacky, TackyInstruction, TackyProgram
+};
+use crate::tacky::tacky_text::{
+    binary_operator_name, conversion_name, emit_instruction, emit_tacky_text, emit_value,
+    emit_variable, unary_operator_name
+};
+
+/*
+The opcode and operands of an instruction, written as in the textual
+tacky syntax (see tacky_text), along with the whole instruction as "text"
+*/
+pub fn instruction_fields(instruction: &TackyInstruction) -> Vec<(&'static str, String)> {
+    let mut fields = match instruction {
+        TackyInstruction::UnaryInstruction(unary) => vec![
+            ("opcode", unary_operator_name(&unary.operator).to_string()),
+            ("src", emit_value(&unary.src)),
+            ("dst", emit_variable(&unary.dst)),
+        ],
+        TackyInstruction::BinaryInstruction(binary) => vec![
+            ("opcode", binary_operator_name(&binary.operator).to_string()),
+            ("left", emit_value(&binary.left)),
+            ("right", emit_value(&binary.right)),
+            ("dst", emit_variable(&binary.dst)),
+        ],
+        TackyInstruction::CopyInstruction(copy) => vec![
+            ("opcode", "copy".to_string()),
+            ("src", emit_value(&copy.src)),
+            ("dst", emit_variable(&copy.dst)),
+        ],
+        TackyInstruction::ConvertInstruction(convert) => vec![
+            ("opcode", conversion_name(&convert.kind).to_string()),
+            ("src", emit_value(&convert.src)),
+            ("dst", emit_variable(&convert.dst)),
+        ],
+        TackyInstruction::GetAddressInstruction(get_address) => vec![
+            ("opcode", "address_of".to_string()),
+            ("src", emit_variable(&get_address.src)),
+            ("dst", emit_variable(&get_address.dst)),
+        ],
+        TackyInstruction::LoadInstruction(load) => vec![
+            ("opcode", "load".to_string()),
+            ("src_pointer", emit_value(&load.src_pointer)),
+            ("offset", load.offset.to_string()),
+            ("dst", emit_variable(&load.dst)),
+        ],
+        TackyInstruction::StoreInstruction(store) => vec![
+            ("opcode", "store".to_string()),
+            ("src", emit_value(&store.src)),
+            ("dst_pointer", emit_value(&store.dst_pointer)),
+            ("offset", store.offset.to_string()),
+        ],
+        TackyInstruction::JumpInstruction(jump) => vec![
+            ("opcode", "jump".to_string()),
+            ("target", jump.target.name.clone()),
+        ],
+        TackyInstruction::JumpIfZeroInstruction(jump) => vec![
+            ("opcode", "jump_if_zero".to_string()),
+            ("condition", emit_value(&jump.condition)),
+            ("target", jump.target.name.clone()),
+        ],
+        TackyInstruction::JumpIfNotZeroInstruction(jump) => vec![
+            ("opcode", "jump_if_not_zero".to_string()),
+            ("condition", emit_value(&jump.condition)),
+            ("target", jump.target.name.clone()),
+        ],
+        TackyInstruction::LabelInstruction(label) => vec![
+            ("opcode", "label".to_string()),
+            ("label", label.label.name.clone()),
+        ],
+        TackyInstruction::AnnotationStartInstruction(annotation) => vec![
+            ("opcode", "annotation_start".to_string()),
+            ("description", annotation.description.clone()),
+        ],
+        TackyInstruction::AnnotationEndInstruction(annotation) => vec![
+            ("opcode", "annotation_end".to_string()),
+            ("description", annotation.description.clone()),
+        ],
+        TackyInstruction::Return(value) => vec![
+            ("opcode", "return".to_string()),
+            ("value", emit_value(value)),
+        ],
+    };
+    fields.push(("text", emit_instruction(instruction)));
+    fields
+}
+
+#[gen_stub_pyclass]
+#[pyclass(module = "py_ca_compiler.compiler")]
+pub struct PyTacky {
+    program: TackyProgram
+}
+#[gen_stub_pymethods]
+#[pymethods]
+impl PyTacky {
+    #[classmethod]
+    pub fn compile_from_source(
+        _cls: &Bound<'_, PyType>, source_filepath: String
+    ) -> PyResult<Self> {
+        let program = tacky_gen_from_filepath(&source_filepath, false)
+            .map_err(compiler_error_to_py)?;
+        Ok(Self { program })
+    }
+    #[classmethod]
+    pub fn compile_from_string(_cls: &Bound<'_, PyType>, source: String) -> PyResult<Self> {
+        let program = tacky_gen_from_str(&source).map_err(compiler_error_to_py)?;
+        Ok(Self { program })
+    }
+
+    pub fn print_tacky_code(&self) -> PyResult<String> {
+        Ok(self.program.print_tacky_code(0))
+    }
+    // in the textual syntax that tacky_text can parse back in
+    pub fn to_text(&self) -> PyResult<String> {
+        Ok(emit_tacky_text(&self.program))
+    }
+
+    pub fn function_name(&self) -> PyResult<String> {
+        Ok(self.program.function.name.name_to_string())
+    }
+    // one dict of instruction_fields per instruction of the function
+    pub fn instructions(&self) -> PyResult<Vec<HashMap<String, String>>> {
+        Ok(self.program.function.instructions.iter()
+            .map(|instruction| {
+                instruction_fields(instruction).into_iter()
+                    .map(|(key, value)| (key.to_string(), value))
+                    .collect()
+            })
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_instruction_fields() {
+        let program = tacky_gen_from_str(
+            "int main(void) { int x = 2; return ~x + 3; }"
+        ).unwrap();
+        let fields: Vec<Vec<(&str, String)>> = program.function.instructions.iter()
+            .map(instruction_fields)
+            .filter(|fields| !fields[0].1.starts_with("annotation"))
+            .collect();
+
+        let opcodes: Vec<&str> = fields.iter().map(|fields| fields[0].1.as_str()).collect();
+        // followed by the return 0 added to the end of every function
+        assert_eq!(opcodes, ["copy", "complement", "add", "return", "return"]);
+        let binary = &fields[2];
+        assert_eq!(binary[1].0, "left");
+        assert_eq!(binary[2], ("right", "(int) 3".to_string()));
+        assert_eq!(binary.last().unwrap().0, "text");
+    }
+}
@@ -1,11 +1,87 @@
-use std::fmt::format;
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
 use std::hash::{Hash, Hasher};
+use std::io::Read;
 use crate::asm_gen::asm_symbols::TAB;
+use crate::lexer::tokens::SourceLocation;
+use crate::parser::ast_printer::format_c_expression;
 use crate::parser::parse::{
     Identifier, ASTProgram, SupportedUnaryOperators, ASTFunction, ExpressionVariant,
-    ASTConstant, parse_from_filepath, SupportedBinaryOperators
+    ASTConstant, parse_from_filepath, parse_from_reader, parse_from_str,
+    SupportedBinaryOperators, BlockItem,
+    StatementVariant, Declaration, Expression
 };
-use crate::parser::parser_helpers::{ParseError, PoppedTokenContext};
+use crate::parser::c_types::{CType, StructTable};
+use crate::parser::parser_helpers::PoppedTokenContext;
+use crate::errors::CompilerError;
+use crate::semantic::identifier_resolution::resolve_identifiers;
+use crate::semantic::lvalue_validation::validate_lvalues;
+
+/*
+Error for programs that get through the semantic passes, but that have
+operands of the wrong type or that use features tacky generation
+doesn't support yet (e.g. dereferencing an int). The location is the
+start of the innermost expression or declaration the error was found in
+*/
+#[derive(Clone, Debug, PartialEq)]
+pub enum TackyErrorVariants {
+    // operands, casts or declarations whose types don't fit together
+    TypeError(String),
+    // valid C that can't be turned into tacky yet
+    Unsupported(String),
+    // names that the semantic passes let through without a declaration
+    UndeclaredVariable(String),
+    // file scope initializers that can't be evaluated at compile time
+    InvalidInitializer(String),
+    // file scope variables that are defined twice
+    Redefinition(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TackyError {
+    pub(crate) variant: TackyErrorVariants,
+    pub(crate) location: Option<SourceLocation>,
+}
+impl TackyError {
+    pub fn type_error(message: String) -> Self {
+        TackyError { variant: TackyErrorVariants::TypeError(message), location: None }
+    }
+    pub fn unsupported(message: String) -> Self {
+        TackyError { variant: TackyErrorVariants::Unsupported(message), location: None }
+    }
+    pub fn location(&self) -> Option<SourceLocation> {
+        self.location
+    }
+    pub fn message(&self) -> String {
+        match &self.variant {
+            TackyErrorVariants::TypeError(message) => message.clone(),
+            TackyErrorVariants::Unsupported(message) => message.clone(),
+            TackyErrorVariants::UndeclaredVariable(name) => {
+                format!("Use of undeclared variable {}", name)
+            },
+            TackyErrorVariants::InvalidInitializer(message) => message.clone(),
+            TackyErrorVariants::Redefinition(name) => {
+                format!("Variable {} is already defined", name)
+            },
+        }
+    }
+    fn located_at(mut self, pop_context: &Option<PoppedTokenContext>) -> Self {
+        // errors keep the location of the innermost expression they were found in
+        if self.location.is_none() {
+            self.location = pop_context.as_ref().and_then(|context| context.start_location);
+        }
+        self
+    }
+}
+impl Display for TackyError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self.location {
+            Some(location) => write!(f, "TackyError at line {}: {}", location, self.message()),
+            None => write!(f, "TackyError: {}", self.message())
+        }
+    }
+}
+impl std::error::Error for TackyError {}
 
 pub trait ToTackyInstruction: Sized {
     fn to_tacky_instruction(&self) -> TackyInstruction;
@@ -18,10 +94,41 @@ pub trait PrintableTacky {
 pub struct TackyVariable {
     pub id: u64,
     pub name: String,
+    pub var_type: CType,
+    // file scope variables live in the data section instead of the stack
+    pub is_static: bool,
 }
 impl TackyVariable {
     pub fn new(id: u64) -> TackyVariable {
-        TackyVariable { id, name: "".to_string() }
+        Self::new_typed(id, CType::Int)
+    }
+    pub fn new_typed(id: u64, var_type: CType) -> TackyVariable {
+        TackyVariable { id, name: "".to_string(), var_type, is_static: false }
+    }
+    pub fn new_named(id: u64, name: String, var_type: CType) -> TackyVariable {
+        // variable declared in the source code (as opposed to a temporary)
+        TackyVariable { id, name, var_type, is_static: false }
+    }
+    pub fn new_static(id: u64, name: String, var_type: CType) -> TackyVariable {
+        TackyVariable { id, name, var_type, is_static: true }
+    }
+}
+
+// maps the names of declared variables to their tacky variables
+pub type TackyVariableMap = HashMap<String, TackyVariable>;
+
+#[derive(Clone, Debug, Default)]
+pub struct TackyScope {
+    // declarations visible while unrolling expressions
+    pub variables: TackyVariableMap,
+    pub structs: StructTable,
+}
+impl TackyScope {
+    pub fn new(structs: StructTable) -> TackyScope {
+        TackyScope { variables: HashMap::new(), structs }
+    }
+    fn resolve_type(&self, c_type: &CType) -> Result<CType, TackyError> {
+        self.structs.resolve(c_type).map_err(TackyError::type_error)
     }
 }
 impl Eq for TackyVariable {}
@@ -38,7 +145,10 @@ impl Hash for TackyVariable {
 impl PrintableTacky for TackyVariable {
     fn print_tacky_code(&self, depth: u64) -> String {
         let indent = TAB.repeat(depth as usize);
-        format!("{}TackyVariable: id={}, name={}\n", indent, self.id, self.name)
+        format!(
+            "{}TackyVariable: id={}, name={}, type={}, static={}\n",
+            indent, self.id, self.name, self.var_type, self.is_static
+        )
     }
 }
 
@@ -80,16 +190,25 @@ impl TackyValue {
             TackyValue::Var(v) => Some(v.id)
         }
     }
+    pub fn get_type(&self) -> CType {
+        match self {
+            TackyValue::Constant(c) => c.const_type.clone(),
+            TackyValue::Var(v) => v.var_type.clone()
+        }
+    }
 }
 impl PrintableTacky for TackyValue {
     fn print_tacky_code(&self, depth: u64) -> String {
         let indent = TAB.repeat(depth as usize);
         match self {
             TackyValue::Constant(c) => {
-                format!("{}Constant: {}\n", indent, c.value)
+                format!("{}Constant: {} ({})\n", indent, c.value, c.const_type)
             },
             TackyValue::Var(v) => {
-                format!("{}Var: id={}, name={}\n", indent, v.id, v.name)
+                format!(
+                    "{}Var: id={}, name={} ({})\n",
+                    indent, v.id, v.name, v.var_type
+                )
             }
         }
     }
@@ -297,6 +416,199 @@ impl ToTackyInstruction for LabelInstruction {
     }
 }
 
+/*
+Annotations mark the region of instructions generated for a piece
+of the source code, so that it can be labeled in the emitted assembly.
+Every start has a matching end with the same description
+*/
+#[derive(Clone, Debug)]
+pub struct AnnotationStartInstruction {
+    pub description: String,
+    // where the annotated piece of source code starts
+    pub pop_context: Option<PoppedTokenContext>
+}
+impl AnnotationStartInstruction {
+    pub fn new(description: String) -> AnnotationStartInstruction {
+        AnnotationStartInstruction { description, pop_context: None }
+    }
+}
+impl ToTackyInstruction for AnnotationStartInstruction {
+    fn to_tacky_instruction(&self) -> TackyInstruction {
+        TackyInstruction::AnnotationStartInstruction(self.clone())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct AnnotationEndInstruction {
+    pub description: String,
+}
+impl AnnotationEndInstruction {
+    pub fn new(description: String) -> AnnotationEndInstruction {
+        AnnotationEndInstruction { description }
+    }
+}
+impl ToTackyInstruction for AnnotationEndInstruction {
+    fn to_tacky_instruction(&self) -> TackyInstruction {
+        TackyInstruction::AnnotationEndInstruction(self.clone())
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct GetAddressInstruction {
+    // dst = &src
+    pub src: TackyVariable,
+    pub dst: TackyVariable,
+    pub pop_context: Option<PoppedTokenContext>
+}
+impl GetAddressInstruction {
+    pub fn new(src: TackyVariable, dst: TackyVariable) -> GetAddressInstruction {
+        GetAddressInstruction { src, dst, pop_context: None }
+    }
+}
+impl ToTackyInstruction for GetAddressInstruction {
+    fn to_tacky_instruction(&self) -> TackyInstruction {
+        TackyInstruction::GetAddressInstruction(self.clone())
+    }
+}
+impl PrintableTacky for GetAddressInstruction {
+    fn print_tacky_code(&self, depth: u64) -> String {
+        let indent = TAB.repeat(depth as usize);
+        let mut result = String::new();
+        result.push_str(&format!("{indent}GetAddressInstruction:\n"));
+        result.push_str(&format!("{indent}{TAB}Src:\n"));
+        result.push_str(&self.src.print_tacky_code(depth + 2));
+        result.push_str(&format!("{indent}{TAB}Dst:\n"));
+        result.push_str(&self.dst.print_tacky_code(depth + 2));
+        result
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct LoadInstruction {
+    // dst = *(src_pointer + offset), with offset in bytes
+    pub src_pointer: TackyValue,
+    pub offset: u64,
+    pub dst: TackyVariable,
+    pub pop_context: Option<PoppedTokenContext>
+}
+impl LoadInstruction {
+    pub fn new(
+        src_pointer: TackyValue, offset: u64, dst: TackyVariable
+    ) -> LoadInstruction {
+        LoadInstruction { src_pointer, offset, dst, pop_context: None }
+    }
+}
+impl ToTackyInstruction for LoadInstruction {
+    fn to_tacky_instruction(&self) -> TackyInstruction {
+        TackyInstruction::LoadInstruction(self.clone())
+    }
+}
+impl PrintableTacky for LoadInstruction {
+    fn print_tacky_code(&self, depth: u64) -> String {
+        let indent = TAB.repeat(depth as usize);
+        let mut result = String::new();
+        result.push_str(&format!("{indent}LoadInstruction:\n"));
+        result.push_str(&format!("{indent}{TAB}SrcPointer:\n"));
+        result.push_str(&self.src_pointer.print_tacky_code(depth + 2));
+        result.push_str(&format!("{indent}{TAB}Offset: {}\n", self.offset));
+        result.push_str(&format!("{indent}{TAB}Dst:\n"));
+        result.push_str(&self.dst.print_tacky_code(depth + 2));
+        result
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct StoreInstruction {
+    // *(dst_pointer + offset) = src, with offset in bytes
+    pub src: TackyValue,
+    pub dst_pointer: TackyValue,
+    pub offset: u64,
+    pub pop_context: Option<PoppedTokenContext>
+}
+impl StoreInstruction {
+    pub fn new(
+        src: TackyValue, dst_pointer: TackyValue, offset: u64
+    ) -> StoreInstruction {
+        StoreInstruction { src, dst_pointer, offset, pop_context: None }
+    }
+}
+impl ToTackyInstruction for StoreInstruction {
+    fn to_tacky_instruction(&self) -> TackyInstruction {
+        TackyInstruction::StoreInstruction(self.clone())
+    }
+}
+impl PrintableTacky for StoreInstruction {
+    fn print_tacky_code(&self, depth: u64) -> String {
+        let indent = TAB.repeat(depth as usize);
+        let mut result = String::new();
+        result.push_str(&format!("{indent}StoreInstruction:\n"));
+        result.push_str(&format!("{indent}{TAB}Src:\n"));
+        result.push_str(&self.src.print_tacky_code(depth + 2));
+        result.push_str(&format!("{indent}{TAB}DstPointer:\n"));
+        result.push_str(&self.dst_pointer.print_tacky_code(depth + 2));
+        result.push_str(&format!("{indent}{TAB}Offset: {}\n", self.offset));
+        result
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ConversionKind {
+    // widen a signed value, preserving its sign
+    SignExtend,
+    // widen an unsigned value, filling the upper bits with zeros
+    ZeroExtend,
+    // keep only the lower bits of a wider value
+    Truncate,
+    // signed int or long to double
+    IntToDouble,
+    // unsigned int or unsigned long to double
+    UIntToDouble,
+    // double to signed int or long, rounding towards zero
+    DoubleToInt,
+    // double to unsigned int or unsigned long, rounding towards zero
+    DoubleToUInt,
+}
+
+#[derive(Clone, Debug)]
+pub struct ConvertInstruction {
+    pub kind: ConversionKind,
+    pub src: TackyValue,
+    pub dst: TackyVariable,
+    pub pop_context: Option<PoppedTokenContext>
+}
+impl ConvertInstruction {
+    pub fn new(
+        kind: ConversionKind,
+        src: TackyValue,
+        dst: TackyVariable
+    ) -> ConvertInstruction {
+        ConvertInstruction {
+            kind,
+            src,
+            dst,
+            pop_context: None
+        }
+    }
+}
+impl ToTackyInstruction for ConvertInstruction {
+    fn to_tacky_instruction(&self) -> TackyInstruction {
+        TackyInstruction::ConvertInstruction(self.clone())
+    }
+}
+impl PrintableTacky for ConvertInstruction {
+    fn print_tacky_code(&self, depth: u64) -> String {
+        let indent = TAB.repeat(depth as usize);
+        let mut result = String::new();
+        result.push_str(&format!("{indent}ConvertInstruction:\n"));
+        result.push_str(&format!("{indent}{TAB}Kind: {:?}\n", self.kind));
+        result.push_str(&format!("{indent}{TAB}Src:\n"));
+        result.push_str(&self.src.print_tacky_code(depth + 2));
+        result.push_str(&format!("{indent}{TAB}Dst:\n"));
+        result.push_str(&self.dst.print_tacky_code(depth + 2));
+        result
+    }
+}
+
 #[derive(Clone, Debug)]
 pub enum TackyInstruction {
     UnaryInstruction(UnaryInstruction),
@@ -306,6 +618,12 @@ pub enum TackyInstruction {
     JumpIfZeroInstruction(JumpIfZeroInstruction),
     JumpIfNotZeroInstruction(JumpIfNotZeroInstruction),
     LabelInstruction(LabelInstruction),
+    ConvertInstruction(ConvertInstruction),
+    GetAddressInstruction(GetAddressInstruction),
+    LoadInstruction(LoadInstruction),
+    StoreInstruction(StoreInstruction),
+    AnnotationStartInstruction(AnnotationStartInstruction),
+    AnnotationEndInstruction(AnnotationEndInstruction),
     Return(TackyValue),
 }
 impl ToTackyInstruction for TackyInstruction {
@@ -314,12 +632,80 @@ impl ToTackyInstruction for TackyInstruction {
     }
 }
 impl TackyInstruction {
+    pub fn get_dst(&self) -> Option<&TackyVariable> {
+        // variable written to by the instruction (stores write through a pointer)
+        match self {
+            TackyInstruction::UnaryInstruction(unary) => Some(&unary.dst),
+            TackyInstruction::BinaryInstruction(binary) => Some(&binary.dst),
+            TackyInstruction::CopyInstruction(copy) => Some(&copy.dst),
+            TackyInstruction::ConvertInstruction(convert) => Some(&convert.dst),
+            TackyInstruction::GetAddressInstruction(get_address) => Some(&get_address.dst),
+            TackyInstruction::LoadInstruction(load) => Some(&load.dst),
+            _ => None
+        }
+    }
+    pub fn get_src_values(&self) -> Vec<&TackyValue> {
+        // values read by the instruction
+        match self {
+            TackyInstruction::UnaryInstruction(unary) => vec![&unary.src],
+            TackyInstruction::BinaryInstruction(binary) => vec![&binary.left, &binary.right],
+            TackyInstruction::CopyInstruction(copy) => vec![&copy.src],
+            TackyInstruction::ConvertInstruction(convert) => vec![&convert.src],
+            TackyInstruction::LoadInstruction(load) => vec![&load.src_pointer],
+            TackyInstruction::StoreInstruction(store) => vec![&store.src, &store.dst_pointer],
+            TackyInstruction::JumpIfZeroInstruction(jump) => vec![&jump.condition],
+            TackyInstruction::JumpIfNotZeroInstruction(jump) => vec![&jump.condition],
+            TackyInstruction::Return(value) => vec![value],
+            TackyInstruction::GetAddressInstruction(_) |
+            TackyInstruction::JumpInstruction(_) |
+            TackyInstruction::LabelInstruction(_) |
+            TackyInstruction::AnnotationStartInstruction(_) |
+            TackyInstruction::AnnotationEndInstruction(_) => vec![]
+        }
+    }
+    pub fn get_src_values_mut(&mut self) -> Vec<&mut TackyValue> {
+        match self {
+            TackyInstruction::UnaryInstruction(unary) => vec![&mut unary.src],
+            TackyInstruction::BinaryInstruction(binary) => {
+                vec![&mut binary.left, &mut binary.right]
+            },
+            TackyInstruction::CopyInstruction(copy) => vec![&mut copy.src],
+            TackyInstruction::ConvertInstruction(convert) => vec![&mut convert.src],
+            TackyInstruction::LoadInstruction(load) => vec![&mut load.src_pointer],
+            TackyInstruction::StoreInstruction(store) => {
+                vec![&mut store.src, &mut store.dst_pointer]
+            },
+            TackyInstruction::JumpIfZeroInstruction(jump) => vec![&mut jump.condition],
+            TackyInstruction::JumpIfNotZeroInstruction(jump) => vec![&mut jump.condition],
+            TackyInstruction::Return(value) => vec![value],
+            TackyInstruction::GetAddressInstruction(_) |
+            TackyInstruction::JumpInstruction(_) |
+            TackyInstruction::LabelInstruction(_) |
+            TackyInstruction::AnnotationStartInstruction(_) |
+            TackyInstruction::AnnotationEndInstruction(_) => vec![]
+        }
+    }
+    pub fn get_src_variables(&self) -> Vec<&TackyVariable> {
+        // variables read by the instruction, or whose address is taken
+        let mut variables: Vec<&TackyVariable> = self.get_src_values().into_iter().filter_map(
+            |value| match value {
+                TackyValue::Var(variable) => Some(variable),
+                TackyValue::Constant(_) => None
+            }
+        ).collect();
+        if let TackyInstruction::GetAddressInstruction(get_address) = self {
+            variables.push(&get_address.src);
+        }
+        variables
+    }
+
     pub fn unroll_short_circuit(
-        left: ExpressionVariant,
-        right: ExpressionVariant,
+        left: &Expression,
+        right: &Expression,
         var_counter: u64,
-        is_and: bool
-    ) -> UnrollResult {
+        is_and: bool,
+        scope: &TackyScope
+    ) -> Result<UnrollResult, TackyError> {
         /*
         TODO: support for an annotated block of instructions
           would be really nice
@@ -352,10 +738,19 @@ impl TackyInstruction {
         result = CondJumpValue
         Label(short_circuit_end_label)
         */
+        let left_unroll_result = Self::unroll_sub_expression(left, var_counter, scope)?;
+        let var_counter = left_unroll_result.next_free_var_id;
+        let right_unroll_result = Self::unroll_sub_expression(right, var_counter, scope)?;
+        let var_counter = right_unroll_result.next_free_var_id;
+        // contains the result of the short-circuit and operation
+        let result_tacky_var = TackyVariable::new(var_counter);
+        let var_counter = var_counter + 1;
+
+        // nested operands are unrolled first, so only the result variable is unique to this one
         let jump_label =
-            Identifier::new(format!("short_circuit_jmp_{}", var_counter));
+            Identifier::new(format!("short_circuit_jmp_{}", result_tacky_var.id));
         let end_label =
-            Identifier::new(format!("short_circuit_end_{}", var_counter));
+            Identifier::new(format!("short_circuit_end_{}", result_tacky_var.id));
 
         let build_conditional_jump = |
             value: TackyValue
@@ -384,14 +779,6 @@ impl TackyInstruction {
             TackyValue::Constant(ASTConstant::new("1"))
         };
 
-        let left_unroll_result = Self::unroll_expression(left, var_counter);
-        let var_counter = left_unroll_result.next_free_var_id;
-        let right_unroll_result = Self::unroll_expression(right, var_counter);
-        let var_counter = right_unroll_result.next_free_var_id;
-        // contains the result of the short-circuit and operation
-        let result_tacky_var = TackyVariable::new(var_counter);
-        let var_counter = var_counter + 1;
-
         let mut circuit_instructions: Vec<TackyInstruction> = vec![];
         // <instructions for e1>
         circuit_instructions.extend(left_unroll_result.instructions);
@@ -427,36 +814,159 @@ impl TackyInstruction {
             |instr| instr.to_tacky_instruction()
         ).collect();
 
-        let unroll_result = UnrollResult::new(
+        Ok(UnrollResult::new(
             tacky_instructions,
             TackyValue::Var(result_tacky_var),
             var_counter
+        ))
+    }
+
+    pub fn convert_value(
+        value: TackyValue,
+        target_type: &CType,
+        var_counter: u64
+    ) -> UnrollResult {
+        /*
+        Converts value to target_type, emitting a conversion instruction
+        into a new temporary variable if needed. Constants are converted
+        at compile time instead.
+        */
+        let src_type = value.get_type();
+        if src_type == *target_type {
+            return UnrollResult::new(vec![], value, var_counter);
+        }
+
+        let is_double_conversion = src_type.is_double() || target_type.is_double();
+        if is_double_conversion && matches!(value, TackyValue::Var(_)) {
+            if src_type.is_character() || target_type.is_character() {
+                /*
+                there are no conversions between bytes and doubles,
+                so character types are converted through int first
+                */
+                let int_conversion = Self::convert_value(value, &CType::Int, var_counter);
+                let var_counter = int_conversion.next_free_var_id;
+                return Self::convert_unrolled(int_conversion, target_type, var_counter);
+            }
+
+            let kind = match (src_type.is_double(), src_type.is_signed()) {
+                (false, true) => ConversionKind::IntToDouble,
+                (false, false) => ConversionKind::UIntToDouble,
+                (true, _) if target_type.is_signed() => ConversionKind::DoubleToInt,
+                (true, _) => ConversionKind::DoubleToUInt,
+            };
+            let converted_var =
+                TackyVariable::new_typed(var_counter, target_type.clone());
+            let instruction = ConvertInstruction::new(
+                kind, value, converted_var.clone()
+            ).to_tacky_instruction();
+            return UnrollResult::new(
+                vec![instruction],
+                TackyValue::Var(converted_var),
+                var_counter + 1
+            );
+        }
+
+        let ast_constant = match value {
+            TackyValue::Constant(ast_constant) => ast_constant,
+            TackyValue::Var(_) => {
+                let converted_var =
+                    TackyVariable::new_typed(var_counter, target_type.clone());
+                let instruction = if src_type.size() == target_type.size() {
+                    // same bit pattern, only the interpretation changes
+                    CopyInstruction::new(
+                        value, converted_var.clone()
+                    ).to_tacky_instruction()
+                } else {
+                    let kind = if target_type.size() < src_type.size() {
+                        ConversionKind::Truncate
+                    } else if src_type.is_signed() {
+                        ConversionKind::SignExtend
+                    } else {
+                        ConversionKind::ZeroExtend
+                    };
+                    ConvertInstruction::new(
+                        kind, value, converted_var.clone()
+                    ).to_tacky_instruction()
+                };
+                return UnrollResult::new(
+                    vec![instruction],
+                    TackyValue::Var(converted_var),
+                    var_counter + 1
+                );
+            }
+        };
+
+        UnrollResult::new(
+            vec![],
+            TackyValue::Constant(ast_constant.converted_to(target_type)),
+            var_counter
+        )
+    }
+
+    fn convert_unrolled(
+        unroll_result: UnrollResult,
+        target_type: &CType,
+        var_counter: u64
+    ) -> UnrollResult {
+        // converts the value of unroll_result, keeping its instructions
+        let conversion = Self::convert_value(
+            unroll_result.value, target_type, var_counter
         );
-        unroll_result
+        let mut instructions = unroll_result.instructions;
+        instructions.extend(conversion.instructions);
+        UnrollResult::new(
+            instructions, conversion.value, conversion.next_free_var_id
+        )
+    }
+
+    fn unroll_sub_expression(
+        expression: &Expression,
+        var_counter: u64,
+        scope: &TackyScope
+    ) -> Result<UnrollResult, TackyError> {
+        Self::unroll_expression(expression.expr_item.clone(), var_counter, scope)
+            .map_err(|err| err.located_at(&expression.pop_context))
     }
 
     pub fn unroll_expression(
         expr_item: ExpressionVariant,
-        var_counter: u64
-    ) -> UnrollResult {
+        var_counter: u64,
+        scope: &TackyScope
+    ) -> Result<UnrollResult, TackyError> {
         match expr_item {
             ExpressionVariant::Constant(ast_constant) => {
-                UnrollResult::new(
+                Ok(UnrollResult::new(
                     Vec::new(),
                     TackyValue::Constant(ast_constant.clone()),
                     var_counter
-                )
+                ))
             },
             ExpressionVariant::UnaryOperation(
                 operator, sub_expr
             ) => {
-                let sub_expr_item = sub_expr.expr_item.clone();
-                let inner_unroll_res = Self::unroll_expression(
-                    sub_expr_item, var_counter
+                let inner_unroll_res = Self::unroll_sub_expression(
+                    &sub_expr, var_counter, scope
+                )?;
+                // integer promotion of the operand (e.g. char -> int)
+                let promoted_type = inner_unroll_res.value.get_type().promoted();
+                let var_counter = inner_unroll_res.next_free_var_id;
+                let inner_unroll_res = Self::convert_unrolled(
+                    inner_unroll_res, &promoted_type, var_counter
                 );
 
                 let var_counter = inner_unroll_res.next_free_var_id;
-                let new_var = TackyVariable::new(var_counter);
+                let operand_type = inner_unroll_res.value.get_type();
+                let is_bitwise_not = matches!(operator, SupportedUnaryOperators::BitwiseNot);
+                if operand_type.is_double() && is_bitwise_not {
+                    return Err(TackyError::type_error(format!(
+                        "Invalid operand to ~ of type {}", operand_type
+                    )));
+                }
+                let result_type = match operator {
+                    SupportedUnaryOperators::Not => CType::Int,
+                    _ => inner_unroll_res.value.get_type()
+                };
+                let new_var = TackyVariable::new_typed(var_counter, result_type);
                 let var_counter = var_counter + 1;
 
                 let new_unary_instruction = UnaryInstruction {
@@ -470,33 +980,75 @@ impl TackyInstruction {
                 let mut instructions = sub_instructions.clone();
                 instructions.push(new_unary_instruction.to_tacky_instruction());
 
-                UnrollResult::new(
+                Ok(UnrollResult::new(
                     instructions,
                     TackyValue::Var(new_var),
                     var_counter
-                )
+                ))
             }
             ExpressionVariant::BinaryOperation(operator, left, right) => {
                 if operator.is_short_circuit() {
                     return Self::unroll_short_circuit(
-                        left.expr_item.clone(),
-                        right.expr_item.clone(),
+                        &left,
+                        &right,
                         var_counter,
-                        operator == SupportedBinaryOperators::And
+                        operator == SupportedBinaryOperators::And,
+                        scope
                     );
+                } else if operator == SupportedBinaryOperators::AssignEqual {
+                    return Self::unroll_assignment(&left, &right, var_counter, scope);
                 }
 
-                let left_expr_item = left.expr_item.clone();
-                let right_expr_item = right.expr_item.clone();
+                let left_unroll = Self::unroll_sub_expression(&left, var_counter, scope)?;
+                let var_counter = left_unroll.next_free_var_id;
+                let right_unroll = Self::unroll_sub_expression(&right, var_counter, scope)?;
+                let var_counter = right_unroll.next_free_var_id;
+
+                let left_type = left_unroll.value.get_type();
+                let right_type = right_unroll.value.get_type();
+                let is_integer = |c_type: &CType| !c_type.is_double() && !c_type.is_pointer();
+                if operator.is_bitwise() && !(is_integer(&left_type) && is_integer(&right_type)) {
+                    return Err(TackyError::type_error(format!(
+                        "Invalid operands to {:?} of types {} and {}",
+                        operator, left_type, right_type
+                    )));
+                }
 
-                let left_unroll =
-                    Self::unroll_expression(left_expr_item, var_counter);
+                /*
+                convert both operands to their common type, except for shifts
+                where the result has the (promoted) type of the left operand,
+                and the shift count is only converted to match it
+                */
+                let common_type = if operator.is_shift() {
+                    left_type.promoted()
+                } else {
+                    CType::common_type(&left_type, &right_type)
+                };
+                let left_unroll = Self::convert_unrolled(
+                    left_unroll, &common_type, var_counter
+                );
                 let var_counter = left_unroll.next_free_var_id;
-                let right_unroll =
-                    Self::unroll_expression(right_expr_item, var_counter);
+                let right_unroll = Self::convert_unrolled(
+                    right_unroll, &common_type, var_counter
+                );
                 let var_counter = right_unroll.next_free_var_id;
 
-                let new_var = TackyVariable::new(var_counter);
+                if common_type.is_pointer() && !operator.is_relational() {
+                    return Err(TackyError::unsupported(format!(
+                        "Pointer arithmetic is not supported: {:?}", operator
+                    )));
+                } else if common_type.is_double() &&
+                    operator == SupportedBinaryOperators::Modulo {
+                    return Err(TackyError::type_error(format!(
+                        "Invalid operands to % of type {}", common_type
+                    )));
+                }
+                let result_type = if operator.is_relational() {
+                    CType::Int
+                } else {
+                    common_type
+                };
+                let new_var = TackyVariable::new_typed(var_counter, result_type);
                 let var_counter = var_counter + 1;
 
                 let new_binary_instruction = BinaryInstruction {
@@ -504,7 +1056,10 @@ impl TackyInstruction {
                     left: left_unroll.value,
                     right: right_unroll.value,
                     dst: new_var.clone(),
-                    pop_context: right.pop_context.clone()
+                    // covers both operands and the operator between them
+                    pop_context: PoppedTokenContext::merge_options(
+                        &left.pop_context, &right.pop_context
+                    )
                 };
 
                 let left_instructions = left_unroll.instructions;
@@ -513,18 +1068,289 @@ impl TackyInstruction {
                 instructions.extend(right_instructions.clone());
                 instructions.push(new_binary_instruction.to_tacky_instruction());
 
-                UnrollResult::new(
+                Ok(UnrollResult::new(
                     instructions,
                     TackyValue::Var(new_var),
                     var_counter
-                )
+                ))
             }
             ExpressionVariant::ParensWrapped(sub_expr) => {
-                let inner_variant = sub_expr.expr_item;
-                Self::unroll_expression(inner_variant, var_counter)
+                Self::unroll_sub_expression(&sub_expr, var_counter, scope)
             }
+            ExpressionVariant::Variable(identifier) => {
+                let variable = Self::lookup_variable(&identifier, scope)?;
+                Ok(UnrollResult::new(vec![], TackyValue::Var(variable), var_counter))
+            }
+            ExpressionVariant::Dereference(_) |
+            ExpressionVariant::MemberAccess(_, _) |
+            ExpressionVariant::PointerMemberAccess(_, _) => {
+                /*
+                <instructions for the address of the object>
+                ptr = <address of the object>
+                result = Load(ptr + offset)
+                */
+                let (address_unroll, offset, object_type) =
+                    Self::unroll_object_address(expr_item, var_counter, scope)?;
+                let var_counter = address_unroll.next_free_var_id;
+                if object_type.is_struct() {
+                    return Err(TackyError::unsupported(format!(
+                        "Using {} values directly is not supported", object_type
+                    )));
+                }
+
+                let result_var = TackyVariable::new_typed(var_counter, object_type);
+                let mut instructions = address_unroll.instructions;
+                instructions.push(LoadInstruction::new(
+                    address_unroll.value, offset, result_var.clone()
+                ).to_tacky_instruction());
+                Ok(UnrollResult::new(
+                    instructions, TackyValue::Var(result_var), var_counter + 1
+                ))
+            }
+            ExpressionVariant::AddressOf(sub_expr) => {
+                Self::unroll_address_of(&sub_expr, var_counter, scope)
+            }
+            ExpressionVariant::Cast(target_type, sub_expr) => {
+                /*
+                <instructions for sub_expr>
+                result = Truncate | SignExtend | ZeroExtend (sub_expr_result)
+                */
+                let target_type = scope.resolve_type(&target_type)?;
+                if target_type.is_struct() {
+                    return Err(TackyError::type_error(format!(
+                        "Cannot cast to non-scalar type {}", target_type
+                    )));
+                }
+                let sub_unroll = Self::unroll_sub_expression(&sub_expr, var_counter, scope)?;
+                let source_type = sub_unroll.value.get_type();
+                if source_type.is_struct() {
+                    return Err(TackyError::type_error(format!(
+                        "Cannot cast from non-scalar type {}", source_type
+                    )));
+                } else if (source_type.is_pointer() && target_type.is_double()) ||
+                    (source_type.is_double() && target_type.is_pointer()) {
+                    return Err(TackyError::type_error(format!(
+                        "Cannot cast between {} and {}", source_type, target_type
+                    )));
+                }
+                let var_counter = sub_unroll.next_free_var_id;
+                Ok(Self::convert_unrolled(sub_unroll, &target_type, var_counter))
+            }
+        }
+    }
+
+    fn lookup_variable(
+        identifier: &Identifier, scope: &TackyScope
+    ) -> Result<TackyVariable, TackyError> {
+        match scope.variables.get(&identifier.name) {
+            Some(variable) => Ok(variable.clone()),
+            None => Err(TackyError {
+                variant: TackyErrorVariants::UndeclaredVariable(identifier.name.clone()),
+                location: None
+            })
         }
     }
+
+    fn strip_parens(expr_item: ExpressionVariant) -> ExpressionVariant {
+        match expr_item {
+            ExpressionVariant::ParensWrapped(sub_expr) => {
+                Self::strip_parens(sub_expr.expr_item)
+            },
+            other => other
+        }
+    }
+
+    fn referenced_type(pointer_type: &CType, scope: &TackyScope) -> Result<CType, TackyError> {
+        match pointer_type.referenced_type() {
+            Some(referenced_type) => scope.resolve_type(&referenced_type),
+            None => Err(TackyError::type_error(format!(
+                "Cannot dereference non-pointer type {}", pointer_type
+            )))
+        }
+    }
+
+    fn unroll_object_address(
+        expr_item: ExpressionVariant,
+        var_counter: u64,
+        scope: &TackyScope
+    ) -> Result<(UnrollResult, u64, CType), TackyError> {
+        /*
+        Unrolls an lvalue expression into a pointer to the object it
+        designates, a byte offset from that pointer and the type of the
+        object, e.g. for s.b where b is at offset 4 of s:
+        ptr = GetAddress(s)
+        returns (ptr, 4, <type of b>)
+        */
+        match Self::strip_parens(expr_item) {
+            ExpressionVariant::Variable(identifier) => {
+                let variable = Self::lookup_variable(&identifier, scope)?;
+                let object_type = variable.var_type.clone();
+                let pointer_var =
+                    TackyVariable::new_typed(var_counter, object_type.pointer_to());
+                let instruction = GetAddressInstruction::new(
+                    variable, pointer_var.clone()
+                ).to_tacky_instruction();
+                let unroll = UnrollResult::new(
+                    vec![instruction], TackyValue::Var(pointer_var), var_counter + 1
+                );
+                Ok((unroll, 0, object_type))
+            },
+            ExpressionVariant::Dereference(pointer_expr) => {
+                let pointer_unroll =
+                    Self::unroll_sub_expression(&pointer_expr, var_counter, scope)?;
+                let object_type =
+                    Self::referenced_type(&pointer_unroll.value.get_type(), scope)?;
+                Ok((pointer_unroll, 0, object_type))
+            },
+            ExpressionVariant::MemberAccess(base_expr, member) => {
+                let (base_unroll, base_offset, base_type) = Self::unroll_object_address(
+                    base_expr.expr_item, var_counter, scope
+                ).map_err(|err| err.located_at(&base_expr.pop_context))?;
+                let (member_offset, member_type) =
+                    Self::lookup_member(&base_type, &member)?;
+                Ok((base_unroll, base_offset + member_offset, member_type))
+            },
+            ExpressionVariant::PointerMemberAccess(pointer_expr, member) => {
+                let pointer_unroll =
+                    Self::unroll_sub_expression(&pointer_expr, var_counter, scope)?;
+                let base_type =
+                    Self::referenced_type(&pointer_unroll.value.get_type(), scope)?;
+                let (member_offset, member_type) =
+                    Self::lookup_member(&base_type, &member)?;
+                Ok((pointer_unroll, member_offset, member_type))
+            },
+            other => Err(TackyError::type_error(format!(
+                "Expression {} is not an lvalue", format_c_expression(&Expression::new(other))
+            )))
+        }
+    }
+
+    fn lookup_member(
+        struct_type: &CType, member: &Identifier
+    ) -> Result<(u64, CType), TackyError> {
+        // byte offset and type of a member of a (complete) struct
+        let layout = match struct_type {
+            CType::Struct(struct_type) => {
+                struct_type.layout().map_err(TackyError::type_error)?
+            },
+            other => return Err(TackyError::type_error(format!(
+                "Member access {} on non-struct type {}", member.name, other
+            )))
+        };
+        match layout.get_member(&member.name) {
+            Some((struct_member, offset)) => {
+                Ok((*offset, struct_member.member_type.clone()))
+            },
+            None => Err(TackyError::type_error(format!(
+                "{} has no member {}", struct_type, member.name
+            )))
+        }
+    }
+
+    fn unroll_address_of(
+        expression: &Expression,
+        var_counter: u64,
+        scope: &TackyScope
+    ) -> Result<UnrollResult, TackyError> {
+        /*
+        &x emits GetAddress(x), &*e cancels out into the value of e
+        (without loading from it), and &s.member adds the offset
+        of the member to the address of s
+        */
+        let (address_unroll, offset, object_type) = Self::unroll_object_address(
+            expression.expr_item.clone(), var_counter, scope
+        ).map_err(|err| err.located_at(&expression.pop_context))?;
+        let var_counter = address_unroll.next_free_var_id;
+        let result_type = object_type.pointer_to();
+        if offset == 0 && address_unroll.value.get_type() == result_type {
+            return Ok(address_unroll);
+        }
+
+        let result_var = TackyVariable::new_typed(var_counter, result_type);
+        let instruction = if offset == 0 {
+            // same address, only the type of the pointer changes
+            CopyInstruction::new(
+                address_unroll.value, result_var.clone()
+            ).to_tacky_instruction()
+        } else {
+            let offset_value = TackyValue::Constant(
+                ASTConstant::new_typed(&offset.to_string(), CType::Long)
+            );
+            BinaryInstruction::new(
+                SupportedBinaryOperators::Add,
+                address_unroll.value, offset_value, result_var.clone()
+            ).to_tacky_instruction()
+        };
+        let mut instructions = address_unroll.instructions;
+        instructions.push(instruction);
+        Ok(UnrollResult::new(instructions, TackyValue::Var(result_var), var_counter + 1))
+    }
+
+    fn unroll_assignment(
+        left: &Expression,
+        right: &Expression,
+        var_counter: u64,
+        scope: &TackyScope
+    ) -> Result<UnrollResult, TackyError> {
+        /*
+        var = e2 is unrolled as:
+        <instructions for e2>
+        Copy(<result of e2 converted to the type of var>, var)
+        with var being the value of the assignment expression.
+        *e1 = e2 (or e1.member = e2, e1->member = e2) is unrolled as:
+        <instructions for the address of e1>
+        ptr = <address of e1>
+        <instructions for e2>
+        v = <result of e2 converted to the type of the object>
+        Store(v, ptr + offset)
+        with v being the value of the assignment expression.
+        The semantic passes have already checked that e1 is an lvalue
+        */
+        let left_item = Self::strip_parens(left.expr_item.clone());
+        if let ExpressionVariant::Variable(identifier) = left_item {
+            let variable = Self::lookup_variable(&identifier, scope)?;
+            if variable.var_type.is_struct() {
+                return Err(TackyError::unsupported(format!(
+                    "Assigning {} values is not supported", variable.var_type
+                )));
+            }
+            let value_unroll = Self::unroll_sub_expression(right, var_counter, scope)?;
+            let var_counter = value_unroll.next_free_var_id;
+            let value_unroll = Self::convert_unrolled(
+                value_unroll, &variable.var_type, var_counter
+            );
+            let var_counter = value_unroll.next_free_var_id;
+
+            let mut instructions = value_unroll.instructions;
+            instructions.push(CopyInstruction::new(
+                value_unroll.value, variable.clone()
+            ).to_tacky_instruction());
+            return Ok(UnrollResult::new(instructions, TackyValue::Var(variable), var_counter));
+        }
+        let (address_unroll, offset, object_type) =
+            Self::unroll_object_address(left_item, var_counter, scope)
+                .map_err(|err| err.located_at(&left.pop_context))?;
+        let var_counter = address_unroll.next_free_var_id;
+        if object_type.is_struct() {
+            return Err(TackyError::unsupported(format!(
+                "Assigning {} values is not supported", object_type
+            )));
+        }
+
+        let value_unroll = Self::unroll_sub_expression(right, var_counter, scope)?;
+        let var_counter = value_unroll.next_free_var_id;
+        let value_unroll = Self::convert_unrolled(
+            value_unroll, &object_type, var_counter
+        );
+        let var_counter = value_unroll.next_free_var_id;
+
+        let mut instructions = address_unroll.instructions;
+        instructions.extend(value_unroll.instructions);
+        instructions.push(StoreInstruction::new(
+            value_unroll.value.clone(), address_unroll.value, offset
+        ).to_tacky_instruction());
+        Ok(UnrollResult::new(instructions, value_unroll.value, var_counter))
+    }
 }
 impl PrintableTacky for TackyInstruction {
     fn print_tacky_code(&self, depth: u64) -> String {
@@ -535,6 +1361,26 @@ impl PrintableTacky for TackyInstruction {
             TackyInstruction::BinaryInstruction(binary) => {
                 binary.print_tacky_code(depth)
             },
+            TackyInstruction::ConvertInstruction(convert) => {
+                convert.print_tacky_code(depth)
+            },
+            TackyInstruction::GetAddressInstruction(get_address) => {
+                get_address.print_tacky_code(depth)
+            },
+            TackyInstruction::LoadInstruction(load) => {
+                load.print_tacky_code(depth)
+            },
+            TackyInstruction::StoreInstruction(store) => {
+                store.print_tacky_code(depth)
+            },
+            TackyInstruction::AnnotationStartInstruction(annotation) => {
+                let indent = TAB.repeat(depth as usize);
+                format!("{indent}AnnotationStart: {}\n", annotation.description)
+            },
+            TackyInstruction::AnnotationEndInstruction(annotation) => {
+                let indent = TAB.repeat(depth as usize);
+                format!("{indent}AnnotationEnd: {}\n", annotation.description)
+            },
             TackyInstruction::Return(value) => {
                 let indent = TAB.repeat(depth as usize);
                 let mut result = String::new();
@@ -550,28 +1396,123 @@ impl PrintableTacky for TackyInstruction {
     }
 }
 
+fn annotate(
+    description: String, pop_context: Option<PoppedTokenContext>,
+    instructions: Vec<TackyInstruction>
+) -> Vec<TackyInstruction> {
+    // wraps the instructions generated for a statement in an annotation pair
+    let annotation_start = AnnotationStartInstruction {
+        description: description.clone(), pop_context
+    };
+    let mut annotated_instructions = vec![annotation_start.to_tacky_instruction()];
+    annotated_instructions.extend(instructions);
+    annotated_instructions.push(
+        AnnotationEndInstruction::new(description).to_tacky_instruction()
+    );
+    annotated_instructions
+}
+
 pub struct TackyFunction {
     pub name: Identifier,
     pub instructions: Vec<TackyInstruction>,
     pub pop_context: Option<PoppedTokenContext>
 }
 impl TackyFunction {
-    pub fn from_function(function: &ASTFunction) -> TackyFunction {
-        let statement = &function.body;
-        let expression = &statement.expression;
-        let expr_item = expression.expr_item.clone();
-        let inner_unroll = TackyInstruction::unroll_expression(expr_item, 0);
-
-        let temp_value = inner_unroll.value;
-        let mut sub_instructions = inner_unroll.instructions;
-        let return_instruction = TackyInstruction::Return(temp_value);
-        sub_instructions.push(return_instruction);
-
-        TackyFunction {
+    pub fn from_function(
+        function: &ASTFunction, file_scope: &TackyScope
+    ) -> Result<TackyFunction, TackyError> {
+        // locals shadow file scope variables, which use the lowest ids
+        let mut scope = file_scope.clone();
+        let mut instructions: Vec<TackyInstruction> = vec![];
+        let mut var_counter: u64 = file_scope.variables.len() as u64;
+
+        for block_item in &function.body {
+            match block_item {
+                BlockItem::StructDeclaration(struct_declaration) => {
+                    let struct_type = struct_declaration.struct_type.clone();
+                    scope.structs.declare(struct_type).map_err(|message| {
+                        TackyError::type_error(message).located_at(&struct_declaration.pop_context)
+                    })?;
+                },
+                BlockItem::Declaration(declaration) => {
+                    let var_type = scope.resolve_type(&declaration.var_type)
+                        .map_err(|err| err.located_at(&declaration.pop_context))?;
+                    let variable = TackyVariable::new_named(
+                        var_counter, declaration.name.name_to_string(), var_type
+                    );
+                    var_counter += 1;
+                    scope.variables.insert(
+                        declaration.name.name_to_string(), variable.clone()
+                    );
+
+                    if let Some(initializer) = &declaration.initializer {
+                        let init_unroll = TackyInstruction::unroll_sub_expression(
+                            initializer, var_counter, &scope
+                        )?;
+                        var_counter = init_unroll.next_free_var_id;
+                        let init_unroll = TackyInstruction::convert_unrolled(
+                            init_unroll, &variable.var_type, var_counter
+                        );
+                        var_counter = init_unroll.next_free_var_id;
+                        let mut init_instructions = init_unroll.instructions;
+                        init_instructions.push(CopyInstruction::new(
+                            init_unroll.value, variable
+                        ).to_tacky_instruction());
+
+                        let description = format!(
+                            "{} = {}", declaration.name.name,
+                            format_c_expression(initializer)
+                        );
+                        instructions.extend(annotate(
+                            description, declaration.pop_context.clone(), init_instructions
+                        ));
+                    }
+                },
+                BlockItem::Statement(statement) => match &statement.variant {
+                    StatementVariant::Return(expression) => {
+                        let inner_unroll = TackyInstruction::unroll_sub_expression(
+                            expression, var_counter, &scope
+                        )?;
+                        var_counter = inner_unroll.next_free_var_id;
+                        let inner_unroll = TackyInstruction::convert_unrolled(
+                            inner_unroll, &function.return_type, var_counter
+                        );
+                        var_counter = inner_unroll.next_free_var_id;
+                        let mut return_instructions = inner_unroll.instructions;
+                        return_instructions.push(TackyInstruction::Return(inner_unroll.value));
+
+                        let description = format!("return {}", format_c_expression(expression));
+                        instructions.extend(annotate(
+                            description, statement.pop_context.clone(), return_instructions
+                        ));
+                    },
+                    StatementVariant::Expression(expression) => {
+                        // the value of the expression is discarded
+                        let inner_unroll = TackyInstruction::unroll_sub_expression(
+                            expression, var_counter, &scope
+                        )?;
+                        var_counter = inner_unroll.next_free_var_id;
+                        instructions.extend(annotate(
+                            format_c_expression(expression), statement.pop_context.clone(),
+                            inner_unroll.instructions
+                        ));
+                    },
+                    StatementVariant::Null => {}
+                }
+            }
+        }
+
+        // functions that fall off the end of their body return 0
+        let default_return = TackyInstruction::convert_value(
+            TackyValue::new_constant("0"), &function.return_type, var_counter
+        );
+        instructions.push(TackyInstruction::Return(default_return.value));
+
+        Ok(TackyFunction {
             name: function.name.clone(),
-            instructions: sub_instructions,
+            instructions,
             pop_context: function.pop_context.clone()
-        }
+        })
     }
     pub fn name_to_string(&self) -> String {
         self.name.name_to_string()
@@ -591,18 +1532,119 @@ impl PrintableTacky for TackyFunction {
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct TackyStaticVariable {
+    pub variable: TackyVariable,
+    // None for variables that are zero initialized
+    pub initial_value: Option<ASTConstant>,
+}
+impl TackyStaticVariable {
+    fn initial_value_of(expression: &Expression) -> Result<ASTConstant, TackyError> {
+        // file scope initializers have to be evaluated at compile time
+        match &expression.expr_item {
+            ExpressionVariant::Constant(ast_constant) => Ok(ast_constant.clone()),
+            ExpressionVariant::ParensWrapped(inner) => Self::initial_value_of(inner),
+            ExpressionVariant::UnaryOperation(SupportedUnaryOperators::Subtract, inner) => {
+                Ok(Self::initial_value_of(inner)?.negated())
+            },
+            ExpressionVariant::Cast(target_type, inner) if !target_type.is_struct() => {
+                Ok(Self::initial_value_of(inner)?.converted_to(target_type))
+            },
+            _ => Err(TackyError {
+                variant: TackyErrorVariants::InvalidInitializer(format!(
+                    "File scope initializer {} is not a constant",
+                    format_c_expression(expression)
+                )),
+                location: None
+            }.located_at(&expression.pop_context))
+        }
+    }
+    pub fn from_declaration(
+        declaration: &Declaration, id: u64, scope: &TackyScope
+    ) -> Result<TackyStaticVariable, TackyError> {
+        let var_type = scope.resolve_type(&declaration.var_type)
+            .map_err(|err| err.located_at(&declaration.pop_context))?;
+        let initial_value = declaration.initializer.as_ref().map(|initializer| {
+            if var_type.is_struct() {
+                return Err(TackyError {
+                    variant: TackyErrorVariants::InvalidInitializer(format!(
+                        "Struct {} can't have a scalar initializer",
+                        declaration.name.name_to_string()
+                    )),
+                    location: None
+                }.located_at(&declaration.pop_context));
+            }
+            Ok(Self::initial_value_of(initializer)?.converted_to(&var_type))
+        }).transpose()?;
+        Ok(TackyStaticVariable {
+            variable: TackyVariable::new_static(
+                id, declaration.name.name_to_string(), var_type
+            ),
+            initial_value
+        })
+    }
+}
+impl PrintableTacky for TackyStaticVariable {
+    fn print_tacky_code(&self, depth: u64) -> String {
+        let indent = TAB.repeat(depth as usize);
+        let initial_value = match &self.initial_value {
+            Some(ast_constant) => ast_constant.value.clone(),
+            None => "zero".to_string()
+        };
+        format!(
+            "{}TackyStaticVariable: name={}, type={}, initial_value={}\n",
+            indent, self.variable.name, self.variable.var_type, initial_value
+        )
+    }
+}
+
 pub struct TackyProgram {
+    pub static_variables: Vec<TackyStaticVariable>,
     pub function: TackyFunction,
     pop_context: Option<PoppedTokenContext>
 }
 impl TackyProgram {
+    pub fn new(
+        static_variables: Vec<TackyStaticVariable>, function: TackyFunction
+    ) -> TackyProgram {
+        TackyProgram { static_variables, function, pop_context: None }
+    }
+    fn build_struct_table(program: &ASTProgram) -> Result<StructTable, TackyError> {
+        // structs declared at file scope
+        let mut struct_table = StructTable::new();
+        for struct_declaration in &program.struct_declarations {
+            let struct_type = struct_declaration.struct_type.clone();
+            struct_table.declare(struct_type).map_err(|message| {
+                TackyError::type_error(message).located_at(&struct_declaration.pop_context)
+            })?;
+        }
+        Ok(struct_table)
+    }
     pub fn from_program(program: &ASTProgram) -> TackyProgram {
-        TackyProgram {
-            pop_context: program.pop_context.clone(),
-            function: TackyFunction::from_function(
-                &program.function
-            )
+        Self::try_from_program(program).unwrap_or_else(|err| panic!("{}", err))
+    }
+    pub fn try_from_program(program: &ASTProgram) -> Result<TackyProgram, TackyError> {
+        let mut file_scope = TackyScope::new(Self::build_struct_table(program)?);
+        let mut static_variables: Vec<TackyStaticVariable> = vec![];
+        for declaration in &program.global_declarations {
+            let name = declaration.name.name_to_string();
+            if file_scope.variables.contains_key(&name) {
+                return Err(TackyError {
+                    variant: TackyErrorVariants::Redefinition(name), location: None
+                }.located_at(&declaration.pop_context));
+            }
+            let static_variable = TackyStaticVariable::from_declaration(
+                declaration, static_variables.len() as u64, &file_scope
+            )?;
+            file_scope.variables.insert(name, static_variable.variable.clone());
+            static_variables.push(static_variable);
         }
+
+        Ok(TackyProgram {
+            pop_context: program.pop_context.clone(),
+            function: TackyFunction::from_function(&program.function, &file_scope)?,
+            static_variables
+        })
     }
 }
 impl PrintableTacky for TackyProgram {
@@ -610,6 +1652,9 @@ impl PrintableTacky for TackyProgram {
         let mut result = String::new();
         let indent = TAB.repeat(depth as usize);
         result.push_str(&format!("{}TackyProgram:\n", indent));
+        for static_variable in &self.static_variables {
+            result.push_str(&static_variable.print_tacky_code(depth + 1));
+        }
         result.push_str(&*self.function.print_tacky_code(depth + 1));
         result
     }
@@ -617,13 +1662,174 @@ impl PrintableTacky for TackyProgram {
 
 pub fn tacky_gen_from_filepath(
     file_path: &str, verbose: bool
-) -> Result<TackyProgram, ParseError> {
-    let parse_result = parse_from_filepath(file_path, verbose);
-    if parse_result.is_err() {
-        return Err(parse_result.err().unwrap());
-    }
-    let program = parse_result?;
-    let tacky_program = TackyProgram::from_program(&program);
+) -> Result<TackyProgram, CompilerError> {
+    let program = parse_from_filepath(file_path, verbose)?;
+    tacky_gen_from_ast(program)
+}
+
+pub fn tacky_gen_from_reader(
+    reader: impl Read, verbose: bool
+) -> Result<TackyProgram, CompilerError> {
+    let program = parse_from_reader(reader, verbose)?;
+    tacky_gen_from_ast(program)
+}
+
+pub fn tacky_gen_from_str(source: &str) -> Result<TackyProgram, CompilerError> {
+    let program = parse_from_str(source)?;
+    tacky_gen_from_ast(program)
+}
+
+fn tacky_gen_from_ast(program: ASTProgram) -> Result<TackyProgram, CompilerError> {
+    let program = resolve_identifiers(program)?;
+    validate_lvalues(&program)?;
+    let tacky_program = TackyProgram::try_from_program(&program)?;
     Ok(tacky_program)
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn tacky_error(source: &str) -> TackyError {
+        match tacky_gen_from_str(source) {
+            Err(CompilerError::TackyError(err)) => err,
+            other => panic!("Expected a tacky error, got {:?}", other.err())
+        }
+    }
+
+    #[test]
+    fn test_struct_and_pointer_type_errors() {
+        let err = tacky_error("int main(void) {\n    int a = 1;\n    return *a;\n}");
+        assert_eq!(err.variant, TackyErrorVariants::TypeError(
+            "Cannot dereference non-pointer type int".to_string()
+        ));
+        assert_eq!(err.location(), Some(SourceLocation::new(3, 12)));
+        assert_eq!(
+            err.to_string(), "TackyError at line 3:12: Cannot dereference non-pointer type int"
+        );
+
+        let err = tacky_error("int main(void) { int a = 1; return a.x + 1; }");
+        assert_eq!(err.message(), "Member access x on non-struct type int");
+        assert_eq!(err.location(), Some(SourceLocation::new(1, 36)));
+        let err = tacky_error(
+            "struct pair { int a; int b; };\nint main(void) { struct pair p; return p.c; }"
+        );
+        assert_eq!(err.message(), "struct pair has no member c");
+        let err = tacky_error(
+            "struct pair { int a; int b; };\nint main(void) { struct pair p; return *p; }"
+        );
+        assert_eq!(err.message(), "Cannot dereference non-pointer type struct pair");
+        let err = tacky_error(
+            "struct pair { int a; int b; };\nint main(void) { struct pair *p; return *p + 1; }"
+        );
+        assert_eq!(err.variant, TackyErrorVariants::Unsupported(
+            "Using struct pair values directly is not supported".to_string()
+        ));
+        assert_eq!(err.location(), Some(SourceLocation::new(2, 41)));
+
+        // struct declarations are located at the struct keyword
+        let err = tacky_error(
+            "int x;\nstruct pair { struct missing m; };\nint main(void) { return 0; }"
+        );
+        assert_eq!(err.to_string(), "TackyError at line 2:1: Unknown struct missing");
+    }
+
+    #[test]
+    fn test_invalid_casts() {
+        let declarations = "struct pair { int a; int b; };\nint main(void) {\n";
+        let err = tacky_error(&format!("{declarations}    return (struct pair) 1;\n}}"));
+        assert_eq!(err.message(), "Cannot cast to non-scalar type struct pair");
+        assert_eq!(err.location(), Some(SourceLocation::new(3, 12)));
+        let err = tacky_error(&format!(
+            "{declarations}    struct pair p;\n    return (int) p;\n}}"
+        ));
+        assert_eq!(err.message(), "Cannot cast from non-scalar type struct pair");
+        let err = tacky_error(&format!(
+            "{declarations}    double d = 1.0;\n    int *p = (int *) d;\n    return 0;\n}}"
+        ));
+        assert_eq!(err.variant, TackyErrorVariants::TypeError(
+            "Cannot cast between double and int *".to_string()
+        ));
+        assert_eq!(err.location(), Some(SourceLocation::new(4, 14)));
+    }
+
+    #[test]
+    fn test_invalid_double_operands() {
+        let err = tacky_error("int main(void) {\n    double d = 1.5;\n    return ~d;\n}");
+        assert_eq!(err.to_string(), "TackyError at line 3:12: Invalid operand to ~ of type double");
+        let err = tacky_error("int main(void) {\n    double d = 1.5;\n    return 3 % d;\n}");
+        assert_eq!(
+            err.to_string(), "TackyError at line 3:12: Invalid operands to % of type double"
+        );
+    }
+
+    #[test]
+    fn test_invalid_bitwise_operands() {
+        let err = tacky_error("int main(void) {\n    double d = 1.5;\n    return 1 | d;\n}");
+        assert_eq!(err.message(), "Invalid operands to BitwiseOr of types int and double");
+        assert_eq!(err.location(), Some(SourceLocation::new(3, 12)));
+        let err = tacky_error(
+            "int main(void) {\n    int a = 1;\n    int *p = &a;\n    return p << 1;\n}"
+        );
+        assert_eq!(err.message(), "Invalid operands to LeftShift of types int * and int");
+    }
+
+    #[test]
+    fn test_invalid_file_scope_declarations() {
+        let err = tacky_error("int a = 1;\nint b = a + 1;\nint main(void) { return b; }");
+        assert_eq!(err.variant, TackyErrorVariants::InvalidInitializer(
+            "File scope initializer a + 1 is not a constant".to_string()
+        ));
+        assert_eq!(err.location(), Some(SourceLocation::new(2, 9)));
+        let err = tacky_error(
+            "struct pair { int a; int b; };\nstruct pair p = 1;\nint main(void) { return 0; }"
+        );
+        assert_eq!(
+            err.to_string(), "TackyError at line 2:1: Struct p can't have a scalar initializer"
+        );
+
+        // file scope redeclarations are merged before tacky generation
+        let program = parse_from_str("int g = 1; int g; int main(void) { return g; }").unwrap();
+        let err = TackyProgram::try_from_program(&program).err().unwrap();
+        assert_eq!(err.variant, TackyErrorVariants::Redefinition("g".to_string()));
+        assert_eq!(err.location(), Some(SourceLocation::new(1, 12)));
+    }
+
+    #[test]
+    fn test_struct_assignment_is_unsupported() {
+        let declarations = "struct pair { int a; int b; };\nint main(void) {\n";
+        let err = tacky_error(&format!(
+            "{declarations}    struct pair p;\n    struct pair q;\n    p = q;\n    return 0;\n}}"
+        ));
+        assert_eq!(err.variant, TackyErrorVariants::Unsupported(
+            "Assigning struct pair values is not supported".to_string()
+        ));
+        assert_eq!(err.location(), Some(SourceLocation::new(5, 5)));
+        let err = tacky_error(&format!(
+            "{declarations}    struct pair p;\n    struct pair *q = &p;\n    *q = p;\n}}"
+        ));
+        assert_eq!(err.message(), "Assigning struct pair values is not supported");
+    }
+
+    #[test]
+    fn test_pointer_arithmetic_is_unsupported() {
+        let err = tacky_error(
+            "int main(void) {\n    int a = 1;\n    int *p = &a;\n    return *(p + 1);\n}"
+        );
+        assert_eq!(err.variant, TackyErrorVariants::Unsupported(
+            "Pointer arithmetic is not supported: Add".to_string()
+        ));
+        assert_eq!(err.location(), Some(SourceLocation::new(4, 14)));
+        // comparing pointers is fine
+        assert!(tacky_gen_from_str(
+            "int main(void) { int a = 1; int *p = &a; return p == &a; }"
+        ).is_ok());
+
+        let scope = TackyScope::default();
+        let err = TackyInstruction::unroll_expression(
+            ExpressionVariant::Variable(Identifier::new("x".to_string())), 0, &scope
+        ).err().unwrap();
+        assert_eq!(err.to_string(), "TackyError: Use of undeclared variable x");
+    }
+}
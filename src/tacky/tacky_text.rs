@@ -0,0 +1,676 @@
+use std::collections::HashMap;
+use std::fmt::{Display, Formatter};
+use crate::asm_gen::asm_symbols::TAB;
+use crate::parser::c_types::{AggregateKind, CType, StructMember, StructTable, StructType};
+use crate::parser::parse::{
+    ASTConstant, Identifier, SupportedBinaryOperators, SupportedUnaryOperators
+};
+use crate::tacky::tacky_symbols::{
+    AnnotationEndInstruction, AnnotationStartInstruction, BinaryInstruction, ConversionKind, ConvertInstruction, CopyInstruction,
+    GetAddressInstruction, JumpIfNotZeroInstruction, JumpIfZeroInstruction,
+    JumpInstruction, LabelInstruction, LoadInstruction, StoreInstruction,
+    TackyFunction, TackyInstruction, TackyProgram, TackyStaticVariable, TackyValue,
+    TackyVariable, ToTackyInstruction, UnaryInstruction
+};
+
+/*
+Textual syntax for tacky programs, so that tacky can be dumped,
+edited by hand and loaded back in (e.g. for IR level tests).
+
+    struct pair { int a; long b; }
+    static @counter: int = 3
+    static @origin: struct pair
+    function main {
+        var %2 x.0: int
+        var %3: long
+        %2 = copy (int) 5
+        %3 = sign_extend %2
+        %3 = add %3, (long) 1
+        jump_if_zero %3, end
+        annotation_start *ptr = x
+        store %2, %4, 0
+        annotation_end *ptr = x
+        label end
+        return %2
+    }
+
+Operands are either local variables (%<id>), static variables (@<name>)
+or constants written as (<type>) <value>. Annotations take the rest
+of the line as their description. Every local variable has to be
+declared with a var line before the instructions of its function.
+*/
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TackyTextError {
+    // 1-indexed line of the text that the error was found on
+    pub line: usize,
+    pub message: String,
+}
+impl Display for TackyTextError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "TackyTextError at line {}: {}", self.line, self.message)
+    }
+}
+
+const UNARY_OPERATOR_NAMES: [(&str, SupportedUnaryOperators); 3] = [
+    ("neg", SupportedUnaryOperators::Subtract),
+    ("complement", SupportedUnaryOperators::BitwiseNot),
+    ("not", SupportedUnaryOperators::Not),
+];
+const BINARY_OPERATOR_NAMES: [(&str, SupportedBinaryOperators); 19] = [
+    ("add", SupportedBinaryOperators::Add),
+    ("sub", SupportedBinaryOperators::Subtract),
+    ("mul", SupportedBinaryOperators::Multiply),
+    ("div", SupportedBinaryOperators::Divide),
+    ("mod", SupportedBinaryOperators::Modulo),
+    ("bit_and", SupportedBinaryOperators::BitwiseAnd),
+    ("bit_or", SupportedBinaryOperators::BitwiseOr),
+    ("bit_xor", SupportedBinaryOperators::BitwiseXor),
+    ("shl", SupportedBinaryOperators::LeftShift),
+    ("shr", SupportedBinaryOperators::RightShift),
+    ("and", SupportedBinaryOperators::And),
+    ("or", SupportedBinaryOperators::Or),
+    ("eq", SupportedBinaryOperators::CheckEqual),
+    ("ne", SupportedBinaryOperators::NotEqual),
+    ("lt", SupportedBinaryOperators::LessThan),
+    ("le", SupportedBinaryOperators::LessOrEqual),
+    ("gt", SupportedBinaryOperators::GreaterThan),
+    ("ge", SupportedBinaryOperators::GreaterOrEqual),
+    ("assign", SupportedBinaryOperators::AssignEqual),
+];
+const CONVERSION_NAMES: [(&str, ConversionKind); 7] = [
+    ("sign_extend", ConversionKind::SignExtend),
+    ("zero_extend", ConversionKind::ZeroExtend),
+    ("truncate", ConversionKind::Truncate),
+    ("int_to_double", ConversionKind::IntToDouble),
+    ("uint_to_double", ConversionKind::UIntToDouble),
+    ("double_to_int", ConversionKind::DoubleToInt),
+    ("double_to_uint", ConversionKind::DoubleToUInt),
+];
+const SCALAR_TYPES: [CType; 8] = [
+    CType::Char, CType::SignedChar, CType::UnsignedChar, CType::Int,
+    CType::Long, CType::UnsignedInt, CType::UnsignedLong, CType::Double
+];
+
+pub(crate) fn unary_operator_name(operator: &SupportedUnaryOperators) -> &'static str {
+    UNARY_OPERATOR_NAMES.iter().find(
+        |(_, candidate)| std::mem::discriminant(candidate) == std::mem::discriminant(operator)
+    ).unwrap().0
+}
+pub(crate) fn binary_operator_name(operator: &SupportedBinaryOperators) -> &'static str {
+    BINARY_OPERATOR_NAMES.iter().find(|(_, candidate)| candidate == operator).unwrap().0
+}
+pub(crate) fn conversion_name(kind: &ConversionKind) -> &'static str {
+    CONVERSION_NAMES.iter().find(|(_, candidate)| candidate == kind).unwrap().0
+}
+
+/*
+Emitter
+*/
+
+pub(crate) fn emit_value(value: &TackyValue) -> String {
+    match value {
+        TackyValue::Constant(ast_constant) => {
+            format!("({}) {}", ast_constant.const_type, ast_constant.value)
+        },
+        TackyValue::Var(variable) => emit_variable(variable)
+    }
+}
+pub(crate) fn emit_variable(variable: &TackyVariable) -> String {
+    if variable.is_static {
+        format!("@{}", variable.name)
+    } else {
+        format!("%{}", variable.id)
+    }
+}
+
+pub(crate) fn emit_instruction(instruction: &TackyInstruction) -> String {
+    match instruction {
+        TackyInstruction::UnaryInstruction(unary) => format!(
+            "{} = {} {}", emit_variable(&unary.dst),
+            unary_operator_name(&unary.operator), emit_value(&unary.src)
+        ),
+        TackyInstruction::BinaryInstruction(binary) => format!(
+            "{} = {} {}, {}", emit_variable(&binary.dst),
+            binary_operator_name(&binary.operator),
+            emit_value(&binary.left), emit_value(&binary.right)
+        ),
+        TackyInstruction::CopyInstruction(copy) => format!(
+            "{} = copy {}", emit_variable(&copy.dst), emit_value(&copy.src)
+        ),
+        TackyInstruction::ConvertInstruction(convert) => format!(
+            "{} = {} {}", emit_variable(&convert.dst),
+            conversion_name(&convert.kind), emit_value(&convert.src)
+        ),
+        TackyInstruction::GetAddressInstruction(get_address) => format!(
+            "{} = address_of {}",
+            emit_variable(&get_address.dst), emit_variable(&get_address.src)
+        ),
+        TackyInstruction::LoadInstruction(load) => format!(
+            "{} = load {}, {}", emit_variable(&load.dst),
+            emit_value(&load.src_pointer), load.offset
+        ),
+        TackyInstruction::StoreInstruction(store) => format!(
+            "store {}, {}, {}", emit_value(&store.src),
+            emit_value(&store.dst_pointer), store.offset
+        ),
+        TackyInstruction::JumpInstruction(jump) => format!("jump {}", jump.target.name),
+        TackyInstruction::JumpIfZeroInstruction(jump) => format!(
+            "jump_if_zero {}, {}", emit_value(&jump.condition), jump.target.name
+        ),
+        TackyInstruction::JumpIfNotZeroInstruction(jump) => format!(
+            "jump_if_not_zero {}, {}", emit_value(&jump.condition), jump.target.name
+        ),
+        TackyInstruction::LabelInstruction(label) => format!("label {}", label.label.name),
+        TackyInstruction::AnnotationStartInstruction(annotation) => {
+            format!("annotation_start {}", annotation.description)
+        },
+        TackyInstruction::AnnotationEndInstruction(annotation) => {
+            format!("annotation_end {}", annotation.description)
+        },
+        TackyInstruction::Return(value) => format!("return {}", emit_value(value)),
+    }
+}
+
+fn instruction_variables(instruction: &TackyInstruction) -> Vec<&TackyVariable> {
+    // every variable read or written by the instruction
+    let mut variables = instruction.get_src_variables();
+    variables.extend(instruction.get_dst());
+    variables
+}
+
+fn collect_struct_types(c_type: &CType, struct_types: &mut Vec<StructType>) {
+    // complete struct types reachable from c_type, dependencies first
+    match c_type {
+        CType::Pointer(inner) => collect_struct_types(inner, struct_types),
+        CType::Struct(struct_type) if struct_type.is_complete() => {
+            if struct_types.iter().any(|collected| collected.tag == struct_type.tag) {
+                return;
+            }
+            for member in struct_type.members.iter().flatten() {
+                collect_struct_types(&member.member_type, struct_types);
+            }
+            // a member may have pointed back to this struct
+            if !struct_types.iter().any(|collected| collected.tag == struct_type.tag) {
+                struct_types.push(struct_type.clone());
+            }
+        },
+        _ => {}
+    }
+}
+
+fn emit_struct(struct_type: &StructType) -> String {
+    let members: Vec<String> = struct_type.members.iter().flatten().map(
+        |member| format!("{} {};", member.member_type, member.name)
+    ).collect();
+    format!("{} {{ {} }}", struct_type, members.join(" "))
+}
+
+pub fn emit_tacky_text(program: &TackyProgram) -> String {
+    let function = &program.function;
+    let mut locals: Vec<&TackyVariable> = vec![];
+    for instruction in &function.instructions {
+        for variable in instruction_variables(instruction) {
+            if !variable.is_static && !locals.iter().any(|local| local.id == variable.id) {
+                locals.push(variable);
+            }
+        }
+    }
+    locals.sort_by_key(|variable| variable.id);
+
+    let mut struct_types: Vec<StructType> = vec![];
+    for static_variable in &program.static_variables {
+        collect_struct_types(&static_variable.variable.var_type, &mut struct_types);
+    }
+    for local in &locals {
+        collect_struct_types(&local.var_type, &mut struct_types);
+    }
+
+    let mut text = String::new();
+    for struct_type in &struct_types {
+        text.push_str(&format!("{}\n", emit_struct(struct_type)));
+    }
+    for static_variable in &program.static_variables {
+        let variable = &static_variable.variable;
+        text.push_str(&format!("static @{}: {}", variable.name, variable.var_type));
+        if let Some(initial_value) = &static_variable.initial_value {
+            text.push_str(&format!(" = {}", initial_value.value));
+        }
+        text.push('\n');
+    }
+
+    text.push_str(&format!("function {} {{\n", function.name_to_string()));
+    for local in &locals {
+        match local.name.as_str() {
+            "" => text.push_str(&format!("{TAB}var %{}: {}\n", local.id, local.var_type)),
+            name => text.push_str(&format!(
+                "{TAB}var %{} {}: {}\n", local.id, name, local.var_type
+            ))
+        }
+    }
+    for instruction in &function.instructions {
+        text.push_str(&format!("{TAB}{}\n", emit_instruction(instruction)));
+    }
+    text.push_str("}\n");
+    text
+}
+
+/*
+Parser
+*/
+
+struct TackyTextParser {
+    structs: StructTable,
+    statics: HashMap<String, TackyVariable>,
+    locals: HashMap<u64, TackyVariable>,
+    line_number: usize,
+}
+impl TackyTextParser {
+    fn error<T>(&self, message: String) -> Result<T, TackyTextError> {
+        Err(TackyTextError { line: self.line_number, message })
+    }
+
+    fn parse_type(&self, text: &str) -> Result<CType, TackyTextError> {
+        // e.g. "unsigned long", "int * *", "struct pair"
+        let mut base_text = text.trim();
+        let mut pointer_depth = 0;
+        while let Some(stripped) = base_text.strip_suffix('*') {
+            base_text = stripped.trim_end();
+            pointer_depth += 1;
+        }
+
+        let words: Vec<&str> = base_text.split_whitespace().collect();
+        let aggregate_kind = match words.first() {
+            Some(&"struct") => Some(AggregateKind::Struct),
+            Some(&"union") => Some(AggregateKind::Union),
+            _ => None
+        };
+        let mut c_type = match (aggregate_kind, words.as_slice()) {
+            (Some(kind), [_, tag]) => {
+                let incomplete = CType::Struct(StructType::new_incomplete(kind, tag.to_string()));
+                // structs that aren't declared yet can only be pointed to
+                match self.structs.resolve(&incomplete) {
+                    Ok(resolved) => resolved,
+                    Err(_) if pointer_depth > 0 => incomplete,
+                    Err(message) => return self.error(message)
+                }
+            },
+            _ => match SCALAR_TYPES.iter().find(|scalar| scalar.to_string() == base_text) {
+                Some(scalar) => scalar.clone(),
+                None => return self.error(format!("Unknown type {}", text))
+            }
+        };
+        for _ in 0..pointer_depth {
+            c_type = c_type.pointer_to();
+        }
+        Ok(c_type)
+    }
+
+    fn parse_constant(
+        &self, value: &str, const_type: &CType
+    ) -> Result<ASTConstant, TackyTextError> {
+        let is_valid = if const_type.is_double() {
+            value.parse::<f64>().is_ok()
+        } else {
+            value.parse::<u64>().is_ok()
+        };
+        if !is_valid {
+            return self.error(format!("Invalid {} constant {}", const_type, value));
+        }
+        Ok(ASTConstant::new_typed(value, const_type.clone()))
+    }
+
+    fn parse_variable(&self, text: &str) -> Result<TackyVariable, TackyTextError> {
+        let text = text.trim();
+        if let Some(name) = text.strip_prefix('@') {
+            return match self.statics.get(name) {
+                Some(variable) => Ok(variable.clone()),
+                None => self.error(format!("Undeclared static variable {}", text))
+            };
+        }
+        let id = match text.strip_prefix('%').map(|id| id.parse::<u64>()) {
+            Some(Ok(id)) => id,
+            _ => return self.error(format!("Invalid variable {}", text))
+        };
+        match self.locals.get(&id) {
+            Some(variable) => Ok(variable.clone()),
+            None => self.error(format!("Undeclared variable {}", text))
+        }
+    }
+
+    fn parse_value(&self, text: &str) -> Result<TackyValue, TackyTextError> {
+        let text = text.trim();
+        if let Some(rest) = text.strip_prefix('(') {
+            let (type_text, value) = match rest.split_once(')') {
+                Some(split) => split,
+                None => return self.error(format!("Invalid constant {}", text))
+            };
+            let const_type = self.parse_type(type_text)?;
+            return Ok(TackyValue::Constant(self.parse_constant(value.trim(), &const_type)?));
+        }
+        Ok(TackyValue::Var(self.parse_variable(text)?))
+    }
+
+    fn parse_offset(&self, text: &str) -> Result<u64, TackyTextError> {
+        match text.trim().parse::<u64>() {
+            Ok(offset) => Ok(offset),
+            Err(_) => self.error(format!("Invalid offset {}", text))
+        }
+    }
+
+    fn split_operands<'a>(
+        &self, text: &'a str, expected: usize
+    ) -> Result<Vec<&'a str>, TackyTextError> {
+        let operands: Vec<&str> = text.split(',').map(|operand| operand.trim()).collect();
+        if operands.len() != expected {
+            return self.error(format!("Expected {} operands in {}", expected, text));
+        }
+        Ok(operands)
+    }
+
+    fn parse_struct(&mut self, line: &str) -> Result<(), TackyTextError> {
+        // struct <tag> { <type> <name>; ... }
+        let (header, body) = match line.strip_suffix('}').and_then(|line| line.split_once('{')) {
+            Some(split) => split,
+            None => return self.error(format!("Invalid struct definition {}", line))
+        };
+        let (kind, tag) = match header.split_whitespace().collect::<Vec<&str>>().as_slice() {
+            ["struct", tag] => (AggregateKind::Struct, tag.to_string()),
+            ["union", tag] => (AggregateKind::Union, tag.to_string()),
+            _ => return self.error(format!("Invalid struct header {}", header))
+        };
+
+        let mut members: Vec<StructMember> = vec![];
+        for member_text in body.split(';').map(|member| member.trim()) {
+            if member_text.is_empty() { continue; }
+            let (type_text, name) = match member_text.rsplit_once(char::is_whitespace) {
+                Some(split) => split,
+                None => return self.error(format!("Invalid member {}", member_text))
+            };
+            members.push(StructMember::new(name.to_string(), self.parse_type(type_text)?));
+        }
+        if let Err(message) = self.structs.declare(StructType::new(kind, tag, members)) {
+            return self.error(message);
+        }
+        Ok(())
+    }
+
+    fn parse_static(&mut self, line: &str) -> Result<TackyStaticVariable, TackyTextError> {
+        // static @<name>: <type> [= <value>]
+        let (name, rest) = match line.strip_prefix('@').and_then(|line| line.split_once(':')) {
+            Some(split) => split,
+            None => return self.error(format!("Invalid static variable {}", line))
+        };
+        let (type_text, initial_text) = match rest.split_once('=') {
+            Some((type_text, initial_text)) => (type_text, Some(initial_text.trim())),
+            None => (rest, None)
+        };
+        let var_type = self.parse_type(type_text)?;
+        let initial_value = match initial_text {
+            Some(value) => Some(self.parse_constant(value, &var_type)?),
+            None => None
+        };
+        if self.statics.contains_key(name) {
+            return self.error(format!("Duplicate static variable {}", name));
+        }
+
+        let id = self.statics.len() as u64;
+        let variable = TackyVariable::new_static(id, name.to_string(), var_type);
+        self.statics.insert(name.to_string(), variable.clone());
+        Ok(TackyStaticVariable { variable, initial_value })
+    }
+
+    fn parse_local(&mut self, line: &str) -> Result<(), TackyTextError> {
+        // var %<id> [<name>]: <type>
+        let (declarator, type_text) = match line.split_once(':') {
+            Some(split) => split,
+            None => return self.error(format!("Invalid variable declaration {}", line))
+        };
+        let mut declarator_parts = declarator.split_whitespace();
+        let id = match declarator_parts.next().and_then(|id| id.strip_prefix('%')) {
+            Some(id) => match id.parse::<u64>() {
+                Ok(id) => id,
+                Err(_) => return self.error(format!("Invalid variable id {}", id))
+            },
+            None => return self.error(format!("Invalid variable declaration {}", line))
+        };
+        let name = declarator_parts.next().unwrap_or("").to_string();
+        let var_type = self.parse_type(type_text)?;
+        let is_duplicate = self.locals.contains_key(&id) ||
+            self.statics.values().any(|variable| variable.id == id);
+        if is_duplicate {
+            return self.error(format!("Duplicate variable id %{}", id));
+        }
+        self.locals.insert(id, TackyVariable::new_named(id, name, var_type));
+        Ok(())
+    }
+
+    fn parse_instruction(&self, line: &str) -> Result<TackyInstruction, TackyTextError> {
+        let (opcode, operands) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+        let operands = operands.trim();
+        let instruction = match opcode {
+            "return" => TackyInstruction::Return(self.parse_value(operands)?),
+            "jump" => JumpInstruction::new(Identifier::new(operands.to_string()))
+                .to_tacky_instruction(),
+            "label" => LabelInstruction::new(Identifier::new(operands.to_string()))
+                .to_tacky_instruction(),
+            "annotation_start" => AnnotationStartInstruction::new(operands.to_string())
+                .to_tacky_instruction(),
+            "annotation_end" => AnnotationEndInstruction::new(operands.to_string())
+                .to_tacky_instruction(),
+            "jump_if_zero" | "jump_if_not_zero" => {
+                let operands = self.split_operands(operands, 2)?;
+                let condition = self.parse_value(operands[0])?;
+                let target = Identifier::new(operands[1].to_string());
+                if opcode == "jump_if_zero" {
+                    JumpIfZeroInstruction::new(condition, target).to_tacky_instruction()
+                } else {
+                    JumpIfNotZeroInstruction::new(condition, target).to_tacky_instruction()
+                }
+            },
+            "store" => {
+                let operands = self.split_operands(operands, 3)?;
+                StoreInstruction::new(
+                    self.parse_value(operands[0])?, self.parse_value(operands[1])?,
+                    self.parse_offset(operands[2])?
+                ).to_tacky_instruction()
+            },
+            _ => return self.parse_assignment(line)
+        };
+        Ok(instruction)
+    }
+
+    fn parse_assignment(&self, line: &str) -> Result<TackyInstruction, TackyTextError> {
+        // <dst> = <opcode> <operands>
+        let (dst_text, rest) = match line.split_once('=') {
+            Some(split) => split,
+            None => return self.error(format!("Unknown instruction {}", line))
+        };
+        let dst = self.parse_variable(dst_text)?;
+        let rest = rest.trim();
+        let (opcode, operands) = rest.split_once(char::is_whitespace).unwrap_or((rest, ""));
+
+        if let Some((_, operator)) = UNARY_OPERATOR_NAMES.iter().find(|(name, _)| *name == opcode) {
+            let src = self.parse_value(operands)?;
+            return Ok(UnaryInstruction::new(operator.clone(), src, dst).to_tacky_instruction());
+        }
+        if let Some((_, operator)) = BINARY_OPERATOR_NAMES.iter().find(|(name, _)| *name == opcode) {
+            let operands = self.split_operands(operands, 2)?;
+            return Ok(BinaryInstruction::new(
+                operator.clone(), self.parse_value(operands[0])?,
+                self.parse_value(operands[1])?, dst
+            ).to_tacky_instruction());
+        }
+        if let Some((_, kind)) = CONVERSION_NAMES.iter().find(|(name, _)| *name == opcode) {
+            let src = self.parse_value(operands)?;
+            return Ok(ConvertInstruction::new(kind.clone(), src, dst).to_tacky_instruction());
+        }
+        let instruction = match opcode {
+            "copy" => CopyInstruction::new(self.parse_value(operands)?, dst)
+                .to_tacky_instruction(),
+            "address_of" => GetAddressInstruction::new(self.parse_variable(operands)?, dst)
+                .to_tacky_instruction(),
+            "load" => {
+                let operands = self.split_operands(operands, 2)?;
+                LoadInstruction::new(
+                    self.parse_value(operands[0])?, self.parse_offset(operands[1])?, dst
+                ).to_tacky_instruction()
+            },
+            _ => return self.error(format!("Unknown instruction {}", opcode))
+        };
+        Ok(instruction)
+    }
+}
+
+pub fn parse_tacky_text(text: &str) -> Result<TackyProgram, TackyTextError> {
+    let mut parser = TackyTextParser {
+        structs: StructTable::new(),
+        statics: HashMap::new(),
+        locals: HashMap::new(),
+        line_number: 0,
+    };
+    let mut static_variables: Vec<TackyStaticVariable> = vec![];
+    let mut function_name: Option<String> = None;
+    let mut instructions: Vec<TackyInstruction> = vec![];
+    let mut function_closed = false;
+
+    for (index, raw_line) in text.lines().enumerate() {
+        parser.line_number = index + 1;
+        let line = raw_line.trim();
+        if line.is_empty() || line.starts_with("//") { continue; }
+        if function_closed {
+            return parser.error("Unexpected text after function".to_string());
+        }
+
+        if function_name.is_none() {
+            if line.starts_with("struct ") || line.starts_with("union ") {
+                parser.parse_struct(line)?;
+            } else if let Some(rest) = line.strip_prefix("static ") {
+                static_variables.push(parser.parse_static(rest.trim())?);
+            } else if let Some(rest) = line.strip_prefix("function ") {
+                match rest.strip_suffix('{') {
+                    Some(name) => function_name = Some(name.trim().to_string()),
+                    None => return parser.error(format!("Invalid function header {}", line))
+                }
+            } else {
+                return parser.error(format!("Unexpected line {}", line));
+            }
+        } else if line == "}" {
+            function_closed = true;
+        } else if let Some(rest) = line.strip_prefix("var ") {
+            if !instructions.is_empty() {
+                return parser.error("Variables must be declared before instructions".to_string());
+            }
+            parser.parse_local(rest.trim())?;
+        } else {
+            instructions.push(parser.parse_instruction(line)?);
+        }
+    }
+
+    let function_name = match (function_name, function_closed) {
+        (Some(function_name), true) => function_name,
+        _ => return parser.error("Missing function definition".to_string())
+    };
+    let function = TackyFunction {
+        name: Identifier::new(function_name),
+        instructions,
+        pop_context: None
+    };
+    Ok(TackyProgram::new(static_variables, function))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::errors::CompilerError;
+    use crate::tacky::tacky_symbols::{tacky_gen_from_reader, tacky_gen_from_str};
+
+    fn tacky_from_source(source: &str) -> TackyProgram {
+        tacky_gen_from_str(source).unwrap()
+    }
+
+    #[test]
+    fn test_tacky_gen_from_reader() {
+        let source = "int main(void) { return 1 + 2; }";
+        let program = tacky_gen_from_reader(source.as_bytes(), false).unwrap();
+        assert_eq!(emit_tacky_text(&program), emit_tacky_text(&tacky_from_source(source)));
+        assert!(matches!(
+            tacky_gen_from_str("int main(void) { return x; }"),
+            Err(CompilerError::SemanticError(_))
+        ));
+    }
+
+    #[test]
+    fn test_round_trip_from_source() {
+        let program = tacky_from_source(
+            "struct pair { int a; long b; }; int counter = 3; double scale;
+            int main(void) {
+                struct pair p; struct pair *ptr = &p; ptr->b = counter;
+                unsigned char c = (unsigned char) p.b;
+                return (int) (scale * 2) + ~c + (counter << 2);
+            }"
+        );
+        let text = emit_tacky_text(&program);
+        assert!(text.starts_with("struct pair { int a; long b; }\nstatic @counter: int = 3\n"));
+        assert!(text.contains("static @scale: double\nfunction main {\n"));
+        assert!(text.contains("var %3 ptr.1: struct pair *\n"));
+        assert!(text.contains("annotation_start ptr.1->b = counter\n"));
+
+        let reloaded = parse_tacky_text(&text).unwrap();
+        assert_eq!(emit_tacky_text(&reloaded), text);
+    }
+
+    #[test]
+    fn test_variable_assignment() {
+        let program = tacky_from_source(
+            "int main(void) { int a = 1; long b; b = a = a + 4; return a; }"
+        );
+        let text = emit_tacky_text(&program);
+        assert!(text.contains("%2 = add %0, (int) 4\n    %0 = copy %2\n"));
+        assert!(text.contains("%3 = sign_extend %0\n    %1 = copy %3\n"));
+    }
+
+    #[test]
+    fn test_parse_hand_written_tacky() {
+        let text = "
+            static @total: long = 5
+            function main {
+                var %1 x: int
+                var %2: long
+                %1 = copy (int) 7
+                %2 = sign_extend %1
+                @total = add @total, %2
+                jump_if_not_zero %2, done
+                %1 = neg %1
+                label done
+                return %1
+            }
+        ";
+        let program = parse_tacky_text(text).unwrap();
+        assert_eq!(program.static_variables.len(), 1);
+        assert_eq!(program.function.instructions.len(), 7);
+        assert!(matches!(
+            &program.function.instructions[1],
+            TackyInstruction::ConvertInstruction(convert)
+                if convert.kind == ConversionKind::SignExtend && convert.dst.var_type == CType::Long
+        ));
+        assert!(matches!(
+            &program.function.instructions[2],
+            TackyInstruction::BinaryInstruction(binary) if binary.dst.is_static
+        ));
+    }
+
+    #[test]
+    fn test_tacky_text_errors() {
+        let error = parse_tacky_text("function main {\n    return %4\n}").err().unwrap();
+        assert_eq!(error.line, 2);
+        assert!(error.message.contains("Undeclared variable %4"));
+
+        let error = parse_tacky_text("function main {\n    %1 = frobnicate\n}").err().unwrap();
+        assert_eq!(error.line, 2);
+
+        let error = parse_tacky_text("static @s: struct missing\n").err().unwrap();
+        assert_eq!(error.line, 1);
+        assert!(parse_tacky_text("function main {\n    return (int) 0\n").is_err());
+    }
+}
@@ -0,0 +1,308 @@
+use std::collections::{HashMap, HashSet};
+use std::fmt::{Display, Formatter};
+use crate::optimizer::control_flow::ControlFlowGraph;
+use crate::parser::c_types::CType;
+use crate::tacky::tacky_symbols::{TackyFunction, TackyInstruction, TackyProgram, TackyVariable};
+
+/*
+Checks structural invariants of tacky programs that the asm backend
+relies on. Generated tacky should always pass these checks, so failures
+point to bugs in tacky generation / optimization passes, or to
+malformed hand written textual tacky
+*/
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum TackyVerifyError {
+    UndefinedLabel(String),
+    DuplicateLabel(String),
+    UndeclaredStatic(String),
+    // temporary variable (by id) read before it is written on some path
+    UndefinedVariable(u64),
+    ConflictingTypes { id: u64, first_type: CType, second_type: CType },
+    // annotation end without a matching start (or vice versa)
+    UnbalancedAnnotation(String),
+}
+impl Display for TackyVerifyError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TackyVerifyError::UndefinedLabel(label) => {
+                write!(f, "Jump to undefined label {}", label)
+            },
+            TackyVerifyError::DuplicateLabel(label) => {
+                write!(f, "Label {} is defined more than once", label)
+            },
+            TackyVerifyError::UndeclaredStatic(name) => {
+                write!(f, "Use of undeclared static variable @{}", name)
+            },
+            TackyVerifyError::UndefinedVariable(id) => {
+                write!(f, "Temporary variable %{} may be read before it is written", id)
+            },
+            TackyVerifyError::ConflictingTypes { id, first_type, second_type } => write!(
+                f, "Variable %{} is used with conflicting types {} and {}",
+                id, first_type, second_type
+            ),
+            TackyVerifyError::UnbalancedAnnotation(description) => {
+                write!(f, "Annotation \"{}\" is not properly nested", description)
+            },
+        }
+    }
+}
+
+fn instruction_variables(instruction: &TackyInstruction) -> Vec<&TackyVariable> {
+    let mut variables = instruction.get_src_variables();
+    variables.extend(instruction.get_dst());
+    variables
+}
+
+fn verify_labels(function: &TackyFunction, errors: &mut Vec<TackyVerifyError>) {
+    let mut labels: HashSet<&String> = HashSet::new();
+    for instruction in &function.instructions {
+        if let TackyInstruction::LabelInstruction(label) = instruction {
+            if !labels.insert(&label.label.name) {
+                errors.push(TackyVerifyError::DuplicateLabel(label.label.name.clone()));
+            }
+        }
+    }
+    for instruction in &function.instructions {
+        let target = match instruction {
+            TackyInstruction::JumpInstruction(jump) => &jump.target,
+            TackyInstruction::JumpIfZeroInstruction(jump) => &jump.target,
+            TackyInstruction::JumpIfNotZeroInstruction(jump) => &jump.target,
+            _ => continue
+        };
+        if !labels.contains(&target.name) {
+            errors.push(TackyVerifyError::UndefinedLabel(target.name.clone()));
+        }
+    }
+}
+
+fn verify_annotations(function: &TackyFunction, errors: &mut Vec<TackyVerifyError>) {
+    // every annotation end has to close the innermost open annotation
+    let mut open_annotations: Vec<&String> = vec![];
+    for instruction in &function.instructions {
+        match instruction {
+            TackyInstruction::AnnotationStartInstruction(annotation) => {
+                open_annotations.push(&annotation.description);
+            },
+            TackyInstruction::AnnotationEndInstruction(annotation)
+                if open_annotations.pop() != Some(&annotation.description) => {
+                errors.push(TackyVerifyError::UnbalancedAnnotation(
+                    annotation.description.clone()
+                ));
+                return;
+            },
+            _ => {}
+        }
+    }
+    if let Some(description) = open_annotations.pop() {
+        errors.push(TackyVerifyError::UnbalancedAnnotation(description.clone()));
+    }
+}
+
+fn verify_variables(program: &TackyProgram, errors: &mut Vec<TackyVerifyError>) {
+    // every use of a variable id has to agree on its type (and staticness)
+    let static_names: HashSet<&String> = program.static_variables.iter().map(
+        |static_variable| &static_variable.variable.name
+    ).collect();
+    let mut variable_types: HashMap<u64, &CType> = HashMap::new();
+    for static_variable in &program.static_variables {
+        variable_types.insert(static_variable.variable.id, &static_variable.variable.var_type);
+    }
+
+    for instruction in &program.function.instructions {
+        for variable in instruction_variables(instruction) {
+            if variable.is_static && !static_names.contains(&variable.name) {
+                errors.push(TackyVerifyError::UndeclaredStatic(variable.name.clone()));
+                continue;
+            }
+            let first_type = *variable_types.entry(variable.id).or_insert(&variable.var_type);
+            if *first_type != variable.var_type {
+                let error = TackyVerifyError::ConflictingTypes {
+                    id: variable.id,
+                    first_type: first_type.clone(),
+                    second_type: variable.var_type.clone()
+                };
+                if !errors.contains(&error) {
+                    errors.push(error);
+                }
+            }
+        }
+    }
+}
+
+fn verify_definitions(function: &TackyFunction, errors: &mut Vec<TackyVerifyError>) {
+    /*
+    temporaries (variables that weren't declared in the source code)
+    must be written on every path before they are read. Named variables
+    may be read uninitialized, and aliased ones can be written through
+    pointers, so those aren't checked
+    */
+    let aliased_variables: HashSet<u64> = function.instructions.iter().filter_map(
+        |instruction| match instruction {
+            TackyInstruction::GetAddressInstruction(get_address) => Some(get_address.src.id),
+            _ => None
+        }
+    ).collect();
+    let is_checked = |variable: &TackyVariable| {
+        !variable.is_static && variable.name.is_empty() &&
+            !aliased_variables.contains(&variable.id)
+    };
+
+    let graph = ControlFlowGraph::from_instructions(function.instructions.clone());
+    let predecessors = graph.predecessors();
+    let num_blocks = graph.blocks.len();
+    // None stands for "every variable", i.e. no path has reached the block yet
+    let mut block_out: Vec<Option<HashSet<u64>>> = vec![None; num_blocks];
+    let mut block_in: Vec<Option<HashSet<u64>>> = vec![None; num_blocks];
+    let mut changed = true;
+    while changed {
+        changed = false;
+        for (index, block) in graph.blocks.iter().enumerate() {
+            let mut defined: Option<HashSet<u64>> = if index == 0 {
+                Some(HashSet::new())
+            } else {
+                None
+            };
+            for predecessor in &predecessors[index] {
+                defined = match (defined, &block_out[*predecessor]) {
+                    (None, predecessor_out) => predecessor_out.clone(),
+                    (Some(defined), Some(predecessor_out)) => {
+                        Some(defined.intersection(predecessor_out).cloned().collect())
+                    },
+                    (defined, None) => defined
+                };
+            }
+            block_in[index] = defined.clone();
+            let Some(mut defined) = defined else { continue };
+            for instruction in &block.instructions {
+                defined.extend(instruction.get_dst().map(|dst| dst.id));
+            }
+            if block_out[index].as_ref() != Some(&defined) {
+                block_out[index] = Some(defined);
+                changed = true;
+            }
+        }
+    }
+
+    for (block, defined) in graph.blocks.iter().zip(block_in) {
+        // blocks that can't be reached are never executed
+        let Some(mut defined) = defined else { continue };
+        for instruction in &block.instructions {
+            for variable in instruction.get_src_variables() {
+                if is_checked(variable) && !defined.contains(&variable.id) {
+                    let error = TackyVerifyError::UndefinedVariable(variable.id);
+                    if !errors.contains(&error) {
+                        errors.push(error);
+                    }
+                }
+            }
+            defined.extend(instruction.get_dst().map(|dst| dst.id));
+        }
+    }
+}
+
+fn to_result(errors: Vec<TackyVerifyError>) -> Result<(), Vec<TackyVerifyError>> {
+    if errors.is_empty() {
+        Ok(())
+    } else {
+        Err(errors)
+    }
+}
+
+pub fn verify_tacky_function(function: &TackyFunction) -> Result<(), Vec<TackyVerifyError>> {
+    // checks that don't need to know about the program's static variables
+    let mut errors: Vec<TackyVerifyError> = vec![];
+    verify_labels(function, &mut errors);
+    verify_annotations(function, &mut errors);
+    // the control flow graph can only be built once all jump targets exist
+    if errors.is_empty() {
+        verify_definitions(function, &mut errors);
+    }
+    to_result(errors)
+}
+
+pub fn verify_tacky_program(program: &TackyProgram) -> Result<(), Vec<TackyVerifyError>> {
+    let mut errors: Vec<TackyVerifyError> = vec![];
+    verify_variables(program, &mut errors);
+    if let Err(function_errors) = verify_tacky_function(&program.function) {
+        errors.extend(function_errors);
+    }
+    to_result(errors)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::lexer::lexer::Lexer;
+    use crate::optimizer::optimize_program;
+    use crate::parser::parse::parse;
+    use crate::parser::parser_helpers::TokenStack;
+    use crate::semantic::identifier_resolution::resolve_identifiers;
+    use crate::tacky::tacky_text::parse_tacky_text;
+
+    fn verify_tacky_text(text: &str) -> Result<(), Vec<TackyVerifyError>> {
+        verify_tacky_program(&parse_tacky_text(text).unwrap())
+    }
+
+    #[test]
+    fn test_verify_generated_tacky() {
+        let tokens = Lexer::new().tokenize(
+            "int total = 2; int main(void) {
+                int x; int *ptr = &x; *ptr = total && 3;
+                return (x > 1 || total) + -x;
+            }"
+        ).unwrap();
+        let program = parse(&mut TokenStack::new_from_vec(tokens)).unwrap();
+        let program = TackyProgram::from_program(&resolve_identifiers(program).unwrap());
+        assert_eq!(verify_tacky_program(&program), Ok(()));
+        assert_eq!(verify_tacky_program(&optimize_program(program)), Ok(()));
+    }
+
+    #[test]
+    fn test_verify_labels() {
+        let errors = verify_tacky_text("
+            function main {
+                label start
+                label start
+                jump end
+                return (int) 0
+            }
+        ").err().unwrap();
+        assert_eq!(errors, vec![
+            TackyVerifyError::DuplicateLabel("start".to_string()),
+            TackyVerifyError::UndefinedLabel("end".to_string())
+        ]);
+    }
+
+    #[test]
+    fn test_verify_definitions() {
+        let errors = verify_tacky_text("
+            function main {
+                var %1 x: int
+                var %2: int
+                var %3: int
+                jump_if_zero %1, skip
+                %2 = copy (int) 1
+                %3 = copy (int) 2
+                label skip
+                %3 = copy (int) 3
+                %1 = add %2, %3
+                return %1
+            }
+        ").err().unwrap();
+        assert_eq!(errors, vec![TackyVerifyError::UndefinedVariable(2)]);
+    }
+
+    #[test]
+    fn test_verify_annotations() {
+        let errors = verify_tacky_text("
+            function main {
+                annotation_start x + 1
+                annotation_start y
+                annotation_end x + 1
+                return (int) 0
+            }
+        ").err().unwrap();
+        assert_eq!(errors, vec![TackyVerifyError::UnbalancedAnnotation("x + 1".to_string())]);
+    }
+}